@@ -0,0 +1,607 @@
+//! Reading .etl files without the Windows APIs
+//!
+//! The etw-reader crate uses `ProcessTrace` to read the events from a trace and TDH to decode
+//! them, which only works on Windows. This crate reads the file format directly: an .etl file
+//! is a sequence of buffers, each of which starts with a `WMI_BUFFER_HEADER` and is followed by
+//! 8-byte aligned event records. It understands the headers of classic kernel events
+//! (`SYSTEM_TRACE_HEADER`, `PERFINFO_TRACE_HEADER`), of classic provider events
+//! (`EVENT_TRACE_HEADER`) and of manifest-based events (`EVENT_HEADER`).
+//!
+//! It does not decode event payloads; [PayloadReader] helps with reading the MOF layouts of the
+//! kernel events, which are documented at
+//! <https://learn.microsoft.com/en-us/windows/win32/etw/msnt-systemtrace>.
+//!
+//! Compressed buffers (`-compress` in xperf / `EVENT_TRACE_COMPRESSED_MODE`) are not supported.
+
+use std::fmt;
+
+/// The size of a `WMI_BUFFER_HEADER`.
+const BUFFER_HEADER_SIZE: usize = 0x48;
+
+const TRACE_HEADER_FLAG: u8 = 0x80;
+
+const TRACE_HEADER_TYPE_SYSTEM32: u8 = 1;
+const TRACE_HEADER_TYPE_SYSTEM64: u8 = 2;
+const TRACE_HEADER_TYPE_COMPACT32: u8 = 3;
+const TRACE_HEADER_TYPE_COMPACT64: u8 = 4;
+const TRACE_HEADER_TYPE_FULL_HEADER32: u8 = 10;
+const TRACE_HEADER_TYPE_INSTANCE32: u8 = 11;
+const TRACE_HEADER_TYPE_PERFINFO32: u8 = 16;
+const TRACE_HEADER_TYPE_PERFINFO64: u8 = 17;
+const TRACE_HEADER_TYPE_EVENT_HEADER32: u8 = 18;
+const TRACE_HEADER_TYPE_EVENT_HEADER64: u8 = 19;
+const TRACE_HEADER_TYPE_FULL_HEADER64: u8 = 20;
+const TRACE_HEADER_TYPE_INSTANCE64: u8 = 21;
+
+const EVENT_HEADER_FLAG_EXTENDED_INFO: u16 = 0x0001;
+
+/// The clock which the event timestamps come from, from `TRACE_LOGFILE_HEADER.ReservedFlags`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockType {
+    /// QueryPerformanceCounter ticks, at `perf_freq` ticks per second
+    Qpc,
+    /// System time, in 100ns units
+    SystemTime,
+    /// CPU cycles, at `cpu_speed_mhz`
+    CpuCycle,
+}
+
+/// A GUID, laid out like the Windows `GUID` struct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Guid {
+    pub data1: u32,
+    pub data2: u16,
+    pub data3: u16,
+    pub data4: [u8; 8],
+}
+
+impl Guid {
+    pub const fn from_u128(uuid: u128) -> Self {
+        Guid {
+            data1: (uuid >> 96) as u32,
+            data2: ((uuid >> 80) & 0xffff) as u16,
+            data3: ((uuid >> 64) & 0xffff) as u16,
+            data4: (uuid as u64).to_be_bytes(),
+        }
+    }
+
+    /// Returns the bytes of the GUID in memory order, i.e. with the first three fields in
+    /// little-endian byte order.
+    pub fn to_bytes(self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[0..4].copy_from_slice(&self.data1.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.data2.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.data3.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.data4);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Guid {
+            data1: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            data2: u16::from_le_bytes(bytes[4..6].try_into().unwrap()),
+            data3: u16::from_le_bytes(bytes[6..8].try_into().unwrap()),
+            data4: bytes[8..16].try_into().unwrap(),
+        }
+    }
+}
+
+/// The information from the `TRACE_LOGFILE_HEADER` event at the start of the file.
+#[derive(Debug, Clone)]
+pub struct LogfileHeader {
+    pub buffer_size: u32,
+    pub pointer_size: u32,
+    pub number_of_processors: u32,
+    pub cpu_speed_mhz: u32,
+    /// The QPC frequency, in ticks per second
+    pub perf_freq: i64,
+    /// The start of the trace, as a FILETIME (100ns units since 1601)
+    pub start_time: i64,
+    /// The end of the trace, as a FILETIME
+    pub end_time: i64,
+    pub clock_type: ClockType,
+    /// The raw timestamp of the header event, which corresponds to `start_time`
+    pub start_timestamp: i64,
+}
+
+impl LogfileHeader {
+    /// Converts a raw event timestamp into nanoseconds since the start of the trace.
+    /// Events from before the start are clamped to the start.
+    pub fn nanos_since_start(&self, timestamp: i64) -> u64 {
+        let delta = timestamp.saturating_sub(self.start_timestamp).max(0) as u128;
+        let nanos = match self.clock_type {
+            ClockType::Qpc if self.perf_freq > 0 => delta * 1_000_000_000 / self.perf_freq as u128,
+            ClockType::CpuCycle if self.cpu_speed_mhz > 0 => {
+                delta * 1_000 / self.cpu_speed_mhz as u128
+            }
+            _ => delta * 100,
+        };
+        nanos as u64
+    }
+}
+
+/// Where an event comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSource {
+    /// A classic kernel event, identified by its group (`EVENT_TRACE_GROUP_*` >> 8), e.g. 0x03
+    /// for process events or 0x0F for PerfInfo events.
+    KernelGroup(u8),
+    /// An event from a provider or a classic event class with this GUID.
+    Provider(Guid),
+}
+
+/// Kernel event groups, for [EventSource::KernelGroup].
+pub mod kernel_group {
+    pub const HEADER: u8 = 0x00;
+    pub const PROCESS: u8 = 0x03;
+    pub const THREAD: u8 = 0x05;
+    pub const PERFINFO: u8 = 0x0F;
+    pub const IMAGE: u8 = 0x14;
+    pub const STACKWALK: u8 = 0x18;
+}
+
+/// One event record from an .etl file.
+#[derive(Debug, Clone)]
+pub struct EtlEvent<'a> {
+    pub source: EventSource,
+    /// The event id; zero for classic events, which are identified by their opcode.
+    pub event_id: u16,
+    pub opcode: u8,
+    pub version: u16,
+    /// `None` for PerfInfo events, which don't record the process and thread.
+    pub process_id: Option<u32>,
+    pub thread_id: Option<u32>,
+    pub timestamp: i64,
+    /// The processor whose buffer this event was in.
+    pub processor: u8,
+    /// 4 or 8, the size of pointers in the payload.
+    pub pointer_size: usize,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EtlEvent<'a> {
+    pub fn payload_reader(&self) -> PayloadReader<'a> {
+        PayloadReader::new(self.payload, self.pointer_size)
+    }
+}
+
+#[derive(Debug)]
+pub enum EtlError {
+    /// The file is too short to contain a buffer.
+    TooShort,
+    /// The first buffer doesn't start with a `TRACE_LOGFILE_HEADER` event.
+    NoLogfileHeader,
+}
+
+impl fmt::Display for EtlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EtlError::TooShort => write!(f, "The file is too short to be an ETL file"),
+            EtlError::NoLogfileHeader => {
+                write!(f, "The file doesn't start with an ETW logfile header")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EtlError {}
+
+/// An .etl file in memory.
+pub struct EtlFile<'a> {
+    data: &'a [u8],
+    header: LogfileHeader,
+}
+
+impl<'a> EtlFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, EtlError> {
+        if data.len() < BUFFER_HEADER_SIZE {
+            return Err(EtlError::TooShort);
+        }
+        let first_event = Buffers::new(data)
+            .next()
+            .and_then(|buffer| BufferEvents::new(buffer).next())
+            .ok_or(EtlError::NoLogfileHeader)?;
+        if first_event.source != EventSource::KernelGroup(kernel_group::HEADER)
+            || first_event.opcode != 0
+        {
+            return Err(EtlError::NoLogfileHeader);
+        }
+        let header = parse_logfile_header(&first_event).ok_or(EtlError::NoLogfileHeader)?;
+        Ok(EtlFile { data, header })
+    }
+
+    pub fn header(&self) -> &LogfileHeader {
+        &self.header
+    }
+
+    /// Returns all events of the file, ordered by timestamp.
+    ///
+    /// Each buffer only has the events of one processor, so the events in the file aren't in
+    /// order. Events with the same timestamp keep their order in the file.
+    pub fn events(&self) -> Vec<EtlEvent<'a>> {
+        let mut events: Vec<EtlEvent<'a>> = Buffers::new(self.data)
+            .flat_map(BufferEvents::new)
+            .collect();
+        events.sort_by_key(|event| event.timestamp);
+        events
+    }
+}
+
+fn parse_logfile_header(event: &EtlEvent) -> Option<LogfileHeader> {
+    let mut reader = event.payload_reader();
+    let buffer_size = reader.u32()?;
+    let _version = reader.u32()?;
+    let _provider_version = reader.u32()?;
+    let number_of_processors = reader.u32()?;
+    let end_time = reader.i64()?;
+    let _timer_resolution = reader.u32()?;
+    let _maximum_file_size = reader.u32()?;
+    let _log_file_mode = reader.u32()?;
+    let _buffers_written = reader.u32()?;
+    let _start_buffers = reader.u32()?;
+    let pointer_size = reader.u32()?;
+    let _events_lost = reader.u32()?;
+    let cpu_speed_mhz = reader.u32()?;
+    // LoggerName and LogFileName pointers, and a TIME_ZONE_INFORMATION, then padding to 8 bytes.
+    reader.skip(2 * event.pointer_size + 172)?;
+    reader.align(8)?;
+    let _boot_time = reader.i64()?;
+    let perf_freq = reader.i64()?;
+    let start_time = reader.i64()?;
+    let clock_type = match reader.u32()? {
+        2 => ClockType::SystemTime,
+        3 => ClockType::CpuCycle,
+        _ => ClockType::Qpc,
+    };
+    Some(LogfileHeader {
+        buffer_size,
+        pointer_size,
+        number_of_processors,
+        cpu_speed_mhz,
+        perf_freq,
+        start_time,
+        end_time,
+        clock_type,
+        start_timestamp: event.timestamp,
+    })
+}
+
+/// An iterator over the buffers of a file.
+struct Buffers<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Buffers<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Buffers { data }
+    }
+}
+
+impl<'a> Iterator for Buffers<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let size = read_u32(self.data, 0)? as usize;
+        if size < BUFFER_HEADER_SIZE || size > self.data.len() {
+            return None;
+        }
+        let (buffer, rest) = self.data.split_at(size);
+        self.data = rest;
+        Some(buffer)
+    }
+}
+
+/// An iterator over the events in one buffer.
+struct BufferEvents<'a> {
+    buffer: &'a [u8],
+    processor: u8,
+    offset: usize,
+    end: usize,
+}
+
+impl<'a> BufferEvents<'a> {
+    fn new(buffer: &'a [u8]) -> Self {
+        // The "Offset" field has the number of bytes which are filled with events.
+        let filled = read_u32(buffer, 0x30).unwrap_or(0) as usize;
+        let end = if (BUFFER_HEADER_SIZE..=buffer.len()).contains(&filled) {
+            filled
+        } else {
+            buffer.len()
+        };
+        BufferEvents {
+            buffer,
+            processor: buffer[0x28],
+            offset: BUFFER_HEADER_SIZE,
+            end,
+        }
+    }
+
+    /// Parses the event at offset `p`, and returns it together with the offset of the next
+    /// event. Returns `None` at the end of the events, and `Some((None, _))` for records
+    /// which are skipped.
+    fn parse_event(&self, p: usize) -> Option<(Option<EtlEvent<'a>>, usize)> {
+        let b = &self.buffer[..self.end];
+        let marker = read_u32(b, p)?;
+        if marker == 0 || marker == u32::MAX {
+            return None;
+        }
+        let header_type = b[p + 2];
+        if b[p + 3] & TRACE_HEADER_FLAG == 0 {
+            return None;
+        }
+        let pointer_size = match header_type {
+            TRACE_HEADER_TYPE_SYSTEM32
+            | TRACE_HEADER_TYPE_COMPACT32
+            | TRACE_HEADER_TYPE_FULL_HEADER32
+            | TRACE_HEADER_TYPE_INSTANCE32
+            | TRACE_HEADER_TYPE_PERFINFO32
+            | TRACE_HEADER_TYPE_EVENT_HEADER32 => 4,
+            _ => 8,
+        };
+        let (event, header_len, size) = match header_type {
+            TRACE_HEADER_TYPE_SYSTEM32
+            | TRACE_HEADER_TYPE_SYSTEM64
+            | TRACE_HEADER_TYPE_COMPACT32
+            | TRACE_HEADER_TYPE_COMPACT64 => {
+                let version = read_u16(b, p)?;
+                let size = read_u16(b, p + 4)? as usize;
+                let hook_id = read_u16(b, p + 6)?;
+                let header_len = match header_type {
+                    TRACE_HEADER_TYPE_SYSTEM32 | TRACE_HEADER_TYPE_SYSTEM64 => 32,
+                    _ => 24,
+                };
+                let event = EtlEvent {
+                    source: EventSource::KernelGroup((hook_id >> 8) as u8),
+                    event_id: 0,
+                    opcode: hook_id as u8,
+                    version,
+                    thread_id: Some(read_u32(b, p + 8)?),
+                    process_id: Some(read_u32(b, p + 12)?),
+                    timestamp: read_i64(b, p + 16)?,
+                    processor: self.processor,
+                    pointer_size,
+                    payload: &[],
+                };
+                (event, header_len, size)
+            }
+            TRACE_HEADER_TYPE_PERFINFO32 | TRACE_HEADER_TYPE_PERFINFO64 => {
+                let version = read_u16(b, p)?;
+                let size = read_u16(b, p + 4)? as usize;
+                let hook_id = read_u16(b, p + 6)?;
+                let event = EtlEvent {
+                    source: EventSource::KernelGroup((hook_id >> 8) as u8),
+                    event_id: 0,
+                    opcode: hook_id as u8,
+                    version,
+                    thread_id: None,
+                    process_id: None,
+                    timestamp: read_i64(b, p + 8)?,
+                    processor: self.processor,
+                    pointer_size,
+                    payload: &[],
+                };
+                (event, 16, size)
+            }
+            TRACE_HEADER_TYPE_EVENT_HEADER32 | TRACE_HEADER_TYPE_EVENT_HEADER64 => {
+                let size = read_u16(b, p)? as usize;
+                let flags = read_u16(b, p + 4)?;
+                let provider = Guid::from_bytes(b.get(p + 24..p + 40)?);
+                let mut header_len = 80;
+                if flags & EVENT_HEADER_FLAG_EXTENDED_INFO != 0 {
+                    // Each extended data item has an 8 byte header (Reserved1, ExtType,
+                    // Linkage, DataSize), followed by its 8-byte aligned data. The Linkage
+                    // bit says whether another item follows.
+                    loop {
+                        let linkage = read_u16(b, p + header_len + 4)?;
+                        let data_size = read_u16(b, p + header_len + 6)? as usize;
+                        header_len += 8 + align8(data_size);
+                        if linkage & 1 == 0 {
+                            break;
+                        }
+                    }
+                }
+                let event = EtlEvent {
+                    source: EventSource::Provider(provider),
+                    event_id: read_u16(b, p + 40)?,
+                    version: *b.get(p + 42)? as u16,
+                    opcode: *b.get(p + 45)?,
+                    thread_id: Some(read_u32(b, p + 8)?),
+                    process_id: Some(read_u32(b, p + 12)?),
+                    timestamp: read_i64(b, p + 16)?,
+                    processor: self.processor,
+                    pointer_size,
+                    payload: &[],
+                };
+                (event, header_len, size)
+            }
+            TRACE_HEADER_TYPE_FULL_HEADER32
+            | TRACE_HEADER_TYPE_FULL_HEADER64
+            | TRACE_HEADER_TYPE_INSTANCE32
+            | TRACE_HEADER_TYPE_INSTANCE64 => {
+                let size = read_u16(b, p)? as usize;
+                let header_len = match header_type {
+                    TRACE_HEADER_TYPE_FULL_HEADER32 | TRACE_HEADER_TYPE_FULL_HEADER64 => 48,
+                    _ => 72,
+                };
+                let event = EtlEvent {
+                    source: EventSource::Provider(Guid::from_bytes(b.get(p + 24..p + 40)?)),
+                    event_id: 0,
+                    opcode: *b.get(p + 4)?,
+                    version: read_u16(b, p + 6)?,
+                    thread_id: Some(read_u32(b, p + 8)?),
+                    process_id: Some(read_u32(b, p + 12)?),
+                    timestamp: read_i64(b, p + 16)?,
+                    processor: self.processor,
+                    pointer_size,
+                    payload: &[],
+                };
+                (event, header_len, size)
+            }
+            _ => {
+                // Other header types (WNODE, message and error headers) start with their size.
+                // They're skipped.
+                let size = read_u16(b, p)? as usize;
+                if size < 4 || p + size > b.len() {
+                    return None;
+                }
+                return Some((None, p + align8(size)));
+            }
+        };
+        if size < header_len || p + size > b.len() {
+            return None;
+        }
+        let event = EtlEvent {
+            payload: &b[p + header_len..p + size],
+            ..event
+        };
+        Some((Some(event), p + align8(size)))
+    }
+}
+
+impl<'a> Iterator for BufferEvents<'a> {
+    type Item = EtlEvent<'a>;
+
+    fn next(&mut self) -> Option<EtlEvent<'a>> {
+        while self.offset + 4 <= self.end {
+            match self.parse_event(self.offset) {
+                Some((event, next_offset)) => {
+                    self.offset = next_offset;
+                    if event.is_some() {
+                        return event;
+                    }
+                }
+                None => break,
+            }
+        }
+        self.offset = self.end;
+        None
+    }
+}
+
+/// Reads the fields of an event payload one after the other.
+#[derive(Debug, Clone)]
+pub struct PayloadReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    pointer_size: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    pub fn new(data: &'a [u8], pointer_size: usize) -> Self {
+        PayloadReader {
+            data,
+            offset: 0,
+            pointer_size,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    pub fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    pub fn align(&mut self, alignment: usize) -> Option<()> {
+        let padding = (alignment - self.offset % alignment) % alignment;
+        self.skip(padding)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    pub fn i64(&mut self) -> Option<i64> {
+        Some(self.u64()? as i64)
+    }
+
+    /// Reads a pointer-sized value.
+    pub fn pointer(&mut self) -> Option<u64> {
+        match self.pointer_size {
+            4 => self.u32().map(u64::from),
+            _ => self.u64(),
+        }
+    }
+
+    pub fn guid(&mut self) -> Option<Guid> {
+        Some(Guid::from_bytes(self.take(16)?))
+    }
+
+    /// Reads a null-terminated 8-bit string.
+    pub fn ansi_string(&mut self) -> Option<String> {
+        let rest = &self.data[self.offset..];
+        let len = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+        let s = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.offset += (len + 1).min(rest.len());
+        Some(s)
+    }
+
+    /// Reads a null-terminated UTF-16 string.
+    pub fn utf16_string(&mut self) -> Option<String> {
+        let mut units = Vec::new();
+        while let Some(unit) = self.u16() {
+            if unit == 0 {
+                break;
+            }
+            units.push(unit);
+        }
+        Some(String::from_utf16_lossy(&units))
+    }
+
+    /// Skips a SID in the layout of the kernel's MOF events: a `TOKEN_USER` (two pointers)
+    /// followed by the SID, or just a zero `u32` if there's no SID.
+    pub fn skip_sid(&mut self) -> Option<()> {
+        if read_u32(self.data, self.offset)? == 0 {
+            return self.skip(4);
+        }
+        let token_size = 2 * self.pointer_size;
+        let sub_authority_count = *self.data.get(self.offset + token_size + 1)? as usize;
+        self.skip(token_size + 8 + 4 * sub_authority_count)
+    }
+
+    /// Reads pointer-sized values until the end of the payload.
+    pub fn remaining_pointers(&mut self) -> Vec<u64> {
+        let mut pointers = Vec::new();
+        while let Some(pointer) = self.pointer() {
+            pointers.push(pointer);
+        }
+        pointers
+    }
+}
+
+fn align8(n: usize) -> usize {
+    (n + 7) & !7
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_i64(data: &[u8], offset: usize) -> Option<i64> {
+    Some(i64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
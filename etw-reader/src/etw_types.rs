@@ -28,6 +28,26 @@ impl EventRecord {
         }
         unsafe { std::slice::from_raw_parts(self.UserData as *mut _, self.UserDataLength.into()) }
     }
+
+    /// Returns the stack addresses from the event's 64-bit stack trace extended data
+    /// item, if the event was logged by a provider which was enabled with stack walking.
+    pub fn stack_trace64(&self) -> Option<&[u64]> {
+        if self.ExtendedDataCount == 0 || self.ExtendedData.is_null() {
+            return None;
+        }
+        let extended = unsafe {
+            std::slice::from_raw_parts(self.ExtendedData, self.ExtendedDataCount as usize)
+        };
+        let item = extended
+            .iter()
+            .find(|e| e.ExtType as u32 == Etw::EVENT_HEADER_EXT_TYPE_STACK_TRACE64)?;
+        // EVENT_EXTENDED_ITEM_STACK_TRACE64 is a u64 MatchId followed by the addresses.
+        let address_count = (item.DataSize as usize).checked_sub(8)? / 8;
+        let addresses = unsafe {
+            std::slice::from_raw_parts((item.DataPtr as *const u64).add(1), address_count)
+        };
+        Some(addresses)
+    }
 }
 
 /// Newtype wrapper over an [EVENT_PROPERTY_INFO]
@@ -1,16 +1,20 @@
 //! ETW Types Parser
 //!
 //! This module act as a helper to parse the Buffer from an ETW Event
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
 use std::convert::TryInto;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+use std::time::{Duration, SystemTime};
 
 use windows::core::GUID;
 
 use super::etw_types::EVENT_HEADER_FLAG_32_BIT_HEADER;
 use super::property::{PropertyInfo, PropertyIter};
 use super::schema::TypedEvent;
-use super::tdh_types::{Property, PropertyDesc, PropertyLength, TdhInType, TdhOutType};
+use super::tdh_types::{
+    PrimitiveDesc, Property, PropertyDesc, PropertyFlags, PropertyLength, TdhInType, TdhOutType,
+};
 use super::{tdh, utils};
 
 #[derive(Debug, Clone, Copy)]
@@ -40,6 +44,8 @@ pub enum ParserError {
     PropertyError(String),
     /// An error while transforming an Utf-8 buffer into String
     Utf8Error(std::string::FromUtf8Error),
+    /// An error while borrowing an Utf-8 buffer as a str
+    Utf8StrError(std::str::Utf8Error),
     /// An error trying to get an slice as an array
     SliceError(std::array::TryFromSliceError),
     /// Represents an internal [SddlNativeError]
@@ -70,6 +76,12 @@ impl From<std::string::FromUtf8Error> for ParserError {
     }
 }
 
+impl From<std::str::Utf8Error> for ParserError {
+    fn from(err: std::str::Utf8Error) -> Self {
+        ParserError::Utf8StrError(err)
+    }
+}
+
 impl From<std::array::TryFromSliceError> for ParserError {
     fn from(err: std::array::TryFromSliceError) -> Self {
         ParserError::SliceError(err)
@@ -108,6 +120,9 @@ pub trait TryParse<T> {
 pub struct Parser<'a> {
     event: &'a TypedEvent<'a>,
     properties: &'a PropertyIter,
+    /// The indexes of the properties which this parser reads: the top-level properties of the
+    /// event, or the members of a struct for the parsers from [Parser::try_parse_structs].
+    property_range: Range<usize>,
     pub buffer: &'a [u8],
     last_property: u32,
     offset: usize,
@@ -129,13 +144,16 @@ impl<'a> Parser<'a> {
     /// };
     /// ```
     pub fn create(event: &'a TypedEvent) -> Self {
+        let properties = event.schema.properties();
         Parser {
             event,
             buffer: event.user_buffer(),
-            properties: event.schema.properties(),
+            properties,
+            property_range: properties.top_level(),
             last_property: 0,
             offset: 0,
-            cache: Vec::new(), // We could fill the cache on creation
+            // Allocate the cache once, instead of growing it property by property.
+            cache: Vec::with_capacity(properties.top_level().len()),
         }
     }
     /*
@@ -171,65 +189,117 @@ impl<'a> Parser<'a> {
         )?)
     }*/
 
-    // TODO: Find a cleaner way to do this, not very happy with it rn
+    /// The properties which this parser reads, i.e. the top-level properties of the event or the
+    /// members of a struct.
+    pub fn properties(&self) -> &'a [Property] {
+        self.properties.properties(self.property_range.clone())
+    }
+
+    fn pointer_size(&self) -> usize {
+        if (self.event.event_flags() & EVENT_HEADER_FLAG_32_BIT_HEADER) != 0 {
+            4
+        } else {
+            8
+        }
+    }
+
     fn find_property_size(&self, property: &Property) -> ParserResult<usize> {
-        match property.length {
-            PropertyLength::Index(_) => {
-                // e.g. Microsoft-Windows-Kernel-Power/SystemTimerResolutionStackRundown uses the AppNameLength property
-                // as the size of AppName
+        let lookup = |index: usize| self.cached_value(index);
+        if let Some(size) = self.property_size(property, self.buffer, &lookup) {
+            return Ok(size);
+        }
+        if self.property_range != self.properties.top_level() {
+            return Err(ParserError::PropertyError(format!(
+                "Unknown size of struct member {}",
+                property.name
+            )));
+        }
+        // Fallback to Tdh
+        Ok(tdh::property_size(self.event.record(), &property.name)? as usize)
+    }
+
+    /// Returns the integer value of the property with the absolute index `index`, if it has been
+    /// parsed already. Lengths and counts refer to properties before them.
+    fn cached_value(&self, index: usize) -> Option<u64> {
+        let cache_index = index.checked_sub(self.property_range.start)?;
+        int_value(self.cache.get(cache_index)?.buffer)
+    }
+
+    /// Computes the size of `property` at the start of `buffer`, from its type, its length and
+    /// count, which can be the values of earlier properties, and, for variable-length types, from
+    /// the data itself. Returns `None` if the size can't be determined this way.
+    fn property_size(
+        &self,
+        property: &Property,
+        buffer: &[u8],
+        lookup: &dyn Fn(usize) -> Option<u64>,
+    ) -> Option<usize> {
+        let count = if property.flags.contains(PropertyFlags::PROPERTY_PARAM_COUNT) {
+            lookup(property.count as usize)? as usize
+        } else if property
+            .flags
+            .contains(PropertyFlags::PROPERTY_PARAM_FIXED_COUNT)
+        {
+            property.count as usize
+        } else {
+            property.count.max(1) as usize
+        };
+        let mut size = 0;
+        for _ in 0..count {
+            size += self.element_size(property, buffer.get(size..)?, lookup)?;
+        }
+        Some(size)
+    }
 
-                // Fallback to Tdh
-                Ok(tdh::property_size(self.event.record(), &property.name).unwrap() as usize)
+    /// Computes the size of one element of `property` at the start of `buffer`.
+    fn element_size(
+        &self,
+        property: &Property,
+        buffer: &[u8],
+        lookup: &dyn Fn(usize) -> Option<u64>,
+    ) -> Option<usize> {
+        match &property.desc {
+            PropertyDesc::Primitive(desc) => {
+                let length = match property.length {
+                    // e.g. Microsoft-Windows-Kernel-Power/SystemTimerResolutionStackRundown uses
+                    // the AppNameLength property as the size of AppName
+                    PropertyLength::Index(index) => Some(lookup(index as usize)? as usize),
+                    PropertyLength::Length(0) => None,
+                    PropertyLength::Length(length) => Some(length as usize),
+                };
+                primitive_size(desc, length, buffer, self.pointer_size())
             }
-            PropertyLength::Length(length) => {
-                // TODO: Study heuristic method used in krabsetw :)
-                if property.flags.is_empty() && length > 0 && property.count == 1 {
-                    return Ok(length as usize);
-                }
-                if property.count == 1 {
-                    if let PropertyDesc::Primitive(desc) = &property.desc {
-                        match desc.in_type {
-                            TdhInType::InTypeBoolean => return Ok(4),
-                            TdhInType::InTypeInt32
-                            | TdhInType::InTypeUInt32
-                            | TdhInType::InTypeHexInt32 => return Ok(4),
-                            TdhInType::InTypeInt64
-                            | TdhInType::InTypeUInt64
-                            | TdhInType::InTypeHexInt64 => return Ok(8),
-                            TdhInType::InTypeInt8 | TdhInType::InTypeUInt8 => return Ok(1),
-                            TdhInType::InTypeInt16 | TdhInType::InTypeUInt16 => return Ok(2),
-                            TdhInType::InTypePointer => {
-                                return Ok(
-                                    if (self.event.event_flags() & EVENT_HEADER_FLAG_32_BIT_HEADER)
-                                        != 0
-                                    {
-                                        4
-                                    } else {
-                                        8
-                                    },
-                                )
-                            }
-                            TdhInType::InTypeGuid => return Ok(std::mem::size_of::<GUID>()),
-                            TdhInType::InTypeUnicodeString => {
-                                return Ok(utils::parse_unk_size_null_unicode_size(self.buffer))
-                            }
-                            TdhInType::InTypeAnsiString => {
-                                return Ok(utils::parse_unk_size_null_ansi_size(self.buffer));
-                            }
-                            _ => {}
-                        }
-                    }
+            PropertyDesc::Struct(desc) => {
+                // The lengths and counts of struct members refer to other members of the same
+                // struct element.
+                let mut members: Vec<(usize, &[u8])> = Vec::new();
+                let mut size = 0;
+                for index in self.properties.struct_members(desc) {
+                    let member = self.properties.property(index)?;
+                    let member_lookup = |i: usize| {
+                        let (_, buffer) = members.iter().find(|(j, _)| *j == i)?;
+                        int_value(buffer)
+                    };
+                    let member_size =
+                        self.property_size(member, buffer.get(size..)?, &member_lookup)?;
+                    members.push((index, &buffer[size..size + member_size]));
+                    size += member_size;
                 }
-                Ok(tdh::property_size(self.event.record(), &property.name).unwrap() as usize)
+                Some(size)
             }
         }
     }
 
+    fn property_index(&self, name: &str) -> Option<usize> {
+        if self.property_range == self.properties.top_level() {
+            return self.properties.name_to_indx.get(name).copied();
+        }
+        self.properties().iter().position(|p| p.name == name)
+    }
+
     pub fn find_property(&mut self, name: &str) -> ParserResult<usize> {
-        let indx = *self
-            .properties
-            .name_to_indx
-            .get(name)
+        let indx = self
+            .property_index(name)
             .ok_or_else(|| ParserError::PropertyError(format!("Unknown property: {}", name)))?;
         if indx < self.cache.len() {
             return Ok(indx);
@@ -238,7 +308,10 @@ impl<'a> Parser<'a> {
         // TODO: Find a way to do this with an iter, try_find looks promising but is not stable yet
         // TODO: Clean this a bit, not a big fan of this loop
         for i in self.cache.len()..=indx {
-            let curr_prop = self.properties.property(i).unwrap();
+            let curr_prop = self
+                .properties
+                .property(self.property_range.start + i)
+                .unwrap();
 
             let prop_size = self.find_property_size(curr_prop)?;
 
@@ -260,6 +333,191 @@ impl<'a> Parser<'a> {
         }
         Ok(indx)
     }
+
+    /// Returns a parser for each element of the struct property `name`, which can be an array of
+    /// structs. The members of each element are parsed with its parser, by their names.
+    ///
+    /// # Example
+    /// ```rust
+    /// let my_callback = |record: EventRecord, schema_locator: &mut SchemaLocator| {
+    ///     let schema = schema_locator.event_schema(record)?;
+    ///     let mut parser = Parser::create(&schema);
+    ///     for mut entry in parser.try_parse_structs("Entries")? {
+    ///         let address: u64 = entry.try_parse("Address")?;
+    ///     }
+    /// };
+    /// ```
+    pub fn try_parse_structs(&mut self, name: &str) -> ParserResult<Vec<Parser<'a>>> {
+        let indx = self.find_property(name)?;
+        let prop_info = self.cache[indx].clone();
+        let PropertyDesc::Struct(desc) = &prop_info.property.desc else {
+            return Err(ParserError::InvalidType);
+        };
+        let mut elements = Vec::new();
+        let mut offset = 0;
+        while offset < prop_info.buffer.len() {
+            let rest = &prop_info.buffer[offset..];
+            let size = match self.element_size(prop_info.property, rest, &|_| None) {
+                Some(size) if size > 0 => size,
+                _ => return Err(ParserError::LengthMismatch),
+            };
+            elements.push(Parser {
+                event: self.event,
+                properties: self.properties,
+                property_range: self.properties.struct_members(desc),
+                buffer: &rest[..size],
+                last_property: 0,
+                offset: prop_info.offset + offset,
+                cache: Vec::with_capacity(desc.num_members as usize),
+            });
+            offset += size;
+        }
+        Ok(elements)
+    }
+}
+
+/// Reads a length or count from the buffer of an integer property.
+fn int_value(buffer: &[u8]) -> Option<u64> {
+    Some(match buffer.len() {
+        1 => buffer[0] as u64,
+        2 => u16::from_ne_bytes(buffer.try_into().ok()?) as u64,
+        4 => u32::from_ne_bytes(buffer.try_into().ok()?) as u64,
+        8 => u64::from_ne_bytes(buffer.try_into().ok()?),
+        _ => return None,
+    })
+}
+
+/// The size of a SID at the start of `buffer`: 8 bytes, followed by the sub-authorities.
+fn sid_size(buffer: &[u8]) -> Option<usize> {
+    let sub_authority_count = *buffer.get(1)? as usize;
+    Some(8 + 4 * sub_authority_count)
+}
+
+/// The size of a WBEM SID, which is a `TOKEN_USER` (two pointers) followed by the SID, or a zero
+/// `u32` if there's no SID.
+fn wbem_sid_size(buffer: &[u8], pointer_size: usize) -> Option<usize> {
+    if buffer.get(..4)? == [0; 4] {
+        return Some(4);
+    }
+    Some(2 * pointer_size + sid_size(buffer.get(2 * pointer_size..)?)?)
+}
+
+/// Computes the size of one element of a primitive type. `length` is the explicit length of the
+/// property, which is in characters for strings.
+fn primitive_size(
+    desc: &PrimitiveDesc,
+    length: Option<usize>,
+    buffer: &[u8],
+    pointer_size: usize,
+) -> Option<usize> {
+    use TdhInType::*;
+    // The length prefix of counted strings is in bytes.
+    let counted_length = |bytes: [u8; 2], big_endian: bool| {
+        let length = if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        };
+        2 + length as usize
+    };
+    Some(match desc.in_type {
+        InTypeNull => 0,
+        InTypeUnicodeString => match length {
+            Some(length) => length * 2,
+            None => utils::parse_unk_size_null_unicode_size(buffer),
+        },
+        InTypeAnsiString => match length {
+            Some(length) => length,
+            None => utils::parse_unk_size_null_ansi_size(buffer),
+        },
+        // These fill the rest of the event if they don't have a length.
+        InTypeNonNullTerminatedString => length.map_or(buffer.len(), |length| length * 2),
+        InTypeNonNullTerminatedAnsiString => length.unwrap_or(buffer.len()),
+        InTypeCountedString | InTypeCountedAnsiString => {
+            counted_length(buffer.get(..2)?.try_into().ok()?, false)
+        }
+        InTypeReverseCountedString | InTypeReverseCountedAnsiString => {
+            counted_length(buffer.get(..2)?.try_into().ok()?, true)
+        }
+        InTypeInt8 | InTypeUInt8 | InTypeAnsiChar => 1,
+        InTypeInt16 | InTypeUInt16 | InTypeUnicodeChar => 2,
+        InTypeInt32 | InTypeUInt32 | InTypeHexInt32 | InTypeFloat | InTypeBoolean => 4,
+        InTypeInt64 | InTypeUInt64 | InTypeHexInt64 | InTypeDouble | InTypeFileTime => 8,
+        InTypeGuid => std::mem::size_of::<GUID>(),
+        InTypeSystemTime => 16,
+        InTypePointer | InTypeSizeT => pointer_size,
+        InTypeSid => sid_size(buffer)?,
+        InTypeWBEMSID => wbem_sid_size(buffer, pointer_size)?,
+        InTypeHexdump => 4 + u32::from_le_bytes(buffer.get(..4)?.try_into().ok()?) as usize,
+        InTypeBinary => match (length, desc.out_type) {
+            (Some(length), _) => length,
+            (None, TdhOutType::OutTypeIpv6) => 16,
+            (None, _) => return None,
+        },
+    })
+}
+
+/// Formats a binary SID like `S-1-5-21-...`, without the Windows APIs.
+fn sid_to_string(sid: &[u8]) -> Option<String> {
+    let revision = *sid.first()?;
+    let sub_authority_count = *sid.get(1)? as usize;
+    let authority = sid
+        .get(2..8)?
+        .iter()
+        .fold(0u64, |authority, byte| (authority << 8) | *byte as u64);
+    let mut s = format!("S-{revision}-{authority}");
+    for i in 0..sub_authority_count {
+        let offset = 8 + 4 * i;
+        let sub_authority = u32::from_le_bytes(sid.get(offset..offset + 4)?.try_into().ok()?);
+        s.push_str(&format!("-{sub_authority}"));
+    }
+    Some(s)
+}
+
+/// Removes the null terminator and any null padding from a string buffer.
+fn trim_nulls(buffer: &[u8]) -> &[u8] {
+    let end = buffer.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    let start = buffer[..end].iter().position(|b| *b != 0).unwrap_or(end);
+    &buffer[start..end]
+}
+
+/// Decodes one string element of a string property. 8-bit strings are borrowed from the event
+/// buffer; UTF-16 strings and SIDs need to be converted.
+fn parse_string<'a>(
+    desc: &PrimitiveDesc,
+    buffer: &'a [u8],
+    pointer_size: usize,
+) -> ParserResult<Cow<'a, str>> {
+    use TdhInType::*;
+    let res = match desc.in_type {
+        InTypeUnicodeString | InTypeNonNullTerminatedString | InTypeUnicodeChar => {
+            Cow::Owned(utils::parse_null_utf16_string(buffer))
+        }
+        InTypeAnsiString | InTypeNonNullTerminatedAnsiString | InTypeAnsiChar => {
+            Cow::Borrowed(std::str::from_utf8(trim_nulls(buffer))?)
+        }
+        InTypeCountedString | InTypeReverseCountedString => Cow::Owned(
+            utils::parse_null_utf16_string(buffer.get(2..).unwrap_or_default()),
+        ),
+        InTypeCountedAnsiString | InTypeReverseCountedAnsiString => {
+            String::from_utf8_lossy(trim_nulls(buffer.get(2..).unwrap_or_default()))
+        }
+        InTypeSid => Cow::Owned(sid_to_string(buffer).ok_or(ParserError::LengthMismatch)?),
+        InTypeWBEMSID => {
+            if buffer.len() == 4 {
+                // No SID
+                Cow::Borrowed("")
+            } else {
+                let sid = buffer
+                    .get(2 * pointer_size..)
+                    .and_then(sid_to_string)
+                    .ok_or(ParserError::LengthMismatch)?;
+                Cow::Owned(sid)
+            }
+        }
+        _ => return Err(ParserError::InvalidType),
+    };
+    Ok(res)
 }
 
 /*
@@ -478,29 +736,63 @@ impl TryParse<f32> for Parser<'_> {
 /// [TdhInTypes]: TdhInType
 impl TryParse<String> for Parser<'_> {
     fn try_parse(&mut self, name: &str) -> ParserResult<String> {
+        TryParse::<Cow<str>>::try_parse(self, name).map(Cow::into_owned)
+    }
+}
+
+/// The `Cow<str>` impl of the `TryParse` trait retrieves the same string types as the `String`
+/// impl, but without copying 8-bit strings out of the event buffer. Prefer it for strings which
+/// are only looked at while handling the event.
+impl<'a> TryParse<Cow<'a, str>> for Parser<'a> {
+    fn try_parse(&mut self, name: &str) -> ParserResult<Cow<'a, str>> {
         let indx = self.find_property(name)?;
         let prop_info = &self.cache[indx];
 
-        // TODO: Handle errors and type checking better
         if let PropertyDesc::Primitive(desc) = &prop_info.property.desc {
-            let res = match desc.in_type {
-                TdhInType::InTypeUnicodeString => utils::parse_null_utf16_string(prop_info.buffer),
-                TdhInType::InTypeAnsiString => String::from_utf8(prop_info.buffer.to_vec())?
-                    .trim_matches(char::default())
-                    .to_string(),
-                TdhInType::InTypeSid => {
-                    panic!()
-                    //sddl::convert_sid_to_string(prop_info.buffer.as_ptr() as isize)?
-                }
-                TdhInType::InTypeCountedString => unimplemented!(),
-                _ => return Err(ParserError::InvalidType),
-            };
-            return Ok(res);
+            return parse_string(desc, prop_info.buffer, self.pointer_size());
         }
         Err(ParserError::InvalidType)
     }
 }
 
+/// The `&str` impl of the `TryParse` trait borrows 8-bit strings (InTypeAnsiString, which is also
+/// used for UTF-8) from the event buffer. It fails for UTF-16 strings, which can't be borrowed.
+impl<'a> TryParse<&'a str> for Parser<'a> {
+    fn try_parse(&mut self, name: &str) -> ParserResult<&'a str> {
+        match TryParse::<Cow<'a, str>>::try_parse(self, name)? {
+            Cow::Borrowed(s) => Ok(s),
+            Cow::Owned(_) => Err(ParserError::InvalidType),
+        }
+    }
+}
+
+/// The `Vec<String>` impl of the `TryParse` trait retrieves the elements of an array of strings,
+/// of any of the string [TdhInTypes] which the `String` impl accepts.
+///
+/// [TdhInTypes]: TdhInType
+impl TryParse<Vec<String>> for Parser<'_> {
+    fn try_parse(&mut self, name: &str) -> ParserResult<Vec<String>> {
+        let indx = self.find_property(name)?;
+        let prop_info = self.cache[indx].clone();
+        let PropertyDesc::Primitive(desc) = &prop_info.property.desc else {
+            return Err(ParserError::InvalidType);
+        };
+        let lookup = |index: usize| self.cached_value(index);
+        let mut strings = Vec::new();
+        let mut rest = prop_info.buffer;
+        while !rest.is_empty() {
+            let size = match self.element_size(prop_info.property, rest, &lookup) {
+                Some(size) if size > 0 && size <= rest.len() => size,
+                _ => return Err(ParserError::LengthMismatch),
+            };
+            let (element, remaining) = rest.split_at(size);
+            strings.push(parse_string(desc, element, self.pointer_size())?.into_owned());
+            rest = remaining;
+        }
+        Ok(strings)
+    }
+}
+
 impl TryParse<GUID> for Parser<'_> {
     fn try_parse(&mut self, name: &str) -> Result<GUID, ParserError> {
         let indx = self.find_property(name)?;
@@ -552,13 +844,14 @@ impl TryParse<IpAddr> for Parser<'_> {
                 return Err(ParserError::InvalidType);
             }
 
-            // Hardcoded values for now
-            let res = match prop_info.property.length {
-                PropertyLength::Length(16) => {
+            // IPv4 addresses are UInt32s and IPv6 addresses are 16 bytes of Binary, both in
+            // network byte order.
+            let res = match prop_info.buffer.len() {
+                16 => {
                     let tmp: [u8; 16] = prop_info.buffer.try_into()?;
                     IpAddr::V6(Ipv6Addr::from(tmp))
                 }
-                PropertyLength::Length(4) => {
+                4 => {
                     let tmp: [u8; 4] = prop_info.buffer.try_into()?;
                     IpAddr::V4(Ipv4Addr::from(tmp))
                 }
@@ -571,6 +864,145 @@ impl TryParse<IpAddr> for Parser<'_> {
     }
 }
 
+impl TryParse<f64> for Parser<'_> {
+    fn try_parse(&mut self, name: &str) -> ParserResult<f64> {
+        use TdhInType::*;
+        let indx = self.find_property(name)?;
+        let prop_info = &self.cache[indx];
+        if let PropertyDesc::Primitive(desc) = &prop_info.property.desc {
+            if desc.in_type == InTypeDouble {
+                if std::mem::size_of::<f64>() != prop_info.buffer.len() {
+                    return Err(ParserError::LengthMismatch);
+                }
+                return Ok(f64::from_ne_bytes(prop_info.buffer.try_into()?));
+            }
+        }
+        Err(ParserError::InvalidType)
+    }
+}
+
+/// The number of 100ns intervals between 1601-01-01, the FILETIME epoch, and 1970-01-01.
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// Returns the number of days between 1970-01-01 and the given date of the proleptic Gregorian
+/// calendar.
+fn days_since_unix_epoch(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The `SystemTime` impl of the `TryParse` trait should be used to retrieve FILETIME
+/// (InTypeFileTime) and SYSTEMTIME (InTypeSystemTime) properties, which are both in UTC.
+impl TryParse<SystemTime> for Parser<'_> {
+    fn try_parse(&mut self, name: &str) -> ParserResult<SystemTime> {
+        let indx = self.find_property(name)?;
+        let prop_info = &self.cache[indx];
+        let PropertyDesc::Primitive(desc) = &prop_info.property.desc else {
+            return Err(ParserError::InvalidType);
+        };
+        match desc.in_type {
+            TdhInType::InTypeFileTime => {
+                if prop_info.buffer.len() != 8 {
+                    return Err(ParserError::LengthMismatch);
+                }
+                let filetime = u64::from_ne_bytes(prop_info.buffer.try_into()?);
+                let since_unix_epoch =
+                    filetime.checked_sub(FILETIME_UNIX_EPOCH).ok_or_else(|| {
+                        ParserError::PropertyError(format!("FILETIME before 1970: {filetime}"))
+                    })?;
+                Ok(SystemTime::UNIX_EPOCH + Duration::from_nanos(since_unix_epoch * 100))
+            }
+            TdhInType::InTypeSystemTime => {
+                if prop_info.buffer.len() != 16 {
+                    return Err(ParserError::LengthMismatch);
+                }
+                // wYear, wMonth, wDayOfWeek, wDay, wHour, wMinute, wSecond, wMilliseconds
+                let fields: Vec<i64> = prop_info
+                    .buffer
+                    .chunks_exact(2)
+                    .map(|field| u16::from_ne_bytes([field[0], field[1]]) as i64)
+                    .collect();
+                let days = days_since_unix_epoch(fields[0], fields[1], fields[3]);
+                let millis = ((days * 24 + fields[4]) * 60 + fields[5]) * 60_000
+                    + fields[6] * 1000
+                    + fields[7];
+                let millis = u64::try_from(millis).map_err(|_| {
+                    ParserError::PropertyError(format!("SYSTEMTIME before 1970: {fields:?}"))
+                })?;
+                Ok(SystemTime::UNIX_EPOCH + Duration::from_millis(millis))
+            }
+            _ => Err(ParserError::InvalidType),
+        }
+    }
+}
+
+macro_rules! impl_try_parse_primitive_array {
+    ($T:ident, $ty:ident) => {
+        impl TryParse<Vec<$T>> for Parser<'_> {
+            fn try_parse(&mut self, name: &str) -> ParserResult<Vec<$T>> {
+                use TdhInType::*;
+                let indx = self.find_property(name)?;
+                let prop_info = &self.cache[indx];
+                if let PropertyDesc::Primitive(desc) = &prop_info.property.desc {
+                    if desc.in_type != $ty {
+                        return Err(ParserError::InvalidType);
+                    }
+                    if prop_info.buffer.len() % std::mem::size_of::<$T>() != 0 {
+                        return Err(ParserError::LengthMismatch);
+                    }
+                    return prop_info
+                        .buffer
+                        .chunks_exact(std::mem::size_of::<$T>())
+                        .map(|element| -> ParserResult<$T> {
+                            Ok($T::from_ne_bytes(element.try_into()?))
+                        })
+                        .collect();
+                };
+                Err(ParserError::InvalidType)
+            }
+        }
+    };
+}
+
+impl_try_parse_primitive_array!(u16, InTypeUInt16);
+impl_try_parse_primitive_array!(u32, InTypeUInt32);
+impl_try_parse_primitive_array!(i32, InTypeInt32);
+
+/// Arrays of 64-bit integers and of pointers, which are 32 bits wide in events from 32-bit
+/// processes.
+impl TryParse<Vec<u64>> for Parser<'_> {
+    fn try_parse(&mut self, name: &str) -> ParserResult<Vec<u64>> {
+        use TdhInType::*;
+        let indx = self.find_property(name)?;
+        let prop_info = &self.cache[indx];
+        let PropertyDesc::Primitive(desc) = &prop_info.property.desc else {
+            return Err(ParserError::InvalidType);
+        };
+        let element_size = match desc.in_type {
+            InTypeUInt64 | InTypeHexInt64 => 8,
+            InTypePointer | InTypeSizeT => self.pointer_size(),
+            _ => return Err(ParserError::InvalidType),
+        };
+        if prop_info.buffer.len() % element_size != 0 {
+            return Err(ParserError::LengthMismatch);
+        }
+        prop_info
+            .buffer
+            .chunks_exact(element_size)
+            .map(|element| -> ParserResult<u64> {
+                Ok(match element_size {
+                    4 => u32::from_ne_bytes(element.try_into()?) as u64,
+                    _ => u64::from_ne_bytes(element.try_into()?),
+                })
+            })
+            .collect()
+    }
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct Pointer(usize);
 
@@ -630,10 +1062,15 @@ impl TryParse<Pointer> for Parser<'_> {
 
 impl TryParse<Vec<u8>> for Parser<'_> {
     fn try_parse(&mut self, name: &str) -> Result<Vec<u8>, ParserError> {
-        let indx = self.find_property(name)?;
-        let prop_info = &self.cache[indx];
+        TryParse::<&[u8]>::try_parse(self, name).map(<[u8]>::to_vec)
+    }
+}
 
-        Ok(prop_info.buffer.to_vec())
+/// The raw bytes of a property, borrowed from the event buffer.
+impl<'a> TryParse<&'a [u8]> for Parser<'a> {
+    fn try_parse(&mut self, name: &str) -> Result<&'a [u8], ParserError> {
+        let indx = self.find_property(name)?;
+        Ok(self.cache[indx].buffer)
     }
 }
 
@@ -0,0 +1,307 @@
+//! Offline schema bundles
+//!
+//! Decoding an event needs its schema, which TDH looks up in the manifests of the providers
+//! which are registered on the machine. A [SchemaBundle] holds the schemas which were resolved
+//! while processing a trace, so that the trace can later be decoded on a machine which doesn't
+//! have these providers, by adding the bundle to the [SchemaLocator] before processing.
+//!
+//! [SchemaLocator]: super::schema::SchemaLocator
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::rc::Rc;
+
+use num_traits::FromPrimitive;
+use serde_derive::{Deserialize, Serialize};
+use windows::core::GUID;
+
+use super::etw_types::DecodingSource;
+use super::schema::EventSchema;
+use super::tdh_types::{
+    PrimitiveDesc, Property, PropertyDesc, PropertyFlags, PropertyLength, PropertyMapInfo,
+    StructDesc, TdhInType, TdhOutType,
+};
+
+/// A set of event schemas which can be saved to a file and loaded again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SchemaBundle {
+    pub schemas: Vec<BundledSchema>,
+}
+
+impl SchemaBundle {
+    /// Loads a bundle which was saved with [SchemaBundle::save].
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bundle: SchemaBundle = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if let Some(schema) = bundle.schemas.iter().find(|schema| !schema.is_valid()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Invalid schema for {}/{}/{}",
+                    schema.provider_name, schema.task_name, schema.opcode_name
+                ),
+            ));
+        }
+        Ok(bundle)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        serde_json::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+}
+
+/// The schema of one event, keyed like the [SchemaLocator] keys its schemas.
+///
+/// [SchemaLocator]: super::schema::SchemaLocator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundledSchema {
+    /// The provider GUID, as 32 hex digits
+    pub provider_guid: String,
+    pub event_id: u16,
+    pub opcode: u8,
+    pub version: u8,
+    pub level: u8,
+    pub decoding_source: BundledDecodingSource,
+    pub provider_name: String,
+    pub task_name: String,
+    pub opcode_name: String,
+    pub event_message: Option<String>,
+    #[serde(default)]
+    pub is_event_metadata: bool,
+    /// The number of top-level properties at the start of `properties`; the members of struct
+    /// properties follow them. `None` if there are no struct members.
+    #[serde(default)]
+    pub top_level_property_count: Option<u32>,
+    pub properties: Vec<BundledProperty>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum BundledDecodingSource {
+    XMLFile,
+    Wbem,
+    Wpp,
+    Tlg,
+    Max,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundledProperty {
+    pub name: String,
+    pub flags: u32,
+    pub length: BundledPropertyLength,
+    pub desc: BundledPropertyDesc,
+    pub map_info: Option<BundledMapInfo>,
+    pub count: u16,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum BundledPropertyLength {
+    Length(u16),
+    Index(u16),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum BundledPropertyDesc {
+    Primitive { in_type: u16, out_type: u16 },
+    Struct { start_index: u16, num_members: u16 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundledMapInfo {
+    pub is_bitmap: bool,
+    pub map: Vec<(u32, String)>,
+}
+
+impl BundledSchema {
+    /// Captures `schema` under the key that the locator stored it with. The key can
+    /// differ from the schema's own descriptor, e.g. for classic MOF events.
+    pub(crate) fn new(
+        provider_guid: GUID,
+        event_id: u16,
+        opcode: u8,
+        version: u8,
+        level: u8,
+        schema: &dyn EventSchema,
+    ) -> Self {
+        let top_level_property_count = schema.property_count();
+        let mut properties: Vec<BundledProperty> = (0..top_level_property_count)
+            .map(|index| BundledProperty::from(&schema.property(index)))
+            .collect();
+        // Also capture the members of structs, which come after the top-level properties.
+        let mut i = 0;
+        while i < properties.len() {
+            if let BundledPropertyDesc::Struct {
+                start_index,
+                num_members,
+            } = properties[i].desc
+            {
+                let end = start_index as u32 + num_members as u32;
+                for index in properties.len() as u32..end {
+                    properties.push(BundledProperty::from(&schema.property(index)));
+                }
+            }
+            i += 1;
+        }
+        BundledSchema {
+            provider_guid: format!("{:032x}", provider_guid.to_u128()),
+            event_id,
+            opcode,
+            version,
+            level,
+            decoding_source: match schema.decoding_source() {
+                DecodingSource::XMLFile => BundledDecodingSource::XMLFile,
+                DecodingSource::Wbem => BundledDecodingSource::Wbem,
+                DecodingSource::Wpp => BundledDecodingSource::Wpp,
+                DecodingSource::Tlg => BundledDecodingSource::Tlg,
+                DecodingSource::Max => BundledDecodingSource::Max,
+            },
+            provider_name: schema.provider_name(),
+            task_name: schema.task_name(),
+            opcode_name: schema.opcode_name(),
+            event_message: schema.event_message(),
+            is_event_metadata: schema.is_event_metadata(),
+            top_level_property_count: (properties.len() as u32 != top_level_property_count)
+                .then_some(top_level_property_count),
+            properties,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        u128::from_str_radix(&self.provider_guid, 16).is_ok()
+            && self.property_count() as usize <= self.properties.len()
+            && self.properties.iter().all(|property| match property.desc {
+                BundledPropertyDesc::Primitive { in_type, .. } => {
+                    TdhInType::from_u16(in_type).is_some()
+                }
+                BundledPropertyDesc::Struct {
+                    start_index,
+                    num_members,
+                } => start_index as usize + num_members as usize <= self.properties.len(),
+            })
+    }
+}
+
+impl From<&Property> for BundledProperty {
+    fn from(property: &Property) -> Self {
+        BundledProperty {
+            name: property.name.clone(),
+            flags: property.flags.bits(),
+            length: match property.length {
+                PropertyLength::Length(length) => BundledPropertyLength::Length(length),
+                PropertyLength::Index(index) => BundledPropertyLength::Index(index),
+            },
+            desc: match &property.desc {
+                PropertyDesc::Primitive(desc) => BundledPropertyDesc::Primitive {
+                    in_type: desc.in_type as u16,
+                    out_type: desc.out_type as u16,
+                },
+                PropertyDesc::Struct(desc) => BundledPropertyDesc::Struct {
+                    start_index: desc.start_index,
+                    num_members: desc.num_members,
+                },
+            },
+            map_info: property.map_info.as_ref().map(|map_info| BundledMapInfo {
+                is_bitmap: map_info.is_bitmap,
+                map: map_info
+                    .map
+                    .iter()
+                    .map(|(value, name)| (*value, name.clone()))
+                    .collect(),
+            }),
+            count: property.count,
+        }
+    }
+}
+
+impl EventSchema for BundledSchema {
+    fn decoding_source(&self) -> DecodingSource {
+        match self.decoding_source {
+            BundledDecodingSource::XMLFile => DecodingSource::XMLFile,
+            BundledDecodingSource::Wbem => DecodingSource::Wbem,
+            BundledDecodingSource::Wpp => DecodingSource::Wpp,
+            BundledDecodingSource::Tlg => DecodingSource::Tlg,
+            BundledDecodingSource::Max => DecodingSource::Max,
+        }
+    }
+
+    fn provider_guid(&self) -> GUID {
+        // Checked when loading the bundle.
+        GUID::from_u128(u128::from_str_radix(&self.provider_guid, 16).unwrap_or_default())
+    }
+
+    fn event_id(&self) -> u16 {
+        self.event_id
+    }
+
+    fn opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    fn event_version(&self) -> u8 {
+        self.version
+    }
+
+    fn provider_name(&self) -> String {
+        self.provider_name.clone()
+    }
+
+    fn task_name(&self) -> String {
+        self.task_name.clone()
+    }
+
+    fn opcode_name(&self) -> String {
+        self.opcode_name.clone()
+    }
+
+    fn level(&self) -> u8 {
+        self.level
+    }
+
+    fn property_count(&self) -> u32 {
+        self.top_level_property_count
+            .unwrap_or(self.properties.len() as u32)
+    }
+
+    fn property(&self, index: u32) -> Property {
+        let property = &self.properties[index as usize];
+        Property {
+            name: property.name.clone(),
+            flags: PropertyFlags::from_bits_truncate(property.flags),
+            length: match property.length {
+                BundledPropertyLength::Length(length) => PropertyLength::Length(length),
+                BundledPropertyLength::Index(index) => PropertyLength::Index(index),
+            },
+            desc: match property.desc {
+                BundledPropertyDesc::Primitive { in_type, out_type } => {
+                    PropertyDesc::Primitive(PrimitiveDesc {
+                        // Checked when loading the bundle.
+                        in_type: TdhInType::from_u16(in_type).unwrap_or(TdhInType::InTypeNull),
+                        out_type: TdhOutType::from_u16(out_type).unwrap_or(TdhOutType::OutTypeNull),
+                    })
+                }
+                BundledPropertyDesc::Struct {
+                    start_index,
+                    num_members,
+                } => PropertyDesc::Struct(StructDesc {
+                    start_index,
+                    num_members,
+                }),
+            },
+            map_info: property.map_info.as_ref().map(|map_info| {
+                Rc::new(PropertyMapInfo {
+                    is_bitmap: map_info.is_bitmap,
+                    map: map_info.map.iter().cloned().collect(),
+                })
+            }),
+            count: property.count,
+        }
+    }
+
+    fn event_message(&self) -> Option<String> {
+        self.event_message.clone()
+    }
+
+    fn is_event_metadata(&self) -> bool {
+        self.is_event_metadata
+    }
+}
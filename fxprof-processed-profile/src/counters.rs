@@ -10,7 +10,7 @@ use crate::{GraphColor, ProcessHandle, Timestamp};
 #[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub struct CounterHandle(pub(crate) usize);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Counter {
     name: String,
     category: String,
@@ -88,7 +88,7 @@ impl Serialize for SerializableCounter<'_> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct CounterSamples {
     time: Vec<Timestamp>,
     number: Vec<u32>,
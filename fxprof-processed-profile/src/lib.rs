@@ -40,6 +40,23 @@
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! ## Building blocks
+//!
+//!  - Processes and threads: [`Profile::add_process`], [`Profile::add_thread`].
+//!  - Samples: get frames with [`Profile::handle_for_frame_with_label`] or
+//!    [`Profile::handle_for_frame_with_address`], combine them into stacks with
+//!    [`Profile::handle_for_stack`], and call [`Profile::add_sample`].
+//!  - Markers: implement [`StaticSchemaMarker`] for your marker type, or register
+//!    a schema at runtime with [`Profile::register_marker_type`], and call
+//!    [`Profile::add_marker`].
+//!  - Counters: [`Profile::add_counter`] and [`Profile::add_counter_sample`].
+//!  - Libraries: [`Profile::add_lib`] and [`Profile::add_lib_mapping`], so that
+//!    frames with [`FrameAddress`]es can be symbolicated.
+//!
+//! To look at the profile, write it to a file and open it with `samply load`,
+//! or pipe the JSON straight into `samply load -`, which also symbolicates the
+//! library addresses.
 
 pub use debugid;
 
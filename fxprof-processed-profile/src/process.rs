@@ -10,7 +10,7 @@ use crate::Timestamp;
 #[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub struct ThreadHandle(pub(crate) usize);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Process {
     pid: String,
     name: String,
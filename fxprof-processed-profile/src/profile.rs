@@ -166,10 +166,12 @@ pub enum TimelineUnit {
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Profile {
     pub(crate) product: String,
     pub(crate) os_name: Option<String>,
+    /// Sections of (label, value) pairs, serialized as `meta.extra`.
+    pub(crate) extra_info: Vec<(String, Vec<(String, String)>)>,
     pub(crate) interval: SamplingInterval,
     pub(crate) timeline_unit: TimelineUnit,
     pub(crate) global_libs: GlobalLibTable,
@@ -208,6 +210,7 @@ impl Profile {
             interval,
             product: product.to_string(),
             os_name: None,
+            extra_info: Vec::new(),
             timeline_unit: TimelineUnit::Milliseconds,
             threads: Vec::new(),
             initial_visible_threads: Vec::new(),
@@ -233,6 +236,11 @@ impl Profile {
         self.interval = interval;
     }
 
+    /// The declared sampling interval.
+    pub fn interval(&self) -> SamplingInterval {
+        self.interval
+    }
+
     /// Change the reference timestamp.
     pub fn set_reference_timestamp(&mut self, reference_timestamp: ReferenceTimestamp) {
         self.reference_timestamp = reference_timestamp;
@@ -256,6 +264,17 @@ impl Profile {
         self.os_name = Some(os_name.to_string());
     }
 
+    /// Add a piece of information about the profile which doesn't fit anywhere
+    /// else, e.g. a recording setting. The profiler shows these entries in the
+    /// profile info panel, grouped by `section`.
+    pub fn add_extra_info(&mut self, section: &str, label: &str, value: &str) {
+        let entry = (label.to_string(), value.to_string());
+        match self.extra_info.iter_mut().find(|(s, _)| s == section) {
+            Some((_, entries)) => entries.push(entry),
+            None => self.extra_info.push((section.to_string(), vec![entry])),
+        }
+    }
+
     /// Set the unit that the timeline should display. Default is [`TimelineUnit::Milliseconds`].
     ///
     /// If this is set to [`TimelineUnit::Bytes`], then the sample [`Timestamp`]s are interpreted
@@ -1378,6 +1397,24 @@ impl Serialize for SerializableProfileMeta<'_> {
             None => {}
         }
         map.serialize_entry("symbolicated", &self.0.symbolicated)?;
+        if !self.0.extra_info.is_empty() {
+            let extra: Vec<_> = self
+                .0
+                .extra_info
+                .iter()
+                .map(|(section, entries)| {
+                    json!({
+                        "label": section,
+                        "entries": entries.iter().map(|(label, value)| json!({
+                            "label": label,
+                            "format": "string",
+                            "value": value,
+                        })).collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            map.serialize_entry("extra", &extra)?;
+        }
         map.serialize_entry("pausedRanges", &[] as &[()])?;
         map.serialize_entry("version", &24)?; // this version is ignored, only "preprocessedProfileVersion" is used
         map.serialize_entry("usesOnlyOneStackType", &(!self.0.contains_js_frame()))?;
@@ -19,7 +19,7 @@ use crate::{Marker, MarkerHandle, MarkerTiming, MarkerTypeHandle, Symbol, Timest
 #[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub struct ProcessHandle(pub(crate) usize);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Thread {
     process: ProcessHandle,
     tid: String,
@@ -1,11 +1,12 @@
 use std::collections::BTreeMap;
 
-use samply_symbols::FrameDebugInfo;
+use samply_symbols::{FrameDebugInfo, SymbolKind};
 
 pub struct AddressResult {
     pub symbol_address: u32,
     pub symbol_name: String,
     pub function_size: Option<u32>,
+    pub symbol_kind: Option<SymbolKind>,
     pub inline_frames: Option<Vec<FrameDebugInfo>>,
 }
 
@@ -30,11 +31,13 @@ impl LookedUpAddresses {
         symbol_address: u32,
         symbol_name: String,
         function_size: Option<u32>,
+        symbol_kind: SymbolKind,
     ) {
         *self.address_results.get_mut(&address).unwrap() = Some(AddressResult {
             symbol_address,
             symbol_name,
             function_size,
+            symbol_kind: Some(symbol_kind),
             inline_frames: None,
         });
     }
@@ -61,6 +64,7 @@ impl LookedUpAddresses {
                     symbol_name: outer_function_name
                         .map_or_else(|| format!("0x{address:x}"), str::to_string),
                     function_size: None,
+                    symbol_kind: None,
                     inline_frames: Some(frames),
                 });
             }
@@ -100,6 +100,7 @@ impl<'a, H: FileAndPathHelper> SymbolicateApi<'a, H> {
                     address_info.symbol.address,
                     address_info.symbol.name,
                     address_info.symbol.size,
+                    address_info.symbol.kind,
                 );
                 match address_info.frames {
                     Some(FramesLookupResult::Available(frames)) => {
@@ -186,7 +187,12 @@ fn create_response(
         }
 
         let stacks = job.stacks.iter().map(|stack| {
-            response_stack_for_request_stack(stack, &job.memory_map, &symbols_by_module_index)
+            response_stack_for_request_stack(
+                stack,
+                &job.memory_map,
+                &symbols_by_module_index,
+                job.include_symbol_kind,
+            )
         });
 
         response_json::Result {
@@ -200,6 +206,7 @@ fn create_response(
         stack: &request_json::Stack,
         memory_map: &[Lib],
         symbols_by_module_index: &HashMap<u32, &AddressResults>,
+        include_symbol_kind: bool,
     ) -> Stack {
         let frames = stack.0.iter().enumerate().map(|(frame_index, frame)| {
             response_frame_for_request_frame(
@@ -207,6 +214,7 @@ fn create_response(
                 frame_index as u32,
                 memory_map,
                 symbols_by_module_index,
+                include_symbol_kind,
             )
         });
         Stack(frames.collect())
@@ -217,6 +225,7 @@ fn create_response(
         frame_index: u32,
         memory_map: &[Lib],
         symbols_by_module_index: &HashMap<u32, &AddressResults>,
+        include_symbol_kind: bool,
     ) -> StackFrame {
         let symbol = symbols_by_module_index
             .get(&frame.module_index)
@@ -231,6 +240,10 @@ fn create_response(
                         function: address_result.symbol_name.clone(),
                         function_offset: frame.address - address_result.symbol_address,
                         function_size: address_result.function_size,
+                        function_kind: address_result
+                            .symbol_kind
+                            .filter(|_| include_symbol_kind)
+                            .map(|kind| kind.as_str()),
                         debug_info: address_result.inline_frames.as_ref().map(|frames| {
                             let (outer, inlines) = frames
                                 .split_last()
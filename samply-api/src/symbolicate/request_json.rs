@@ -21,6 +21,10 @@ impl Request {
 pub struct Job {
     pub memory_map: Vec<Lib>,
     pub stacks: Vec<Stack>,
+    /// Whether to include the `function_kind` of each symbol in the response.
+    /// Off by default to keep the response compatible with the Tecken format.
+    #[serde(default)]
+    pub include_symbol_kind: bool,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
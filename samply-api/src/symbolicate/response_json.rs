@@ -47,6 +47,10 @@ pub struct Symbol {
     )]
     pub function_size: Option<u32>,
 
+    /// "function", "thunk", "public" or "synthesized", if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_kind: Option<&'static str>,
+
     #[serde(flatten)]
     pub debug_info: Option<DebugInfo>,
 }
@@ -119,6 +123,7 @@ mod test {
                             function: String::from("sctp_send_initiate"),
                             function_offset: 0x4ca,
                             function_size: None,
+                            function_kind: None,
                             debug_info: None,
                         }),
                     },
@@ -277,6 +277,32 @@ fn win64_local_v5_snapshot_1() {
     );
 }
 
+#[test]
+fn win64_local_v5_include_symbol_kind() {
+    // Same lookup as win64_local_v5_snapshot_1, but opting in to symbol kinds.
+    // The symbol comes from the DLL exports, so its kind is "public".
+    compare_snapshot(
+        "/symbolicate/v5",
+        r#"{
+                "memoryMap": [
+                  [
+                    "mozglue.dll",
+                    "B3CC644ECC086E044C4C44205044422E1"
+                  ]
+                ],
+                "stacks": [
+                  [
+                    [0, 214644]
+                  ]
+                ],
+                "includeSymbolKind": true
+              }"#,
+        fixtures_dir().join("win64-local"),
+        "api-v5-win64-local-symbol-kind.txt",
+        "output-api-v5-win64-local-symbol-kind.txt",
+    );
+}
+
 #[test]
 fn win64_ci_v5_snapshot() {
     compare_snapshot(
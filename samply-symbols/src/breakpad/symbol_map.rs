@@ -16,7 +16,7 @@ use super::index::{
 use crate::symbol_map::{GetInnerSymbolMap, SymbolMapTrait};
 use crate::{
     Error, FileContents, FileContentsWrapper, FrameDebugInfo, FramesLookupResult, LookupAddress,
-    SourceFilePath, SymbolInfo, SyncAddressInfo,
+    SourceFilePath, SymbolInfo, SymbolKind, SyncAddressInfo,
 };
 
 pub fn get_symbol_map_for_breakpad_sym<FC: FileContents + 'static>(
@@ -316,6 +316,7 @@ impl<T: FileContents> SymbolMapTrait for BreakpadSymbolMapInner<'_, T> {
                             next_symbol_address.checked_sub(symbol_address)
                         }),
                         name: info.name.to_string(),
+                        kind: SymbolKind::Public,
                     },
                     frames: None,
                 })
@@ -362,6 +363,7 @@ impl<T: FileContents> SymbolMapTrait for BreakpadSymbolMapInner<'_, T> {
                         address: symbol_address,
                         size: Some(info.size),
                         name: info.name.to_string(),
+                        kind: SymbolKind::Function,
                     },
                     frames: Some(FramesLookupResult::Available(frames)),
                 })
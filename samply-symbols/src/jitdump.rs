@@ -16,7 +16,7 @@ use yoke_derive::Yokeable;
 use crate::error::Error;
 use crate::shared::{
     FileContents, FileContentsCursor, FileContentsWrapper, FrameDebugInfo, FramesLookupResult,
-    LookupAddress, SourceFilePath, SymbolInfo,
+    LookupAddress, SourceFilePath, SymbolInfo, SymbolKind,
 };
 use crate::symbol_map::{GetInnerSymbolMap, SymbolMap, SymbolMapTrait};
 use crate::{FileAndPathHelper, SyncAddressInfo};
@@ -294,6 +294,7 @@ impl<T: FileContents> JitDumpSymbolMapInner<'_, T> {
                 address: symbol_address,
                 size: Some(self.index.entries[index].code_bytes_len as u32),
                 name,
+                kind: SymbolKind::Function,
             },
             frames,
         })
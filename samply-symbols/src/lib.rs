@@ -231,6 +231,7 @@ mod macho;
 mod mapped_path;
 mod path_mapper;
 mod shared;
+mod source_link;
 mod symbol_map;
 mod symbol_map_object;
 mod windows;
@@ -241,7 +242,7 @@ pub use crate::breakpad::{
 };
 pub use crate::cache::{FileByteSource, FileContentsWithChunkedCaching};
 pub use crate::compact_symbol_table::CompactSymbolTable;
-pub use crate::debugid_util::{debug_id_for_object, DebugIdExt};
+pub use crate::debugid_util::{code_id_for_object, debug_id_for_object, DebugIdExt};
 pub use crate::demangle::demangle_any;
 pub use crate::error::Error;
 pub use crate::external_file::{load_external_file, ExternalFileSymbolMap};
@@ -253,7 +254,7 @@ pub use crate::shared::{
     ExternalFileAddressInFileRef, ExternalFileAddressRef, ExternalFileRef, FileAndPathHelper,
     FileAndPathHelperError, FileAndPathHelperResult, FileContents, FileContentsWrapper,
     FileLocation, FrameDebugInfo, FramesLookupResult, LibraryInfo, LookupAddress,
-    MultiArchDisambiguator, OptionallySendFuture, PeCodeId, SourceFilePath, SymbolInfo,
+    MultiArchDisambiguator, OptionallySendFuture, PeCodeId, SourceFilePath, SymbolInfo, SymbolKind,
     SyncAddressInfo,
 };
 pub use crate::symbol_map::{SymbolMap, SymbolMapTrait};
@@ -284,9 +285,25 @@ where
         &self,
         debug_file_location: &H::FL,
         source_file_path: &SourceFilePath,
+    ) -> Result<String, Error> {
+        let result = self
+            .load_source_file_at(debug_file_location, source_file_path.raw_path())
+            .await;
+        match (result, source_file_path.source_url()) {
+            // The file wasn't on disk, e.g. because the binary was built on a
+            // different machine. Download it from the URL in the debug file.
+            (Err(_), Some(url)) => self.load_source_file_at(debug_file_location, url).await,
+            (result, _) => result,
+        }
+    }
+
+    async fn load_source_file_at(
+        &self,
+        debug_file_location: &H::FL,
+        path_or_url: &str,
     ) -> Result<String, Error> {
         let source_file_location = debug_file_location
-            .location_for_source_file(source_file_path.raw_path())
+            .location_for_source_file(path_or_url)
             .ok_or(Error::FileLocationRefusedSourceFileLocation)?;
         let file_contents = self
             .helper
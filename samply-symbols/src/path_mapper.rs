@@ -36,6 +36,11 @@ impl<E: ExtraPathMapper> PathMapper<E> {
         }
     }
 
+    /// The extra mapper, if there is one.
+    pub fn extra_mapper_mut(&mut self) -> Option<&mut E> {
+        self.extra_mapper.as_mut()
+    }
+
     /// Compute the mapped path for a raw path.
     pub fn map_path(&mut self, raw_path: &str) -> Option<MappedPath> {
         if let Some(extra_mapper) = &mut self.extra_mapper {
@@ -525,6 +525,10 @@ pub struct SourceFilePath {
     /// A variant of the path which may allow obtaining the source code for this file
     /// from the web.
     mapped_path: Option<MappedPath>,
+
+    /// A URL from which the source file can be downloaded, e.g. from the srcsrv
+    /// or SourceLink stream of a PDB file.
+    source_url: Option<String>,
 }
 
 impl SourceFilePath {
@@ -533,9 +537,16 @@ impl SourceFilePath {
         Self {
             raw_path,
             mapped_path,
+            source_url: None,
         }
     }
 
+    /// Set the URL from which the source file can be downloaded.
+    pub fn with_source_url(mut self, source_url: Option<String>) -> Self {
+        self.source_url = source_url;
+        self
+    }
+
     /// Create a `SourceFilePath` from a path in a Breakpad .sym file. Such files can
     /// contain the "special path" serialization of a mapped path, but they can
     /// also contain absolute paths.
@@ -544,6 +555,7 @@ impl SourceFilePath {
         Self {
             raw_path,
             mapped_path,
+            source_url: None,
         }
     }
 
@@ -609,6 +621,14 @@ impl SourceFilePath {
     pub fn into_mapped_path(self) -> Option<MappedPath> {
         self.mapped_path
     }
+
+    /// A URL from which the source file can be downloaded, if the debug file
+    /// has one. This is found in the `srcsrv` and `sourcelink` streams of PDB
+    /// files, and used by [`SymbolManager::load_source_file`](crate::SymbolManager::load_source_file)
+    /// if the file can't be found at its raw path.
+    pub fn source_url(&self) -> Option<&str> {
+        self.source_url.as_deref()
+    }
 }
 
 /// The "relative address base" is the base address which [`LookupAddress::Relative`]
@@ -675,6 +695,48 @@ pub struct SymbolInfo {
     pub size: Option<u32>,
     /// The function name, demangled.
     pub name: String,
+    /// What kind of symbol this information was derived from.
+    pub kind: SymbolKind,
+}
+
+/// The kind of symbol that a [`SymbolInfo`] was created from.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SymbolKind {
+    /// A regular function, e.g. from a function symbol in the symbol table,
+    /// a PDB procedure, or a Breakpad `FUNC` record.
+    Function,
+    /// A thunk or stub which forwards to another function, e.g. an MSVC
+    /// `[thunk]:` symbol or an ELF PLT stub.
+    Thunk,
+    /// A public or exported symbol without function information, e.g. a PDB
+    /// public symbol, a Breakpad `PUBLIC` record, or a DLL export.
+    Public,
+    /// A placeholder symbol which was synthesized from other information, e.g.
+    /// a function start address from unwind info, or the entry point.
+    Synthesized,
+}
+
+impl SymbolKind {
+    /// Returns `Thunk` if the symbol name looks like a thunk or stub, and
+    /// `fallback` otherwise.
+    pub fn from_name_or(name: &str, fallback: SymbolKind) -> SymbolKind {
+        if name.starts_with("[thunk]:") || name.ends_with("@plt") {
+            SymbolKind::Thunk
+        } else {
+            fallback
+        }
+    }
+
+    /// A short lowercase name for this kind, as used in the symbolication API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SymbolKind::Function => "function",
+            SymbolKind::Thunk => "thunk",
+            SymbolKind::Public => "public",
+            SymbolKind::Synthesized => "synthesized",
+        }
+    }
 }
 
 /// The lookup result for an address.
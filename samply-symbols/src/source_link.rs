@@ -0,0 +1,90 @@
+/// The SourceLink information from the `sourcelink` stream of a PDB file.
+///
+/// The stream contains JSON of the following form:
+///
+/// ```json
+/// {"documents": {"C:\\src\\app\\*": "https://raw.githubusercontent.com/org/app/<rev>/*"}}
+/// ```
+///
+/// Each key is a path on the build machine, and each value is the URL at which
+/// that file can be downloaded. A `*` at the end of a key matches the rest of
+/// the path, which is then substituted for the `*` in the URL, with forward
+/// slashes.
+///
+/// See <https://github.com/dotnet/designs/blob/main/accepted/2020/diagnostics/source-link.md>.
+#[derive(Debug, Clone, Default)]
+pub struct SourceLinkMap {
+    /// (path pattern, URL pattern), sorted by decreasing pattern length so that
+    /// the most specific pattern wins.
+    documents: Vec<(String, String)>,
+}
+
+impl SourceLinkMap {
+    /// Parses the JSON of a SourceLink stream. Returns `None` if it doesn't
+    /// have a `documents` object.
+    pub fn parse(json: &[u8]) -> Option<Self> {
+        let value: serde_json::Value = serde_json::from_slice(json).ok()?;
+        let documents = value.get("documents")?.as_object()?;
+        let mut documents: Vec<(String, String)> = documents
+            .iter()
+            .filter_map(|(pattern, url)| Some((pattern.clone(), url.as_str()?.to_owned())))
+            .collect();
+        documents.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.len()));
+        Some(Self { documents })
+    }
+
+    /// Returns the URL of the file at `path` on the build machine, if a pattern
+    /// matches it. Like Windows paths, the patterns match case-insensitively.
+    pub fn url_for_path(&self, path: &str) -> Option<String> {
+        self.documents.iter().find_map(|(pattern, url)| {
+            match (pattern.strip_suffix('*'), url.split_once('*')) {
+                (Some(prefix), Some((url_start, url_end))) => {
+                    let rest = strip_prefix_ignore_ascii_case(path, prefix)?;
+                    Some(format!("{url_start}{}{url_end}", rest.replace('\\', "/")))
+                }
+                (None, _) if pattern.eq_ignore_ascii_case(path) => Some(url.clone()),
+                _ => None,
+            }
+        })
+    }
+}
+
+fn strip_prefix_ignore_ascii_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let start = s.get(..prefix.len())?;
+    if start.eq_ignore_ascii_case(prefix) {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn map_paths() {
+        let map = SourceLinkMap::parse(
+            br#"{"documents": {
+                "C:\\src\\app\\*": "https://raw.githubusercontent.com/org/app/0123abcd/*",
+                "C:\\src\\app\\external\\lib\\*": "https://dev.azure.com/org/proj/_apis/git/repositories/lib/items?api-version=1.0&versionType=commit&version=4567ef&path=/*",
+                "C:\\src\\generated.h": "https://example.com/generated.h"
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            map.url_for_path(r"c:\SRC\app\main\main.cpp").as_deref(),
+            Some("https://raw.githubusercontent.com/org/app/0123abcd/main/main.cpp")
+        );
+        assert_eq!(
+            map.url_for_path(r"C:\src\app\external\lib\lib.cpp").as_deref(),
+            Some("https://dev.azure.com/org/proj/_apis/git/repositories/lib/items?api-version=1.0&versionType=commit&version=4567ef&path=/lib.cpp")
+        );
+        assert_eq!(
+            map.url_for_path(r"C:\src\generated.h").as_deref(),
+            Some("https://example.com/generated.h")
+        );
+        assert_eq!(map.url_for_path(r"C:\other\file.cpp"), None);
+        assert!(SourceLinkMap::parse(b"not json").is_none());
+    }
+}
@@ -16,7 +16,7 @@ use crate::dwarf::convert_frames;
 use crate::path_mapper::PathMapper;
 use crate::shared::{
     relative_address_base, ExternalFileAddressInFileRef, ExternalFileAddressRef, ExternalFileRef,
-    FramesLookupResult, LookupAddress, SymbolInfo,
+    FramesLookupResult, LookupAddress, SymbolInfo, SymbolKind as SymbolInfoKind,
 };
 use crate::symbol_map::{
     GetInnerSymbolMap, GetInnerSymbolMapWithLookupFramesExt, SymbolMapTrait,
@@ -67,6 +67,20 @@ impl<'a, Symbol: object::ObjectSymbol<'a>> FullSymbolListEntry<'a, Symbol> {
         Some(name)
     }
 
+    fn symbol_info_kind(&self, name: &str) -> SymbolInfoKind {
+        match self {
+            FullSymbolListEntry::Symbol(_) => {
+                SymbolInfoKind::from_name_or(name, SymbolInfoKind::Function)
+            }
+            FullSymbolListEntry::Export(_) => {
+                SymbolInfoKind::from_name_or(name, SymbolInfoKind::Public)
+            }
+            FullSymbolListEntry::Synthesized
+            | FullSymbolListEntry::SynthesizedEntryPoint
+            | FullSymbolListEntry::EndAddress => SymbolInfoKind::Synthesized,
+        }
+    }
+
     fn counts_as_proper_symbol(&self) -> bool {
         match self {
             FullSymbolListEntry::Symbol(_) | FullSymbolListEntry::Export(_) => true,
@@ -252,7 +266,10 @@ impl<'a, Symbol: object::ObjectSymbol<'a> + 'a> SymbolList<'a, Symbol> {
         Self { entries }
     }
 
-    pub fn lookup_relative_address(&self, address: u32) -> Option<(u32, u32, Cow<'a, str>)> {
+    pub fn lookup_relative_address(
+        &self,
+        address: u32,
+    ) -> Option<(u32, u32, Cow<'a, str>, SymbolInfoKind)> {
         let index = match self
             .entries
             .binary_search_by_key(&address, |&(addr, _)| addr)
@@ -271,7 +288,8 @@ impl<'a, Symbol: object::ObjectSymbol<'a> + 'a> SymbolList<'a, Symbol> {
             }
             _ => entry.name(*start_addr)?,
         };
-        Some((*start_addr, *end_addr, name))
+        let kind = entry.symbol_info_kind(&name);
+        Some((*start_addr, *end_addr, name, kind))
     }
 }
 
@@ -533,13 +551,15 @@ where
                 )
             }
         };
-        let (start_addr, end_addr, name) = self.list.lookup_relative_address(relative_address)?;
+        let (start_addr, end_addr, name, kind) =
+            self.list.lookup_relative_address(relative_address)?;
         let function_size = end_addr - start_addr;
         let name = demangle::demangle_any(&name);
         let symbol = SymbolInfo {
             address: start_addr,
             size: Some(function_size),
             name,
+            kind,
         };
 
         let mut frames = None;
@@ -20,8 +20,9 @@ use crate::mapped_path::MappedPath;
 use crate::path_mapper::{ExtraPathMapper, PathMapper};
 use crate::shared::{
     FileAndPathHelper, FileContents, FileContentsWrapper, FileLocation, FrameDebugInfo,
-    FramesLookupResult, LookupAddress, SourceFilePath, SymbolInfo,
+    FramesLookupResult, LookupAddress, SourceFilePath, SymbolInfo, SymbolKind,
 };
+use crate::source_link::SourceLinkMap;
 use crate::symbol_map::{GetInnerSymbolMap, SymbolMap, SymbolMapTrait};
 use crate::symbol_map_object::{
     ObjectSymbolMap, ObjectSymbolMapInnerWrapper, ObjectSymbolMapOuter,
@@ -165,6 +166,7 @@ struct PdbObject<'data, FC: FileContents + 'static> {
     context_data: pdb_addr2line::ContextPdbData<'data, 'data, &'data FileContentsWrapper<FC>>,
     debug_id: DebugId,
     srcsrv_stream: Option<Box<dyn Deref<Target = [u8]> + Send + 'data>>,
+    sourcelink_stream: Option<Box<dyn Deref<Target = [u8]> + Send + 'data>>,
 }
 
 trait PdbObjectTrait {
@@ -178,13 +180,21 @@ impl<FC: FileContents + 'static> PdbObjectTrait for PdbObject<'_, FC> {
     fn make_pdb_symbol_map(&self) -> Result<PdbSymbolMapInner<'_>, Error> {
         let context = self.make_context()?;
 
-        let path_mapper = match &self.srcsrv_stream {
+        let srcsrv = match &self.srcsrv_stream {
             Some(srcsrv_stream) => Some(SrcSrvPathMapper::new(srcsrv::SrcSrvStream::parse(
                 srcsrv_stream.deref(),
             )?)),
             None => None,
         };
-        let path_mapper = PathMapper::new_with_maybe_extra_mapper(path_mapper);
+        let source_link = self
+            .sourcelink_stream
+            .as_ref()
+            .and_then(|stream| SourceLinkMap::parse(stream));
+        let path_mapper = PathMapper::new_with_maybe_extra_mapper(Some(PdbPathMapper {
+            srcsrv,
+            source_link,
+            cache: HashMap::new(),
+        }));
 
         let symbol_map = PdbSymbolMapInner {
             context,
@@ -236,7 +246,7 @@ pub struct PdbSymbolMapInnerWrapper<'data>(Box<dyn SymbolMapTrait + Send + 'data
 struct PdbSymbolMapInner<'object> {
     context: Box<dyn PdbAddr2lineContextTrait + Send + 'object>,
     debug_id: DebugId,
-    path_mapper: Mutex<PathMapper<SrcSrvPathMapper<'object>>>,
+    path_mapper: Mutex<PathMapper<PdbPathMapper<'object>>>,
 }
 
 impl SymbolMapTrait for PdbSymbolMapInner<'_> {
@@ -283,16 +293,27 @@ impl SymbolMapTrait for PdbSymbolMapInner<'_> {
             .end_rva
             .map(|end_rva| end_rva - function_frames.start_rva);
 
+        // Procedures have line information, public symbols don't.
+        let has_debug_info = has_debug_info(&function_frames);
+        let fallback_kind = if has_debug_info {
+            SymbolKind::Function
+        } else {
+            SymbolKind::Public
+        };
         let symbol = SymbolInfo {
             address: symbol_address,
             size: function_size,
+            kind: SymbolKind::from_name_or(&symbol_name, fallback_kind),
             name: symbol_name,
         };
-        let frames = if has_debug_info(&function_frames) {
+        let frames = if has_debug_info {
             let mut path_mapper = self.path_mapper.lock().unwrap();
             let mut map_path = |path: Cow<str>| {
                 let mapped_path = path_mapper.map_path(&path);
-                SourceFilePath::new(path.into_owned(), mapped_path)
+                let source_url = path_mapper
+                    .extra_mapper_mut()
+                    .and_then(|pdb_mapper| pdb_mapper.source_url(&path));
+                SourceFilePath::new(path.into_owned(), mapped_path).with_source_url(source_url)
             };
             let frames: Vec<_> = function_frames
                 .frames
@@ -339,6 +360,11 @@ impl<T: FileContents + 'static> PdbObjectWithFileData<T> {
                 Err(pdb::Error::StreamNameNotFound | pdb::Error::StreamNotFound(_)) => None,
                 Err(e) => return Err(Error::PdbError("pdb.named_stream(srcsrv)", e)),
             };
+            let sourcelink_stream = match pdb.named_stream(b"sourcelink") {
+                Ok(stream) => Some(box_stream(stream)),
+                Err(pdb::Error::StreamNameNotFound | pdb::Error::StreamNotFound(_)) => None,
+                Err(e) => return Err(Error::PdbError("pdb.named_stream(sourcelink)", e)),
+            };
 
             let context_data = pdb_addr2line::ContextPdbData::try_from_pdb(pdb)
                 .context("ContextConstructionData::try_from_pdb")?;
@@ -347,6 +373,7 @@ impl<T: FileContents + 'static> PdbObjectWithFileData<T> {
                 context_data,
                 debug_id,
                 srcsrv_stream,
+                sourcelink_stream,
             };
 
             Ok(PdbObjectWrapper(Box::new(pdb_object)))
@@ -423,6 +450,52 @@ pub fn get_symbol_map_for_pdb<H: FileAndPathHelper>(
     ))
 }
 
+/// Maps the raw file paths of a PDB file, using its srcsrv stream and its
+/// SourceLink stream. Besides the mapped path, this also finds the URL from
+/// which the file can be downloaded, which is used when the mapped path has a
+/// form that the Firefox profiler can't fetch by itself, e.g. for files on
+/// Azure DevOps.
+struct PdbPathMapper<'a> {
+    srcsrv: Option<SrcSrvPathMapper<'a>>,
+    source_link: Option<SourceLinkMap>,
+    /// The mapped path and the download URL for each raw path.
+    cache: HashMap<String, (Option<MappedPath>, Option<String>)>,
+}
+
+impl PdbPathMapper<'_> {
+    fn lookup(&mut self, path: &str) -> &(Option<MappedPath>, Option<String>) {
+        if !self.cache.contains_key(path) {
+            let (mut mapped_path, mut url) = match &self.srcsrv {
+                Some(srcsrv) => srcsrv.lookup(path),
+                None => (None, None),
+            };
+            if url.is_none() {
+                url = self
+                    .source_link
+                    .as_ref()
+                    .and_then(|source_link| source_link.url_for_path(path));
+            }
+            if mapped_path.is_none() {
+                mapped_path = url.as_deref().and_then(MappedPath::from_url);
+            }
+            self.cache.insert(path.to_string(), (mapped_path, url));
+        }
+        &self.cache[path]
+    }
+
+    /// The URL from which the source file at `path` can be downloaded, if the
+    /// srcsrv or SourceLink stream has one.
+    fn source_url(&mut self, path: &str) -> Option<String> {
+        self.lookup(path).1.clone()
+    }
+}
+
+impl ExtraPathMapper for PdbPathMapper<'_> {
+    fn map_path(&mut self, path: &str) -> Option<MappedPath> {
+        self.lookup(path).0.clone()
+    }
+}
+
 /// Map raw file paths to special "permalink" paths, using the srcsrv stream.
 /// This allows finding source code for applications that were not compiled on this
 /// machine, for example when using PDBs that were downloaded from a symbol server.
@@ -432,32 +505,26 @@ pub fn get_symbol_map_for_pdb<H: FileAndPathHelper>(
 ///   - "s3:<bucket>:<digest_and_path>:"
 struct SrcSrvPathMapper<'a> {
     srcsrv_stream: srcsrv::SrcSrvStream<'a>,
-    cache: HashMap<String, Option<MappedPath>>,
     command_is_file_download_with_url_in_var4_and_uncompress_function_in_var5: bool,
 }
 
-impl ExtraPathMapper for SrcSrvPathMapper<'_> {
-    fn map_path(&mut self, path: &str) -> Option<MappedPath> {
-        if let Some(value) = self.cache.get(path) {
-            return value.clone();
-        }
-
-        let value = match self
+impl SrcSrvPathMapper<'_> {
+    /// Returns the mapped path and the download URL for a raw path.
+    fn lookup(&self, path: &str) -> (Option<MappedPath>, Option<String>) {
+        match self
             .srcsrv_stream
             .source_and_raw_var_values_for_path(path, "C:\\Dummy")
         {
             Ok(Some((srcsrv::SourceRetrievalMethod::Download { url }, _map))) => {
-                MappedPath::from_url(&url)
+                (MappedPath::from_url(&url), Some(url))
             }
             Ok(Some((srcsrv::SourceRetrievalMethod::ExecuteCommand { .. }, map))) => {
                 // We're not going to execute a command here.
                 // Instead, we have special handling for a few known cases (well, only one case for now).
-                self.gitiles_to_mapped_path(&map)
+                (self.gitiles_to_mapped_path(&map), None)
             }
-            _ => None,
-        };
-        self.cache.insert(path.to_string(), value.clone());
-        value
+            _ => (None, None),
+        }
     }
 }
 
@@ -468,7 +535,6 @@ impl<'a> SrcSrvPathMapper<'a> {
 
         SrcSrvPathMapper {
             srcsrv_stream,
-            cache: HashMap::new(),
             command_is_file_download_with_url_in_var4_and_uncompress_function_in_var5,
         }
     }
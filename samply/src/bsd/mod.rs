@@ -0,0 +1,4 @@
+mod proc_maps;
+pub mod profiler;
+mod ptrace_sampler;
+mod sys;
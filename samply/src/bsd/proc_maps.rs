@@ -0,0 +1,111 @@
+//! Enumerates the file-backed mappings of a process. Neither system has a
+//! stable API for this which includes file paths, so this parses the output of
+//! `procstat -v` on FreeBSD and `procmap` on OpenBSD.
+
+use std::process::Command;
+
+use libc::pid_t;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMapping {
+    pub start: u64,
+    pub end: u64,
+    pub is_executable: bool,
+    pub path: String,
+}
+
+/// Returns the file-backed mappings of the process, sorted by address.
+pub fn file_mappings(pid: pid_t) -> Vec<FileMapping> {
+    let Some(output) = mapping_tool_output(pid) else {
+        return Vec::new();
+    };
+    let mut mappings: Vec<FileMapping> = output.lines().filter_map(parse_line).collect();
+    mappings.sort_by_key(|mapping| mapping.start);
+    mappings
+}
+
+#[cfg(target_os = "freebsd")]
+fn mapping_tool_output(pid: pid_t) -> Option<String> {
+    let output = Command::new("procstat")
+        .arg("-v")
+        .arg(pid.to_string())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(target_os = "openbsd")]
+fn mapping_tool_output(pid: pid_t) -> Option<String> {
+    let output = Command::new("procmap")
+        .arg("-p")
+        .arg(pid.to_string())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses a line of `procstat -v` output:
+///
+/// ```text
+///   PID              START                END PRT  RES PRES REF SHD FLAG  TP PATH
+///  4242           0x200000           0x203000 r--    3   21   4   1 CN--- vn /usr/bin/yes
+/// ```
+#[cfg(target_os = "freebsd")]
+fn parse_line(line: &str) -> Option<FileMapping> {
+    let mut fields = line.split_whitespace();
+    let _pid = fields.next()?;
+    let start = parse_hex(fields.next()?)?;
+    let end = parse_hex(fields.next()?)?;
+    let prot = fields.next()?;
+    let kind = fields.nth(5)?;
+    if kind != "vn" {
+        return None;
+    }
+    let path = fields.collect::<Vec<_>>().join(" ");
+    if !path.starts_with('/') {
+        return None;
+    }
+    Some(FileMapping {
+        start,
+        end,
+        is_executable: prot.contains('x'),
+        path,
+    })
+}
+
+/// Parses a line of `procmap` output:
+///
+/// ```text
+/// 00000a1e8ee00000     16K read               /usr/bin/yes
+/// 00000a1e8ee04000      8K read/exec          /usr/bin/yes
+/// ```
+///
+/// `procmap` can only show paths for vnodes which are still in the kernel's
+/// name cache, so some mappings may be missing.
+#[cfg(target_os = "openbsd")]
+fn parse_line(line: &str) -> Option<FileMapping> {
+    let mut fields = line.split_whitespace();
+    let start = parse_hex(fields.next()?)?;
+    let size_kb: u64 = fields.next()?.strip_suffix('K')?.parse().ok()?;
+    let prot = fields.next()?;
+    let path = fields.collect::<Vec<_>>().join(" ");
+    if !path.starts_with('/') {
+        return None;
+    }
+    Some(FileMapping {
+        start,
+        end: start + size_kb * 1024,
+        is_executable: prot.contains("exec"),
+        path,
+    })
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
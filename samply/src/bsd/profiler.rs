@@ -0,0 +1,127 @@
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::{Instant, SystemTime};
+
+use fxprof_processed_profile::{Profile, ReferenceTimestamp, SamplingInterval};
+use tokio::sync::oneshot::error::TryRecvError;
+
+use super::ptrace_sampler::ProcessSampler;
+use super::sys;
+use crate::shared::ctrl_c::CtrlC;
+use crate::shared::interval_jitter::IntervalJitter;
+use crate::shared::prop_types::{ProfileCreationProps, RecordingMode, RecordingProps};
+use crate::shared::thread_rates::ThreadRates;
+
+pub fn run(
+    recording_mode: RecordingMode,
+    recording_props: RecordingProps,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<(Profile, ExitStatus), i32> {
+    if profile_creation_props.utc_timestamps {
+        eprintln!("--timestamps utc is currently not supported on FreeBSD, ignoring it.");
+    }
+    let reference_time = Instant::now();
+    let timebase = ReferenceTimestamp::from_system_time(SystemTime::now());
+    let thread_rates = ThreadRates::new(
+        recording_props.interval,
+        &recording_props.thread_rate_overrides,
+    );
+    let mut profile = Profile::new(
+        profile_creation_props.profile_name(),
+        timebase,
+        SamplingInterval::from_nanos(thread_rates.tick_interval().as_nanos() as u64),
+    );
+    if let Some(os_name) = sys::os_name() {
+        profile.set_os_name(&os_name);
+    }
+
+    let mut exit_status = ExitStatus::from_raw(0);
+    match recording_mode {
+        RecordingMode::Pid(pid) => {
+            let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+            let mut sampler = match ProcessSampler::attach(
+                pid as libc::pid_t,
+                &mut profile,
+                reference_time,
+            ) {
+                Ok(sampler) => sampler,
+                Err(err) => {
+                    eprintln!("Could not attach to process with pid {pid}: {err}");
+                    eprintln!("Attaching requires the same user as the process, or root. On FreeBSD, the security.bsd.unprivileged_proc_debug sysctl also needs to be 1.");
+                    return Err(1);
+                }
+            };
+            eprintln!("Profiling process with pid {pid}...");
+            eprintln!("Press Ctrl+C to stop.");
+            sampler.run(&mut profile, &recording_props, reference_time, || {
+                !matches!(ctrl_c_receiver.try_recv(), Err(TryRecvError::Empty))
+            });
+        }
+        RecordingMode::Launch(process_launch_props) => {
+            // Ignore Ctrl+C while the subcommand is running. The signal still reaches the process
+            // under observation while we continue to record it.
+            let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+
+            for _ in 0..process_launch_props.iteration_count {
+                let mut child = std::process::Command::new(&process_launch_props.command_name);
+                child.args(&process_launch_props.args);
+                child.envs(process_launch_props.env_vars.iter().map(|(k, v)| (k, v)));
+                let mut child = match child.spawn() {
+                    Ok(child) => child,
+                    Err(err) => {
+                        eprintln!(
+                            "Could not launch {:?}: {err}",
+                            process_launch_props.command_name
+                        );
+                        return Err(1);
+                    }
+                };
+
+                let sampler_exit_status = match ProcessSampler::attach(
+                    child.id() as libc::pid_t,
+                    &mut profile,
+                    reference_time,
+                ) {
+                    Ok(mut sampler) => {
+                        sampler.run(&mut profile, &recording_props, reference_time, || false)
+                    }
+                    Err(err) => {
+                        eprintln!("Could not attach to the launched process: {err}");
+                        None
+                    }
+                };
+
+                // If the process exited while we were tracing it, we have
+                // already reaped it.
+                exit_status = match sampler_exit_status {
+                    Some(status) => status,
+                    None => child.wait().unwrap(),
+                };
+                if !process_launch_props.ignore_exit_code && !exit_status.success() {
+                    eprintln!(
+                        "Skipping remaining iterations due to non-success exit status: \"{}\"",
+                        exit_status
+                    );
+                    break;
+                }
+            }
+
+            // The launched subprocess is done. From now on, we want to terminate if the user presses Ctrl+C.
+            ctrl_c_receiver.close();
+        }
+        RecordingMode::All | RecordingMode::WaitForProcess(_) | RecordingMode::Multiple { .. } => {
+            eprintln!(
+                "On this platform, samply can only record a launched process or an existing process (--pid)."
+            );
+            return Err(1);
+        }
+    }
+
+    IntervalJitter::new(
+        thread_rates.tick_interval(),
+        recording_props.interval_jitter,
+    )
+    .add_to_profile_meta(&mut profile);
+    thread_rates.add_to_profile_meta(&mut profile);
+    Ok((profile, exit_status))
+}
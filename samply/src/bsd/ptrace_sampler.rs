@@ -0,0 +1,392 @@
+//! A sampler for a single process, based on ptrace.
+//!
+//! Neither FreeBSD nor OpenBSD has an equivalent of perf_event_open which
+//! unprivileged users can use to get user stacks, so this attaches to the
+//! process as a debugger. On every tick, the process is stopped, the registers
+//! of each thread which has used CPU time since the previous tick are read,
+//! and its stack is walked using frame pointers. Then the process is resumed.
+//!
+//! Threads which haven't run since the previous tick get a zero-CPU sample
+//! with their previous stack.
+
+use std::collections::{HashMap, HashSet};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+use fxprof_processed_profile::{
+    CategoryHandle, CpuDelta, FrameAddress, FrameFlags, LibraryInfo, ProcessHandle, Profile,
+    StackHandle, ThreadHandle, Timestamp,
+};
+use libc::pid_t;
+use wholesym::samply_symbols::{code_id_for_object, debug_id_for_object, relative_address_base};
+
+use super::proc_maps::{self, FileMapping};
+use super::sys;
+use crate::linux_shared::compute_vma_bias;
+use crate::shared::interval_jitter::IntervalJitter;
+use crate::shared::prop_types::RecordingProps;
+use crate::shared::thread_rates::ThreadRates;
+
+/// Mappings are re-enumerated this often. This runs an external tool, so it's
+/// not done on every tick.
+const MAPPINGS_REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Stacks deeper than this are truncated.
+const MAX_STACK_DEPTH: usize = 1024;
+
+enum StopOutcome {
+    Stopped,
+    Exited(ExitStatus),
+}
+
+struct SampledThread {
+    profile_thread: ThreadHandle,
+    previous_cpu_time_us: u64,
+    previous_stack: Option<StackHandle>,
+    name: Option<String>,
+}
+
+pub struct ProcessSampler {
+    pid: pid_t,
+    profile_process: ProcessHandle,
+    threads: HashMap<pid_t, SampledThread>,
+    known_mappings: HashSet<(u64, u64)>,
+    frames: Vec<u64>,
+}
+
+impl ProcessSampler {
+    /// Attaches to the process. The process keeps running.
+    pub fn attach(
+        pid: pid_t,
+        profile: &mut Profile,
+        reference_time: Instant,
+    ) -> std::io::Result<Self> {
+        sys::attach(pid)?;
+        if let StopOutcome::Exited(_) = wait_for_stop(pid)? {
+            return Err(std::io::Error::other("the process exited"));
+        }
+
+        let now = timestamp_since(reference_time);
+        let name = sys::process_name(pid).unwrap_or_else(|| format!("Process {pid}"));
+        let profile_process = profile.add_process(&name, pid as u32, now);
+        let mut sampler = Self {
+            pid,
+            profile_process,
+            threads: HashMap::new(),
+            known_mappings: HashSet::new(),
+            frames: Vec::new(),
+        };
+        sampler.refresh_mappings(profile);
+        sys::continue_process(pid, 0)?;
+        Ok(sampler)
+    }
+
+    /// Samples the process until it exits, until `should_stop` returns true,
+    /// or until a limit in `recording_props` is reached. Then detaches from
+    /// the process.
+    ///
+    /// Returns the exit status if the process exited. The process has been
+    /// reaped in that case.
+    pub fn run(
+        &mut self,
+        profile: &mut Profile,
+        recording_props: &RecordingProps,
+        reference_time: Instant,
+        mut should_stop: impl FnMut() -> bool,
+    ) -> Option<ExitStatus> {
+        let start = Instant::now();
+        let mut last_refresh = start;
+        let mut sample_count = 0;
+        let mut exit_status = None;
+        // With per-thread rates, we stop the process at the highest rate and
+        // only sample the slower threads on some of the stops.
+        let thread_rates = ThreadRates::new(
+            recording_props.interval,
+            &recording_props.thread_rate_overrides,
+        );
+        let interval_jitter = IntervalJitter::new(
+            thread_rates.tick_interval(),
+            recording_props.interval_jitter,
+        );
+        loop {
+            if should_stop() {
+                break;
+            }
+            if recording_props
+                .time_limit
+                .is_some_and(|time_limit| start.elapsed() >= time_limit)
+            {
+                break;
+            }
+            if recording_props
+                .sample_limit
+                .is_some_and(|limit| sample_count >= limit)
+            {
+                break;
+            }
+
+            match self.stop() {
+                Ok(StopOutcome::Stopped) => {}
+                Ok(StopOutcome::Exited(status)) => {
+                    exit_status = Some(status);
+                    break;
+                }
+                Err(_) => break,
+            }
+
+            let now = timestamp_since(reference_time);
+            if last_refresh.elapsed() >= MAPPINGS_REFRESH_INTERVAL {
+                self.refresh_mappings(profile);
+                last_refresh = Instant::now();
+            }
+            self.sample_threads(profile, now, &thread_rates, sample_count);
+            sample_count += 1;
+
+            if sys::continue_process(self.pid, 0).is_err() {
+                break;
+            }
+            std::thread::sleep(interval_jitter.next_interval());
+        }
+
+        if exit_status.is_none() {
+            match self.stop() {
+                Ok(StopOutcome::Stopped) => {
+                    if let Err(err) = sys::detach(self.pid) {
+                        eprintln!("Could not detach from process {}: {err}", self.pid);
+                    }
+                }
+                Ok(StopOutcome::Exited(status)) => exit_status = Some(status),
+                Err(_) => {}
+            }
+        }
+
+        let end = timestamp_since(reference_time);
+        for thread in self.threads.values() {
+            profile.set_thread_end_time(thread.profile_thread, end);
+        }
+        profile.set_process_end_time(self.profile_process, end);
+        exit_status
+    }
+
+    fn stop(&self) -> std::io::Result<StopOutcome> {
+        if unsafe { libc::kill(self.pid, libc::SIGSTOP) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        wait_for_stop(self.pid)
+    }
+
+    fn refresh_mappings(&mut self, profile: &mut Profile) {
+        let mappings = proc_maps::file_mappings(self.pid);
+
+        // The lowest mapping of each file maps the start of the file.
+        let mut first_mappings: HashMap<&str, &FileMapping> = HashMap::new();
+        for mapping in &mappings {
+            first_mappings
+                .entry(mapping.path.as_str())
+                .or_insert(mapping);
+        }
+
+        for mapping in mappings.iter().filter(|mapping| mapping.is_executable) {
+            if !self.known_mappings.insert((mapping.start, mapping.end)) {
+                continue;
+            }
+            let first_mapping = first_mappings[mapping.path.as_str()];
+            let Some((lib_info, bias, base_svma)) = library_info_and_bias(
+                &mapping.path,
+                first_mapping.start,
+                first_mapping.end - first_mapping.start,
+            ) else {
+                continue;
+            };
+            let relative_address_at_start =
+                mapping.start.wrapping_sub(bias).wrapping_sub(base_svma) as u32;
+            let lib = profile.add_lib(lib_info);
+            profile.add_lib_mapping(
+                self.profile_process,
+                lib,
+                mapping.start,
+                mapping.end,
+                relative_address_at_start,
+            );
+        }
+    }
+
+    fn sample_threads(
+        &mut self,
+        profile: &mut Profile,
+        now: Timestamp,
+        thread_rates: &ThreadRates,
+        tick: u64,
+    ) {
+        let Some(thread_infos) = sys::process_threads(self.pid) else {
+            return;
+        };
+
+        let is_first_sample = self.threads.is_empty();
+        for (index, info) in thread_infos.iter().enumerate() {
+            let thread = self.threads.entry(info.tid).or_insert_with(|| {
+                // The kernel lists threads in creation order.
+                let is_main = is_first_sample && index == 0;
+                SampledThread {
+                    profile_thread: profile.add_thread(
+                        self.profile_process,
+                        info.tid as u32,
+                        now,
+                        is_main,
+                    ),
+                    previous_cpu_time_us: info.cpu_time_us,
+                    previous_stack: None,
+                    name: None,
+                }
+            });
+            if info.name.is_some() && info.name != thread.name {
+                if let Some(name) = &info.name {
+                    profile.set_thread_name(thread.profile_thread, name);
+                }
+                thread.name.clone_from(&info.name);
+            }
+
+            let ticks_per_sample = thread_rates.ticks_per_sample(thread.name.as_deref());
+            if tick % u64::from(ticks_per_sample) != 0 {
+                continue;
+            }
+            let weight = ticks_per_sample as i32;
+
+            let cpu_delta_us = info.cpu_time_us.saturating_sub(thread.previous_cpu_time_us);
+            thread.previous_cpu_time_us = info.cpu_time_us;
+
+            if cpu_delta_us == 0 && thread.previous_stack.is_some() {
+                profile.add_sample_same_stack_zero_cpu(thread.profile_thread, now, weight);
+                continue;
+            }
+
+            let Some(regs) = sys::get_stack_regs(info.tid) else {
+                continue;
+            };
+            self.frames.clear();
+            walk_stack(self.pid, regs, &mut self.frames);
+
+            let profile_thread = thread.profile_thread;
+            let mut frames = self
+                .frames
+                .iter()
+                .enumerate()
+                .map(|(i, address)| match i {
+                    0 => FrameAddress::InstructionPointer(*address),
+                    _ => FrameAddress::ReturnAddress(*address),
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev();
+            let stack = profile.handle_for_stack_frames(profile_thread, |profile| {
+                let frame_address = frames.next()?;
+                Some(profile.handle_for_frame_with_address(
+                    profile_thread,
+                    frame_address,
+                    CategoryHandle::OTHER,
+                    FrameFlags::empty(),
+                ))
+            });
+            let cpu_delta = CpuDelta::from_micros(cpu_delta_us);
+            profile.add_sample(profile_thread, now, stack, cpu_delta, weight);
+            thread.previous_stack = stack;
+        }
+
+        self.threads.retain(|tid, thread| {
+            let is_alive = thread_infos.iter().any(|info| info.tid == *tid);
+            if !is_alive {
+                profile.set_thread_end_time(thread.profile_thread, now);
+            }
+            is_alive
+        });
+    }
+}
+
+/// Waits until the traced process stops with SIGSTOP. Other signals which
+/// arrive in the meantime are passed on to the process.
+fn wait_for_stop(pid: pid_t) -> std::io::Result<StopOutcome> {
+    loop {
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+            return Ok(StopOutcome::Exited(ExitStatus::from_raw(status)));
+        }
+        if libc::WIFSTOPPED(status) {
+            let signal = libc::WSTOPSIG(status);
+            if signal == libc::SIGSTOP {
+                return Ok(StopOutcome::Stopped);
+            }
+            sys::continue_process(pid, signal)?;
+        }
+    }
+}
+
+/// Walks the frame pointer chain. On both x86_64 and aarch64, a frame record
+/// consists of the caller's frame pointer followed by the return address.
+/// The instruction pointer comes first in `frames`, followed by the return
+/// addresses.
+fn walk_stack(pid: pid_t, regs: sys::StackRegs, frames: &mut Vec<u64>) {
+    frames.push(regs.pc);
+    let mut fp = regs.fp;
+    let mut previous_fp = regs.sp;
+    while frames.len() < MAX_STACK_DEPTH {
+        // Frame records live further up the stack than the ones they link to.
+        if fp == 0 || fp % 8 != 0 || fp < previous_fp {
+            break;
+        }
+        let (Some(next_fp), Some(return_address)) =
+            (sys::read_u64(pid, fp), sys::read_u64(pid, fp + 8))
+        else {
+            break;
+        };
+        if return_address == 0 {
+            break;
+        }
+        frames.push(return_address);
+        previous_fp = fp + 16;
+        fp = next_fp;
+    }
+}
+
+/// Reads the library info from the file at `path`, and computes the bias
+/// between its SVMAs and the AVMAs in the process from the mapping of the
+/// start of the file. Also returns the SVMA which relative addresses are
+/// relative to.
+fn library_info_and_bias(
+    path: &str,
+    first_mapping_start: u64,
+    first_mapping_size: u64,
+) -> Option<(LibraryInfo, u64, u64)> {
+    let file = std::fs::File::open(path).ok()?;
+    let mmap = unsafe { memmap2::Mmap::map(&file) }.ok()?;
+    let object = object::File::parse(&mmap[..]).ok()?;
+    let debug_id = debug_id_for_object(&object)?;
+    let bias = compute_vma_bias(&object, 0, first_mapping_start, first_mapping_size)?;
+    let base_svma = relative_address_base(&object);
+    let name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_owned());
+    let lib_info = LibraryInfo {
+        name: name.clone(),
+        debug_name: name,
+        path: path.to_owned(),
+        debug_path: path.to_owned(),
+        debug_id,
+        code_id: code_id_for_object(&object).map(|code_id| code_id.to_string()),
+        arch: None,
+    };
+    Some((lib_info, bias, base_svma))
+}
+
+fn timestamp_since(reference_time: Instant) -> Timestamp {
+    Timestamp::from_nanos_since_reference(reference_time.elapsed().as_nanos() as u64)
+}
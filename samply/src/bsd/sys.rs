@@ -0,0 +1,373 @@
+//! Thin wrappers around ptrace(2) and the kern.proc sysctls, which differ
+//! slightly between FreeBSD and OpenBSD.
+
+use std::ffi::{c_void, CStr};
+
+use libc::{c_int, pid_t};
+
+#[cfg(target_os = "freebsd")]
+mod consts {
+    use libc::c_int;
+
+    pub const PT_CONTINUE: c_int = 7;
+    pub const PT_ATTACH: c_int = 10;
+    pub const PT_DETACH: c_int = 11;
+    pub const PT_IO: c_int = 12;
+    pub const PT_GETREGS: c_int = 33;
+    pub const PIOD_READ_D: c_int = 1;
+}
+
+#[cfg(target_os = "openbsd")]
+mod consts {
+    use libc::c_int;
+
+    pub const PT_CONTINUE: c_int = 7;
+    pub const PT_ATTACH: c_int = 9;
+    pub const PT_DETACH: c_int = 10;
+    pub const PT_IO: c_int = 11;
+    // PT_FIRSTMACH + 1
+    pub const PT_GETREGS: c_int = 33;
+    pub const PIOD_READ_D: c_int = 1;
+}
+
+use consts::*;
+
+/// `struct ptrace_io_desc` from `<sys/ptrace.h>`, which has the same layout
+/// on both systems.
+#[repr(C)]
+struct PtraceIoDesc {
+    piod_op: c_int,
+    piod_offs: *mut c_void,
+    piod_addr: *mut c_void,
+    piod_len: libc::size_t,
+}
+
+/// `struct reg` from `<machine/reg.h>`.
+#[cfg(all(target_os = "freebsd", target_arch = "x86_64"))]
+type MachineRegs = libc::reg;
+
+#[cfg(all(target_os = "freebsd", target_arch = "aarch64"))]
+type MachineRegs = libc::reg;
+
+#[cfg(all(target_os = "openbsd", target_arch = "x86_64"))]
+#[repr(C)]
+#[allow(dead_code)]
+struct MachineRegs {
+    r_rdi: i64,
+    r_rsi: i64,
+    r_rdx: i64,
+    r_rcx: i64,
+    r_r8: i64,
+    r_r9: i64,
+    r_r10: i64,
+    r_r11: i64,
+    r_r12: i64,
+    r_r13: i64,
+    r_r14: i64,
+    r_r15: i64,
+    r_rbp: i64,
+    r_rbx: i64,
+    r_rax: i64,
+    r_gsbase: i64,
+    r_rip: i64,
+    r_cs: i64,
+    r_rflags: i64,
+    r_rsp: i64,
+    r_ss: i64,
+    r_ds: i64,
+    r_es: i64,
+    r_fs: i64,
+    r_gs: i64,
+}
+
+#[cfg(all(target_os = "openbsd", target_arch = "aarch64"))]
+#[repr(C)]
+#[allow(dead_code)]
+struct MachineRegs {
+    r_reg: [u64; 30],
+    r_lr: u64,
+    r_sp: u64,
+    r_pc: u64,
+    r_spsr: u64,
+    r_tpidr: u64,
+}
+
+/// The registers needed to walk a thread's stack with frame pointers.
+#[derive(Debug, Clone, Copy)]
+pub struct StackRegs {
+    pub pc: u64,
+    pub sp: u64,
+    pub fp: u64,
+}
+
+impl StackRegs {
+    #[cfg(all(target_os = "freebsd", target_arch = "x86_64"))]
+    fn from_machine_regs(regs: &MachineRegs) -> Self {
+        Self {
+            pc: regs.r_rip as u64,
+            sp: regs.r_rsp as u64,
+            fp: regs.r_rbp as u64,
+        }
+    }
+
+    #[cfg(all(target_os = "freebsd", target_arch = "aarch64"))]
+    fn from_machine_regs(regs: &MachineRegs) -> Self {
+        Self {
+            pc: regs.elr,
+            sp: regs.sp,
+            fp: regs.x[29],
+        }
+    }
+
+    #[cfg(all(target_os = "openbsd", target_arch = "x86_64"))]
+    fn from_machine_regs(regs: &MachineRegs) -> Self {
+        Self {
+            pc: regs.r_rip as u64,
+            sp: regs.r_rsp as u64,
+            fp: regs.r_rbp as u64,
+        }
+    }
+
+    #[cfg(all(target_os = "openbsd", target_arch = "aarch64"))]
+    fn from_machine_regs(regs: &MachineRegs) -> Self {
+        Self {
+            pc: regs.r_pc,
+            sp: regs.r_sp,
+            fp: regs.r_reg[29],
+        }
+    }
+}
+
+unsafe fn ptrace(request: c_int, pid: pid_t, addr: *mut c_void, data: c_int) -> c_int {
+    libc::ptrace(request, pid, addr as *mut libc::c_char, data)
+}
+
+/// Attaches to the process. This sends it a SIGSTOP; the caller needs to wait
+/// for the stop.
+pub fn attach(pid: pid_t) -> std::io::Result<()> {
+    if unsafe { ptrace(PT_ATTACH, pid, std::ptr::null_mut(), 0) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Detaches from the stopped process and lets it run.
+pub fn detach(pid: pid_t) -> std::io::Result<()> {
+    // An address of 1 means "continue where it stopped".
+    if unsafe { ptrace(PT_DETACH, pid, 1 as *mut c_void, 0) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Resumes the stopped process, delivering `signal` to it unless it's zero.
+pub fn continue_process(pid: pid_t, signal: c_int) -> std::io::Result<()> {
+    if unsafe { ptrace(PT_CONTINUE, pid, 1 as *mut c_void, signal) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reads the registers of a thread of a stopped, traced process. Both systems
+/// accept a thread id in place of the pid for this request.
+pub fn get_stack_regs(tid: pid_t) -> Option<StackRegs> {
+    let mut regs: MachineRegs = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        ptrace(
+            PT_GETREGS,
+            tid,
+            &mut regs as *mut MachineRegs as *mut c_void,
+            0,
+        )
+    };
+    if result == -1 {
+        return None;
+    }
+    Some(StackRegs::from_machine_regs(&regs))
+}
+
+/// Reads memory from a stopped, traced process. Returns false if the address
+/// isn't readable.
+pub fn read_memory(pid: pid_t, address: u64, buffer: &mut [u8]) -> bool {
+    let mut desc = PtraceIoDesc {
+        piod_op: PIOD_READ_D,
+        piod_offs: address as *mut c_void,
+        piod_addr: buffer.as_mut_ptr() as *mut c_void,
+        piod_len: buffer.len(),
+    };
+    let result = unsafe { ptrace(PT_IO, pid, &mut desc as *mut PtraceIoDesc as *mut c_void, 0) };
+    result != -1 && desc.piod_len == buffer.len()
+}
+
+pub fn read_u64(pid: pid_t, address: u64) -> Option<u64> {
+    let mut bytes = [0; 8];
+    read_memory(pid, address, &mut bytes).then(|| u64::from_ne_bytes(bytes))
+}
+
+/// A thread of the target process, as reported by the kern.proc sysctl.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub tid: pid_t,
+    pub name: Option<String>,
+    /// The thread's CPU time so far, in microseconds.
+    pub cpu_time_us: u64,
+}
+
+fn c_chars_to_string(chars: &[libc::c_char]) -> Option<String> {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    if bytes.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Runs a sysctl which returns an array of `kinfo_proc` structs.
+fn kinfo_procs(mib: &mut [c_int]) -> Option<Vec<libc::kinfo_proc>> {
+    let entry_size = std::mem::size_of::<libc::kinfo_proc>();
+    loop {
+        #[cfg(target_os = "openbsd")]
+        {
+            // OpenBSD wants the element size and count in the name. A count
+            // of zero queries the size.
+            let mib_len = mib.len();
+            mib[mib_len - 2] = entry_size as c_int;
+            mib[mib_len - 1] = 0;
+        }
+        let mut len = 0;
+        let result = unsafe {
+            libc::sysctl(
+                mib.as_mut_ptr(),
+                mib.len() as _,
+                std::ptr::null_mut(),
+                &mut len,
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        if result == -1 {
+            return None;
+        }
+        // Leave room for threads which start between the two calls.
+        let capacity = len / entry_size + 4;
+        len = capacity * entry_size;
+        let mut procs: Vec<libc::kinfo_proc> = Vec::with_capacity(capacity);
+        #[cfg(target_os = "openbsd")]
+        {
+            let mib_len = mib.len();
+            mib[mib_len - 1] = capacity as c_int;
+        }
+        let result = unsafe {
+            libc::sysctl(
+                mib.as_mut_ptr(),
+                mib.len() as _,
+                procs.as_mut_ptr() as *mut c_void,
+                &mut len,
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        if result == -1 {
+            if std::io::Error::last_os_error().raw_os_error() == Some(libc::ENOMEM) {
+                continue;
+            }
+            return None;
+        }
+        unsafe { procs.set_len(len / entry_size) };
+        return Some(procs);
+    }
+}
+
+/// Returns the threads of the process, or `None` if the process is gone.
+#[cfg(target_os = "freebsd")]
+pub fn process_threads(pid: pid_t) -> Option<Vec<ThreadInfo>> {
+    let mut mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROC,
+        libc::KERN_PROC_PID | libc::KERN_PROC_INC_THREAD,
+        pid,
+    ];
+    let procs = kinfo_procs(&mut mib)?;
+    if procs.is_empty() {
+        return None;
+    }
+    Some(
+        procs
+            .iter()
+            .map(|proc| ThreadInfo {
+                tid: proc.ki_tid,
+                name: c_chars_to_string(&proc.ki_tdname),
+                cpu_time_us: proc.ki_runtime,
+            })
+            .collect(),
+    )
+}
+
+/// Returns the threads of the process, or `None` if the process is gone.
+#[cfg(target_os = "openbsd")]
+pub fn process_threads(pid: pid_t) -> Option<Vec<ThreadInfo>> {
+    let mut mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROC,
+        libc::KERN_PROC_PID | libc::KERN_PROC_SHOW_THREADS,
+        pid,
+        0,
+        0,
+    ];
+    let procs = kinfo_procs(&mut mib)?;
+    if procs.is_empty() {
+        return None;
+    }
+    Some(
+        procs
+            .iter()
+            // The entry for the process itself has a tid of -1.
+            .filter(|proc| proc.p_tid != -1)
+            .map(|proc| ThreadInfo {
+                tid: proc.p_tid,
+                name: c_chars_to_string(&proc.p_name),
+                cpu_time_us: u64::from(proc.p_rtime_sec) * 1_000_000 + u64::from(proc.p_rtime_usec),
+            })
+            .collect(),
+    )
+}
+
+/// Returns the command name of the process.
+pub fn process_name(pid: pid_t) -> Option<String> {
+    #[cfg(target_os = "freebsd")]
+    let mut mib = [libc::CTL_KERN, libc::KERN_PROC, libc::KERN_PROC_PID, pid];
+    #[cfg(target_os = "openbsd")]
+    let mut mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROC,
+        libc::KERN_PROC_PID,
+        pid,
+        0,
+        0,
+    ];
+    let procs = kinfo_procs(&mut mib)?;
+    let proc = procs.first()?;
+    #[cfg(target_os = "freebsd")]
+    let name = &proc.ki_comm;
+    #[cfg(target_os = "openbsd")]
+    let name = &proc.p_comm;
+    c_chars_to_string(name)
+}
+
+/// Returns something like "FreeBSD 14.1-RELEASE".
+pub fn os_name() -> Option<String> {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } == -1 {
+        return None;
+    }
+    let sysname = unsafe { CStr::from_ptr(uts.sysname.as_ptr()) };
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) };
+    Some(format!(
+        "{} {}",
+        sysname.to_string_lossy(),
+        release.to_string_lossy()
+    ))
+}
@@ -1,17 +1,37 @@
+use std::ffi::OsString;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
 
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum, ValueHint};
+use clap_complete::engine::ArgValueCompleter;
+use clap_complete::Shell;
+use regex::Regex;
 
-use super::cli_utils::{parse_time_range, split_at_first_equals};
+use super::cli_utils::{
+    complete_profile_files, parse_byte_size, parse_env_var, parse_http_header,
+    parse_jitter_percent, parse_label, parse_path_remapping, parse_rate_override,
+    parse_source_repository, parse_time_range, parse_time_threshold, split_at_first_equals,
+};
+use super::import::ImportFormat;
+use super::report::StackPattern;
 use super::server::{PortSelection, ServerProps};
+use super::shared::cpu_topology::{parse_cpu_list, CoreType};
+use super::shared::derived_counters::DerivedCounter;
 use super::shared::included_processes::IncludedProcesses;
+use super::shared::metrics_scraper::{MetricsSource, DEFAULT_METRICS_INTERVAL};
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use super::shared::probes::ProbeSpec;
+#[cfg(target_os = "windows")]
+use super::shared::prop_types::ServiceProps;
 use super::shared::prop_types::{
-    CoreClrProfileProps, ImportProps, ProcessLaunchProps, ProfileCreationProps, RecordingMode,
-    RecordingProps, SymbolProps,
+    AssertProps, CoreClrProfileProps, CpuFilter, DaemonProps, DiffFormat, DiffProps, EtwPreset,
+    ExportFormat, ExportProps, GrepProps, ImportProps, ProcessLaunchProps, ProfileCreationProps,
+    PushProps, PushTarget, RecordingMode, RecordingProps, ReportAggregation, ReportProps,
+    SummarizeProps, SymbolProps, TimeThreshold, TimeThresholdKind,
 };
+use super::shared::redaction::RedactionRules;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -28,12 +48,21 @@ EXAMPLES:
     # Profile an existing process by pid:
     samply record -p 12345
 
+    # Profile a running server and a freshly launched client into one profile (Linux only):
+    samply record -p 12345 -- ./yourclient yourargs
+
     # Alternative usage: Save profile to file for later viewing, and then load it.
     samply record --save-only -o prof.json -- ./yourcommand yourargs
     samply load prof.json # Opens in the browser and supplies symbols
 
     # Import perf.data files from Linux perf or Android simpleperf:
     samply import perf.data
+
+    # Print the hottest source lines of a saved profile:
+    samply report --by line prof.json
+
+    # Install shell completions (bash, zsh, fish, elvish or powershell):
+    samply completions bash > ~/.local/share/bash-completion/completions/samply
 "#
 )]
 pub struct Opt {
@@ -47,32 +76,112 @@ pub enum Action {
         target_os = "android",
         target_os = "macos",
         target_os = "linux",
-        target_os = "windows"
+        target_os = "windows",
+        target_os = "freebsd",
+        target_os = "openbsd"
     ))]
     /// Record a profile and display it.
     Record(RecordArgs),
 
+    #[cfg(any(
+        target_os = "android",
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "windows",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    ))]
+    /// Record a running process until Ctrl+C or --duration, and display the
+    /// profile. This is a shorthand for `samply record --pid`, with fewer
+    /// options.
+    Attach(AttachArgs),
+
+    #[cfg(any(
+        target_os = "android",
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "windows",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    ))]
+    /// Keep recording in fixed-length chunks, and save each chunk to a file.
+    Daemon(DaemonArgs),
+
     /// Load a profile from a file and display it.
     Load(LoadArgs),
 
-    /// Import a perf.data file and display the profile.
+    /// Import a perf.data file, the text output of `perf script`, a Valgrind massif
+    /// file, a heaptrack data file or folded stacks, and display the profile.
     Import(ImportArgs),
 
+    /// Print a summary of where the samples in a saved profile were taken.
+    Report(ReportArgs),
+
+    /// Upload a saved profile to a Pyroscope server or an OTLP endpoint.
+    Push(PushArgs),
+
+    /// Check a saved profile against time thresholds, and exit with a non-zero
+    /// exit code if any of them are exceeded.
+    Assert(AssertArgs),
+
+    /// Print a short summary of a saved profile as Markdown, for bug reports:
+    /// the hottest functions per thread, the biggest off-CPU waits, the longest
+    /// markers, and anything which makes the profile less accurate.
+    Summarize(SummarizeArgs),
+
+    /// Count the samples in a saved profile whose stacks match a pattern, and
+    /// print the heaviest distinct matching stacks.
+    Grep(GrepArgs),
+
+    /// Convert a saved profile into a format for other tools, such as a
+    /// FlameScope heat map.
+    Export(ExportArgs),
+
+    /// Compare the stacks of two saved profiles, for differential flame graphs.
+    Diff(DiffArgs),
+
+    /// Check that a saved profile is structurally valid, and print every
+    /// problem which is found.
+    Validate(ValidateArgs),
+
+    /// Add the system log entries of the profiled processes during the
+    /// recording (journald, the macOS unified log or the Windows event logs)
+    /// as markers to a saved profile.
+    AddLogs(AddLogsArgs),
+
     #[cfg(target_os = "windows")]
     #[clap(hide = true)]
     /// Used in the elevated helper process.
     RunElevatedHelper(RunElevatedHelperArgs),
 
+    /// Capture the stacks of all threads of a running process at a single
+    /// moment, print them, and save them as a profile.
+    #[cfg(target_os = "macos")]
+    Snapshot(SnapshotArgs),
+
     /// Codesign the samply binary on macOS to allow attaching to processes.
     #[cfg(target_os = "macos")]
     Setup(SetupArgs),
+
+    /// Produce symbol files from binaries with samply's symbol parsers.
+    Symtool(SymtoolArgs),
+
+    /// Print a shell completion script for samply to stdout.
+    Completions(CompletionsArgs),
 }
 
 #[derive(Debug, Args)]
 pub struct LoadArgs {
-    /// Path to the file that should be loaded.
+    /// Path to the file that should be loaded, or `-` to read the profile from
+    /// stdin.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
     pub file: PathBuf,
 
+    /// The age identity file to decrypt an encrypted profile with, e.g. one
+    /// saved with --encrypt-output. Can be specified multiple times.
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub identity: Vec<PathBuf>,
+
     #[command(flatten)]
     pub server_args: ServerArgs,
 
@@ -82,12 +191,31 @@ pub struct LoadArgs {
 
 #[derive(Debug, Args)]
 pub struct ImportArgs {
-    /// Path to the profile file that should be imported.
+    /// Path to the profile file that should be imported, or `-` to read it from
+    /// stdin.
+    #[arg(value_hint = ValueHint::FilePath)]
     pub file: PathBuf,
 
+    /// The format of the input file. By default, the format is detected from
+    /// the file's contents.
+    #[arg(long, value_enum)]
+    pub format: Option<ImportFormatArg>,
+
     /// Optional extra paths to ETL files for user sessions.
     pub user_etl: Vec<PathBuf>,
 
+    /// Decode ETW events with the schemas from this file, which was written with
+    /// --export-etw-schemas on a machine which has the providers' manifests.
+    /// Can be specified multiple times.
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub etw_schemas: Vec<PathBuf>,
+
+    /// Write the schemas of the ETW events in the imported file to this file,
+    /// so that the file can be imported on machines without the providers'
+    /// manifests, using --etw-schemas.
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub export_etw_schemas: Option<PathBuf>,
+
     #[command(flatten)]
     pub profile_creation_args: ProfileCreationArgs,
 
@@ -95,7 +223,8 @@ pub struct ImportArgs {
     #[arg(short, long)]
     pub save_only: bool,
 
-    /// Output filename.
+    /// Output filename, or `-` to write the profile JSON to stdout, without
+    /// running a server.
     #[arg(short, long, default_value = "profile.json.gz")]
     pub output: PathBuf,
 
@@ -124,6 +253,353 @@ pub struct ImportArgs {
     /// Time range of recording to include in profile. Format is "start-stop" or "start+duration" with each part optional, e.g. "5s", "5s-", "-10s", "1s-10s" or "1s+9s".
     #[arg(long, value_parser=parse_time_range)]
     pub time_range: Option<(std::time::Duration, std::time::Duration)>,
+
+    /// Add the calls from an strace or ltrace log (recorded with `-ttt -f`) as
+    /// markers to this existing profile of the same run, instead of creating a
+    /// new profile. The merged profile is saved to the output file.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub merge_into: Option<PathBuf>,
+
+    /// A copy of the guest's /proc/kallsyms, for symbolicating the guest kernel
+    /// samples in a perf.data file recorded with `perf kvm`.
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub guest_kallsyms: Option<PathBuf>,
+
+    /// A copy of the guest's /proc/modules, which has the address ranges of the
+    /// guest's kernel modules.
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, requires = "guest_kallsyms")]
+    pub guest_modules: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    /// Path to the profile file that should be summarized.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
+    pub file: PathBuf,
+
+    /// How to group the samples: by the source line or by the instruction
+    /// address of the innermost frame, by function (with self and total time),
+    /// or as a call tree.
+    #[arg(long, value_enum, default_value_t = ReportByArg::Address)]
+    pub by: ReportByArg,
+
+    /// The maximum number of rows to print.
+    #[arg(short = 'n', long, default_value_t = 50)]
+    pub limit: usize,
+
+    /// With "--by address", also print the disassembly around each hot address.
+    #[arg(long)]
+    pub disassemble: bool,
+
+    /// With "--by call-tree", print the inverted call tree, whose roots are the
+    /// functions in which the samples were taken.
+    #[arg(long)]
+    pub inverted: bool,
+
+    /// With "--by function" and "--by call-tree", keep the library and the
+    /// library-relative address of each native frame next to its function
+    /// name, e.g. "memcpy [libc.so.6!0x9f2c0]". Different addresses in the
+    /// same function are listed separately.
+    #[arg(long)]
+    pub addresses: bool,
+
+    /// Only include samples which have a function matching this regular
+    /// expression on the stack, and drop the callers of the outermost matching
+    /// function ("focus on function").
+    #[arg(long, value_name = "REGEX", value_parser = regex::Regex::new)]
+    pub focus: Option<regex::Regex>,
+
+    /// Remove functions matching this regular expression from all stacks, and
+    /// attribute their time to their callers ("merge function"). Can be specified
+    /// multiple times.
+    #[arg(long, value_name = "REGEX", value_parser = regex::Regex::new)]
+    pub hide: Vec<regex::Regex>,
+
+    /// Only include the samples which were taken on these CPUs, e.g. "0-3,8".
+    /// This needs a profile which was recorded with --per-cpu-threads.
+    #[arg(long, value_name = "LIST", value_parser = parse_cpu_list)]
+    pub cpu: Option<CpuList>,
+
+    /// Only include the samples which were taken on cores of this type, on
+    /// CPUs with performance and efficiency cores. This needs a profile which
+    /// was recorded on Linux with --per-cpu-threads.
+    #[arg(long, value_enum)]
+    pub core_type: Option<CoreTypeArg>,
+
+    #[command(flatten)]
+    pub symbol_args: SymbolArgs,
+}
+
+/// A list of CPU numbers. With this alias, clap parses `--cpu 0-3,8` into one
+/// list instead of expecting one value per CPU.
+type CpuList = Vec<u32>;
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CoreTypeArg {
+    Performance,
+    Efficiency,
+}
+
+#[derive(Debug, Args)]
+pub struct AssertArgs {
+    /// Path to the profile file that should be checked.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
+    pub file: PathBuf,
+
+    /// Fail if the samples whose innermost function matches the regular
+    /// expression exceed the given percentage of all samples, e.g. 'malloc>5%'.
+    /// Can be specified multiple times.
+    #[arg(long, value_name = "REGEX>PERCENT", value_parser = parse_time_threshold)]
+    pub max_self_time: Vec<(regex::Regex, f64)>,
+
+    /// Fail if the samples with a function matching the regular expression
+    /// anywhere on the stack exceed the given percentage of all samples,
+    /// e.g. 'MyHotPath>30%'. Can be specified multiple times.
+    #[arg(long, value_name = "REGEX>PERCENT", value_parser = parse_time_threshold)]
+    pub max_total_time: Vec<(regex::Regex, f64)>,
+
+    #[command(flatten)]
+    pub symbol_args: SymbolArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct SummarizeArgs {
+    /// Path to the profile file that should be summarized.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
+    pub file: PathBuf,
+
+    /// The maximum number of entries in each section.
+    #[arg(short = 'n', long, default_value_t = 5)]
+    pub limit: usize,
+
+    #[command(flatten)]
+    pub symbol_args: SymbolArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Path to the profile file that should be exported.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
+    pub file: PathBuf,
+
+    /// The output format. "flamescope" is a heat map of the sample counts, with
+    /// one column per second and one row per slice of a second, which shows
+    /// periodic work as horizontal lines.
+    #[arg(long, value_enum)]
+    pub format: ExportFormatArg,
+
+    /// With "--format flamescope", how many rows each second is divided into.
+    #[arg(long, default_value_t = 50)]
+    pub rows: usize,
+
+    /// The file to write to. By default, the output is written to stdout.
+    #[arg(short, long, value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExportFormatArg {
+    Flamescope,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// Path to the profile to compare against, e.g. from before a change.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
+    pub before: PathBuf,
+
+    /// Path to the profile which is compared, e.g. from after a change.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
+    pub after: PathBuf,
+
+    /// The output format. "collapsed-diff" prints one line per stack, with the
+    /// function names joined by ';' and followed by the sample counts in both
+    /// profiles, like difffolded.pl. Pipe it into flamegraph.pl to get a
+    /// differential flame graph.
+    #[arg(long, value_enum, default_value_t = DiffFormatArg::CollapsedDiff)]
+    pub format: DiffFormatArg,
+
+    /// Scale the counts of the first profile so that both profiles have the
+    /// same number of samples, e.g. when they were recorded for different
+    /// durations.
+    #[arg(long)]
+    pub normalize: bool,
+
+    /// The file to write to. By default, the output is written to stdout.
+    #[arg(short, long, value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub symbol_args: SymbolArgs,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DiffFormatArg {
+    CollapsedDiff,
+}
+
+#[derive(Debug, Args)]
+pub struct GrepArgs {
+    /// Path to the profile file that should be searched.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
+    pub file: PathBuf,
+
+    /// A regular expression which is matched against the function names of each
+    /// stack, joined by ';' and listed from the innermost function to the root,
+    /// like in a backtrace. For example, 'mutex_lock.*my_crate::' matches stacks
+    /// in which a function from my_crate called mutex_lock, directly or not.
+    #[arg(value_name = "PATTERN", value_parser = regex::Regex::new)]
+    pub pattern: regex::Regex,
+
+    /// List the functions from the root to the innermost function instead, both
+    /// for matching and for printing.
+    #[arg(long)]
+    pub root_first: bool,
+
+    /// Only print the number of matching samples.
+    #[arg(short, long)]
+    pub count: bool,
+
+    /// The maximum number of stacks to print.
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub limit: usize,
+
+    /// Show the library and the library-relative address of each native frame
+    /// next to its function name.
+    #[arg(long)]
+    pub addresses: bool,
+
+    #[command(flatten)]
+    pub symbol_args: SymbolArgs,
+}
+
+#[cfg(target_os = "macos")]
+#[derive(Debug, Args)]
+pub struct SnapshotArgs {
+    /// Process ID of the process to capture.
+    #[arg(short, long)]
+    pub pid: u32,
+
+    /// Output filename for the profile with the captured stacks.
+    #[arg(short, long, default_value = "snapshot.json.gz")]
+    pub output: PathBuf,
+
+    /// Show the library and library-relative address next to each native function.
+    #[arg(long)]
+    pub addresses: bool,
+
+    #[command(flatten)]
+    pub profile_creation_args: ProfileCreationArgs,
+
+    #[command(flatten)]
+    pub symbol_args: SymbolArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    /// Path to the profile file that should be checked.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct AddLogsArgs {
+    /// Path to the profile file, which should have been recorded on this
+    /// machine.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
+    pub file: PathBuf,
+
+    /// Only add the entries of the process with this PID, instead of those of
+    /// all processes in the profile (can be specified multiple times).
+    #[arg(long)]
+    pub pid: Vec<u32>,
+
+    /// Where to save the profile with the added markers. By default, the
+    /// profile file is overwritten.
+    #[arg(short, long, value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct PushArgs {
+    /// Path to the profile file that should be uploaded.
+    #[arg(value_hint = ValueHint::FilePath, add = ArgValueCompleter::new(complete_profile_files))]
+    pub file: PathBuf,
+
+    #[command(flatten)]
+    pub push_target_args: PushTargetArgs,
+
+    #[command(flatten)]
+    pub symbol_args: SymbolArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct PushTargetArgs {
+    /// Upload profiles to the Pyroscope server at this URL, e.g. http://localhost:4040
+    #[arg(long, value_name = "URL", conflicts_with = "otlp")]
+    pub pyroscope: Option<String>,
+
+    /// Upload profiles to this OTLP/HTTP endpoint, e.g. http://localhost:4318.
+    /// This uses the OpenTelemetry profiles signal, which is still in development.
+    #[arg(long, value_name = "URL")]
+    pub otlp: Option<String>,
+
+    /// The name of the service which the uploaded profiles belong to.
+    #[arg(long, default_value = "samply")]
+    pub service_name: String,
+
+    /// Attach a label to the uploaded profiles, e.g. 'env=staging'. Can be
+    /// specified multiple times.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_label)]
+    pub label: Vec<(String, String)>,
+
+    /// Send an extra HTTP header with each upload, e.g. 'Authorization: Bearer <token>'.
+    /// Can be specified multiple times.
+    #[arg(long, value_name = "HEADER", value_parser = parse_http_header)]
+    pub push_header: Vec<(String, String)>,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImportFormatArg {
+    /// A perf.data file from Linux perf or Android simpleperf.
+    Perf,
+    /// The text output of `perf script`.
+    PerfScript,
+    /// A Valgrind massif output file.
+    Massif,
+    /// A heaptrack data file, optionally gzip-compressed.
+    Heaptrack,
+    /// Pre-aggregated stacks in the folded format (`a;b;c 42`), e.g. from
+    /// `stackcollapse-perf.pl` or converted pprof profiles. Detected for files
+    /// ending in `.folded` or `.collapsed`.
+    Folded,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimestampsArg {
+    /// Relative to the start of the profile.
+    Relative,
+    /// Relative to the Unix epoch, i.e. UTC wall-clock times.
+    Utc,
+}
+
+#[cfg(target_os = "windows")]
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EtwPresetArg {
+    /// CPU samples and context switches.
+    Cpu,
+    /// Also disk and file I/O.
+    Io,
+    /// Also VirtualAlloc / VirtualFree and hard page faults.
+    Full,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReportByArg {
+    Line,
+    Address,
+    Function,
+    CallTree,
 }
 
 #[allow(unused)]
@@ -133,10 +609,35 @@ pub struct RecordArgs {
     #[arg(short, long, default_value = "1000")]
     pub rate: f64,
 
+    /// Randomize the time between samples by up to this percentage of the
+    /// sampling interval, in either direction. This avoids aliasing with
+    /// periodic work, such as a 60 Hz render loop sampled at 1 kHz.
+    #[arg(long, value_name = "PERCENT", default_value = "0", value_parser = parse_jitter_percent)]
+    pub interval_jitter: f64,
+
+    /// Sample the threads whose names match a pattern at a different rate,
+    /// e.g. 'RenderThread=4000,Audio*=8000'. `*` matches any characters.
+    /// Samples are weighted so that threads with different rates stay
+    /// comparable. Supported on macOS, FreeBSD and OpenBSD, and on Windows
+    /// with --user-mode-sampler.
+    #[arg(long, value_name = "PATTERN=HZ", value_delimiter = ',', value_parser = parse_rate_override)]
+    pub rate_override: Vec<(String, f64)>,
+
     /// Limit the recorded time to the specified number of seconds
     #[arg(short, long)]
     pub duration: Option<f64>,
 
+    /// Record application metrics as counter tracks while recording: scrape a
+    /// Prometheus endpoint, e.g. 'http://localhost:9090/metrics', or listen for
+    /// statsd packets, e.g. 'statsd://127.0.0.1:8125'. Add '?names=foo,bar' to
+    /// only record some of the metrics. Can be specified multiple times.
+    #[arg(long, value_name = "URL", value_parser = MetricsSource::parse)]
+    pub scrape_metrics: Vec<MetricsSource>,
+
+    /// How often to scrape the Prometheus endpoints given with --scrape-metrics.
+    #[arg(long, value_name = "INTERVAL", default_value = "1s", value_parser = humantime::parse_duration)]
+    pub scrape_interval: Duration,
+
     /// How many times to run the profiled command.
     #[arg(long, default_value = "1")]
     pub iteration_count: u32,
@@ -163,31 +664,136 @@ pub struct RecordArgs {
     pub symbol_args: SymbolArgs,
 
     /// Profile the execution of this command.
-    #[arg(
-        required_unless_present_any = ["pid", "all"],
-        conflicts_with_all = ["pid", "all"],
-        allow_hyphen_values = true,
-        trailing_var_arg = true
+    #[cfg_attr(
+        target_os = "windows",
+        arg(
+            required_unless_present_any = ["pid", "all", "wait_for_process", "service"],
+            conflicts_with_all = ["all", "wait_for_process", "service"]
+        )
+    )]
+    #[cfg_attr(
+        target_os = "linux",
+        arg(
+            required_unless_present_any = ["pid", "all", "wait_for_process", "systemd_unit"],
+            conflicts_with_all = ["all", "wait_for_process", "systemd_unit"]
+        )
+    )]
+    #[cfg_attr(
+        target_os = "macos",
+        arg(
+            required_unless_present_any = ["pid", "all", "wait_for_process", "launchd_label"],
+            conflicts_with_all = ["all", "wait_for_process", "launchd_label"]
+        )
     )]
+    #[cfg_attr(
+        not(any(target_os = "windows", target_os = "linux", target_os = "macos")),
+        arg(
+            required_unless_present_any = ["pid", "all", "wait_for_process"],
+            conflicts_with_all = ["all", "wait_for_process"]
+        )
+    )]
+    #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
     pub command: Vec<std::ffi::OsString>,
 
-    /// Process ID of existing process to attach to.
+    /// Process ID of existing process to attach to. Can be specified multiple
+    /// times, and together with a command, to record several processes into
+    /// the same profile, e.g. `--pid 123 --pid 456 -- my-client`. Recording
+    /// stops once the command has finished (Linux only for more than one process).
     #[arg(short, long, conflicts_with = "all")]
-    pub pid: Option<u32>,
+    pub pid: Vec<u32>,
 
     /// Profile entire system (all processes). Not supported on macOS.
     #[arg(short, long, conflicts_with = "pid")]
     pub all: bool,
 
+    /// Wait for a process whose name contains this string to start, and record it
+    /// from its start. Processes which are already running are ignored.
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["pid", "all"])]
+    pub wait_for_process: Option<String>,
+
+    /// Keep recording when the process given with --pid or --wait-for-process
+    /// quits, e.g. because it crashed or was restarted by a supervisor, and
+    /// record the next process with the same executable name from its start.
+    /// The new process gets a "Restart" marker on its main thread.
+    #[arg(long, conflicts_with_all = ["all", "command"])]
+    pub follow_restarts: bool,
+
+    /// Only keep the samples of the last N seconds in memory, so that a
+    /// long-running process can be recorded for as long as needed. The last N
+    /// seconds are saved when the recording ends, e.g. because the process
+    /// crashed, and also whenever samply receives SIGUSR2, or the process is
+    /// restarted with --follow-restarts or --systemd-unit, to the output path
+    /// with the time added, e.g. profile-2024-05-01T12-00-00Z.json.gz. Only
+    /// samples are dropped: markers, and the stacks of the dropped samples,
+    /// stay in memory for the whole recording (Linux only).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, value_name = "N")]
+    pub ring_buffer_seconds: Option<f64>,
+
+    /// Record the process of this Windows service, by its service name, e.g.
+    /// 'Dnscache'. If the service shares an svchost.exe process with other
+    /// services, the threads of the other services are left out (Windows only).
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["pid", "all", "wait_for_process"])]
+    pub service: Option<String>,
+
+    /// Start the service given with --service if it isn't running. Recording
+    /// begins as soon as the service has a process (Windows only).
+    #[cfg(target_os = "windows")]
+    #[arg(long, requires = "service")]
+    pub start_service: bool,
+
+    /// Record the processes of this systemd unit, e.g. 'nginx.service'. The
+    /// unit's cgroup is followed during the recording, so processes which the
+    /// unit starts later, or after a restart, are recorded too (Linux only).
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_name = "UNIT", conflicts_with_all = ["pid", "all", "wait_for_process"])]
+    pub systemd_unit: Option<String>,
+
+    /// Record the processes of this launchd job, by its label, e.g.
+    /// 'com.example.foo'. If the job is restarted during the recording, its
+    /// new process is recorded too. System daemons need sudo (macOS only).
+    #[cfg(target_os = "macos")]
+    #[arg(long, value_name = "LABEL", conflicts_with_all = ["pid", "all", "wait_for_process"])]
+    pub launchd_label: Option<String>,
+
+    /// Set an environment variable for the launched command. Can be specified
+    /// multiple times. Variables which are given before the command name, as in
+    /// `samply record KEY=VALUE my-command`, take precedence.
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    pub env_vars: Vec<(String, String)>,
+
+    /// Set the environment variables which make common runtimes write perf maps or
+    /// jitdump files for their JIT code: Node.js / V8, Deno, Wasmtime, Mono, the JVM
+    /// and Python 3.12+. Variables which are already set are left alone.
+    #[arg(long)]
+    pub enable_jitdump_env: bool,
+
     /// VM hack for arm64 Windows VMs to not try to record PROFILE events (Windows only).
     #[cfg(target_os = "windows")]
     #[arg(long)]
     pub vm_hack: bool,
 
-    /// Enable Graphics-related event capture.
+    /// Enable Graphics-related event capture: vsync, DXGI presents and DWM frames (Windows only).
     #[arg(long)]
     pub gfx: bool,
 
+    /// Enable audio glitch capture: underruns and glitches reported by the audio engine (Windows only).
+    #[arg(long)]
+    pub audio: bool,
+
+    /// Enable network request capture: DNS queries and WinHTTP/WinINet requests (Windows only).
+    #[arg(long)]
+    pub network: bool,
+
+    /// Enable registry access capture: the key, operation and duration of registry operations (Windows only).
+    #[arg(long)]
+    pub registry: bool,
+
+    /// Enable RPC and COM call capture, with correlation IDs which connect the client and the server side of a call (Windows only).
+    #[arg(long)]
+    pub rpc: bool,
+
     /// Enable browser-related event capture (JavaScript stacks and trace events)
     #[arg(long)]
     pub browsers: bool,
@@ -196,6 +802,207 @@ pub struct RecordArgs {
     #[cfg(target_os = "windows")]
     #[arg(long)]
     pub keep_etl: bool,
+
+    /// Enable this ETW provider (name or GUID) with stack walking, and record its
+    /// events as markers with the stack at the time of the event (Windows only).
+    /// Can be specified multiple times.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "PROVIDER")]
+    pub provider_with_stacks: Vec<String>,
+
+    /// Re-request the process, thread and module lists every so often while
+    /// recording, e.g. "10s", so that modules of processes which were already
+    /// running when recording started are known (Windows only).
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "INTERVAL", value_parser = humantime::parse_duration)]
+    pub rundown_interval: Option<Duration>,
+
+    /// Sample the process by suspending its threads and walking their stacks,
+    /// instead of using ETW. This doesn't need administrator rights, but it's
+    /// less accurate and only records user-mode stacks. It's used automatically
+    /// if the elevated helper can't be started (Windows only).
+    #[cfg(target_os = "windows")]
+    #[arg(long, conflicts_with_all = ["all", "wait_for_process"])]
+    pub user_mode_sampler: bool,
+
+    /// Choose which kernel events are recorded: cpu (samples and context
+    /// switches), io (also disk and file I/O) or full (also virtual memory
+    /// allocations and page faults). See --list-presets (Windows only).
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_enum, default_value_t = EtwPresetArg::Cpu)]
+    pub preset: EtwPresetArg,
+
+    /// Print the kernel flags and events of each --preset and exit (Windows only).
+    #[cfg(target_os = "windows")]
+    #[arg(long, exclusive = true)]
+    pub list_presets: bool,
+
+    /// Record as root via sudo, for processes which samply can't attach to
+    /// otherwise, such as processes of other users. The profile is still
+    /// served by the unprivileged samply (macOS only).
+    #[cfg(target_os = "macos")]
+    #[arg(long, requires = "pid")]
+    pub use_sudo_helper: bool,
+
+    /// Set by --use-sudo-helper on the samply which runs as root.
+    #[cfg(target_os = "macos")]
+    #[arg(long, hide = true)]
+    pub sudo_helper_child: bool,
+
+    /// Also sample kernel stacks with kperf, so that time spent in system calls
+    /// shows the kernel functions below the syscall. Needs root (macOS only).
+    #[cfg(target_os = "macos")]
+    #[arg(long)]
+    pub kernel_stacks: bool,
+
+    /// Read the launched command's stderr through a pipe, pass it on to
+    /// samply's stderr, and show Rust panic messages as markers on the
+    /// panicking thread (Linux only).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, conflicts_with_all = ["pid", "all", "wait_for_process"])]
+    pub panic_markers: bool,
+
+    /// Read the launched command's stdout and stderr through pipes, pass them
+    /// on, and add a marker with the text of each line on the main thread
+    /// (Linux only). Long lines are truncated, and lines beyond 16MB in
+    /// total are dropped.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, conflicts_with_all = ["pid", "all", "wait_for_process"])]
+    pub capture_output: bool,
+
+    /// Read the launched command's stdout through a pipe, pass it on, and add
+    /// an interval marker for each test whose start and result it prints, in
+    /// libtest's JSON format (`cargo test -- -Z unstable-options --format json`)
+    /// or as a pytest report log (`pytest --report-log=/dev/stdout`) (Linux only).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, conflicts_with_all = ["pid", "all", "wait_for_process"])]
+    pub test_markers: bool,
+
+    /// Copy the code of JIT-compiled functions which are listed in a perf map
+    /// file (/tmp/perf-<pid>.map) out of the process's memory, into jitdump
+    /// files in a directory next to the profile, with the extension ".jit".
+    /// This makes the assembly view work for these functions (Linux only).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long)]
+    pub save_jit_code: bool,
+
+    /// Add a marker each time a function is called, with a uprobe, e.g.
+    /// './app:my_function'. BINARY is a path, or the name of an executable on
+    /// the PATH or of a library, e.g. 'libc'. SYMBOL is the name in the
+    /// binary's symbol table. Can be specified multiple times (Linux only).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, value_name = "BINARY:SYMBOL", value_parser = ProbeSpec::parse_uprobe)]
+    pub uprobe: Vec<ProbeSpec>,
+
+    /// Add a marker each time a USDT probe (a dtrace-style static tracepoint)
+    /// is hit, e.g. 'libfoo:provider:probe'. Can be specified multiple times
+    /// (Linux only).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, value_name = "BINARY:PROVIDER:PROBE", value_parser = ProbeSpec::parse_usdt)]
+    pub usdt: Vec<ProbeSpec>,
+
+    /// Add a marker each time a kernel function is called, with a kprobe, e.g.
+    /// 'do_sys_openat2'. Needs root. Can be specified multiple times (Linux
+    /// only).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, value_name = "FUNCTION")]
+    pub kprobe: Vec<String>,
+
+    /// Add a marker each time a kernel tracepoint is hit, with the values of
+    /// its fields, e.g. 'sched:sched_wakeup' or 'syscalls:sys_enter_*'. '*'
+    /// matches any characters. Needs root or a readable /sys/kernel/tracing.
+    /// Can be specified multiple times (Linux only).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long, value_name = "CATEGORY:NAME")]
+    pub tracepoint: Vec<String>,
+
+    /// Sample the stacks of the --uprobe, --usdt, --kprobe and --tracepoint
+    /// hits, and show them on the markers (Linux only).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[arg(long)]
+    pub probe_stacks: bool,
+
+    /// Also sample threads while they're blocked, e.g. on a lock or on I/O, so
+    /// that the time spent waiting shows up under the stack which waited,
+    /// next to the on-CPU samples. On Linux, this records the sched_switch
+    /// tracepoint, which needs root or a readable /sys/kernel/tracing. macOS
+    /// and Windows always sample blocked threads.
+    #[arg(long)]
+    pub off_cpu: bool,
+}
+
+#[allow(unused)]
+#[derive(Debug, Args)]
+pub struct AttachArgs {
+    /// Process ID of the process to record.
+    pub pid: u32,
+
+    /// Sampling rate, in Hz
+    #[arg(short, long, default_value = "1000")]
+    pub rate: f64,
+
+    /// Limit the recorded time to the specified number of seconds
+    #[arg(short, long)]
+    pub duration: Option<f64>,
+
+    /// Also sample threads while they're blocked, like `samply record
+    /// --off-cpu` (Linux only; macOS and Windows always sample blocked threads).
+    #[arg(long)]
+    pub off_cpu: bool,
+
+    #[command(flatten)]
+    pub profile_creation_args: ProfileCreationArgs,
+
+    /// Do not run a local server after recording.
+    #[arg(short, long)]
+    pub save_only: bool,
+
+    /// Output filename.
+    #[arg(short, long, default_value = "profile.json.gz")]
+    pub output: PathBuf,
+
+    #[command(flatten)]
+    pub server_args: ServerArgs,
+
+    #[command(flatten)]
+    pub symbol_args: SymbolArgs,
+}
+
+#[allow(unused)]
+#[derive(Debug, Args)]
+pub struct DaemonArgs {
+    /// The directory in which the profiles are saved. Each profile is named
+    /// after the time at which its recording started.
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub output_dir: PathBuf,
+
+    /// How long to record each profile for, e.g. "60s" or "10min".
+    #[arg(long, default_value = "60s", value_parser = humantime::parse_duration)]
+    pub interval: Duration,
+
+    /// Delete profiles which are older than this, e.g. "24h". By default, all
+    /// profiles are kept.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub keep: Option<Duration>,
+
+    /// Sampling rate, in Hz
+    #[arg(short, long, default_value = "49")]
+    pub rate: f64,
+
+    /// Process ID of existing process to record. Required everywhere but on
+    /// Windows, where all processes are recorded if it's not specified.
+    #[arg(short, long, required = !cfg!(target_os = "windows"))]
+    pub pid: Option<u32>,
+
+    #[command(flatten)]
+    pub profile_creation_args: ProfileCreationArgs,
+
+    /// Where to upload each profile after it has been saved, if anywhere.
+    #[command(flatten)]
+    pub push_target_args: PushTargetArgs,
+
+    #[command(flatten)]
+    pub symbol_args: SymbolArgs,
 }
 
 #[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
@@ -226,6 +1033,18 @@ pub struct ServerArgs {
     #[arg(short, long)]
     pub no_open: bool,
 
+    /// The command to open the profiler UI with, e.g. `firefox` or
+    /// "chromium --new-window". The URL is passed as the last argument.
+    /// By default, the BROWSER environment variable or the system's default
+    /// browser is used.
+    #[arg(long, value_name = "COMMAND", conflicts_with = "no_open")]
+    pub browser: Option<String>,
+
+    /// Print the profiler URL as a QR code, e.g. for opening it on another
+    /// device. Use together with --address to listen on a reachable address.
+    #[arg(long)]
+    pub qr: bool,
+
     /// The address to use for the local web server
     #[arg(long, default_value = "127.0.0.1")]
     pub address: String,
@@ -237,12 +1056,38 @@ pub struct ServerArgs {
     /// Print debugging output.
     #[arg(short, long)]
     pub verbose: bool,
+
+    /// The Firefox Profiler front-end to open the profile in, for example a
+    /// deploy preview or a self-hosted instance. Defaults to the PROFILER_URL
+    /// environment variable, or to https://profiler.firefox.com.
+    #[arg(long, value_name = "URL", conflicts_with = "profiler_dir")]
+    pub profiler_url: Option<String>,
+
+    /// Serve the Firefox Profiler front-end from this directory and open the
+    /// profile in it, instead of loading the front-end from the web. The
+    /// directory needs to contain a static build of the profiler (the `dist`
+    /// directory after running `yarn build-prod` in a checkout of
+    /// https://github.com/firefox-devtools/profiler). This lets you view
+    /// profiles on machines without internet access.
+    #[arg(long, value_name = "DIR")]
+    pub profiler_dir: Option<PathBuf>,
+
+    /// Only allow cross-origin requests to the server from this origin, e.g.
+    /// https://profiler.firefox.com. Can be specified multiple times. The
+    /// origin of the profiler URL is always allowed. By default, requests
+    /// from any origin are allowed.
+    #[arg(long = "allowed-origin", value_name = "ORIGIN")]
+    pub allowed_origins: Vec<String>,
 }
 
 /// Arguments describing where to obtain symbol files.
 #[derive(Debug, Args)]
 pub struct SymbolArgs {
-    /// Extra directories containing symbol files
+    /// Extra directories containing symbol files. Subdirectories are searched
+    /// too, e.g. in a build output directory. Can also be a zip or tar
+    /// file (.zip, .tar, .tar.gz, .tar.xz, .tar.zst), e.g. a symbol bundle
+    /// from CI, whose files are found by debug name and ID without extracting
+    /// the whole bundle.
     #[arg(long)]
     pub symbol_dir: Vec<PathBuf>,
 
@@ -254,7 +1099,8 @@ pub struct SymbolArgs {
     #[arg(long)]
     pub windows_symbol_cache: Option<PathBuf>,
 
-    /// Additional URLs of symbol servers serving Breakpad .sym files
+    /// Additional URLs of symbol servers serving Breakpad .sym files. Can also be
+    /// s3://bucket/prefix or gs://bucket/prefix URLs.
     #[arg(long)]
     pub breakpad_symbol_server: Vec<String>,
 
@@ -269,6 +1115,53 @@ pub struct SymbolArgs {
     /// Extra directory containing symbol files, with the directory structure used by simpleperf's scripts
     #[arg(long)]
     pub simpleperf_binary_cache: Option<PathBuf>,
+
+    /// Archives which may contain binaries or debug files, found by build ID:
+    /// static libraries (.a), .deb and .rpm packages (e.g. -dbgsym / -debuginfo
+    /// packages), and zip files like .apk, .aab and .ipa. Can be specified
+    /// multiple times.
+    #[arg(long, value_name = "PATH")]
+    pub symbol_archive: Vec<PathBuf>,
+
+    /// Look for source files whose path starts with FROM in the directory TO
+    /// instead, e.g. /builds/worker/checkouts/gecko=~/mozilla-central. Can be
+    /// specified multiple times.
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_path_remapping)]
+    pub remap_path_prefix: Vec<(String, PathBuf)>,
+
+    /// Fetch source files whose path starts with PREFIX and which aren't on
+    /// disk from a repository, at the revision from which the binary was
+    /// built, e.g. /builds/worker/checkouts/gecko=hg+https://hg.mozilla.org/mozilla-central@REV
+    /// or /home/runner/work/app=https://github.com/org/app@REV. Can be
+    /// specified multiple times.
+    #[arg(long, value_name = "PREFIX=REMOTE@REV", value_parser = parse_source_repository)]
+    pub source_repo: Vec<(String, wholesym::SourceRepository)>,
+
+    /// Proxy URL for symbol downloads. By default, the HTTP_PROXY, HTTPS_PROXY
+    /// and NO_PROXY environment variables are respected.
+    #[arg(long, value_name = "URL")]
+    pub download_proxy: Option<String>,
+
+    /// How many seconds to wait for a symbol server to connect or to send more data
+    #[arg(long, value_name = "SECONDS")]
+    pub download_timeout: Option<f64>,
+
+    /// How often to retry symbol downloads which failed because of network
+    /// or server problems. The wait between retries doubles each time.
+    #[arg(long, value_name = "COUNT")]
+    pub download_retries: Option<u32>,
+
+    /// The maximum number of symbol files to download at the same time
+    #[arg(long, value_name = "COUNT")]
+    pub max_concurrent_downloads: Option<usize>,
+
+    /// Don't symbolicate libraries whose name matches this pattern up front,
+    /// e.g. 'libxul.so' or 'xul*'; `*` matches any characters. These libraries
+    /// are left out of --unstable-presymbolicate, `samply report` and `samply
+    /// export`, and the profiler only loads their symbols from samply's server
+    /// when it asks for them. Can be specified multiple times.
+    #[arg(long, value_name = "PATTERN")]
+    pub no_symbolicate: Vec<String>,
 }
 
 #[derive(Debug, Args, Clone)]
@@ -276,6 +1169,12 @@ pub struct SetupArgs {
     /// Don't wait for confirmation to codesign.
     #[arg(short = 'y', long)]
     pub yes: bool,
+
+    /// Instead of samply, re-sign this binary with the get-task-allow
+    /// entitlement, so that samply can attach to it even though it uses the
+    /// hardened runtime. Only use this for binaries that you built yourself.
+    #[arg(long, value_name = "BINARY")]
+    pub allow_attach: Option<PathBuf>,
 }
 
 #[derive(Debug, Args, Clone)]
@@ -317,6 +1216,15 @@ pub struct ProfileCreationArgs {
     #[arg(long)]
     pub cswitch_markers: bool,
 
+    /// Attach the stack of a nearby sample to each marker from a marker file,
+    /// as an approximation of where the marker was emitted from. The stack
+    /// isn't captured when the marker is emitted; it's taken from the first
+    /// sample on the marker's thread during the marker, or from the last sample
+    /// before it if that sample is at most one sampling interval before the
+    /// marker, and can belong to code which ran before or after the marker.
+    #[arg(long)]
+    pub marker_stacks_from_samples: bool,
+
     /// Include up to <INCLUDE_ARGS> command line arguments in the process name.
     /// This can help differentiate processes if the same executable is used
     /// for different types of programs. And in --reuse-threads mode it
@@ -332,14 +1240,135 @@ pub struct ProfileCreationArgs {
     #[arg(long)]
     pub unstable_presymbolicate: bool,
 
-    /// Emit markers for any unknown ETW events that are encountered.
-    #[cfg(target_os = "windows")]
+    /// Emit markers for any unknown ETW events that are encountered.
+    #[cfg(target_os = "windows")]
+    #[arg(long)]
+    pub unknown_event_markers: bool,
+
+    /// Treat this ETW event as a frame boundary, e.g.
+    /// 'Microsoft-Windows-DxgKrnl/Present'. The provider is enabled during recording.
+    /// Frame durations are shown in a "Frame rate" track, and slow frames are
+    /// marked (Windows only). On all platforms, marker spans named "frame" from a
+    /// marker file are treated as frames.
+    #[cfg(target_os = "windows")]
+    #[arg(long, value_name = "PROVIDER/EVENT")]
+    pub frame_marker: Option<String>,
+
+    /// If the profile JSON would be larger than this size (e.g. '200MB'), reduce
+    /// it until it fits: merge adjacent samples with the same stack, truncate
+    /// long marker strings, and keep only one in N samples. The applied
+    /// reductions are listed in the profile's meta information.
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size)]
+    pub max_output_size: Option<u64>,
+
+    /// Add a counter track which is computed from other counters when the
+    /// profile is saved, e.g. 'io = "Disk read" + "Disk write"' or
+    /// 'rss_growth = rate(RSS)'. Expressions can use counter names (in double
+    /// quotes if they aren't identifiers), numbers, + - * /, parentheses, and
+    /// rate(...), the change per second. Can be specified multiple times.
+    #[arg(long, value_name = "NAME=EXPRESSION", value_parser = DerivedCounter::parse)]
+    pub derived_counter: Vec<DerivedCounter>,
+
+    /// Add a "Sched latency" track for each thread with its run-queue latency,
+    /// the time between its wakeup and running on a CPU, and mark wakeups which
+    /// waited longer than MS milliseconds (5 by default). Needs ReadyThread events
+    /// on Windows, and sched:sched_wakeup and context switch records in imported
+    /// perf.data files on Linux.
+    #[arg(long, value_name = "MS", num_args = 0..=1, require_equals = true, default_missing_value = "5")]
+    pub sched_latency: Option<f64>,
+
+    /// Save the profile encrypted with age, to the output path with '.age'
+    /// appended, instead of unencrypted. The profile isn't opened in the
+    /// browser; open it later with 'samply load --identity'. Needs the age (or
+    /// rage) command.
+    #[arg(long, requires = "recipient")]
+    pub encrypt_output: bool,
+
+    /// An age recipient for --encrypt-output: a public key like 'age1...' or
+    /// 'ssh-ed25519 ...', or the path of a recipients file. Can be specified
+    /// multiple times.
+    #[arg(long, value_name = "RECIPIENT", requires = "encrypt_output")]
+    pub recipient: Vec<String>,
+
+    /// Replace the matches of this regular expression in marker names and
+    /// marker data with '[redacted]' before the profile is saved, e.g.
+    /// 'Bearer \S+'. Can be specified multiple times.
+    #[arg(long, value_name = "REGEX")]
+    pub redact: Vec<Regex>,
+
+    /// Remove the query string and the fragment of URLs in marker names and
+    /// marker data before the profile is saved.
+    #[arg(long)]
+    pub redact_url_queries: bool,
+
+    /// Replace the path component after this prefix in marker names and marker
+    /// data with a hash before the profile is saved, e.g. with '/home',
+    /// '/home/alice/notes.txt' becomes '/home/#872213e7/notes.txt'. The same
+    /// component always gets the same hash. Can be specified multiple times.
+    #[arg(long, value_name = "PREFIX")]
+    pub hash_path_prefix: Vec<String>,
+
+    /// What the timestamps in the profile are relative to. With 'utc', they are
+    /// UTC wall-clock times, so that they can be correlated with the timestamps
+    /// in logs and in profiles from other machines. Supported when recording on
+    /// Linux and macOS, and when importing perf.data files.
+    #[arg(long, value_name = "REFERENCE", value_enum, default_value_t = TimestampsArg::Relative)]
+    pub timestamps: TimestampsArg,
+
+    /// Also save a profile of samply itself, with how long it took to record or
+    /// convert the profile, to read each library, to save the profile and to
+    /// symbolicate each library. It is saved next to the profile, e.g. as
+    /// profile.json.self-profile.json, and can be attached to reports about
+    /// samply being slow.
+    #[arg(long)]
+    pub self_profile: bool,
+
+    /// Enable CoreCLR event conversion.
+    #[clap(long, require_equals = true, value_name = "FLAG", value_enum, value_delimiter = ',', num_args = 0.., default_values_t = vec![CoreClrArgs::Enabled])]
+    pub coreclr: Vec<CoreClrArgs>,
+}
+
+#[derive(Debug, Args)]
+pub struct SymtoolArgs {
+    #[command(subcommand)]
+    pub action: SymtoolAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SymtoolAction {
+    /// Write a Breakpad .sym file for a binary, in the directory layout which
+    /// Tecken (the Mozilla symbol server) expects for uploads. Zip up the
+    /// output directory to upload it.
+    ExportBreakpad(ExportBreakpadArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ExportBreakpadArgs {
+    /// Path to the binary. On Windows, its PDB is found with the usual
+    /// symbol search, e.g. next to the binary or on a symbol server.
+    #[arg(value_hint = ValueHint::FilePath)]
+    pub binary: PathBuf,
+
+    /// The directory to write the symbol file into. The file is put at
+    /// <DIR>/<debug name>/<debug ID>/<name>.sym.
+    #[arg(short, long, value_name = "DIR", default_value = ".")]
+    pub output_dir: PathBuf,
+
+    #[command(flatten)]
+    pub symbol_args: SymbolArgs,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct CompletionsArgs {
+    /// The shell to generate completions for.
+    #[arg(value_enum)]
+    pub shell: Shell,
+
+    /// Emit a script which asks samply for completions at runtime, instead of
+    /// a static script. This also completes profile files in the current
+    /// directory for "samply load".
     #[arg(long)]
-    pub unknown_event_markers: bool,
-
-    /// Enable CoreCLR event conversion.
-    #[clap(long, require_equals = true, value_name = "FLAG", value_enum, value_delimiter = ',', num_args = 0.., default_values_t = vec![CoreClrArgs::Enabled])]
-    pub coreclr: Vec<CoreClrArgs>,
+    pub dynamic: bool,
 }
 
 #[derive(Debug, Args)]
@@ -361,9 +1390,154 @@ impl LoadArgs {
     }
 }
 
+impl ReportArgs {
+    pub fn report_props(&self) -> ReportProps {
+        ReportProps {
+            aggregation: match self.by {
+                ReportByArg::Line => ReportAggregation::Line,
+                ReportByArg::Address => ReportAggregation::Address,
+                ReportByArg::Function => ReportAggregation::Function,
+                ReportByArg::CallTree => ReportAggregation::CallTree,
+            },
+            limit: self.limit,
+            disassemble: self.disassemble,
+            inverted: self.inverted,
+            addresses: self.addresses,
+            focus: self.focus.clone(),
+            hide: self.hide.clone(),
+            cpu_filter: match (&self.cpu, self.core_type) {
+                (None, None) => None,
+                (cpus, core_type) => Some(CpuFilter {
+                    cpus: cpus.clone(),
+                    core_type: core_type.map(|core_type| match core_type {
+                        CoreTypeArg::Performance => CoreType::Performance,
+                        CoreTypeArg::Efficiency => CoreType::Efficiency,
+                    }),
+                }),
+            },
+        }
+    }
+
+    pub fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+}
+
+impl PushArgs {
+    pub fn push_props(&self) -> Option<PushProps> {
+        self.push_target_args.push_props()
+    }
+
+    pub fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+}
+
+impl PushTargetArgs {
+    /// Returns `None` if no upload target was specified.
+    pub fn push_props(&self) -> Option<PushProps> {
+        let target = match (&self.pyroscope, &self.otlp) {
+            (Some(url), _) => PushTarget::Pyroscope(url.clone()),
+            (None, Some(url)) => PushTarget::Otlp(url.clone()),
+            (None, None) => return None,
+        };
+        Some(PushProps {
+            target,
+            service_name: self.service_name.clone(),
+            labels: self.label.clone(),
+            headers: self.push_header.clone(),
+        })
+    }
+}
+
+impl AssertArgs {
+    pub fn assert_props(&self) -> AssertProps {
+        let self_time = self
+            .max_self_time
+            .iter()
+            .map(|threshold| (TimeThresholdKind::SelfTime, threshold));
+        let total_time = self
+            .max_total_time
+            .iter()
+            .map(|threshold| (TimeThresholdKind::TotalTime, threshold));
+        let thresholds = self_time
+            .chain(total_time)
+            .map(|(kind, (function, max_percentage))| TimeThreshold {
+                kind,
+                function: function.clone(),
+                max_percentage: *max_percentage,
+            })
+            .collect();
+        AssertProps { thresholds }
+    }
+
+    pub fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+}
+
+impl SummarizeArgs {
+    pub fn summarize_props(&self) -> SummarizeProps {
+        SummarizeProps { limit: self.limit }
+    }
+
+    pub fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+}
+
+impl ExportArgs {
+    pub fn export_props(&self) -> ExportProps {
+        ExportProps {
+            format: match self.format {
+                ExportFormatArg::Flamescope => ExportFormat::FlameScope,
+            },
+            rows_per_second: self.rows,
+            output: self.output.clone(),
+        }
+    }
+}
+
+impl DiffArgs {
+    pub fn diff_props(&self) -> DiffProps {
+        DiffProps {
+            format: match self.format {
+                DiffFormatArg::CollapsedDiff => DiffFormat::CollapsedDiff,
+            },
+            normalize: self.normalize,
+            output: self.output.clone(),
+        }
+    }
+
+    pub fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+}
+
+impl GrepArgs {
+    pub fn grep_props(&self) -> GrepProps {
+        GrepProps {
+            pattern: StackPattern {
+                regex: self.pattern.clone(),
+                root_first: self.root_first,
+            },
+            count_only: self.count,
+            limit: self.limit,
+            addresses: self.addresses,
+        }
+    }
+
+    pub fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+}
+
 impl ImportArgs {
     pub fn server_props(&self) -> Option<ServerProps> {
-        if self.save_only {
+        if self.save_only
+            || self.output.as_os_str() == "-"
+            || self.profile_creation_args.encrypt_output
+        {
             None
         } else {
             Some(self.server_args.server_props())
@@ -399,8 +1573,19 @@ impl ImportArgs {
             symbol_props: self.symbol_props(),
             included_processes: self.included_processes(),
             user_etl: self.user_etl.clone(),
+            etw_schemas: self.etw_schemas.clone(),
+            export_etw_schemas: self.export_etw_schemas.clone(),
             aux_file_dir: self.aux_file_dir.clone(),
             time_range: self.time_range,
+            guest_kallsyms: self.guest_kallsyms.clone(),
+            guest_modules: self.guest_modules.clone(),
+            format: self.format.map(|format| match format {
+                ImportFormatArg::Perf => ImportFormat::PerfData,
+                ImportFormatArg::PerfScript => ImportFormat::PerfScript,
+                ImportFormatArg::Massif => ImportFormat::Massif,
+                ImportFormatArg::Heaptrack => ImportFormat::Heaptrack,
+                ImportFormatArg::Folded => ImportFormat::Folded,
+            }),
         }
     }
 }
@@ -408,13 +1593,25 @@ impl ImportArgs {
 impl RecordArgs {
     #[allow(unused)]
     pub fn server_props(&self) -> Option<ServerProps> {
-        if self.save_only {
+        if self.save_only
+            || self.is_sudo_helper_child()
+            || self.profile_creation_args.encrypt_output
+        {
             None
         } else {
             Some(self.server_args.server_props())
         }
     }
 
+    /// Whether this is the samply which --use-sudo-helper runs as root. It
+    /// only records and saves the profile.
+    pub fn is_sudo_helper_child(&self) -> bool {
+        #[cfg(target_os = "macos")]
+        return self.sudo_helper_child;
+        #[cfg(not(target_os = "macos"))]
+        return false;
+    }
+
     pub fn symbol_props(&self) -> SymbolProps {
         self.symbol_args.symbol_props()
     }
@@ -429,12 +1626,30 @@ impl RecordArgs {
             );
             std::process::exit(1);
         }
+        if self.follow_restarts && self.pid.len() != 1 && self.wait_for_process.is_none() {
+            eprintln!("Error: --follow-restarts needs a single --pid or --wait-for-process.");
+            std::process::exit(1);
+        }
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        if self
+            .ring_buffer_seconds
+            .is_some_and(|seconds| !(seconds.is_finite() && seconds > 0.0))
+        {
+            eprintln!("Error: --ring-buffer-seconds must be a finite number greater than zero.");
+            std::process::exit(1);
+        }
         let interval = Duration::from_secs_f64(1.0 / self.rate);
         RecordingProps {
             output_file: self.output.clone(),
             time_limit,
             interval,
+            interval_jitter: self.interval_jitter,
+            thread_rate_overrides: self.rate_override.clone(),
             gfx: self.gfx,
+            audio: self.audio,
+            network: self.network,
+            registry: self.registry,
+            rpc: self.rpc,
             browsers: self.browsers,
             #[cfg(target_os = "windows")]
             vm_hack: self.vm_hack,
@@ -444,21 +1659,116 @@ impl RecordArgs {
             keep_etl: self.keep_etl,
             #[cfg(not(target_os = "windows"))]
             keep_etl: false,
+            #[cfg(target_os = "windows")]
+            providers_with_stacks: self.provider_with_stacks.clone(),
+            #[cfg(not(target_os = "windows"))]
+            providers_with_stacks: Vec::new(),
+            #[cfg(target_os = "windows")]
+            rundown_interval: self.rundown_interval,
+            #[cfg(not(target_os = "windows"))]
+            rundown_interval: None,
+            sample_limit: None,
+            #[cfg(target_os = "windows")]
+            user_mode_sampler: self.user_mode_sampler,
+            #[cfg(not(target_os = "windows"))]
+            user_mode_sampler: false,
+            #[cfg(target_os = "macos")]
+            kernel_stacks: self.kernel_stacks,
+            #[cfg(not(target_os = "macos"))]
+            kernel_stacks: false,
+            #[cfg(target_os = "windows")]
+            etw_preset: match self.preset {
+                EtwPresetArg::Cpu => EtwPreset::Cpu,
+                EtwPresetArg::Io => EtwPreset::Io,
+                EtwPresetArg::Full => EtwPreset::Full,
+            },
+            #[cfg(not(target_os = "windows"))]
+            etw_preset: EtwPreset::Cpu,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            panic_markers: self.panic_markers,
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            panic_markers: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            capture_output: self.capture_output,
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            capture_output: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            test_markers: self.test_markers,
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            test_markers: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            save_jit_code: self.save_jit_code,
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            save_jit_code: false,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            probes: self.uprobe.iter().chain(&self.usdt).cloned().collect(),
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            probes: Vec::new(),
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            kprobes: self.kprobe.clone(),
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            kprobes: Vec::new(),
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            tracepoints: self.tracepoint.clone(),
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            tracepoints: Vec::new(),
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            probe_stacks: self.probe_stacks,
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            probe_stacks: false,
+            off_cpu: self.off_cpu,
+            follow_restarts: self.follow_restarts,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            ring_buffer: self.ring_buffer_seconds.map(Duration::from_secs_f64),
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            ring_buffer: None,
+            metrics_sources: self.scrape_metrics.clone(),
+            metrics_interval: self.scrape_interval,
         }
     }
 
     pub fn recording_mode(&self) -> RecordingMode {
-        let (command, iteration_count) = match (self.all, &self.pid) {
-            (true, _) => return RecordingMode::All,
-            (false, Some(pid)) => return RecordingMode::Pid(*pid),
-            (false, None) => (&self.command, self.iteration_count),
-        };
+        if let Some(name) = &self.wait_for_process {
+            return RecordingMode::WaitForProcess(name.clone());
+        }
+        #[cfg(target_os = "windows")]
+        if let Some(name) = &self.service {
+            return RecordingMode::Service(ServiceProps {
+                name: name.clone(),
+                start: self.start_service,
+            });
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(unit) = &self.systemd_unit {
+            return RecordingMode::SystemdUnit(unit.clone());
+        }
+        #[cfg(target_os = "macos")]
+        if let Some(label) = &self.launchd_label {
+            return RecordingMode::LaunchdJob(label.clone());
+        }
+        if self.all {
+            return RecordingMode::All;
+        }
+        if self.command.is_empty() {
+            return match self.pid.as_slice() {
+                [pid] => RecordingMode::Pid(*pid),
+                pids => RecordingMode::Multiple {
+                    pids: pids.to_vec(),
+                    launch: None,
+                },
+            };
+        }
+        let (command, iteration_count) = (&self.command, self.iteration_count);
 
         assert!(
             !command.is_empty(),
             "CLI parsing should have ensured that we have at least one command name"
         );
-        let mut env_vars = Vec::new();
+        let mut env_vars: Vec<(OsString, OsString)> = self
+            .env_vars
+            .iter()
+            .map(|(name, val)| (name.into(), val.into()))
+            .collect();
         let mut i = 0;
         while let Some((var_name, var_val)) = command.get(i).and_then(|s| split_at_first_equals(s))
         {
@@ -469,6 +1779,9 @@ impl RecordArgs {
             eprintln!("Error: No command name found. Every item looks like an environment variable (contains '='): {command:?}");
             std::process::exit(1);
         }
+        if self.enable_jitdump_env {
+            add_jitdump_env_vars(&mut env_vars);
+        }
         let command_name = command[i].clone();
         let args = command[(i + 1)..].to_owned();
         let launch_props = ProcessLaunchProps {
@@ -479,25 +1792,287 @@ impl RecordArgs {
             ignore_exit_code: self.ignore_exit_code,
         };
 
-        RecordingMode::Launch(launch_props)
+        if self.pid.is_empty() {
+            RecordingMode::Launch(launch_props)
+        } else {
+            RecordingMode::Multiple {
+                pids: self.pid.clone(),
+                launch: Some(launch_props),
+            }
+        }
     }
 
     pub fn profile_creation_props(&self) -> ProfileCreationProps {
         let fallback_profile_name = match self.recording_mode() {
             RecordingMode::All => "All processes".to_string(),
             RecordingMode::Pid(pid) => format!("PID {pid}"),
-            RecordingMode::Launch(launch_props) => {
+            RecordingMode::WaitForProcess(name) => name,
+            RecordingMode::Launch(launch_props)
+            | RecordingMode::Multiple {
+                launch: Some(launch_props),
+                ..
+            } => {
                 let filename = Path::new(&launch_props.command_name)
                     .file_name()
                     .unwrap_or(launch_props.command_name.as_os_str());
                 filename.to_string_lossy().into()
             }
+            RecordingMode::Multiple { pids, launch: None } => {
+                let pids: Vec<String> = pids.iter().map(u32::to_string).collect();
+                format!("PIDs {}", pids.join(", "))
+            }
+            #[cfg(target_os = "windows")]
+            RecordingMode::Service(service_props) => service_props.name,
+            #[cfg(target_os = "linux")]
+            RecordingMode::SystemdUnit(unit) => unit,
+            #[cfg(target_os = "macos")]
+            RecordingMode::LaunchdJob(label) => label,
+        };
+        self.profile_creation_args
+            .profile_creation_props_with_fallback_name(fallback_profile_name)
+    }
+}
+
+/// Environment variables which make runtimes with a JIT emit perf maps
+/// (`/tmp/perf-<pid>.map`) or jitdump files, which samply picks up.
+const JITDUMP_ENV_VARS: &[(&str, &str)] = &[
+    (
+        "NODE_OPTIONS",
+        "--perf-prof --perf-basic-prof --interpreted-frames-native-stack",
+    ),
+    ("DENO_V8_FLAGS", "--perf-prof,--perf-basic-prof"),
+    ("WASMTIME_PROFILING_STRATEGY", "jitdump"),
+    ("MONO_ENV_OPTIONS", "--jitmap"),
+    (
+        "JAVA_TOOL_OPTIONS",
+        "-XX:+UnlockDiagnosticVMOptions -XX:+DumpPerfMapAtExit",
+    ),
+    ("PYTHONPERFSUPPORT", "1"),
+];
+
+fn add_jitdump_env_vars(env_vars: &mut Vec<(OsString, OsString)>) {
+    for (name, value) in JITDUMP_ENV_VARS {
+        if env_vars.iter().any(|(n, _)| n == name) {
+            continue;
+        }
+        // The options variables may already carry other flags in our own environment,
+        // which the launched command inherits. Keep those.
+        let value = match std::env::var_os(name) {
+            Some(existing) if name.ends_with("OPTIONS") => {
+                let mut combined = existing;
+                combined.push(" ");
+                combined.push(value);
+                combined
+            }
+            Some(_) => continue,
+            None => OsString::from(value),
+        };
+        env_vars.push((OsString::from(name), value));
+    }
+}
+
+impl AttachArgs {
+    #[allow(unused)]
+    pub fn recording_props(&self) -> RecordingProps {
+        if self.rate <= 0.0 {
+            eprintln!(
+                "Error: sampling rate must be greater than zero, got {}",
+                self.rate
+            );
+            std::process::exit(1);
+        }
+        RecordingProps {
+            output_file: self.output.clone(),
+            time_limit: self.duration.map(Duration::from_secs_f64),
+            interval: Duration::from_secs_f64(1.0 / self.rate),
+            interval_jitter: 0.0,
+            thread_rate_overrides: Vec::new(),
+            vm_hack: false,
+            gfx: false,
+            audio: false,
+            network: false,
+            registry: false,
+            rpc: false,
+            browsers: false,
+            keep_etl: false,
+            providers_with_stacks: Vec::new(),
+            rundown_interval: None,
+            sample_limit: None,
+            user_mode_sampler: false,
+            kernel_stacks: false,
+            etw_preset: EtwPreset::Cpu,
+            panic_markers: false,
+            capture_output: false,
+            test_markers: false,
+            save_jit_code: false,
+            probes: Vec::new(),
+            kprobes: Vec::new(),
+            tracepoints: Vec::new(),
+            probe_stacks: false,
+            off_cpu: self.off_cpu,
+            follow_restarts: false,
+            ring_buffer: None,
+            metrics_sources: Vec::new(),
+            metrics_interval: DEFAULT_METRICS_INTERVAL,
+        }
+    }
+
+    pub fn recording_mode(&self) -> RecordingMode {
+        RecordingMode::Pid(self.pid)
+    }
+
+    pub fn profile_creation_props(&self) -> ProfileCreationProps {
+        self.profile_creation_args
+            .profile_creation_props_with_fallback_name(format!("PID {}", self.pid))
+    }
+
+    pub fn server_props(&self) -> Option<ServerProps> {
+        if self.save_only || self.profile_creation_args.encrypt_output {
+            None
+        } else {
+            Some(self.server_args.server_props())
+        }
+    }
+
+    pub fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+}
+
+impl DaemonArgs {
+    pub fn daemon_props(&self) -> DaemonProps {
+        if self.interval.is_zero() {
+            eprintln!("Error: the interval must be greater than zero");
+            std::process::exit(1);
+        }
+        DaemonProps {
+            output_dir: self.output_dir.clone(),
+            chunk_duration: self.interval,
+            keep: self.keep,
+            push: self.push_target_args.push_props(),
+        }
+    }
+
+    #[allow(unused)]
+    pub fn recording_props(&self) -> RecordingProps {
+        if self.rate <= 0.0 {
+            eprintln!(
+                "Error: sampling rate must be greater than zero, got {}",
+                self.rate
+            );
+            std::process::exit(1);
+        }
+        RecordingProps {
+            // The daemon sets the output file and the time limit for each chunk.
+            output_file: self.output_dir.clone(),
+            time_limit: Some(self.interval),
+            interval: Duration::from_secs_f64(1.0 / self.rate),
+            interval_jitter: 0.0,
+            thread_rate_overrides: Vec::new(),
+            vm_hack: false,
+            gfx: false,
+            audio: false,
+            network: false,
+            registry: false,
+            rpc: false,
+            browsers: false,
+            keep_etl: false,
+            providers_with_stacks: Vec::new(),
+            rundown_interval: None,
+            sample_limit: None,
+            user_mode_sampler: false,
+            kernel_stacks: false,
+            etw_preset: EtwPreset::Cpu,
+            panic_markers: false,
+            capture_output: false,
+            test_markers: false,
+            save_jit_code: false,
+            probes: Vec::new(),
+            kprobes: Vec::new(),
+            tracepoints: Vec::new(),
+            probe_stacks: false,
+            off_cpu: false,
+            follow_restarts: false,
+            ring_buffer: None,
+            metrics_sources: Vec::new(),
+            metrics_interval: DEFAULT_METRICS_INTERVAL,
+        }
+    }
+
+    pub fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+
+    pub fn recording_mode(&self) -> RecordingMode {
+        match self.pid {
+            Some(pid) => RecordingMode::Pid(pid),
+            None => RecordingMode::All,
+        }
+    }
+
+    pub fn profile_creation_props(&self) -> ProfileCreationProps {
+        let fallback_profile_name = match self.pid {
+            Some(pid) => format!("PID {pid}"),
+            None => "All processes".to_string(),
         };
         self.profile_creation_args
             .profile_creation_props_with_fallback_name(fallback_profile_name)
     }
 }
 
+#[cfg(target_os = "macos")]
+impl SnapshotArgs {
+    pub fn recording_props(&self) -> RecordingProps {
+        RecordingProps {
+            output_file: self.output.clone(),
+            time_limit: None,
+            interval: Duration::from_millis(1),
+            interval_jitter: 0.0,
+            thread_rate_overrides: Vec::new(),
+            vm_hack: false,
+            gfx: false,
+            audio: false,
+            network: false,
+            registry: false,
+            rpc: false,
+            browsers: false,
+            keep_etl: false,
+            providers_with_stacks: Vec::new(),
+            rundown_interval: None,
+            sample_limit: Some(1),
+            user_mode_sampler: false,
+            kernel_stacks: false,
+            etw_preset: EtwPreset::Cpu,
+            panic_markers: false,
+            capture_output: false,
+            test_markers: false,
+            save_jit_code: false,
+            probes: Vec::new(),
+            kprobes: Vec::new(),
+            tracepoints: Vec::new(),
+            probe_stacks: false,
+            off_cpu: false,
+            follow_restarts: false,
+            ring_buffer: None,
+            metrics_sources: Vec::new(),
+            metrics_interval: DEFAULT_METRICS_INTERVAL,
+        }
+    }
+
+    pub fn profile_creation_props(&self) -> ProfileCreationProps {
+        ProfileCreationProps {
+            thread_state_labels: true,
+            ..self
+                .profile_creation_args
+                .profile_creation_props_with_fallback_name(format!("PID {}", self.pid))
+        }
+    }
+
+    pub fn symbol_props(&self) -> SymbolProps {
+        self.symbol_args.symbol_props()
+    }
+}
+
 impl ProfileCreationArgs {
     pub fn coreclr_profile_props(&self) -> CoreClrProfileProps {
         // on Windows, the ..Default::default() has no effect, and clippy doesn't like it
@@ -533,6 +2108,27 @@ impl ProfileCreationArgs {
             unstable_presymbolicate: self.unstable_presymbolicate,
             should_emit_jit_markers: self.jit_markers,
             should_emit_cswitch_markers: self.cswitch_markers,
+            marker_stacks_from_samples: self.marker_stacks_from_samples,
+            max_output_size: self.max_output_size,
+            #[cfg(target_os = "windows")]
+            frame_marker: self.frame_marker.clone(),
+            #[cfg(not(target_os = "windows"))]
+            frame_marker: None,
+            thread_state_labels: false,
+            utc_timestamps: self.timestamps == TimestampsArg::Utc,
+            derived_counters: self.derived_counter.clone(),
+            sched_latency_threshold_ms: self.sched_latency,
+            encryption_recipients: if self.encrypt_output {
+                self.recipient.clone()
+            } else {
+                Vec::new()
+            },
+            redaction: RedactionRules::new(
+                self.redact.clone(),
+                self.redact_url_queries,
+                &self.hash_path_prefix,
+            ),
+            self_profile: self.self_profile,
             coreclr: self.coreclr_profile_props(),
             #[cfg(target_os = "windows")]
             unknown_event_markers: self.unknown_event_markers,
@@ -545,6 +2141,15 @@ impl ProfileCreationArgs {
 impl ServerArgs {
     pub fn server_props(&self) -> ServerProps {
         let open_in_browser = !self.no_open;
+        let browser = self.browser.as_ref().map(|browser| {
+            match shlex::split(browser).filter(|args| !args.is_empty()) {
+                Some(args) => args,
+                None => {
+                    eprintln!("Could not parse browser command {browser:?}");
+                    std::process::exit(1)
+                }
+            }
+        });
         let port_selection = match PortSelection::try_from_str(&self.port) {
             Ok(p) => p,
             Err(e) => {
@@ -573,6 +2178,15 @@ impl ServerArgs {
             port_selection,
             verbose: self.verbose,
             open_in_browser,
+            browser,
+            print_qr_code: self.qr,
+            profiler_url: self.profiler_url.clone(),
+            profiler_dir: self.profiler_dir.clone(),
+            allowed_origins: self
+                .allowed_origins
+                .iter()
+                .map(|origin| origin.trim_end_matches('/').to_string())
+                .collect(),
         }
     }
 }
@@ -587,6 +2201,14 @@ impl SymbolArgs {
             breakpad_symbol_dir: self.breakpad_symbol_dir.clone(),
             breakpad_symbol_cache: self.breakpad_symbol_cache.clone(),
             simpleperf_binary_cache: self.simpleperf_binary_cache.clone(),
+            symbol_archives: self.symbol_archive.clone(),
+            path_remappings: self.remap_path_prefix.clone(),
+            source_repositories: self.source_repo.clone(),
+            download_proxy: self.download_proxy.clone(),
+            download_timeout: self.download_timeout.map(Duration::from_secs_f64),
+            download_retries: self.download_retries,
+            max_concurrent_downloads: self.max_concurrent_downloads,
+            no_symbolicate: self.no_symbolicate.clone(),
         }
     }
 }
@@ -621,8 +2243,283 @@ mod test {
             "Arguments which come before the command name should be treated as samply arguments."
         );
 
-        // Make sure you can't pass both a pid and a command name at the same time.
-        let opt_res = Opt::try_parse_from(["samply", "record", "-p", "1234", "rustup"]);
+        // Several pids and a command name are recorded together.
+        let opt = Opt::parse_from(["samply", "record", "-p", "12", "-p", "34", "--", "rustup"]);
+        let Action::Record(record_args) = opt.action else {
+            panic!("expected the record action");
+        };
+        assert!(matches!(
+            record_args.recording_mode(),
+            RecordingMode::Multiple { pids, launch: Some(launch_props) }
+                if pids == [12, 34] && launch_props.command_name == "rustup"
+        ));
+        let opt = Opt::parse_from(["samply", "record", "-p", "12", "-p", "34"]);
+        let Action::Record(record_args) = opt.action else {
+            panic!("expected the record action");
+        };
+        assert_eq!(
+            record_args.profile_creation_props().fallback_profile_name,
+            "PIDs 12, 34"
+        );
+        let opt_res = Opt::try_parse_from(["samply", "record", "-p", "1234", "--all"]);
+        assert!(opt_res.is_err());
+
+        let opt = Opt::parse_from(["samply", "record", "--wait-for-process", "helper"]);
+        let Action::Record(record_args) = opt.action else {
+            panic!("expected the record action");
+        };
+        assert!(
+            matches!(record_args.recording_mode(), RecordingMode::WaitForProcess(name) if name == "helper")
+        );
+        let opt_res =
+            Opt::try_parse_from(["samply", "record", "--wait-for-process", "helper", "rustup"]);
+        assert!(opt_res.is_err());
+
+        let opt = Opt::parse_from([
+            "samply",
+            "record",
+            "--env",
+            "A=1",
+            "--env",
+            "B=",
+            "--enable-jitdump-env",
+            "WASMTIME_PROFILING_STRATEGY=perfmap",
+            "rustup",
+        ]);
+        let Action::Record(record_args) = opt.action else {
+            panic!("expected the record action");
+        };
+        let RecordingMode::Launch(launch_props) = record_args.recording_mode() else {
+            panic!("expected the launch recording mode");
+        };
+        assert_eq!(launch_props.command_name, "rustup");
+        let expected_env_vars: Vec<(OsString, OsString)> = vec![
+            ("A".into(), "1".into()),
+            ("B".into(), "".into()),
+            ("WASMTIME_PROFILING_STRATEGY".into(), "perfmap".into()),
+        ];
+        assert_eq!(launch_props.env_vars[..3], expected_env_vars[..]);
+        assert_eq!(
+            launch_props
+                .env_vars
+                .iter()
+                .filter(|(name, _)| name == "WASMTIME_PROFILING_STRATEGY")
+                .count(),
+            1
+        );
+        assert!(Opt::try_parse_from(["samply", "record", "--env", "NOEQUALS", "rustup"]).is_err());
+
+        let opt = Opt::parse_from([
+            "samply",
+            "record",
+            "--rate-override",
+            "RenderThread=4000,Audio*=8000",
+            "--rate-override",
+            "GC=100",
+            "rustup",
+        ]);
+        let Action::Record(record_args) = opt.action else {
+            panic!("expected the record action");
+        };
+        assert_eq!(
+            record_args.recording_props().thread_rate_overrides,
+            vec![
+                ("RenderThread".to_string(), 4000.0),
+                ("Audio*".to_string(), 8000.0),
+                ("GC".to_string(), 100.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_cli_report() {
+        let opt = Opt::parse_from(["samply", "report", "profile.json"]);
+        assert!(
+            matches!(opt.action, Action::Report(args) if args.by == ReportByArg::Address && args.limit == 50)
+        );
+
+        let opt = Opt::parse_from([
+            "samply",
+            "report",
+            "--by",
+            "line",
+            "-n",
+            "10",
+            "profile.json",
+        ]);
+        assert!(
+            matches!(opt.action, Action::Report(args) if args.by == ReportByArg::Line && args.limit == 10)
+        );
+
+        let opt = Opt::parse_from([
+            "samply",
+            "report",
+            "--by",
+            "call-tree",
+            "--inverted",
+            "--focus",
+            "^main$",
+            "--hide",
+            "^alloc::",
+            "--hide",
+            "malloc",
+            "profile.json",
+        ]);
+        assert!(matches!(opt.action, Action::Report(args)
+            if args.by == ReportByArg::CallTree && args.inverted && args.focus.is_some() && args.hide.len() == 2));
+
+        let opt_res = Opt::try_parse_from(["samply", "report", "--focus", "(", "profile.json"]);
+        assert!(opt_res.is_err());
+    }
+
+    #[cfg(any(target_os = "android", target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn verify_cli_attach() {
+        let opt = Opt::parse_from(["samply", "attach", "1234", "--duration", "10", "-s"]);
+        let Action::Attach(args) = opt.action else {
+            panic!("expected the attach action");
+        };
+        assert!(matches!(args.recording_mode(), RecordingMode::Pid(1234)));
+        assert_eq!(
+            args.recording_props().time_limit,
+            Some(Duration::from_secs(10))
+        );
+        assert!(args.server_props().is_none());
+
+        assert!(Opt::try_parse_from(["samply", "attach"]).is_err());
+    }
+
+    #[test]
+    fn verify_cli_assert() {
+        let opt = Opt::parse_from([
+            "samply",
+            "assert",
+            "profile.json",
+            "--max-self-time",
+            "malloc>5%",
+            "--max-total-time",
+            "MyHotPath>30%",
+        ]);
+        let Action::Assert(args) = opt.action else {
+            panic!("expected the assert action");
+        };
+        let props = args.assert_props();
+        assert_eq!(props.thresholds.len(), 2);
+        assert_eq!(props.thresholds[0].kind, TimeThresholdKind::SelfTime);
+        assert_eq!(props.thresholds[0].function.as_str(), "malloc");
+        assert_eq!(props.thresholds[1].kind, TimeThresholdKind::TotalTime);
+        assert_eq!(props.thresholds[1].max_percentage, 30.0);
+    }
+
+    #[test]
+    fn verify_cli_push() {
+        let opt = Opt::parse_from([
+            "samply",
+            "push",
+            "profile.json.gz",
+            "--pyroscope",
+            "http://localhost:4040",
+            "--service-name",
+            "my-service",
+            "--label",
+            "env=staging",
+        ]);
+        let Action::Push(args) = opt.action else {
+            panic!("expected the push action");
+        };
+        let props = args.push_props().unwrap();
+        assert_eq!(
+            props.target,
+            PushTarget::Pyroscope("http://localhost:4040".to_string())
+        );
+        assert_eq!(props.service_name, "my-service");
+        assert_eq!(
+            props.labels,
+            vec![("env".to_string(), "staging".to_string())]
+        );
+
+        let opt_res = Opt::try_parse_from([
+            "samply",
+            "push",
+            "profile.json.gz",
+            "--pyroscope",
+            "http://localhost:4040",
+            "--otlp",
+            "http://localhost:4318",
+        ]);
+        assert!(opt_res.is_err());
+    }
+
+    #[test]
+    fn verify_cli_daemon() {
+        let opt = Opt::parse_from([
+            "samply",
+            "daemon",
+            "--interval",
+            "60s",
+            "--keep",
+            "24h",
+            "--output-dir",
+            "/var/lib/samply",
+            "--pid",
+            "1234",
+        ]);
+        let Action::Daemon(args) = opt.action else {
+            panic!("expected the daemon action");
+        };
+        let props = args.daemon_props();
+        assert_eq!(props.chunk_duration, Duration::from_secs(60));
+        assert_eq!(props.keep, Some(Duration::from_secs(24 * 60 * 60)));
+        assert_eq!(props.output_dir, PathBuf::from("/var/lib/samply"));
+        assert!(matches!(args.recording_mode(), RecordingMode::Pid(1234)));
+
+        let opt_res = Opt::try_parse_from(["samply", "daemon", "--interval", "60s"]);
+        assert!(opt_res.is_err());
+    }
+
+    #[test]
+    fn verify_cli_daemon_without_pid() {
+        let opt_res = Opt::try_parse_from(["samply", "daemon", "--output-dir", "/var/lib/samply"]);
+        if cfg!(target_os = "windows") {
+            let Action::Daemon(args) = opt_res.unwrap().action else {
+                panic!("expected the daemon action");
+            };
+            assert!(matches!(args.recording_mode(), RecordingMode::All));
+        } else {
+            let err = opt_res.unwrap_err();
+            assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+            assert!(err.to_string().contains("--pid"));
+        }
+    }
+
+    #[test]
+    fn verify_cli_symtool() {
+        let opt = Opt::parse_from([
+            "samply",
+            "symtool",
+            "export-breakpad",
+            "target/release/app",
+            "-o",
+            "symbols",
+        ]);
+        let Action::Symtool(SymtoolArgs {
+            action: SymtoolAction::ExportBreakpad(args),
+        }) = opt.action
+        else {
+            panic!("expected the symtool export-breakpad action");
+        };
+        assert_eq!(args.binary, PathBuf::from("target/release/app"));
+        assert_eq!(args.output_dir, PathBuf::from("symbols"));
+    }
+
+    #[test]
+    fn verify_cli_completions() {
+        let opt = Opt::parse_from(["samply", "completions", "zsh"]);
+        assert!(
+            matches!(opt.action, Action::Completions(args) if args.shell == Shell::Zsh && !args.dynamic)
+        );
+
+        let opt_res = Opt::try_parse_from(["samply", "completions", "tcsh"]);
         assert!(opt_res.is_err());
     }
 }
@@ -27,6 +27,143 @@ pub fn parse_time_range(
     Ok((start, if is_duration { start + end } else { end }))
 }
 
+/// Parses a threshold of the form `<function regex>><percentage>%`, e.g. `malloc>5%`.
+pub fn parse_time_threshold(arg: &str) -> Result<(regex::Regex, f64), String> {
+    let Some((function, percentage)) = arg.rsplit_once('>') else {
+        return Err("expected a threshold of the form 'function>5%'".to_string());
+    };
+    let percentage = percentage.trim().trim_end_matches('%');
+    let percentage: f64 = percentage
+        .parse()
+        .map_err(|_| format!("invalid percentage {percentage:?}"))?;
+    let function = regex::Regex::new(function.trim()).map_err(|e| e.to_string())?;
+    Ok((function, percentage))
+}
+
+/// Parses a jitter percentage, e.g. `10` or `10%`. Jitter of 100% or more
+/// would allow zero-length intervals, so it must be below 100.
+pub fn parse_jitter_percent(arg: &str) -> Result<f64, String> {
+    let percentage = arg.trim().trim_end_matches('%');
+    let percentage: f64 = percentage
+        .parse()
+        .map_err(|_| format!("invalid percentage {percentage:?}"))?;
+    if !(0.0..100.0).contains(&percentage) {
+        return Err("the jitter must be at least 0% and less than 100%".to_string());
+    }
+    Ok(percentage)
+}
+
+/// Parses a per-thread sampling rate of the form `PATTERN=HZ`, e.g.
+/// `Audio*=8000`.
+pub fn parse_rate_override(arg: &str) -> Result<(String, f64), String> {
+    let (pattern, rate) = arg
+        .rsplit_once('=')
+        .filter(|(pattern, _)| !pattern.trim().is_empty())
+        .ok_or_else(|| "expected a rate of the form 'ThreadName=4000'".to_string())?;
+    let rate: f64 = rate
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid rate {rate:?}"))?;
+    if !(rate > 0.0 && rate.is_finite()) {
+        return Err(format!(
+            "the rate for {pattern:?} must be greater than zero"
+        ));
+    }
+    Ok((pattern.trim().to_string(), rate))
+}
+
+/// Parses a size in bytes, with an optional unit, e.g. `200MB`, `1.5G` or `4096`.
+/// The units are powers of 1000.
+pub fn parse_byte_size(arg: &str) -> Result<u64, String> {
+    let arg = arg.trim();
+    let number_end = arg
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(number_end);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {arg:?}, expected something like '200MB'"))?;
+    let factor = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" | "KB" => 1e3,
+        "M" | "MB" => 1e6,
+        "G" | "GB" => 1e9,
+        _ => return Err(format!("unknown size unit {unit:?}")),
+    };
+    Ok((number * factor) as u64)
+}
+
+/// Parses a label of the form `key=value`.
+pub fn parse_label(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err("expected a label of the form 'key=value'".to_string()),
+    }
+}
+
+/// Parses an environment variable of the form `KEY=VALUE`. The value may be empty.
+pub fn parse_env_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err("expected an environment variable of the form 'KEY=VALUE'".to_string()),
+    }
+}
+
+/// Parses an HTTP header of the form `Name: value`.
+pub fn parse_http_header(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err("expected a header of the form 'Name: value'".to_string()),
+    }
+}
+
+/// Parses a path remapping of the form `FROM=TO`. A leading `~` in `TO` is
+/// replaced with the home directory.
+pub fn parse_path_remapping(arg: &str) -> Result<(String, std::path::PathBuf), String> {
+    let (from, to) = match arg.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => (from, to),
+        _ => return Err("expected a remapping of the form 'FROM=TO'".to_string()),
+    };
+    let to = match (to.strip_prefix('~'), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            std::path::PathBuf::from(home).join(rest.trim_start_matches('/'))
+        }
+        _ => std::path::PathBuf::from(to),
+    };
+    Ok((from.to_string(), to))
+}
+
+/// Parses a source repository of the form `PREFIX=REMOTE@REV`, where
+/// `REMOTE` is a git remote, or a mercurial remote with an `hg+` prefix.
+/// A `git+` prefix is also accepted.
+pub fn parse_source_repository(arg: &str) -> Result<(String, wholesym::SourceRepository), String> {
+    let err =
+        || "expected a source repository of the form 'PREFIX=[git+|hg+]REMOTE@REV'".to_string();
+    let (prefix, repository) = arg.split_once('=').ok_or_else(err)?;
+    let (remote, rev) = repository.rsplit_once('@').ok_or_else(err)?;
+    if prefix.is_empty() || rev.is_empty() || rev.contains('/') {
+        return Err(err());
+    }
+    let rev = rev.to_string();
+    let repository = if let Some(remote) = remote.strip_prefix("hg+") {
+        wholesym::SourceRepository::Hg {
+            remote: remote.to_string(),
+            rev,
+        }
+    } else {
+        let remote = remote.strip_prefix("git+").unwrap_or(remote);
+        wholesym::SourceRepository::Git {
+            remote: remote.to_string(),
+            rev,
+        }
+    };
+    Ok((prefix.to_string(), repository))
+}
+
 pub fn split_at_first_equals(s: &OsStr) -> Option<(&OsStr, &OsStr)> {
     let bytes = s.as_encoded_bytes();
     let pos = bytes.iter().position(|b| *b == b'=')?;
@@ -43,3 +180,159 @@ pub fn split_at_first_equals(s: &OsStr) -> Option<(&OsStr, &OsStr)> {
     };
     Some((name, val))
 }
+
+/// Dynamic completion candidates for profile files: any `.json`, `.json.gz`
+/// or `.json.xz` files in the directory of the partially-typed path (the
+/// current directory by default, which is where `samply record` saves its
+/// output).
+pub fn complete_profile_files(current: &OsStr) -> Vec<clap_complete::CompletionCandidate> {
+    let current = current.to_string_lossy();
+    let (dir, prefix) = match current.rfind(std::path::is_separator) {
+        Some(pos) => (&current[..=pos], &current[(pos + 1)..]),
+        None => ("", &current[..]),
+    };
+    let read_dir_path = if dir.is_empty() { "." } else { dir };
+    let Ok(entries) = std::fs::read_dir(read_dir_path) else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) {
+                return None;
+            }
+            if entry.file_type().ok()?.is_dir() {
+                return Some(format!("{dir}{name}{}", std::path::MAIN_SEPARATOR));
+            }
+            if is_profile_file_name(&name) {
+                Some(format!("{dir}{name}"))
+            } else {
+                None
+            }
+        })
+        .collect();
+    candidates.sort();
+    candidates
+        .into_iter()
+        .map(clap_complete::CompletionCandidate::new)
+        .collect()
+}
+
+fn is_profile_file_name(name: &str) -> bool {
+    let is_json = [".json", ".json.gz", ".json.xz"]
+        .iter()
+        .any(|ext| name.ends_with(ext));
+    // Exclude the .syms.json sidecar files written by --unstable-presymbolicate.
+    is_json && !name.ends_with(".syms.json")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn time_thresholds() {
+        let (function, percentage) = parse_time_threshold("malloc>5%").unwrap();
+        assert_eq!(function.as_str(), "malloc");
+        assert_eq!(percentage, 5.0);
+
+        let (function, percentage) = parse_time_threshold("a->b>12.5").unwrap();
+        assert_eq!(function.as_str(), "a->b");
+        assert_eq!(percentage, 12.5);
+
+        assert!(parse_time_threshold("malloc").is_err());
+        assert!(parse_time_threshold("malloc>lots").is_err());
+        assert!(parse_time_threshold("(>5%").is_err());
+    }
+
+    #[test]
+    fn jitter_percentages() {
+        assert_eq!(parse_jitter_percent("10"), Ok(10.0));
+        assert_eq!(parse_jitter_percent("12.5%"), Ok(12.5));
+        assert!(parse_jitter_percent("100").is_err());
+        assert!(parse_jitter_percent("-5").is_err());
+        assert!(parse_jitter_percent("some").is_err());
+    }
+
+    #[test]
+    fn rate_overrides() {
+        assert_eq!(
+            parse_rate_override("Audio*=8000").unwrap(),
+            ("Audio*".to_string(), 8000.0)
+        );
+        assert!(parse_rate_override("RenderThread").is_err());
+        assert!(parse_rate_override("=4000").is_err());
+        assert!(parse_rate_override("RenderThread=0").is_err());
+        assert!(parse_rate_override("RenderThread=fast").is_err());
+    }
+
+    #[test]
+    fn byte_sizes() {
+        assert_eq!(parse_byte_size("200MB"), Ok(200_000_000));
+        assert_eq!(parse_byte_size("1.5g"), Ok(1_500_000_000));
+        assert_eq!(parse_byte_size("4096"), Ok(4096));
+        assert!(parse_byte_size("10 parsecs").is_err());
+        assert!(parse_byte_size("MB").is_err());
+    }
+
+    #[test]
+    fn labels_and_headers() {
+        assert_eq!(
+            parse_label("env=staging").unwrap(),
+            ("env".to_string(), "staging".to_string())
+        );
+        assert!(parse_label("staging").is_err());
+        assert!(parse_label("=staging").is_err());
+        assert_eq!(
+            parse_http_header("Authorization: Bearer abc").unwrap(),
+            ("Authorization".to_string(), "Bearer abc".to_string())
+        );
+        assert!(parse_http_header("Authorization").is_err());
+    }
+
+    #[test]
+    fn path_remappings() {
+        let (from, to) = parse_path_remapping("/builds/worker=/src/gecko").unwrap();
+        assert_eq!(from, "/builds/worker");
+        assert_eq!(to, std::path::PathBuf::from("/src/gecko"));
+        assert!(parse_path_remapping("/builds/worker").is_err());
+        assert!(parse_path_remapping("=/src").is_err());
+    }
+
+    #[test]
+    fn source_repositories() {
+        assert_eq!(
+            parse_source_repository(
+                "/builds/worker/checkouts/gecko=hg+https://hg.mozilla.org/mozilla-central@1c5b6ab"
+            )
+            .unwrap(),
+            (
+                "/builds/worker/checkouts/gecko".to_string(),
+                wholesym::SourceRepository::Hg {
+                    remote: "https://hg.mozilla.org/mozilla-central".to_string(),
+                    rev: "1c5b6ab".to_string()
+                }
+            )
+        );
+        assert_eq!(
+            parse_source_repository("/home/runner/work/app=git@example.com:org/app.git@v1.2")
+                .unwrap()
+                .1,
+            wholesym::SourceRepository::Git {
+                remote: "git@example.com:org/app.git".to_string(),
+                rev: "v1.2".to_string()
+            }
+        );
+        assert!(parse_source_repository("/src=https://github.com/mstange/samply").is_err());
+        assert!(parse_source_repository("https://github.com/mstange/samply@abc").is_err());
+    }
+
+    #[test]
+    fn profile_file_names() {
+        assert!(is_profile_file_name("profile.json.gz"));
+        assert!(is_profile_file_name("prof.json"));
+        assert!(!is_profile_file_name("prof.syms.json"));
+        assert!(!is_profile_file_name("perf.data"));
+    }
+}
@@ -0,0 +1,131 @@
+//! The `samply daemon` command, which records profiles in fixed-length chunks
+//! until it is stopped, and deletes old chunks.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::export::push_profile;
+use crate::profiler;
+use crate::shared::ctrl_c::CtrlC;
+use crate::shared::prop_types::{
+    DaemonProps, ProfileCreationProps, RecordingMode, RecordingProps, SymbolProps,
+};
+use crate::shared::save_profile::{save_profile_with_post_processing, PostProcessing};
+
+const CHUNK_FILE_PREFIX: &str = "samply-";
+const CHUNK_FILE_SUFFIX: &str = ".json.gz";
+
+pub fn run_daemon(
+    daemon_props: DaemonProps,
+    recording_mode: RecordingMode,
+    recording_props: RecordingProps,
+    profile_creation_props: ProfileCreationProps,
+    symbol_props: SymbolProps,
+) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(&daemon_props.output_dir)?;
+
+    loop {
+        let chunk_start = Instant::now();
+        let chunk_path = daemon_props
+            .output_dir
+            .join(chunk_file_name(SystemTime::now()));
+
+        let recording_props = RecordingProps {
+            output_file: chunk_path.clone(),
+            time_limit: Some(daemon_props.chunk_duration),
+            ..recording_props.clone()
+        };
+        let (profile, _exit_status) = match profiler::run(
+            recording_mode.clone(),
+            recording_props,
+            profile_creation_props.clone(),
+        ) {
+            Ok(result) => result,
+            Err(err) => {
+                return Err(std::io::Error::other(format!(
+                    "Encountered an error during profiling: {err:?}"
+                )))
+            }
+        };
+        save_profile_with_post_processing(
+            &profile,
+            &chunk_path,
+            &PostProcessing::from_props(&profile_creation_props),
+        )?;
+        drop(profile);
+        eprintln!("Saved profile chunk to {}", chunk_path.display());
+
+        if let Some(push_props) = &daemon_props.push {
+            // Keep recording if the upload fails; the chunk is still on disk.
+            if let Err(err) = push_profile(&chunk_path, push_props, symbol_props.clone()) {
+                eprintln!("Could not upload {}: {err}", chunk_path.display());
+            }
+        }
+
+        if let Some(keep) = daemon_props.keep {
+            remove_expired_chunks(&daemon_props.output_dir, keep);
+        }
+
+        if CtrlC::was_pressed() {
+            break;
+        }
+        if chunk_start.elapsed() < daemon_props.chunk_duration {
+            // The recording stopped before the time limit, without Ctrl+C. This
+            // happens when the profiled process has exited.
+            eprintln!("The profiled process has exited, stopping.");
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the file name for a chunk which started at `start_time`, e.g.
+/// `samply-2024-06-01T12-30-00Z.json.gz`. The names sort chronologically.
+fn chunk_file_name(start_time: SystemTime) -> String {
+    // Colons are not allowed in file names on Windows.
+    let timestamp = humantime::format_rfc3339_seconds(start_time)
+        .to_string()
+        .replace(':', "-");
+    format!("{CHUNK_FILE_PREFIX}{timestamp}{CHUNK_FILE_SUFFIX}")
+}
+
+fn is_chunk_file_name(name: &str) -> bool {
+    name.starts_with(CHUNK_FILE_PREFIX) && name.ends_with(CHUNK_FILE_SUFFIX)
+}
+
+/// Deletes the chunks in `output_dir` which were last modified more than `keep` ago.
+fn remove_expired_chunks(output_dir: &Path, keep: Duration) {
+    let Ok(entries) = std::fs::read_dir(output_dir) else {
+        return;
+    };
+    let now = SystemTime::now();
+    let expired_chunks: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| is_chunk_file_name(&entry.file_name().to_string_lossy()))
+        .filter(|entry| {
+            let modified = entry.metadata().and_then(|metadata| metadata.modified());
+            modified.is_ok_and(|modified| now.duration_since(modified).is_ok_and(|age| age > keep))
+        })
+        .map(|entry| entry.path())
+        .collect();
+    for path in expired_chunks {
+        if let Err(err) = std::fs::remove_file(&path) {
+            eprintln!("Could not remove expired profile chunk {path:?}: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chunk_file_names() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let name = chunk_file_name(time);
+        assert_eq!(name, "samply-2023-11-14T22-13-20Z.json.gz");
+        assert!(is_chunk_file_name(&name));
+        assert!(!is_chunk_file_name("profile.json.gz"));
+        assert!(!is_chunk_file_name("samply-notes.txt"));
+    }
+}
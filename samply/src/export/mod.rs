@@ -0,0 +1,92 @@
+//! Pushing saved profiles to observability backends, for `samply push` and
+//! `samply daemon --push-*`.
+
+mod otlp;
+mod pyroscope;
+
+use std::path::Path;
+
+use crate::report::{symbolicated_stacks, SymbolicatedStacks};
+use crate::shared::prop_types::{PushProps, PushTarget, SymbolProps};
+
+#[derive(thiserror::Error, Debug)]
+pub enum PushError {
+    #[error("Could not read the profile: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("The HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("The server responded with status {0}: {1}")]
+    Status(u16, String),
+}
+
+/// An HTTP POST request which uploads a profile.
+struct PushRequest {
+    url: String,
+    query: Vec<(&'static str, String)>,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+/// Symbolicates the profile at `profile_path` and uploads it to the target in `push_props`.
+pub fn push_profile(
+    profile_path: &Path,
+    push_props: &PushProps,
+    symbol_props: SymbolProps,
+) -> Result<(), PushError> {
+    let stacks = symbolicated_stacks(profile_path, symbol_props)?;
+    let requests = build_requests(&stacks, push_props);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let client = reqwest::Client::new();
+        for request in requests {
+            let mut builder = client
+                .post(&request.url)
+                .query(&request.query)
+                .header(reqwest::header::CONTENT_TYPE, request.content_type)
+                .body(request.body);
+            for (name, value) in &push_props.headers {
+                builder = builder.header(name, value);
+            }
+            let response = builder.send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                let text = response.text().await.unwrap_or_default();
+                return Err(PushError::Status(status.as_u16(), text));
+            }
+        }
+        Ok(())
+    })
+}
+
+fn build_requests(stacks: &SymbolicatedStacks, push_props: &PushProps) -> Vec<PushRequest> {
+    match &push_props.target {
+        PushTarget::Pyroscope(base_url) => {
+            // The folded format has no per-sample labels, so the stacks of each
+            // label set are uploaded separately, with the labels in the
+            // application name.
+            let url = format!("{}/ingest", base_url.trim_end_matches('/'));
+            pyroscope::stacks_by_labels(stacks)
+                .into_iter()
+                .map(|(labels, label_stacks)| PushRequest {
+                    url: url.clone(),
+                    query: pyroscope::ingest_query(stacks, push_props, &labels),
+                    content_type: "text/plain",
+                    body: pyroscope::folded_stacks(&label_stacks).into_bytes(),
+                })
+                .collect()
+        }
+        PushTarget::Otlp(base_url) => vec![PushRequest {
+            url: format!("{}/v1development/profiles", base_url.trim_end_matches('/')),
+            query: Vec::new(),
+            content_type: "application/json",
+            body: otlp::profiles_data(stacks, push_props)
+                .to_string()
+                .into_bytes(),
+        }],
+    }
+}
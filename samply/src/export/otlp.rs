@@ -0,0 +1,168 @@
+//! The OpenTelemetry profiles signal, sent as OTLP/HTTP JSON.
+//!
+//! The profiles signal is still in development. This follows the `v1development`
+//! protocol from opentelemetry-proto 1.5, in which each profile has its own
+//! string, function and location tables.
+
+use indexmap::IndexSet;
+use serde_json::{json, Value};
+
+use crate::report::SymbolicatedStacks;
+use crate::shared::prop_types::PushProps;
+
+/// Returns the `ProfilesData` message for the stacks, in the OTLP JSON encoding.
+pub fn profiles_data(stacks: &SymbolicatedStacks, push_props: &PushProps) -> Value {
+    let mut strings: IndexSet<String> = IndexSet::new();
+    // The string table must start with the empty string.
+    strings.insert(String::new());
+    let mut string_index = |s: &str| strings.insert_full(s.to_owned()).0;
+
+    let sample_type = json!({
+        "typeStrindex": string_index("samples"),
+        "unitStrindex": string_index("count"),
+    });
+    let period_type = json!({
+        "typeStrindex": string_index("cpu"),
+        "unitStrindex": string_index("nanoseconds"),
+    });
+
+    // There's one location per function, so the location table and the
+    // function table have the same indexes.
+    let mut functions: IndexSet<&str> = IndexSet::new();
+    let mut attributes: IndexSet<(&str, &str)> = IndexSet::new();
+    let mut location_indices: Vec<usize> = Vec::new();
+    let mut samples: Vec<Value> = Vec::new();
+    for stack in &stacks.stacks {
+        let count = stack.weight.round() as i64;
+        if count == 0 || stack.functions.is_empty() {
+            continue;
+        }
+        let locations_start_index = location_indices.len();
+        // Locations are ordered from the leaf to the root.
+        for function in stack.functions.iter().rev() {
+            location_indices.push(functions.insert_full(function.as_str()).0);
+        }
+        let attribute_indices: Vec<usize> = stack
+            .labels
+            .iter()
+            .map(|(key, value)| attributes.insert_full((key.as_str(), value.as_str())).0)
+            .collect();
+        samples.push(json!({
+            "locationsStartIndex": locations_start_index,
+            "locationsLength": stack.functions.len(),
+            // 64-bit integers are encoded as strings in OTLP JSON.
+            "value": [count.to_string()],
+            "attributeIndices": attribute_indices,
+        }));
+    }
+    let function_table: Vec<Value> = functions
+        .iter()
+        .map(|name| json!({ "nameStrindex": string_index(name) }))
+        .collect();
+    let location_table: Vec<Value> = (0..functions.len())
+        .map(|function_index| json!({ "line": [{ "functionIndex": function_index }] }))
+        .collect();
+    let attribute_table: Vec<Value> = attributes
+        .iter()
+        .map(|(key, value)| string_attribute(key, value))
+        .collect();
+
+    let mut resource_attributes = vec![string_attribute("service.name", &push_props.service_name)];
+    resource_attributes.extend(
+        push_props
+            .labels
+            .iter()
+            .map(|(key, value)| string_attribute(key, value)),
+    );
+
+    let time_nanos = (stacks.start_time * 1_000_000.0) as u64;
+    let duration_nanos = (stacks.duration * 1_000_000.0) as u64;
+    let period_nanos = (stacks.interval * 1_000_000.0) as u64;
+    let profile_id = uuid::Uuid::new_v4().simple().to_string();
+
+    json!({
+        "resourceProfiles": [{
+            "resource": { "attributes": resource_attributes },
+            "scopeProfiles": [{
+                "scope": { "name": "samply", "version": env!("CARGO_PKG_VERSION") },
+                "profiles": [{
+                    "profileId": profile_id,
+                    "sampleType": [sample_type],
+                    "sample": samples,
+                    "locationIndices": location_indices,
+                    "locationTable": location_table,
+                    "functionTable": function_table,
+                    "attributeTable": attribute_table,
+                    "stringTable": strings.into_iter().collect::<Vec<String>>(),
+                    "timeNanos": time_nanos.to_string(),
+                    "durationNanos": duration_nanos.to_string(),
+                    "periodType": period_type,
+                    "period": period_nanos.to_string(),
+                }],
+            }],
+        }],
+    })
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::SymbolicatedStack;
+    use crate::shared::prop_types::PushTarget;
+
+    #[test]
+    fn otlp_profile() {
+        let stacks = SymbolicatedStacks {
+            start_time: 1000.0,
+            duration: 5.0,
+            interval: 1.0,
+            stacks: vec![
+                SymbolicatedStack {
+                    labels: vec![],
+                    functions: vec!["main".into(), "work".into()],
+                    weight: 3.0,
+                },
+                SymbolicatedStack {
+                    labels: vec![("endpoint".into(), "/api/foo".into())],
+                    functions: vec!["main".into()],
+                    weight: 2.0,
+                },
+            ],
+        };
+        let push_props = PushProps {
+            target: PushTarget::Otlp("http://localhost:4318".into()),
+            service_name: "my-service".into(),
+            labels: vec![],
+            headers: vec![],
+        };
+        let data = profiles_data(&stacks, &push_props);
+        let resource_profile = &data["resourceProfiles"][0];
+        assert_eq!(
+            resource_profile["resource"]["attributes"][0]["value"]["stringValue"],
+            "my-service"
+        );
+        let profile = &resource_profile["scopeProfiles"][0]["profiles"][0];
+        assert_eq!(profile["timeNanos"], "1000000000");
+        assert_eq!(profile["period"], "1000000");
+        assert_eq!(profile["locationIndices"], json!([0, 1, 1]));
+        assert_eq!(profile["sample"][0]["value"], json!(["3"]));
+        assert_eq!(profile["sample"][1]["locationsStartIndex"], 2);
+        assert_eq!(profile["sample"][1]["attributeIndices"], json!([0]));
+        assert_eq!(profile["attributeTable"][0]["key"], "endpoint");
+
+        let strings = profile["stringTable"].as_array().unwrap();
+        assert_eq!(strings[0], "");
+        let function_name = |index: usize| {
+            let name_index = profile["functionTable"][index]["nameStrindex"]
+                .as_u64()
+                .unwrap();
+            strings[name_index as usize].as_str().unwrap()
+        };
+        assert_eq!(function_name(0), "work");
+        assert_eq!(function_name(1), "main");
+    }
+}
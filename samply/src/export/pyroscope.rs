@@ -0,0 +1,132 @@
+//! The Pyroscope `/ingest` API, with profiles in the "folded" (collapsed stacks) format.
+
+use std::collections::BTreeMap;
+
+use crate::report::{SymbolicatedStack, SymbolicatedStacks};
+use crate::shared::prop_types::PushProps;
+
+/// Groups the stacks by their labels.
+pub fn stacks_by_labels(
+    stacks: &SymbolicatedStacks,
+) -> BTreeMap<Vec<(String, String)>, Vec<&SymbolicatedStack>> {
+    let mut groups: BTreeMap<Vec<(String, String)>, Vec<&SymbolicatedStack>> = BTreeMap::new();
+    for stack in &stacks.stacks {
+        groups.entry(stack.labels.clone()).or_default().push(stack);
+    }
+    groups
+}
+
+/// Returns one line per stack, with the function names from root to leaf
+/// separated by semicolons, followed by the sample count.
+pub fn folded_stacks(stacks: &[&SymbolicatedStack]) -> String {
+    let mut folded = String::new();
+    for stack in stacks {
+        let count = stack.weight.round() as u64;
+        if count == 0 || stack.functions.is_empty() {
+            continue;
+        }
+        let names: Vec<String> = stack
+            .functions
+            .iter()
+            .map(|function| function.replace(';', ":"))
+            .collect();
+        folded.push_str(&names.join(";"));
+        folded.push_str(&format!(" {count}\n"));
+    }
+    folded
+}
+
+/// Returns the query parameters for an ingest request with the stacks that have
+/// the labels `sample_labels`.
+pub fn ingest_query(
+    stacks: &SymbolicatedStacks,
+    push_props: &PushProps,
+    sample_labels: &[(String, String)],
+) -> Vec<(&'static str, String)> {
+    let from = (stacks.start_time / 1000.0) as u64;
+    let until = ((stacks.start_time + stacks.duration) / 1000.0).ceil() as u64;
+    let sample_rate = if stacks.interval > 0.0 {
+        (1000.0 / stacks.interval).round() as u64
+    } else {
+        100
+    };
+    vec![
+        ("name", application_name(push_props, sample_labels)),
+        ("from", from.to_string()),
+        ("until", until.max(from + 1).to_string()),
+        ("format", "folded".to_string()),
+        ("sampleRate", sample_rate.to_string()),
+        ("spyName", "samply".to_string()),
+        ("units", "samples".to_string()),
+        ("aggregationType", "sum".to_string()),
+    ]
+}
+
+/// Returns the application name with the labels, e.g. `my-service{env=staging}`.
+fn application_name(push_props: &PushProps, sample_labels: &[(String, String)]) -> String {
+    let labels: Vec<String> = push_props
+        .labels
+        .iter()
+        .chain(sample_labels)
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+    if labels.is_empty() {
+        return push_props.service_name.clone();
+    }
+    format!("{}{{{}}}", push_props.service_name, labels.join(","))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shared::prop_types::PushTarget;
+
+    fn stack(labels: &[(&str, &str)], functions: &[&str], weight: f64) -> SymbolicatedStack {
+        SymbolicatedStack {
+            labels: labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            functions: functions.iter().map(|f| f.to_string()).collect(),
+            weight,
+        }
+    }
+
+    #[test]
+    fn folded_stacks_and_query() {
+        let stacks = SymbolicatedStacks {
+            start_time: 1_700_000_000_000.0,
+            duration: 2500.0,
+            interval: 1.0,
+            stacks: vec![
+                stack(&[], &["main", "parse;inner"], 3.0),
+                stack(&[], &["main"], 1.0),
+                stack(&[], &["idle"], 0.0),
+                stack(&[("endpoint", "/api/foo")], &["main", "handle"], 2.0),
+            ],
+        };
+        let groups = stacks_by_labels(&stacks);
+        assert_eq!(groups.len(), 2);
+        let unlabeled: Vec<(String, String)> = Vec::new();
+        assert_eq!(
+            folded_stacks(&groups[&unlabeled]),
+            "main;parse:inner 3\nmain 1\n"
+        );
+
+        let push_props = PushProps {
+            target: PushTarget::Pyroscope("http://localhost:4040".into()),
+            service_name: "my-service".into(),
+            labels: vec![("env".into(), "staging".into())],
+            headers: vec![],
+        };
+        let query = ingest_query(&stacks, &push_props, &[]);
+        assert!(query.contains(&("name", "my-service{env=staging}".into())));
+        assert!(query.contains(&("from", "1700000000".into())));
+        assert!(query.contains(&("until", "1700000003".into())));
+        assert!(query.contains(&("sampleRate", "1000".into())));
+
+        let labels = vec![("endpoint".to_string(), "/api/foo".to_string())];
+        let query = ingest_query(&stacks, &push_props, &labels);
+        assert!(query.contains(&("name", "my-service{env=staging,endpoint=/api/foo}".into())));
+    }
+}
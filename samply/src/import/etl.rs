@@ -0,0 +1,581 @@
+//! Import of ETW traces (.etl files) on platforms other than Windows.
+//!
+//! On Windows, .etl files are processed with the Windows APIs, see
+//! `windows::import`. Elsewhere, this module reads the kernel events from the
+//! file directly and creates a profile from the CPU samples and their stacks.
+//! It understands the events which `xperf` and `wpr` record for CPU
+//! profiling: process, thread and image events, `SampledProfile` and
+//! `StackWalk`, and the `KernelTraceControl` events with the PDB information
+//! of the images.
+//!
+//! Only the classic kernel events are used; events from other providers, which
+//! need their schema to be decoded, are ignored.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use debugid::DebugId;
+use etl_file::{kernel_group, EtlEvent, EtlFile, EventSource, Guid, LogfileHeader};
+use fxprof_processed_profile::{
+    Category, CategoryColor, CpuDelta, FrameAddress, FrameFlags, LibraryInfo, ProcessHandle,
+    Profile, ReferenceTimestamp, SamplingInterval, ThreadHandle, Timestamp,
+};
+use wholesym::{CodeId, PeCodeId};
+
+use crate::shared::prop_types::ProfileCreationProps;
+
+/// The provider of the `ImageID` and `DbgID_RSDS` events, which xperf adds
+/// when it merges a trace.
+const KERNEL_TRACE_CONTROL: Guid = Guid::from_u128(0xb3e675d7_2554_4f18_830b_2762732560de);
+
+const IMAGE_ID_OPCODE: u8 = 0;
+const DBG_ID_RSDS_OPCODE: u8 = 36;
+
+/// The number of 100ns units between 1601-01-01 and 1970-01-01.
+const FILETIME_UNIX_EPOCH: i64 = 116_444_736_000_000_000;
+
+/// The PE information from the `KernelTraceControl` events of an image,
+/// which precede the image's load event.
+#[derive(Debug, Default)]
+struct PendingImageInfo {
+    timestamp: Option<u32>,
+    debug_id: Option<DebugId>,
+    pdb_path: Option<String>,
+}
+
+struct Sample {
+    thread: ThreadHandle,
+    timestamp: Timestamp,
+    instruction_pointer: u64,
+    /// The addresses from the stack walk events for this sample, from the
+    /// innermost to the outermost frame. The kernel stack comes first.
+    stack: Vec<u64>,
+}
+
+struct Converter {
+    header: LogfileHeader,
+    profile: Profile,
+    processes: HashMap<u32, ProcessHandle>,
+    threads: HashMap<u32, ThreadHandle>,
+    /// The process of each thread, from the thread events.
+    thread_pids: HashMap<u32, u32>,
+    pending_image_info: HashMap<u64, PendingImageInfo>,
+    samples: Vec<Sample>,
+    /// The index in `samples` of the sample of a (tid, raw timestamp), for
+    /// attaching stack walks.
+    sample_indexes: HashMap<(u32, i64), usize>,
+    interval: Option<SamplingInterval>,
+}
+
+impl Converter {
+    fn new(header: LogfileHeader, profile: Profile) -> Self {
+        Converter {
+            header,
+            profile,
+            processes: HashMap::new(),
+            threads: HashMap::new(),
+            thread_pids: HashMap::new(),
+            pending_image_info: HashMap::new(),
+            samples: Vec::new(),
+            sample_indexes: HashMap::new(),
+            interval: None,
+        }
+    }
+
+    fn timestamp(&self, raw_timestamp: i64) -> Timestamp {
+        Timestamp::from_nanos_since_reference(self.header.nanos_since_start(raw_timestamp))
+    }
+
+    fn is_kernel_address(&self, address: u64) -> bool {
+        match self.header.pointer_size {
+            4 => address >= 0x8000_0000,
+            _ => address >= 0xffff_8000_0000_0000,
+        }
+    }
+
+    fn process_handle(&mut self, pid: u32, timestamp: Timestamp) -> ProcessHandle {
+        if let Some(process) = self.processes.get(&pid) {
+            return *process;
+        }
+        let process = self
+            .profile
+            .add_process(&format!("<{pid}>"), pid, timestamp);
+        self.processes.insert(pid, process);
+        process
+    }
+
+    fn thread_handle(&mut self, pid: u32, tid: u32, timestamp: Timestamp) -> ThreadHandle {
+        if let Some(thread) = self.threads.get(&tid) {
+            return *thread;
+        }
+        let process = self.process_handle(pid, timestamp);
+        let thread = self.profile.add_thread(process, tid, timestamp, false);
+        self.threads.insert(tid, thread);
+        thread
+    }
+
+    fn handle_event(&mut self, event: &EtlEvent) -> Option<()> {
+        match event.source {
+            EventSource::KernelGroup(kernel_group::PROCESS) => match event.opcode {
+                1 | 3 => self.handle_process_start(event),
+                2 | 4 => self.handle_process_end(event),
+                10 => self.handle_image_load(event),
+                _ => None,
+            },
+            EventSource::KernelGroup(kernel_group::THREAD) => match event.opcode {
+                1 | 3 => self.handle_thread_start(event),
+                2 | 4 => self.handle_thread_end(event),
+                72 => self.handle_thread_name(event),
+                _ => None,
+            },
+            EventSource::KernelGroup(kernel_group::IMAGE) => match event.opcode {
+                3 | 10 => self.handle_image_load(event),
+                _ => None,
+            },
+            EventSource::KernelGroup(kernel_group::PERFINFO) => match event.opcode {
+                46 => self.handle_sampled_profile(event),
+                72 | 73 => self.handle_sample_interval(event),
+                _ => None,
+            },
+            EventSource::KernelGroup(kernel_group::STACKWALK) => match event.opcode {
+                32 => self.handle_stack_walk(event),
+                _ => None,
+            },
+            EventSource::Provider(KERNEL_TRACE_CONTROL) => match event.opcode {
+                IMAGE_ID_OPCODE => self.handle_image_id(event),
+                DBG_ID_RSDS_OPCODE => self.handle_dbg_id_rsds(event),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn handle_process_start(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let _unique_process_key = reader.pointer()?;
+        let pid = reader.u32()?;
+        let _parent_id = reader.u32()?;
+        let _session_id = reader.u32()?;
+        let _exit_status = reader.u32()?;
+        let _directory_table_base = reader.pointer()?;
+        if event.version >= 4 {
+            let _flags = reader.u32()?;
+        }
+        reader.skip_sid()?;
+        let image_file_name = reader.ansi_string()?;
+        if pid == 0 {
+            return None;
+        }
+        let timestamp = self.timestamp(event.timestamp);
+        match self.processes.get(&pid) {
+            Some(process) => self.profile.set_process_name(*process, &image_file_name),
+            None => {
+                let process = self.profile.add_process(&image_file_name, pid, timestamp);
+                self.processes.insert(pid, process);
+            }
+        }
+        Some(())
+    }
+
+    fn handle_process_end(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let _unique_process_key = reader.pointer()?;
+        let pid = reader.u32()?;
+        let process = *self.processes.get(&pid)?;
+        let timestamp = self.timestamp(event.timestamp);
+        self.profile.set_process_end_time(process, timestamp);
+        Some(())
+    }
+
+    fn handle_thread_start(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let pid = reader.u32()?;
+        let tid = reader.u32()?;
+        self.thread_pids.insert(tid, pid);
+        Some(())
+    }
+
+    fn handle_thread_end(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let _pid = reader.u32()?;
+        let tid = reader.u32()?;
+        // Thread ids are reused, so a later thread with the same tid gets a
+        // new thread handle.
+        let thread = self.threads.remove(&tid)?;
+        let timestamp = self.timestamp(event.timestamp);
+        self.profile.set_thread_end_time(thread, timestamp);
+        Some(())
+    }
+
+    fn handle_thread_name(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let pid = reader.u32()?;
+        let tid = reader.u32()?;
+        let name = reader.utf16_string()?;
+        if pid == 0 || name.is_empty() {
+            return None;
+        }
+        let timestamp = self.timestamp(event.timestamp);
+        let thread = self.thread_handle(pid, tid, timestamp);
+        self.profile.set_thread_name(thread, &name);
+        Some(())
+    }
+
+    fn handle_image_id(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let image_base = reader.pointer()?;
+        let _image_size = reader.pointer()?;
+        let _pid = reader.u32()?;
+        let timestamp = reader.u32()?;
+        self.pending_image_info
+            .entry(image_base)
+            .or_default()
+            .timestamp = Some(timestamp);
+        Some(())
+    }
+
+    fn handle_dbg_id_rsds(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let image_base = reader.pointer()?;
+        let _pid = reader.u32()?;
+        let guid = reader.guid()?;
+        let age = reader.u32()?;
+        let pdb_path = reader.ansi_string()?;
+        let info = self.pending_image_info.entry(image_base).or_default();
+        info.debug_id = DebugId::from_guid_age(&guid.to_bytes(), age).ok();
+        info.pdb_path = Some(pdb_path);
+        Some(())
+    }
+
+    fn handle_image_load(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let image_base = reader.pointer()?;
+        let image_size = reader.pointer()?;
+        let pid = reader.u32()?;
+        let _checksum = reader.u32()?;
+        let load_timestamp = reader.u32()?;
+        let _reserved0 = reader.u32()?;
+        let _default_base = reader.pointer()?;
+        reader.skip(4 * 4)?;
+        let file_name = reader.utf16_string()?;
+
+        let info = self
+            .pending_image_info
+            .remove(&image_base)
+            .unwrap_or_default();
+        let pe_timestamp = info
+            .timestamp
+            .or_else(|| (load_timestamp != 0).then_some(load_timestamp));
+        let code_id = pe_timestamp.map(|timestamp| {
+            CodeId::PeCodeId(PeCodeId {
+                timestamp,
+                image_size: image_size as u32,
+            })
+        });
+        let pdb_path = info.pdb_path.unwrap_or_else(|| file_name.clone());
+        let lib = self.profile.add_lib(LibraryInfo {
+            name: file_name_of(&file_name).to_owned(),
+            debug_name: file_name_of(&pdb_path).to_owned(),
+            path: file_name,
+            debug_path: pdb_path,
+            debug_id: info.debug_id.unwrap_or_default(),
+            code_id: code_id.map(|code_id| code_id.to_string()),
+            arch: None,
+        });
+        let image_end = image_base.saturating_add(image_size);
+        if pid == 0 {
+            self.profile
+                .add_kernel_lib_mapping(lib, image_base, image_end, 0);
+        } else {
+            let timestamp = self.timestamp(event.timestamp);
+            let process = self.process_handle(pid, timestamp);
+            self.profile
+                .add_lib_mapping(process, lib, image_base, image_end, 0);
+        }
+        Some(())
+    }
+
+    fn handle_sample_interval(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let source = reader.u32()?;
+        let new_interval = reader.u32()?;
+        // Source 0 is the timer; the interval is in 100ns units.
+        if source == 0 && new_interval != 0 {
+            self.interval = Some(SamplingInterval::from_nanos(u64::from(new_interval) * 100));
+        }
+        Some(())
+    }
+
+    fn handle_sampled_profile(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let instruction_pointer = reader.pointer()?;
+        let tid = reader.u32()?;
+        if tid == 0 {
+            // The idle thread.
+            return None;
+        }
+        let pid = *self.thread_pids.get(&tid)?;
+        if pid == 0 {
+            return None;
+        }
+        let timestamp = self.timestamp(event.timestamp);
+        let thread = self.thread_handle(pid, tid, timestamp);
+        self.sample_indexes
+            .insert((tid, event.timestamp), self.samples.len());
+        self.samples.push(Sample {
+            thread,
+            timestamp,
+            instruction_pointer,
+            stack: Vec::new(),
+        });
+        Some(())
+    }
+
+    fn handle_stack_walk(&mut self, event: &EtlEvent) -> Option<()> {
+        let mut reader = event.payload_reader();
+        let event_timestamp = reader.i64()?;
+        let _pid = reader.u32()?;
+        let tid = reader.u32()?;
+        let index = *self.sample_indexes.get(&(tid, event_timestamp))?;
+        self.samples[index]
+            .stack
+            .extend(reader.remaining_pointers());
+        Some(())
+    }
+
+    fn add_samples(&mut self) {
+        let user_category = self
+            .profile
+            .handle_for_category(Category("User", CategoryColor::Yellow));
+        let kernel_category = self
+            .profile
+            .handle_for_category(Category("Kernel", CategoryColor::Orange));
+        for sample in std::mem::take(&mut self.samples) {
+            let addresses = match sample.stack.is_empty() {
+                true => vec![sample.instruction_pointer],
+                false => sample.stack,
+            };
+            let frames: Vec<(FrameAddress, bool)> = addresses
+                .iter()
+                .enumerate()
+                .map(|(i, address)| {
+                    let frame_address = match i {
+                        0 => FrameAddress::InstructionPointer(*address),
+                        _ => FrameAddress::ReturnAddress(*address),
+                    };
+                    (frame_address, self.is_kernel_address(*address))
+                })
+                .collect();
+            let thread = sample.thread;
+            let mut frames = frames.into_iter().rev();
+            let stack = self.profile.handle_for_stack_frames(thread, |profile| {
+                let (frame_address, is_kernel) = frames.next()?;
+                let category = match is_kernel {
+                    true => kernel_category,
+                    false => user_category,
+                };
+                Some(profile.handle_for_frame_with_address(
+                    thread,
+                    frame_address,
+                    category,
+                    FrameFlags::empty(),
+                ))
+            });
+            self.profile
+                .add_sample(thread, sample.timestamp, stack, CpuDelta::ZERO, 1);
+        }
+    }
+}
+
+/// Returns the file name of a Windows path like
+/// `\Device\HarddiskVolume3\Windows\System32\ntdll.dll`.
+fn file_name_of(path: &str) -> &str {
+    path.rsplit(['\\', '/']).next().unwrap_or(path)
+}
+
+fn system_time_from_filetime(filetime: i64) -> SystemTime {
+    let since_unix_epoch = filetime.saturating_sub(FILETIME_UNIX_EPOCH).max(0) as u64;
+    SystemTime::UNIX_EPOCH + Duration::from_nanos(since_unix_epoch.saturating_mul(100))
+}
+
+pub fn convert(
+    data: &[u8],
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, std::io::Error> {
+    let file = EtlFile::parse(data)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let header = file.header().clone();
+    let reference_timestamp =
+        ReferenceTimestamp::from_system_time(system_time_from_filetime(header.start_time));
+    let profile = Profile::new(
+        profile_creation_props.profile_name(),
+        reference_timestamp,
+        SamplingInterval::from_millis(1),
+    );
+    let mut converter = Converter::new(header, profile);
+    converter.profile.set_os_name("Windows");
+
+    for event in file.events() {
+        converter.handle_event(&event);
+    }
+    converter.add_samples();
+
+    if let Some(interval) = converter.interval {
+        converter.profile.set_interval(interval);
+    }
+    Ok(converter.profile)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds a 64-bit `SYSTEM_TRACE_HEADER` event.
+    fn system_event(
+        group: u8,
+        opcode: u8,
+        version: u16,
+        timestamp: i64,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let size = 32 + payload.len();
+        let mut event = Vec::new();
+        event.extend_from_slice(&version.to_le_bytes());
+        event.push(2); // TRACE_HEADER_TYPE_SYSTEM64
+        event.push(0xc0);
+        event.extend_from_slice(&(size as u16).to_le_bytes());
+        event.extend_from_slice(&u16::from_be_bytes([group, opcode]).to_le_bytes());
+        event.extend_from_slice(&0u32.to_le_bytes()); // thread id
+        event.extend_from_slice(&0u32.to_le_bytes()); // process id
+        event.extend_from_slice(&timestamp.to_le_bytes());
+        event.extend_from_slice(&[0; 8]); // kernel and user time
+        event.extend_from_slice(payload);
+        event.resize((size + 7) & !7, 0);
+        event
+    }
+
+    /// Builds a 64-bit `PERFINFO_TRACE_HEADER` event.
+    fn perfinfo_event(group: u8, opcode: u8, timestamp: i64, payload: &[u8]) -> Vec<u8> {
+        let size = 16 + payload.len();
+        let mut event = Vec::new();
+        event.extend_from_slice(&2u16.to_le_bytes());
+        event.push(17); // TRACE_HEADER_TYPE_PERFINFO64
+        event.push(0xc0);
+        event.extend_from_slice(&(size as u16).to_le_bytes());
+        event.extend_from_slice(&u16::from_be_bytes([group, opcode]).to_le_bytes());
+        event.extend_from_slice(&timestamp.to_le_bytes());
+        event.extend_from_slice(payload);
+        event.resize((size + 7) & !7, 0);
+        event
+    }
+
+    fn logfile_header_payload() -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&0x10000u32.to_le_bytes()); // BufferSize
+        payload.extend_from_slice(&[0; 8]); // Version, ProviderVersion
+        payload.extend_from_slice(&1u32.to_le_bytes()); // NumberOfProcessors
+        payload.extend_from_slice(&0i64.to_le_bytes()); // EndTime
+        payload.extend_from_slice(&[0; 20]); // TimerResolution .. StartBuffers
+        payload.extend_from_slice(&8u32.to_le_bytes()); // PointerSize
+        payload.extend_from_slice(&0u32.to_le_bytes()); // EventsLost
+        payload.extend_from_slice(&3000u32.to_le_bytes()); // CpuSpeedInMHz
+        payload.extend_from_slice(&[0; 16 + 172]); // names and time zone
+        payload.resize((payload.len() + 7) & !7, 0);
+        payload.extend_from_slice(&0i64.to_le_bytes()); // BootTime
+        payload.extend_from_slice(&10_000_000i64.to_le_bytes()); // PerfFreq
+        payload.extend_from_slice(&(FILETIME_UNIX_EPOCH + 10_000_000).to_le_bytes()); // StartTime
+        payload.extend_from_slice(&1u32.to_le_bytes()); // ReservedFlags: QPC
+        payload
+    }
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .chain([0])
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn convert_sampled_profile() {
+        let mut process = Vec::new();
+        process.extend_from_slice(&0u64.to_le_bytes()); // UniqueProcessKey
+        process.extend_from_slice(&1234u32.to_le_bytes());
+        process.extend_from_slice(&[0; 12]); // ParentId, SessionId, ExitStatus
+        process.extend_from_slice(&0u64.to_le_bytes()); // DirectoryTableBase
+        process.extend_from_slice(&0u32.to_le_bytes()); // Flags
+        process.extend_from_slice(&0u32.to_le_bytes()); // no SID
+        process.extend_from_slice(b"app.exe\0");
+
+        let mut thread = Vec::new();
+        thread.extend_from_slice(&1234u32.to_le_bytes());
+        thread.extend_from_slice(&1235u32.to_le_bytes());
+
+        let mut image = Vec::new();
+        image.extend_from_slice(&0x7ff6_0000_0000u64.to_le_bytes());
+        image.extend_from_slice(&0x10000u64.to_le_bytes());
+        image.extend_from_slice(&1234u32.to_le_bytes());
+        image.extend_from_slice(&[0; 8]); // checksum, TimeDateStamp
+        image.extend_from_slice(&0u32.to_le_bytes());
+        image.extend_from_slice(&0u64.to_le_bytes()); // DefaultBase
+        image.extend_from_slice(&[0; 16]);
+        image.extend_from_slice(&utf16(r"\Device\HarddiskVolume3\app\app.exe"));
+
+        let mut sample = Vec::new();
+        sample.extend_from_slice(&0x7ff6_0000_1010u64.to_le_bytes());
+        sample.extend_from_slice(&1235u32.to_le_bytes());
+        sample.extend_from_slice(&1u16.to_le_bytes());
+
+        let mut stack = Vec::new();
+        stack.extend_from_slice(&2000i64.to_le_bytes());
+        stack.extend_from_slice(&1234u32.to_le_bytes());
+        stack.extend_from_slice(&1235u32.to_le_bytes());
+        stack.extend_from_slice(&0x7ff6_0000_1010u64.to_le_bytes());
+        stack.extend_from_slice(&0x7ff6_0000_2020u64.to_le_bytes());
+
+        let mut events = Vec::new();
+        events.extend(system_event(0, 0, 2, 1000, &logfile_header_payload()));
+        events.extend(system_event(3, 3, 4, 1000, &process));
+        events.extend(system_event(5, 3, 3, 1000, &thread));
+        events.extend(system_event(0x14, 3, 3, 1000, &image));
+        events.extend(perfinfo_event(0x0F, 46, 2000, &sample));
+        events.extend(perfinfo_event(0x18, 32, 2100, &stack));
+
+        let buffer_size = 0x48 + events.len();
+        let mut data = vec![0; 0x48];
+        data[0..4].copy_from_slice(&(buffer_size as u32).to_le_bytes());
+        data[0x30..0x34].copy_from_slice(&(buffer_size as u32).to_le_bytes());
+        data.extend(events);
+
+        let file = EtlFile::parse(&data).unwrap();
+        assert_eq!(file.header().perf_freq, 10_000_000);
+        assert_eq!(file.header().nanos_since_start(2000), 100_000);
+        let events = file.events();
+        assert_eq!(events.len(), 6);
+        assert_eq!(
+            events[4].source,
+            EventSource::KernelGroup(kernel_group::PERFINFO)
+        );
+
+        let profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let mut converter = Converter::new(file.header().clone(), profile);
+        for event in &events {
+            converter.handle_event(event);
+        }
+        assert!(converter.processes.contains_key(&1234));
+        assert_eq!(converter.samples.len(), 1);
+        assert_eq!(
+            converter.samples[0].stack,
+            [0x7ff6_0000_1010, 0x7ff6_0000_2020]
+        );
+        assert!(!converter.is_kernel_address(0x7ff6_0000_1010));
+        assert!(converter.is_kernel_address(0xffff_f800_0000_0000));
+        assert_eq!(
+            file_name_of(r"\Device\HarddiskVolume3\app\app.exe"),
+            "app.exe"
+        );
+    }
+}
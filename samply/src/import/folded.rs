@@ -0,0 +1,133 @@
+//! Import of pre-aggregated stacks in the "folded" format, which is written by
+//! the `stackcollapse-*` scripts from FlameGraph, by `inferno-collapse-*`, and
+//! by many tools which convert pprof profiles:
+//!
+//! ```text
+//! main;compute;sqrt 42
+//! main;idle 7
+//! ```
+//!
+//! Each line is a stack from the root to the leaf function, separated by
+//! semicolons, followed by the number of samples with this stack. Each line
+//! becomes one sample with the count as its weight. The stacks have no
+//! timestamps, so the samples are laid out one after the other, with each one
+//! taking up as many sampling intervals as its count.
+
+use std::io::BufRead;
+use std::time::SystemTime;
+
+use fxprof_processed_profile::{
+    Category, CategoryColor, CpuDelta, FrameFlags, Profile, ReferenceTimestamp, SamplingInterval,
+    Timestamp,
+};
+
+use crate::shared::prop_types::ProfileCreationProps;
+use crate::shared::sample_weight::weights_for_count;
+
+/// Splits a line into its stack frames and its sample count.
+fn parse_line(line: &str) -> Option<(Vec<&str>, u64)> {
+    let (stack, count) = line.trim_end().rsplit_once(char::is_whitespace)?;
+    let count = count.parse().ok()?;
+    let frames = stack
+        .trim()
+        .split(';')
+        .filter(|frame| !frame.is_empty())
+        .collect();
+    Some((frames, count))
+}
+
+pub fn convert<R: BufRead>(
+    reader: R,
+    pid: u32,
+    file_mod_time: Option<SystemTime>,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, std::io::Error> {
+    let reference_timestamp =
+        ReferenceTimestamp::from_system_time(file_mod_time.unwrap_or_else(SystemTime::now));
+    let mut profile = Profile::new(
+        profile_creation_props.profile_name(),
+        reference_timestamp,
+        SamplingInterval::from_millis(1),
+    );
+    profile.set_symbolicated(true);
+    let start_time = Timestamp::from_millis_since_reference(0.0);
+    let process = profile.add_process(profile_creation_props.profile_name(), pid, start_time);
+    let thread = profile.add_thread(process, pid, start_time, true);
+    let category = profile.handle_for_category(Category("Other", CategoryColor::Gray));
+
+    let mut elapsed_samples = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let Some((frames, count)) = parse_line(&line) else {
+            continue;
+        };
+        let mut frames = frames.into_iter();
+        let stack = profile.handle_for_stack_frames(thread, |profile| {
+            let name = profile.handle_for_string(frames.next()?);
+            Some(profile.handle_for_frame_with_label(thread, name, category, FrameFlags::empty()))
+        });
+        for weight in weights_for_count(count) {
+            let timestamp = Timestamp::from_millis_since_reference(elapsed_samples as f64);
+            profile.add_sample(thread, timestamp, stack, CpuDelta::ZERO, weight);
+            elapsed_samples += weight as u64;
+        }
+    }
+
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shared::redaction::RedactionRules;
+
+    #[test]
+    fn parse_lines() {
+        assert_eq!(
+            parse_line("main;compute;sqrt 42"),
+            Some((vec!["main", "compute", "sqrt"], 42))
+        );
+        assert_eq!(
+            parse_line("my app;main 3\r"),
+            Some((vec!["my app", "main"], 3))
+        );
+        assert_eq!(parse_line("main;compute"), None);
+        assert_eq!(parse_line(""), None);
+    }
+
+    #[test]
+    fn convert_stacks() {
+        let input = "main;compute;sqrt 42\nmain;idle 7\n";
+        let props = ProfileCreationProps {
+            profile_name: Some("test".into()),
+            fallback_profile_name: String::new(),
+            main_thread_only: false,
+            reuse_threads: false,
+            fold_recursive_prefix: false,
+            unlink_aux_files: false,
+            create_per_cpu_threads: false,
+            arg_count_to_include_in_process_name: 0,
+            override_arch: None,
+            unstable_presymbolicate: false,
+            coreclr: Default::default(),
+            unknown_event_markers: false,
+            should_emit_jit_markers: false,
+            should_emit_cswitch_markers: false,
+            marker_stacks_from_samples: false,
+            max_output_size: None,
+            frame_marker: None,
+            thread_state_labels: false,
+            utc_timestamps: false,
+            derived_counters: Vec::new(),
+            sched_latency_threshold_ms: None,
+            encryption_recipients: Vec::new(),
+            redaction: RedactionRules::default(),
+            self_profile: false,
+        };
+        let profile = convert(input.as_bytes(), 0, None, props).unwrap();
+        let json = serde_json::to_value(&profile).unwrap();
+        let samples = &json["threads"][0]["samples"];
+        assert_eq!(samples["weight"], serde_json::json!([42, 7]));
+        assert_eq!(samples["timeDeltas"], serde_json::json!([0.0, 42.0]));
+    }
+}
@@ -0,0 +1,350 @@
+//! Import of heaptrack data files (`heaptrack.<name>.<pid>.gz`).
+//!
+//! A heaptrack data file is a line-based text format, with all numbers in
+//! hexadecimal:
+//!
+//!  - `X <command line>`: the command line of the profiled process.
+//!  - `s <string>`: a string, referred to by its 1-based index.
+//!  - `i <ip> <module> [<function> [<file> <line>]]...`: an instruction pointer,
+//!    with the string indexes of its module, function and source file. Extra
+//!    `<function> <file> <line>` triples are for inlined functions. 1-based.
+//!  - `t <ip> <parent trace>`: a stack, as a node in a tree of stacks. 1-based,
+//!    0 is the empty stack.
+//!  - `a <size> <trace>`: an allocation size and stack. 0-based.
+//!  - `+ <allocation>` and `- <allocation>`: an allocation or deallocation.
+//!  - `c <milliseconds>`: the time since the start.
+//!  - `R <bytes>`: the resident set size.
+//!
+//! Allocations and deallocations become allocation samples, and the heap size
+//! and RSS become memory counters.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::time::SystemTime;
+
+use fxprof_processed_profile::{
+    Category, CategoryColor, CategoryHandle, CounterHandle, FrameFlags, FrameHandle, ProcessHandle,
+    Profile, ReferenceTimestamp, SamplingInterval, SourceLocation, StackHandle, ThreadHandle,
+    Timestamp,
+};
+
+use crate::shared::prop_types::ProfileCreationProps;
+
+struct Converter {
+    profile: Profile,
+    process: ProcessHandle,
+    thread: ThreadHandle,
+    category: CategoryHandle,
+    heap_counter: CounterHandle,
+    rss_counter: CounterHandle,
+    strings: Vec<String>,
+    /// The frames for each instruction pointer, from the outermost to the
+    /// innermost (inlined) function.
+    ip_frames: Vec<Vec<FrameHandle>>,
+    /// The instruction pointer index and parent trace index of each trace.
+    traces: Vec<(usize, usize)>,
+    trace_stacks: HashMap<usize, Option<StackHandle>>,
+    /// The size and trace index of each allocation info.
+    allocation_infos: Vec<(u64, usize)>,
+    /// The synthetic addresses of the live allocations of each allocation info.
+    /// Heaptrack doesn't record addresses, but the profiler uses them to match
+    /// deallocations to allocations.
+    live_allocations: HashMap<usize, Vec<u64>>,
+    next_address: u64,
+    now: Timestamp,
+    heap_bytes: i64,
+    counted_heap_bytes: i64,
+    rss_bytes: u64,
+}
+
+impl Converter {
+    fn string(&self, index: usize) -> &str {
+        index
+            .checked_sub(1)
+            .and_then(|index| self.strings.get(index))
+            .map_or("", String::as_str)
+    }
+
+    fn add_ip(&mut self, fields: &[u64]) {
+        let [ip, module, rest @ ..] = fields else {
+            self.ip_frames.push(Vec::new());
+            return;
+        };
+        let module = self.string(*module as usize).to_owned();
+        let mut frames = Vec::new();
+        if rest.is_empty() {
+            let file_name = module.rsplit('/').next().unwrap_or(&module);
+            let name = self
+                .profile
+                .handle_for_string(&format!("{file_name}!{ip:#x}"));
+            frames.push(self.profile.handle_for_frame_with_label(
+                self.thread,
+                name,
+                self.category,
+                FrameFlags::empty(),
+            ));
+        }
+        for function in rest.chunks(3) {
+            let name = self.string(function[0] as usize).to_owned();
+            let name = self.profile.handle_for_string(&name);
+            let frame = match function {
+                [_, file, line] => {
+                    let file = self.string(*file as usize).to_owned();
+                    let source_location = SourceLocation {
+                        file_path: Some(self.profile.handle_for_string(&file)),
+                        line: Some(*line as u32),
+                        col: None,
+                    };
+                    self.profile
+                        .handle_for_frame_with_label_and_source_location(
+                            self.thread,
+                            name,
+                            source_location,
+                            self.category,
+                            FrameFlags::empty(),
+                        )
+                }
+                _ => self.profile.handle_for_frame_with_label(
+                    self.thread,
+                    name,
+                    self.category,
+                    FrameFlags::empty(),
+                ),
+            };
+            frames.push(frame);
+        }
+        self.ip_frames.push(frames);
+    }
+
+    fn stack_for_trace(&mut self, trace_index: usize) -> Option<StackHandle> {
+        if trace_index == 0 {
+            return None;
+        }
+        if let Some(stack) = self.trace_stacks.get(&trace_index) {
+            return *stack;
+        }
+        // Collect the traces from this one up to the root, then create the
+        // stack nodes from the root down.
+        let mut chain = Vec::new();
+        let mut index = trace_index;
+        let mut prefix = None;
+        while index != 0 {
+            if let Some(stack) = self.trace_stacks.get(&index) {
+                prefix = *stack;
+                break;
+            }
+            chain.push(index);
+            index = self.traces.get(index - 1).map_or(0, |(_, parent)| *parent);
+        }
+        for index in chain.into_iter().rev() {
+            let (ip_index, _) = self.traces[index - 1];
+            let frames = ip_index
+                .checked_sub(1)
+                .and_then(|ip_index| self.ip_frames.get(ip_index))
+                .cloned()
+                .unwrap_or_default();
+            for frame in frames {
+                prefix = Some(self.profile.handle_for_stack(self.thread, frame, prefix));
+            }
+            self.trace_stacks.insert(index, prefix);
+        }
+        prefix
+    }
+
+    fn add_allocation(&mut self, info_index: usize, is_allocation: bool) {
+        let Some(&(size, trace_index)) = self.allocation_infos.get(info_index) else {
+            return;
+        };
+        let stack = self.stack_for_trace(trace_index);
+        let live = self.live_allocations.entry(info_index).or_default();
+        let (address, size) = if is_allocation {
+            self.next_address += 1;
+            live.push(self.next_address);
+            (self.next_address, size as i64)
+        } else {
+            (live.pop().unwrap_or(0), -(size as i64))
+        };
+        self.heap_bytes += size;
+        self.profile
+            .add_allocation_sample(self.thread, self.now, stack, address, size);
+    }
+
+    /// Adds the heap size change since the last time step to the heap counter.
+    fn flush_heap_counter(&mut self) {
+        let delta = self.heap_bytes - self.counted_heap_bytes;
+        if delta != 0 {
+            self.profile
+                .add_counter_sample(self.heap_counter, self.now, delta as f64, 1);
+            self.counted_heap_bytes = self.heap_bytes;
+        }
+    }
+
+    fn process_line(&mut self, line: &str) {
+        let Some((kind, rest)) = line.split_once(' ') else {
+            return;
+        };
+        match kind {
+            "s" => {
+                self.strings.push(parse_string(rest).to_owned());
+                return;
+            }
+            "X" => {
+                if let Some(exe) = rest.split_whitespace().next() {
+                    let name = exe.rsplit('/').next().unwrap_or(exe);
+                    self.profile.set_process_name(self.process, name);
+                }
+                return;
+            }
+            _ => {}
+        }
+        let fields: Vec<u64> = rest
+            .split_whitespace()
+            .map_while(|field| u64::from_str_radix(field, 16).ok())
+            .collect();
+        match (kind, fields.as_slice()) {
+            ("i", fields) => self.add_ip(fields),
+            ("t", [ip, parent, ..]) => self.traces.push((*ip as usize, *parent as usize)),
+            ("a", [size, trace, ..]) => self.allocation_infos.push((*size, *trace as usize)),
+            ("+", [info, ..]) => self.add_allocation(*info as usize, true),
+            ("-", [info, ..]) => self.add_allocation(*info as usize, false),
+            ("c", [millis, ..]) => {
+                self.flush_heap_counter();
+                self.now = Timestamp::from_millis_since_reference(*millis as f64);
+            }
+            ("R", [rss, ..]) => {
+                let delta = *rss as f64 - self.rss_bytes as f64;
+                self.profile
+                    .add_counter_sample(self.rss_counter, self.now, delta, 1);
+                self.rss_bytes = *rss;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Newer heaptrack versions prefix strings with their length in hex.
+fn parse_string(rest: &str) -> &str {
+    match rest.split_once(' ') {
+        Some((len, string)) if usize::from_str_radix(len, 16).ok() == Some(string.len()) => string,
+        _ => rest,
+    }
+}
+
+pub fn convert<R: BufRead>(
+    reader: R,
+    pid: u32,
+    file_mod_time: Option<SystemTime>,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, std::io::Error> {
+    let reference_timestamp =
+        ReferenceTimestamp::from_system_time(file_mod_time.unwrap_or_else(SystemTime::now));
+    let mut profile = Profile::new(
+        profile_creation_props.profile_name(),
+        reference_timestamp,
+        SamplingInterval::from_millis(1),
+    );
+    let start_time = Timestamp::from_millis_since_reference(0.0);
+    let process = profile.add_process("heaptrack", pid, start_time);
+    let thread = profile.add_thread(process, pid, start_time, true);
+    let category = profile.handle_for_category(Category("Heap", CategoryColor::Orange));
+    let heap_counter = profile.add_counter(process, "Heap", "Memory", "Heap size, from heaptrack");
+    let rss_counter = profile.add_counter(process, "RSS", "Memory", "Resident set size");
+    let mut converter = Converter {
+        profile,
+        process,
+        thread,
+        category,
+        heap_counter,
+        rss_counter,
+        strings: Vec::new(),
+        ip_frames: Vec::new(),
+        traces: Vec::new(),
+        trace_stacks: HashMap::new(),
+        allocation_infos: Vec::new(),
+        live_allocations: HashMap::new(),
+        next_address: 0,
+        now: start_time,
+        heap_bytes: 0,
+        counted_heap_bytes: 0,
+        rss_bytes: 0,
+    };
+
+    for line in reader.lines() {
+        converter.process_line(&line?);
+    }
+    converter.flush_heap_counter();
+
+    Ok(converter.profile)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shared::redaction::RedactionRules;
+
+    #[test]
+    fn parse_strings() {
+        assert_eq!(parse_string("5 hello"), "hello");
+        assert_eq!(parse_string("main"), "main");
+        assert_eq!(parse_string("foo bar"), "foo bar");
+    }
+
+    #[test]
+    fn allocations() {
+        let input = "\
+v 10800 3
+X ./myapp
+s /usr/bin/myapp
+s main
+s main.c
+s alloc_buffer
+i 1000 1 2 3 a
+i 2000 1 4 3 14
+t 1 0
+t 2 1
+a 40 2
+c 0
++ 0
++ 0
+c a
+- 0
+R 1000
+";
+        let props = ProfileCreationProps {
+            profile_name: Some("test".into()),
+            fallback_profile_name: String::new(),
+            main_thread_only: false,
+            reuse_threads: false,
+            fold_recursive_prefix: false,
+            unlink_aux_files: false,
+            create_per_cpu_threads: false,
+            arg_count_to_include_in_process_name: 0,
+            override_arch: None,
+            unstable_presymbolicate: false,
+            coreclr: Default::default(),
+            unknown_event_markers: false,
+            should_emit_jit_markers: false,
+            should_emit_cswitch_markers: false,
+            marker_stacks_from_samples: false,
+            max_output_size: None,
+            frame_marker: None,
+            thread_state_labels: false,
+            utc_timestamps: false,
+            derived_counters: Vec::new(),
+            sched_latency_threshold_ms: None,
+            encryption_recipients: Vec::new(),
+            redaction: RedactionRules::default(),
+            self_profile: false,
+        };
+        let profile = convert(input.as_bytes(), 42, None, props).unwrap();
+        let json = serde_json::to_value(&profile).unwrap();
+        let thread = &json["threads"][0];
+        let allocations = &thread["nativeAllocations"];
+        assert_eq!(allocations["weight"], serde_json::json!([64, 64, -64]));
+        assert_eq!(allocations["memoryAddress"], serde_json::json!([1, 2, 2]));
+        let strings = thread["stringArray"].as_array().unwrap();
+        assert!(strings.contains(&"alloc_buffer".into()));
+        assert_eq!(json["counters"].as_array().unwrap().len(), 2);
+        assert_eq!(thread["processName"], "myapp");
+    }
+}
@@ -0,0 +1,316 @@
+//! Import of Valgrind massif output files (`massif.out.<pid>`).
+//!
+//! Massif records snapshots of the heap size, and, for some of them, a tree of
+//! the allocation stacks with the number of bytes which are live at that time.
+//! The heap sizes become a memory counter. The stack trees become allocation
+//! samples: at each detailed snapshot, every stack gets a sample with the
+//! number of bytes which were allocated or freed since the previous detailed
+//! snapshot, so that the allocation call tree at the end of the profile shows
+//! the memory which is retained by each stack.
+//!
+//! Massif can measure time in milliseconds (`--time-unit=ms`), in instructions
+//! (the default), or in allocated bytes. Instructions and bytes are put on the
+//! timeline as if they were nanoseconds.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::time::SystemTime;
+
+use fxprof_processed_profile::{
+    Category, CategoryColor, CategoryHandle, FrameFlags, Profile, ReferenceTimestamp,
+    SamplingInterval, SourceLocation, StackHandle, ThreadHandle, TimelineUnit, Timestamp,
+};
+
+use crate::shared::prop_types::ProfileCreationProps;
+
+/// A snapshot from the massif file.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Snapshot {
+    time: u64,
+    heap_bytes: u64,
+    /// For detailed snapshots, the live bytes per allocation stack. The stacks
+    /// are ordered from the caller of the allocation function to the outermost
+    /// caller.
+    stacks: Option<Vec<(Vec<String>, u64)>>,
+}
+
+/// The parsed massif file.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct MassifFile {
+    cmd: Option<String>,
+    time_unit: String,
+    snapshots: Vec<Snapshot>,
+}
+
+pub fn convert<R: BufRead>(
+    reader: R,
+    pid: u32,
+    file_mod_time: Option<SystemTime>,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, std::io::Error> {
+    let massif = parse_massif(reader)?;
+
+    let reference_timestamp =
+        ReferenceTimestamp::from_system_time(file_mod_time.unwrap_or_else(SystemTime::now));
+    let mut profile = Profile::new(
+        profile_creation_props.profile_name(),
+        reference_timestamp,
+        SamplingInterval::from_millis(1),
+    );
+    let to_timestamp = |time: u64| match massif.time_unit.as_str() {
+        "ms" => Timestamp::from_millis_since_reference(time as f64),
+        _ => Timestamp::from_nanos_since_reference(time),
+    };
+    if massif.time_unit == "B" {
+        profile.set_timeline_unit(TimelineUnit::Bytes);
+    }
+
+    let process_name = massif
+        .cmd
+        .as_deref()
+        .and_then(|cmd| cmd.split_whitespace().next())
+        .map(|exe| exe.rsplit('/').next().unwrap_or(exe))
+        .unwrap_or("massif");
+    let start_time = to_timestamp(0);
+    let process = profile.add_process(process_name, pid, start_time);
+    let thread = profile.add_thread(process, pid, start_time, true);
+    let heap_counter = profile.add_counter(process, "Heap", "Memory", "Heap size, from massif");
+    let category = profile.handle_for_category(Category("Heap", CategoryColor::Orange));
+
+    let mut stack_handles: HashMap<Vec<String>, Option<StackHandle>> = HashMap::new();
+    let mut live_bytes: HashMap<Vec<String>, u64> = HashMap::new();
+    let mut previous_heap_bytes = 0;
+    for snapshot in &massif.snapshots {
+        let timestamp = to_timestamp(snapshot.time);
+        profile.add_counter_sample(
+            heap_counter,
+            timestamp,
+            snapshot.heap_bytes as f64 - previous_heap_bytes as f64,
+            1,
+        );
+        previous_heap_bytes = snapshot.heap_bytes;
+
+        let Some(stacks) = &snapshot.stacks else {
+            continue;
+        };
+        let mut current: HashMap<&Vec<String>, u64> = HashMap::new();
+        for (frames, bytes) in stacks {
+            *current.entry(frames).or_default() += bytes;
+        }
+        let mut all_stacks: Vec<Vec<String>> = current.keys().map(|f| (*f).clone()).collect();
+        all_stacks.extend(live_bytes.keys().cloned());
+        all_stacks.sort();
+        all_stacks.dedup();
+        for frames in all_stacks {
+            let new_bytes = current.get(&frames).copied().unwrap_or(0);
+            let old_bytes = live_bytes.get(&frames).copied().unwrap_or(0);
+            if new_bytes == old_bytes {
+                continue;
+            }
+            let stack = *stack_handles
+                .entry(frames.clone())
+                .or_insert_with(|| stack_for_frames(&mut profile, thread, category, &frames));
+            profile.add_allocation_sample(
+                thread,
+                timestamp,
+                stack,
+                0,
+                new_bytes as i64 - old_bytes as i64,
+            );
+            live_bytes.insert(frames, new_bytes);
+        }
+    }
+
+    Ok(profile)
+}
+
+/// Creates the stack for frame names which are ordered from the innermost to
+/// the outermost frame.
+fn stack_for_frames(
+    profile: &mut Profile,
+    thread: ThreadHandle,
+    category: CategoryHandle,
+    frames: &[String],
+) -> Option<StackHandle> {
+    let mut frames = frames.iter().rev();
+    profile.handle_for_stack_frames(thread, |profile| {
+        let (name, file_line) = parse_frame_label(frames.next()?);
+        let name = profile.handle_for_string(name);
+        Some(match file_line {
+            Some((file, line)) => {
+                let source_location = SourceLocation {
+                    file_path: Some(profile.handle_for_string(file)),
+                    line: Some(line),
+                    col: None,
+                };
+                profile.handle_for_frame_with_label_and_source_location(
+                    thread,
+                    name,
+                    source_location,
+                    category,
+                    FrameFlags::empty(),
+                )
+            }
+            None => {
+                profile.handle_for_frame_with_label(thread, name, category, FrameFlags::empty())
+            }
+        })
+    })
+}
+
+fn parse_massif<R: BufRead>(reader: R) -> Result<MassifFile, std::io::Error> {
+    let mut massif = MassifFile {
+        time_unit: "i".to_string(),
+        ..Default::default()
+    };
+    // The labels on the path from the root of the heap tree to the current node.
+    let mut tree_path: Vec<String> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(node) = line.trim_start().strip_prefix('n') {
+            let depth = line.len() - line.trim_start().len();
+            let Some((children, rest)) = node.split_once(": ") else {
+                continue;
+            };
+            let Ok(children) = children.parse::<u32>() else {
+                continue;
+            };
+            let (bytes, label) = rest.split_once(' ').unwrap_or((rest, ""));
+            let bytes: u64 = bytes.parse().unwrap_or(0);
+            tree_path.truncate(depth);
+            tree_path.push(label.to_owned());
+            if children == 0 && depth > 0 {
+                // Skip the root, which stands for the allocation functions.
+                let frames = tree_path[1..].to_vec();
+                if let Some(snapshot) = massif.snapshots.last_mut() {
+                    snapshot
+                        .stacks
+                        .get_or_insert_with(Vec::new)
+                        .push((frames, bytes));
+                }
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once(['=', ':']) else {
+            continue;
+        };
+        let value = value.trim();
+        match key {
+            "cmd" => massif.cmd = Some(value.to_owned()),
+            "time_unit" => massif.time_unit = value.to_owned(),
+            "snapshot" => massif.snapshots.push(Snapshot::default()),
+            _ => {
+                let Some(snapshot) = massif.snapshots.last_mut() else {
+                    continue;
+                };
+                match key {
+                    "time" => snapshot.time = value.parse().unwrap_or(0),
+                    "mem_heap_B" => snapshot.heap_bytes = value.parse().unwrap_or(0),
+                    "heap_tree" if value != "empty" => {
+                        snapshot.stacks.get_or_insert_with(Vec::new);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(massif)
+}
+
+/// Splits a heap tree label like `0x4005A4: foo (a.c:10)` into the function name
+/// and the source location, if known.
+fn parse_frame_label(label: &str) -> (&str, Option<(&str, u32)>) {
+    let label = match label.split_once(": ") {
+        Some((address, rest)) if address.starts_with("0x") => rest,
+        _ => label,
+    };
+    if let Some((name, location)) = label
+        .strip_suffix(')')
+        .and_then(|label| label.rsplit_once(" ("))
+    {
+        if let Some((file, line)) = location.rsplit_once(':') {
+            if let Ok(line) = line.parse() {
+                return (name, Some((file, line)));
+            }
+        }
+        if location.starts_with("in ") {
+            // "foo (in /usr/lib/libfoo.so)"
+            return (name, None);
+        }
+    }
+    (label, None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MASSIF_FILE: &str = "\
+desc: --time-unit=ms
+cmd: ./myapp --flag
+time_unit: ms
+#-----------
+snapshot=0
+#-----------
+time=0
+mem_heap_B=0
+mem_heap_extra_B=0
+mem_stacks_B=0
+heap_tree=empty
+#-----------
+snapshot=1
+#-----------
+time=10
+mem_heap_B=1000
+mem_heap_extra_B=16
+mem_stacks_B=0
+heap_tree=detailed
+n2: 1000 (heap allocation functions) malloc/new/new[], --alloc-fns, etc.
+ n1: 600 0x4005A4: foo (a.c:10)
+  n0: 600 0x4005B4: main (a.c:20)
+ n0: 400 in 3 places, all below massif's threshold (1.00%)
+";
+
+    #[test]
+    fn parse() {
+        let massif = parse_massif(MASSIF_FILE.as_bytes()).unwrap();
+        assert_eq!(massif.cmd.as_deref(), Some("./myapp --flag"));
+        assert_eq!(massif.time_unit, "ms");
+        assert_eq!(massif.snapshots.len(), 2);
+        assert_eq!(massif.snapshots[0].stacks, None);
+        assert_eq!(massif.snapshots[1].time, 10);
+        assert_eq!(massif.snapshots[1].heap_bytes, 1000);
+        assert_eq!(
+            massif.snapshots[1].stacks,
+            Some(vec![
+                (
+                    vec![
+                        "0x4005A4: foo (a.c:10)".to_string(),
+                        "0x4005B4: main (a.c:20)".to_string()
+                    ],
+                    600
+                ),
+                (
+                    vec!["in 3 places, all below massif's threshold (1.00%)".to_string()],
+                    400
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn frame_labels() {
+        assert_eq!(
+            parse_frame_label("0x4005A4: foo (a.c:10)"),
+            ("foo", Some(("a.c", 10)))
+        );
+        assert_eq!(
+            parse_frame_label("0x4005A4: bar (in /usr/lib/libbar.so)"),
+            ("bar", None)
+        );
+        assert_eq!(
+            parse_frame_label("in 3 places, all below massif's threshold (1.00%)"),
+            ("in 3 places, all below massif's threshold (1.00%)", None)
+        );
+    }
+}
@@ -1 +1,140 @@
+pub mod etl;
+pub mod folded;
+pub mod heaptrack;
+pub mod massif;
+pub mod os_log;
 pub mod perf;
+pub mod perf_script;
+mod profile_markers;
+pub mod strace;
+
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+/// The kinds of files which `samply import` understands, other than ETW traces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// A perf.data file from Linux perf or Android simpleperf.
+    PerfData,
+    /// The text output of `perf script`.
+    PerfScript,
+    /// A Valgrind massif output file.
+    Massif,
+    /// A heaptrack data file.
+    Heaptrack,
+    /// A gzip-compressed heaptrack data file.
+    HeaptrackGz,
+    /// Pre-aggregated stacks in the folded format, one `a;b;c <count>` line
+    /// per stack.
+    Folded,
+    /// A zstd-compressed file, which we can't read.
+    Zstd,
+}
+
+impl ImportFormat {
+    /// Looks at the first bytes of the file to find out its format, and rewinds
+    /// the file.
+    pub fn detect(mut file: &File) -> Self {
+        let mut start = [0; 8];
+        let len = read_up_to(file, &mut start);
+        let _ = file.rewind();
+        Self::from_start_bytes(&start[..len])
+    }
+
+    /// Returns the format for text formats without a recognizable start, by
+    /// the file extension.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "folded" | "collapsed" => Some(ImportFormat::Folded),
+            _ => None,
+        }
+    }
+
+    fn from_start_bytes(start: &[u8]) -> Self {
+        if start.starts_with(b"PERFILE2") || start.starts_with(b"2ELIFREP") {
+            ImportFormat::PerfData
+        } else if start.starts_with(&[0x1f, 0x8b]) {
+            ImportFormat::HeaptrackGz
+        } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            ImportFormat::Zstd
+        } else if start.starts_with(b"desc:") {
+            ImportFormat::Massif
+        } else if start.starts_with(b"v ") {
+            ImportFormat::Heaptrack
+        } else {
+            ImportFormat::PerfScript
+        }
+    }
+}
+
+fn read_up_to(mut file: &File, buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+    }
+    len
+}
+
+/// Finds the pid in file names like `massif.out.1234` or
+/// `heaptrack.myapp.1234.gz`.
+pub fn pid_from_file_name(file_name: &str) -> Option<u32> {
+    let file_name = file_name
+        .strip_suffix(".gz")
+        .or_else(|| file_name.strip_suffix(".zst"))
+        .unwrap_or(file_name);
+    file_name.rsplit('.').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(
+            ImportFormat::from_start_bytes(b"PERFILE2"),
+            ImportFormat::PerfData
+        );
+        assert_eq!(
+            ImportFormat::from_start_bytes(b"desc: --"),
+            ImportFormat::Massif
+        );
+        assert_eq!(
+            ImportFormat::from_start_bytes(b"v 10800 "),
+            ImportFormat::Heaptrack
+        );
+        assert_eq!(
+            ImportFormat::from_start_bytes(&[0x1f, 0x8b, 8, 0]),
+            ImportFormat::HeaptrackGz
+        );
+        assert_eq!(
+            ImportFormat::from_start_bytes(b"myapp 12"),
+            ImportFormat::PerfScript
+        );
+        assert_eq!(
+            ImportFormat::from_start_bytes(b""),
+            ImportFormat::PerfScript
+        );
+    }
+
+    #[test]
+    fn detect_format_from_extension() {
+        assert_eq!(
+            ImportFormat::from_extension(Path::new("out.folded")),
+            Some(ImportFormat::Folded)
+        );
+        assert_eq!(ImportFormat::from_extension(Path::new("perf.data")), None);
+    }
+
+    #[test]
+    fn pid_from_file_names() {
+        assert_eq!(pid_from_file_name("massif.out.1234"), Some(1234));
+        assert_eq!(pid_from_file_name("heaptrack.myapp.42.gz"), Some(42));
+        assert_eq!(pid_from_file_name("heaptrack.myapp.42.zst"), Some(42));
+        assert_eq!(pid_from_file_name("perf.data"), None);
+    }
+}
@@ -0,0 +1,485 @@
+//! Adding the entries of the system log to an existing profile, for
+//! `samply add-logs`.
+//!
+//! The entries come from the systemd journal on Linux (`journalctl`), from the
+//! unified log on macOS (`log show`), and from the Application and System
+//! event logs on Windows (`Get-WinEvent` in PowerShell). Only the entries of
+//! the profile's processes during the profile's time range are added, as
+//! instant markers on the thread which logged them, or on the main thread of
+//! the process if the entry has no thread ID.
+
+use std::collections::HashMap;
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+use std::process::Command;
+
+use serde_json::{json, Value};
+
+use super::profile_markers::{
+    add_marker_schema, category_index, push_marker, string_index, thread_pid, thread_tid,
+};
+
+/// An entry from the system log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    /// In milliseconds since the Unix epoch.
+    pub time: f64,
+    pub pid: u32,
+    pub tid: Option<u32>,
+    /// The severity, e.g. "err" or "Error".
+    pub level: String,
+    /// The program, subsystem or event provider which logged the entry.
+    pub source: String,
+    pub message: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OsLogError {
+    #[error("Could not run {0}: {1}")]
+    CouldNotRun(&'static str, std::io::Error),
+
+    #[error("{0} failed: {1}")]
+    CommandFailed(&'static str, String),
+
+    #[cfg(any(target_os = "windows", test))]
+    #[error("Could not parse the output of {0}: {1}")]
+    Parse(&'static str, serde_json::Error),
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    #[error("Reading the system log is not supported on this platform")]
+    Unsupported,
+}
+
+/// Returns the pids of the profile's processes, and the profile's start and
+/// end time in milliseconds since the Unix epoch.
+pub fn profile_pids_and_time_range(profile: &Value) -> (Vec<u32>, f64, f64) {
+    let start_time = profile["meta"]["startTime"].as_f64().unwrap_or(0.0);
+    let mut pids = Vec::new();
+    let mut end = 0.0f64;
+    for thread in profile["threads"].as_array().map_or(&[][..], Vec::as_slice) {
+        if let Some(pid) = thread_pid(thread) {
+            if !pids.contains(&pid) {
+                pids.push(pid);
+            }
+        }
+        end = end.max(thread_end_time(thread));
+    }
+    (pids, start_time, start_time + end)
+}
+
+/// The latest time in the thread, relative to the profile's start time.
+fn thread_end_time(thread: &Value) -> f64 {
+    let mut end = 0.0f64;
+    for key in ["registerTime", "unregisterTime"] {
+        if let Some(time) = thread[key].as_f64() {
+            end = end.max(time);
+        }
+    }
+    let samples = &thread["samples"];
+    if let Some(times) = samples["time"].as_array() {
+        if let Some(time) = times.last().and_then(Value::as_f64) {
+            end = end.max(time);
+        }
+    } else if let Some(deltas) = samples["timeDeltas"].as_array() {
+        end = end.max(deltas.iter().filter_map(Value::as_f64).sum());
+    }
+    for key in ["startTime", "endTime"] {
+        if let Some(times) = thread["markers"][key].as_array() {
+            for time in times.iter().filter_map(Value::as_f64) {
+                end = end.max(time);
+            }
+        }
+    }
+    end
+}
+
+/// Reads the log entries of these processes between `start` and `end`, in
+/// milliseconds since the Unix epoch.
+pub fn read_os_log(pids: &[u32], start: f64, end: f64) -> Result<Vec<LogEntry>, OsLogError> {
+    if pids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut entries = query_os_log(pids, start, end)?;
+    entries.retain(|entry| pids.contains(&entry.pid) && (start..=end).contains(&entry.time));
+    entries.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Ok(entries)
+}
+
+#[cfg(target_os = "linux")]
+fn query_os_log(pids: &[u32], start: f64, end: f64) -> Result<Vec<LogEntry>, OsLogError> {
+    let mut command = Command::new("journalctl");
+    command
+        .arg("--output=json")
+        .arg(format!("--since=@{}", (start / 1000.0).floor()))
+        .arg(format!("--until=@{}", (end / 1000.0).ceil()));
+    // Several matches for the same field are combined with OR.
+    for pid in pids {
+        command.arg(format!("_PID={pid}"));
+    }
+    let output = run_command("journalctl", &mut command)?;
+    Ok(parse_journal_json(&output))
+}
+
+#[cfg(target_os = "macos")]
+fn query_os_log(pids: &[u32], start: f64, end: f64) -> Result<Vec<LogEntry>, OsLogError> {
+    let predicate = pids
+        .iter()
+        .map(|pid| format!("processID == {pid}"))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let mut command = Command::new("log");
+    command
+        .args(["show", "--style", "ndjson", "--info"])
+        .arg("--start")
+        .arg(format_local_time((start / 1000.0).floor() as i64))
+        .arg("--end")
+        .arg(format_local_time((end / 1000.0).ceil() as i64))
+        .arg("--predicate")
+        .arg(predicate);
+    let output = run_command("log show", &mut command)?;
+    Ok(parse_log_show_ndjson(&output))
+}
+
+#[cfg(target_os = "windows")]
+fn query_os_log(pids: &[u32], start: f64, end: f64) -> Result<Vec<LogEntry>, OsLogError> {
+    let pids = pids
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    // ConvertTo-Json turns a single object into an object instead of an array,
+    // unless it's passed with -InputObject.
+    let script = format!(
+        "$pids = @({pids}); \
+         $events = Get-WinEvent -ErrorAction SilentlyContinue -FilterHashtable @{{ \
+           LogName = 'Application', 'System'; \
+           StartTime = [DateTimeOffset]::FromUnixTimeMilliseconds({start}).LocalDateTime; \
+           EndTime = [DateTimeOffset]::FromUnixTimeMilliseconds({end}).LocalDateTime }} | \
+         Where-Object {{ $pids -contains $_.ProcessId }} | \
+         ForEach-Object {{ [pscustomobject]@{{ \
+           Time = ([DateTimeOffset]$_.TimeCreated).ToUnixTimeMilliseconds(); \
+           Pid = $_.ProcessId; Tid = $_.ThreadId; Level = $_.LevelDisplayName; \
+           Source = $_.ProviderName; Message = $_.Message }} }}; \
+         ConvertTo-Json -Compress -InputObject @($events)",
+        start = start.floor() as i64,
+        end = end.ceil() as i64,
+    );
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+    let output = run_command("Get-WinEvent", &mut command)?;
+    parse_get_winevent_json(&output)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn query_os_log(_pids: &[u32], _start: f64, _end: f64) -> Result<Vec<LogEntry>, OsLogError> {
+    Err(OsLogError::Unsupported)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+fn run_command(name: &'static str, command: &mut Command) -> Result<String, OsLogError> {
+    let output = command
+        .output()
+        .map_err(|err| OsLogError::CouldNotRun(name, err))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(OsLogError::CommandFailed(name, stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses the output of `journalctl --output=json`, which has one JSON object
+/// per line. All values are strings, except for messages which aren't valid
+/// UTF-8; those are arrays of bytes.
+#[cfg(any(target_os = "linux", test))]
+fn parse_journal_json(output: &str) -> Vec<LogEntry> {
+    const PRIORITIES: [&str; 8] = [
+        "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+    ];
+    output
+        .lines()
+        .filter_map(|line| {
+            let entry: Value = serde_json::from_str(line).ok()?;
+            let field = |key: &str| entry[key].as_str();
+            let micros: u64 = field("__REALTIME_TIMESTAMP")?.parse().ok()?;
+            let message = match &entry["MESSAGE"] {
+                Value::String(message) => message.clone(),
+                Value::Array(bytes) => {
+                    let bytes: Vec<u8> = bytes
+                        .iter()
+                        .filter_map(|byte| Some(byte.as_u64()? as u8))
+                        .collect();
+                    String::from_utf8_lossy(&bytes).into_owned()
+                }
+                _ => return None,
+            };
+            let level = field("PRIORITY")
+                .and_then(|priority| PRIORITIES.get(priority.parse::<usize>().ok()?))
+                .unwrap_or(&"info");
+            Some(LogEntry {
+                time: micros as f64 / 1000.0,
+                pid: field("_PID")?.parse().ok()?,
+                tid: field("TID").and_then(|tid| tid.parse().ok()),
+                level: level.to_string(),
+                source: field("SYSLOG_IDENTIFIER")
+                    .or(field("_COMM"))
+                    .unwrap_or("journal")
+                    .to_string(),
+                message,
+            })
+        })
+        .collect()
+}
+
+/// Parses the output of `log show --style ndjson`, which has one JSON object
+/// per line, followed by a summary object without an event message.
+#[cfg(any(target_os = "macos", test))]
+fn parse_log_show_ndjson(output: &str) -> Vec<LogEntry> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let entry: Value = serde_json::from_str(line).ok()?;
+            let source = match entry["subsystem"].as_str() {
+                Some(subsystem) if !subsystem.is_empty() => subsystem,
+                _ => entry["processImagePath"]
+                    .as_str()
+                    .and_then(|path| path.rsplit('/').next())
+                    .unwrap_or("log"),
+            };
+            Some(LogEntry {
+                time: parse_log_show_timestamp(entry["timestamp"].as_str()?)?,
+                pid: entry["processID"].as_u64()? as u32,
+                tid: entry["threadID"].as_u64().map(|tid| tid as u32),
+                level: entry["messageType"]
+                    .as_str()
+                    .unwrap_or("Default")
+                    .to_string(),
+                source: source.to_string(),
+                message: entry["eventMessage"].as_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parses a timestamp like `2024-05-01 10:11:12.123456-0700` into
+/// milliseconds since the Unix epoch.
+#[cfg(any(target_os = "macos", test))]
+fn parse_log_show_timestamp(timestamp: &str) -> Option<f64> {
+    let (date, time_and_offset) = timestamp.split_once(' ')?;
+    let mut date_parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (
+        date_parts.next()??,
+        date_parts.next()??,
+        date_parts.next()??,
+    );
+    let offset_start = time_and_offset.rfind(['+', '-'])?;
+    let (time, offset) = time_and_offset.split_at(offset_start);
+    let mut time_parts = time.splitn(3, ':');
+    let hours: i64 = time_parts.next()?.parse().ok()?;
+    let minutes: i64 = time_parts.next()?.parse().ok()?;
+    let seconds: f64 = time_parts.next()?.parse().ok()?;
+    let offset_sign = if offset.starts_with('-') { -1 } else { 1 };
+    let offset_hours: i64 = offset.get(1..3)?.parse().ok()?;
+    let offset_minutes: i64 = offset.get(3..5)?.parse().ok()?;
+    let offset_seconds = offset_sign * (offset_hours * 3600 + offset_minutes * 60);
+
+    let whole_seconds =
+        days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 - offset_seconds;
+    Some((whole_seconds as f64 + seconds) * 1000.0)
+}
+
+/// The number of days between 1970-01-01 and the date, from
+/// <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+#[cfg(any(target_os = "macos", test))]
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Formats the time in the local time zone, which is how `log show` expects
+/// its `--start` and `--end` arguments.
+#[cfg(target_os = "macos")]
+fn format_local_time(seconds_since_epoch: i64) -> String {
+    let time = seconds_since_epoch as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// Parses the JSON array which the PowerShell script in `query_os_log` prints.
+#[cfg(any(target_os = "windows", test))]
+fn parse_get_winevent_json(output: &str) -> Result<Vec<LogEntry>, OsLogError> {
+    #[derive(serde_derive::Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct WinEventJson {
+        time: f64,
+        pid: u32,
+        tid: Option<u32>,
+        level: Option<String>,
+        source: Option<String>,
+        message: Option<String>,
+    }
+
+    let output = output.trim();
+    if output.is_empty() {
+        return Ok(Vec::new());
+    }
+    let events: Vec<WinEventJson> =
+        serde_json::from_str(output).map_err(|err| OsLogError::Parse("Get-WinEvent", err))?;
+    Ok(events
+        .into_iter()
+        .map(|event| LogEntry {
+            time: event.time,
+            pid: event.pid,
+            tid: event.tid,
+            level: event.level.unwrap_or_default(),
+            source: event.source.unwrap_or_default(),
+            message: event.message.unwrap_or_default(),
+        })
+        .collect())
+}
+
+const OS_LOG_MARKER_TYPE: &str = "OsLog";
+const LOG_CATEGORY: &str = "Log";
+
+/// Adds the entries as markers to the thread with the entry's thread ID, or to
+/// the main thread of the entry's process. Returns the number of added markers.
+pub fn merge_into_profile(profile: &mut Value, entries: &[LogEntry]) -> usize {
+    let start_time = profile["meta"]["startTime"].as_f64().unwrap_or(0.0);
+    let category = category_index(profile, LOG_CATEGORY, "grey");
+    add_marker_schema(profile, os_log_marker_schema());
+
+    let Some(threads) = profile["threads"].as_array_mut() else {
+        return 0;
+    };
+    let mut thread_by_pid_and_tid: HashMap<(u32, u32), usize> = HashMap::new();
+    let mut thread_by_pid: HashMap<u32, usize> = HashMap::new();
+    for (index, thread) in threads.iter().enumerate() {
+        let Some(pid) = thread_pid(thread) else {
+            continue;
+        };
+        if let Some(tid) = thread_tid(thread) {
+            thread_by_pid_and_tid.entry((pid, tid)).or_insert(index);
+        }
+        if thread["isMainThread"] == true {
+            thread_by_pid.insert(pid, index);
+        } else {
+            thread_by_pid.entry(pid).or_insert(index);
+        }
+    }
+
+    let mut added = 0;
+    for entry in entries {
+        let thread_index = entry
+            .tid
+            .and_then(|tid| thread_by_pid_and_tid.get(&(entry.pid, tid)))
+            .or_else(|| thread_by_pid.get(&entry.pid));
+        let Some(thread) = thread_index.and_then(|index| threads.get_mut(*index)) else {
+            continue;
+        };
+        let name = string_index(thread, &entry.source);
+        let data = json!({
+            "type": OS_LOG_MARKER_TYPE,
+            "level": entry.level,
+            "message": entry.message,
+        });
+        push_marker(thread, name, category, entry.time - start_time, None, data);
+        added += 1;
+    }
+    added
+}
+
+fn os_log_marker_schema() -> Value {
+    json!({
+        "name": OS_LOG_MARKER_TYPE,
+        "display": ["marker-chart", "marker-table", "timeline-overview"],
+        "chartLabel": "{marker.data.message}",
+        "tooltipLabel": "{marker.name} ({marker.data.level}): {marker.data.message}",
+        "tableLabel": "{marker.data.level}: {marker.data.message}",
+        "description": "An entry from the system log.",
+        "fields": [
+            { "key": "level", "label": "Level", "format": "string", "searchable": true },
+            { "key": "message", "label": "Message", "format": "string", "searchable": true },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_logs() {
+        let journal = r#"{"__REALTIME_TIMESTAMP":"1700000000123456","_PID":"42","TID":"43","PRIORITY":"3","SYSLOG_IDENTIFIER":"myapp","MESSAGE":"disk full"}
+{"__REALTIME_TIMESTAMP":"1700000000200000","_PID":"42","_COMM":"myapp","MESSAGE":[104,105]}
+"#;
+        let entries = parse_journal_json(journal);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].time, 1700000000123.456);
+        assert_eq!(entries[0].tid, Some(43));
+        assert_eq!(entries[0].level, "err");
+        assert_eq!(entries[0].source, "myapp");
+        assert_eq!(entries[1].message, "hi");
+        assert_eq!(entries[1].tid, None);
+
+        let log_show = r#"{"timestamp":"2023-11-14 14:13:20.500000-0800","processID":42,"threadID":7,"messageType":"Error","subsystem":"","processImagePath":"/usr/bin/myapp","eventMessage":"oops"}
+{"count":1,"finished":1}
+"#;
+        let entries = parse_log_show_ndjson(log_show);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].time, 1700000000500.0);
+        assert_eq!(entries[0].source, "myapp");
+
+        let winevent = r#"[{"Time":1700000000000,"Pid":42,"Tid":7,"Level":"Warning","Source":"Application Error","Message":null}]"#;
+        let entries = parse_get_winevent_json(winevent).unwrap();
+        assert_eq!(entries[0].tid, Some(7));
+        assert_eq!(entries[0].message, "");
+    }
+
+    #[test]
+    fn merge() {
+        let mut profile = json!({
+            "meta": { "startTime": 1700000000000.0, "categories": [], "markerSchema": [] },
+            "threads": [
+                { "pid": "42", "tid": 42, "isMainThread": true, "stringArray": [],
+                  "markers": { "length": 0, "category": [], "data": [], "endTime": [],
+                               "name": [], "phase": [], "startTime": [] } },
+                { "pid": "42", "tid": 43, "isMainThread": false, "stringArray": [],
+                  "markers": { "length": 0, "category": [], "data": [], "endTime": [],
+                               "name": [], "phase": [], "startTime": [] },
+                  "samples": { "timeDeltas": [100.0, 150.0] } },
+            ],
+        });
+        let (pids, start, end) = profile_pids_and_time_range(&profile);
+        assert_eq!(
+            (pids, start, end),
+            (vec![42], 1700000000000.0, 1700000000250.0)
+        );
+
+        let entry = |tid| LogEntry {
+            time: 1700000000010.0,
+            pid: 42,
+            tid,
+            level: "info".into(),
+            source: "myapp".into(),
+            message: "hello".into(),
+        };
+        let entries = [entry(Some(43)), entry(None), entry(Some(99))];
+        assert_eq!(merge_into_profile(&mut profile, &entries), 3);
+        assert_eq!(profile["threads"][0]["markers"]["length"], 2);
+        assert_eq!(profile["threads"][1]["markers"]["length"], 1);
+        assert_eq!(profile["threads"][1]["markers"]["phase"], json!([0]));
+        assert_eq!(profile["threads"][1]["markers"]["startTime"], json!([10.0]));
+        assert_eq!(profile["meta"]["markerSchema"][0]["name"], "OsLog");
+    }
+}
@@ -2,16 +2,20 @@ use std::collections::HashMap;
 use std::fmt::Write;
 use std::io::{Read, Seek};
 use std::path::PathBuf;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use framehop::{Module, Unwinder};
-use fxprof_processed_profile::{Profile, ReferenceTimestamp};
-use linux_perf_data::{linux_perf_event_reader, DsoInfo, DsoKey, PerfFileReader, PerfFileRecord};
-use linux_perf_event_reader::EventRecord;
+use fxprof_processed_profile::Profile;
+use linux_perf_data::{
+    linux_perf_event_reader, DsoInfo, DsoKey, Endianness, Feature, PerfFile, PerfFileReader,
+    PerfFileRecord,
+};
+use linux_perf_event_reader::{EventRecord, PerfEventType};
 
 use crate::linux_shared::{
-    ConvertRegs, ConvertRegsAarch64, ConvertRegsX86_64, Converter, EventInterpretation, KnownEvent,
-    MmapRangeOrVec,
+    parse_tracing_data, ConvertRegs, ConvertRegsAarch64, ConvertRegsArm, ConvertRegsRiscv64,
+    ConvertRegsX86_64, Converter, EventInterpretation, FramePointerCache, GuestKernelLib,
+    KnownEvent, MmapRangeOrVec, TracepointFormat, UnwinderArm, UnwinderRiscv64,
 };
 use crate::shared::prop_types::ProfileCreationProps;
 
@@ -29,6 +33,7 @@ pub fn convert<C: Read + Seek>(
     file_mod_time: Option<SystemTime>,
     binary_lookup_dirs: Vec<PathBuf>,
     aux_file_lookup_dirs: Vec<PathBuf>,
+    guest_kernel_libs: Vec<GuestKernelLib>,
     profile_creation_props: ProfileCreationProps,
 ) -> Result<Profile, Error> {
     let perf_file = PerfFileReader::parse_file(cursor)?;
@@ -44,6 +49,32 @@ pub fn convert<C: Read + Seek>(
                 binary_lookup_dirs,
                 aux_file_lookup_dirs,
                 cache,
+                guest_kernel_libs,
+                profile_creation_props,
+            )
+        }
+        Some("riscv64") => {
+            let cache = FramePointerCache::new();
+            convert_impl::<UnwinderRiscv64<MmapRangeOrVec>, ConvertRegsRiscv64, _>(
+                perf_file,
+                file_mod_time,
+                binary_lookup_dirs,
+                aux_file_lookup_dirs,
+                cache,
+                guest_kernel_libs,
+                profile_creation_props,
+            )
+        }
+        // uname reports e.g. "armv7l" or "armv6l" on 32-bit ARM.
+        Some(arch) if arch.starts_with("arm") => {
+            let cache = FramePointerCache::new();
+            convert_impl::<UnwinderArm<MmapRangeOrVec>, ConvertRegsArm, _>(
+                perf_file,
+                file_mod_time,
+                binary_lookup_dirs,
+                aux_file_lookup_dirs,
+                cache,
+                guest_kernel_libs,
                 profile_creation_props,
             )
         }
@@ -61,6 +92,7 @@ pub fn convert<C: Read + Seek>(
                 binary_lookup_dirs,
                 aux_file_lookup_dirs,
                 cache,
+                guest_kernel_libs,
                 profile_creation_props,
             )
         }
@@ -74,6 +106,7 @@ fn convert_impl<U, C, R>(
     binary_lookup_dirs: Vec<PathBuf>,
     aux_file_lookup_dirs: Vec<PathBuf>,
     cache: U::Cache,
+    guest_kernel_libs: Vec<GuestKernelLib>,
     profile_creation_props: ProfileCreationProps,
 ) -> Profile
 where
@@ -106,15 +139,19 @@ where
     }
     let interpretation = EventInterpretation::divine_from_attrs(attributes);
     let simpleperf_symbol_tables = perf_file.simpleperf_symbol_tables().ok().flatten();
-    let reference_timestamp = if let Some(seconds_since_unix_epoch) =
-        get_simpleperf_timestamp(simpleperf_meta_info.as_ref())
-    {
-        ReferenceTimestamp::from_millis_since_unix_epoch(seconds_since_unix_epoch * 1000.0)
-    } else if let Some(mod_time) = file_mod_time {
-        ReferenceTimestamp::from_system_time(mod_time)
-    } else {
-        ReferenceTimestamp::from_system_time(SystemTime::now())
-    };
+    let reference_time =
+        if let Some(time) = wall_clock_time_from_clock_data(&perf_file, first_sample_time) {
+            time
+        } else if let Some(seconds_since_unix_epoch) =
+            get_simpleperf_timestamp(simpleperf_meta_info.as_ref())
+        {
+            Duration::from_secs_f64(seconds_since_unix_epoch)
+        } else {
+            file_mod_time
+                .unwrap_or_else(SystemTime::now)
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+        };
 
     let (profile_name, mut profile_name_postfix_for_first_process) = if let Some(profile_name) =
         profile_creation_props.profile_name.clone()
@@ -166,7 +203,7 @@ where
 
     let mut converter = Converter::<U>::new(
         &profile_creation_props,
-        reference_timestamp,
+        reference_time,
         &profile_name,
         build_ids,
         linux_version,
@@ -186,6 +223,8 @@ where
     {
         converter.set_os_name(&format!("Android {android_version}"));
     }
+    converter.set_guest_kernel_libs(guest_kernel_libs);
+    converter.set_tracepoint_formats(tracepoint_formats(&perf_file));
 
     let mut last_timestamp = 0;
 
@@ -208,7 +247,7 @@ where
 
         match parsed_record {
             EventRecord::Sample(e) => {
-                if attr_index == interpretation.main_event_attr_index {
+                if Some(attr_index) == interpretation.main_event_attr_index {
                     converter.handle_main_event_sample::<C>(&e);
                 } else if Some(attr_index) == interpretation.sched_switch_attr_index {
                     converter.handle_sched_switch_sample::<C>(&e);
@@ -216,9 +255,29 @@ where
 
                 match interpretation.known_event_indices.get(&attr_index) {
                     Some(KnownEvent::RssStat) => converter.handle_rss_stat_sample::<C>(&e),
+                    Some(KnownEvent::MmapEnter) => converter.handle_mmap_enter_sample::<C>(&e),
+                    Some(KnownEvent::MmapExit) => converter.handle_mmap_exit_sample(&e),
+                    Some(KnownEvent::MunmapEnter) => converter.handle_munmap_enter_sample::<C>(&e),
+                    Some(KnownEvent::CloneExit) => converter.handle_clone_exit_sample::<C>(&e),
+                    Some(KnownEvent::CxxThrow) => converter.handle_exception_probe_sample::<C>(
+                        &e,
+                        "Exception",
+                        "C++ exception",
+                    ),
+                    Some(KnownEvent::RustPanic) => {
+                        converter.handle_exception_probe_sample::<C>(&e, "Panic", "Rust panic")
+                    }
+                    Some(KnownEvent::IrqHandlerEntry) => converter.handle_irq_handler_entry(&e),
+                    Some(KnownEvent::IrqHandlerExit) => converter.handle_irq_handler_exit(&e),
+                    Some(KnownEvent::SoftirqEntry) => converter.handle_softirq_entry(&e),
+                    Some(KnownEvent::SoftirqExit) => converter.handle_softirq_exit(&e),
+                    Some(KnownEvent::SchedWakeup) => {
+                        converter.handle_sched_wakeup_sample(&e);
+                        converter.handle_other_event_sample::<C>(&e, attr_index);
+                    }
                     _ => {
                         // the main event and sched_switch are already covered by regular samples so don't add other event markers
-                        if !(attr_index == interpretation.main_event_attr_index
+                        if !(Some(attr_index) == interpretation.main_event_attr_index
                             || Some(attr_index) == interpretation.sched_switch_attr_index)
                         {
                             converter.handle_other_event_sample::<C>(&e, attr_index)
@@ -266,6 +325,47 @@ where
     converter.finish()
 }
 
+/// The formats of the file's tracepoint events, by attribute index, from the
+/// TRACING_DATA section, so that the markers for tracepoint records show the
+/// tracepoint's fields.
+fn tracepoint_formats(perf_file: &PerfFile) -> HashMap<usize, TracepointFormat> {
+    let Some(mut formats) = perf_file
+        .feature_section_data(Feature::TRACING_DATA)
+        .and_then(parse_tracing_data)
+    else {
+        return HashMap::new();
+    };
+    perf_file
+        .event_attributes()
+        .iter()
+        .enumerate()
+        .filter_map(|(attr_index, attr_desc)| match attr_desc.attr.type_ {
+            PerfEventType::Tracepoint(id) => Some((attr_index, formats.remove(&id)?)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the wall-clock time of the raw timestamp `timestamp`, from the file's
+/// clock data. `perf record` writes this when it's run with a clock like
+/// `-k CLOCK_MONOTONIC`: a CLOCK_REALTIME timestamp and a timestamp of the
+/// sample clock, taken at the same time.
+fn wall_clock_time_from_clock_data(perf_file: &PerfFile, timestamp: u64) -> Option<Duration> {
+    // u32 version, u32 clockid, u64 wall clock time in ns, u64 clockid time in ns
+    let data = perf_file.feature_section_data(Feature::CLOCK_DATA)?;
+    let read_u64 = |offset: usize| -> Option<u64> {
+        let bytes = data.get(offset..offset + 8)?.try_into().ok()?;
+        Some(match perf_file.endian() {
+            Endianness::LittleEndian => u64::from_le_bytes(bytes),
+            Endianness::BigEndian => u64::from_be_bytes(bytes),
+        })
+    };
+    let wall_clock_ns = read_u64(8)?;
+    let clockid_time_ns = read_u64(16)?;
+    let ns = (wall_clock_ns + timestamp).checked_sub(clockid_time_ns)?;
+    Some(Duration::from_nanos(ns))
+}
+
 fn get_simpleperf_timestamp(meta_info: Option<&HashMap<&str, &str>>) -> Option<f64> {
     let meta_info = meta_info?;
     let timestamp_str = meta_info.get("timestamp")?;
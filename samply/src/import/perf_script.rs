@@ -0,0 +1,414 @@
+//! Import of the text output of `perf script`, for when only that output is
+//! left and not the original perf.data file.
+//!
+//! `perf script` prints each sample as a header line, followed by one line per
+//! stack frame, from the innermost to the outermost frame, and an empty line:
+//!
+//! ```text
+//! myapp 1234/1235 [002] 81234.567890:     250000 cpu-clock:u:
+//!         55d0c1a2b3c4 compute+0x14 (/usr/bin/myapp)
+//!         55d0c1a2b000 main+0x20 (/usr/bin/myapp)
+//!         7f3a1b02a1c9 __libc_start_call_main+0x79 (/usr/lib/libc.so.6)
+//! ```
+//!
+//! The frames have already been symbolicated by perf, so they are added as
+//! label frames.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::time::SystemTime;
+
+use fxprof_processed_profile::{
+    Category, CategoryColor, CpuDelta, FrameFlags, PlatformSpecificReferenceTimestamp,
+    ProcessHandle, Profile, ReferenceTimestamp, SamplingInterval, ThreadHandle, Timestamp,
+};
+
+use crate::shared::prop_types::ProfileCreationProps;
+use crate::shared::sample_weight::weight_for_period;
+
+/// The header line of a sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SampleHeader<'a> {
+    comm: String,
+    pid: Option<u32>,
+    tid: u32,
+    timestamp_ns: u64,
+    period: Option<u64>,
+    event: Option<&'a str>,
+    /// The rest of the line after the event name. Without call chains,
+    /// `perf script` prints the sampled instruction here.
+    rest: &'a str,
+}
+
+/// A line with a single stack frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StackLine<'a> {
+    address: u64,
+    symbol: Option<&'a str>,
+    dso: Option<&'a str>,
+}
+
+impl StackLine<'_> {
+    fn is_kernel(&self) -> bool {
+        self.dso.is_some_and(|dso| dso.starts_with("[kernel"))
+    }
+
+    /// The function name, or `<dso>!<address>` if perf couldn't symbolicate it.
+    fn frame_name(&self) -> String {
+        match (self.symbol, self.dso) {
+            (Some(symbol), _) => symbol.to_owned(),
+            (None, Some(dso)) => {
+                let file_name = dso.rsplit('/').next().unwrap_or(dso);
+                format!("{file_name}!{:#x}", self.address)
+            }
+            (None, None) => format!("{:#x}", self.address),
+        }
+    }
+}
+
+struct Converter {
+    profile: Profile,
+    user_category: Category<'static>,
+    kernel_category: Category<'static>,
+    processes: HashMap<u32, ProcessHandle>,
+    threads: HashMap<u32, ThreadHandle>,
+    first_timestamp_ns: Option<u64>,
+    /// The sampling interval, from the period of the first clock event sample.
+    interval_ns: Option<u64>,
+}
+
+impl Converter {
+    fn timestamp(&mut self, timestamp_ns: u64) -> Timestamp {
+        let first_timestamp_ns = *self.first_timestamp_ns.get_or_insert_with(|| {
+            self.profile.set_platform_specific_reference_timestamp(
+                PlatformSpecificReferenceTimestamp::ClockMonotonicNanosecondsSinceBoot(
+                    timestamp_ns,
+                ),
+            );
+            timestamp_ns
+        });
+        Timestamp::from_nanos_since_reference(timestamp_ns.saturating_sub(first_timestamp_ns))
+    }
+
+    fn thread_handle(&mut self, header: &SampleHeader, timestamp: Timestamp) -> ThreadHandle {
+        let tid = header.tid;
+        let pid = header.pid.unwrap_or(tid);
+        if let Some(thread) = self.threads.get(&tid) {
+            return *thread;
+        }
+        let process = match self.processes.get(&pid) {
+            Some(process) => *process,
+            None => {
+                let process = self.profile.add_process(&header.comm, pid, timestamp);
+                self.processes.insert(pid, process);
+                process
+            }
+        };
+        let is_main = tid == pid;
+        if is_main {
+            self.profile.set_process_name(process, &header.comm);
+        }
+        let thread = self.profile.add_thread(process, tid, timestamp, is_main);
+        self.profile.set_thread_name(thread, &header.comm);
+        self.threads.insert(tid, thread);
+        thread
+    }
+
+    /// Adds the sample from the lines of a sample: the header line, followed by
+    /// the stack frame lines.
+    fn process_sample_lines(&mut self, lines: &[String]) {
+        let Some((header_line, stack_lines)) = lines.split_first() else {
+            return;
+        };
+        let Some(header) = parse_header(header_line) else {
+            return;
+        };
+        if self.interval_ns.is_none() && is_clock_event(header.event) {
+            // For the software clock events, the period is in nanoseconds.
+            self.interval_ns = header.period;
+        }
+        let frames: Vec<StackLine> = std::iter::once(header.rest)
+            .chain(stack_lines.iter().map(String::as_str))
+            .filter_map(parse_stack_line)
+            .collect();
+        self.add_sample(&header, &frames);
+    }
+
+    fn add_sample(&mut self, header: &SampleHeader, frames: &[StackLine]) {
+        let timestamp = self.timestamp(header.timestamp_ns);
+        let thread = self.thread_handle(header, timestamp);
+        let user_category = self.profile.handle_for_category(self.user_category);
+        let kernel_category = self.profile.handle_for_category(self.kernel_category);
+        // The frames are listed from the innermost to the outermost frame.
+        let mut frames = frames.iter().rev();
+        let stack = self.profile.handle_for_stack_frames(thread, |profile| {
+            let frame = frames.next()?;
+            let category = match frame.is_kernel() {
+                true => kernel_category,
+                false => user_category,
+            };
+            let name = profile.handle_for_string(&frame.frame_name());
+            Some(profile.handle_for_frame_with_label(thread, name, category, FrameFlags::empty()))
+        });
+        // Clock event samples which cover more than one interval, e.g. after
+        // throttling, count for more than one sample.
+        let weight = match (header.period, self.interval_ns) {
+            (Some(period), Some(interval_ns)) if is_clock_event(header.event) => {
+                weight_for_period(period, interval_ns)
+            }
+            _ => 1,
+        };
+        self.profile
+            .add_sample(thread, timestamp, stack, CpuDelta::ZERO, weight);
+    }
+}
+
+pub fn convert<R: BufRead>(
+    reader: R,
+    file_mod_time: Option<SystemTime>,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<Profile, std::io::Error> {
+    let reference_timestamp =
+        ReferenceTimestamp::from_system_time(file_mod_time.unwrap_or_else(SystemTime::now));
+    let mut converter = Converter {
+        profile: Profile::new(
+            profile_creation_props.profile_name(),
+            reference_timestamp,
+            SamplingInterval::from_millis(1),
+        ),
+        user_category: Category("User", CategoryColor::Yellow),
+        kernel_category: Category("Kernel", CategoryColor::Orange),
+        processes: HashMap::new(),
+        threads: HashMap::new(),
+        first_timestamp_ns: None,
+        interval_ns: None,
+    };
+    converter.profile.set_os_name("Linux");
+    converter.profile.set_symbolicated(true);
+
+    let mut sample_lines: Vec<String> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            converter.process_sample_lines(&sample_lines);
+            sample_lines.clear();
+            continue;
+        }
+        if !line.starts_with(char::is_whitespace) {
+            // A new header line, without an empty line after the previous sample.
+            converter.process_sample_lines(&sample_lines);
+            sample_lines.clear();
+        }
+        sample_lines.push(line);
+    }
+    converter.process_sample_lines(&sample_lines);
+
+    if let Some(interval_ns) = converter.interval_ns.filter(|interval| *interval > 0) {
+        converter
+            .profile
+            .set_interval(SamplingInterval::from_nanos(interval_ns));
+    }
+    Ok(converter.profile)
+}
+
+fn is_clock_event(event: Option<&str>) -> bool {
+    event.is_some_and(|event| event.starts_with("cpu-clock") || event.starts_with("task-clock"))
+}
+
+/// Parses a sample header line like
+/// `myapp 1234/1235 [002] 81234.567890:     250000 cpu-clock:u:`.
+/// The command name may contain spaces, and the pid, CPU and period are optional.
+fn parse_header(line: &str) -> Option<SampleHeader<'_>> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let timestamp_index = tokens.iter().position(|token| {
+        token
+            .strip_suffix(':')
+            .and_then(parse_timestamp_ns)
+            .is_some()
+    })?;
+    let timestamp_ns = parse_timestamp_ns(tokens[timestamp_index].strip_suffix(':')?)?;
+
+    let mut before = &tokens[..timestamp_index];
+    if let Some((last, rest)) = before.split_last() {
+        if last.starts_with('[') && last.ends_with(']') {
+            before = rest;
+        }
+    }
+    let (pid_tid, comm) = before.split_last()?;
+    let (pid, tid) = match pid_tid.split_once('/') {
+        Some((pid, tid)) => (Some(pid.parse().ok()?), tid.parse().ok()?),
+        None => (None, pid_tid.parse().ok()?),
+    };
+
+    let mut after = &tokens[timestamp_index + 1..];
+    let period = match after.first().and_then(|token| token.parse().ok()) {
+        Some(period) => {
+            after = &after[1..];
+            Some(period)
+        }
+        None => None,
+    };
+    let event = after.first().map(|event| event.trim_end_matches(':'));
+    let rest = match after.get(1) {
+        Some(first_rest_token) => {
+            let offset = first_rest_token.as_ptr() as usize - line.as_ptr() as usize;
+            &line[offset..]
+        }
+        None => "",
+    };
+
+    Some(SampleHeader {
+        comm: comm.join(" "),
+        pid,
+        tid,
+        timestamp_ns,
+        period,
+        event,
+        rest,
+    })
+}
+
+/// Parses a timestamp like `81234.567890`, in seconds, into nanoseconds.
+fn parse_timestamp_ns(s: &str) -> Option<u64> {
+    let (seconds, fraction) = s.split_once('.')?;
+    if fraction.is_empty() || fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let seconds: u64 = seconds.parse().ok()?;
+    let nanos: u64 = format!("{fraction:0<9}").parse().ok()?;
+    Some(seconds * 1_000_000_000 + nanos)
+}
+
+/// Parses a stack frame line like `55d0c1a2b3c4 compute+0x14 (/usr/bin/myapp)`.
+fn parse_stack_line(line: &str) -> Option<StackLine<'_>> {
+    let line = line.trim();
+    let (address, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let address = u64::from_str_radix(address, 16).ok()?;
+    let rest = rest.trim();
+    let (symbol, dso) = match rest.strip_suffix(')').and_then(|r| r.rsplit_once('(')) {
+        Some((symbol, dso)) => (symbol.trim(), Some(dso)),
+        None => (rest, None),
+    };
+    let symbol = match symbol {
+        "" | "[unknown]" => None,
+        symbol => Some(strip_offset(symbol)),
+    };
+    let dso = dso.filter(|dso| *dso != "[unknown]");
+    Some(StackLine {
+        address,
+        symbol,
+        dso,
+    })
+}
+
+/// Removes the `+0x14` offset from a symbol name like `compute+0x14`.
+fn strip_offset(symbol: &str) -> &str {
+    match symbol.rsplit_once("+0x") {
+        Some((name, offset)) if offset.bytes().all(|b| b.is_ascii_hexdigit()) => name,
+        _ => symbol,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shared::redaction::RedactionRules;
+
+    #[test]
+    fn headers() {
+        let header =
+            parse_header("my app 1234/1235 [002] 81234.567890:     250000 cpu-clock:u: ").unwrap();
+        assert_eq!(header.comm, "my app");
+        assert_eq!(header.pid, Some(1234));
+        assert_eq!(header.tid, 1235);
+        assert_eq!(header.timestamp_ns, 81_234_567_890_000);
+        assert_eq!(header.period, Some(250000));
+        assert_eq!(header.event, Some("cpu-clock:u"));
+        assert_eq!(header.rest, "");
+
+        let header = parse_header(
+            "swapper     0 12.5: cycles:  ffffffff81a2b3c4 native_safe_halt+0x4 ([kernel.kallsyms])",
+        )
+        .unwrap();
+        assert_eq!(header.comm, "swapper");
+        assert_eq!(header.pid, None);
+        assert_eq!(header.tid, 0);
+        assert_eq!(header.timestamp_ns, 12_500_000_000);
+        assert_eq!(header.period, None);
+        assert_eq!(
+            header.rest,
+            "ffffffff81a2b3c4 native_safe_halt+0x4 ([kernel.kallsyms])"
+        );
+
+        assert_eq!(parse_header("no timestamp here"), None);
+    }
+
+    #[test]
+    fn stack_lines() {
+        let frame =
+            parse_stack_line("\t55d0c1a2b3c4 std::vec::Vec<T>::push+0x14 (/usr/bin/my app)")
+                .unwrap();
+        assert_eq!(frame.address, 0x55d0c1a2b3c4);
+        assert_eq!(frame.symbol, Some("std::vec::Vec<T>::push"));
+        assert_eq!(frame.dso, Some("/usr/bin/my app"));
+        assert!(!frame.is_kernel());
+
+        let frame = parse_stack_line("\t7f3a1b02a1c9 [unknown] (/usr/lib/libc.so.6)").unwrap();
+        assert_eq!(frame.frame_name(), "libc.so.6!0x7f3a1b02a1c9");
+
+        let frame =
+            parse_stack_line("ffffffff81a2b3c4 native_safe_halt ([kernel.kallsyms])").unwrap();
+        assert!(frame.is_kernel());
+    }
+
+    #[test]
+    fn convert_samples() {
+        let input = "\
+myapp 10/10 [000] 1.000000: 1000000 cpu-clock:u:
+\t2000 compute+0x4 (/usr/bin/myapp)
+\t1000 main+0x10 (/usr/bin/myapp)
+
+myapp 10/11 [001] 1.001000: 1000000 cpu-clock:u:
+\t1000 main+0x10 (/usr/bin/myapp)
+
+myapp 10/11 [001] 1.004000: 3000000 cpu-clock:u:
+\t1000 main+0x10 (/usr/bin/myapp)
+";
+        let props = ProfileCreationProps {
+            profile_name: Some("test".into()),
+            fallback_profile_name: String::new(),
+            main_thread_only: false,
+            reuse_threads: false,
+            fold_recursive_prefix: false,
+            unlink_aux_files: false,
+            create_per_cpu_threads: false,
+            arg_count_to_include_in_process_name: 0,
+            override_arch: None,
+            unstable_presymbolicate: false,
+            coreclr: Default::default(),
+            unknown_event_markers: false,
+            should_emit_jit_markers: false,
+            should_emit_cswitch_markers: false,
+            marker_stacks_from_samples: false,
+            max_output_size: None,
+            frame_marker: None,
+            thread_state_labels: false,
+            utc_timestamps: false,
+            derived_counters: Vec::new(),
+            sched_latency_threshold_ms: None,
+            encryption_recipients: Vec::new(),
+            redaction: RedactionRules::default(),
+            self_profile: false,
+        };
+        let profile = convert(input.as_bytes(), None, props).unwrap();
+        let json = serde_json::to_value(&profile).unwrap();
+        let threads = json["threads"].as_array().unwrap();
+        assert_eq!(threads.len(), 2);
+        assert_eq!(json["meta"]["interval"], 1.0);
+        assert_eq!(threads[1]["samples"]["weight"], serde_json::json!([1, 3]));
+        assert!(threads.iter().any(|thread| thread["stringArray"]
+            .as_array()
+            .unwrap()
+            .contains(&"compute".into())));
+    }
+}
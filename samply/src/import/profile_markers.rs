@@ -0,0 +1,100 @@
+//! Helpers for adding markers to the JSON of an existing processed profile,
+//! for the importers which merge events into a profile of the same run.
+
+use serde_json::{json, Value};
+
+/// Returns the index of the string in the thread's string table, and adds it
+/// if needed.
+pub fn string_index(thread: &mut Value, s: &str) -> usize {
+    let Some(strings) = thread["stringArray"].as_array_mut() else {
+        return 0;
+    };
+    match strings.iter().position(|existing| existing == s) {
+        Some(index) => index,
+        None => {
+            strings.push(json!(s));
+            strings.len() - 1
+        }
+    }
+}
+
+/// Returns the index of the category with this name, and adds it if needed.
+pub fn category_index(profile: &mut Value, name: &str, color: &str) -> usize {
+    let Some(categories) = profile["meta"]["categories"].as_array_mut() else {
+        return 0;
+    };
+    if let Some(index) = categories
+        .iter()
+        .position(|category| category["name"] == name)
+    {
+        return index;
+    }
+    categories.push(json!({
+        "name": name,
+        "color": color,
+        "subcategories": ["Other"],
+    }));
+    categories.len() - 1
+}
+
+/// Adds the schema to the profile's marker schemas, unless there already is
+/// one with the same name.
+pub fn add_marker_schema(profile: &mut Value, schema: Value) {
+    let Some(schemas) = profile["meta"]["markerSchema"].as_array_mut() else {
+        return;
+    };
+    if schemas
+        .iter()
+        .any(|existing| existing["name"] == schema["name"])
+    {
+        return;
+    }
+    schemas.push(schema);
+}
+
+/// Appends a marker to the thread's marker table. The marker is an instant
+/// marker if `end` is `None`, and an interval marker otherwise. The times are
+/// in milliseconds since the profile's start time.
+pub fn push_marker(
+    thread: &mut Value,
+    name: usize,
+    category: usize,
+    start: f64,
+    end: Option<f64>,
+    data: Value,
+) {
+    let markers = &mut thread["markers"];
+    let mut push = |column: &str, value: Value| {
+        if let Some(column) = markers[column].as_array_mut() {
+            column.push(value);
+        }
+    };
+    push("category", json!(category));
+    push("data", data);
+    push("name", json!(name));
+    push("phase", json!(if end.is_some() { 1 } else { 0 }));
+    push("startTime", json!(start));
+    push("endTime", json!(end));
+    let length = markers["length"].as_u64().unwrap_or(0);
+    markers["length"] = json!(length + 1);
+}
+
+/// Returns the pid of the thread's process, which is a string in profiles
+/// from samply.
+pub fn thread_pid(thread: &Value) -> Option<u32> {
+    match &thread["pid"] {
+        Value::Number(pid) => pid.as_u64().map(|pid| pid as u32),
+        Value::String(pid) => pid.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Returns the thread's ID, which is a number in profiles from samply and a
+/// string in some others.
+pub fn thread_tid(thread: &Value) -> Option<u32> {
+    match &thread["tid"] {
+        Value::Number(tid) => tid.as_u64().map(|tid| tid as u32),
+        Value::String(tid) => tid.parse().ok(),
+        _ => None,
+    }
+}
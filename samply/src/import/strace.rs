@@ -0,0 +1,321 @@
+//! Merging of strace and ltrace logs into an existing profile.
+//!
+//! The logs need to be recorded with absolute timestamps (`-ttt`), and with
+//! `-f` if the program has more than one thread, so that each line has the
+//! thread ID:
+//!
+//! ```text
+//! 1234  1700000000.123456 openat(AT_FDCWD, "a.txt", O_RDONLY) = 3 <0.000012>
+//! 1235  1700000000.123500 futex(0x7f00, FUTEX_WAIT, 0, NULL <unfinished ...>
+//! 1235  1700000000.223500 <... futex resumed>) = 0
+//! ```
+//!
+//! Each call becomes an interval marker on the profile thread with the same
+//! thread ID. The end of a call is taken from its duration if the log was
+//! recorded with `-T`, or from the time at which it was resumed. The profile's
+//! start time is in wall-clock time, like the strace timestamps, so the calls
+//! line up with the samples if both were recorded on the same machine.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use serde_json::{json, Value};
+
+use super::profile_markers::{
+    add_marker_schema, category_index, push_marker, string_index, thread_tid,
+};
+
+/// A system call or library call from the log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    /// The thread ID, if the log has one.
+    pub tid: Option<u32>,
+    /// The start time, in milliseconds since the Unix epoch.
+    pub start: f64,
+    /// The end time, in milliseconds since the Unix epoch.
+    pub end: f64,
+    pub name: String,
+    pub args: String,
+    pub result: String,
+}
+
+/// The start of a call which hasn't been resumed yet.
+struct UnfinishedCall {
+    start: f64,
+    args: String,
+}
+
+/// Reads the calls from an strace or ltrace log.
+pub fn parse_log<R: BufRead>(reader: R) -> Result<Vec<Call>, std::io::Error> {
+    let mut calls = Vec::new();
+    let mut unfinished: HashMap<(Option<u32>, String), UnfinishedCall> = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let Some(line) = parse_line(&line) else {
+            continue;
+        };
+        match line.kind {
+            LineKind::Complete { args, result } => calls.push(Call {
+                tid: line.tid,
+                start: line.time,
+                end: line.time + line.duration.unwrap_or(0.0),
+                name: line.name.to_owned(),
+                args: args.to_owned(),
+                result: result.to_owned(),
+            }),
+            LineKind::Unfinished { args } => {
+                unfinished.insert(
+                    (line.tid, line.name.to_owned()),
+                    UnfinishedCall {
+                        start: line.time,
+                        args: args.to_owned(),
+                    },
+                );
+            }
+            LineKind::Resumed { args, result } => {
+                let Some(call) = unfinished.remove(&(line.tid, line.name.to_owned())) else {
+                    continue;
+                };
+                let end = match line.duration {
+                    Some(duration) => call.start + duration,
+                    None => line.time,
+                };
+                calls.push(Call {
+                    tid: line.tid,
+                    start: call.start,
+                    end,
+                    name: line.name.to_owned(),
+                    args: format!("{}{args}", call.args),
+                    result: result.to_owned(),
+                });
+            }
+        }
+    }
+    Ok(calls)
+}
+
+#[derive(Debug, PartialEq)]
+enum LineKind<'a> {
+    /// `name(args) = result`
+    Complete { args: &'a str, result: &'a str },
+    /// `name(args <unfinished ...>`
+    Unfinished { args: &'a str },
+    /// `<... name resumed>args) = result`
+    Resumed { args: &'a str, result: &'a str },
+}
+
+#[derive(Debug, PartialEq)]
+struct Line<'a> {
+    tid: Option<u32>,
+    /// In milliseconds since the Unix epoch.
+    time: f64,
+    /// The duration from `-T`, in milliseconds.
+    duration: Option<f64>,
+    name: &'a str,
+    kind: LineKind<'a>,
+}
+
+fn parse_line(line: &str) -> Option<Line<'_>> {
+    let mut rest = line.trim_start();
+    // The thread ID is either at the start of the line (with -o), or in
+    // "[pid 1234]" (when writing to stderr).
+    let mut tid = None;
+    if let Some(after_pid) = rest.strip_prefix("[pid") {
+        let (pid, after) = after_pid.split_once(']')?;
+        tid = Some(pid.trim().parse().ok()?);
+        rest = after.trim_start();
+    }
+    let (first, after) = rest.split_once(' ')?;
+    if tid.is_none() && !first.contains('.') {
+        tid = Some(first.parse().ok()?);
+        rest = after.trim_start();
+    }
+    let (time, rest) = rest.split_once(' ')?;
+    let time = time.parse::<f64>().ok()? * 1000.0;
+
+    // Split off the "-T" duration, like " <0.000012>".
+    let (rest, duration) = match rest.strip_suffix('>').and_then(|r| r.rsplit_once(" <")) {
+        Some((before, duration)) => match duration.parse::<f64>() {
+            Ok(duration) => (before, Some(duration * 1000.0)),
+            Err(_) => (rest, None),
+        },
+        None => (rest, None),
+    };
+
+    if let Some(resumed) = rest.strip_prefix("<... ") {
+        let (name, rest) = resumed.split_once(" resumed>")?;
+        let (args, result) = split_result(rest)?;
+        return Some(Line {
+            tid,
+            time,
+            duration,
+            name,
+            kind: LineKind::Resumed { args, result },
+        });
+    }
+
+    let (name, rest) = rest.split_once('(')?;
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_@.".contains(c))
+    {
+        // Signals ("--- SIGCHLD ... ---") and exits ("+++ exited with 0 +++").
+        return None;
+    }
+    let kind = match rest.strip_suffix(" <unfinished ...>") {
+        Some(args) => LineKind::Unfinished { args },
+        None => {
+            let (args, result) = split_result(rest)?;
+            LineKind::Complete { args, result }
+        }
+    };
+    Some(Line {
+        tid,
+        time,
+        duration,
+        name,
+        kind,
+    })
+}
+
+/// Splits `args) = result` into the arguments and the result.
+fn split_result(rest: &str) -> Option<(&str, &str)> {
+    let (args, result) = rest.rsplit_once(") = ")?;
+    Some((args, result.trim()))
+}
+
+const SYSCALL_MARKER_TYPE: &str = "Syscall";
+const SYSCALL_CATEGORY: &str = "Syscall";
+
+/// Adds the calls as markers to the threads with the same thread ID in the
+/// processed profile JSON. Calls without a thread ID go to the main thread, if
+/// there is exactly one process. Returns the number of added markers.
+pub fn merge_into_profile(profile: &mut Value, calls: &[Call]) -> usize {
+    let start_time = profile["meta"]["startTime"].as_f64().unwrap_or(0.0);
+    let category = category_index(profile, SYSCALL_CATEGORY, "orange");
+    add_marker_schema(profile, syscall_marker_schema());
+
+    let Some(threads) = profile["threads"].as_array_mut() else {
+        return 0;
+    };
+    let mut thread_by_tid: HashMap<u32, usize> = HashMap::new();
+    let mut main_threads = Vec::new();
+    for (index, thread) in threads.iter().enumerate() {
+        if let Some(tid) = thread_tid(thread) {
+            thread_by_tid.entry(tid).or_insert(index);
+        }
+        if thread["isMainThread"] == true {
+            main_threads.push(index);
+        }
+    }
+    let default_thread = match main_threads[..] {
+        [thread] => Some(thread),
+        _ => None,
+    };
+
+    let mut added = 0;
+    for call in calls {
+        let thread_index = match call.tid {
+            Some(tid) => thread_by_tid.get(&tid).copied(),
+            None => default_thread,
+        };
+        let Some(thread) = thread_index.and_then(|index| threads.get_mut(index)) else {
+            continue;
+        };
+        let name = string_index(thread, &call.name);
+        let data = json!({
+            "type": SYSCALL_MARKER_TYPE,
+            "args": call.args,
+            "result": call.result,
+        });
+        push_marker(
+            thread,
+            name,
+            category,
+            call.start - start_time,
+            Some(call.end - start_time),
+            data,
+        );
+        added += 1;
+    }
+    added
+}
+
+fn syscall_marker_schema() -> Value {
+    json!({
+        "name": SYSCALL_MARKER_TYPE,
+        "display": ["marker-chart", "marker-table", "timeline-overview"],
+        "chartLabel": "{marker.name}",
+        "tooltipLabel": "{marker.name}({marker.data.args}) = {marker.data.result}",
+        "tableLabel": "{marker.name}({marker.data.args}) = {marker.data.result}",
+        "description": "A system call or library call from an strace or ltrace log.",
+        "fields": [
+            { "key": "args", "label": "Arguments", "format": "string", "searchable": true },
+            { "key": "result", "label": "Result", "format": "string", "searchable": true },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_lines() {
+        let log = "\
+1234  1700000000.100000 openat(AT_FDCWD, \"a.txt\", O_RDONLY) = 3 <0.000500>
+1235  1700000000.200000 futex(0x7f00, FUTEX_WAIT, 0, NULL <unfinished ...>
+1234  1700000000.250000 --- SIGCHLD {si_signo=SIGCHLD} ---
+1235  1700000000.300000 <... futex resumed>) = 0
+[pid  1236] 1700000000.400000 malloc(16) = 0x5555
+1700000000.500000 exit_group(0) = ?
+";
+        let calls = parse_log(log.as_bytes()).unwrap();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls[0].tid, Some(1234));
+        assert_eq!(calls[0].name, "openat");
+        assert_eq!(calls[0].args, "AT_FDCWD, \"a.txt\", O_RDONLY");
+        assert_eq!(calls[0].result, "3");
+        assert!((calls[0].end - calls[0].start - 0.5).abs() < 1e-3);
+        assert_eq!(calls[1].name, "futex");
+        assert_eq!(calls[1].tid, Some(1235));
+        assert!((calls[1].end - calls[1].start - 100.0).abs() < 1e-3);
+        assert_eq!(calls[2].tid, Some(1236));
+        assert_eq!(calls[2].result, "0x5555");
+        assert_eq!(calls[3].tid, None);
+        assert_eq!(calls[3].result, "?");
+    }
+
+    #[test]
+    fn merge() {
+        let mut profile = json!({
+            "meta": { "startTime": 1700000000000.0, "categories": [], "markerSchema": [] },
+            "threads": [{
+                "tid": 1234,
+                "isMainThread": true,
+                "stringArray": [],
+                "markers": {
+                    "length": 0, "category": [], "data": [], "endTime": [],
+                    "name": [], "phase": [], "startTime": [],
+                },
+            }],
+        });
+        let calls = vec![Call {
+            tid: Some(1234),
+            start: 1700000000100.0,
+            end: 1700000000101.0,
+            name: "read".into(),
+            args: "3, ..., 4096".into(),
+            result: "12".into(),
+        }];
+        assert_eq!(merge_into_profile(&mut profile, &calls), 1);
+        let markers = &profile["threads"][0]["markers"];
+        assert_eq!(markers["length"], 1);
+        assert_eq!(markers["startTime"], json!([100.0]));
+        assert_eq!(markers["data"][0]["type"], "Syscall");
+        assert_eq!(profile["threads"][0]["stringArray"], json!(["read"]));
+        assert_eq!(profile["meta"]["categories"][0]["name"], "Syscall");
+        assert_eq!(profile["meta"]["markerSchema"][0]["name"], "Syscall");
+    }
+}
@@ -0,0 +1,142 @@
+//! Following processes which come and go during the recording, for
+//! `samply record --follow-restarts` and `--systemd-unit`.
+//!
+//! The main thread polls for new processes to attach to, while the observer
+//! thread keeps recording. A new process which appears after all processes
+//! which were attached to before have quit is a restart of the recorded
+//! process, and gets a "Restart" marker.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// A source of processes to attach to during the recording.
+pub trait FollowedProcesses {
+    /// The processes which need to be attached to since the last call.
+    fn new_pids(&mut self) -> Vec<u32>;
+
+    /// How long to wait before the next call to `new_pids`.
+    fn poll_interval(&self) -> Duration;
+}
+
+/// New instances of a process, by the name of its executable, for
+/// `--follow-restarts`.
+///
+/// While a recorded process is running, new processes with the same name are
+/// left alone, so that other instances which happen to be started aren't
+/// recorded. Once all recorded processes have quit, the next process whose
+/// executable name contains the name is recorded, from its start.
+pub struct ProcessRestarts {
+    name: String,
+    /// The processes which were attached to and which haven't quit yet.
+    running_pids: Vec<u32>,
+    /// All processes which existed at the last call, so that only processes
+    /// which were started since then are considered.
+    known_pids: HashSet<u32>,
+    /// The new processes which didn't match the name yet, with the time they
+    /// were first seen. A supervisor forks and then execs the new instance,
+    /// so the name of a new process is checked again for a little while.
+    unmatched_pids: HashMap<u32, Instant>,
+}
+
+impl ProcessRestarts {
+    /// How often the recorded processes are checked while they're running.
+    const RUNNING_POLL_INTERVAL: Duration = Duration::from_millis(200);
+    /// How often /proc is checked for a new instance once they have quit.
+    /// This is short so that the new instance is stopped early in its
+    /// startup, like with `--wait-for-process`.
+    const WAITING_POLL_INTERVAL: Duration = Duration::from_millis(5);
+    /// How long a new process can take to exec after it was forked.
+    const EXEC_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+    /// Follows the restarts of the process `pid`, which is already recorded,
+    /// with new instances found by `name`.
+    pub fn new(name: &str, pid: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            running_pids: vec![pid],
+            known_pids: list_pids().into_iter().collect(),
+            unmatched_pids: HashMap::new(),
+        }
+    }
+}
+
+impl FollowedProcesses for ProcessRestarts {
+    fn new_pids(&mut self) -> Vec<u32> {
+        self.running_pids.retain(|&pid| is_running(pid));
+        let pids: HashSet<u32> = list_pids().into_iter().collect();
+        let now = Instant::now();
+        let mut new_pids = Vec::new();
+        let candidates = match self.running_pids.is_empty() {
+            true => pids.iter().copied().collect(),
+            false => Vec::new(),
+        };
+        for pid in candidates {
+            if self.known_pids.contains(&pid) {
+                match self.unmatched_pids.get(&pid) {
+                    Some(&first_seen) if now - first_seen < Self::EXEC_GRACE_PERIOD => {}
+                    _ => continue,
+                }
+            }
+            if !process_exe_name(pid).is_some_and(|exe_name| exe_name.contains(&self.name)) {
+                self.unmatched_pids.entry(pid).or_insert(now);
+                continue;
+            }
+            // Stop it so that its startup isn't missed. It's resumed once the
+            // perf events have been attached.
+            let ok = unsafe { libc::kill(pid as _, libc::SIGSTOP) };
+            if ok == 0 {
+                new_pids.push(pid);
+            }
+        }
+        // Forget the processes which have quit, so that a reused pid is
+        // considered again.
+        self.unmatched_pids.retain(|pid, first_seen| {
+            pids.contains(pid) && now - *first_seen < Self::EXEC_GRACE_PERIOD
+        });
+        self.known_pids = pids;
+        self.running_pids.extend(&new_pids);
+        new_pids
+    }
+
+    fn poll_interval(&self) -> Duration {
+        if self.running_pids.is_empty() {
+            Self::WAITING_POLL_INTERVAL
+        } else {
+            Self::RUNNING_POLL_INTERVAL
+        }
+    }
+}
+
+/// Whether the process exists and hasn't quit. A process which has quit but
+/// hasn't been waited for by its parent yet is a zombie, which counts as quit.
+pub fn is_running(pid: u32) -> bool {
+    let Ok(stat) = std::fs::read_to_string(format!("/proc/{pid}/stat")) else {
+        return false;
+    };
+    // The process name comes before the state, in parentheses, and can
+    // contain spaces and parentheses itself.
+    let state = stat
+        .rsplit_once(')')
+        .and_then(|(_, after_name)| after_name.split_whitespace().next());
+    !matches!(state, Some("Z" | "X") | None)
+}
+
+pub fn list_pids() -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect()
+}
+
+pub fn process_exe_name(pid: u32) -> Option<String> {
+    if let Ok(exe) = std::fs::read_link(format!("/proc/{pid}/exe")) {
+        if let Some(file_name) = exe.file_name() {
+            return Some(file_name.to_string_lossy().into_owned());
+        }
+    }
+    // Fall back to the (truncated) comm name, e.g. if we can't read the exe link.
+    let comm = std::fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    Some(comm.trim_end().to_string())
+}
@@ -1,7 +1,12 @@
+mod follow;
 mod perf_event;
 mod perf_group;
 mod proc_maps;
 mod process;
 pub mod profiler;
+mod ring_buffer;
 mod sorter;
 mod sys;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod tracefs;
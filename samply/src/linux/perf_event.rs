@@ -1,6 +1,7 @@
 use std::cell::RefCell;
 use std::cmp::max;
 use std::collections::BinaryHeap;
+use std::ffi::CString;
 use std::ops::Range;
 use std::os::unix::io::RawFd;
 use std::rc::Rc;
@@ -86,6 +87,7 @@ pub struct Perf {
     fd: RawFd,
     position: u64,
     parse_info: RecordParseInfo,
+    event_source: EventSource,
 }
 
 impl Drop for Perf {
@@ -148,6 +150,51 @@ fn next_raw_event(
 pub enum EventSource {
     HwCpuCycles,
     SwCpuClock,
+    /// The tracepoint with this id, from /sys/kernel/tracing/events/*/*/id.
+    /// Every hit is sampled.
+    Tracepoint(u64),
+    /// The probe with this index in the recorded probes. Every hit is
+    /// sampled.
+    Probe(usize),
+}
+
+impl EventSource {
+    /// Whether the event is sampled on every hit rather than at a frequency.
+    pub fn samples_every_hit(self) -> bool {
+        matches!(self, EventSource::Tracepoint(_) | EventSource::Probe(_))
+    }
+}
+
+/// A probe whose hits are recorded, for `--uprobe`, `--usdt`, `--kprobe` and
+/// `--tracepoint`.
+#[derive(Clone, Debug)]
+pub enum Probe {
+    /// A uprobe, for the kernel's uprobe PMU.
+    Uprobe {
+        /// The type of the uprobe PMU, from /sys/bus/event_source/devices/uprobe/type.
+        pmu_type: u32,
+        path: CString,
+        /// The file offset of the probed instruction.
+        offset: u64,
+        /// The file offset of the USDT semaphore, or 0.
+        semaphore_offset: u64,
+    },
+    /// A kprobe at the start of a kernel function, for the kernel's kprobe PMU.
+    Kprobe {
+        /// The type of the kprobe PMU, from /sys/bus/event_source/devices/kprobe/type.
+        pmu_type: u32,
+        function: CString,
+    },
+    /// The tracepoint with this id. Its samples have the tracepoint's fields
+    /// in their raw data.
+    Tracepoint(u64),
+}
+
+impl Probe {
+    /// Whether the probe is hit in the kernel.
+    pub fn is_kernel(&self) -> bool {
+        matches!(self, Probe::Kprobe { .. } | Probe::Tracepoint(_))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -163,6 +210,7 @@ pub struct PerfBuilder {
     enable_on_exec: bool,
     exclude_kernel: bool,
     gather_context_switches: bool,
+    probe: Option<Probe>,
 }
 
 impl PerfBuilder {
@@ -207,6 +255,14 @@ impl PerfBuilder {
         self
     }
 
+    /// Samples the hits of a probe, which is identified by `index` in the
+    /// event source of the events.
+    pub fn probe(mut self, index: usize, probe: &Probe) -> Self {
+        self.event_source = EventSource::Probe(index);
+        self.probe = Some(probe.clone());
+        self
+    }
+
     pub fn inherit_to_children(mut self) -> Self {
         self.inherit = true;
         self
@@ -251,7 +307,7 @@ impl PerfBuilder {
         //     start_disabled
         // );
 
-        let max_sample_rate = Perf::max_sample_rate();
+        let max_sample_rate = Perf::max_sample_rate().filter(|_| !event_source.samples_every_hit());
         if let Some(max_sample_rate) = max_sample_rate {
             // debug!("Maximum sample rate: {}", max_sample_rate);
             if frequency > max_sample_rate {
@@ -295,6 +351,38 @@ impl PerfBuilder {
                 attr.kind = PERF_TYPE_SOFTWARE;
                 attr.config = PERF_COUNT_SW_CPU_CLOCK;
             }
+            EventSource::Tracepoint(id) => {
+                attr.kind = PERF_TYPE_TRACEPOINT;
+                attr.config = id;
+            }
+            EventSource::Probe(_) => {
+                match self.probe.as_ref().expect("probe events need a probe") {
+                    Probe::Uprobe {
+                        pmu_type,
+                        path,
+                        offset,
+                        semaphore_offset,
+                    } => {
+                        attr.kind = *pmu_type;
+                        // The semaphore offset goes into the "ref_ctr_offset" bits,
+                        // config:32-63 in /sys/bus/event_source/devices/uprobe/format.
+                        attr.config = semaphore_offset << 32;
+                        // config1 is the path and config2 the offset.
+                        attr.bp_addr_or_config = path.as_ptr() as u64;
+                        attr.bp_len_or_config = *offset;
+                    }
+                    Probe::Kprobe { pmu_type, function } => {
+                        attr.kind = *pmu_type;
+                        // config1 is the function name and config2 the offset into
+                        // it. Bit 0 of config would make it a kretprobe.
+                        attr.bp_addr_or_config = function.as_ptr() as u64;
+                    }
+                    Probe::Tracepoint(id) => {
+                        attr.kind = PERF_TYPE_TRACEPOINT;
+                        attr.config = *id;
+                    }
+                }
+            }
         }
 
         attr.sample_type = PERF_SAMPLE_IP
@@ -311,20 +399,30 @@ impl PerfBuilder {
             attr.sample_type |= PERF_SAMPLE_STACK_USER;
         }
 
+        if let Some(Probe::Tracepoint(_)) = self.probe {
+            attr.sample_type |= PERF_SAMPLE_RAW;
+        }
+
         attr.sample_regs_user = reg_mask;
         attr.sample_stack_user = stack_size;
-        attr.sample_period_or_freq = frequency;
         attr.clock_id = libc::CLOCK_MONOTONIC;
 
-        attr.flags = PERF_ATTR_FLAG_DISABLED
-            | PERF_ATTR_FLAG_MMAP
-            | PERF_ATTR_FLAG_MMAP2
-            | PERF_ATTR_FLAG_MMAP_DATA
-            | PERF_ATTR_FLAG_COMM
-            | PERF_ATTR_FLAG_FREQ
-            | PERF_ATTR_FLAG_TASK
-            | PERF_ATTR_FLAG_SAMPLE_ID_ALL
-            | PERF_ATTR_FLAG_USE_CLOCKID;
+        attr.flags =
+            PERF_ATTR_FLAG_DISABLED | PERF_ATTR_FLAG_SAMPLE_ID_ALL | PERF_ATTR_FLAG_USE_CLOCKID;
+
+        if event_source.samples_every_hit() {
+            // Sample every hit. The mmap, comm and fork records come from the
+            // sampling events of the same threads.
+            attr.sample_period_or_freq = 1;
+        } else {
+            attr.sample_period_or_freq = frequency;
+            attr.flags |= PERF_ATTR_FLAG_MMAP
+                | PERF_ATTR_FLAG_MMAP2
+                | PERF_ATTR_FLAG_MMAP_DATA
+                | PERF_ATTR_FLAG_COMM
+                | PERF_ATTR_FLAG_FREQ
+                | PERF_ATTR_FLAG_TASK;
+        }
 
         if self.enable_on_exec {
             attr.flags |= PERF_ATTR_FLAG_ENABLE_ON_EXEC;
@@ -407,6 +505,7 @@ impl PerfBuilder {
             fd,
             position: 0,
             parse_info,
+            event_source,
         };
 
         if !start_disabled {
@@ -436,6 +535,7 @@ impl Perf {
             enable_on_exec: false,
             exclude_kernel: true,
             gather_context_switches: false,
+            probe: None,
         }
     }
 
@@ -445,6 +545,16 @@ impl Perf {
         assert!(result != -1);
     }
 
+    /// Changes the sampling frequency, or the sampling period for events
+    /// which aren't in frequency mode.
+    pub fn set_period_or_freq(&mut self, value: u64) -> io::Result<()> {
+        let result = unsafe { libc::ioctl(self.fd, PERF_EVENT_IOC_PERIOD as _, &value) };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn are_events_pending(&self) -> bool {
         let head = unsafe { read_head(self.buffer) };
@@ -456,6 +566,11 @@ impl Perf {
         self.fd
     }
 
+    #[inline]
+    pub fn event_source(&self) -> EventSource {
+        self.event_source
+    }
+
     #[inline]
     pub fn iter(&mut self) -> EventIter {
         EventIter::new(self)
@@ -524,6 +639,7 @@ pub struct EventRef {
     prev_position: u64,
     position: u64,
     parse_info: RecordParseInfo,
+    event_source: EventSource,
 }
 
 impl fmt::Debug for EventRef {
@@ -551,6 +667,11 @@ impl EventRef {
 
         self.event_location.get(buffer, self.parse_info)
     }
+
+    /// The source of the perf event which recorded this event.
+    pub fn event_source(&self) -> EventSource {
+        self.event_source
+    }
 }
 
 pub struct EventIter<'a> {
@@ -580,6 +701,7 @@ impl Iterator for EventIter<'_> {
             prev_position,
             position: perf.position,
             parse_info: self.perf.parse_info,
+            event_source: self.perf.event_source,
         })
     }
 }
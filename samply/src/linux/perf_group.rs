@@ -10,7 +10,7 @@ use linux_perf_data::linux_perf_event_reader::get_record_timestamp;
 use mio::unix::SourceFd;
 use mio::{Events, Interest, Poll, Token};
 
-use super::perf_event::{EventRef, EventSource, Perf};
+use super::perf_event::{EventRef, EventSource, Perf, Probe};
 use super::sorter::EventSorter;
 
 struct StoppedProcess(u32);
@@ -72,9 +72,24 @@ pub struct PerfGroup {
     stack_size: u32,
     regs_mask: u64,
     event_source: EventSource,
+    tracing_events: TracingEvents,
     stopped_processes: Vec<StoppedProcess>,
 }
 
+/// The events which are sampled on every hit, next to the sampling events of
+/// each recorded thread.
+#[derive(Debug, Clone, Default)]
+pub struct TracingEvents {
+    /// The id of the sched:sched_switch tracepoint, if the stacks at which
+    /// threads go off-CPU are sampled, for `--off-cpu`.
+    pub sched_switch_tracepoint: Option<u64>,
+    /// The probes, for `--uprobe`, `--usdt`, `--kprobe` and `--tracepoint`.
+    /// The index of a probe is in the event source of its events.
+    pub probes: Vec<Probe>,
+    /// Whether the stacks of the probe hits are sampled.
+    pub probe_stacks: bool,
+}
+
 fn get_threads(pid: u32) -> Result<Vec<u32>, io::Error> {
     let entries = fs::read_dir(format!("/proc/{pid}/task"))?;
     let tids = entries
@@ -98,7 +113,13 @@ pub enum AttachMode {
 }
 
 impl PerfGroup {
-    pub fn new(frequency: u32, stack_size: u32, regs_mask: u64, event_source: EventSource) -> Self {
+    pub fn new(
+        frequency: u32,
+        stack_size: u32,
+        regs_mask: u64,
+        event_source: EventSource,
+        tracing_events: TracingEvents,
+    ) -> Self {
         PerfGroup {
             event_sorter: EventSorter::new(),
             members: Default::default(),
@@ -108,6 +129,7 @@ impl PerfGroup {
             stack_size,
             event_source,
             regs_mask,
+            tracing_events,
             stopped_processes: Vec::new(),
         }
     }
@@ -118,9 +140,16 @@ impl PerfGroup {
         stack_size: u32,
         event_source: EventSource,
         regs_mask: u64,
+        tracing_events: TracingEvents,
         attach_mode: AttachMode,
     ) -> Result<Self, io::Error> {
-        let mut group = PerfGroup::new(frequency, stack_size, regs_mask, event_source);
+        let mut group = PerfGroup::new(
+            frequency,
+            stack_size,
+            regs_mask,
+            event_source,
+            tracing_events,
+        );
         group.open_process(pid, attach_mode)?;
         Ok(group)
     }
@@ -153,6 +182,9 @@ impl PerfGroup {
             let perf = builder.open()?;
 
             perf_events.push((Some(cpu), perf));
+            for perf in self.open_tracing_events(pid, Some(cpu), attach_mode)? {
+                perf_events.push((Some(cpu), perf));
+            }
         }
 
         if cpu_count * (threads.len() + 1) >= 1000 {
@@ -172,6 +204,9 @@ impl PerfGroup {
                 let perf = builder.open()?;
 
                 perf_events.push((None, perf));
+                for perf in self.open_tracing_events(tid, None, attach_mode)? {
+                    perf_events.push((None, perf));
+                }
             }
         } else {
             for cpu in 0..cpu_count as u32 {
@@ -193,6 +228,9 @@ impl PerfGroup {
                     let perf = builder.open()?;
 
                     perf_events.push((Some(cpu), perf));
+                    for perf in self.open_tracing_events(tid, Some(cpu), attach_mode)? {
+                        perf_events.push((Some(cpu), perf));
+                    }
                 }
             }
         }
@@ -210,6 +248,58 @@ impl PerfGroup {
         Ok(())
     }
 
+    /// Opens the tracing events for a thread, or for a process and its future
+    /// threads if a CPU is given, next to its sampling event. The samples of
+    /// the sched_switch tracepoint have the user stack at which the thread
+    /// went off-CPU.
+    fn open_tracing_events(
+        &self,
+        pid: u32,
+        cpu: Option<u32>,
+        attach_mode: AttachMode,
+    ) -> Result<Vec<Perf>, io::Error> {
+        let tracepoint_builder = self
+            .tracing_events
+            .sched_switch_tracepoint
+            .map(|tracepoint| {
+                Perf::build()
+                    .sample_user_stack(self.stack_size)
+                    .sample_user_regs(self.regs_mask)
+                    .sample_kernel()
+                    .event_source(EventSource::Tracepoint(tracepoint))
+            });
+        let probe_builders = self
+            .tracing_events
+            .probes
+            .iter()
+            .enumerate()
+            .map(|(index, probe)| {
+                let mut builder = Perf::build().probe(index, probe);
+                if probe.is_kernel() {
+                    builder = builder.sample_kernel();
+                }
+                match self.tracing_events.probe_stacks {
+                    true => builder
+                        .sample_user_stack(self.stack_size)
+                        .sample_user_regs(self.regs_mask),
+                    false => builder,
+                }
+            });
+        let mut perf_events = Vec::new();
+        for builder in tracepoint_builder.into_iter().chain(probe_builders) {
+            let mut builder = builder.pid(pid).start_disabled();
+            builder = match cpu {
+                Some(cpu) => builder.only_cpu(cpu).inherit_to_children(),
+                None => builder.any_cpu(),
+            };
+            if attach_mode == AttachMode::AttachWithEnableOnExec {
+                builder = builder.enable_on_exec();
+            }
+            perf_events.push(builder.open()?);
+        }
+        Ok(perf_events)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.members.is_empty()
     }
@@ -222,6 +312,27 @@ impl PerfGroup {
         self.stopped_processes.clear();
     }
 
+    /// Changes the sampling frequency of all events, including the ones of
+    /// processes which are opened later.
+    pub fn set_frequency(&mut self, frequency: u32) {
+        self.frequency = frequency;
+        // The kernel turns the frequency of cpu-clock events into a fixed
+        // period in nanoseconds when the event is opened, so these events
+        // need to be given a period.
+        let value = match self.event_source {
+            EventSource::HwCpuCycles => u64::from(frequency),
+            EventSource::SwCpuClock => 1_000_000_000 / u64::from(frequency.max(1)),
+            EventSource::Tracepoint(_) | EventSource::Probe(_) => {
+                unreachable!("the sampling events are sampled at a frequency")
+            }
+        };
+        for perf in self.members.values_mut() {
+            if !perf.event_source().samples_every_hit() {
+                let _ = perf.set_period_or_freq(value);
+            }
+        }
+    }
+
     pub fn wait(&mut self) {
         for member in self.members.values() {
             if member.are_events_pending() {
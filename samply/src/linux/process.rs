@@ -13,6 +13,10 @@ pub struct SuspendedLaunchedProcess {
     pid: Pid,
     send_end_of_resume_pipe: OwnedFd,
     recv_end_of_execerr_pipe: OwnedFd,
+    /// The read end of the child's stdout, if it was captured.
+    recv_end_of_stdout_pipe: Option<OwnedFd>,
+    /// The read end of the child's stderr, if it was captured.
+    recv_end_of_stderr_pipe: Option<OwnedFd>,
 }
 
 impl SuspendedLaunchedProcess {
@@ -20,6 +24,8 @@ impl SuspendedLaunchedProcess {
         command_name: &OsStr,
         command_args: &[OsString],
         env_vars: &[(OsString, OsString)],
+        capture_stdout: bool,
+        capture_stderr: bool,
     ) -> std::io::Result<Self> {
         let argv: Vec<CString> = std::iter::once(command_name)
             .chain(command_args.iter().map(|s| s.as_os_str()))
@@ -51,21 +57,47 @@ impl SuspendedLaunchedProcess {
 
         let (resume_rp, resume_sp) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).unwrap();
         let (execerr_rp, execerr_sp) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).unwrap();
+        let stdout_pipe = if capture_stdout {
+            Some(nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)?)
+        } else {
+            None
+        };
+        let stderr_pipe = if capture_stderr {
+            Some(nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)?)
+        } else {
+            None
+        };
 
         match unsafe { nix::unistd::fork() }.expect("Fork failed") {
             nix::unistd::ForkResult::Child => {
                 // std::panic::always_abort();
                 nix::unistd::close(resume_sp.into_raw_fd()).unwrap();
                 nix::unistd::close(execerr_rp.into_raw_fd()).unwrap();
+                // dup2 clears O_CLOEXEC on the new descriptor, so fds 1 and 2 stay
+                // open across exec while the original pipe fds get closed.
+                if let Some((stdout_rp, stdout_sp)) = stdout_pipe {
+                    nix::unistd::dup2(stdout_sp.as_raw_fd(), libc::STDOUT_FILENO).unwrap();
+                    drop(stdout_rp);
+                    drop(stdout_sp);
+                }
+                if let Some((stderr_rp, stderr_sp)) = stderr_pipe {
+                    nix::unistd::dup2(stderr_sp.as_raw_fd(), libc::STDERR_FILENO).unwrap();
+                    drop(stderr_rp);
+                    drop(stderr_sp);
+                }
                 Self::run_child(resume_rp, execerr_sp, &argv, envp)
             }
             nix::unistd::ForkResult::Parent { child } => {
                 nix::unistd::close(resume_rp.into_raw_fd())?;
                 nix::unistd::close(execerr_sp.into_raw_fd())?;
+                let recv_end_of_stdout_pipe = stdout_pipe.map(|(stdout_rp, _stdout_sp)| stdout_rp);
+                let recv_end_of_stderr_pipe = stderr_pipe.map(|(stderr_rp, _stderr_sp)| stderr_rp);
                 Ok(Self {
                     pid: child,
                     send_end_of_resume_pipe: resume_sp,
                     recv_end_of_execerr_pipe: execerr_rp,
+                    recv_end_of_stdout_pipe,
+                    recv_end_of_stderr_pipe,
                 })
             }
         }
@@ -75,6 +107,17 @@ impl SuspendedLaunchedProcess {
         self.pid.as_raw() as u32
     }
 
+    /// The read end of the launched command's stdout, if `capture_stdout` was
+    /// set. It reaches EOF once the command and all its children have exited.
+    pub fn take_stdout(&mut self) -> Option<OwnedFd> {
+        self.recv_end_of_stdout_pipe.take()
+    }
+
+    /// The read end of the launched command's stderr, like [`Self::take_stdout`].
+    pub fn take_stderr(&mut self) -> Option<OwnedFd> {
+        self.recv_end_of_stderr_pipe.take()
+    }
+
     const EXECERR_MSG_FOOTER: [u8; 4] = *b"NOEX";
 
     pub fn unsuspend_and_run(self) -> std::io::Result<RunningProcess> {
@@ -1,31 +1,49 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
 use std::ops::Deref;
+use std::os::fd::OwnedFd;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::process::ExitStatusExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::ExitStatus;
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
 use crossbeam_channel::{Receiver, Sender};
-use fxprof_processed_profile::{Profile, ReferenceTimestamp};
+use fxprof_processed_profile::Profile;
 use linux_perf_data::linux_perf_event_reader::{
     CpuMode, Endianness, EventRecord, Mmap2FileId, Mmap2InodeAndVersion, Mmap2Record, RawData,
 };
 use nix::sys::wait::WaitStatus;
 use tokio::sync::oneshot;
 
-use super::perf_event::EventSource;
-use super::perf_group::{AttachMode, PerfGroup};
+use super::follow::{is_running, list_pids, process_exe_name, FollowedProcesses, ProcessRestarts};
+use super::perf_event::{EventSource, Probe};
+use super::perf_group::{AttachMode, PerfGroup, TracingEvents};
 use super::proc_maps;
 use super::process::SuspendedLaunchedProcess;
+use super::ring_buffer::RingBuffer;
+#[cfg(target_os = "linux")]
+use super::systemd::{check_unit_exists, UnitCgroup};
+use super::tracefs::{find_tracepoints, tracepoint_id, TRACEFS_PERMISSION_HINT};
 use crate::linux_shared::vdso::VdsoObject;
 use crate::linux_shared::{
-    ConvertRegs, Converter, EventInterpretation, MmapRangeOrVec, OffCpuIndicator,
+    ConvertRegs, Converter, EventInterpretation, MmapRangeOrVec, OffCpuIndicator, RecordedProbe,
 };
+use crate::shared::cpu_topology::add_core_types_to_profile_meta;
 use crate::shared::ctrl_c::CtrlC;
+use crate::shared::interval_jitter::IntervalJitter;
+use crate::shared::open_handles::OPEN_HANDLES_POLL_INTERVAL;
+use crate::shared::output_capture::{OutputCaptureBudget, OutputStream, MAX_TOTAL_BYTES};
+use crate::shared::panic_message::{PanicMessage, PanicMessageParser};
+use crate::shared::probes::resolve_probe;
 use crate::shared::prop_types::{
     ProcessLaunchProps, ProfileCreationProps, RecordingMode, RecordingProps,
 };
+use crate::shared::suspend::SleepDetector;
+use crate::shared::test_output::{TestOutputParser, TestRun};
 
 #[cfg(target_arch = "x86_64")]
 pub type ConvertRegsNative = crate::linux_shared::ConvertRegsX86_64;
@@ -33,23 +51,93 @@ pub type ConvertRegsNative = crate::linux_shared::ConvertRegsX86_64;
 #[cfg(target_arch = "aarch64")]
 pub type ConvertRegsNative = crate::linux_shared::ConvertRegsAarch64;
 
+#[cfg(target_arch = "riscv64")]
+pub type ConvertRegsNative = crate::linux_shared::ConvertRegsRiscv64;
+
+#[cfg(target_arch = "arm")]
+pub type ConvertRegsNative = crate::linux_shared::ConvertRegsArm;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+type UnwinderNative = framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+type CacheNative = framehop::CacheNative<framehop::MayAllocateDuringUnwind>;
+
+// framehop doesn't support these architectures; use frame pointers.
+#[cfg(target_arch = "riscv64")]
+type UnwinderNative = crate::linux_shared::UnwinderRiscv64<MmapRangeOrVec>;
+#[cfg(target_arch = "arm")]
+type UnwinderNative = crate::linux_shared::UnwinderArm<MmapRangeOrVec>;
+#[cfg(any(target_arch = "riscv64", target_arch = "arm"))]
+type CacheNative = crate::linux_shared::FramePointerCache;
+
 pub fn run(
     recording_mode: RecordingMode,
     recording_props: RecordingProps,
     profile_creation_props: ProfileCreationProps,
 ) -> Result<(Profile, ExitStatus), ()> {
-    let process_launch_props = match recording_mode {
+    if !recording_props.thread_rate_overrides.is_empty() {
+        // perf events are opened per process and CPU, and inherited by new
+        // threads, so they can't have different rates for different threads.
+        eprintln!("Error: --rate-override isn't supported on Linux.");
+        std::process::exit(1);
+    }
+    let (process_launch_props, attach_pids) = match recording_mode {
         RecordingMode::All => {
             // TODO: Implement, by sudo launching a helper process which opens cpu-wide perf events
             eprintln!("Error: Profiling all processes is currently not supported on Linux.");
             eprintln!("You can profile processes which you launch via samply, or attach to a single process.");
             std::process::exit(1)
         }
+        RecordingMode::Pid(pid) if recording_props.follow_restarts => {
+            let Some(name) = process_exe_name(pid) else {
+                eprintln!("Error: There is no process with PID {pid}.");
+                std::process::exit(1)
+            };
+            let profile = start_profiling_followed(
+                vec![pid],
+                ProcessRestarts::new(&name, pid),
+                &format!("process with PID {pid} and its restarts"),
+                recording_props,
+                profile_creation_props,
+            );
+            return Ok((profile, ExitStatus::from_raw(0)));
+        }
         RecordingMode::Pid(pid) => {
-            let profile = start_profiling_pid(pid, recording_props, profile_creation_props);
+            let profile = start_profiling_pids(vec![pid], recording_props, profile_creation_props);
+            return Ok((profile, ExitStatus::from_raw(0)));
+        }
+        RecordingMode::WaitForProcess(name) => {
+            let Some(pid) = wait_for_process_named(&name) else {
+                std::process::exit(0)
+            };
+            let profile = if recording_props.follow_restarts {
+                start_profiling_followed(
+                    vec![pid],
+                    ProcessRestarts::new(&name, pid),
+                    &format!("process \"{name}\" and its restarts"),
+                    recording_props,
+                    profile_creation_props,
+                )
+            } else {
+                start_profiling_pids(vec![pid], recording_props, profile_creation_props)
+            };
+            return Ok((profile, ExitStatus::from_raw(0)));
+        }
+        RecordingMode::Multiple { pids, launch: None } => {
+            let profile = start_profiling_pids(pids, recording_props, profile_creation_props);
             return Ok((profile, ExitStatus::from_raw(0)));
         }
-        RecordingMode::Launch(process_launch_props) => process_launch_props,
+        #[cfg(target_os = "linux")]
+        RecordingMode::SystemdUnit(unit) => {
+            let profile =
+                start_profiling_systemd_unit(&unit, recording_props, profile_creation_props);
+            return Ok((profile, ExitStatus::from_raw(0)));
+        }
+        RecordingMode::Multiple {
+            pids,
+            launch: Some(process_launch_props),
+        } => (process_launch_props, pids),
+        RecordingMode::Launch(process_launch_props) => (process_launch_props, Vec::new()),
     };
 
     // We want to profile a child process which we are about to launch.
@@ -70,6 +158,8 @@ pub fn run(
         }
     }
 
+    let (tracing_events, recorded_probes) = tracing_events(&recording_props);
+
     // Ignore Ctrl+C while the subcommand is running. The signal still reaches the process
     // under observation while we continue to record it. (ctrl+c will send the SIGINT signal
     // to all processes in the foreground process group).
@@ -77,11 +167,21 @@ pub fn run(
 
     // Start a new process for the launched command and get its pid.
     // The command will not start running until we tell it to.
-    let process =
-        SuspendedLaunchedProcess::launch_in_suspended_state(&command_name, &args, &env_vars)
-            .unwrap();
+    let mut process = SuspendedLaunchedProcess::launch_in_suspended_state(
+        &command_name,
+        &args,
+        &env_vars,
+        recording_props.capture_output || recording_props.test_markers,
+        recording_props.capture_output || recording_props.panic_markers,
+    )
+    .unwrap();
     let pid = process.pid();
 
+    // The lines and panics which the launched command prints are sent to the
+    // observer thread, which turns them into markers.
+    let (output_sender, output_receiver) = crossbeam_channel::unbounded();
+    spawn_output_readers(&mut process, &recording_props, &output_sender);
+
     // Create a channel for the observer thread to notify the main thread once
     // profiling has been initialized and the launched process can start.
     let (profile_another_pid_request_sender, profile_another_pid_request_receiver) =
@@ -91,6 +191,7 @@ pub fn run(
 
     // Launch the observer thread. This thread will manage the perf events.
     let interval = recording_props.interval;
+    let interval_jitter = IntervalJitter::new(interval, recording_props.interval_jitter);
     let time_limit = recording_props.time_limit;
     let initial_exec_name = command_name.to_string_lossy().to_string();
     let initial_cmdline: Vec<String> = std::iter::once(initial_exec_name.clone())
@@ -101,8 +202,15 @@ pub fn run(
         None => initial_exec_name,
     };
     let initial_exec_name_and_cmdline = (initial_exec_name, initial_cmdline);
+
+    // Profiling normally stops once all recorded processes have quit. If we
+    // also attach to existing processes, e.g. a server for the launched
+    // client, we stop once the launched command is done instead.
+    let (stop_sender, stop_receiver) = oneshot::channel();
+    let jit_code_dir = jit_code_dir(&recording_props);
+    let ring_buffer = RingBuffer::from_props(&recording_props, &profile_creation_props);
     let observer_thread = thread::spawn(move || {
-        let mut converter = make_converter(interval, profile_creation_props);
+        let mut converter = make_converter(interval, profile_creation_props, jit_code_dir);
 
         // Wait for the initial pid to profile.
         let SamplerRequest::StartProfilingAnotherProcess(pid, attach_mode) =
@@ -112,27 +220,27 @@ pub fn run(
         };
 
         // Create the perf events, setting ENABLE_ON_EXEC.
-        let perf_group = init_profiler(interval, pid, attach_mode, &mut converter);
+        converter.set_probes(recorded_probes);
+        let perf_group = init_profiler(interval, tracing_events, pid, attach_mode, &mut converter);
 
         // Tell the main thread to tell the child process to begin executing.
         profile_another_pid_reply_sender.send(true).unwrap();
 
-        // Create a stop receiver which is never notified. We won't stop profiling until the
-        // child process is done.
+        // Start profiling the process.
         // If Ctrl+C is pressed, it will reach the child process, and the child process
         // will act on it and maybe terminate. If it does, profiling stops too because
         // the main thread's wait() call below will exit.
-        let (_stop_sender, stop_receiver) = oneshot::channel();
-
-        // Start profiling the process.
         run_profiler(
             perf_group,
             converter,
             time_limit,
+            interval_jitter,
             profile_another_pid_request_receiver,
             profile_another_pid_reply_sender,
             stop_receiver,
+            output_receiver,
             Some(initial_exec_name_and_cmdline),
+            ring_buffer,
         )
     });
 
@@ -167,6 +275,19 @@ pub fn run(
 
     // Phew, we're profiling!
 
+    // Attach to the other processes which should be in the same profile.
+    for &pid in &attach_pids {
+        profile_another_pid_request_sender
+            .send(SamplerRequest::StartProfilingAnotherProcess(
+                pid,
+                AttachMode::StopAttachEnableResume,
+            ))
+            .unwrap();
+        if profile_another_pid_reply_receiver.recv().unwrap() {
+            eprintln!("Also recording process with PID {pid}.");
+        }
+    }
+
     // Wait for the child process to quit.
     // This is where the main thread spends all its time during profiling.
     let mut wait_status = process.wait().unwrap();
@@ -183,10 +304,16 @@ pub fn run(
             break;
         }
         eprintln!("Running iteration {i} of {iteration_count}...");
-        let process =
-            SuspendedLaunchedProcess::launch_in_suspended_state(&command_name, &args, &env_vars)
-                .unwrap();
+        let mut process = SuspendedLaunchedProcess::launch_in_suspended_state(
+            &command_name,
+            &args,
+            &env_vars,
+            recording_props.capture_output || recording_props.test_markers,
+            recording_props.capture_output || recording_props.panic_markers,
+        )
+        .unwrap();
         let pid = process.pid();
+        spawn_output_readers(&mut process, &recording_props, &output_sender);
 
         // Tell the sampler to start profiling another pid, and wait for it to signal us to go ahead.
         profile_another_pid_request_sender
@@ -220,6 +347,9 @@ pub fn run(
     profile_another_pid_request_sender
         .send(SamplerRequest::StopProfilingOncePerfEventsExhausted)
         .unwrap();
+    if !attach_pids.is_empty() {
+        let _ = stop_sender.send(());
+    }
 
     // The launched subprocess is done. From now on, we want to terminate if the user presses Ctrl+C.
     ctrl_c_receiver.close();
@@ -239,11 +369,43 @@ pub fn run(
     Ok((profile, exit_status))
 }
 
-fn start_profiling_pid(
-    pid: u32,
+/// Polls /proc until a new process whose executable name contains `name` appears,
+/// and stops it with SIGSTOP so that we can attach before it runs much further.
+/// Returns `None` if Ctrl+C is pressed before a matching process appears.
+fn wait_for_process_named(name: &str) -> Option<u32> {
+    let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+    let mut known_pids: HashSet<u32> = list_pids().into_iter().collect();
+    eprintln!("Waiting for a process named \"{name}\" to start...");
+    loop {
+        for pid in list_pids() {
+            if !known_pids.insert(pid) {
+                continue;
+            }
+            if !process_exe_name(pid).is_some_and(|exe_name| exe_name.contains(name)) {
+                continue;
+            }
+            let ok = unsafe { libc::kill(pid as _, libc::SIGSTOP) };
+            if ok == 0 {
+                eprintln!("Found process {pid}, starting to record.");
+                return Some(pid);
+            }
+        }
+        if ctrl_c_receiver.try_recv().is_ok() {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Records the existing processes with these pids, until Ctrl+C is pressed or
+/// until all of them have quit.
+fn start_profiling_pids(
+    pids: Vec<u32>,
     recording_props: RecordingProps,
     profile_creation_props: ProfileCreationProps,
 ) -> Profile {
+    let (tracing_events, recorded_probes) = tracing_events(&recording_props);
+
     // When the first Ctrl+C is received, stop recording.
     let ctrl_c_receiver = CtrlC::observe_oneshot();
 
@@ -254,17 +416,22 @@ fn start_profiling_pid(
     let (profile_another_pid_reply_sender, profile_another_pid_reply_receiver) =
         crossbeam_channel::bounded(2);
 
+    let ring_buffer = RingBuffer::from_props(&recording_props, &profile_creation_props);
     let observer_thread = thread::spawn({
         move || {
             let interval = recording_props.interval;
+            let interval_jitter = IntervalJitter::new(interval, recording_props.interval_jitter);
             let time_limit = recording_props.time_limit;
-            let mut converter = make_converter(interval, profile_creation_props);
+            let jit_code_dir = jit_code_dir(&recording_props);
+            let mut converter = make_converter(interval, profile_creation_props, jit_code_dir);
             let SamplerRequest::StartProfilingAnotherProcess(pid, attach_mode) =
                 profile_another_pid_request_receiver.recv().unwrap()
             else {
                 panic!("The first message should be a StartProfilingAnotherProcess")
             };
-            let perf_group = init_profiler(interval, pid, attach_mode, &mut converter);
+            converter.set_probes(recorded_probes);
+            let perf_group =
+                init_profiler(interval, tracing_events, pid, attach_mode, &mut converter);
 
             // Tell the main thread that we are now executing.
             profile_another_pid_reply_sender.send(true).unwrap();
@@ -273,27 +440,42 @@ fn start_profiling_pid(
                 perf_group,
                 converter,
                 time_limit,
+                interval_jitter,
                 profile_another_pid_request_receiver,
                 profile_another_pid_reply_sender,
                 ctrl_c_receiver,
+                crossbeam_channel::never(),
                 None,
+                ring_buffer,
             )
         }
     });
 
     // We're on the main thread here and the observer thread has just been launched.
 
-    // Request profiling of our process and wait for profiler initialization.
-    profile_another_pid_request_sender
-        .send(SamplerRequest::StartProfilingAnotherProcess(
-            pid,
-            AttachMode::StopAttachEnableResume,
-        ))
-        .unwrap();
-    let _ = profile_another_pid_reply_receiver.recv().unwrap();
+    // Request profiling of our processes and wait for profiler initialization.
+    // The first process initializes the profiler, which exits on failure.
+    let mut recorded_pids = Vec::new();
+    for &pid in &pids {
+        profile_another_pid_request_sender
+            .send(SamplerRequest::StartProfilingAnotherProcess(
+                pid,
+                AttachMode::StopAttachEnableResume,
+            ))
+            .unwrap();
+        if profile_another_pid_reply_receiver.recv().unwrap() {
+            recorded_pids.push(pid.to_string());
+        }
+    }
 
     // Now that we know that profiler initialization has succeeded, tell the user about it.
-    eprintln!("Recording process with PID {pid} until Ctrl+C...");
+    match recorded_pids.as_slice() {
+        [pid] => eprintln!("Recording process with PID {pid} until Ctrl+C..."),
+        pids => eprintln!(
+            "Recording processes with PIDs {} until Ctrl+C...",
+            pids.join(", ")
+        ),
+    }
 
     profile_another_pid_request_sender
         .send(SamplerRequest::StopProfilingOncePerfEventsExhausted)
@@ -310,16 +492,213 @@ fn start_profiling_pid(
     // dropped its CtrlC receiver by now.
 }
 
+/// Records the processes of a systemd unit, until Ctrl+C is pressed or the time
+/// limit is reached. Processes which join the unit's cgroup during the
+/// recording, e.g. because the unit was restarted, are recorded too.
+#[cfg(target_os = "linux")]
+fn start_profiling_systemd_unit(
+    unit: &str,
+    recording_props: RecordingProps,
+    profile_creation_props: ProfileCreationProps,
+) -> Profile {
+    if let Err(err) = check_unit_exists(unit) {
+        eprintln!("Error: {err}");
+        std::process::exit(1)
+    }
+    let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+    let mut cgroup = UnitCgroup::new(unit);
+
+    // Wait for the unit to have a process.
+    let mut pids = cgroup.new_pids();
+    if pids.is_empty() {
+        eprintln!("Waiting for systemd unit {unit} to start...");
+    }
+    while pids.is_empty() {
+        if ctrl_c_receiver.try_recv().is_ok() {
+            std::process::exit(0)
+        }
+        thread::sleep(cgroup.poll_interval());
+        pids = cgroup.new_pids();
+    }
+    start_profiling_followed(
+        pids,
+        cgroup,
+        &format!("systemd unit {unit}"),
+        recording_props,
+        profile_creation_props,
+    )
+}
+
+/// Records the processes `pids`, and the processes which `followed` finds
+/// during the recording, until Ctrl+C is pressed or the time limit is reached.
+/// When all recorded processes have quit, the observer thread keeps running
+/// and waits for the next process.
+fn start_profiling_followed(
+    mut pids: Vec<u32>,
+    mut followed: impl FollowedProcesses,
+    description: &str,
+    recording_props: RecordingProps,
+    profile_creation_props: ProfileCreationProps,
+) -> Profile {
+    let (tracing_events, recorded_probes) = tracing_events(&recording_props);
+    let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+    let (profile_another_pid_request_sender, profile_another_pid_request_receiver) =
+        crossbeam_channel::bounded(2);
+    let (profile_another_pid_reply_sender, profile_another_pid_reply_receiver) =
+        crossbeam_channel::bounded(2);
+
+    // The observer thread keeps running while there are no processes, between
+    // restarts, until we tell it to stop.
+    let (stop_sender, stop_receiver) = oneshot::channel();
+    let deadline = recording_props
+        .time_limit
+        .map(|time_limit| Instant::now() + time_limit);
+    let ring_buffer = RingBuffer::from_props(&recording_props, &profile_creation_props);
+    let observer_thread = thread::spawn(move || {
+        let interval = recording_props.interval;
+        let interval_jitter = IntervalJitter::new(interval, recording_props.interval_jitter);
+        let time_limit = recording_props.time_limit;
+        let jit_code_dir = jit_code_dir(&recording_props);
+        let mut converter = make_converter(interval, profile_creation_props, jit_code_dir);
+        let SamplerRequest::StartProfilingAnotherProcess(pid, attach_mode) =
+            profile_another_pid_request_receiver.recv().unwrap()
+        else {
+            panic!("The first message should be a StartProfilingAnotherProcess")
+        };
+        converter.set_probes(recorded_probes);
+        let perf_group = init_profiler(interval, tracing_events, pid, attach_mode, &mut converter);
+        profile_another_pid_reply_sender.send(true).unwrap();
+
+        run_profiler(
+            perf_group,
+            converter,
+            time_limit,
+            interval_jitter,
+            profile_another_pid_request_receiver,
+            profile_another_pid_reply_sender,
+            stop_receiver,
+            crossbeam_channel::never(),
+            None,
+            ring_buffer,
+        )
+    });
+
+    eprintln!("Recording {description} until Ctrl+C...");
+    // The processes which were attached to and which were running at the last
+    // check. New processes after all of them have quit are a restart.
+    let mut running_pids: Vec<u32> = Vec::new();
+    let mut previous_pid = None;
+    let mut restart_count = 0;
+    'recording: loop {
+        running_pids.retain(|&pid| is_running(pid));
+        let mut restart = match previous_pid {
+            Some(previous_pid) if !pids.is_empty() && running_pids.is_empty() => {
+                restart_count += 1;
+                Some((restart_count, previous_pid))
+            }
+            _ => None,
+        };
+        for pid in pids {
+            // The observer thread stops by itself once the time limit is
+            // reached, and then these fail.
+            // Only the first new process gets the restart marker.
+            let is_restart = restart.is_some();
+            let request = match restart.take() {
+                Some((number, previous_pid)) => SamplerRequest::StartProfilingRestartedProcess {
+                    pid,
+                    number,
+                    previous_pid,
+                },
+                None => SamplerRequest::StartProfilingAnotherProcess(
+                    pid,
+                    AttachMode::StopAttachEnableResume,
+                ),
+            };
+            if profile_another_pid_request_sender.send(request).is_err() {
+                break 'recording;
+            }
+            match profile_another_pid_reply_receiver.recv() {
+                Ok(true) if is_restart => {
+                    eprintln!("The process was restarted, recording process with PID {pid}.")
+                }
+                Ok(true) => eprintln!("Recording process with PID {pid}."),
+                Ok(false) => continue,
+                Err(_) => break 'recording,
+            }
+            running_pids.push(pid);
+            previous_pid = Some(pid);
+        }
+        if ctrl_c_receiver.try_recv().is_ok()
+            || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            || observer_thread.is_finished()
+        {
+            break;
+        }
+        thread::sleep(followed.poll_interval());
+        pids = followed.new_pids();
+    }
+
+    // The observer thread might be waiting for a process to attach to, so
+    // send it a request, too.
+    let _ = stop_sender.send(());
+    let _ = profile_another_pid_request_sender
+        .send(SamplerRequest::StopProfilingOncePerfEventsExhausted);
+    observer_thread
+        .join()
+        .expect("couldn't join observer thread")
+}
+
 fn paranoia_level() -> Option<u32> {
     let level = read_string_lossy("/proc/sys/kernel/perf_event_paranoid").ok()?;
     let level = level.trim().parse::<u32>().ok()?;
     Some(level)
 }
 
+/// The directory for `--save-jit-code`, next to the profile: "profile.json.gz"
+/// gets "profile.json.jit". The path is absolute so that it can be found by
+/// the symbolication server, whatever its working directory.
+fn jit_code_dir(recording_props: &RecordingProps) -> Option<PathBuf> {
+    if !recording_props.save_jit_code {
+        return None;
+    }
+    let dir = recording_props.output_file.with_extension("jit");
+    if dir.is_absolute() {
+        return Some(dir);
+    }
+    match std::env::current_dir() {
+        Ok(current_dir) => Some(current_dir.join(dir)),
+        Err(e) => {
+            eprintln!("Not saving JIT code, the current directory is unknown: {e}");
+            None
+        }
+    }
+}
+
+/// The wall-clock time at which CLOCK_MONOTONIC was zero, i.e. the UTC time of
+/// the raw timestamp 0. This is the anchor of the profile's timestamps. The
+/// clocks are read several times, and the readings which are closest together
+/// are used, so that the anchor isn't off by a preemption between the readings.
+fn boot_time_since_unix_epoch() -> Duration {
+    let mut best: Option<(u64, u64)> = None; // (gap, boot time)
+    for _ in 0..5 {
+        let realtime_before = clock_nanos(libc::CLOCK_REALTIME);
+        let mono = clock_nanos(libc::CLOCK_MONOTONIC);
+        let realtime_after = clock_nanos(libc::CLOCK_REALTIME);
+        let gap = realtime_after.saturating_sub(realtime_before);
+        let realtime = realtime_before + gap / 2;
+        let boot_time = realtime.saturating_sub(mono);
+        if best.map_or(true, |(best_gap, _)| gap < best_gap) {
+            best = Some((gap, boot_time));
+        }
+    }
+    Duration::from_nanos(best.map_or(0, |(_, boot_time)| boot_time))
+}
+
 fn make_converter(
     interval: Duration,
     profile_creation_props: ProfileCreationProps,
-) -> Converter<framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>> {
+    jit_code_dir: Option<PathBuf>,
+) -> Converter<UnwinderNative> {
     let interval_nanos = if interval.as_nanos() > 0 {
         interval.as_nanos() as u64
     } else {
@@ -335,26 +714,25 @@ fn make_converter(
     };
     let machine_info = uname::uname().ok();
     let interpretation = EventInterpretation {
-        main_event_attr_index: 0,
+        main_event_attr_index: Some(0),
         main_event_name: "cycles".to_string(),
         sampling_is_time_based: Some(interval_nanos),
+        sample_period_is_nanos: true,
         off_cpu_indicator: Some(OffCpuIndicator::ContextSwitches),
         sched_switch_attr_index: None,
         known_event_indices: HashMap::new(),
         event_names: vec!["cycles".to_string()],
     };
 
-    let mut converter = Converter::<
-        framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>,
-    >::new(
+    let mut converter = Converter::<UnwinderNative>::new(
         &profile_creation_props,
-        ReferenceTimestamp::from_system_time(SystemTime::now()),
+        boot_time_since_unix_epoch(),
         profile_creation_props.profile_name(),
         HashMap::new(),
         machine_info.as_ref().map(|info| info.release.as_str()),
         first_sample_time,
         endian,
-        framehop::CacheNative::new(),
+        CacheNative::new(),
         Vec::new(),
         Vec::new(),
         interpretation,
@@ -364,16 +742,18 @@ fn make_converter(
     if let Ok(os_release) = os_release::OsRelease::new() {
         converter.set_os_name(&os_release.pretty_name);
     }
+    if let Some(jit_code_dir) = jit_code_dir {
+        converter.set_jit_code_dir(jit_code_dir);
+    }
     converter
 }
 
 fn init_profiler(
     interval: Duration,
+    tracing_events: TracingEvents,
     pid: u32,
     attach_mode: AttachMode,
-    converter: &mut Converter<
-        framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>,
-    >,
+    converter: &mut Converter<UnwinderNative>,
 ) -> PerfGroup {
     let interval_nanos = if interval.as_nanos() > 0 {
         interval.as_nanos() as u64
@@ -391,6 +771,7 @@ fn init_profiler(
         stack_size,
         EventSource::HwCpuCycles,
         regs_mask,
+        tracing_events.clone(),
         attach_mode,
     );
 
@@ -428,6 +809,7 @@ fn init_profiler(
                 stack_size,
                 EventSource::SwCpuClock,
                 regs_mask,
+                tracing_events,
                 attach_mode,
             );
             match perf {
@@ -440,6 +822,129 @@ fn init_profiler(
         }
     };
 
+    register_process_from_procfs(pid, converter);
+
+    // eprintln!("Enabling perf events...");
+    match attach_mode {
+        AttachMode::StopAttachEnableResume => perf.enable(),
+        AttachMode::AttachWithEnableOnExec => {
+            // The perf event will get enabled automatically once the forked child process execs.
+        }
+    }
+
+    perf
+}
+
+/// The events for `--off-cpu`, `--uprobe`, `--usdt`, `--kprobe` and
+/// `--tracepoint`, and the probes for the converter, by probe index. The
+/// probes are resolved before the recording starts, so that a misspelled
+/// probe is an error right away.
+fn tracing_events(recording_props: &RecordingProps) -> (TracingEvents, Vec<RecordedProbe>) {
+    let sched_switch_tracepoint = if recording_props.off_cpu {
+        sched_switch_tracepoint_id()
+    } else {
+        None
+    };
+    let mut probes = Vec::new();
+    let mut recorded_probes = Vec::new();
+    if !recording_props.probes.is_empty() {
+        let pmu_type = pmu_type_or_exit("uprobe");
+        for spec in &recording_props.probes {
+            let location = match resolve_probe(spec) {
+                Ok(location) => location,
+                Err(error) => {
+                    eprintln!("Error: {error}");
+                    std::process::exit(1)
+                }
+            };
+            recorded_probes.push(RecordedProbe::User {
+                name: spec.marker_name(),
+                binary: location.path.to_string_lossy().into_owned(),
+            });
+            probes.push(Probe::Uprobe {
+                pmu_type,
+                path: CString::new(location.path.as_os_str().as_bytes()).unwrap(),
+                offset: location.offset,
+                semaphore_offset: location.semaphore_offset,
+            });
+        }
+    }
+    if !recording_props.kprobes.is_empty() {
+        let pmu_type = pmu_type_or_exit("kprobe");
+        for function in &recording_props.kprobes {
+            let Ok(function_cstring) = CString::new(function.as_str()) else {
+                eprintln!("Error: Invalid kernel function name {function:?}");
+                std::process::exit(1)
+            };
+            recorded_probes.push(RecordedProbe::Kernel {
+                name: function.clone(),
+                format: None,
+            });
+            probes.push(Probe::Kprobe {
+                pmu_type,
+                function: function_cstring,
+            });
+        }
+    }
+    for pattern in &recording_props.tracepoints {
+        let tracepoints = match find_tracepoints(pattern) {
+            Ok(tracepoints) => tracepoints,
+            Err(error) => {
+                eprintln!("Error: {error}");
+                std::process::exit(1)
+            }
+        };
+        for tracepoint in tracepoints {
+            let id = tracepoint
+                .format
+                .id
+                .expect("tracepoints are only found with an id");
+            recorded_probes.push(RecordedProbe::Kernel {
+                name: tracepoint.name,
+                format: Some(tracepoint.format),
+            });
+            probes.push(Probe::Tracepoint(id));
+        }
+    }
+    let tracing_events = TracingEvents {
+        sched_switch_tracepoint,
+        probes,
+        probe_stacks: recording_props.probe_stacks,
+    };
+    (tracing_events, recorded_probes)
+}
+
+/// The type of the kernel's uprobe or kprobe PMU, or exits if the kernel
+/// doesn't support them.
+fn pmu_type_or_exit(pmu: &str) -> u32 {
+    let path = format!("/sys/bus/event_source/devices/{pmu}/type");
+    match read_string_lossy(&path)
+        .ok()
+        .and_then(|t| t.trim().parse().ok())
+    {
+        Some(pmu_type) => pmu_type,
+        None => {
+            eprintln!("Error: This kernel doesn't support {pmu} perf events; {path} is missing.");
+            std::process::exit(1)
+        }
+    }
+}
+
+/// The id of the sched:sched_switch tracepoint, for `--off-cpu`. Its samples
+/// have the stacks at which threads block. tracefs is often only readable by
+/// root; without it, off-CPU time isn't attributed to any stack.
+fn sched_switch_tracepoint_id() -> Option<u64> {
+    let id = tracepoint_id("sched", "sched_switch");
+    if id.is_none() {
+        eprintln!("Not recording off-CPU stacks: couldn't read the id of the sched:sched_switch tracepoint from /sys/kernel/tracing.");
+        eprintln!("{TRACEFS_PERMISSION_HINT}");
+    }
+    id
+}
+
+/// Tells the converter about an existing process: its name and command line,
+/// its threads, and the memory mappings of its libraries.
+fn register_process_from_procfs(pid: u32, converter: &mut Converter<UnwinderNative>) {
     let (exe_name, cmdline) = get_process_cmdline(pid).expect("Couldn't read process cmdline");
     let comm_data = std::fs::read(format!("/proc/{pid}/comm")).expect("Couldn't read process comm");
     let length = memchr::memchr(b'\0', &comm_data).unwrap_or(comm_data.len());
@@ -513,34 +1018,86 @@ fn init_profiler(
             0,
         );
     }
+}
 
-    // eprintln!("Enabling perf events...");
-    match attach_mode {
-        AttachMode::StopAttachEnableResume => perf.enable(),
-        AttachMode::AttachWithEnableOnExec => {
-            // The perf event will get enabled automatically once the forked child process execs.
-        }
+/// Starts recording another process, and returns whether that worked. Existing
+/// processes are recorded right away; launched processes once they exec.
+fn open_another_process(
+    perf: &mut PerfGroup,
+    converter: &mut Converter<UnwinderNative>,
+    pid: u32,
+    attach_mode: AttachMode,
+) -> bool {
+    if let Err(error) = perf.open_process(pid, attach_mode) {
+        eprintln!("Failed to start profiling on subsequent process: {error}");
+        return false;
     }
-
-    perf
+    if attach_mode == AttachMode::StopAttachEnableResume {
+        register_process_from_procfs(pid, converter);
+        perf.enable();
+    }
+    true
 }
 
 enum SamplerRequest {
     StartProfilingAnotherProcess(u32, AttachMode),
+    /// Attach to a new instance of the recorded process, and mark the restart.
+    StartProfilingRestartedProcess {
+        pid: u32,
+        number: u32,
+        previous_pid: u32,
+    },
     StopProfilingOncePerfEventsExhausted,
 }
 
+/// Handles a request from the main thread, and replies whether attaching to
+/// the process succeeded. Returns whether a restarted process was attached to.
+fn handle_sampler_request(
+    request: SamplerRequest,
+    perf: &mut PerfGroup,
+    converter: &mut Converter<UnwinderNative>,
+    reply_sender: &Sender<bool>,
+    should_stop_profiling_once_perf_events_exhausted: &mut bool,
+) -> bool {
+    match request {
+        SamplerRequest::StartProfilingAnotherProcess(another_pid, attach_mode) => {
+            let succeeded = open_another_process(perf, converter, another_pid, attach_mode);
+            reply_sender.send(succeeded).unwrap();
+            false
+        }
+        SamplerRequest::StartProfilingRestartedProcess {
+            pid,
+            number,
+            previous_pid,
+        } => {
+            let succeeded =
+                open_another_process(perf, converter, pid, AttachMode::StopAttachEnableResume);
+            if succeeded {
+                let now_mono = clock_nanos(libc::CLOCK_MONOTONIC);
+                converter.handle_restart(pid as i32, now_mono, number, previous_pid);
+            }
+            reply_sender.send(succeeded).unwrap();
+            succeeded
+        }
+        SamplerRequest::StopProfilingOncePerfEventsExhausted => {
+            *should_stop_profiling_once_perf_events_exhausted = true;
+            false
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 fn run_profiler(
     mut perf: PerfGroup,
-    mut converter: Converter<
-        framehop::UnwinderNative<MmapRangeOrVec, framehop::MayAllocateDuringUnwind>,
-    >,
-    _time_limit: Option<Duration>,
+    mut converter: Converter<UnwinderNative>,
+    time_limit: Option<Duration>,
+    interval_jitter: IntervalJitter,
     more_processes_request_receiver: Receiver<SamplerRequest>,
     more_processes_reply_sender: Sender<bool>,
     mut stop_receiver: oneshot::Receiver<()>,
+    output_receiver: Receiver<(u64, u32, LaunchedProcessOutput)>,
     mut initial_exec_name_and_cmdline: Option<(String, Vec<String>)>,
+    mut ring_buffer: Option<RingBuffer>,
 ) -> Profile {
     // eprintln!("Running...");
 
@@ -548,50 +1105,49 @@ fn run_profiler(
     let mut pending_lost_events = 0;
     let mut total_lost_events = 0;
     let mut last_timestamp = 0;
+    let deadline = time_limit.map(|time_limit| Instant::now() + time_limit);
+    let mut next_jitter_time = Instant::now();
+    let mut next_open_fds_poll = Instant::now();
+    let mut output_budget = OutputCaptureBudget::default();
+    let mut sleep_detector = SleepDetector::new(
+        clock_nanos(libc::CLOCK_MONOTONIC),
+        clock_nanos(libc::CLOCK_BOOTTIME),
+    );
     loop {
         if stop_receiver.try_recv().is_ok() {
             break;
         }
 
-        match more_processes_request_receiver.try_recv() {
-            Ok(SamplerRequest::StartProfilingAnotherProcess(another_pid, attach_mode)) => {
-                match perf.open_process(another_pid, attach_mode) {
-                    Ok(_) => {
-                        more_processes_reply_sender.send(true).unwrap();
-                    }
-                    Err(error) => {
-                        eprintln!("Failed to start profiling on subsequent process: {error}");
-                        more_processes_reply_sender.send(false).unwrap();
-                    }
-                }
-            }
-            Ok(SamplerRequest::StopProfilingOncePerfEventsExhausted) => {
-                should_stop_profiling_once_perf_events_exhausted = true;
-            }
-            Err(_) => {
-                // No requests pending at the moment.
-            }
+        let now_mono = clock_nanos(libc::CLOCK_MONOTONIC);
+        if let Some(slept) = sleep_detector.check(now_mono, clock_nanos(libc::CLOCK_BOOTTIME)) {
+            converter.handle_system_sleep(now_mono, slept);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break;
+        }
+
+        // With --ring-buffer-seconds, a restart of the recorded process saves
+        // the window up to the restart, like a crash would.
+        let mut restarted = false;
+        if let Ok(request) = more_processes_request_receiver.try_recv() {
+            restarted |= handle_sampler_request(
+                request,
+                &mut perf,
+                &mut converter,
+                &more_processes_reply_sender,
+                &mut should_stop_profiling_once_perf_events_exhausted,
+            );
         }
 
         if perf.is_empty() && !should_stop_profiling_once_perf_events_exhausted {
-            match more_processes_request_receiver.recv() {
-                Ok(SamplerRequest::StartProfilingAnotherProcess(another_pid, attach_mode)) => {
-                    match perf.open_process(another_pid, attach_mode) {
-                        Ok(_) => {
-                            more_processes_reply_sender.send(true).unwrap();
-                        }
-                        Err(error) => {
-                            eprintln!("Failed to start profiling on subsequent process: {error}");
-                            more_processes_reply_sender.send(false).unwrap();
-                        }
-                    }
-                }
-                Ok(SamplerRequest::StopProfilingOncePerfEventsExhausted) => {
-                    should_stop_profiling_once_perf_events_exhausted = true;
-                }
-                Err(_) => {
-                    // No requests pending at the moment.
-                }
+            if let Ok(request) = more_processes_request_receiver.recv() {
+                restarted |= handle_sampler_request(
+                    request,
+                    &mut perf,
+                    &mut converter,
+                    &more_processes_reply_sender,
+                    &mut should_stop_profiling_once_perf_events_exhausted,
+                );
             }
         }
 
@@ -599,6 +1155,10 @@ fn run_profiler(
             break;
         }
 
+        // Add the output before the events so that the panicking thread's
+        // EXIT record hasn't been processed yet.
+        add_launched_process_output(&mut converter, &mut output_budget, &output_receiver);
+
         perf.consume_events(&mut |event_ref| {
             let record = event_ref.get();
             let parsed_record = record.parse().unwrap();
@@ -614,13 +1174,17 @@ fn run_profiler(
             }
 
             match parsed_record {
-                EventRecord::Sample(e) => {
-                    converter.handle_main_event_sample::<ConvertRegsNative>(&e);
-                    /*
-                    } else if interpretation.sched_switch_attr_index == Some(attr_index) {
-                        converter.handle_sched_switch_sample::<C>(e);
-                    }*/
-                }
+                EventRecord::Sample(e) => match event_ref.event_source() {
+                    EventSource::Tracepoint(_) => {
+                        converter.handle_sched_switch_sample::<ConvertRegsNative>(&e);
+                    }
+                    EventSource::Probe(index) => {
+                        converter.handle_probe_sample::<ConvertRegsNative>(&e, index);
+                    }
+                    EventSource::HwCpuCycles | EventSource::SwCpuClock => {
+                        converter.handle_main_event_sample::<ConvertRegsNative>(&e);
+                    }
+                },
                 EventRecord::Fork(e) => {
                     converter.handle_fork(e);
                 }
@@ -677,14 +1241,210 @@ fn run_profiler(
             }
         });
 
+        // The kernel samples with a fixed period, so pick a new random
+        // period each time we wake up to read events, but not more often
+        // than once per sampling interval.
+        if interval_jitter.is_enabled() && Instant::now() >= next_jitter_time {
+            let next_interval = interval_jitter.next_interval();
+            perf.set_frequency((1_000_000_000 / next_interval.as_nanos().max(1)) as u32);
+            next_jitter_time = Instant::now() + next_interval;
+        }
+
+        if Instant::now() >= next_open_fds_poll {
+            let now_mono = clock_nanos(libc::CLOCK_MONOTONIC);
+            for pid in converter.live_pids() {
+                if let Some(count) = count_open_fds(pid) {
+                    converter.handle_open_fd_count(pid, now_mono, count);
+                }
+            }
+            next_open_fds_poll = Instant::now() + OPEN_HANDLES_POLL_INTERVAL;
+        }
+
+        if let Some(ring_buffer) = &mut ring_buffer {
+            let now_mono = clock_nanos(libc::CLOCK_MONOTONIC);
+            if let Some(threshold) = ring_buffer.discard_threshold(now_mono) {
+                converter.discard_samples_before(threshold);
+            }
+            if ring_buffer.take_dump_request() || restarted {
+                save_ring_buffer_dump(&converter, ring_buffer, &interval_jitter);
+            }
+        }
+
         perf.wait();
     }
 
+    add_launched_process_output(&mut converter, &mut output_budget, &output_receiver);
+    if output_budget.dropped_line_count() > 0 {
+        eprintln!(
+            "Dropped {} output lines because the captured output exceeded {}MB.",
+            output_budget.dropped_line_count(),
+            MAX_TOTAL_BYTES / 1024 / 1024
+        );
+    }
+
     if total_lost_events > 0 {
         eprintln!("Lost {total_lost_events} events.");
     }
 
-    converter.finish()
+    let mut profile = converter.finish();
+    interval_jitter.add_to_profile_meta(&mut profile);
+    add_core_types_to_profile_meta(&mut profile);
+    if let Some(ring_buffer) = &ring_buffer {
+        ring_buffer.add_to_profile_meta(&mut profile);
+    }
+    profile
+}
+
+/// Saves the samples in the ring buffer. The converter keeps them, so that
+/// the next dump and the final profile also contain them while they're in the
+/// window.
+fn save_ring_buffer_dump(
+    converter: &Converter<UnwinderNative>,
+    ring_buffer: &RingBuffer,
+    interval_jitter: &IntervalJitter,
+) {
+    let mut profile = converter.snapshot();
+    interval_jitter.add_to_profile_meta(&mut profile);
+    add_core_types_to_profile_meta(&mut profile);
+    ring_buffer.add_to_profile_meta(&mut profile);
+    ring_buffer.save_dump(&profile);
+}
+
+/// What the threads which read the launched command's output send to the
+/// observer thread.
+enum LaunchedProcessOutput {
+    Line(OutputStream, String),
+    Panic(PanicMessage),
+    Test(TestRun),
+}
+
+/// What to look for in one of the launched command's output streams.
+#[derive(Debug, Clone, Copy)]
+struct OutputParsing {
+    lines: bool,
+    panics: bool,
+    tests: bool,
+}
+
+/// Starts a thread for each captured output stream of the launched process.
+fn spawn_output_readers(
+    process: &mut SuspendedLaunchedProcess,
+    recording_props: &RecordingProps,
+    output_sender: &Sender<(u64, u32, LaunchedProcessOutput)>,
+) {
+    let pid = process.pid();
+    if let Some(stdout) = process.take_stdout() {
+        let parsing = OutputParsing {
+            lines: recording_props.capture_output,
+            panics: false,
+            tests: recording_props.test_markers,
+        };
+        spawn_output_reader(
+            stdout,
+            pid,
+            OutputStream::Stdout,
+            parsing,
+            output_sender.clone(),
+        );
+    }
+    if let Some(stderr) = process.take_stderr() {
+        let parsing = OutputParsing {
+            lines: recording_props.capture_output,
+            panics: recording_props.panic_markers,
+            tests: false,
+        };
+        spawn_output_reader(
+            stderr,
+            pid,
+            OutputStream::Stderr,
+            parsing,
+            output_sender.clone(),
+        );
+    }
+}
+
+/// Copies one of the launched command's output streams to the same stream of
+/// our own, line by line, and sends the lines, panic messages and finished
+/// tests in it to `output_sender`, timestamped when they were read.
+fn spawn_output_reader(
+    fd: OwnedFd,
+    pid: u32,
+    stream: OutputStream,
+    parsing: OutputParsing,
+    output_sender: Sender<(u64, u32, LaunchedProcessOutput)>,
+) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(File::from(fd));
+        let mut panic_parser = PanicMessageParser::default();
+        let mut test_parser = TestOutputParser::default();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            let _ = match stream {
+                OutputStream::Stdout => std::io::stdout().write_all(&line),
+                OutputStream::Stderr => std::io::stderr().write_all(&line),
+            };
+            let timestamp_mono = clock_nanos(libc::CLOCK_MONOTONIC);
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\n', '\r']);
+            if parsing.panics {
+                if let Some(panic) = panic_parser.push_line(text) {
+                    let output = LaunchedProcessOutput::Panic(panic);
+                    let _ = output_sender.send((timestamp_mono, pid, output));
+                }
+            }
+            if parsing.tests {
+                if let Some(test) = test_parser.push_line(text, timestamp_mono) {
+                    let output = LaunchedProcessOutput::Test(test);
+                    let _ = output_sender.send((timestamp_mono, pid, output));
+                }
+            }
+            if parsing.lines {
+                let output = LaunchedProcessOutput::Line(stream, text.to_string());
+                let _ = output_sender.send((timestamp_mono, pid, output));
+            }
+        }
+        if let Some(panic) = panic_parser.finish() {
+            let output = LaunchedProcessOutput::Panic(panic);
+            let _ = output_sender.send((clock_nanos(libc::CLOCK_MONOTONIC), pid, output));
+        }
+    });
+}
+
+fn add_launched_process_output(
+    converter: &mut Converter<UnwinderNative>,
+    output_budget: &mut OutputCaptureBudget,
+    output_receiver: &Receiver<(u64, u32, LaunchedProcessOutput)>,
+) {
+    for (timestamp_mono, pid, output) in output_receiver.try_iter() {
+        match output {
+            LaunchedProcessOutput::Line(stream, text) => {
+                if let Some(text) = output_budget.take_line(&text) {
+                    converter.handle_output_line(pid as i32, timestamp_mono, stream, &text);
+                }
+            }
+            LaunchedProcessOutput::Panic(panic) => {
+                converter.handle_panic_message(pid as i32, timestamp_mono, &panic);
+            }
+            LaunchedProcessOutput::Test(test) => {
+                converter.handle_test_run(pid as i32, &test);
+            }
+        }
+    }
+}
+
+/// Returns the current time of `clock` in nanoseconds.
+fn clock_nanos(clock: libc::clockid_t) -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
 }
 
 pub fn read_string_lossy<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
@@ -692,6 +1452,13 @@ pub fn read_string_lossy<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
     Ok(String::from_utf8_lossy(&data).into_owned())
 }
 
+/// Counts the entries of /proc/{pid}/fd. Returns None if the process is gone
+/// or belongs to a different user.
+fn count_open_fds(pid: i32) -> Option<u64> {
+    let entries = std::fs::read_dir(format!("/proc/{pid}/fd")).ok()?;
+    Some(entries.count() as u64)
+}
+
 fn get_process_cmdline(pid: u32) -> std::io::Result<(String, Vec<String>)> {
     let path = format!("/proc/{pid}/cmdline");
     let cmdline_bytes = std::fs::read(&path)?;
@@ -0,0 +1,116 @@
+//! `samply record --ring-buffer-seconds`, which only keeps the samples of the
+//! last N seconds in memory, like a flight recorder.
+//!
+//! The profile is saved when the recording ends, e.g. because the recorded
+//! process crashed. In addition, samply saves a dump of the last N seconds
+//! whenever it receives SIGUSR2, and when a process recorded with
+//! `--follow-restarts` or `--systemd-unit` is restarted, and then keeps
+//! recording.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use fxprof_processed_profile::Profile;
+
+use crate::shared::prop_types::{ProfileCreationProps, RecordingProps};
+use crate::shared::save_profile::{save_profile_with_post_processing, PostProcessing};
+
+/// How often the samples which have left the window are dropped.
+const DISCARD_INTERVAL: Duration = Duration::from_secs(1);
+
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigusr2(_signal: libc::c_int) {
+    DUMP_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+pub struct RingBuffer {
+    window: Duration,
+    output: PathBuf,
+    post_processing: PostProcessing,
+    next_discard: Instant,
+}
+
+impl RingBuffer {
+    /// Returns `None` without `--ring-buffer-seconds`. Otherwise, installs
+    /// the SIGUSR2 handler.
+    pub fn from_props(
+        recording_props: &RecordingProps,
+        profile_creation_props: &ProfileCreationProps,
+    ) -> Option<Self> {
+        let window = recording_props.ring_buffer?;
+        unsafe {
+            libc::signal(
+                libc::SIGUSR2,
+                handle_sigusr2 as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+        eprintln!(
+            "Keeping the samples of the last {}s. Run `kill -USR2 {}` to save them.",
+            window.as_secs_f64(),
+            std::process::id()
+        );
+        Some(Self {
+            window,
+            output: recording_props.output_file.clone(),
+            post_processing: PostProcessing::from_props(profile_creation_props),
+            next_discard: Instant::now() + DISCARD_INTERVAL,
+        })
+    }
+
+    /// The time before which samples should be dropped, because they have
+    /// left the window. Returns `None` if samples were dropped less than
+    /// [`DISCARD_INTERVAL`] ago.
+    pub fn discard_threshold(&mut self, now_mono: u64) -> Option<u64> {
+        if Instant::now() < self.next_discard {
+            return None;
+        }
+        self.next_discard = Instant::now() + DISCARD_INTERVAL;
+        Some(now_mono.saturating_sub(self.window.as_nanos() as u64))
+    }
+
+    /// Whether samply has received SIGUSR2 since the last call.
+    pub fn take_dump_request(&self) -> bool {
+        DUMP_REQUESTED.swap(false, Ordering::SeqCst)
+    }
+
+    /// Saves a dump of the window next to the output file.
+    pub fn save_dump(&self, profile: &Profile) {
+        let path = dump_path(&self.output, SystemTime::now());
+        match save_profile_with_post_processing(profile, &path, &self.post_processing) {
+            Ok(()) => eprintln!(
+                "Saved the last {}s to {}.",
+                self.window.as_secs_f64(),
+                path.display()
+            ),
+            Err(err) => eprintln!("Could not save {}: {err}", path.display()),
+        }
+    }
+
+    pub fn add_to_profile_meta(&self, profile: &mut Profile) {
+        profile.add_extra_info(
+            "Sampling",
+            "Ring buffer",
+            &format!("Last {}s of samples", self.window.as_secs_f64()),
+        );
+    }
+}
+
+/// The path of a dump, which is the output path with the time of the dump
+/// added to the file name, e.g. profile-2023-11-14T22-13-20Z.json.gz for
+/// profile.json.gz.
+fn dump_path(output: &Path, time: SystemTime) -> PathBuf {
+    let timestamp = humantime::format_rfc3339_seconds(time)
+        .to_string()
+        .replace(':', "-");
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let dump_file_name = match file_name.split_once('.') {
+        Some((stem, extensions)) => format!("{stem}-{timestamp}.{extensions}"),
+        None => format!("{file_name}-{timestamp}"),
+    };
+    output.with_file_name(dump_file_name)
+}
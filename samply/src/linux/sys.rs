@@ -222,6 +222,7 @@ mod ioctl {
         pub const IOC_SIZEBITS: c_ulong = 14;
         pub const IOC_DIRBITS: c_ulong = 2;
         pub const IOC_NONE: c_ulong = 0;
+        pub const IOC_WRITE: c_ulong = 1;
     }
 
     #[cfg(any(
@@ -236,6 +237,7 @@ mod ioctl {
         pub const IOC_SIZEBITS: c_ulong = 13;
         pub const IOC_DIRBITS: c_ulong = 3;
         pub const IOC_NONE: c_ulong = 1;
+        pub const IOC_WRITE: c_ulong = 4;
     }
 
     pub use self::arch::*;
@@ -265,6 +267,7 @@ macro_rules! io {
 
 pub const PERF_EVENT_IOC_ENABLE: c_ulong = io!(b'$', 0);
 pub const PERF_EVENT_IOC_DISABLE: c_ulong = io!(b'$', 1);
+pub const PERF_EVENT_IOC_PERIOD: c_ulong = ioc!(ioctl::IOC_WRITE, b'$', 4, 8);
 
 #[repr(C)]
 pub struct PerfEventAttr {
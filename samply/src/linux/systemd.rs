@@ -0,0 +1,155 @@
+//! Finding the processes of a systemd unit, for `samply record --systemd-unit`.
+//!
+//! systemd puts all processes of a unit into the unit's cgroup, so the cgroup
+//! is polled during the recording, and new processes which aren't children
+//! of recorded processes are attached to. Children of recorded processes are
+//! recorded anyway, because the perf events are inherited.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use super::follow::FollowedProcesses;
+
+/// The cgroup filesystem, unified (v2) or with the "name=systemd" hierarchy
+/// (v1), in this order.
+const CGROUP_ROOTS: [&str; 3] = [
+    "/sys/fs/cgroup",
+    "/sys/fs/cgroup/unified",
+    "/sys/fs/cgroup/systemd",
+];
+
+pub struct UnitCgroup {
+    unit: String,
+    /// The directory of the unit's cgroup. `None` until the unit has been
+    /// started, and again once the unit has stopped and the cgroup is gone.
+    dir: Option<PathBuf>,
+    /// The processes which are recorded, either because they were attached
+    /// to, or because their parent was.
+    recorded_pids: HashSet<u32>,
+}
+
+impl UnitCgroup {
+    /// How often the cgroup is checked for new processes.
+    const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+    pub fn new(unit: &str) -> Self {
+        Self {
+            unit: unit.to_string(),
+            dir: None,
+            recorded_pids: HashSet::new(),
+        }
+    }
+
+    /// All processes in the unit's cgroup and its child cgroups.
+    fn pids(&mut self) -> Vec<u32> {
+        if let Some(dir) = &self.dir {
+            if dir.exists() {
+                return pids_in_cgroup(dir);
+            }
+        }
+        self.dir = unit_cgroup_dir(&self.unit);
+        match &self.dir {
+            Some(dir) => pids_in_cgroup(dir),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl FollowedProcesses for UnitCgroup {
+    /// The processes which have joined the unit since the last call. Processes
+    /// whose parent is recorded are left out.
+    fn new_pids(&mut self) -> Vec<u32> {
+        let pids = self.pids();
+        // Forget the processes which have quit, so that a reused pid is
+        // attached to again.
+        self.recorded_pids.retain(|pid| pids.contains(pid));
+        let mut new_pids = Vec::new();
+        for &pid in &pids {
+            if self.recorded_pids.contains(&pid) {
+                continue;
+            }
+            let parent_is_recorded =
+                parent_pid(pid).is_some_and(|ppid| self.recorded_pids.contains(&ppid));
+            if !parent_is_recorded {
+                new_pids.push(pid);
+            }
+        }
+        self.recorded_pids.extend(pids);
+        new_pids
+    }
+
+    fn poll_interval(&self) -> Duration {
+        Self::POLL_INTERVAL
+    }
+}
+
+/// Checks that systemd knows the unit, so that a misspelled unit name is an
+/// error rather than a unit which is waited for forever.
+pub fn check_unit_exists(unit: &str) -> Result<(), String> {
+    let load_state = systemctl_show(unit, "LoadState")?;
+    match load_state.as_str() {
+        "not-found" | "" => Err(format!("systemd doesn't know the unit {unit}.")),
+        _ => Ok(()),
+    }
+}
+
+/// The value of a property of the unit, from `systemctl show`.
+fn systemctl_show(unit: &str, property: &str) -> Result<String, String> {
+    let output = Command::new("systemctl")
+        .args(["show", &format!("--property={property}"), "--value", unit])
+        .output()
+        .map_err(|err| format!("Couldn't run systemctl: {err}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("systemctl failed: {}", stderr.trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Asks systemd for the cgroup of the unit, e.g. "/system.slice/foo.service",
+/// and finds its directory. Returns `None` if the unit isn't running.
+fn unit_cgroup_dir(unit: &str) -> Option<PathBuf> {
+    let control_group = systemctl_show(unit, "ControlGroup").ok()?;
+    if control_group.is_empty() {
+        return None;
+    }
+    CGROUP_ROOTS.iter().find_map(|root| {
+        let dir = Path::new(root).join(control_group.trim_start_matches('/'));
+        dir.join("cgroup.procs").exists().then_some(dir)
+    })
+}
+
+fn pids_in_cgroup(dir: &Path) -> Vec<u32> {
+    let mut pids = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        if let Ok(procs) = std::fs::read_to_string(dir.join("cgroup.procs")) {
+            pids.extend(
+                procs
+                    .lines()
+                    .filter_map(|line| line.trim().parse::<u32>().ok()),
+            );
+        }
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                dirs.push(entry.path());
+            }
+        }
+    }
+    pids
+}
+
+/// The parent pid from /proc/<pid>/stat. The process name comes before it,
+/// in parentheses, and can contain spaces and parentheses itself.
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (_, after_name) = stat.rsplit_once(')')?;
+    let mut fields = after_name.split_whitespace();
+    let _state = fields.next()?;
+    fields.next()?.parse().ok()
+}
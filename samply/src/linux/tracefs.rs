@@ -0,0 +1,94 @@
+//! Finding tracepoints in tracefs, for `--off-cpu` and `--tracepoint`.
+//!
+//! Each tracepoint has a directory events/CATEGORY/NAME, with its id, which is
+//! the config of its perf events, and its format, which describes the fields
+//! in the raw data of its samples. tracefs is often only readable by root.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::linux_shared::TracepointFormat;
+use crate::shared::name_pattern::matches_pattern;
+
+const TRACEFS_DIRS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+pub const TRACEFS_PERMISSION_HINT: &str = "Run samply as root, or make tracefs readable, e.g. with `sudo chmod -R a+rX /sys/kernel/tracing`.";
+
+/// A tracepoint which matched a `--tracepoint` pattern.
+pub struct Tracepoint {
+    /// "category:name", e.g. "sched:sched_wakeup".
+    pub name: String,
+    pub format: TracepointFormat,
+}
+
+/// The events directory of the first readable tracefs mount.
+fn events_dir() -> Option<PathBuf> {
+    TRACEFS_DIRS
+        .iter()
+        .map(|dir| PathBuf::from(dir).join("events"))
+        .find(|dir| fs::read_dir(dir).is_ok())
+}
+
+/// The id of a tracepoint, if tracefs is readable and the tracepoint exists.
+pub fn tracepoint_id(category: &str, name: &str) -> Option<u64> {
+    let path = events_dir()?.join(category).join(name).join("id");
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// The tracepoints which match a `CATEGORY:NAME` pattern, in which `*` stands
+/// for any number of characters in either part, e.g. `syscalls:sys_enter_*`.
+pub fn find_tracepoints(pattern: &str) -> Result<Vec<Tracepoint>, String> {
+    let Some((category_pattern, name_pattern)) = pattern.split_once(':') else {
+        return Err(format!(
+            "Invalid tracepoint {pattern:?}; tracepoints are given as CATEGORY:NAME, e.g. sched:sched_wakeup."
+        ));
+    };
+    let Some(events_dir) = events_dir() else {
+        return Err(format!(
+            "Couldn't read the tracepoints from /sys/kernel/tracing. {TRACEFS_PERMISSION_HINT}"
+        ));
+    };
+    let mut tracepoints = Vec::new();
+    for category in subdirectory_names(&events_dir) {
+        if !matches_pattern(category_pattern, &category) {
+            continue;
+        }
+        let category_dir = events_dir.join(&category);
+        for name in subdirectory_names(&category_dir) {
+            if !matches_pattern(name_pattern, &name) {
+                continue;
+            }
+            let Ok(format) = fs::read_to_string(category_dir.join(&name).join("format")) else {
+                continue;
+            };
+            let format = TracepointFormat::parse(&format);
+            if format.id.is_some() {
+                tracepoints.push(Tracepoint {
+                    name: format!("{category}:{name}"),
+                    format,
+                });
+            }
+        }
+    }
+    if tracepoints.is_empty() {
+        return Err(format!(
+            "No tracepoint matches {pattern:?}. The available tracepoints are listed in {}.",
+            events_dir.with_file_name("available_events").display()
+        ));
+    }
+    Ok(tracepoints)
+}
+
+/// The names of the subdirectories of `dir`, sorted.
+fn subdirectory_names(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
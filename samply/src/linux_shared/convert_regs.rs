@@ -2,15 +2,31 @@ use framehop::aarch64::UnwindRegsAarch64;
 use framehop::x86_64::UnwindRegsX86_64;
 use linux_perf_data::linux_perf_event_reader;
 use linux_perf_event_reader::constants::{
-    PERF_REG_ARM64_LR, PERF_REG_ARM64_PC, PERF_REG_ARM64_SP, PERF_REG_ARM64_X29, PERF_REG_X86_BP,
+    PERF_REG_ARM64_LR, PERF_REG_ARM64_PC, PERF_REG_ARM64_SP, PERF_REG_ARM64_X11,
+    PERF_REG_ARM64_X29, PERF_REG_ARM_FP, PERF_REG_ARM_PC, PERF_REG_ARM_SP, PERF_REG_X86_BP,
     PERF_REG_X86_IP, PERF_REG_X86_SP,
 };
 use linux_perf_event_reader::Regs;
 
+use super::frame_pointer_unwinder::{UnwindRegsArm, UnwindRegsRiscv64};
+
+// From arch/riscv/include/uapi/asm/perf_regs.h in the Linux kernel.
+const PERF_REG_RISCV_PC: u64 = 0;
+const PERF_REG_RISCV_SP: u64 = 2;
+const PERF_REG_RISCV_S0: u64 = 8;
+
 pub trait ConvertRegs {
     type UnwindRegs;
     fn convert_regs(regs: &Regs) -> (u64, u64, Self::UnwindRegs);
 
+    /// The frame pointer of a 32-bit process running on this 64-bit
+    /// architecture, or `None` if the architecture can't run 32-bit code.
+    /// The pc and sp of such processes are in the same registers as for
+    /// 64-bit processes.
+    fn compat_frame_pointer(_regs: &Regs) -> Option<u64> {
+        None
+    }
+
     #[allow(unused)]
     fn regs_mask() -> u64;
 }
@@ -26,6 +42,11 @@ impl ConvertRegs for ConvertRegsX86_64 {
         (ip, sp, regs)
     }
 
+    /// ebp, in the lower half of rbp.
+    fn compat_frame_pointer(regs: &Regs) -> Option<u64> {
+        regs.get(PERF_REG_X86_BP)
+    }
+
     fn regs_mask() -> u64 {
         (1 << PERF_REG_X86_IP) | (1 << PERF_REG_X86_SP) | (1 << PERF_REG_X86_BP)
     }
@@ -43,10 +64,51 @@ impl ConvertRegs for ConvertRegsAarch64 {
         (ip, sp, regs)
     }
 
+    /// AArch32 code keeps its frame pointer in r11, which is x11. The kernel
+    /// reports the AArch32 sp and lr for the AArch64 sp and lr registers.
+    fn compat_frame_pointer(regs: &Regs) -> Option<u64> {
+        regs.get(PERF_REG_ARM64_X11)
+    }
+
     fn regs_mask() -> u64 {
         (1 << PERF_REG_ARM64_PC)
             | (1 << PERF_REG_ARM64_LR)
             | (1 << PERF_REG_ARM64_SP)
+            | (1 << PERF_REG_ARM64_X11)
             | (1 << PERF_REG_ARM64_X29)
     }
 }
+
+pub struct ConvertRegsRiscv64;
+impl ConvertRegs for ConvertRegsRiscv64 {
+    type UnwindRegs = UnwindRegsRiscv64;
+    fn convert_regs(regs: &Regs) -> (u64, u64, UnwindRegsRiscv64) {
+        let ip = regs.get(PERF_REG_RISCV_PC).unwrap();
+        let sp = regs.get(PERF_REG_RISCV_SP).unwrap();
+        let fp = regs.get(PERF_REG_RISCV_S0).unwrap();
+        let regs = UnwindRegsRiscv64::new(sp, fp);
+        (ip, sp, regs)
+    }
+
+    fn regs_mask() -> u64 {
+        (1 << PERF_REG_RISCV_PC) | (1 << PERF_REG_RISCV_SP) | (1 << PERF_REG_RISCV_S0)
+    }
+}
+
+/// 32-bit ARM, with r11 as the frame pointer. Thumb code uses r7 instead, and
+/// isn't supported.
+pub struct ConvertRegsArm;
+impl ConvertRegs for ConvertRegsArm {
+    type UnwindRegs = UnwindRegsArm;
+    fn convert_regs(regs: &Regs) -> (u64, u64, UnwindRegsArm) {
+        let ip = regs.get(PERF_REG_ARM_PC).unwrap();
+        let sp = regs.get(PERF_REG_ARM_SP).unwrap();
+        let fp = regs.get(PERF_REG_ARM_FP).unwrap();
+        let regs = UnwindRegsArm::new(sp, fp);
+        (ip, sp, regs)
+    }
+
+    fn regs_mask() -> u64 {
+        (1 << PERF_REG_ARM_PC) | (1 << PERF_REG_ARM_SP) | (1 << PERF_REG_ARM_FP)
+    }
+}
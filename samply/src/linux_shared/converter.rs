@@ -1,16 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use byteorder::LittleEndian;
 use debugid::DebugId;
-use framehop::{ExplicitModuleSectionInfo, FrameAddress, Module, Unwinder};
+use framehop::{ExplicitModuleSectionInfo, FrameAddress, Module, UnwindIterator, Unwinder};
 use fxprof_processed_profile::{
     Category, CategoryColor, CategoryHandle, CpuDelta, FrameFlags, LibraryHandle, LibraryInfo,
     MarkerFieldFlags, MarkerFieldFormat, MarkerTiming, PlatformSpecificReferenceTimestamp, Profile,
-    ReferenceTimestamp, SamplingInterval, StaticSchemaMarker, StaticSchemaMarkerField,
-    StringHandle, SubcategoryHandle, SymbolTable, ThreadHandle,
+    SamplingInterval, StaticSchemaMarker, StaticSchemaMarkerField, StringHandle, SubcategoryHandle,
+    SymbolTable, ThreadHandle,
 };
 use linux_perf_data::linux_perf_event_reader::TaskWasPreempted;
 use linux_perf_data::simpleperf_dso_type::{DSO_DEX_FILE, DSO_KERNEL, DSO_KERNEL_MODULE};
@@ -20,37 +21,53 @@ use linux_perf_data::{
 };
 use linux_perf_event_reader::constants::PERF_CONTEXT_MAX;
 use linux_perf_event_reader::{
-    CommOrExecRecord, CommonData, ContextSwitchRecord, ForkOrExitRecord, Mmap2FileId, Mmap2Record,
-    MmapRecord, RawDataU64, SampleRecord,
+    CommOrExecRecord, CommonData, ContextSwitchRecord, CpuMode, ForkOrExitRecord, Mmap2FileId,
+    Mmap2Record, MmapRecord, RawDataU64, SampleRecord,
 };
 use memmap2::Mmap;
-use object::{CompressedFileRange, CompressionFormat, Object, ObjectSection};
+use object::{BinaryFormat, CompressedFileRange, CompressionFormat, Object, ObjectSection};
 use samply_symbols::{debug_id_for_object, DebugIdExt};
 use wholesym::samply_symbols::demangle_any;
 use wholesym::{samply_symbols, CodeId, ElfBuildId};
 
 use super::avma_range::AvmaRange;
 use super::convert_regs::ConvertRegs;
-use super::event_interpretation::{EventInterpretation, OffCpuIndicator};
+use super::event_interpretation::{EventInterpretation, KnownEvent, OffCpuIndicator};
+use super::frame_pointer_unwinder::{FramePointerCache, UnwindRegsArm, UnwinderArm};
 use super::injected_jit_object::{correct_bad_perf_jit_so_file, jit_function_name};
-use super::kernel_symbols::{kernel_module_build_id, KernelSymbols};
+use super::irq::{parse_irq_handler_entry, parse_irq_number, softirq_name, IrqKind, IrqTracks};
+use super::kernel_symbols::{
+    detect_dso_key, kernel_module_build_id, kernel_module_debug_path, GuestKernelLib, KernelSymbols,
+};
 use super::mmap_range_or_vec::MmapRangeOrVec;
 use super::pe_mappings::{PeMappings, SuspectedPeMapping};
 use super::processes::Processes;
 use super::rss_stat::{RssStat, MM_ANONPAGES, MM_FILEPAGES, MM_SHMEMPAGES, MM_SWAPENTS};
+use super::sched_wakeup::parse_sched_wakeup_tid;
 use super::svma_file_range::compute_vma_bias;
+use super::thread::{PendingMmap, PendingThreadCreate};
+use super::tracepoint_format::TracepointFormat;
 use super::vdso::VdsoObject;
+use super::vm_syscalls::{is_syscall_error, parse_sys_enter_args, parse_sys_exit_ret};
 use crate::shared::context_switch::{ContextSwitchHandler, OffCpuSampleGroup};
 use crate::shared::jit_category_manager::JitCategoryManager;
 use crate::shared::lib_mappings::{AndroidArtInfo, LibMappingInfo};
+use crate::shared::output_capture::OutputStream;
+use crate::shared::panic_message::PanicMessage;
 use crate::shared::per_cpu::Cpus;
 use crate::shared::process_name::make_process_name;
 use crate::shared::process_sample_data::{
-    OtherEventMarker, RssStatMarker, RssStatMember, SchedSwitchMarkerOnCpuTrack,
-    SchedSwitchMarkerOnThreadTrack,
+    ExceptionMarker, ExecMarker, KernelEventMarker, OtherEventMarker, OutputLineMarker,
+    ProbeMarker, RestartMarker, RssStatMarker, RssStatMember, SchedSwitchMarkerOnCpuTrack,
+    SchedSwitchMarkerOnThreadTrack, TestMarker, ThreadLifecycleMarker, VirtualMemoryMarker,
 };
 use crate::shared::prop_types::ProfileCreationProps;
+use crate::shared::sample_weight::weight_for_period;
+use crate::shared::sched_latency::SchedLatencyTracker;
+use crate::shared::self_profile;
+use crate::shared::suspend::{add_power_track, add_sleep_instant};
 use crate::shared::synthetic_jit_library::SyntheticJitLibrary;
+use crate::shared::test_output::TestRun;
 use crate::shared::timestamp_converter::TimestampConverter;
 use crate::shared::types::{StackFrame, StackMode};
 use crate::shared::unresolved_samples::{
@@ -58,6 +75,19 @@ use crate::shared::unresolved_samples::{
 };
 use crate::shared::utils::open_file_with_fallback;
 
+/// A probe whose hits are recorded as markers.
+#[derive(Debug, Clone)]
+pub enum RecordedProbe {
+    /// A uprobe or a USDT probe, with its marker name and the path of its
+    /// binary.
+    User { name: String, binary: String },
+    /// A kprobe, or a tracepoint with the format of its fields.
+    Kernel {
+        name: String,
+        format: Option<TracepointFormat>,
+    },
+}
+
 pub struct Converter<U>
 where
     U: Unwinder<Module = Module<MmapRangeOrVec>> + Default,
@@ -75,15 +105,43 @@ where
     context_switch_handler: ContextSwitchHandler,
     unresolved_stacks: UnresolvedStacks,
     off_cpu_weight_per_sample: i32,
+    /// The sampling interval, if the periods of the samples are in nanoseconds.
+    /// Samples whose period spans several intervals get a higher weight.
+    sample_weight_interval_ns: Option<u64>,
     off_cpu_indicator: Option<OffCpuIndicator>,
     event_names: Vec<String>,
+    /// The recorded probes, by probe index, for `--uprobe`, `--usdt`,
+    /// `--kprobe` and `--tracepoint`.
+    probes: Vec<RecordedProbe>,
+    /// The formats of the tracepoint events in an imported perf.data file, by
+    /// attribute index, so that their markers show the tracepoint fields.
+    tracepoint_formats: HashMap<usize, TracepointFormat>,
+    sched_switch_attr_index: Option<usize>,
+    /// The format of the sched_switch tracepoint, for the tid of the thread
+    /// which goes on-CPU.
+    sched_switch_format: Option<TracepointFormat>,
+    /// The process of each thread, from the FORK and COMM records, for
+    /// threads which are only known by their tid, like the next thread of a
+    /// sched_switch.
+    pids_by_tid: HashMap<i32, i32>,
     kernel_symbols: Option<KernelSymbols>,
     kernel_image_mapping: Option<KernelImageMapping>,
+    /// Whether the profile was recorded on the running kernel, so that the
+    /// module and BPF program symbols from its /proc/kallsyms apply.
+    uses_running_kernel_symbols: bool,
+    /// The libraries of a VM guest's kernel, for samples from `perf kvm`.
+    guest_kernel_mappings: Vec<(u64, u64, LibraryHandle)>,
+    /// The processes to which the guest kernel mappings have been added.
+    processes_with_guest_mappings: HashSet<i32>,
     simpleperf: SimpleperfConverterData,
     pe_mappings: PeMappings,
     jit_category_manager: JitCategoryManager,
     arg_count_to_include_in_process_name: usize,
     cpus: Option<Cpus>,
+    /// The per-CPU IRQ tracks, if the irq tracepoints were recorded.
+    irq_tracks: Option<IrqTracks>,
+    /// The run-queue latency of each thread, with `--sched-latency`.
+    sched_latency: Option<SchedLatencyTracker>,
     stack_scratch: Vec<StackFrame>,
 
     /// Whether repeated frames at the base of the stack should be folded
@@ -107,6 +165,9 @@ where
 
     /// Whether to emit context switch markers.
     should_emit_cswitch_markers: bool,
+
+    /// The track for system sleep markers, created for the first sleep.
+    power_track: Option<ThreadHandle>,
 }
 
 struct SimpleperfConverterData {
@@ -131,7 +192,7 @@ where
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         profile_creation_props: &ProfileCreationProps,
-        reference_timestamp: ReferenceTimestamp,
+        reference_time: Duration,
         profile_name: &str,
         build_ids: HashMap<DsoKey, DsoInfo>,
         linux_version: Option<&str>,
@@ -148,6 +209,11 @@ where
             Some(nanos) => SamplingInterval::from_nanos(nanos),
             None => SamplingInterval::from_millis(1),
         };
+        let (timestamp_converter, reference_timestamp) = TimestampConverter::with_reference_time(
+            first_sample_time,
+            reference_time,
+            profile_creation_props.utc_timestamps,
+        );
         let mut profile = Profile::new(profile_name, reference_timestamp, interval);
         if let Some(linux_version) = linux_version {
             profile.set_os_name(&format!("Linux {linux_version}"));
@@ -155,15 +221,18 @@ where
         let (off_cpu_sampling_interval_ns, off_cpu_weight_per_sample) =
             match &interpretation.sampling_is_time_based {
                 Some(interval_ns) => (*interval_ns, 1),
+                // Without a sampling event, the off-CPU samples are the only
+                // samples, so they carry the weight.
+                None if interpretation.main_event_attr_index.is_none() => {
+                    (DEFAULT_OFF_CPU_SAMPLING_INTERVAL_NS, 1)
+                }
                 None => (DEFAULT_OFF_CPU_SAMPLING_INTERVAL_NS, 0),
             };
+        let sample_weight_interval_ns = interpretation
+            .sampling_is_time_based
+            .filter(|_| interpretation.sample_period_is_nanos);
         let kernel_symbols = KernelSymbols::new_for_running_kernel().ok();
 
-        let timestamp_converter = TimestampConverter {
-            reference_raw: first_sample_time,
-            raw_to_ns_factor: 1,
-        };
-
         profile.set_platform_specific_reference_timestamp(
             PlatformSpecificReferenceTimestamp::ClockMonotonicNanosecondsSinceBoot(
                 timestamp_converter.reference_raw,
@@ -177,6 +246,22 @@ where
             None
         };
 
+        let has_irq_events = interpretation.known_event_indices.values().any(|event| {
+            matches!(
+                event,
+                KnownEvent::IrqHandlerEntry
+                    | KnownEvent::IrqHandlerExit
+                    | KnownEvent::SoftirqEntry
+                    | KnownEvent::SoftirqExit
+            )
+        });
+        let irq_tracks = if has_irq_events {
+            let start_timestamp = timestamp_converter.convert_time(first_sample_time);
+            Some(IrqTracks::new(start_timestamp, &mut profile))
+        } else {
+            None
+        };
+
         let simpleperf = SimpleperfConverterData::new(
             simpleperf_symbol_tables,
             profile_creation_props,
@@ -190,6 +275,7 @@ where
                 profile_creation_props.reuse_threads,
                 profile_creation_props.unlink_aux_files,
                 profile_creation_props.should_emit_jit_markers,
+                profile_creation_props.marker_stacks_from_samples,
             ),
             timestamp_converter,
             current_sample_time: first_sample_time,
@@ -199,12 +285,21 @@ where
             binary_lookup_dirs,
             aux_file_lookup_dirs,
             off_cpu_weight_per_sample,
+            sample_weight_interval_ns,
             context_switch_handler: ContextSwitchHandler::new(off_cpu_sampling_interval_ns),
             unresolved_stacks: UnresolvedStacks::default(),
             off_cpu_indicator: interpretation.off_cpu_indicator,
             event_names: interpretation.event_names,
+            probes: Vec::new(),
+            tracepoint_formats: HashMap::new(),
+            sched_switch_attr_index: interpretation.sched_switch_attr_index,
+            sched_switch_format: None,
+            pids_by_tid: HashMap::new(),
             kernel_symbols,
             kernel_image_mapping: None,
+            uses_running_kernel_symbols: false,
+            guest_kernel_mappings: Vec::new(),
+            processes_with_guest_mappings: HashSet::new(),
             simpleperf,
             pe_mappings: PeMappings::new(),
             jit_category_manager: JitCategoryManager::new(),
@@ -212,14 +307,20 @@ where
             arg_count_to_include_in_process_name: profile_creation_props
                 .arg_count_to_include_in_process_name,
             cpus,
+            irq_tracks,
+            sched_latency: profile_creation_props
+                .sched_latency_threshold_ms
+                .map(SchedLatencyTracker::new),
             stack_scratch: Vec::new(),
             call_chain_return_addresses_are_preadjusted,
             should_emit_jit_markers: profile_creation_props.should_emit_jit_markers,
             should_emit_cswitch_markers: profile_creation_props.should_emit_cswitch_markers,
+            power_track: None,
         }
     }
 
     pub fn finish(mut self) -> Profile {
+        let _phase = self_profile::phase("Finish profile", None);
         let mut profile = self.profile;
         self.simpleperf
             .jit_app_cache_library
@@ -241,6 +342,88 @@ where
         self.profile.set_os_name(os_name);
     }
 
+    /// Makes the code of JIT functions from perf map files get saved into
+    /// jitdump files in this directory, for the assembly view.
+    pub fn set_jit_code_dir(&mut self, jit_code_dir: PathBuf) {
+        self.processes.set_jit_code_dir(jit_code_dir);
+    }
+
+    /// Sets the recorded probes, by probe index.
+    pub fn set_probes(&mut self, probes: Vec<RecordedProbe>) {
+        self.probes = probes;
+    }
+
+    /// Sets the formats of the tracepoint events, by attribute index, from
+    /// the tracing data of a perf.data file.
+    pub fn set_tracepoint_formats(&mut self, formats: HashMap<usize, TracepointFormat>) {
+        self.sched_switch_format = self
+            .sched_switch_attr_index
+            .and_then(|index| formats.get(&index).cloned());
+        self.tracepoint_formats = formats;
+    }
+
+    /// Sets the libraries of the VM guest's kernel, so that the guest kernel
+    /// frames of samples recorded with `perf kvm` can be symbolicated.
+    pub fn set_guest_kernel_libs(&mut self, libs: Vec<GuestKernelLib>) {
+        for lib in libs {
+            let lib_handle = self.profile.add_lib(LibraryInfo {
+                name: lib.name.clone(),
+                debug_name: lib.name.clone(),
+                path: lib.name.clone(),
+                debug_path: lib.name,
+                debug_id: DebugId::nil(),
+                code_id: None,
+                arch: None,
+            });
+            self.profile
+                .set_lib_symbol_table(lib_handle, Arc::new(lib.symbol_table));
+            self.guest_kernel_mappings
+                .push((lib.start_avma, lib.end_avma, lib_handle));
+        }
+    }
+
+    /// Guest kernel frames are looked up in the lib mappings of the process
+    /// which ran the guest, rather than in the host kernel's mappings. Add the
+    /// guest kernel's mappings to this process if we haven't done so yet.
+    fn add_guest_kernel_mappings(&mut self, pid: i32, timestamp: u64) {
+        if self.guest_kernel_mappings.is_empty() || !self.processes_with_guest_mappings.insert(pid)
+        {
+            return;
+        }
+        let category: SubcategoryHandle = self.guest_category().into();
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        for (start_avma, end_avma, lib_handle) in &self.guest_kernel_mappings {
+            process.add_regular_lib_mapping(
+                timestamp,
+                *start_avma,
+                *end_avma,
+                0,
+                LibMappingInfo::new_lib_with_category(*lib_handle, category),
+            );
+        }
+    }
+
+    fn guest_category(&mut self) -> CategoryHandle {
+        self.profile
+            .handle_for_category(Category("Guest", CategoryColor::Magenta))
+    }
+
+    /// Guest kernel frames become user frames, so that they're looked up in
+    /// the guest kernel mappings of the process. Guest user frames are
+    /// dropped, because we don't know the guest's processes and libraries.
+    fn convert_guest_stack(stack: &mut Vec<StackFrame>) {
+        stack.retain_mut(|frame| match frame {
+            StackFrame::InstructionPointer(_, mode)
+            | StackFrame::ReturnAddress(_, mode)
+            | StackFrame::AdjustedReturnAddress(_, mode) => {
+                let is_guest_kernel = *mode == StackMode::Kernel;
+                *mode = StackMode::User;
+                is_guest_kernel
+            }
+            StackFrame::TruncatedStackMarker => true,
+        });
+    }
+
     pub fn handle_main_event_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
         &mut self,
         e: &SampleRecord,
@@ -258,8 +441,21 @@ where
 
         let profile_timestamp = self.timestamp_converter.convert_time(timestamp);
 
+        let is_guest = matches!(e.cpu_mode, CpuMode::GuestKernel | CpuMode::GuestUser);
+        let guest_label = if is_guest {
+            self.add_guest_kernel_mappings(pid, timestamp);
+            let label = match e.cpu_mode {
+                CpuMode::GuestKernel => "Guest kernel",
+                _ => "Guest user",
+            };
+            Some((self.profile.handle_for_string(label), self.guest_category()))
+        } else {
+            None
+        };
+
         let process = self.processes.get_by_pid(pid, &mut self.profile);
-        process.check_jitdump(
+        process.check_jit_files(
+            timestamp,
             &mut self.jit_category_manager,
             &mut self.profile,
             &self.timestamp_converter,
@@ -269,11 +465,15 @@ where
         Self::get_sample_stack::<C>(
             e,
             &process.unwinder,
+            process.is_32bit,
             &mut self.cache,
             stack,
             self.fold_recursive_prefix,
             self.call_chain_return_addresses_are_preadjusted,
         );
+        if is_guest {
+            Self::convert_guest_stack(stack);
+        }
 
         let thread = process.threads.get_thread_by_tid(tid, &mut self.profile);
 
@@ -319,15 +519,29 @@ where
             CpuDelta::from_nanos(0)
         };
 
+        let weight = match (self.sample_weight_interval_ns, e.period) {
+            (Some(interval_ns), Some(period)) => weight_for_period(period, interval_ns),
+            _ => 1,
+        };
+
         let stack_index = self.unresolved_stacks.convert(stack.iter().rev().cloned());
+        // Guest samples go below a "Guest kernel" or "Guest user" root frame.
+        let guest_label_frame = guest_label.map(|(label, category)| {
+            self.profile.handle_for_frame_with_label(
+                thread_handle,
+                label,
+                category,
+                FrameFlags::empty(),
+            )
+        });
         process.unresolved_samples.add_sample(
             thread_handle,
             profile_timestamp,
             timestamp,
             stack_index,
             cpu_delta,
-            1,
-            None,
+            weight,
+            guest_label_frame,
         );
 
         if let (Some(cpu_index), Some(cpus)) = (e.cpu, &mut self.cpus) {
@@ -361,7 +575,7 @@ where
                 timestamp,
                 stack_index,
                 cpu_delta,
-                1,
+                weight,
                 Some(label_frame),
             );
 
@@ -377,7 +591,7 @@ where
                 timestamp,
                 stack_index,
                 CpuDelta::ZERO,
-                1,
+                weight,
                 Some(label_frame),
             );
         }
@@ -390,7 +604,8 @@ where
         let pid = e.pid.expect("Can't handle samples without pids");
         let tid = e.tid.expect("Can't handle samples without tids");
         let process = self.processes.get_by_pid(pid, &mut self.profile);
-        process.check_jitdump(
+        process.check_jit_files(
+            e.timestamp.unwrap_or(self.current_sample_time),
             &mut self.jit_category_manager,
             &mut self.profile,
             &self.timestamp_converter,
@@ -400,6 +615,7 @@ where
         Self::get_sample_stack::<C>(
             e,
             &process.unwinder,
+            process.is_32bit,
             &mut self.cache,
             stack,
             self.fold_recursive_prefix,
@@ -452,6 +668,28 @@ where
                 marker_handle,
             );
         }
+
+        self.pids_by_tid.insert(tid, pid);
+        if self.off_cpu_indicator == Some(OffCpuIndicator::SchedSwitchAndSamples) {
+            // The next thread goes on-CPU now. Without this, a thread is only
+            // known to be running again once it's sampled, and without a
+            // sampling event, e.g. for `perf record -e 'sched:*'`, never.
+            let next_tid = match (&self.sched_switch_format, e.raw) {
+                (Some(format), Some(raw)) => {
+                    format.field_value("next_pid", &raw.as_slice(), self.endian)
+                }
+                _ => None,
+            };
+            // Thread 0 is the idle thread.
+            if let Some(next_tid) = next_tid
+                .and_then(|tid| i32::try_from(tid).ok())
+                .filter(|&tid| tid != 0)
+            {
+                if let Some(&next_pid) = self.pids_by_tid.get(&next_tid) {
+                    self.handle_thread_switch_in(next_pid, next_tid, timestamp_mono);
+                }
+            }
+        }
     }
 
     pub fn handle_rss_stat_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
@@ -502,7 +740,8 @@ where
                 .add_counter_sample(counter, timestamp, delta as f64, 1);
         }
 
-        process.check_jitdump(
+        process.check_jit_files(
+            timestamp_mono,
             &mut self.jit_category_manager,
             &mut self.profile,
             &self.timestamp_converter,
@@ -512,6 +751,7 @@ where
         Self::get_sample_stack::<C>(
             e,
             &process.unwinder,
+            process.is_32bit,
             &mut self.cache,
             stack,
             self.fold_recursive_prefix,
@@ -541,6 +781,235 @@ where
         );
     }
 
+    pub fn handle_mmap_enter_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
+        &mut self,
+        e: &SampleRecord,
+    ) {
+        let (Some(pid), Some(tid), Some(timestamp_mono), Some(raw)) =
+            (e.pid, e.tid, e.timestamp, e.raw)
+        else {
+            return;
+        };
+        let Ok([_addr, len]) = parse_sys_enter_args::<2>(raw, self.endian) else {
+            return;
+        };
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let stack = &mut self.stack_scratch;
+        Self::get_sample_stack::<C>(
+            e,
+            &process.unwinder,
+            process.is_32bit,
+            &mut self.cache,
+            stack,
+            self.fold_recursive_prefix,
+            self.call_chain_return_addresses_are_preadjusted,
+        );
+        let stack = self.unresolved_stacks.convert(stack.iter().rev().cloned());
+        let thread = process.threads.get_thread_by_tid(tid, &mut self.profile);
+        thread.pending_mmap = Some(PendingMmap {
+            start_timestamp_mono: timestamp_mono,
+            len,
+            stack,
+        });
+    }
+
+    pub fn handle_mmap_exit_sample(&mut self, e: &SampleRecord) {
+        let (Some(pid), Some(tid), Some(timestamp_mono), Some(raw)) =
+            (e.pid, e.tid, e.timestamp, e.raw)
+        else {
+            return;
+        };
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let thread = process.threads.get_thread_by_tid(tid, &mut self.profile);
+        let Some(pending) = thread.pending_mmap.take() else {
+            return;
+        };
+        let Ok(ret) = parse_sys_exit_ret(raw, self.endian) else {
+            return;
+        };
+        if is_syscall_error(ret) {
+            return;
+        }
+        let timing = MarkerTiming::Interval(
+            self.timestamp_converter
+                .convert_time(pending.start_timestamp_mono),
+            self.timestamp_converter.convert_time(timestamp_mono),
+        );
+        self.add_virtual_memory_marker(
+            pid,
+            tid,
+            "mmap",
+            ret as u64,
+            pending.len as i64,
+            timing,
+            pending.start_timestamp_mono,
+            pending.stack,
+        );
+    }
+
+    pub fn handle_munmap_enter_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
+        &mut self,
+        e: &SampleRecord,
+    ) {
+        let (Some(pid), Some(tid), Some(timestamp_mono), Some(raw)) =
+            (e.pid, e.tid, e.timestamp, e.raw)
+        else {
+            return;
+        };
+        let Ok([addr, len]) = parse_sys_enter_args::<2>(raw, self.endian) else {
+            return;
+        };
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let stack = &mut self.stack_scratch;
+        Self::get_sample_stack::<C>(
+            e,
+            &process.unwinder,
+            process.is_32bit,
+            &mut self.cache,
+            stack,
+            self.fold_recursive_prefix,
+            self.call_chain_return_addresses_are_preadjusted,
+        );
+        let stack = self.unresolved_stacks.convert(stack.iter().rev().cloned());
+        let timing = MarkerTiming::Instant(self.timestamp_converter.convert_time(timestamp_mono));
+        self.add_virtual_memory_marker(
+            pid,
+            tid,
+            "munmap",
+            addr,
+            -(len as i64),
+            timing,
+            timestamp_mono,
+            stack,
+        );
+    }
+
+    /// Updates the process's address space counter and adds a marker with the
+    /// stack of the mmap or munmap call.
+    #[allow(clippy::too_many_arguments)]
+    fn add_virtual_memory_marker(
+        &mut self,
+        pid: i32,
+        tid: i32,
+        name: &str,
+        address: u64,
+        delta_bytes: i64,
+        timing: MarkerTiming,
+        timestamp_mono: u64,
+        stack: UnresolvedStackHandle,
+    ) {
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        let counter = process.get_or_make_address_space_counter(&mut self.profile);
+        self.profile
+            .add_counter_sample(counter, timestamp, delta_bytes as f64, 1);
+        process.address_space_size += delta_bytes;
+
+        let marker = VirtualMemoryMarker {
+            name: self.profile.handle_for_string(name),
+            address: self.profile.handle_for_string(&format!("{address:#x}")),
+            size_bytes: delta_bytes.unsigned_abs(),
+            total_bytes: process.address_space_size,
+        };
+        let thread_handle = process
+            .threads
+            .get_thread_by_tid(tid, &mut self.profile)
+            .profile_thread;
+        let marker_handle = self.profile.add_marker(thread_handle, timing, marker);
+        process.unresolved_samples.attach_stack_to_marker(
+            thread_handle,
+            timestamp,
+            timestamp_mono,
+            stack,
+            marker_handle,
+        );
+    }
+
+    pub fn handle_sched_wakeup_sample(&mut self, e: &SampleRecord) {
+        let (Some(timestamp_mono), Some(raw), Some(sched_latency)) =
+            (e.timestamp, e.raw, &mut self.sched_latency)
+        else {
+            return;
+        };
+        let Ok(tid) = parse_sched_wakeup_tid(raw, self.endian) else {
+            return;
+        };
+        let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        sched_latency.handle_wakeup(tid as u32, timestamp);
+    }
+
+    pub fn handle_irq_handler_entry(&mut self, e: &SampleRecord) {
+        let (Some(cpu), Some(timestamp_mono), Some(raw), Some(irq_tracks)) =
+            (e.cpu, e.timestamp, e.raw, &mut self.irq_tracks)
+        else {
+            return;
+        };
+        let Ok((irq, name)) = parse_irq_handler_entry(raw, self.endian) else {
+            return;
+        };
+        irq_tracks.handle_entry(
+            cpu,
+            IrqKind::Hard(irq),
+            name,
+            timestamp_mono,
+            &mut self.profile,
+        );
+    }
+
+    pub fn handle_irq_handler_exit(&mut self, e: &SampleRecord) {
+        let (Some(cpu), Some(timestamp_mono), Some(raw), Some(irq_tracks)) =
+            (e.cpu, e.timestamp, e.raw, &mut self.irq_tracks)
+        else {
+            return;
+        };
+        let Ok(irq) = parse_irq_number(raw, self.endian) else {
+            return;
+        };
+        irq_tracks.handle_exit(
+            cpu,
+            IrqKind::Hard(irq as i32),
+            timestamp_mono,
+            &self.timestamp_converter,
+            &mut self.profile,
+        );
+    }
+
+    pub fn handle_softirq_entry(&mut self, e: &SampleRecord) {
+        let (Some(cpu), Some(timestamp_mono), Some(raw), Some(irq_tracks)) =
+            (e.cpu, e.timestamp, e.raw, &mut self.irq_tracks)
+        else {
+            return;
+        };
+        let Ok(vec) = parse_irq_number(raw, self.endian) else {
+            return;
+        };
+        irq_tracks.handle_entry(
+            cpu,
+            IrqKind::Soft(vec),
+            softirq_name(vec),
+            timestamp_mono,
+            &mut self.profile,
+        );
+    }
+
+    pub fn handle_softirq_exit(&mut self, e: &SampleRecord) {
+        let (Some(cpu), Some(timestamp_mono), Some(raw), Some(irq_tracks)) =
+            (e.cpu, e.timestamp, e.raw, &mut self.irq_tracks)
+        else {
+            return;
+        };
+        let Ok(vec) = parse_irq_number(raw, self.endian) else {
+            return;
+        };
+        irq_tracks.handle_exit(
+            cpu,
+            IrqKind::Soft(vec),
+            timestamp_mono,
+            &self.timestamp_converter,
+            &mut self.profile,
+        );
+    }
+
     pub fn handle_other_event_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
         &mut self,
         e: &SampleRecord,
@@ -553,7 +1022,8 @@ where
         let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
         // let tid = e.tid.expect("Can't handle samples without tids");
         let process = self.processes.get_by_pid(pid, &mut self.profile);
-        process.check_jitdump(
+        process.check_jit_files(
+            timestamp_mono,
             &mut self.jit_category_manager,
             &mut self.profile,
             &self.timestamp_converter,
@@ -563,6 +1033,7 @@ where
         Self::get_sample_stack::<C>(
             e,
             &process.unwinder,
+            process.is_32bit,
             &mut self.cache,
             stack,
             self.fold_recursive_prefix,
@@ -583,9 +1054,19 @@ where
         if let Some(name) = self.event_names.get(attr_index) {
             let timing = MarkerTiming::Instant(timestamp);
             let name = self.profile.handle_for_string(name);
-            let marker_handle =
-                self.profile
-                    .add_marker(thread_handle, timing, OtherEventMarker(name));
+            let marker_handle = match (self.tracepoint_formats.get(&attr_index), e.raw) {
+                (Some(format), Some(raw)) => {
+                    let fields = format.format_fields(&raw.as_slice(), self.endian);
+                    let marker = KernelEventMarker {
+                        name,
+                        fields: self.profile.handle_for_string(&fields),
+                    };
+                    self.profile.add_marker(thread_handle, timing, marker)
+                }
+                _ => self
+                    .profile
+                    .add_marker(thread_handle, timing, OtherEventMarker(name)),
+            };
             process.unresolved_samples.attach_stack_to_marker(
                 thread_handle,
                 timestamp,
@@ -596,6 +1077,78 @@ where
         }
     }
 
+    /// Adds a marker for a hit of the probe with this index, with the stack of
+    /// the hit if it was sampled.
+    pub fn handle_probe_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
+        &mut self,
+        e: &SampleRecord,
+        probe_index: usize,
+    ) {
+        let Some(probe) = self.probes.get(probe_index) else {
+            return;
+        };
+        let pid = e.pid.expect("Can't handle samples without pids");
+        let tid = e.tid.expect("Can't handle samples without tids");
+        let timestamp_mono = e
+            .timestamp
+            .expect("Can't handle samples without timestamps");
+        let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let thread_handle = process
+            .threads
+            .get_thread_by_tid(tid, &mut self.profile)
+            .profile_thread;
+        let timing = MarkerTiming::Instant(timestamp);
+        let marker_handle = match probe {
+            RecordedProbe::User { name, binary } => {
+                let marker = ProbeMarker {
+                    name: self.profile.handle_for_string(name),
+                    binary: self.profile.handle_for_string(binary),
+                };
+                self.profile.add_marker(thread_handle, timing, marker)
+            }
+            RecordedProbe::Kernel { name, format } => {
+                let fields = match (format, e.raw) {
+                    (Some(format), Some(raw)) => format.format_fields(&raw.as_slice(), self.endian),
+                    _ => String::new(),
+                };
+                let marker = KernelEventMarker {
+                    name: self.profile.handle_for_string(name),
+                    fields: self.profile.handle_for_string(&fields),
+                };
+                self.profile.add_marker(thread_handle, timing, marker)
+            }
+        };
+        if e.user_regs.is_none() {
+            return;
+        }
+
+        process.check_jit_files(
+            timestamp_mono,
+            &mut self.jit_category_manager,
+            &mut self.profile,
+            &self.timestamp_converter,
+        );
+        let stack = &mut self.stack_scratch;
+        Self::get_sample_stack::<C>(
+            e,
+            &process.unwinder,
+            process.is_32bit,
+            &mut self.cache,
+            stack,
+            self.fold_recursive_prefix,
+            self.call_chain_return_addresses_are_preadjusted,
+        );
+        let unresolved_stack = self.unresolved_stacks.convert(stack.iter().rev().cloned());
+        process.unresolved_samples.attach_stack_to_marker(
+            thread_handle,
+            timestamp,
+            timestamp_mono,
+            unresolved_stack,
+            marker_handle,
+        );
+    }
+
     /// Get the stack contained in this sample, and put it into `stack`.
     ///
     /// We can have both the kernel stack and the user stack, or just one of
@@ -614,10 +1167,13 @@ where
     ///  - With DWARF unwinding (`perf record --call-graph dwarf`), the raw
     ///    bytes on the stack are just copied into the perf.data file, and we
     ///    need to do the unwinding now, based on the register values in
-    ///    `e.user_regs` and the raw stack bytes in `e.user_stack`.
+    ///    `e.user_regs` and the raw stack bytes in `e.user_stack`. The stacks
+    ///    of 32-bit processes on 64-bit systems are walked with frame pointers
+    ///    instead, because `unwinder` only understands 64-bit code.
     fn get_sample_stack<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
         e: &SampleRecord,
         unwinder: &U,
+        is_32bit_process: bool,
         cache: &mut U::Cache,
         stack: &mut Vec<StackFrame>,
         fold_recursive_prefix: bool,
@@ -655,6 +1211,10 @@ where
         // Append the user stack with the help of DWARF unwinding.
         if let (Some(regs), Some((user_stack, _))) = (&e.user_regs, e.user_stack) {
             let ustack_bytes = RawDataU64::from_raw_data::<LittleEndian>(user_stack);
+            let compat_fp = match is_32bit_process {
+                true => C::compat_frame_pointer(regs),
+                false => None,
+            };
             let (pc, sp, regs) = C::convert_regs(regs);
             let mut read_stack = |addr: u64| {
                 // ustack_bytes has the stack bytes starting from the current stack pointer.
@@ -664,25 +1224,18 @@ where
             };
 
             // Unwind.
-            let mut frames = unwinder.iter_frames(pc, regs, cache, &mut read_stack);
-            loop {
-                let frame = match frames.next() {
-                    Ok(Some(frame)) => frame,
-                    Ok(None) => break,
-                    Err(_) => {
-                        stack.push(StackFrame::TruncatedStackMarker);
-                        break;
-                    }
-                };
-                let stack_frame = match frame {
-                    FrameAddress::InstructionPointer(addr) => {
-                        StackFrame::InstructionPointer(addr, StackMode::User)
-                    }
-                    FrameAddress::ReturnAddress(addr) => {
-                        StackFrame::ReturnAddress(addr.into(), StackMode::User)
-                    }
-                };
-                stack.push(stack_frame);
+            match compat_fp {
+                Some(fp) => {
+                    let unwinder = UnwinderArm::<Vec<u8>>::default();
+                    let mut cache = FramePointerCache::new();
+                    let regs = UnwindRegsArm::new(sp, fp);
+                    let frames = unwinder.iter_frames(pc, regs, &mut cache, &mut read_stack);
+                    Self::push_user_frames(frames, stack);
+                }
+                None => {
+                    let frames = unwinder.iter_frames(pc, regs, cache, &mut read_stack);
+                    Self::push_user_frames(frames, stack);
+                }
             }
         }
 
@@ -698,7 +1251,37 @@ where
         }
     }
 
+    fn push_user_frames<V: Unwinder, F: FnMut(u64) -> Result<u64, ()>>(
+        mut frames: UnwindIterator<'_, '_, '_, V, F>,
+        stack: &mut Vec<StackFrame>,
+    ) {
+        loop {
+            let frame = match frames.next() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(_) => {
+                    stack.push(StackFrame::TruncatedStackMarker);
+                    break;
+                }
+            };
+            let stack_frame = match frame {
+                FrameAddress::InstructionPointer(addr) => {
+                    StackFrame::InstructionPointer(addr, StackMode::User)
+                }
+                FrameAddress::ReturnAddress(addr) => {
+                    StackFrame::ReturnAddress(addr.into(), StackMode::User)
+                }
+            };
+            stack.push(stack_frame);
+        }
+    }
+
     pub fn handle_mmap(&mut self, e: MmapRecord, timestamp: u64) {
+        if matches!(e.cpu_mode, CpuMode::GuestKernel | CpuMode::GuestUser) {
+            // Guest mappings aren't in the address space of a host process.
+            // Guest kernel symbols come from set_guest_kernel_libs instead.
+            return;
+        }
         let mut path = e.path.as_slice();
         self.add_mmap_marker(e.pid, e.tid, &path, timestamp);
 
@@ -715,7 +1298,7 @@ where
             return;
         }
 
-        let dso_key = match DsoKey::detect(&path, e.cpu_mode) {
+        let dso_key = match detect_dso_key(&path, e.cpu_mode) {
             Some(dso_key) => dso_key,
             None => return,
         };
@@ -747,6 +1330,9 @@ where
     }
 
     pub fn handle_mmap2(&mut self, e: Mmap2Record, timestamp: u64) {
+        if matches!(e.cpu_mode, CpuMode::GuestKernel | CpuMode::GuestUser) {
+            return;
+        }
         let path = e.path.as_slice();
         self.add_mmap_marker(e.pid, e.tid, &path, timestamp);
 
@@ -851,31 +1437,12 @@ where
         let timestamp = common
             .timestamp
             .expect("Can't handle context switch without time");
-        let process = self.processes.get_by_pid(pid, &mut self.profile);
-        let thread = process.threads.get_thread_by_tid(tid, &mut self.profile);
 
         match e {
             ContextSwitchRecord::In { .. } => {
-                // Consume off-cpu time and clear the saved off-CPU stack.
-                let off_cpu_sample = self
-                    .context_switch_handler
-                    .handle_switch_in(timestamp, &mut thread.context_switch_data);
-                if let (Some(off_cpu_sample), Some(off_cpu_stack)) =
-                    (off_cpu_sample, thread.off_cpu_stack.take())
-                {
-                    let cpu_delta_ns = self
-                        .context_switch_handler
-                        .consume_cpu_delta(&mut thread.context_switch_data);
-                    process_off_cpu_sample_group(
-                        off_cpu_sample,
-                        thread.profile_thread,
-                        cpu_delta_ns,
-                        &self.timestamp_converter,
-                        self.off_cpu_weight_per_sample,
-                        off_cpu_stack,
-                        &mut process.unresolved_samples,
-                    );
-                }
+                self.handle_thread_switch_in(pid, tid, timestamp);
+                let process = self.processes.get_by_pid(pid, &mut self.profile);
+                let thread = process.threads.get_thread_by_tid(tid, &mut self.profile);
                 if let (Some(cpus), Some(cpu_index)) = (&mut self.cpus, common.cpu) {
                     let combined_thread = cpus.combined_thread_handle();
                     let idle_frame_label = cpus.idle_frame_label();
@@ -932,6 +1499,8 @@ where
                 }
             }
             ContextSwitchRecord::Out { preempted, .. } => {
+                let process = self.processes.get_by_pid(pid, &mut self.profile);
+                let thread = process.threads.get_thread_by_tid(tid, &mut self.profile);
                 self.context_switch_handler
                     .handle_switch_out(timestamp, &mut thread.context_switch_data);
                 if let (Some(cpus), Some(cpu_index)) = (&mut self.cpus, Some(common.cpu.unwrap())) {
@@ -955,12 +1524,50 @@ where
         }
     }
 
+    /// Accounts for a thread going back on-CPU, from a context switch record
+    /// or a sched_switch sample: adds the off-CPU samples for the time it was
+    /// blocked, with the stack at which it went off-CPU.
+    fn handle_thread_switch_in(&mut self, pid: i32, tid: i32, timestamp: u64) {
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let thread = process.threads.get_thread_by_tid(tid, &mut self.profile);
+        // Consume off-cpu time and clear the saved off-CPU stack.
+        let off_cpu_sample = self
+            .context_switch_handler
+            .handle_switch_in(timestamp, &mut thread.context_switch_data);
+        if let (Some(off_cpu_sample), Some(off_cpu_stack)) =
+            (off_cpu_sample, thread.off_cpu_stack.take())
+        {
+            let cpu_delta_ns = self
+                .context_switch_handler
+                .consume_cpu_delta(&mut thread.context_switch_data);
+            process_off_cpu_sample_group(
+                off_cpu_sample,
+                thread.profile_thread,
+                cpu_delta_ns,
+                &self.timestamp_converter,
+                self.off_cpu_weight_per_sample,
+                off_cpu_stack,
+                &mut process.unresolved_samples,
+            );
+        }
+        if let Some(sched_latency) = &mut self.sched_latency {
+            sched_latency.handle_switch_in(
+                tid as u32,
+                self.timestamp_converter.convert_time(timestamp),
+                process.profile_process,
+                thread.profile_thread,
+                &mut self.profile,
+            );
+        }
+    }
+
     /// Called for a FORK record.
     ///
     /// FORK records are emitted if a new thread is started or if a new
     /// process is created. The name is inherited from the forking thread.
     pub fn handle_fork(&mut self, e: ForkOrExitRecord) {
         let start_time = self.timestamp_converter.convert_time(e.timestamp);
+        self.pids_by_tid.insert(e.tid, e.pid);
 
         let is_main = e.pid == e.tid;
         let parent_process = self.processes.get_by_pid(e.ppid, &mut self.profile);
@@ -986,15 +1593,258 @@ where
                 .threads
                 .get_thread_by_tid(e.ptid, &mut self.profile);
             let parent_thread_name = parent_thread.name.clone();
+            let parent_thread_handle = parent_thread.profile_thread;
             parent_process.recycle_or_get_new_thread(
                 e.tid,
                 parent_thread_name,
                 start_time,
                 &mut self.profile,
             );
+
+            let marker = ThreadLifecycleMarker {
+                name: self.profile.handle_for_string("ThreadCreate"),
+                tid: e.tid as u32,
+            };
+            let marker = self.profile.add_marker(
+                parent_thread_handle,
+                MarkerTiming::Instant(start_time),
+                marker,
+            );
+            // The FORK record has no stack. If the clone syscalls are recorded,
+            // the stack comes with the exit of the syscall.
+            let parent_thread = parent_process
+                .threads
+                .get_thread_by_tid(e.ptid, &mut self.profile);
+            parent_thread.pending_thread_create = Some(PendingThreadCreate {
+                child_tid: e.tid,
+                timestamp_mono: e.timestamp,
+                marker,
+            });
         }
     }
 
+    /// Called for the sys_exit_clone and sys_exit_clone3 tracepoints, which
+    /// carry the stack of the thread which created a new thread.
+    pub fn handle_clone_exit_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
+        &mut self,
+        e: &SampleRecord,
+    ) {
+        let (Some(pid), Some(tid), Some(raw)) = (e.pid, e.tid, e.raw) else {
+            return;
+        };
+        let Ok(child_tid) = parse_sys_exit_ret(raw, self.endian) else {
+            return;
+        };
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let thread = process.threads.get_thread_by_tid(tid, &mut self.profile);
+        let thread_handle = thread.profile_thread;
+        let Some(pending) = thread.pending_thread_create else {
+            return;
+        };
+        if i64::from(pending.child_tid) != child_tid {
+            return;
+        }
+        thread.pending_thread_create = None;
+        let stack = &mut self.stack_scratch;
+        Self::get_sample_stack::<C>(
+            e,
+            &process.unwinder,
+            process.is_32bit,
+            &mut self.cache,
+            stack,
+            self.fold_recursive_prefix,
+            self.call_chain_return_addresses_are_preadjusted,
+        );
+        let stack = self.unresolved_stacks.convert(stack.iter().rev().cloned());
+        process.unresolved_samples.attach_stack_to_marker(
+            thread_handle,
+            self.timestamp_converter
+                .convert_time(pending.timestamp_mono),
+            pending.timestamp_mono,
+            stack,
+            pending.marker,
+        );
+    }
+
+    /// Adds an exception marker with the stack of a `__cxa_throw` or
+    /// `rust_panic` uprobe hit. The probes don't capture any arguments, so
+    /// the message only says which kind of probe it was.
+    pub fn handle_exception_probe_sample<C: ConvertRegs<UnwindRegs = U::UnwindRegs>>(
+        &mut self,
+        e: &SampleRecord,
+        name: &str,
+        message: &str,
+    ) {
+        let (Some(pid), Some(tid), Some(timestamp_mono)) = (e.pid, e.tid, e.timestamp) else {
+            return;
+        };
+        let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let stack = &mut self.stack_scratch;
+        Self::get_sample_stack::<C>(
+            e,
+            &process.unwinder,
+            process.is_32bit,
+            &mut self.cache,
+            stack,
+            self.fold_recursive_prefix,
+            self.call_chain_return_addresses_are_preadjusted,
+        );
+        let stack = self.unresolved_stacks.convert(stack.iter().rev().cloned());
+        let marker = ExceptionMarker {
+            name: self.profile.handle_for_string(name),
+            message: self.profile.handle_for_string(message),
+            location: self.profile.handle_for_string(""),
+        };
+        let thread_handle = process
+            .threads
+            .get_thread_by_tid(tid, &mut self.profile)
+            .profile_thread;
+        let marker_handle =
+            self.profile
+                .add_marker(thread_handle, MarkerTiming::Instant(timestamp), marker);
+        process.unresolved_samples.attach_stack_to_marker(
+            thread_handle,
+            timestamp,
+            timestamp_mono,
+            stack,
+            marker_handle,
+        );
+    }
+
+    /// Adds a marker for a line which a launched command printed, on the main
+    /// thread of its process.
+    pub fn handle_output_line(
+        &mut self,
+        pid: i32,
+        timestamp_mono: u64,
+        stream: OutputStream,
+        text: &str,
+    ) {
+        let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let thread_handle = process.threads.main_thread.profile_thread;
+        let marker = OutputLineMarker {
+            name: self.profile.handle_for_string(stream.name()),
+            text: self.profile.handle_for_string(text),
+        };
+        self.profile
+            .add_marker(thread_handle, MarkerTiming::Instant(timestamp), marker);
+    }
+
+    /// Adds a "Restart" marker on the main thread of a process which was
+    /// attached to because the recorded process was restarted.
+    pub fn handle_restart(
+        &mut self,
+        pid: i32,
+        timestamp_mono: u64,
+        number: u32,
+        previous_pid: u32,
+    ) {
+        let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let thread_handle = process.threads.main_thread.profile_thread;
+        let marker = RestartMarker {
+            number,
+            previous_pid,
+        };
+        self.profile
+            .add_marker(thread_handle, MarkerTiming::Instant(timestamp), marker);
+    }
+
+    /// Adds an interval marker for a test which ran in a launched test run, on
+    /// the main thread of the launched process.
+    pub fn handle_test_run(&mut self, pid: i32, test: &TestRun) {
+        let start = self.timestamp_converter.convert_time(test.start);
+        let end = self.timestamp_converter.convert_time(test.end);
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let thread_handle = process.threads.main_thread.profile_thread;
+        let marker = TestMarker {
+            test: self.profile.handle_for_string(&test.name),
+            outcome: self.profile.handle_for_string(&test.outcome),
+        };
+        self.profile
+            .add_marker(thread_handle, MarkerTiming::Interval(start, end), marker);
+    }
+
+    /// Adds a "Panic" marker for a panic message which a launched Rust
+    /// program printed to stderr. The message doesn't come with a stack; it
+    /// is put on the thread with the printed tid or name, or on the main
+    /// thread if no such thread is known.
+    pub fn handle_panic_message(&mut self, pid: i32, timestamp_mono: u64, panic: &PanicMessage) {
+        let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        let thread_handle = match (panic.tid, panic.thread_name.as_deref()) {
+            (Some(tid), _) => {
+                process
+                    .threads
+                    .get_thread_by_tid(tid as i32, &mut self.profile)
+                    .profile_thread
+            }
+            (None, Some(name)) => process.threads.thread_handle_for_name(name),
+            (None, None) => process.threads.main_thread.profile_thread,
+        };
+        let marker = ExceptionMarker {
+            name: self.profile.handle_for_string("Panic"),
+            message: self.profile.handle_for_string(&panic.message),
+            location: self.profile.handle_for_string(&panic.location),
+        };
+        self.profile
+            .add_marker(thread_handle, MarkerTiming::Instant(timestamp), marker);
+    }
+
+    /// Adds a marker for a system sleep of `slept` which ended at
+    /// `timestamp_mono`. The perf timestamps don't include the sleep.
+    pub fn handle_system_sleep(&mut self, timestamp_mono: u64, slept: Duration) {
+        let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        let power_track = *self
+            .power_track
+            .get_or_insert_with(|| add_power_track(&mut self.profile, timestamp));
+        add_sleep_instant(
+            &mut self.profile,
+            power_track,
+            timestamp,
+            slept,
+            "CLOCK_BOOTTIME advanced further than CLOCK_MONOTONIC",
+        );
+    }
+
+    /// The pids of the processes which are being profiled and haven't exited.
+    pub fn live_pids(&self) -> Vec<i32> {
+        self.processes.live_pids()
+    }
+
+    /// Drops the samples which were taken before `timestamp_mono`, so that
+    /// `--ring-buffer-seconds` only keeps the most recent samples in memory.
+    #[allow(unused)]
+    pub fn discard_samples_before(&mut self, timestamp_mono: u64) {
+        self.processes.discard_samples_before(timestamp_mono);
+    }
+
+    /// Returns a profile with the samples so far, for a
+    /// `--ring-buffer-seconds` dump. Unlike [`finish`](Self::finish), this
+    /// doesn't end the recording.
+    #[allow(unused)]
+    pub fn snapshot(&self) -> Profile {
+        let mut profile = self.profile.clone();
+        self.processes.snapshot(
+            &mut profile,
+            &self.unresolved_stacks,
+            &self.timestamp_converter,
+        );
+        profile
+    }
+
+    /// Called periodically during live recording with the number of open
+    /// file descriptors of a process.
+    pub fn handle_open_fd_count(&mut self, pid: i32, timestamp_mono: u64, count: u64) {
+        let timestamp = self.timestamp_converter.convert_time(timestamp_mono);
+        let process = self.processes.get_by_pid(pid, &mut self.profile);
+        process
+            .get_or_make_open_fds_counter(&mut self.profile)
+            .add_sample(&mut self.profile, timestamp, count);
+    }
+
     /// Called for an EXIT record.
     pub fn handle_exit(&mut self, e: ForkOrExitRecord) {
         let is_main = e.pid == e.tid;
@@ -1009,6 +1859,16 @@ where
             );
         } else {
             let process = self.processes.get_by_pid(e.pid, &mut self.profile);
+            let thread_handle = process
+                .threads
+                .get_thread_by_tid(e.tid, &mut self.profile)
+                .profile_thread;
+            let marker = ThreadLifecycleMarker {
+                name: self.profile.handle_for_string("ThreadExit"),
+                tid: e.tid as u32,
+            };
+            self.profile
+                .add_marker(thread_handle, MarkerTiming::Instant(end_time), marker);
             process
                 .threads
                 .remove_non_main_thread(e.tid, end_time, &mut self.profile);
@@ -1016,6 +1876,7 @@ where
     }
 
     pub fn handle_comm(&mut self, e: CommOrExecRecord, timestamp: Option<u64>) {
+        self.pids_by_tid.insert(e.tid, e.pid);
         if e.is_execve {
             self.handle_exec(e, timestamp, None);
         } else {
@@ -1048,22 +1909,26 @@ where
 
         // eprintln!("Process execve: pid={}, tid={}, new name: {}", e.pid, e.tid, name);
 
-        // Mark the old thread / process as ended.
         if is_main {
-            self.processes.remove(
+            // The process keeps its track, and switches to the libraries of the new image.
+            if let Some(old_name) = self.processes.exec_process(
                 e.pid,
+                timestamp_mono,
                 timestamp,
+                name.clone(),
                 &mut self.profile,
-                &mut self.jit_category_manager,
-                &self.timestamp_converter,
-            );
-            self.processes.recycle_or_get_new(
-                e.pid,
-                Some(name.to_string()),
-                timestamp,
-                &mut self.profile,
-            );
+            ) {
+                let process = self.processes.get_by_pid(e.pid, &mut self.profile);
+                let main_thread = process.threads.main_thread.profile_thread;
+                let marker = ExecMarker {
+                    old_name: self.profile.handle_for_string(&old_name),
+                    new_name: self.profile.handle_for_string(&name),
+                };
+                self.profile
+                    .add_marker(main_thread, MarkerTiming::Instant(timestamp), marker);
+            }
         } else {
+            // Mark the old thread as ended.
             eprintln!(
                 "Unexpected is_execve on non-main thread! pid: {}, tid: {}",
                 e.pid, e.tid
@@ -1177,7 +2042,7 @@ where
                 // Take a guess at the vmlinux debug file path.
                 format!("/usr/lib/debug/boot/vmlinux-{linux_version}")
             }
-            _ => path.clone(),
+            _ => kernel_module_debug_path(&path).unwrap_or_else(|| path.clone()),
         };
 
         let symbol_table = if dso_key == DsoKey::Kernel {
@@ -1186,6 +2051,7 @@ where
                     if build_id == &kernel_symbols.build_id && kernel_symbols.base_avma != 0 =>
                 {
                     // Run `echo '0' | sudo tee /proc/sys/kernel/kptr_restrict` to get here without root.
+                    self.uses_running_kernel_symbols = true;
                     Some(kernel_symbols.symbol_table.clone())
                 }
                 _ => {
@@ -1215,6 +2081,15 @@ where
                 .kernel_modules
                 .get(path_slice)
                 .map(|s| s.symbol_table.clone())
+                .or_else(|| {
+                    let kernel_symbols = self
+                        .kernel_symbols
+                        .as_ref()
+                        .filter(|_| self.uses_running_kernel_symbols)?;
+                    let symbol_table = kernel_symbols
+                        .module_symbol_table(dso_key.name(), base_address..base_address + len)?;
+                    Some(Arc::new(symbol_table))
+                })
         };
 
         let lib_handle = self.profile.add_lib(LibraryInfo {
@@ -1242,7 +2117,49 @@ where
                 base_address,
                 end_address,
             });
-        } else if let Some(kernel_image_mapping) = &self.kernel_image_mapping {
+            if self.uses_running_kernel_symbols {
+                self.add_bpf_program_mappings();
+            }
+        } else {
+            self.fix_kernel_image_overlap(base_address);
+        }
+    }
+
+    /// Adds the JIT-compiled BPF programs from the running kernel's
+    /// /proc/kallsyms as functions of a "[bpf]" library. perf.data files only
+    /// describe them in KSYMBOL records, which we can't read.
+    fn add_bpf_program_mappings(&mut self) {
+        let Some(kernel_symbols) = &self.kernel_symbols else {
+            return;
+        };
+        let bpf_programs = kernel_symbols.bpf_programs.clone();
+        if bpf_programs.is_empty() {
+            return;
+        }
+        let mut bpf_lib = SyntheticJitLibrary::new(
+            "[bpf]".to_string(),
+            CategoryHandle::OTHER.into(),
+            &mut self.profile,
+            false,
+        );
+        for program in bpf_programs {
+            let size = (program.end_avma - program.start_avma) as u32;
+            let relative_address = bpf_lib.add_function(program.name, size);
+            self.profile.add_kernel_lib_mapping(
+                bpf_lib.lib_handle(),
+                program.start_avma,
+                program.end_avma,
+                relative_address,
+            );
+            self.fix_kernel_image_overlap(program.start_avma);
+        }
+        bpf_lib.finish_and_set_symbol_table(&mut self.profile);
+    }
+
+    /// Called after adding a kernel lib mapping which is not the main kernel
+    /// image, at `base_address`.
+    fn fix_kernel_image_overlap(&mut self, base_address: u64) {
+        if let Some(kernel_image_mapping) = &self.kernel_image_mapping {
             // We added a kernel module which is not the main kernel image.
             // See if the module overlaps with it. This can happen when the main kernel
             // image is advertised with a bad address range. For example, in profiles from
@@ -1329,6 +2246,7 @@ where
         let Some(path) = path_from_unix_bytes(path_slice) else {
             return;
         };
+        let _phase = self_profile::phase("Load library", Some(&path.to_string_lossy()));
 
         let mut mapping_info = MappingInfo::new_elf(path, avma_range);
         if path_slice.is_empty() {
@@ -1447,6 +2365,10 @@ where
                 return;
             }
 
+            if file.format() == BinaryFormat::Elf {
+                process.is_32bit = !file.is_64();
+            }
+
             let module_section_info =
                 Self::module_section_info_with_object(Some(mmap.clone()), &file);
             let Some(library_info) =
@@ -1467,7 +2389,7 @@ where
             );
 
             let relative_address_at_start = (mapping_start_avma - module.base_avma()) as u32;
-            process.unwinder.add_module(module);
+            process.add_unwinder_module(avma_range.start()..avma_range.end(), module);
             let lib_handle = self.profile.add_lib(library_info);
 
             if name.starts_with("jitted-") && name.ends_with(".so") {
@@ -1496,8 +2418,9 @@ where
             return;
         }
 
-        // Case 3: This is the VDSO mapping.
-        if name == "[vdso]" {
+        // Case 3: This is the VDSO mapping. Our own VDSO is only the same as
+        // the process's if the process has the same bitness as we do.
+        if name == "[vdso]" && !process.is_32bit {
             if let Some(vdso) = VdsoObject::shared_instance_for_this_process() {
                 if expected_code_id.as_ref().is_some_and(|expected_code_id| {
                     !Self::code_id_matches(Some(vdso.code_id()), expected_code_id, &path)
@@ -1527,7 +2450,7 @@ where
                 );
 
                 let relative_address_at_start = (mapping_start_avma - module.base_avma()) as u32;
-                process.unwinder.add_module(module);
+                process.add_unwinder_module(avma_range.start()..avma_range.end(), module);
                 let lib_handle = self.profile.add_lib(library_info);
 
                 process.add_regular_lib_mapping(
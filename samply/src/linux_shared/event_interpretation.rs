@@ -9,8 +9,20 @@ pub enum KnownEvent {
     RssStat,
     MmapEnter,
     MmapExit,
+    MunmapEnter,
+    CloneExit,
     MprotectEnter,
     PageFault,
+    /// A uprobe on `__cxa_throw`, the function which throws C++ exceptions.
+    CxxThrow,
+    /// A uprobe on `rust_panic`, which the Rust standard library calls for
+    /// every panic after the panic hook has run.
+    RustPanic,
+    IrqHandlerEntry,
+    IrqHandlerExit,
+    SoftirqEntry,
+    SoftirqExit,
+    SchedWakeup,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -24,10 +36,18 @@ pub enum OffCpuIndicator {
 
 #[derive(Debug, Clone)]
 pub struct EventInterpretation {
-    pub main_event_attr_index: usize,
+    /// The event whose samples are the profile's samples: the first event
+    /// which isn't a tracepoint or perf's dummy tracking event. If there's no
+    /// such event but there are context switches, e.g. for
+    /// `perf record -e 'sched:*'`, there's no main event, and the samples come
+    /// from the context switches.
+    pub main_event_attr_index: Option<usize>,
     #[allow(unused)]
     pub main_event_name: String,
     pub sampling_is_time_based: Option<u64>,
+    /// Whether the period of the main event's samples is in nanoseconds, i.e.
+    /// whether it is a clock event.
+    pub sample_period_is_nanos: bool,
     pub off_cpu_indicator: Option<OffCpuIndicator>,
     pub sched_switch_attr_index: Option<usize>,
     pub known_event_indices: HashMap<usize, KnownEvent>,
@@ -36,41 +56,76 @@ pub struct EventInterpretation {
 
 impl EventInterpretation {
     pub fn divine_from_attrs(attrs: &[AttributeDescription]) -> Self {
-        let main_event_attr_index = 0;
-        let main_event_name = attrs[0]
-            .name
-            .as_deref()
-            .unwrap_or("<unnamed event>")
-            .to_string();
-        let sampling_is_time_based = match (attrs[0].attr.type_, attrs[0].attr.sampling_policy) {
-            (_, SamplingPolicy::NoSampling) => {
+        // perf only asks for the context switch records on one of the events.
+        let have_context_switches = attrs
+            .iter()
+            .any(|attr_desc| attr_desc.attr.flags.contains(AttrFlags::CONTEXT_SWITCH));
+        let sched_switch_attr_index = attrs
+            .iter()
+            .position(|attr_desc| attr_desc.name.as_deref() == Some("sched:sched_switch"));
+        let off_cpu_indicator = match (have_context_switches, sched_switch_attr_index) {
+            (true, _) => Some(OffCpuIndicator::ContextSwitches),
+            (false, Some(_)) => Some(OffCpuIndicator::SchedSwitchAndSamples),
+            _ => None,
+        };
+        let is_tracking_event = |attr_desc: &AttributeDescription| {
+            matches!(
+                attr_desc.attr.type_,
+                PerfEventType::Software(SoftwareCounterType::Dummy)
+            )
+        };
+        let main_event_attr_index = attrs
+            .iter()
+            .position(|attr_desc| {
+                !is_tracking_event(attr_desc)
+                    && !matches!(attr_desc.attr.type_, PerfEventType::Tracepoint(_))
+            })
+            .or_else(|| {
+                // Without context switches, the hits of the first tracepoint
+                // are the samples, e.g. for `perf record -e probe:malloc -g`.
+                match off_cpu_indicator {
+                    Some(_) => None,
+                    None => attrs
+                        .iter()
+                        .position(|attr_desc| !is_tracking_event(attr_desc)),
+                }
+            });
+        let main_attr = main_event_attr_index.map(|index| &attrs[index].attr);
+        let main_event_name = match main_event_attr_index {
+            Some(index) => attrs[index].name.as_deref().unwrap_or("<unnamed event>"),
+            None => "<no sampling event>",
+        }
+        .to_string();
+        let sampling_is_time_based = match main_attr.map(|attr| (attr.type_, attr.sampling_policy))
+        {
+            None => None,
+            Some((_, SamplingPolicy::NoSampling)) => {
                 panic!("Can only convert profiles with sampled events")
             }
-            (_, SamplingPolicy::Frequency(freq)) => {
+            Some((_, SamplingPolicy::Frequency(freq))) => {
                 let nanos = 1_000_000_000 / freq;
                 Some(nanos)
             }
-            (
+            Some((
                 PerfEventType::Software(
                     SoftwareCounterType::CpuClock | SoftwareCounterType::TaskClock,
                 ),
                 SamplingPolicy::Period(period),
-            ) => {
+            )) => {
                 // Assume that we're using a nanosecond clock. TODO: Check how we can know this for sure
                 let nanos = u64::from(period);
                 Some(nanos)
             }
-            (_, SamplingPolicy::Period(_)) => None,
-        };
-        let have_context_switches = attrs[0].attr.flags.contains(AttrFlags::CONTEXT_SWITCH);
-        let sched_switch_attr_index = attrs
-            .iter()
-            .position(|attr_desc| attr_desc.name.as_deref() == Some("sched:sched_switch"));
-        let off_cpu_indicator = match (have_context_switches, sched_switch_attr_index) {
-            (true, _) => Some(OffCpuIndicator::ContextSwitches),
-            (false, Some(_)) => Some(OffCpuIndicator::SchedSwitchAndSamples),
-            _ => None,
+            Some((_, SamplingPolicy::Period(_))) => None,
         };
+        let sample_period_is_nanos = main_attr.is_some_and(|attr| {
+            matches!(
+                attr.type_,
+                PerfEventType::Software(
+                    SoftwareCounterType::CpuClock | SoftwareCounterType::TaskClock
+                )
+            )
+        });
         let mut known_event_indices = HashMap::new();
 
         let known_events = [
@@ -79,6 +134,15 @@ impl EventInterpretation {
             ("syscalls:sys_enter_mprotect", KnownEvent::MprotectEnter),
             ("syscalls:sys_enter_mmap", KnownEvent::MmapEnter),
             ("syscalls:sys_exit_mmap", KnownEvent::MmapExit),
+            ("syscalls:sys_enter_munmap", KnownEvent::MunmapEnter),
+            ("syscalls:sys_exit_clone", KnownEvent::CloneExit),
+            ("syscalls:sys_exit_clone3", KnownEvent::CloneExit),
+            ("irq:irq_handler_entry", KnownEvent::IrqHandlerEntry),
+            ("irq:irq_handler_exit", KnownEvent::IrqHandlerExit),
+            ("irq:softirq_entry", KnownEvent::SoftirqEntry),
+            ("irq:softirq_exit", KnownEvent::SoftirqExit),
+            ("sched:sched_wakeup", KnownEvent::SchedWakeup),
+            ("sched:sched_wakeup_new", KnownEvent::SchedWakeup),
         ];
 
         for (event_name, event) in known_events {
@@ -90,6 +154,24 @@ impl EventInterpretation {
             }
         }
 
+        // Probes are named after the group which `perf probe` picked, e.g.
+        // "probe_libstdc++:__cxa_throw", so only look at the part after the colon.
+        for (index, attr_desc) in attrs.iter().enumerate() {
+            let Some((_group, probe_name)) = attr_desc
+                .name
+                .as_deref()
+                .and_then(|name| name.split_once(':'))
+            else {
+                continue;
+            };
+            let event = match probe_name {
+                "__cxa_throw" => KnownEvent::CxxThrow,
+                "rust_panic" => KnownEvent::RustPanic,
+                _ => continue,
+            };
+            known_event_indices.entry(index).or_insert(event);
+        }
+
         let event_names = attrs
             .iter()
             .enumerate()
@@ -105,6 +187,7 @@ impl EventInterpretation {
             main_event_attr_index,
             main_event_name,
             sampling_is_time_based,
+            sample_period_is_nanos,
             off_cpu_indicator,
             sched_switch_attr_index,
             known_event_indices,
@@ -0,0 +1,294 @@
+//! Frame pointer unwinders for riscv64 and 32-bit ARM.
+//!
+//! framehop only has unwinders for x86_64 and aarch64. On these other
+//! architectures, we walk the frame pointer chain instead, so binaries need
+//! to be compiled with frame pointers (`-fno-omit-frame-pointer`, or
+//! `-C force-frame-pointers=yes` for Rust) to get full stacks.
+//!
+//! The same goes for 32-bit processes on x86_64 and aarch64, whose code the
+//! 64-bit unwinders don't understand. Their stacks are walked with
+//! [`UnwinderArm`]: x86's `push ebp; mov ebp, esp` creates the same frame
+//! record as ARM's `push {fp, lr}; mov fp, sp`.
+
+use std::marker::PhantomData;
+use std::ops::{Deref, Range};
+
+use framehop::{Error, FrameAddress, Module, Unwinder};
+
+/// Keeps track of the address ranges of the added modules, which is all that's
+/// needed to answer `max_known_code_address`.
+#[derive(Debug, Clone)]
+struct ModuleRanges(Vec<Range<u64>>);
+
+impl ModuleRanges {
+    fn add(&mut self, range: Range<u64>) {
+        self.0.push(range);
+    }
+
+    fn remove(&mut self, start: u64) {
+        self.0.retain(|range| range.start != start);
+    }
+
+    fn max_end(&self) -> u64 {
+        self.0.iter().map(|range| range.end).max().unwrap_or(0)
+    }
+}
+
+/// The frame pointer unwinders don't cache anything.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FramePointerCache;
+
+impl FramePointerCache {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnwindRegsRiscv64 {
+    sp: u64,
+    fp: u64,
+}
+
+impl UnwindRegsRiscv64 {
+    pub fn new(sp: u64, fp: u64) -> Self {
+        Self { sp, fp }
+    }
+}
+
+/// Unwinds riscv64 stacks. With frame pointers, `s0` points just past the
+/// frame record, which holds the return address at `s0 - 8` and the caller's
+/// `s0` at `s0 - 16`.
+pub struct UnwinderRiscv64<D> {
+    modules: ModuleRanges,
+    _data: PhantomData<D>,
+}
+
+impl<D> Default for UnwinderRiscv64<D> {
+    fn default() -> Self {
+        Self {
+            modules: ModuleRanges(Vec::new()),
+            _data: PhantomData,
+        }
+    }
+}
+
+impl<D> Clone for UnwinderRiscv64<D> {
+    fn clone(&self) -> Self {
+        Self {
+            modules: self.modules.clone(),
+            _data: PhantomData,
+        }
+    }
+}
+
+impl<D: Deref<Target = [u8]>> Unwinder for UnwinderRiscv64<D> {
+    type UnwindRegs = UnwindRegsRiscv64;
+    type Cache = FramePointerCache;
+    type Module = Module<D>;
+
+    fn add_module(&mut self, module: Module<D>) {
+        self.modules.add(module.avma_range());
+    }
+
+    fn remove_module(&mut self, module_avma_range_start: u64) {
+        self.modules.remove(module_avma_range_start);
+    }
+
+    fn max_known_code_address(&self) -> u64 {
+        self.modules.max_end()
+    }
+
+    fn unwind_frame<F>(
+        &self,
+        _address: FrameAddress,
+        regs: &mut UnwindRegsRiscv64,
+        _cache: &mut FramePointerCache,
+        read_stack: &mut F,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: FnMut(u64) -> Result<u64, ()>,
+    {
+        let fp = regs.fp;
+        if fp == 0 {
+            return Ok(None);
+        }
+        if fp <= regs.sp || fp % 8 != 0 {
+            return Err(Error::FramepointerUnwindingMovedBackwards);
+        }
+        let return_address = read_stack(fp - 8).map_err(|_| Error::CouldNotReadStack(fp - 8))?;
+        let caller_fp = read_stack(fp - 16).map_err(|_| Error::CouldNotReadStack(fp - 16))?;
+        if return_address == 0 {
+            return Ok(None);
+        }
+        regs.sp = fp;
+        regs.fp = caller_fp;
+        Ok(Some(return_address))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnwindRegsArm {
+    /// The stack pointer at the time of the sample. The stack is read in
+    /// 8-byte words counted from here, so 4-byte reads need to know it.
+    stack_base: u64,
+    sp: u64,
+    fp: u64,
+}
+
+impl UnwindRegsArm {
+    pub fn new(sp: u64, fp: u64) -> Self {
+        Self {
+            stack_base: sp,
+            sp,
+            fp,
+        }
+    }
+}
+
+/// Unwinds 32-bit ARM stacks. This expects the AAPCS frame record which clang
+/// and rustc emit with frame pointers: `push {fp, lr}; mov fp, sp`, so `fp`
+/// points at the caller's `fp`, and the return address is at `fp + 4`.
+pub struct UnwinderArm<D> {
+    modules: ModuleRanges,
+    _data: PhantomData<D>,
+}
+
+impl<D> Default for UnwinderArm<D> {
+    fn default() -> Self {
+        Self {
+            modules: ModuleRanges(Vec::new()),
+            _data: PhantomData,
+        }
+    }
+}
+
+impl<D> Clone for UnwinderArm<D> {
+    fn clone(&self) -> Self {
+        Self {
+            modules: self.modules.clone(),
+            _data: PhantomData,
+        }
+    }
+}
+
+/// Reads the 4-byte word at `address`. `read_stack` returns 8-byte words at
+/// 8-byte offsets from `stack_base`.
+fn read_u32<F>(stack_base: u64, address: u64, read_stack: &mut F) -> Result<u64, Error>
+where
+    F: FnMut(u64) -> Result<u64, ()>,
+{
+    let offset = address
+        .checked_sub(stack_base)
+        .ok_or(Error::CouldNotReadStack(address))?;
+    let word =
+        read_stack(stack_base + offset / 8 * 8).map_err(|_| Error::CouldNotReadStack(address))?;
+    // ARM Linux is little-endian.
+    if offset % 8 == 0 {
+        Ok(word & 0xffff_ffff)
+    } else {
+        Ok(word >> 32)
+    }
+}
+
+impl<D: Deref<Target = [u8]>> Unwinder for UnwinderArm<D> {
+    type UnwindRegs = UnwindRegsArm;
+    type Cache = FramePointerCache;
+    type Module = Module<D>;
+
+    fn add_module(&mut self, module: Module<D>) {
+        self.modules.add(module.avma_range());
+    }
+
+    fn remove_module(&mut self, module_avma_range_start: u64) {
+        self.modules.remove(module_avma_range_start);
+    }
+
+    fn max_known_code_address(&self) -> u64 {
+        self.modules.max_end()
+    }
+
+    fn unwind_frame<F>(
+        &self,
+        _address: FrameAddress,
+        regs: &mut UnwindRegsArm,
+        _cache: &mut FramePointerCache,
+        read_stack: &mut F,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: FnMut(u64) -> Result<u64, ()>,
+    {
+        let fp = regs.fp;
+        if fp == 0 {
+            return Ok(None);
+        }
+        if fp < regs.sp || fp % 4 != 0 {
+            return Err(Error::FramepointerUnwindingMovedBackwards);
+        }
+        let caller_fp = read_u32(regs.stack_base, fp, read_stack)?;
+        let return_address = read_u32(regs.stack_base, fp + 4, read_stack)?;
+        if return_address == 0 {
+            return Ok(None);
+        }
+        regs.sp = fp + 8;
+        regs.fp = caller_fp;
+        Ok(Some(return_address))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn riscv64_walks_frame_records() {
+        // Two frame records: one at 0x1010 (fp 0x1020), one at 0x1030 (fp 0x1040).
+        let sp = 0x1000;
+        let stack: Vec<u64> = vec![0, 0, 0x1040, 0x5000, 0, 0, 0, 0x6000];
+        let mut read_stack = |addr: u64| {
+            let index = usize::try_from((addr.checked_sub(sp).ok_or(())?) / 8).map_err(|_| ())?;
+            stack.get(index).copied().ok_or(())
+        };
+        let unwinder = UnwinderRiscv64::<Vec<u8>>::default();
+        let mut cache = FramePointerCache::new();
+        let mut frames = unwinder.iter_frames(
+            0x4000,
+            UnwindRegsRiscv64::new(sp, 0x1020),
+            &mut cache,
+            &mut read_stack,
+        );
+        assert_eq!(
+            frames.next(),
+            Ok(Some(FrameAddress::InstructionPointer(0x4000)))
+        );
+        assert_eq!(frames.next().unwrap().unwrap().address(), 0x5000);
+        assert_eq!(frames.next().unwrap().unwrap().address(), 0x6000);
+    }
+
+    #[test]
+    fn arm_reads_unaligned_words() {
+        // Frame record at 0x1004: caller fp 0x100c, return address 0x5000.
+        // Frame record at 0x100c: caller fp 0, return address 0x6000.
+        let sp = 0x1000;
+        let stack: Vec<u64> = vec![0x100c_0000_0000, 0x0000_0000_0000_5000, 0x6000];
+        let mut read_stack = |addr: u64| {
+            let index = usize::try_from((addr.checked_sub(sp).ok_or(())?) / 8).map_err(|_| ())?;
+            stack.get(index).copied().ok_or(())
+        };
+        let unwinder = UnwinderArm::<Vec<u8>>::default();
+        let mut cache = FramePointerCache::new();
+        let mut frames = unwinder.iter_frames(
+            0x4000,
+            UnwindRegsArm::new(sp, 0x1004),
+            &mut cache,
+            &mut read_stack,
+        );
+        assert_eq!(
+            frames.next(),
+            Ok(Some(FrameAddress::InstructionPointer(0x4000)))
+        );
+        assert_eq!(frames.next().unwrap().unwrap().address(), 0x5000);
+        assert_eq!(frames.next().unwrap().unwrap().address(), 0x6000);
+        assert_eq!(frames.next(), Ok(None));
+    }
+}
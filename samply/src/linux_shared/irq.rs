@@ -0,0 +1,226 @@
+use byteorder::ByteOrder;
+use fxprof_processed_profile::{MarkerTiming, ProcessHandle, Profile, ThreadHandle, Timestamp};
+use linux_perf_data::{linux_perf_event_reader, Endianness};
+use linux_perf_event_reader::RawData;
+
+use crate::shared::process_sample_data::IrqMarker;
+use crate::shared::timestamp_converter::TimestampConverter;
+
+/// The names of the softirq vectors, indexed by the `vec` field of the
+/// `irq:softirq_entry` and `irq:softirq_exit` tracepoints.
+const SOFTIRQ_NAMES: [&str; 10] = [
+    "HI", "TIMER", "NET_TX", "NET_RX", "BLOCK", "IRQ_POLL", "TASKLET", "SCHED", "HRTIMER", "RCU",
+];
+
+/// An interrupt handler or a softirq which is running on a CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqKind {
+    /// A hardware interrupt, with its IRQ number.
+    Hard(i32),
+    /// A softirq, with its vector number.
+    Soft(u32),
+}
+
+impl IrqKind {
+    fn marker_name(self) -> &'static str {
+        match self {
+            IrqKind::Hard(_) => "IRQ",
+            IrqKind::Soft(_) => "Softirq",
+        }
+    }
+
+    fn number(self) -> i64 {
+        match self {
+            IrqKind::Hard(irq) => irq.into(),
+            IrqKind::Soft(vec) => vec.into(),
+        }
+    }
+}
+
+/// The name of a softirq vector, e.g. "NET_RX".
+pub fn softirq_name(vec: u32) -> String {
+    match SOFTIRQ_NAMES.get(vec as usize) {
+        Some(name) => name.to_string(),
+        None => format!("softirq {vec}"),
+    }
+}
+
+/// The IRQ number and the handler name of an `irq:irq_handler_entry`
+/// tracepoint.
+///
+/// ```
+/// # cat /sys/kernel/debug/tracing/events/irq/irq_handler_entry/format
+/// name: irq_handler_entry
+/// ID: 136
+/// format:
+///         field:unsigned short common_type;       offset:0;       size:2; signed:0;
+///         field:unsigned char common_flags;       offset:2;       size:1; signed:0;
+///         field:unsigned char common_preempt_count;       offset:3;       size:1; signed:0;
+///         field:int common_pid;   offset:4;       size:4; signed:1;
+///
+///         field:int irq;  offset:8;       size:4; signed:1;
+///         field:__data_loc char[] name;   offset:12;      size:4; signed:1;
+/// ```
+pub fn parse_irq_handler_entry(
+    data: RawData,
+    endian: Endianness,
+) -> Result<(i32, String), std::io::Error> {
+    match endian {
+        Endianness::LittleEndian => parse_irq_handler_entry_impl::<byteorder::LittleEndian>(data),
+        Endianness::BigEndian => parse_irq_handler_entry_impl::<byteorder::BigEndian>(data),
+    }
+}
+
+fn parse_irq_handler_entry_impl<O: ByteOrder>(
+    data: RawData,
+) -> Result<(i32, String), std::io::Error> {
+    let mut fields = data;
+    fields.skip(8)?;
+    let irq = fields.read_i32::<O>()?;
+    // A __data_loc field has the offset of the data from the start of the
+    // record in its low 16 bits, and the length in its high 16 bits.
+    let data_loc = fields.read_u32::<O>()?;
+    let (offset, len) = ((data_loc & 0xffff) as usize, (data_loc >> 16) as usize);
+    let mut name = data;
+    name.skip(offset)?;
+    let name = name.split_off_prefix(len)?.as_slice();
+    let name = name.split(|b| *b == 0).next().unwrap_or_default();
+    Ok((irq, String::from_utf8_lossy(name).into_owned()))
+}
+
+/// The IRQ number of an `irq:irq_handler_exit` tracepoint, or the vector
+/// number of an `irq:softirq_entry` or `irq:softirq_exit` tracepoint. Both are
+/// the first field after the common fields.
+///
+/// ```
+/// # cat /sys/kernel/debug/tracing/events/irq/softirq_entry/format
+/// name: softirq_entry
+/// ID: 134
+/// format:
+///         field:unsigned short common_type;       offset:0;       size:2; signed:0;
+///         field:unsigned char common_flags;       offset:2;       size:1; signed:0;
+///         field:unsigned char common_preempt_count;       offset:3;       size:1; signed:0;
+///         field:int common_pid;   offset:4;       size:4; signed:1;
+///
+///         field:unsigned int vec; offset:8;       size:4; signed:0;
+/// ```
+pub fn parse_irq_number(data: RawData, endian: Endianness) -> Result<u32, std::io::Error> {
+    let mut data = data;
+    data.skip(8)?;
+    match endian {
+        Endianness::LittleEndian => data.read_u32::<byteorder::LittleEndian>(),
+        Endianness::BigEndian => data.read_u32::<byteorder::BigEndian>(),
+    }
+}
+
+struct PendingIrq {
+    kind: IrqKind,
+    name: String,
+    start_timestamp_mono: u64,
+}
+
+struct IrqCpu {
+    thread_handle: ThreadHandle,
+    /// The interrupts which are running on this CPU, innermost last. A
+    /// hardware interrupt can interrupt a softirq.
+    pending: Vec<PendingIrq>,
+}
+
+/// The synthetic "IRQ CPU N" threads, which show the time each CPU spent in
+/// interrupt handlers and softirqs, as markers.
+pub struct IrqTracks {
+    start_time: Timestamp,
+    process_handle: ProcessHandle,
+    cpus: Vec<IrqCpu>,
+}
+
+impl IrqTracks {
+    pub fn new(start_time: Timestamp, profile: &mut Profile) -> Self {
+        let process_handle = profile.add_process("IRQ", 0, start_time);
+        Self {
+            start_time,
+            process_handle,
+            cpus: Vec::new(),
+        }
+    }
+
+    fn get_mut(&mut self, cpu: usize, profile: &mut Profile) -> &mut IrqCpu {
+        while self.cpus.len() <= cpu {
+            let i = self.cpus.len();
+            let thread_handle =
+                profile.add_thread(self.process_handle, i as u32, self.start_time, false);
+            profile.set_thread_name(thread_handle, &format!("IRQ CPU {i}"));
+            self.cpus.push(IrqCpu {
+                thread_handle,
+                pending: Vec::new(),
+            });
+        }
+        &mut self.cpus[cpu]
+    }
+
+    pub fn handle_entry(
+        &mut self,
+        cpu: u32,
+        kind: IrqKind,
+        name: String,
+        timestamp_mono: u64,
+        profile: &mut Profile,
+    ) {
+        self.get_mut(cpu as usize, profile)
+            .pending
+            .push(PendingIrq {
+                kind,
+                name,
+                start_timestamp_mono: timestamp_mono,
+            });
+    }
+
+    /// Adds the marker for the interrupt which ends on this CPU. Entries
+    /// whose exit got lost are dropped.
+    pub fn handle_exit(
+        &mut self,
+        cpu: u32,
+        kind: IrqKind,
+        timestamp_mono: u64,
+        converter: &TimestampConverter,
+        profile: &mut Profile,
+    ) {
+        let cpu = self.get_mut(cpu as usize, profile);
+        let Some(index) = cpu.pending.iter().rposition(|pending| pending.kind == kind) else {
+            return;
+        };
+        let pending = cpu.pending.split_off(index).swap_remove(0);
+        let timing = MarkerTiming::Interval(
+            converter.convert_time(pending.start_timestamp_mono),
+            converter.convert_time(timestamp_mono),
+        );
+        let marker = IrqMarker {
+            name: profile.handle_for_string(kind.marker_name()),
+            handler: profile.handle_for_string(&pending.name),
+            number: kind.number(),
+        };
+        profile.add_marker(cpu.thread_handle, timing, marker);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn irq_tracepoints() {
+        let mut entry = vec![0u8; 24];
+        entry[8..12].copy_from_slice(&42i32.to_le_bytes());
+        entry[12..16].copy_from_slice(&((5u32 << 16) | 16).to_le_bytes());
+        entry[16..21].copy_from_slice(b"eth0\0");
+        let (irq, name) =
+            parse_irq_handler_entry(RawData::Single(&entry), Endianness::LittleEndian).unwrap();
+        assert_eq!((irq, name.as_str()), (42, "eth0"));
+
+        let mut softirq = vec![0u8; 12];
+        softirq[8..12].copy_from_slice(&3u32.to_le_bytes());
+        let vec = parse_irq_number(RawData::Single(&softirq), Endianness::LittleEndian).unwrap();
+        assert_eq!(softirq_name(vec), "NET_RX");
+        assert_eq!(softirq_name(12), "softirq 12");
+    }
+}
@@ -1,8 +1,11 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use fxprof_processed_profile::{Symbol, SymbolTable};
+use linux_perf_data::linux_perf_event_reader::CpuMode;
+use linux_perf_data::DsoKey;
 use object::{elf, read, NativeEndian, Object};
 use read::elf::NoteIterator;
 
@@ -31,6 +34,24 @@ pub struct KernelSymbols {
     pub build_id: Vec<u8>,
     pub base_avma: u64,
     pub symbol_table: Arc<SymbolTable>,
+    /// The symbols of the loaded kernel modules, with absolute addresses, by
+    /// module name.
+    pub module_symbols: ModuleSymbols,
+    /// The JIT-compiled BPF programs which were loaded when the symbols were
+    /// read.
+    pub bpf_programs: Vec<BpfProgram>,
+}
+
+/// The (absolute address, name) symbols of each kernel module, by module name.
+pub type ModuleSymbols = HashMap<Vec<u8>, Vec<(u64, Vec<u8>)>>;
+
+/// A JIT-compiled BPF program, from a `bpf_prog_<tag>_<name>` entry in
+/// /proc/kallsyms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BpfProgram {
+    pub name: String,
+    pub start_avma: u64,
+    pub end_avma: u64,
 }
 
 impl KernelSymbols {
@@ -44,12 +65,117 @@ impl KernelSymbols {
             .map_err(KernelSymbolsError::CouldNotReadProcKallsyms)?;
         let (base_avma, symbol_table) = parse_kallsyms(&kallsyms)?;
         let symbol_table = Arc::new(symbol_table);
+        let (module_symbols, bpf_programs) = parse_kallsyms_modules(&kallsyms);
         Ok(KernelSymbols {
             build_id,
             base_avma,
             symbol_table,
+            module_symbols,
+            bpf_programs,
         })
     }
+
+    /// Returns the symbol table of a kernel module which is mapped at `range`,
+    /// if the module was loaded when the symbols were read. `dso_name` is the
+    /// module's name in perf's notation, e.g. "[snd-seq-device]".
+    pub fn module_symbol_table(
+        &self,
+        dso_name: &str,
+        range: std::ops::Range<u64>,
+    ) -> Option<SymbolTable> {
+        let symbols = self.module_symbols.get(&kallsyms_module_name(dso_name))?;
+        Some(relative_symbol_table(symbols, range))
+    }
+}
+
+/// Converts a module name in perf's notation, e.g. "[snd-seq-device]", into
+/// the name which /proc/kallsyms and /proc/modules use, e.g. "snd_seq_device".
+fn kallsyms_module_name(dso_name: &str) -> Vec<u8> {
+    let name = dso_name.trim_start_matches('[').trim_end_matches(']');
+    name.replace('-', "_").into_bytes()
+}
+
+/// The file extensions of compressed kernel modules.
+const COMPRESSED_MODULE_EXTENSIONS: &[&str] = &[".ko.zst", ".ko.xz", ".ko.gz"];
+
+/// Like `DsoKey::detect`, but also detects compressed kernel modules, such as
+/// "/lib/modules/6.8.0-31-generic/kernel/sound/core/snd-seq-device.ko.zst",
+/// which `DsoKey::detect` takes for the kernel image.
+pub fn detect_dso_key(path: &[u8], cpu_mode: CpuMode) -> Option<DsoKey> {
+    if cpu_mode == CpuMode::Kernel {
+        let path = String::from_utf8_lossy(path);
+        let file_name = path.rsplit('/').next().unwrap_or_default();
+        for extension in COMPRESSED_MODULE_EXTENSIONS {
+            if let Some(module_name) = file_name.strip_suffix(extension) {
+                return Some(DsoKey::KernelModule {
+                    name: format!("[{module_name}]"),
+                });
+            }
+        }
+    }
+    DsoKey::detect(path, cpu_mode)
+}
+
+/// Takes a guess at the path of a kernel module's debug file, which debug
+/// info packages install below /usr/lib/debug, without compression.
+pub fn kernel_module_debug_path(path: &str) -> Option<String> {
+    if !path.starts_with("/lib/modules/") {
+        return None;
+    }
+    let path = COMPRESSED_MODULE_EXTENSIONS
+        .iter()
+        .find_map(|extension| Some(format!("{}.ko", path.strip_suffix(extension)?)))
+        .unwrap_or_else(|| path.to_string());
+    Some(format!("/usr/lib/debug{path}"))
+}
+
+/// kallsyms has no symbol sizes. Assume that a BPF program is at most this
+/// big, if the next symbol doesn't start earlier.
+const BPF_PROGRAM_SIZE_GUESS: u64 = 0x1000;
+
+/// Collects the symbols of kernel modules and the BPF programs from
+/// /proc/kallsyms, where they're listed with the module name in brackets,
+/// e.g. "bpf_prog_6deef7357e7b4530_sd_devices\t[bpf]".
+fn parse_kallsyms_modules(data: &[u8]) -> (ModuleSymbols, Vec<BpfProgram>) {
+    let mut module_symbols = ModuleSymbols::new();
+    let mut bpf_program_starts = Vec::new();
+    let mut all_addresses = Vec::new();
+    for (address, name) in KallSymIter::new(data) {
+        all_addresses.push(address);
+        match split_module_name(name) {
+            (symbol_name, Some(b"bpf")) => {
+                if symbol_name.starts_with(b"bpf_prog_") {
+                    bpf_program_starts.push((address, symbol_name));
+                }
+            }
+            (symbol_name, Some(module_name)) => {
+                module_symbols
+                    .entry(module_name.to_vec())
+                    .or_default()
+                    .push((address, symbol_name.to_vec()));
+            }
+            (_, None) => {}
+        }
+    }
+    all_addresses.sort_unstable();
+
+    let bpf_programs = bpf_program_starts
+        .into_iter()
+        .filter(|(address, _)| *address != 0)
+        .map(|(start_avma, name)| {
+            let next_symbol_index = all_addresses.partition_point(|a| *a <= start_avma);
+            let end_avma = match all_addresses.get(next_symbol_index) {
+                Some(next) => (*next).min(start_avma + BPF_PROGRAM_SIZE_GUESS),
+                None => start_avma + BPF_PROGRAM_SIZE_GUESS,
+            };
+            BpfProgram {
+                name: String::from_utf8_lossy(name).into_owned(),
+                start_avma,
+                end_avma,
+            }
+        })
+        .collect();
+    (module_symbols, bpf_programs)
 }
 
 pub fn build_id_from_notes_section_data(section_data: &[u8]) -> Option<&[u8]> {
@@ -132,6 +258,157 @@ pub fn parse_kallsyms(data: &[u8]) -> Result<(u64, SymbolTable), KernelSymbolsEr
     Ok((text_addr, SymbolTable::new(symbols)))
 }
 
+/// A library of a virtual machine guest's kernel: the kernel image or a
+/// module, with symbols from the guest's /proc/kallsyms.
+#[derive(Debug)]
+pub struct GuestKernelLib {
+    pub name: String,
+    pub start_avma: u64,
+    pub end_avma: u64,
+    pub symbol_table: SymbolTable,
+}
+
+/// Splits a kallsyms name like `"tls_get_info_size\t[tls]"` into the symbol
+/// name and the module name.
+fn split_module_name(name: &[u8]) -> (&[u8], Option<&[u8]>) {
+    let Some(without_bracket) = name.strip_suffix(b"]") else {
+        return (name, None);
+    };
+    let Some(bracket_pos) = memchr::memrchr(b'[', without_bracket) else {
+        return (name, None);
+    };
+    let mut symbol_name = &name[..bracket_pos];
+    if !symbol_name.ends_with(b" ") && !symbol_name.ends_with(b"\t") {
+        return (name, None);
+    }
+    while let Some(trimmed) = symbol_name
+        .strip_suffix(b" ")
+        .or_else(|| symbol_name.strip_suffix(b"\t"))
+    {
+        symbol_name = trimmed;
+    }
+    (symbol_name, Some(&without_bracket[bracket_pos + 1..]))
+}
+
+/// Parses a copy of /proc/modules into (name, start address, size) entries.
+/// Modules whose address is hidden (shown as zero) are skipped.
+fn parse_proc_modules(data: &[u8]) -> Vec<(Vec<u8>, u64, u64)> {
+    // Format: <name> <size> <refcount> <dependencies> <state> <address>
+    data.split(|b| *b == b'\n')
+        .filter_map(|line| {
+            let line = std::str::from_utf8(line).ok()?;
+            let mut fields = line.split_ascii_whitespace();
+            let name = fields.next()?;
+            let size: u64 = fields.next()?.parse().ok()?;
+            let address = fields.last()?.strip_prefix("0x")?;
+            let address = u64::from_str_radix(address, 16).ok()?;
+            (address != 0).then(|| (name.as_bytes().to_vec(), address, size))
+        })
+        .collect()
+}
+
+/// Builds the libraries of a guest kernel from copies of the guest's
+/// /proc/kallsyms and, optionally, /proc/modules. Without /proc/modules, the
+/// address range of each module is guessed from its symbols.
+pub fn parse_guest_kernel_symbols(
+    kallsyms: &[u8],
+    proc_modules: Option<&[u8]>,
+) -> Result<Vec<GuestKernelLib>, KernelSymbolsError> {
+    let mut text_addr = None;
+    let mut image_symbols = Vec::new();
+    let mut module_symbols: BTreeMap<&[u8], Vec<(u64, &[u8])>> = BTreeMap::new();
+    for (address, name) in KallSymIter::new(kallsyms) {
+        match split_module_name(name) {
+            (symbol_name, Some(module_name)) => {
+                module_symbols
+                    .entry(module_name)
+                    .or_default()
+                    .push((address, symbol_name));
+            }
+            (b"_text", None) if text_addr.is_none() => {
+                text_addr = Some(address);
+                image_symbols.push((address, &b"_text"[..]));
+            }
+            (symbol_name, None) => image_symbols.push((address, symbol_name)),
+        }
+    }
+    let text_addr = text_addr.ok_or(KernelSymbolsError::NoTextSymbol)?;
+
+    let mut module_ranges: Vec<(Vec<u8>, u64, u64)> = match proc_modules {
+        Some(proc_modules) => parse_proc_modules(proc_modules)
+            .into_iter()
+            .map(|(name, start, size)| (name, start, start + size))
+            .collect(),
+        None => module_symbols
+            .iter()
+            .filter_map(|(name, symbols)| {
+                let start = symbols.iter().map(|(address, _)| *address).min()?;
+                let end = symbols.iter().map(|(address, _)| *address).max()?;
+                Some((name.to_vec(), start, end + GUEST_LAST_SYMBOL_SIZE_GUESS))
+            })
+            .collect(),
+    };
+    module_ranges.sort_by_key(|(_, start, _)| *start);
+
+    // The image ends after its last symbol, or where the first module after it
+    // starts.
+    let last_image_symbol = image_symbols
+        .iter()
+        .map(|(address, _)| *address)
+        .filter(|address| *address >= text_addr)
+        .max()
+        .unwrap_or(text_addr);
+    let mut image_end = last_image_symbol + GUEST_LAST_SYMBOL_SIZE_GUESS;
+    if let Some((_, next_module_start, _)) = module_ranges
+        .iter()
+        .find(|(_, start, _)| *start > text_addr && *start < image_end)
+    {
+        image_end = *next_module_start;
+    }
+
+    let mut libs = vec![GuestKernelLib {
+        name: "[guest.kernel.kallsyms]".to_string(),
+        start_avma: text_addr,
+        end_avma: image_end,
+        symbol_table: relative_symbol_table(&image_symbols, text_addr..image_end),
+    }];
+    for (name, start, end) in module_ranges {
+        let symbols = module_symbols
+            .get(name.as_slice())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        libs.push(GuestKernelLib {
+            name: format!("[guest.{}]", String::from_utf8_lossy(&name)),
+            start_avma: start,
+            end_avma: end,
+            symbol_table: relative_symbol_table(symbols, start..end),
+        });
+    }
+    Ok(libs)
+}
+
+/// kallsyms has no symbol sizes. Assume that the last symbol of the kernel
+/// image or of a module is at most this big.
+const GUEST_LAST_SYMBOL_SIZE_GUESS: u64 = 0x1000;
+
+fn relative_symbol_table<N: AsRef<[u8]>>(
+    symbols: &[(u64, N)],
+    range: std::ops::Range<u64>,
+) -> SymbolTable {
+    let symbols = symbols
+        .iter()
+        .filter(|(address, _)| range.contains(address))
+        .filter_map(|(address, name)| {
+            Some(Symbol {
+                address: u32::try_from(address - range.start).ok()?,
+                size: None,
+                name: String::from_utf8_lossy(name.as_ref()).to_string(),
+            })
+        })
+        .collect();
+    SymbolTable::new(symbols)
+}
+
 /// Match a hex string, parse it to a u32 or a u64.
 fn hex_str<T: std::ops::Shl<T, Output = T> + std::ops::BitOr<T, Output = T> + From<u8>>(
     input: &[u8],
@@ -171,8 +448,14 @@ pub fn kernel_module_build_id(path: &Path, binary_lookup_dirs: &[PathBuf]) -> Op
 #[cfg(test)]
 mod test {
     use debugid::CodeId;
+    use linux_perf_data::linux_perf_event_reader::CpuMode;
+    use linux_perf_data::DsoKey;
 
-    use super::build_id_from_notes_section_data;
+    use super::{
+        build_id_from_notes_section_data, detect_dso_key, kallsyms_module_name,
+        kernel_module_debug_path, parse_guest_kernel_symbols, parse_kallsyms_modules,
+        relative_symbol_table,
+    };
     use crate::linux_shared::kernel_symbols::parse_kallsyms;
 
     #[test]
@@ -343,4 +626,80 @@ ffff80000b5c5744 t bpf_prog_654d7024997e7811   [bpf]"#;
             "tegra_clk_periph_fixed_is_enabled"
         );
     }
+
+    #[test]
+    fn guest_kernel_symbols() {
+        let kallsyms = b"ffffffff81000000 T _text
+ffffffff81000010 T start_kernel
+ffffffff81000200 T do_idle
+ffffffffc0a01000 t vmx_vcpu_run\t[kvm_intel]
+ffffffffc0a01400 t vmx_handle_exit\t[kvm_intel]
+ffffffffc0b00000 t tls_get_info_size\t[tls]";
+        let proc_modules = b"kvm_intel 380928 0 - Live 0xffffffffc0a00000
+tls 118784 0 - Live 0xffffffffc0b00000
+hidden 4096 0 - Live 0x0000000000000000";
+
+        let libs = parse_guest_kernel_symbols(kallsyms, Some(proc_modules)).unwrap();
+        assert_eq!(libs.len(), 3);
+        assert_eq!(libs[0].name, "[guest.kernel.kallsyms]");
+        assert_eq!(libs[0].start_avma, 0xffffffff81000000);
+        assert_eq!(libs[0].end_avma, 0xffffffff81001200);
+        assert_eq!(&libs[0].symbol_table.lookup(0x210).unwrap().name, "do_idle");
+        assert_eq!(libs[1].name, "[guest.kvm_intel]");
+        assert_eq!(libs[1].start_avma, 0xffffffffc0a00000);
+        assert_eq!(libs[1].end_avma, 0xffffffffc0a00000 + 380928);
+        assert_eq!(
+            &libs[1].symbol_table.lookup(0x1404).unwrap().name,
+            "vmx_handle_exit"
+        );
+
+        // Without /proc/modules, the module ranges are guessed from the symbols.
+        let libs = parse_guest_kernel_symbols(kallsyms, None).unwrap();
+        assert_eq!(libs[1].name, "[guest.kvm_intel]");
+        assert_eq!(libs[1].start_avma, 0xffffffffc0a01000);
+        assert_eq!(libs[1].end_avma, 0xffffffffc0a02400);
+    }
+
+    #[test]
+    fn module_and_bpf_symbols() {
+        let kallsyms = b"ffffffff81000000 T _text
+ffffffff81000010 T start_kernel
+ffffffffc0a01000 t seq_open\t[snd_seq_device]
+ffffffffc0a01400 t seq_release\t[snd_seq_device]
+ffffffffc0b00000 t bpf_prog_6deef7357e7b4530_sd_devices\t[bpf]
+ffffffffc0b00200 t bpf_prog_654d7024997e7811_sd_fw_egress\t[bpf]
+ffffffffc0b10000 t bpf_trampoline_6442\t[bpf]";
+        let (module_symbols, bpf_programs) = parse_kallsyms_modules(kallsyms);
+        assert_eq!(kallsyms_module_name("[snd-seq-device]"), b"snd_seq_device");
+        let symbols = &module_symbols[&b"snd_seq_device"[..]];
+        let symbol_table = relative_symbol_table(symbols, 0xffffffffc0a00000..0xffffffffc0a10000);
+        assert_eq!(&symbol_table.lookup(0x1404).unwrap().name, "seq_release");
+        assert!(!module_symbols.contains_key(&b"bpf"[..]));
+
+        assert_eq!(bpf_programs.len(), 2);
+        assert_eq!(bpf_programs[0].name, "bpf_prog_6deef7357e7b4530_sd_devices");
+        assert_eq!(bpf_programs[0].start_avma, 0xffffffffc0b00000);
+        assert_eq!(bpf_programs[0].end_avma, 0xffffffffc0b00200);
+        assert_eq!(bpf_programs[1].end_avma, 0xffffffffc0b01200);
+    }
+
+    #[test]
+    fn compressed_kernel_modules() {
+        let path = b"/lib/modules/6.8.0-31-generic/kernel/sound/core/snd-seq-device.ko.zst";
+        assert_eq!(
+            detect_dso_key(path, CpuMode::Kernel),
+            Some(DsoKey::KernelModule {
+                name: "[snd-seq-device]".to_string()
+            })
+        );
+        assert_eq!(
+            detect_dso_key(b"[kernel.kallsyms]_text", CpuMode::Kernel),
+            Some(DsoKey::Kernel)
+        );
+        assert_eq!(
+            kernel_module_debug_path(std::str::from_utf8(path).unwrap()).as_deref(),
+            Some("/usr/lib/debug/lib/modules/6.8.0-31-generic/kernel/sound/core/snd-seq-device.ko")
+        );
+        assert_eq!(kernel_module_debug_path("[tls]"), None);
+    }
 }
@@ -2,7 +2,9 @@ mod avma_range;
 mod convert_regs;
 mod converter;
 mod event_interpretation;
+mod frame_pointer_unwinder;
 mod injected_jit_object;
+mod irq;
 mod kernel_symbols;
 mod mmap_range_or_vec;
 mod object_rewriter;
@@ -11,13 +13,23 @@ mod process;
 mod process_threads;
 mod processes;
 mod rss_stat;
+mod sched_wakeup;
 mod svma_file_range;
 mod thread;
+mod tracepoint_format;
 #[allow(unused)]
 pub mod vdso;
+mod vm_syscalls;
 
-pub use convert_regs::{ConvertRegs, ConvertRegsAarch64, ConvertRegsX86_64};
-pub use converter::Converter;
+pub use convert_regs::{
+    ConvertRegs, ConvertRegsAarch64, ConvertRegsArm, ConvertRegsRiscv64, ConvertRegsX86_64,
+};
+pub use converter::{Converter, RecordedProbe};
 #[allow(unused)]
 pub use event_interpretation::{EventInterpretation, KnownEvent, OffCpuIndicator};
+pub use frame_pointer_unwinder::{FramePointerCache, UnwinderArm, UnwinderRiscv64};
+pub use kernel_symbols::{parse_guest_kernel_symbols, GuestKernelLib};
 pub use mmap_range_or_vec::MmapRangeOrVec;
+#[allow(unused)]
+pub use svma_file_range::compute_vma_bias;
+pub use tracepoint_format::{parse_tracing_data, TracepointFormat};
@@ -1,4 +1,7 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use framehop::Unwinder;
 use fxprof_processed_profile::{
@@ -14,7 +17,8 @@ use crate::shared::jit_function_recycler::JitFunctionRecycler;
 use crate::shared::jitdump_manager::JitDumpManager;
 use crate::shared::lib_mappings::{LibMappingAdd, LibMappingInfo, LibMappingOp, LibMappingOpQueue};
 use crate::shared::marker_file::get_markers;
-use crate::shared::perf_map::try_load_perf_map;
+use crate::shared::open_handles::OpenHandlesCounter;
+use crate::shared::perf_map::PerfMapProcessor;
 use crate::shared::process_sample_data::{MarkerSpanOnThread, ProcessSampleData};
 use crate::shared::recycling::{ProcessRecyclingData, ThreadRecycler};
 use crate::shared::synthetic_jit_library::SyntheticJitLibrary;
@@ -23,12 +27,20 @@ use crate::shared::unresolved_samples::UnresolvedSamples;
 
 pub struct Process<U> {
     pub profile_process: ProcessHandle,
-    pub unwinder: U,
+    /// Shared with the parent process after a fork, until either process maps
+    /// or unmaps a library.
+    pub unwinder: Arc<U>,
+    /// The avma ranges of the modules in `unwinder`, keyed by start address.
+    unwinder_module_ranges: Arc<BTreeMap<u64, u64>>,
     pub jitdump_manager: JitDumpManager,
+    /// Whether this process runs 32-bit code on a 64-bit system, based on the
+    /// ELF class of its most recently mapped binary. The stacks of such
+    /// processes are walked with frame pointers instead of with `unwinder`.
+    pub is_32bit: bool,
+    perf_map_processor: PerfMapProcessor,
     pub lib_mapping_ops: LibMappingOpQueue,
     pub name: Option<String>,
     pub threads: ProcessThreads,
-    pub pid: i32,
     pub unresolved_samples: UnresolvedSamples,
     pub jit_app_cache_mapping_ops: LibMappingOpQueue,
     pub jit_function_recycler: Option<JitFunctionRecycler>,
@@ -38,10 +50,17 @@ pub struct Process<U> {
     pub prev_mm_swapents_size: i64,
     pub prev_mm_shmempages_size: i64,
     pub mem_counter: Option<CounterHandle>,
+    /// The bytes mapped with mmap minus the bytes unmapped with munmap, from
+    /// the syscalls tracepoints.
+    pub address_space_size: i64,
+    address_space_counter: Option<CounterHandle>,
+    open_fds: Option<OpenHandlesCounter>,
 }
 
 pub struct ProcessForkData<U> {
-    unwinder: U,
+    unwinder: Arc<U>,
+    is_32bit: bool,
+    unwinder_module_ranges: Arc<BTreeMap<u64, u64>>,
     lib_mapping_ops: LibMappingOpQueue,
 }
 
@@ -60,14 +79,17 @@ where
         jit_function_recycler: Option<JitFunctionRecycler>,
         unlink_aux_files: bool,
         should_emit_jit_markers: bool,
+        jit_code_dir: Option<PathBuf>,
     ) -> Self {
         Self {
             profile_process: process_handle,
-            unwinder: U::default(),
+            unwinder: Default::default(),
+            unwinder_module_ranges: Default::default(),
             jitdump_manager: JitDumpManager::new(unlink_aux_files, should_emit_jit_markers),
+            is_32bit: false,
+            perf_map_processor: PerfMapProcessor::new(pid as u32, jit_code_dir),
             lib_mapping_ops: Default::default(),
             name: name.clone(),
-            pid,
             threads: ProcessThreads::new(
                 pid,
                 process_handle,
@@ -85,23 +107,64 @@ where
             prev_mm_swapents_size: 0,
             prev_mm_shmempages_size: 0,
             mem_counter: None,
+            address_space_size: 0,
+            address_space_counter: None,
+            open_fds: None,
         }
     }
 
-    /// Called when this process forks and creates a child process.
-    pub fn clone_fork_data(&self) -> ProcessForkData<U> {
+    /// Called when this process forks and creates a child process. The library
+    /// state is shared with the child, and only copied once one of the processes
+    /// changes it.
+    pub fn clone_fork_data(&mut self) -> ProcessForkData<U> {
         ProcessForkData {
             unwinder: self.unwinder.clone(),
-            lib_mapping_ops: self.lib_mapping_ops.clone(),
+            is_32bit: self.is_32bit,
+            unwinder_module_ranges: self.unwinder_module_ranges.clone(),
+            lib_mapping_ops: self.lib_mapping_ops.share(),
         }
     }
 
     /// Called on the child process that was created by the fork.
     pub fn adopt_fork_data_from_parent(&mut self, fork_data: ProcessForkData<U>) {
         self.unwinder = fork_data.unwinder;
+        self.is_32bit = fork_data.is_32bit;
+        self.unwinder_module_ranges = fork_data.unwinder_module_ranges;
         self.lib_mapping_ops = fork_data.lib_mapping_ops;
     }
 
+    /// Adds a module to the unwinder, after removing any existing modules whose
+    /// address range overlaps with the new module. This happens when a library
+    /// is unmapped and a different library (or a new version of the same library)
+    /// is mapped at the same address; without this, the unwinder would keep using
+    /// the stale unwind info.
+    pub fn add_unwinder_module(&mut self, avma_range: Range<u64>, module: U::Module) {
+        let overlapping_starts: Vec<u64> = self
+            .unwinder_module_ranges
+            .range(..avma_range.end)
+            .filter(|(_start, end)| **end > avma_range.start)
+            .map(|(start, _end)| *start)
+            .collect();
+        let unwinder = Arc::make_mut(&mut self.unwinder);
+        let unwinder_module_ranges = Arc::make_mut(&mut self.unwinder_module_ranges);
+        for start in overlapping_starts {
+            unwinder.remove_module(start);
+            unwinder_module_ranges.remove(&start);
+        }
+        unwinder.add_module(module);
+        unwinder_module_ranges.insert(avma_range.start, avma_range.end);
+    }
+
+    /// Called when the process execs a new image. The libraries of the old image
+    /// are unmapped at `timestamp`, and the new image's libraries will be added
+    /// by the mmap records which follow.
+    pub fn forget_image(&mut self, timestamp: u64) {
+        self.unwinder = Default::default();
+        self.unwinder_module_ranges = Default::default();
+        self.is_32bit = false;
+        self.lib_mapping_ops.push(timestamp, LibMappingOp::Clear);
+    }
+
     pub fn rename_with_recycling(
         &mut self,
         name: String,
@@ -158,8 +221,11 @@ where
             .recycle_or_get_new_thread(tid, name, start_time, profile)
     }
 
-    pub fn check_jitdump(
+    /// Picks up new entries from this process's jitdump files and perf map file.
+    /// `timestamp` is the raw timestamp of the event that is currently being processed.
+    pub fn check_jit_files(
         &mut self,
+        timestamp: u64,
         jit_category_manager: &mut JitCategoryManager,
         profile: &mut Profile,
         timestamp_converter: &TimestampConverter,
@@ -170,6 +236,12 @@ where
             self.jit_function_recycler.as_mut(),
             timestamp_converter,
         );
+        self.perf_map_processor.check_for_updates(
+            timestamp,
+            profile,
+            jit_category_manager,
+            self.jit_function_recycler.as_mut(),
+        );
     }
 
     pub fn add_marker_file_path(
@@ -193,18 +265,8 @@ where
         jit_category_manager: &mut JitCategoryManager,
         timestamp_converter: &TimestampConverter,
     ) -> (ProcessSampleData, Option<(String, ProcessRecyclingData)>) {
-        self.unwinder = U::default();
-
-        let perf_map_mappings = if !self.unresolved_samples.is_empty() {
-            try_load_perf_map(
-                self.pid as u32,
-                profile,
-                jit_category_manager,
-                self.jit_function_recycler.as_mut(),
-            )
-        } else {
-            None
-        };
+        self.unwinder = Default::default();
+        let marker_spans = self.marker_spans(timestamp_converter);
 
         let jitdump_manager = self.jitdump_manager;
         let mut jitdump_ops = jitdump_manager.finish(
@@ -218,27 +280,21 @@ where
             jitdump_ops.insert(0, self.jit_app_cache_mapping_ops);
         }
 
-        let mut marker_spans = Vec::new();
-        for (thread_handle, marker_file_path, lookup_dirs) in self.marker_file_paths {
-            if let Ok(marker_spans_from_this_file) =
-                get_markers(&marker_file_path, &lookup_dirs, *timestamp_converter)
-            {
-                marker_spans.extend(marker_spans_from_this_file.into_iter().map(|span| {
-                    MarkerSpanOnThread {
-                        thread_handle,
-                        start_time: span.start_time,
-                        end_time: span.end_time,
-                        name: span.name,
-                    }
-                }));
+        if !self.unresolved_samples.is_empty() {
+            if let Some(perf_map_ops) = self.perf_map_processor.finish(
+                profile,
+                jit_category_manager,
+                self.jit_function_recycler.as_mut(),
+            ) {
+                jitdump_ops.push(perf_map_ops);
             }
         }
 
         let process_sample_data = ProcessSampleData::new(
+            self.profile_process,
             std::mem::take(&mut self.unresolved_samples),
             std::mem::take(&mut self.lib_mapping_ops),
             jitdump_ops,
-            perf_map_mappings,
             marker_spans,
         );
 
@@ -264,6 +320,53 @@ where
         (process_sample_data, process_recycling_data)
     }
 
+    /// The sample data of this process so far, for a `--ring-buffer-seconds`
+    /// dump. Unlike [`finish`](Self::finish), this leaves the process as it
+    /// is, so that the recording can go on. JIT symbol tables are committed to
+    /// `profile`, which is a copy of the profile which is being recorded.
+    pub fn snapshot(
+        &self,
+        profile: &mut Profile,
+        timestamp_converter: &TimestampConverter,
+    ) -> ProcessSampleData {
+        let mut jitdump_ops = self.jitdump_manager.snapshot(profile);
+        if !self.jit_app_cache_mapping_ops.is_empty() {
+            jitdump_ops.insert(0, self.jit_app_cache_mapping_ops.clone());
+        }
+        if !self.unresolved_samples.is_empty() {
+            if let Some(perf_map_ops) = self.perf_map_processor.snapshot(profile) {
+                jitdump_ops.push(perf_map_ops);
+            }
+        }
+        ProcessSampleData::new(
+            self.profile_process,
+            self.unresolved_samples.clone(),
+            self.lib_mapping_ops.clone(),
+            jitdump_ops,
+            self.marker_spans(timestamp_converter),
+        )
+    }
+
+    /// The marker spans from the marker files of this process.
+    fn marker_spans(&self, timestamp_converter: &TimestampConverter) -> Vec<MarkerSpanOnThread> {
+        let mut marker_spans = Vec::new();
+        for (thread_handle, marker_file_path, lookup_dirs) in &self.marker_file_paths {
+            if let Ok(marker_spans_from_this_file) =
+                get_markers(marker_file_path, lookup_dirs, *timestamp_converter)
+            {
+                marker_spans.extend(marker_spans_from_this_file.into_iter().map(|span| {
+                    MarkerSpanOnThread {
+                        thread_handle: *thread_handle,
+                        start_time: span.start_time,
+                        end_time: span.end_time,
+                        name: span.name,
+                    }
+                }));
+            }
+        }
+        marker_spans
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn add_regular_lib_mapping(
         &mut self,
@@ -349,6 +452,26 @@ where
         );
     }
 
+    pub fn get_or_make_address_space_counter(&mut self, profile: &mut Profile) -> CounterHandle {
+        *self.address_space_counter.get_or_insert_with(|| {
+            profile.add_counter(
+                self.profile_process,
+                "mmap",
+                "Memory",
+                "Address space mapped with mmap",
+            )
+        })
+    }
+
+    pub fn get_or_make_open_fds_counter(
+        &mut self,
+        profile: &mut Profile,
+    ) -> &mut OpenHandlesCounter {
+        let profile_process = self.profile_process;
+        self.open_fds
+            .get_or_insert_with(|| OpenHandlesCounter::for_fds(profile, profile_process))
+    }
+
     pub fn get_or_make_mem_counter(&mut self, profile: &mut Profile) -> CounterHandle {
         *self.mem_counter.get_or_insert_with(|| {
             profile.add_counter(
@@ -173,17 +173,20 @@ impl ProcessThreads {
                 false,
             );
             let thread_label = make_thread_label(profile, None, self.pid, tid);
-            Thread {
-                profile_thread,
-                context_switch_data: Default::default(),
-                last_sample_timestamp: None,
-                off_cpu_stack: None,
-                name: None,
-                thread_label,
-            }
+            Thread::new(profile_thread, thread_label, None)
         })
     }
 
+    /// Returns the thread with this name, or the main thread if there is none.
+    /// Rust calls the main thread "main", which is not the name we have for it.
+    pub fn thread_handle_for_name(&self, name: &str) -> ThreadHandle {
+        self.threads_by_tid
+            .values()
+            .find(|thread| thread.name.as_deref() == Some(name))
+            .unwrap_or(&self.main_thread)
+            .profile_thread
+    }
+
     pub fn remove_non_main_thread(&mut self, tid: i32, time: Timestamp, profile: &mut Profile) {
         let Some(mut thread) = self.threads_by_tid.remove(&tid) else {
             return;
@@ -1,5 +1,6 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 use framehop::Unwinder;
 use fxprof_processed_profile::{Category, CategoryColor, Profile, Timestamp};
@@ -31,13 +32,25 @@ where
 
     /// Whether to emit JitFunctionAdd markers.
     should_emit_jit_markers: bool,
+
+    /// Whether to attach sample stacks to markers from marker files.
+    marker_stacks_from_samples: bool,
+
+    /// Some() if the code of perf map JIT functions should be saved into
+    /// jitdump files in this directory.
+    jit_code_dir: Option<PathBuf>,
 }
 
 impl<U> Processes<U>
 where
     U: Unwinder + Default,
 {
-    pub fn new(allow_reuse: bool, unlink_aux_data: bool, should_emit_jit_markers: bool) -> Self {
+    pub fn new(
+        allow_reuse: bool,
+        unlink_aux_data: bool,
+        should_emit_jit_markers: bool,
+        marker_stacks_from_samples: bool,
+    ) -> Self {
         let process_recycler = if allow_reuse {
             Some(ProcessRecycler::new())
         } else {
@@ -49,9 +62,15 @@ where
             process_sample_datas: Vec::new(),
             unlink_aux_data,
             should_emit_jit_markers,
+            marker_stacks_from_samples,
+            jit_code_dir: None,
         }
     }
 
+    pub fn set_jit_code_dir(&mut self, jit_code_dir: PathBuf) {
+        self.jit_code_dir = Some(jit_code_dir);
+    }
+
     pub fn recycle_or_get_new(
         &mut self,
         pid: i32,
@@ -83,6 +102,7 @@ where
                             Some(jit_function_recycler),
                             self.unlink_aux_data,
                             self.should_emit_jit_markers,
+                            self.jit_code_dir.clone(),
                         );
                         return entry.insert(process);
                     }
@@ -118,6 +138,7 @@ where
                     jit_function_recycler,
                     self.unlink_aux_data,
                     self.should_emit_jit_markers,
+                    self.jit_code_dir.clone(),
                 );
                 entry.insert(process)
             }
@@ -144,6 +165,24 @@ where
         }
     }
 
+    /// The pids of the processes which haven't exited yet.
+    pub fn live_pids(&self) -> Vec<i32> {
+        self.processes_by_pid.keys().copied().collect()
+    }
+
+    /// Drops the samples of all processes, including the ones which have
+    /// exited, which were taken before `timestamp_mono`.
+    pub fn discard_samples_before(&mut self, timestamp_mono: u64) {
+        for process in self.processes_by_pid.values_mut() {
+            process
+                .unresolved_samples
+                .discard_samples_before(timestamp_mono);
+        }
+        for process_sample_data in &mut self.process_sample_datas {
+            process_sample_data.discard_samples_before(timestamp_mono);
+        }
+    }
+
     pub fn get_by_pid(&mut self, pid: i32, profile: &mut Profile) -> &mut Process<U> {
         self.processes_by_pid.entry(pid).or_insert_with(|| {
             let fake_start_time = Timestamp::from_millis_since_reference(0.0);
@@ -170,6 +209,7 @@ where
                 jit_function_recycler,
                 self.unlink_aux_data,
                 self.should_emit_jit_markers,
+                self.jit_code_dir.clone(),
             )
         })
     }
@@ -201,6 +241,31 @@ where
         }
     }
 
+    /// Called when the process execs a new image. Unlike an exit followed by a
+    /// new process, this keeps the process on its track, so that its samples from
+    /// before and after the exec stay on one timeline. Returns the old name of
+    /// the process, if the process was known before the exec.
+    pub fn exec_process(
+        &mut self,
+        pid: i32,
+        timestamp_raw: u64,
+        timestamp: Timestamp,
+        name: String,
+        profile: &mut Profile,
+    ) -> Option<String> {
+        let Some(process) = self.processes_by_pid.get_mut(&pid) else {
+            self.recycle_or_get_new(pid, Some(name), timestamp, profile);
+            return None;
+        };
+        process.forget_image(timestamp_raw);
+        let old_name = process.name.clone();
+        if old_name.as_deref() != Some(&name) {
+            let main_thread_label_frame = make_thread_label(profile, Some(&name), pid, pid);
+            process.rename_without_recycling(name, main_thread_label_frame, profile);
+        }
+        Some(old_name.unwrap_or_else(|| format!("<{pid}>")))
+    }
+
     pub fn rename_process(
         &mut self,
         pid: i32,
@@ -251,6 +316,44 @@ where
             }
         }
 
+        Self::flush_samples_to_profile(
+            self.process_sample_datas,
+            profile,
+            unresolved_stacks,
+            self.marker_stacks_from_samples,
+        );
+    }
+
+    /// Adds the samples so far to `profile`, which is a copy of the profile
+    /// which is being recorded, without ending the recording.
+    pub fn snapshot(
+        &self,
+        profile: &mut Profile,
+        unresolved_stacks: &UnresolvedStacks,
+        timestamp_converter: &TimestampConverter,
+    ) {
+        let mut process_sample_datas = self.process_sample_datas.clone();
+        for process in self.processes_by_pid.values() {
+            let process_sample_data = process.snapshot(profile, timestamp_converter);
+            if !process_sample_data.is_empty() {
+                process_sample_datas.push(process_sample_data);
+            }
+        }
+
+        Self::flush_samples_to_profile(
+            process_sample_datas,
+            profile,
+            unresolved_stacks,
+            self.marker_stacks_from_samples,
+        );
+    }
+
+    fn flush_samples_to_profile(
+        process_sample_datas: Vec<ProcessSampleData>,
+        profile: &mut Profile,
+        unresolved_stacks: &UnresolvedStacks,
+        marker_stacks_from_samples: bool,
+    ) {
         let user_category = profile
             .handle_for_category(Category("User", CategoryColor::Yellow))
             .into();
@@ -258,13 +361,14 @@ where
             .handle_for_category(Category("Kernel", CategoryColor::Orange))
             .into();
         let mut stack_frame_scratch_buf = Vec::new();
-        for process_sample_data in self.process_sample_datas {
+        for process_sample_data in process_sample_datas {
             process_sample_data.flush_samples_to_profile(
                 profile,
                 user_category,
                 kernel_category,
                 &mut stack_frame_scratch_buf,
                 unresolved_stacks,
+                marker_stacks_from_samples,
             );
         }
     }
@@ -0,0 +1,44 @@
+use linux_perf_data::{linux_perf_event_reader, Endianness};
+use linux_perf_event_reader::RawData;
+
+/// The tid of the thread which a `sched:sched_wakeup` or
+/// `sched:sched_wakeup_new` tracepoint woke up.
+///
+/// ```
+/// # cat /sys/kernel/debug/tracing/events/sched/sched_wakeup/format
+/// name: sched_wakeup
+/// ID: 318
+/// format:
+///         field:unsigned short common_type;       offset:0;       size:2; signed:0;
+///         field:unsigned char common_flags;       offset:2;       size:1; signed:0;
+///         field:unsigned char common_preempt_count;       offset:3;       size:1; signed:0;
+///         field:int common_pid;   offset:4;       size:4; signed:1;
+///
+///         field:char comm[16];    offset:8;       size:16;        signed:0;
+///         field:pid_t pid;        offset:24;      size:4; signed:1;
+///         field:int prio; offset:28;      size:4; signed:1;
+///         field:int target_cpu;   offset:32;      size:4; signed:1;
+/// ```
+pub fn parse_sched_wakeup_tid(data: RawData, endian: Endianness) -> Result<i32, std::io::Error> {
+    let mut data = data;
+    data.skip(24)?;
+    match endian {
+        Endianness::LittleEndian => data.read_i32::<byteorder::LittleEndian>(),
+        Endianness::BigEndian => data.read_i32::<byteorder::BigEndian>(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sched_wakeup_tracepoint() {
+        let mut wakeup = vec![0u8; 36];
+        wakeup[8..13].copy_from_slice(b"rustc");
+        wakeup[24..28].copy_from_slice(&4321i32.to_le_bytes());
+        let tid =
+            parse_sched_wakeup_tid(RawData::Single(&wakeup), Endianness::LittleEndian).unwrap();
+        assert_eq!(tid, 4321);
+    }
+}
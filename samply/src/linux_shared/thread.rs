@@ -1,10 +1,27 @@
 use std::fmt::Debug;
 
-use fxprof_processed_profile::{Profile, StringHandle, ThreadHandle, Timestamp};
+use fxprof_processed_profile::{MarkerHandle, Profile, StringHandle, ThreadHandle, Timestamp};
 
 use crate::shared::context_switch::ThreadContextSwitchData;
 use crate::shared::unresolved_samples::UnresolvedStackHandle;
 
+/// An mmap call which hasn't returned yet.
+#[derive(Debug, Clone, Copy)]
+pub struct PendingMmap {
+    pub start_timestamp_mono: u64,
+    pub len: u64,
+    pub stack: UnresolvedStackHandle,
+}
+
+/// A "ThreadCreate" marker on the creating thread which is waiting for the
+/// stack from the exit of the clone syscall.
+#[derive(Debug, Clone, Copy)]
+pub struct PendingThreadCreate {
+    pub child_tid: i32,
+    pub timestamp_mono: u64,
+    pub marker: MarkerHandle,
+}
+
 #[derive(Debug)]
 pub struct Thread {
     pub profile_thread: ThreadHandle,
@@ -15,6 +32,11 @@ pub struct Thread {
     ///
     /// Refers to a stack in the containing Process's UnresolvedSamples stack table.
     pub off_cpu_stack: Option<UnresolvedStackHandle>,
+    /// Some() between the sys_enter_mmap and sys_exit_mmap tracepoints.
+    pub pending_mmap: Option<PendingMmap>,
+    /// Some() between the FORK record of a new thread and the
+    /// sys_exit_clone / sys_exit_clone3 tracepoint of the creating thread.
+    pub pending_thread_create: Option<PendingThreadCreate>,
     pub name: Option<String>,
     pub thread_label: StringHandle,
 }
@@ -30,6 +52,8 @@ impl Thread {
             context_switch_data: Default::default(),
             last_sample_timestamp: None,
             off_cpu_stack: None,
+            pending_mmap: None,
+            pending_thread_create: None,
             name,
             thread_label,
         }
@@ -0,0 +1,332 @@
+//! The fields of a tracepoint, from its `format` file in tracefs, e.g.
+//! /sys/kernel/tracing/events/sched/sched_wakeup/format, so that the raw data
+//! of the tracepoint's samples can be shown as `name=value` pairs.
+//!
+//! ```
+//! name: sched_wakeup
+//! ID: 374
+//! format:
+//!         field:unsigned short common_type;       offset:0;       size:2; signed:0;
+//!         field:unsigned char common_flags;       offset:2;       size:1; signed:0;
+//!         field:unsigned char common_preempt_count;       offset:3;       size:1; signed:0;
+//!         field:int common_pid;   offset:4;       size:4; signed:1;
+//!
+//!         field:char comm[16];    offset:8;       size:16;        signed:0;
+//!         field:pid_t pid;        offset:24;      size:4; signed:1;
+//!         field:int prio; offset:28;      size:4; signed:1;
+//!         field:int target_cpu;   offset:32;      size:4; signed:1;
+//!
+//! print fmt: "comm=%s pid=%d prio=%d target_cpu=%03d", REC->comm, REC->pid, ...
+//! ```
+//!
+//! perf.data files have the format files of their tracepoints in the
+//! TRACING_DATA feature section.
+
+use std::collections::HashMap;
+
+use linux_perf_data::Endianness;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TracepointField {
+    name: String,
+    /// The C type, e.g. "pid_t", "const char *" or "__data_loc char[]".
+    type_name: String,
+    /// Whether the field is an array, e.g. `char comm[16]`.
+    is_array: bool,
+    offset: usize,
+    size: usize,
+    signed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracepointFormat {
+    /// The tracepoint id, which is the perf event config of the tracepoint.
+    pub id: Option<u64>,
+    /// The fields after the common fields, which all tracepoints have.
+    fields: Vec<TracepointField>,
+}
+
+impl TracepointFormat {
+    /// Parses the contents of a tracepoint's format file.
+    pub fn parse(format: &str) -> Self {
+        let mut id = None;
+        let mut fields = Vec::new();
+        for line in format.lines() {
+            let line = line.trim();
+            if let Some(value) = line.strip_prefix("ID:") {
+                id = value.trim().parse().ok();
+            } else if line.starts_with("field:") {
+                fields.extend(parse_field(line));
+            }
+        }
+        fields.retain(|field| !field.name.starts_with("common_"));
+        Self { id, fields }
+    }
+
+    /// The value of the integer field `name` in a sample's raw data.
+    pub fn field_value(&self, name: &str, data: &[u8], endian: Endianness) -> Option<i64> {
+        let field = self.fields.iter().find(|field| field.name == name)?;
+        if field.is_array || field.type_name.starts_with("__data_loc") {
+            return None;
+        }
+        let bytes = data.get(field.offset..field.offset + field.size)?;
+        let value = read_unsigned(bytes, endian)?;
+        Some(match field.signed {
+            true => sign_extend(value, field.size),
+            false => value as i64,
+        })
+    }
+
+    /// Formats the fields in a sample's raw data as `name=value` pairs,
+    /// separated by spaces. Strings are shown as they are, pointers in hex, and
+    /// other arrays are left out.
+    pub fn format_fields(&self, data: &[u8], endian: Endianness) -> String {
+        let mut formatted = Vec::new();
+        for field in &self.fields {
+            let Some(bytes) = data.get(field.offset..field.offset + field.size) else {
+                continue;
+            };
+            let value = if field.type_name.starts_with("__data_loc") {
+                // The low 16 bits are the offset of the data, the high 16 bits
+                // its length.
+                let Some(location) = read_unsigned(bytes, endian) else {
+                    continue;
+                };
+                let offset = (location & 0xffff) as usize;
+                let length = (location >> 16) as usize;
+                match data.get(offset..offset + length) {
+                    Some(string) => c_string(string),
+                    None => continue,
+                }
+            } else if field.is_array {
+                if !field.type_name.ends_with("char") {
+                    continue;
+                }
+                c_string(bytes)
+            } else if field.type_name.contains('*') {
+                match read_unsigned(bytes, endian) {
+                    Some(value) => format!("{value:#x}"),
+                    None => continue,
+                }
+            } else {
+                match read_unsigned(bytes, endian) {
+                    Some(value) if field.signed => sign_extend(value, field.size).to_string(),
+                    Some(value) => value.to_string(),
+                    None => continue,
+                }
+            };
+            formatted.push(format!("{}={value}", field.name));
+        }
+        formatted.join(" ")
+    }
+}
+
+/// The tracepoint formats in the TRACING_DATA section of a perf.data file, by
+/// tracepoint id. The section is laid out as in `tracing_data_header()` and
+/// the `record_*` functions of perf's util/trace-event-info.c, in the byte
+/// order of the machine which recorded it:
+///
+/// ```plain
+/// "\x17\x08\x44tracing", version string, u8 big_endian, u8 long_size, u32 page_size
+/// "header_page\0", u64 size, data
+/// "header_event\0", u64 size, data
+/// u32 count, count * (u64 size, ftrace event format)
+/// u32 system_count, system_count * (system name, u32 count, count * (u64 size, event format))
+/// ...
+/// ```
+pub fn parse_tracing_data(data: &[u8]) -> Option<HashMap<u64, TracepointFormat>> {
+    let mut reader = data.strip_prefix(b"\x17\x08\x44tracing")?;
+    let _version = read_c_str(&mut reader)?;
+    let endian = match read_bytes(&mut reader, 1)? {
+        [0] => Endianness::LittleEndian,
+        _ => Endianness::BigEndian,
+    };
+    let _long_size = read_bytes(&mut reader, 1)?;
+    let _page_size = read_bytes(&mut reader, 4)?;
+    for header_name in ["header_page", "header_event"] {
+        if read_c_str(&mut reader)? != header_name {
+            return None;
+        }
+        let size = read_number(&mut reader, 8, endian)?;
+        read_bytes(&mut reader, usize::try_from(size).ok()?)?;
+    }
+    let mut formats = HashMap::new();
+    let mut read_formats = |reader: &mut &[u8]| -> Option<()> {
+        let count = read_number(reader, 4, endian)?;
+        for _ in 0..count {
+            let size = read_number(reader, 8, endian)?;
+            let format = read_bytes(reader, usize::try_from(size).ok()?)?;
+            let format = TracepointFormat::parse(&String::from_utf8_lossy(format));
+            if let Some(id) = format.id {
+                formats.insert(id, format);
+            }
+        }
+        Some(())
+    };
+    // The ftrace formats, e.g. ftrace:function.
+    read_formats(&mut reader)?;
+    let system_count = read_number(&mut reader, 4, endian)?;
+    for _ in 0..system_count {
+        let _system_name = read_c_str(&mut reader)?;
+        read_formats(&mut reader)?;
+    }
+    Some(formats)
+}
+
+fn read_bytes<'a>(reader: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if reader.len() < len {
+        return None;
+    }
+    let (bytes, rest) = reader.split_at(len);
+    *reader = rest;
+    Some(bytes)
+}
+
+fn read_number(reader: &mut &[u8], size: usize, endian: Endianness) -> Option<u64> {
+    read_unsigned(read_bytes(reader, size)?, endian)
+}
+
+fn read_c_str<'a>(reader: &mut &'a [u8]) -> Option<&'a str> {
+    let len = memchr::memchr(0, reader)?;
+    let string = std::str::from_utf8(&reader[..len]).ok()?;
+    *reader = &reader[len + 1..];
+    Some(string)
+}
+
+/// Parses a line like `field:char comm[16]; offset:8; size:16; signed:0;`.
+fn parse_field(line: &str) -> Option<TracepointField> {
+    let mut parts = line.split(';').map(str::trim);
+    let declaration = parts.next()?.strip_prefix("field:")?;
+    let mut offset = None;
+    let mut size = None;
+    let mut signed = false;
+    for part in parts {
+        match part.split_once(':') {
+            Some(("offset", value)) => offset = value.parse().ok(),
+            Some(("size", value)) => size = value.parse().ok(),
+            Some(("signed", value)) => signed = value == "1",
+            _ => {}
+        }
+    }
+    let (type_name, name) = declaration.rsplit_once(' ')?;
+    let (name, is_array) = match name.split_once('[') {
+        Some((name, _)) => (name, true),
+        None => (name, false),
+    };
+    Some(TracepointField {
+        name: name.to_string(),
+        type_name: type_name.trim().to_string(),
+        is_array,
+        offset: offset?,
+        size: size?,
+        signed,
+    })
+}
+
+fn read_unsigned(bytes: &[u8], endian: Endianness) -> Option<u64> {
+    let mut value = [0; 8];
+    match endian {
+        Endianness::LittleEndian => {
+            value.get_mut(..bytes.len())?.copy_from_slice(bytes);
+            Some(u64::from_le_bytes(value))
+        }
+        Endianness::BigEndian => {
+            value.get_mut(8 - bytes.len()..)?.copy_from_slice(bytes);
+            Some(u64::from_be_bytes(value))
+        }
+    }
+}
+
+fn sign_extend(value: u64, size: usize) -> i64 {
+    let shift = 64 - 8 * size as u32;
+    ((value << shift) as i64) >> shift
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let length = memchr::memchr(0, bytes).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..length]).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SCHED_PROCESS_EXEC: &str = "name: sched_process_exec
+ID: 300
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:__data_loc char[] filename;\toffset:8;\tsize:4;\tsigned:0;
+\tfield:pid_t pid;\toffset:12;\tsize:4;\tsigned:1;
+\tfield:char comm[8];\toffset:16;\tsize:8;\tsigned:0;
+\tfield:const void * ptr;\toffset:24;\tsize:8;\tsigned:0;
+\tfield:u32 words[2];\toffset:32;\tsize:8;\tsigned:0;
+
+print fmt: \"filename=%s pid=%d\", __get_str(filename), REC->pid
+";
+
+    #[test]
+    fn tracepoint_fields() {
+        let format = TracepointFormat::parse(SCHED_PROCESS_EXEC);
+        assert_eq!(format.id, Some(300));
+        assert_eq!(format.fields.len(), 5);
+
+        let mut data = vec![0u8; 40];
+        data[8..12].copy_from_slice(&((9u32 << 16) | 40).to_le_bytes());
+        data[12..16].copy_from_slice(&(-1i32).to_le_bytes());
+        data[16..20].copy_from_slice(b"bash");
+        data[24..32].copy_from_slice(&0xffff1234u64.to_le_bytes());
+        data.extend(b"/bin/cat\0");
+        assert_eq!(
+            format.format_fields(&data, Endianness::LittleEndian),
+            "filename=/bin/cat pid=-1 comm=bash ptr=0xffff1234"
+        );
+        // Fields which aren't in the data are left out.
+        assert_eq!(
+            format.format_fields(&data[..16], Endianness::LittleEndian),
+            "pid=-1"
+        );
+        assert_eq!(
+            format.field_value("pid", &data, Endianness::LittleEndian),
+            Some(-1)
+        );
+        assert_eq!(
+            format.field_value("comm", &data, Endianness::LittleEndian),
+            None
+        );
+        assert_eq!(
+            format.field_value("common_pid", &data, Endianness::LittleEndian),
+            None
+        );
+    }
+
+    #[test]
+    fn perf_tracing_data() {
+        let mut data = b"\x17\x08\x44tracing0.6\0".to_vec();
+        data.extend([0, 8]);
+        data.extend(4096u32.to_le_bytes());
+        for (name, contents) in [("header_page", "a"), ("header_event", "bc")] {
+            data.extend(name.as_bytes());
+            data.push(0);
+            data.extend((contents.len() as u64).to_le_bytes());
+            data.extend(contents.as_bytes());
+        }
+        // No ftrace formats.
+        data.extend(0u32.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+        data.extend(b"sched\0");
+        data.extend(1u32.to_le_bytes());
+        data.extend((SCHED_PROCESS_EXEC.len() as u64).to_le_bytes());
+        data.extend(SCHED_PROCESS_EXEC.as_bytes());
+        // kallsyms, which isn't needed.
+        data.extend(0u32.to_le_bytes());
+
+        let formats = parse_tracing_data(&data).unwrap();
+        assert_eq!(formats.len(), 1);
+        assert_eq!(formats[&300], TracepointFormat::parse(SCHED_PROCESS_EXEC));
+        assert_eq!(parse_tracing_data(&data[..40]), None);
+    }
+}
@@ -0,0 +1,102 @@
+use byteorder::ByteOrder;
+use linux_perf_data::{linux_perf_event_reader, Endianness};
+use linux_perf_event_reader::RawData;
+
+/// The arguments of a `syscalls:sys_enter_*` tracepoint.
+///
+/// ```
+/// # cat /sys/kernel/debug/tracing/events/syscalls/sys_enter_mmap/format
+/// name: sys_enter_mmap
+/// ID: 98
+/// format:
+///         field:unsigned short common_type;       offset:0;       size:2; signed:0;
+///         field:unsigned char common_flags;       offset:2;       size:1; signed:0;
+///         field:unsigned char common_preempt_count;       offset:3;       size:1; signed:0;
+///         field:int common_pid;   offset:4;       size:4; signed:1;
+///
+///         field:int __syscall_nr; offset:8;       size:4; signed:1;
+///         field:unsigned long addr;       offset:16;      size:8; signed:0;
+///         field:unsigned long len;        offset:24;      size:8; signed:0;
+///         field:unsigned long prot;       offset:32;      size:8; signed:0;
+///         field:unsigned long flags;      offset:40;      size:8; signed:0;
+///         field:unsigned long fd; offset:48;      size:8; signed:0;
+///         field:unsigned long off;        offset:56;      size:8; signed:0;
+/// ```
+///
+/// This is the layout on 64-bit kernels; every argument takes 8 bytes.
+pub fn parse_sys_enter_args<const N: usize>(
+    data: RawData,
+    endian: Endianness,
+) -> Result<[u64; N], std::io::Error> {
+    match endian {
+        Endianness::LittleEndian => parse_sys_enter_args_impl::<byteorder::LittleEndian, N>(data),
+        Endianness::BigEndian => parse_sys_enter_args_impl::<byteorder::BigEndian, N>(data),
+    }
+}
+
+fn parse_sys_enter_args_impl<O: ByteOrder, const N: usize>(
+    mut data: RawData,
+) -> Result<[u64; N], std::io::Error> {
+    // Skip the common fields, the syscall number and its padding.
+    data.skip(16)?;
+    let mut args = [0; N];
+    for arg in &mut args {
+        *arg = data.read_u64::<O>()?;
+    }
+    Ok(args)
+}
+
+/// The return value of a `syscalls:sys_exit_*` tracepoint.
+///
+/// ```
+/// # cat /sys/kernel/debug/tracing/events/syscalls/sys_exit_mmap/format
+/// name: sys_exit_mmap
+/// ID: 97
+/// format:
+///         field:unsigned short common_type;       offset:0;       size:2; signed:0;
+///         field:unsigned char common_flags;       offset:2;       size:1; signed:0;
+///         field:unsigned char common_preempt_count;       offset:3;       size:1; signed:0;
+///         field:int common_pid;   offset:4;       size:4; signed:1;
+///
+///         field:int __syscall_nr; offset:8;       size:4; signed:1;
+///         field:long ret; offset:16;      size:8; signed:1;
+/// ```
+pub fn parse_sys_exit_ret(data: RawData, endian: Endianness) -> Result<i64, std::io::Error> {
+    match endian {
+        Endianness::LittleEndian => parse_sys_exit_ret_impl::<byteorder::LittleEndian>(data),
+        Endianness::BigEndian => parse_sys_exit_ret_impl::<byteorder::BigEndian>(data),
+    }
+}
+
+fn parse_sys_exit_ret_impl<O: ByteOrder>(mut data: RawData) -> Result<i64, std::io::Error> {
+    data.skip(16)?;
+    Ok(data.read_u64::<O>()? as i64)
+}
+
+/// Syscalls return small negative numbers (-errno) on failure.
+pub fn is_syscall_error(ret: i64) -> bool {
+    (-4095..0).contains(&ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mmap_tracepoints() {
+        let mut enter = vec![0u8; 64];
+        enter[16..24].copy_from_slice(&0u64.to_le_bytes());
+        enter[24..32].copy_from_slice(&0x20_0000u64.to_le_bytes());
+        enter[32..40].copy_from_slice(&3u64.to_le_bytes());
+        let [addr, len, prot] =
+            parse_sys_enter_args::<3>(RawData::Single(&enter), Endianness::LittleEndian).unwrap();
+        assert_eq!((addr, len, prot), (0, 0x20_0000, 3));
+
+        let mut exit = vec![0u8; 24];
+        exit[16..24].copy_from_slice(&(-12i64).to_le_bytes());
+        let ret = parse_sys_exit_ret(RawData::Single(&exit), Endianness::LittleEndian).unwrap();
+        assert_eq!(ret, -12);
+        assert!(is_syscall_error(ret));
+        assert!(!is_syscall_error(0x7f00_0000_0000));
+    }
+}
@@ -1,5 +1,7 @@
 use std::env;
+use std::ffi::c_void;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 
 const ENTITLEMENTS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
 <!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
@@ -67,3 +69,134 @@ Press any key to continue, or Ctrl-C to cancel.
 
     println!(r"Code signing successful!");
 }
+
+const GET_TASK_ALLOW_ENTITLEMENTS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>com.apple.security.get-task-allow</key>
+	<true/>
+</dict>
+</plist>
+"#;
+
+/// Re-signs `target` for the local machine with the get-task-allow
+/// entitlement, which lets samply attach to it even though it uses the
+/// hardened runtime. This replaces the target's existing signature and
+/// entitlements, so it's only for binaries that you built yourself.
+pub fn allow_attach_setup(target: &Path, skip_prompt: bool) {
+    if !skip_prompt {
+        print!(
+            r#"
+Processes which use the hardened runtime don't allow other processes to attach
+to them, unless they have the com.apple.security.get-task-allow entitlement.
+{} will be re-signed for your local machine only, with this entitlement and
+without any of its other entitlements. The following command will be run:
+
+    codesign --force --options runtime --sign - \
+      --entitlements entitlements.xml {}
+
+Press any key to continue, or Ctrl-C to cancel.
+"#,
+            target.display(),
+            target.display(),
+        );
+
+        let mut input = String::new();
+        std::io::stdin()
+            .read_line(&mut input)
+            .expect("Failed to read input?");
+    }
+
+    let mut entitlements_file = tempfile::Builder::new()
+        .prefix("samply_entitlements")
+        .suffix(".xml")
+        .tempfile()
+        .expect("Failed to create temporary file for entitlements!");
+    entitlements_file
+        .write_all(GET_TASK_ALLOW_ENTITLEMENTS_XML.as_bytes())
+        .expect("Failed to write entitlements to temporary file!");
+
+    let output = std::process::Command::new("codesign")
+        .arg("--force")
+        .arg("--options")
+        .arg("runtime")
+        .arg("--sign")
+        .arg("-")
+        .arg("--entitlements")
+        .arg(entitlements_file.path())
+        .arg(target)
+        .output()
+        .expect("Failed to run codesign!");
+    if !output.status.success() {
+        eprintln!("codesign failed:");
+        eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+        std::process::exit(1);
+    }
+
+    println!(
+        "Code signing successful! Restart the process so that the new signature takes effect."
+    );
+}
+
+// From <kern/cs_blobs.h>.
+const CS_OPS_STATUS: u32 = 0;
+const CS_GET_TASK_ALLOW: u32 = 0x4;
+const CS_RUNTIME: u32 = 0x10000;
+const CS_PLATFORM_BINARY: u32 = 0x4000000;
+
+extern "C" {
+    fn csops(pid: libc::pid_t, ops: u32, useraddr: *mut c_void, usersize: usize) -> libc::c_int;
+}
+
+fn code_signing_flags(pid: u32) -> Option<u32> {
+    let mut flags: u32 = 0;
+    let result = unsafe {
+        csops(
+            pid as libc::pid_t,
+            CS_OPS_STATUS,
+            &mut flags as *mut u32 as *mut c_void,
+            std::mem::size_of::<u32>(),
+        )
+    };
+    (result == 0).then_some(flags)
+}
+
+fn executable_path(pid: u32) -> Option<PathBuf> {
+    let mut buffer = vec![0u8; libc::PROC_PIDPATHINFO_MAXSIZE as usize];
+    let len = unsafe {
+        libc::proc_pidpath(
+            pid as libc::c_int,
+            buffer.as_mut_ptr() as *mut c_void,
+            buffer.len() as u32,
+        )
+    };
+    if len <= 0 {
+        return None;
+    }
+    buffer.truncate(len as usize);
+    Some(PathBuf::from(String::from_utf8_lossy(&buffer).into_owned()))
+}
+
+/// Prints why samply couldn't get the task port of `pid`, based on the code
+/// signing flags of the process, and what can be done about it.
+pub fn explain_attach_failure(pid: u32) {
+    let path = executable_path(pid)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| format!("The process {pid}"));
+    let flags = code_signing_flags(pid).unwrap_or(0);
+    if flags & CS_PLATFORM_BINARY != 0 {
+        eprintln!("{path} is part of macOS and is protected by System Integrity Protection,");
+        eprintln!("so samply can't attach to it.");
+    } else if flags & CS_RUNTIME != 0 && flags & CS_GET_TASK_ALLOW == 0 {
+        eprintln!("{path} uses the hardened runtime, which doesn't allow other processes");
+        eprintln!("to attach to it. If you built it yourself, you can give it the get-task-allow");
+        eprintln!("entitlement for local profiling, and then restart it:");
+        eprintln!();
+        eprintln!("    samply setup --allow-attach {path}");
+    } else {
+        eprintln!("Please run 'samply setup' in order to grant appropriate entitlements");
+        eprintln!("to the binary. If the process belongs to another user or to root, record");
+        eprintln!("it with --use-sudo-helper.");
+    }
+}
@@ -0,0 +1,408 @@
+//! Kernel stack sampling with kperf and kdebug.
+//!
+//! The task-based sampler only sees user-space stacks: while a thread is in a
+//! system call, its user stack ends at the syscall stub. With root privileges,
+//! we can ask kperf to sample the kernel stack of whichever thread is running
+//! on each CPU at a timer interrupt. The samples arrive as kdebug trace events,
+//! which we drain every sampling tick. A user-space sample then gets the kernel
+//! stack of the same thread appended, if the thread was sampled by kperf since
+//! its previous sample.
+//!
+//! kperf is a private framework, so it's loaded at runtime. The kdebug sysctl
+//! interface is stable but mostly undocumented; the constants below come from
+//! xnu's `bsd/sys/kdebug.h` and `osfmk/kperf/buffer.h`.
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::path::Path;
+use std::time::Duration;
+
+use fxprof_processed_profile::debugid::DebugId;
+use fxprof_processed_profile::{LibraryInfo, Profile};
+use uuid::Uuid;
+use wholesym::samply_symbols::object::{self, Object, ObjectSegment};
+use wholesym::CodeId;
+
+use super::time::mach_ticks_to_nanos;
+use crate::shared::types::FastHashMap;
+
+const KPERF_PATH: &CStr = c"/System/Library/PrivateFrameworks/kperf.framework/kperf";
+
+const KPERF_SAMPLER_TH_INFO: u32 = 1 << 0;
+const KPERF_SAMPLER_KSTACK: u32 = 1 << 2;
+
+const KERN_KDEBUG: c_int = 24;
+const KERN_KDENABLE: c_int = 3;
+const KERN_KDSETBUF: c_int = 4;
+const KERN_KDSETUP: c_int = 6;
+const KERN_KDREMOVE: c_int = 7;
+const KERN_KDREADTR: c_int = 10;
+const KERN_KDSET_TYPEFILTER: c_int = 22;
+const KDEBUG_ENABLE_TRACE: c_int = 1;
+
+/// One bit per (class, subclass) pair.
+const KDBG_TYPEFILTER_BITMAP_SIZE: usize = 256 * 256 / 8;
+
+const DBG_PERF: u32 = 37;
+const PERF_THREADINFO: u32 = 1;
+const PERF_CALLSTACK: u32 = 2;
+const PERF_TI_SAMPLE: u32 = 0;
+const PERF_CS_KDATA: u32 = 3;
+const PERF_CS_KHDR: u32 = 5;
+
+/// Enough for a few hundred milliseconds of samples on a machine with many cores.
+const KDEBUG_BUFFER_ENTRY_COUNT: usize = 1 << 20;
+
+/// Kernel stacks deeper than this are truncated.
+const MAX_KERNEL_STACK_DEPTH: usize = 128;
+
+/// A trace event, as returned by `KERN_KDREADTR`. This is the 64-bit layout.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct KdBuf {
+    timestamp: u64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+    arg5: u64,
+    debugid: u32,
+    cpuid: u32,
+    unused: u64,
+}
+
+impl KdBuf {
+    fn class(&self) -> u32 {
+        self.debugid >> 24
+    }
+
+    fn subclass(&self) -> u32 {
+        (self.debugid >> 16) & 0xff
+    }
+
+    fn code(&self) -> u32 {
+        (self.debugid >> 2) & 0x3fff
+    }
+}
+
+struct Kperf {
+    _handle: *mut c_void,
+    kperf_action_count_set: unsafe extern "C" fn(u32) -> c_int,
+    kperf_action_samplers_set: unsafe extern "C" fn(u32, u32) -> c_int,
+    kperf_timer_count_set: unsafe extern "C" fn(u32) -> c_int,
+    kperf_timer_period_set: unsafe extern "C" fn(u32, u64) -> c_int,
+    kperf_timer_action_set: unsafe extern "C" fn(u32, u32) -> c_int,
+    kperf_sample_set: unsafe extern "C" fn(u32) -> c_int,
+    kperf_reset: unsafe extern "C" fn() -> c_int,
+    kperf_ns_to_ticks: unsafe extern "C" fn(u64) -> u64,
+}
+
+impl Kperf {
+    fn load() -> Result<Self, String> {
+        let handle = unsafe { libc::dlopen(KPERF_PATH.as_ptr(), libc::RTLD_LAZY) };
+        if handle.is_null() {
+            return Err("Could not load the kperf framework".to_string());
+        }
+        unsafe fn sym<T>(handle: *mut c_void, name: &CStr) -> Result<T, String> {
+            let ptr = libc::dlsym(handle, name.as_ptr() as *const c_char);
+            if ptr.is_null() {
+                return Err(format!("kperf is missing {name:?}"));
+            }
+            Ok(std::mem::transmute_copy(&ptr))
+        }
+        unsafe {
+            Ok(Kperf {
+                _handle: handle,
+                kperf_action_count_set: sym(handle, c"kperf_action_count_set")?,
+                kperf_action_samplers_set: sym(handle, c"kperf_action_samplers_set")?,
+                kperf_timer_count_set: sym(handle, c"kperf_timer_count_set")?,
+                kperf_timer_period_set: sym(handle, c"kperf_timer_period_set")?,
+                kperf_timer_action_set: sym(handle, c"kperf_timer_action_set")?,
+                kperf_sample_set: sym(handle, c"kperf_sample_set")?,
+                kperf_reset: sym(handle, c"kperf_reset")?,
+                kperf_ns_to_ticks: sym(handle, c"kperf_ns_to_ticks")?,
+            })
+        }
+    }
+}
+
+fn kdebug_sysctl(
+    op: c_int,
+    value: c_int,
+    old: *mut c_void,
+    old_len: &mut usize,
+    new: *mut c_void,
+    new_len: usize,
+) -> Result<(), std::io::Error> {
+    let mut mib = [libc::CTL_KERN, KERN_KDEBUG, op, value];
+    let ret = unsafe { libc::sysctl(mib.as_mut_ptr(), 4, old, old_len, new, new_len) };
+    match ret {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+fn kdebug_op(op: c_int, value: c_int) -> Result<(), std::io::Error> {
+    let mut len = 0;
+    kdebug_sysctl(
+        op,
+        value,
+        std::ptr::null_mut(),
+        &mut len,
+        std::ptr::null_mut(),
+        0,
+    )
+}
+
+/// A kernel stack sampled by kperf, with the callee-most frame first.
+struct KernelStack {
+    timestamp_mono: u64,
+    frames: Vec<u64>,
+}
+
+/// A kernel stack whose frames are still arriving, per CPU.
+#[derive(Default)]
+struct PendingStack {
+    tid: u32,
+    timestamp_mono: u64,
+    expected_frame_count: usize,
+    frames: Vec<u64>,
+}
+
+pub struct KernelSampler {
+    kperf: Kperf,
+    buffer: Vec<KdBuf>,
+    pending_per_cpu: FastHashMap<u32, PendingStack>,
+    latest_per_thread: FastHashMap<u32, KernelStack>,
+}
+
+impl KernelSampler {
+    /// Sets up kdebug tracing and starts kperf's sampling timer. Needs root.
+    pub fn start(interval: Duration) -> Result<Self, String> {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err("Kernel stack sampling needs root privileges (sudo).".to_string());
+        }
+        let kperf = Kperf::load()?;
+
+        // Start from a clean state, in case a previous session didn't clean up.
+        let _ = kdebug_op(KERN_KDREMOVE, 0);
+        unsafe { (kperf.kperf_reset)() };
+
+        let setup = || -> Result<(), std::io::Error> {
+            kdebug_op(KERN_KDSETBUF, KDEBUG_BUFFER_ENTRY_COUNT as c_int)?;
+            kdebug_op(KERN_KDSETUP, 0)?;
+            let mut typefilter = vec![0u8; KDBG_TYPEFILTER_BITMAP_SIZE];
+            for subclass in [PERF_THREADINFO, PERF_CALLSTACK] {
+                let bit = ((DBG_PERF << 8) | subclass) as usize;
+                typefilter[bit / 8] |= 1 << (bit % 8);
+            }
+            let mut len = 0;
+            kdebug_sysctl(
+                KERN_KDSET_TYPEFILTER,
+                0,
+                std::ptr::null_mut(),
+                &mut len,
+                typefilter.as_mut_ptr() as *mut c_void,
+                typefilter.len(),
+            )?;
+            kdebug_op(KERN_KDENABLE, KDEBUG_ENABLE_TRACE)
+        };
+        setup().map_err(|e| format!("Could not set up kdebug tracing: {e}"))?;
+
+        let ok = unsafe {
+            let period = (kperf.kperf_ns_to_ticks)(interval.as_nanos() as u64);
+            (kperf.kperf_action_count_set)(1) == 0
+                && (kperf.kperf_action_samplers_set)(
+                    1,
+                    KPERF_SAMPLER_TH_INFO | KPERF_SAMPLER_KSTACK,
+                ) == 0
+                && (kperf.kperf_timer_count_set)(1) == 0
+                && (kperf.kperf_timer_period_set)(0, period) == 0
+                && (kperf.kperf_timer_action_set)(0, 1) == 0
+                && (kperf.kperf_sample_set)(1) == 0
+        };
+        if !ok {
+            unsafe { (kperf.kperf_reset)() };
+            let _ = kdebug_op(KERN_KDREMOVE, 0);
+            return Err("Could not configure kperf sampling".to_string());
+        }
+
+        Ok(KernelSampler {
+            kperf,
+            buffer: vec![KdBuf::default(); KDEBUG_BUFFER_ENTRY_COUNT],
+            pending_per_cpu: FastHashMap::default(),
+            latest_per_thread: FastHashMap::default(),
+        })
+    }
+
+    /// Reads the trace events which arrived since the last call. Called once
+    /// per sampling tick, before the user stacks are sampled.
+    pub fn read_pending(&mut self) {
+        let mut count = self.buffer.len() * std::mem::size_of::<KdBuf>();
+        let result = kdebug_sysctl(
+            KERN_KDREADTR,
+            0,
+            self.buffer.as_mut_ptr() as *mut c_void,
+            &mut count,
+            std::ptr::null_mut(),
+            0,
+        );
+        if let Err(e) = result {
+            log::warn!("Reading kdebug trace events failed: {e}");
+            return;
+        }
+        // KERN_KDREADTR returns the number of events, not the number of bytes.
+        for i in 0..count.min(self.buffer.len()) {
+            let event = self.buffer[i];
+            self.handle_event(&event);
+        }
+    }
+
+    fn handle_event(&mut self, event: &KdBuf) {
+        if event.class() != DBG_PERF {
+            return;
+        }
+        match (event.subclass(), event.code()) {
+            (PERF_THREADINFO, PERF_TI_SAMPLE) => {
+                // arg1 is the pid, arg2 the thread ID of the sampled thread.
+                self.pending_per_cpu.insert(
+                    event.cpuid,
+                    PendingStack {
+                        tid: event.arg2 as u32,
+                        timestamp_mono: mach_ticks_to_nanos(event.timestamp),
+                        ..Default::default()
+                    },
+                );
+            }
+            (PERF_CALLSTACK, PERF_CS_KHDR) => {
+                // arg1 has flags, arg2 the number of frames which follow in
+                // PERF_CS_KDATA events, four per event.
+                if let Some(pending) = self.pending_per_cpu.get_mut(&event.cpuid) {
+                    pending.expected_frame_count =
+                        (event.arg2 as usize).min(MAX_KERNEL_STACK_DEPTH);
+                    pending.frames.clear();
+                    if pending.expected_frame_count == 0 {
+                        self.pending_per_cpu.remove(&event.cpuid);
+                    }
+                }
+            }
+            (PERF_CALLSTACK, PERF_CS_KDATA) => {
+                let Some(pending) = self.pending_per_cpu.get_mut(&event.cpuid) else {
+                    return;
+                };
+                for frame in [event.arg1, event.arg2, event.arg3, event.arg4] {
+                    if pending.frames.len() < pending.expected_frame_count {
+                        pending.frames.push(frame);
+                    }
+                }
+                if pending.frames.len() >= pending.expected_frame_count {
+                    let pending = self.pending_per_cpu.remove(&event.cpuid).unwrap();
+                    let frames: Vec<u64> = pending.frames.into_iter().filter(|f| *f != 0).collect();
+                    if !frames.is_empty() {
+                        self.latest_per_thread.insert(
+                            pending.tid,
+                            KernelStack {
+                                timestamp_mono: pending.timestamp_mono,
+                                frames,
+                            },
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the frames of the most recent kernel stack of this thread, if
+    /// it was sampled after `since_mono`. The callee-most frame comes first.
+    pub fn kernel_stack_since(&self, tid: u32, since_mono: u64) -> Option<&[u64]> {
+        let stack = self.latest_per_thread.get(&tid)?;
+        (stack.timestamp_mono > since_mono).then_some(&stack.frames[..])
+    }
+}
+
+impl Drop for KernelSampler {
+    fn drop(&mut self) {
+        unsafe {
+            (self.kperf.kperf_sample_set)(0);
+            (self.kperf.kperf_reset)();
+        }
+        let _ = kdebug_op(KERN_KDENABLE, 0);
+        let _ = kdebug_op(KERN_KDREMOVE, 0);
+    }
+}
+
+/// Adds the running kernel as a library, so that kernel frames can be
+/// symbolicated. kperf reports unslid kernel addresses, so the mapping uses
+/// the addresses from the kernel binary on disk.
+pub fn add_kernel_lib(profile: &mut Profile) {
+    let Some(uuid) = running_kernel_uuid() else {
+        return;
+    };
+    let Some((path, address_range)) = find_kernel_binary(uuid) else {
+        log::info!("Could not find the kernel binary with UUID {uuid}");
+        return;
+    };
+    let path = path.to_string_lossy().into_owned();
+    let name = path.rsplit('/').next().unwrap_or(&path).to_string();
+    let lib = profile.add_lib(LibraryInfo {
+        name: name.clone(),
+        debug_name: name,
+        path: path.clone(),
+        debug_path: path,
+        debug_id: DebugId::from_uuid(uuid),
+        code_id: Some(CodeId::MachoUuid(uuid).to_string()),
+        arch: None,
+    });
+    profile.add_kernel_lib_mapping(lib, address_range.0, address_range.1, 0);
+}
+
+fn running_kernel_uuid() -> Option<Uuid> {
+    let mut buf = [0u8; 64];
+    let mut len = buf.len();
+    let ret = unsafe {
+        libc::sysctlbyname(
+            c"kern.uuid".as_ptr(),
+            buf.as_mut_ptr() as *mut c_void,
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    let s = CStr::from_bytes_until_nul(&buf[..len])
+        .ok()?
+        .to_str()
+        .ok()?;
+    Uuid::parse_str(s).ok()
+}
+
+/// Finds the kernel with this UUID in /System/Library/Kernels, and returns its
+/// path and the address range from the start of its __TEXT segment to the end
+/// of its last segment.
+fn find_kernel_binary(uuid: Uuid) -> Option<(std::path::PathBuf, (u64, u64))> {
+    let dir = Path::new("/System/Library/Kernels");
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        let Ok(data) = std::fs::read(&path) else {
+            continue;
+        };
+        let Ok(file) = object::File::parse(&data[..]) else {
+            continue;
+        };
+        if file.mach_uuid().ok().flatten() != Some(*uuid.as_bytes()) {
+            continue;
+        }
+        let mut start = None;
+        let mut end = 0;
+        for segment in file.segments() {
+            if segment.name().ok().flatten() == Some("__TEXT") {
+                start = Some(segment.address());
+            }
+            end = end.max(segment.address() + segment.size());
+        }
+        return Some((path, (start?, end)));
+    }
+    None
+}
@@ -4,11 +4,13 @@ mod dyld_bindings;
 pub mod codesign_setup;
 mod error;
 pub mod kernel_error;
+mod kernel_sampler;
 mod mach_ipc;
 mod proc_maps;
 mod process_launcher;
 pub mod profiler;
 mod sampler;
+pub mod sudo_helper;
 mod task_profiler;
 pub mod thread_act;
 pub mod thread_info;
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::Write;
@@ -7,17 +7,22 @@ use std::os::unix::prelude::OsStrExt;
 use std::path::PathBuf;
 use std::process::{Child, Command, ExitStatus};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
 use flate2::write::GzDecoder;
 use mach2::port::{mach_port_t, MACH_PORT_NULL};
 use mach2::task::{task_resume, task_suspend};
 use mach2::traps::{mach_task_self, task_for_pid};
 use tempfile::tempdir;
+use tokio::sync::oneshot::error::TryRecvError;
 
 use super::mach_ipc::{BlockingMode, OsIpcMultiShotServer};
 pub use super::mach_ipc::{MachError, OsIpcSender};
+use super::sampler::{TaskInit, TaskInitOrShutdown};
+use super::time::get_monotonic_timestamp;
 use crate::shared::ctrl_c::CtrlC;
+use crate::shared::process_sample_data::RestartMarker;
 
 pub trait RootTaskRunner {
     fn run_root_task(&mut self) -> Result<ExitStatus, MachError>;
@@ -271,6 +276,9 @@ impl AcceptedTask {
 pub struct ExistingProcessRunner {
     pid: u32,
     aux_child: Option<Child>,
+    /// Disconnects when the sampler stops on its own, e.g. because the time
+    /// limit was reached.
+    sampler_done: Receiver<()>,
 }
 
 impl RootTaskRunner for ExistingProcessRunner {
@@ -279,9 +287,17 @@ impl RootTaskRunner for ExistingProcessRunner {
 
         eprintln!("Profiling {}, press Ctrl-C to stop...", self.pid);
 
-        ctrl_c_receiver
-            .blocking_recv()
-            .expect("Ctrl+C receiver failed");
+        loop {
+            match ctrl_c_receiver.try_recv() {
+                Err(TryRecvError::Empty) => {}
+                Ok(()) => break,
+                Err(TryRecvError::Closed) => panic!("Ctrl+C receiver failed"),
+            }
+            match self.sampler_done.recv_timeout(Duration::from_millis(50)) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+        }
 
         if let Some(aux_child) = self.aux_child.as_mut() {
             let aux_pid = aux_child.id();
@@ -316,26 +332,24 @@ impl ExistingProcessRunner {
         descendants
     }
 
-    pub fn new(pid: u32, task_accepter: &mut TaskAccepter) -> ExistingProcessRunner {
+    pub fn new(
+        pid: u32,
+        task_accepter: &mut TaskAccepter,
+        sampler_done: Receiver<()>,
+    ) -> ExistingProcessRunner {
         let mut queue_pid = |pid, failure_is_ok| {
-            let task = unsafe {
-                let mut task = MACH_PORT_NULL;
-                let kr = task_for_pid(mach_task_self(), pid as i32, &mut task);
-                if kr != 0 {
+            let task = match suspended_task_for_pid(pid) {
+                Ok(task) => task,
+                Err(kr) => {
                     if failure_is_ok {
                         eprintln!("Warning: task_for_pid for child task failed with error code {kr}. Ignoring child, it may have already exited.");
                         return;
                     }
 
                     eprintln!("Error: task_for_pid for target task failed with error code {kr}.");
-                    eprintln!(
-                        "Please run 'samply setup' in order to grant appropriate entitlements"
-                    );
-                    eprintln!("to the binary.");
+                    super::codesign_setup::explain_attach_failure(pid);
                     std::process::exit(1);
                 }
-                task_suspend(task);
-                task
             };
             task_accepter.queue_received_stuff(ReceivedStuff::AcceptedTask(AcceptedTask {
                 task,
@@ -356,6 +370,7 @@ impl ExistingProcessRunner {
         ExistingProcessRunner {
             pid,
             aux_child: None,
+            sampler_done,
         }
     }
 
@@ -363,9 +378,10 @@ impl ExistingProcessRunner {
     pub fn new_with_aux_child(
         pid: u32,
         task_accepter: &mut TaskAccepter,
+        sampler_done: Receiver<()>,
         aux_child: Child,
     ) -> ExistingProcessRunner {
-        let runner = Self::new(pid, task_accepter);
+        let runner = Self::new(pid, task_accepter, sampler_done);
 
         ExistingProcessRunner {
             aux_child: Some(aux_child),
@@ -374,6 +390,421 @@ impl ExistingProcessRunner {
     }
 }
 
+/// The task port of the process, suspended. Returns the error code of
+/// `task_for_pid` on failure.
+fn suspended_task_for_pid(pid: u32) -> Result<mach_port_t, i32> {
+    unsafe {
+        let mut task = MACH_PORT_NULL;
+        let kr = task_for_pid(mach_task_self(), pid as i32, &mut task);
+        if kr != 0 {
+            return Err(kr);
+        }
+        task_suspend(task);
+        Ok(task)
+    }
+}
+
+/// Records the process of a launchd job and its descendants, for
+/// `--launchd-label`. launchd is asked for the job's process again and again,
+/// so that the new process is recorded when the job is restarted.
+pub struct LaunchdJobRunner {
+    label: String,
+    task_sender: Sender<TaskInitOrShutdown>,
+    /// The job's process and its descendants, as of the last check.
+    recorded_pids: HashSet<u32>,
+    /// The job's process as of the last check, and how often it has changed.
+    job_pid: u32,
+    restart_count: u32,
+    /// Disconnects when the sampler stops on its own, e.g. because the time
+    /// limit was reached.
+    sampler_done: Receiver<()>,
+}
+
+impl RootTaskRunner for LaunchdJobRunner {
+    fn run_root_task(&mut self) -> Result<ExitStatus, MachError> {
+        let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+
+        eprintln!(
+            "Recording launchd job {}, press Ctrl-C to stop...",
+            self.label
+        );
+
+        loop {
+            match ctrl_c_receiver.try_recv() {
+                Err(TryRecvError::Empty) => {}
+                Ok(()) => break,
+                Err(TryRecvError::Closed) => panic!("Ctrl+C receiver failed"),
+            }
+            match self.sampler_done.recv_timeout(LAUNCHD_POLL_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+            // The job can be unloaded and loaded again, so it's only an
+            // error if the job doesn't exist at the start.
+            if let Ok(Some(pid)) = launchd_job_pid(&self.label) {
+                self.record_new_processes(pid, true);
+            }
+        }
+
+        eprintln!("Done.");
+
+        Ok(ExitStatus::default())
+    }
+}
+
+/// How often launchd is asked for the job's process.
+const LAUNCHD_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+impl LaunchdJobRunner {
+    /// Waits for the job to have a process and starts recording it. Returns
+    /// `None` if Ctrl+C is pressed before that.
+    pub fn new(
+        label: &str,
+        task_sender: Sender<TaskInitOrShutdown>,
+        sampler_done: Receiver<()>,
+    ) -> Option<LaunchdJobRunner> {
+        let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+        let mut announced_wait = false;
+        let pid = loop {
+            match launchd_job_pid(label) {
+                Ok(Some(pid)) => break pid,
+                Ok(None) if !announced_wait => {
+                    eprintln!("Waiting for launchd job {label} to start...");
+                    announced_wait = true;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    eprintln!("Error: Could not find the launchd job {label}: {err}");
+                    eprintln!("The jobs of system daemons are only visible to root, so you may need to run samply with sudo.");
+                    std::process::exit(1);
+                }
+            }
+            if ctrl_c_receiver.try_recv().is_ok() {
+                return None;
+            }
+            std::thread::sleep(LAUNCHD_POLL_INTERVAL);
+        };
+
+        let mut runner = LaunchdJobRunner {
+            label: label.to_string(),
+            task_sender,
+            recorded_pids: HashSet::new(),
+            job_pid: pid,
+            restart_count: 0,
+            sampler_done,
+        };
+        runner.record_new_processes(pid, false);
+        Some(runner)
+    }
+
+    /// Starts recording the job's process and its descendants, if they aren't
+    /// recorded yet.
+    fn record_new_processes(&mut self, job_pid: u32, failure_is_ok: bool) {
+        let mut pids = vec![job_pid];
+        pids.extend(ExistingProcessRunner::get_all_descendant_pids(job_pid));
+        // Forget the processes which have quit, so that a reused pid is
+        // recorded again.
+        self.recorded_pids.retain(|pid| pids.contains(pid));
+
+        for pid in pids {
+            if !self.recorded_pids.insert(pid) {
+                continue;
+            }
+            let task = match suspended_task_for_pid(pid) {
+                Ok(task) => task,
+                Err(kr) if failure_is_ok || pid != job_pid => {
+                    eprintln!("Warning: task_for_pid for process {pid} failed with error code {kr}. Ignoring it, it may have already exited.");
+                    continue;
+                }
+                Err(kr) => {
+                    eprintln!("Error: task_for_pid for target task failed with error code {kr}.");
+                    super::codesign_setup::explain_attach_failure(pid);
+                    std::process::exit(1);
+                }
+            };
+            // The job's new process after a restart gets a restart marker.
+            let mut restart = None;
+            if failure_is_ok && pid == job_pid {
+                eprintln!(
+                    "Recording the new process of launchd job {}, with PID {pid}.",
+                    self.label
+                );
+                if pid != self.job_pid {
+                    self.restart_count += 1;
+                    restart = Some(RestartMarker {
+                        number: self.restart_count,
+                        previous_pid: self.job_pid,
+                    });
+                    self.job_pid = pid;
+                }
+            }
+            send_task_to_sampler(&self.task_sender, task, pid, restart);
+        }
+    }
+}
+
+/// Hands the suspended task of a process which we didn't launch to the
+/// sampler, and resumes it.
+fn send_task_to_sampler(
+    task_sender: &Sender<TaskInitOrShutdown>,
+    task: mach_port_t,
+    pid: u32,
+    restart: Option<RestartMarker>,
+) {
+    // Processes which we didn't launch don't tell us about jitdump files, so
+    // nothing is ever sent on this channel.
+    let (_path_sender, path_receiver) = unbounded();
+    let _ = task_sender.send(TaskInitOrShutdown::TaskInit(TaskInit {
+        start_time_mono: get_monotonic_timestamp(),
+        task,
+        pid,
+        path_receiver,
+        restart,
+    }));
+    unsafe { task_resume(task) };
+}
+
+/// Records an existing process and its descendants, for `--pid` or
+/// `--wait-for-process` with `--follow-restarts`. Once all of them have quit,
+/// the next process whose executable name contains the name is recorded
+/// from its start, with a restart marker.
+pub struct RestartingProcessRunner {
+    name: String,
+    task_sender: Sender<TaskInitOrShutdown>,
+    /// The recorded processes which haven't quit yet.
+    running_pids: Vec<u32>,
+    /// The process which was recorded last.
+    previous_pid: u32,
+    restart_count: u32,
+    /// All processes which existed at the last check, so that only processes
+    /// which were started since then are considered.
+    known_pids: HashSet<u32>,
+    /// The new processes which didn't match the name yet, with the time they
+    /// were first seen. A new process may not have exec'd yet.
+    unmatched_pids: HashMap<u32, Instant>,
+    /// Disconnects when the sampler stops on its own, e.g. because the time
+    /// limit was reached.
+    sampler_done: Receiver<()>,
+}
+
+impl RootTaskRunner for RestartingProcessRunner {
+    fn run_root_task(&mut self) -> Result<ExitStatus, MachError> {
+        let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+
+        eprintln!(
+            "Profiling {} and its restarts, press Ctrl-C to stop...",
+            self.previous_pid
+        );
+
+        loop {
+            match ctrl_c_receiver.try_recv() {
+                Err(TryRecvError::Empty) => {}
+                Ok(()) => break,
+                Err(TryRecvError::Closed) => panic!("Ctrl+C receiver failed"),
+            }
+            self.running_pids.retain(|&pid| is_running(pid));
+            let poll_interval = if self.running_pids.is_empty() {
+                RESTART_WAITING_POLL_INTERVAL
+            } else {
+                RESTART_RUNNING_POLL_INTERVAL
+            };
+            match self.sampler_done.recv_timeout(poll_interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+            self.record_restarted_processes();
+        }
+
+        eprintln!("Done.");
+
+        Ok(ExitStatus::default())
+    }
+}
+
+/// How often the recorded processes are checked while they're running.
+const RESTART_RUNNING_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How often the process list is checked for a new instance once they have
+/// quit, like in `wait_for_process_named`.
+const RESTART_WAITING_POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// How long a new process can take to exec after it was forked.
+const RESTART_EXEC_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+impl RestartingProcessRunner {
+    pub fn new(
+        root_pid: u32,
+        name: &str,
+        task_sender: Sender<TaskInitOrShutdown>,
+        sampler_done: Receiver<()>,
+    ) -> RestartingProcessRunner {
+        let mut running_pids = vec![root_pid];
+        running_pids.extend(ExistingProcessRunner::get_all_descendant_pids(root_pid));
+        for &pid in &running_pids {
+            match suspended_task_for_pid(pid) {
+                Ok(task) => send_task_to_sampler(&task_sender, task, pid, None),
+                Err(kr) if pid != root_pid => {
+                    eprintln!("Warning: task_for_pid for child task failed with error code {kr}. Ignoring child, it may have already exited.");
+                }
+                Err(kr) => {
+                    eprintln!("Error: task_for_pid for target task failed with error code {kr}.");
+                    super::codesign_setup::explain_attach_failure(pid);
+                    std::process::exit(1);
+                }
+            }
+        }
+        RestartingProcessRunner {
+            name: name.to_string(),
+            task_sender,
+            running_pids,
+            previous_pid: root_pid,
+            restart_count: 0,
+            known_pids: list_all_pids().into_iter().collect(),
+            unmatched_pids: HashMap::new(),
+            sampler_done,
+        }
+    }
+
+    /// Once all recorded processes have quit, records the new processes
+    /// whose executable name contains the name.
+    fn record_restarted_processes(&mut self) {
+        let pids: HashSet<u32> = list_all_pids().into_iter().collect();
+        let now = Instant::now();
+        if self.running_pids.is_empty() {
+            for &pid in &pids {
+                if self.known_pids.contains(&pid) {
+                    match self.unmatched_pids.get(&pid) {
+                        Some(&first_seen) if now - first_seen < RESTART_EXEC_GRACE_PERIOD => {}
+                        _ => continue,
+                    }
+                }
+                if !process_exe_name(pid).is_some_and(|exe_name| exe_name.contains(&self.name)) {
+                    self.unmatched_pids.entry(pid).or_insert(now);
+                    continue;
+                }
+                let Ok(task) = suspended_task_for_pid(pid) else {
+                    continue;
+                };
+                // Only the first new process gets the restart marker.
+                let restart = if self.running_pids.is_empty() {
+                    self.restart_count += 1;
+                    eprintln!("The process was restarted, recording process with PID {pid}.");
+                    Some(RestartMarker {
+                        number: self.restart_count,
+                        previous_pid: self.previous_pid,
+                    })
+                } else {
+                    None
+                };
+                send_task_to_sampler(&self.task_sender, task, pid, restart);
+                self.running_pids.push(pid);
+                self.previous_pid = pid;
+            }
+        }
+        // Forget the processes which have quit, so that a reused pid is
+        // considered again.
+        self.unmatched_pids.retain(|pid, first_seen| {
+            pids.contains(pid) && now - *first_seen < RESTART_EXEC_GRACE_PERIOD
+        });
+        self.known_pids = pids;
+    }
+}
+
+/// Whether the process exists. Processes of other users can't be signaled,
+/// but they exist.
+fn is_running(pid: u32) -> bool {
+    let result = unsafe { libc::kill(pid as i32, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// The pid of the launchd job's process, from `launchctl list <label>`, or
+/// `None` if the job isn't running. Fails if there's no job with this label.
+fn launchd_job_pid(label: &str) -> Result<Option<u32>, String> {
+    let output = Command::new("launchctl")
+        .args(["list", label])
+        .output()
+        .map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    // The output is a property list in the "old-style" format, with a line
+    // like `"PID" = 123;` while the job is running.
+    let pid = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| {
+            let pid = line.trim().strip_prefix("\"PID\" = ")?;
+            pid.trim_end_matches(';').parse().ok()
+        });
+    Ok(pid)
+}
+
+/// Polls the process list until a new process whose executable name contains `name`
+/// appears. Returns `None` if Ctrl+C is pressed before a matching process appears.
+pub fn wait_for_process_named(name: &str) -> Option<u32> {
+    let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+    let mut known_pids: HashSet<u32> = list_all_pids().into_iter().collect();
+    eprintln!("Waiting for a process named \"{name}\" to start...");
+    loop {
+        for pid in list_all_pids() {
+            if !known_pids.insert(pid) {
+                continue;
+            }
+            if process_exe_name(pid).is_some_and(|exe_name| exe_name.contains(name)) {
+                eprintln!("Found process {pid}, starting to record.");
+                return Some(pid);
+            }
+        }
+        if ctrl_c_receiver.try_recv().is_ok() {
+            return None;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn list_all_pids() -> Vec<u32> {
+    extern "C" {
+        pub fn proc_listallpids(buffer: *mut c_void, buffersize: c_int) -> c_int;
+    }
+
+    let count_or_err = unsafe { proc_listallpids(core::ptr::null_mut(), 0) };
+    if count_or_err <= 0 {
+        return Vec::new();
+    }
+
+    // Leave some room for processes which start between the two calls.
+    let reserved_count = count_or_err as usize + 64;
+    let mut pids: Vec<u32> = Vec::with_capacity(reserved_count);
+    let buffer_size = (reserved_count * core::mem::size_of::<u32>()) as c_int;
+    let count_or_err = unsafe { proc_listallpids(pids.as_mut_ptr().cast::<c_void>(), buffer_size) };
+    if count_or_err <= 0 {
+        return Vec::new();
+    }
+    unsafe {
+        pids.set_len((count_or_err as usize).min(reserved_count));
+    }
+    pids
+}
+
+pub fn process_exe_name(pid: u32) -> Option<String> {
+    extern "C" {
+        pub fn proc_pidpath(pid: c_int, buffer: *mut c_void, buffersize: u32) -> c_int;
+    }
+    const PROC_PIDPATHINFO_MAXSIZE: usize = 4096;
+
+    let mut buffer = vec![0u8; PROC_PIDPATHINFO_MAXSIZE];
+    let len = unsafe {
+        proc_pidpath(
+            pid as c_int,
+            buffer.as_mut_ptr().cast::<c_void>(),
+            buffer.len() as u32,
+        )
+    };
+    if len <= 0 {
+        return None;
+    }
+    buffer.truncate(len as usize);
+    let path = PathBuf::from(OsStr::from_bytes(&buffer));
+    Some(path.file_name()?.to_string_lossy().into_owned())
+}
+
 fn find_child_processes(parent_pid: u32) -> Option<Vec<u32>> {
     extern "C" {
         pub fn proc_listpids(
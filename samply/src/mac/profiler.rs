@@ -9,7 +9,8 @@ use fxprof_processed_profile::Profile;
 
 use super::error::SamplingError;
 use super::process_launcher::{
-    ExistingProcessRunner, MachError, ReceivedStuff, RootTaskRunner, TaskAccepter, TaskLauncher,
+    process_exe_name, wait_for_process_named, ExistingProcessRunner, LaunchdJobRunner, MachError,
+    ReceivedStuff, RestartingProcessRunner, RootTaskRunner, TaskAccepter, TaskLauncher,
 };
 use super::sampler::{ProcessSpecificPath, Sampler, TaskInit, TaskInitOrShutdown};
 use super::time::get_monotonic_timestamp;
@@ -23,6 +24,14 @@ pub fn run(
     mut profile_creation_props: ProfileCreationProps,
 ) -> Result<(Profile, ExitStatus), MachError> {
     let mut task_accepter = TaskAccepter::new()?;
+    // The sender is dropped when the sampler thread is done.
+    let (sampler_done_sender, sampler_done_receiver) = unbounded::<()>();
+    let (task_sender, task_receiver) = unbounded();
+    let wait_for_new_tasks = match recording_mode {
+        RecordingMode::LaunchdJob(_) => true,
+        RecordingMode::Pid(_) | RecordingMode::WaitForProcess(_) => recording_props.follow_restarts,
+        _ => false,
+    };
 
     let mut root_task_runner: Box<dyn RootTaskRunner> = match recording_mode {
         RecordingMode::All => {
@@ -30,7 +39,57 @@ pub fn run(
             eprintln!("You can only profile processes which you launch via samply, or attach to via --pid.");
             std::process::exit(1)
         }
-        RecordingMode::Pid(pid) => Box::new(ExistingProcessRunner::new(pid, &mut task_accepter)),
+        RecordingMode::Pid(pid) if recording_props.follow_restarts => {
+            let Some(name) = process_exe_name(pid) else {
+                eprintln!("Error: There is no process with PID {pid}.");
+                std::process::exit(1)
+            };
+            Box::new(RestartingProcessRunner::new(
+                pid,
+                &name,
+                task_sender.clone(),
+                sampler_done_receiver,
+            ))
+        }
+        RecordingMode::Pid(pid) => Box::new(ExistingProcessRunner::new(
+            pid,
+            &mut task_accepter,
+            sampler_done_receiver,
+        )),
+        RecordingMode::WaitForProcess(name) => {
+            let Some(pid) = wait_for_process_named(&name) else {
+                std::process::exit(0)
+            };
+            if recording_props.follow_restarts {
+                Box::new(RestartingProcessRunner::new(
+                    pid,
+                    &name,
+                    task_sender.clone(),
+                    sampler_done_receiver,
+                ))
+            } else {
+                Box::new(ExistingProcessRunner::new(
+                    pid,
+                    &mut task_accepter,
+                    sampler_done_receiver,
+                ))
+            }
+        }
+        RecordingMode::LaunchdJob(label) => {
+            let Some(runner) =
+                LaunchdJobRunner::new(&label, task_sender.clone(), sampler_done_receiver)
+            else {
+                std::process::exit(0)
+            };
+            Box::new(runner)
+        }
+        RecordingMode::Multiple { .. } => {
+            eprintln!(
+                "Error: Recording several processes at once is currently only supported on Linux."
+            );
+            eprintln!("You can attach to one process via --pid, or launch a process via samply.");
+            std::process::exit(1)
+        }
         RecordingMode::Launch(process_launch_props) => {
             let ProcessLaunchProps {
                 mut env_vars,
@@ -75,10 +134,14 @@ pub fn run(
         }
     };
 
-    let (task_sender, task_receiver) = unbounded();
-
     let sampler_thread = thread::spawn(move || {
-        let sampler = Sampler::new(task_receiver, recording_props, profile_creation_props);
+        let _sampler_done_sender = sampler_done_sender;
+        let sampler = Sampler::new(
+            task_receiver,
+            recording_props,
+            profile_creation_props,
+            wait_for_new_tasks,
+        );
         sampler.run()
     });
 
@@ -106,6 +169,7 @@ pub fn run(
                         task: accepted_task.task(),
                         pid,
                         path_receiver,
+                        restart: None,
                     }));
                     path_senders_per_pid.insert(pid, path_sender);
                     if send_result.is_err() {
@@ -196,6 +260,8 @@ pub fn run(
             eprintln!("On macOS, samply cannot profile system commands, such as the sleep command or system python. This is because system executables are signed in such a way that they block the DYLD_INSERT_LIBRARIES environment variable, which subverts samply's attempt to siphon out the mach task port of the process.");
             eprintln!();
             eprintln!("Suggested remedy: You can profile any binaries that you've compiled yourself, or which are unsigned or locally-signed, such as anything installed by cargo install or by Homebrew.");
+            eprintln!();
+            eprintln!("Programs which use the hardened runtime also block DYLD_INSERT_LIBRARIES. For these, start the program yourself and attach to it with `samply record --pid <pid>`.");
             std::process::exit(1)
         }
         Err(e) => {
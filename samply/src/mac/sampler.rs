@@ -4,16 +4,19 @@ use std::time::{Duration, SystemTime};
 use std::{mem, thread};
 
 use crossbeam_channel::Receiver;
-use fxprof_processed_profile::{
-    Category, CategoryColor, Profile, ReferenceTimestamp, SubcategoryHandle,
-};
+use fxprof_processed_profile::{Category, CategoryColor, Profile, SubcategoryHandle};
 use mach2::port::mach_port_t;
 
 use super::error::SamplingError;
+use super::kernel_sampler::{self, KernelSampler};
 use super::task_profiler::TaskProfiler;
-use super::time::get_monotonic_timestamp;
+use super::time::{get_continuous_timestamp, get_monotonic_timestamp};
+use crate::shared::interval_jitter::IntervalJitter;
+use crate::shared::process_sample_data::RestartMarker;
 use crate::shared::prop_types::{ProfileCreationProps, RecordingProps};
 use crate::shared::recycling::ProcessRecycler;
+use crate::shared::suspend::{add_power_track, add_sleep_instant, SleepDetector};
+use crate::shared::thread_rates::ThreadRates;
 use crate::shared::timestamp_converter::TimestampConverter;
 use crate::shared::unresolved_samples::UnresolvedStacks;
 
@@ -36,12 +39,18 @@ pub struct TaskInit {
     pub task: mach_port_t,
     pub pid: u32,
     pub path_receiver: Receiver<ProcessSpecificPath>,
+    /// Set if the task is a new instance of the recorded process, with
+    /// `--follow-restarts`.
+    pub restart: Option<RestartMarker>,
 }
 
 pub struct Sampler {
     task_receiver: Receiver<TaskInitOrShutdown>,
     recording_props: Arc<RecordingProps>,
     profile_creation_props: Arc<ProfileCreationProps>,
+    /// Keep running once all tasks have terminated, until the Shutdown
+    /// message, e.g. while a launchd job is restarting.
+    wait_for_new_tasks: bool,
 }
 
 impl Sampler {
@@ -49,11 +58,13 @@ impl Sampler {
         task_receiver: Receiver<TaskInitOrShutdown>,
         recording_props: RecordingProps,
         profile_creation_props: ProfileCreationProps,
+        wait_for_new_tasks: bool,
     ) -> Self {
         Sampler {
             task_receiver,
             recording_props: Arc::new(recording_props),
             profile_creation_props: Arc::new(profile_creation_props),
+            wait_for_new_tasks,
         }
     }
 
@@ -61,15 +72,24 @@ impl Sampler {
         let reference_mono = get_monotonic_timestamp();
         let reference_system_time = SystemTime::now();
 
-        let timestamp_converter = TimestampConverter {
-            reference_raw: reference_mono,
-            raw_to_ns_factor: 1,
-        };
+        let (timestamp_converter, reference_timestamp) = TimestampConverter::with_reference_time(
+            reference_mono,
+            reference_system_time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+            self.profile_creation_props.utc_timestamps,
+        );
 
+        // With per-thread rates, we wake up at the highest rate, and sample
+        // weights are in units of this tick interval.
+        let thread_rates = ThreadRates::new(
+            self.recording_props.interval,
+            &self.recording_props.thread_rate_overrides,
+        );
         let mut profile = Profile::new(
             self.profile_creation_props.profile_name(),
-            ReferenceTimestamp::from_system_time(reference_system_time),
-            self.recording_props.interval.into(),
+            reference_timestamp,
+            thread_rates.tick_interval().into(),
         );
         if let Some(macos_name_and_version) = get_macos_name_and_version() {
             profile.set_os_name(&macos_name_and_version);
@@ -81,6 +101,21 @@ impl Sampler {
         let default_category = SubcategoryHandle::from(
             profile.handle_for_category(Category("User", CategoryColor::Yellow)),
         );
+        let mut kernel_category = default_category;
+
+        let mut kernel_sampler = None;
+        if self.recording_props.kernel_stacks {
+            match KernelSampler::start(thread_rates.tick_interval()) {
+                Ok(sampler) => {
+                    kernel_sampler::add_kernel_lib(&mut profile);
+                    kernel_category = SubcategoryHandle::from(
+                        profile.handle_for_category(Category("Kernel", CategoryColor::Orange)),
+                    );
+                    kernel_sampler = Some(sampler);
+                }
+                Err(e) => eprintln!("Not sampling kernel stacks: {e}"),
+            }
+        }
 
         let root_task_init = match self.task_receiver.recv() {
             Ok(TaskInitOrShutdown::TaskInit(task_init)) => task_init,
@@ -115,7 +150,14 @@ impl Sampler {
         let mut unwinder_cache = Default::default();
         let mut unresolved_stacks = UnresolvedStacks::default();
         let mut last_sleep_overshoot = 0;
+        let interval_jitter = IntervalJitter::new(
+            thread_rates.tick_interval(),
+            self.recording_props.interval_jitter,
+        );
         let mut stop_profiling = false;
+        let mut sleep_detector = SleepDetector::new(reference_mono, get_continuous_timestamp());
+        let mut power_track = None;
+        let mut sample_round_count = 0;
 
         loop {
             loop {
@@ -160,7 +202,7 @@ impl Sampler {
                 break;
             }
 
-            if live_tasks.is_empty() {
+            if live_tasks.is_empty() && !self.wait_for_new_tasks {
                 eprintln!("All tasks terminated.");
                 break;
             }
@@ -175,11 +217,27 @@ impl Sampler {
 
             let sample_timestamp = timestamp_converter.convert_time(sample_mono);
 
+            if let Some(slept) = sleep_detector.check(sample_mono, get_continuous_timestamp()) {
+                let power_track = *power_track
+                    .get_or_insert_with(|| add_power_track(&mut profile, sample_timestamp));
+                add_sleep_instant(
+                    &mut profile,
+                    power_track,
+                    sample_timestamp,
+                    slept,
+                    "mach_continuous_time advanced further than mach_absolute_time",
+                );
+            }
+
+            if let Some(kernel_sampler) = kernel_sampler.as_mut() {
+                kernel_sampler.read_pending();
+            }
+
             let mut tasks = Vec::with_capacity(live_tasks.capacity());
             mem::swap(&mut live_tasks, &mut tasks);
             for mut task in tasks.into_iter() {
                 task.check_received_paths();
-                task.check_jitdump(&mut profile, &mut jit_category_manager);
+                task.check_jit_files(sample_mono, &mut profile, &mut jit_category_manager);
                 let still_alive = task.sample(
                     sample_timestamp,
                     sample_mono,
@@ -187,6 +245,9 @@ impl Sampler {
                     &mut profile,
                     &mut stack_scratch_buffer,
                     &mut unresolved_stacks,
+                    kernel_sampler.as_ref(),
+                    &thread_rates,
+                    sample_round_count,
                 )?;
                 if still_alive {
                     live_tasks.push(task);
@@ -205,8 +266,17 @@ impl Sampler {
                 }
             }
 
+            sample_round_count += 1;
+            if self
+                .recording_props
+                .sample_limit
+                .is_some_and(|limit| sample_round_count >= limit)
+            {
+                break;
+            }
+
             let intended_wakeup_time =
-                sample_mono + self.recording_props.interval.as_nanos() as u64;
+                sample_mono + interval_jitter.next_interval().as_nanos() as u64;
             let before_sleep = get_monotonic_timestamp();
             let indended_wait_time = intended_wakeup_time.saturating_sub(before_sleep);
             let sleep_time = indended_wait_time.saturating_sub(last_sleep_overshoot);
@@ -229,12 +299,15 @@ impl Sampler {
             process_sample_data.flush_samples_to_profile(
                 &mut profile,
                 default_category,
-                default_category,
+                kernel_category,
                 &mut stack_frame_scratch_buf,
                 &unresolved_stacks,
+                self.profile_creation_props.marker_stacks_from_samples,
             );
         }
 
+        interval_jitter.add_to_profile_meta(&mut profile);
+        thread_rates.add_to_profile_meta(&mut profile);
         Ok(profile)
     }
 }
@@ -0,0 +1,48 @@
+//! `samply record --use-sudo-helper`: the recording runs in a second samply
+//! which is started as root via sudo, because only root can get the task port
+//! of processes which belong to other users. The unprivileged samply serves
+//! the profile afterwards, so that the browser isn't launched as root.
+
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Runs this samply command line again via sudo, with `--use-sudo-helper`
+/// replaced by the hidden `--sudo-helper-child`, and waits for it to exit.
+pub fn rerun_as_root() -> std::io::Result<ExitStatus> {
+    let mut args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    if let Some(arg) = args.iter_mut().find(|arg| *arg == "--use-sudo-helper") {
+        *arg = "--sudo-helper-child".into();
+    }
+    eprintln!("Recording as root via sudo. You may be asked for your password.");
+    Command::new("sudo")
+        .arg("--")
+        .arg(std::env::current_exe()?)
+        .args(args)
+        .status()
+}
+
+/// Gives the files which the root samply wrote to the user who ran sudo, so
+/// that they can be served, moved and deleted without root.
+pub fn chown_to_sudo_user(paths: &[&Path]) {
+    let uid = std::env::var("SUDO_UID")
+        .ok()
+        .and_then(|uid| uid.parse().ok());
+    let gid = std::env::var("SUDO_GID")
+        .ok()
+        .and_then(|gid| gid.parse().ok());
+    if uid.is_none() {
+        return;
+    }
+    for path in paths {
+        if path.exists() {
+            if let Err(err) = std::os::unix::fs::chown(path, uid, gid) {
+                eprintln!("Could not change the owner of {}: {err}", path.display());
+            }
+        }
+    }
+}
@@ -4,6 +4,7 @@ use std::mem;
 use std::ops::{Deref, Range};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
 use crossbeam_channel::Receiver;
 use framehop::{
@@ -12,12 +13,14 @@ use framehop::{
 };
 use fxprof_processed_profile::debugid::DebugId;
 use fxprof_processed_profile::{
-    LibraryInfo, ProcessHandle, Profile, StringHandle, ThreadHandle, Timestamp,
+    CategoryHandle, CounterHandle, FrameFlags, LibraryInfo, MarkerTiming, ProcessHandle, Profile,
+    StringHandle, ThreadHandle, Timestamp,
 };
 use mach2::mach_types::{thread_act_port_array_t, thread_act_t};
 use mach2::message::mach_msg_type_number_t;
 use mach2::port::mach_port_t;
-use mach2::task::task_threads;
+use mach2::task::{task_info, task_threads};
+use mach2::task_info::task_info_t;
 use mach2::traps::mach_task_self;
 use mach2::vm::mach_vm_deallocate;
 use mach2::vm_types::{mach_vm_address_t, mach_vm_size_t};
@@ -27,6 +30,7 @@ use wholesym::samply_symbols;
 
 use super::error::SamplingError;
 use super::kernel_error::{IntoResult, KernelError};
+use super::kernel_sampler::KernelSampler;
 use super::proc_maps::{
     proc_cmdline, DyldInfo, DyldInfoManager, Modification, ModuleSvmaInfo, StackwalkerRef,
     VmSubData,
@@ -41,11 +45,15 @@ use crate::shared::lib_mappings::{
 };
 use crate::shared::marker_file;
 use crate::shared::marker_file::get_markers;
-use crate::shared::perf_map::try_load_perf_map;
+use crate::shared::open_handles::{OpenHandlesCounter, OPEN_HANDLES_POLL_INTERVAL};
+use crate::shared::perf_map::PerfMapProcessor;
 use crate::shared::process_name::make_process_name;
 use crate::shared::process_sample_data::{MarkerSpanOnThread, ProcessSampleData};
 use crate::shared::prop_types::ProfileCreationProps;
 use crate::shared::recycling::{ProcessRecycler, ProcessRecyclingData, ThreadRecycler};
+use crate::shared::sample_labels::label_frame_name;
+use crate::shared::suspend::{add_suspend_span, SuspendKind};
+use crate::shared::thread_rates::ThreadRates;
 use crate::shared::timestamp_converter::TimestampConverter;
 use crate::shared::unresolved_samples::{UnresolvedSamples, UnresolvedStacks};
 
@@ -109,6 +117,7 @@ pub struct TaskProfiler {
     unwinder: UnwinderNative<UnwindSectionBytes, MayAllocateDuringUnwind>,
     path_receiver: Receiver<ProcessSpecificPath>,
     jitdump_manager: JitDumpManager,
+    perf_map_processor: PerfMapProcessor,
     marker_file_paths: Vec<(ThreadHandle, PathBuf)>,
     unresolved_samples: UnresolvedSamples,
     lib_mapping_ops: LibMappingOpQueue,
@@ -116,6 +125,14 @@ pub struct TaskProfiler {
     jit_function_recycler: Option<JitFunctionRecycler>,
     timestamp_converter: TimestampConverter,
     profile_creation_props: Arc<ProfileCreationProps>,
+    /// The time of the first sample at which the task was found suspended,
+    /// while it stays suspended.
+    suspended_since: Option<Timestamp>,
+    /// The counter for the task's virtual size, and the last value which was
+    /// added to it.
+    address_space: Option<(CounterHandle, u64)>,
+    open_fds: Option<OpenHandlesCounter>,
+    next_open_fds_poll: Instant,
 }
 
 impl TaskProfiler {
@@ -132,6 +149,7 @@ impl TaskProfiler {
             task,
             pid,
             path_receiver,
+            restart,
         } = task_init;
         let start_time = timestamp_converter.convert_time(start_time_mono);
 
@@ -292,6 +310,7 @@ impl TaskProfiler {
                 profile_creation_props.unlink_aux_files,
                 profile_creation_props.should_emit_jit_markers,
             ),
+            perf_map_processor: PerfMapProcessor::new(pid, None),
             marker_file_paths: Vec::new(),
             lib_mapping_ops: Default::default(),
             unresolved_samples: Default::default(),
@@ -299,10 +318,22 @@ impl TaskProfiler {
             jit_function_recycler,
             timestamp_converter,
             profile_creation_props,
+            suspended_since: None,
+            address_space: None,
+            open_fds: None,
+            next_open_fds_poll: Instant::now(),
         };
 
         task_profiler.process_lib_modifications(start_time_mono, initial_lib_mods, profile);
 
+        if let Some(restart) = restart {
+            profile.add_marker(
+                main_thread_handle,
+                MarkerTiming::Instant(start_time),
+                restart,
+            );
+        }
+
         Ok(task_profiler)
     }
 
@@ -314,6 +345,9 @@ impl TaskProfiler {
         profile: &mut Profile,
         stack_scratch_buffer: &mut Vec<FrameAddress>,
         unresolved_stacks: &mut UnresolvedStacks,
+        kernel_sampler: Option<&KernelSampler>,
+        thread_rates: &ThreadRates,
+        tick: u64,
     ) -> Result<bool, SamplingError> {
         let result = self.sample_impl(
             now,
@@ -322,6 +356,9 @@ impl TaskProfiler {
             profile,
             stack_scratch_buffer,
             unresolved_stacks,
+            kernel_sampler,
+            thread_rates,
+            tick,
         );
         match result {
             Ok(()) => Ok(true),
@@ -352,7 +389,15 @@ impl TaskProfiler {
         profile: &mut Profile,
         stack_scratch_buffer: &mut Vec<FrameAddress>,
         unresolved_stacks: &mut UnresolvedStacks,
+        kernel_sampler: Option<&KernelSampler>,
+        thread_rates: &ThreadRates,
+        tick: u64,
     ) -> Result<(), SamplingError> {
+        let basic_info = get_task_basic_info(self.task);
+        self.check_suspension(now, basic_info.as_ref(), profile);
+        self.check_address_space(now, basic_info.as_ref(), profile);
+        self.check_open_fds(now, profile);
+
         // First, check for any newly-loaded libraries.
         if let Ok(changes) = self.lib_info_manager.check_for_changes() {
             self.process_lib_modifications(now_mono, changes, profile);
@@ -412,6 +457,26 @@ impl TaskProfiler {
             // Grab a sample from the thread.
             let stackwalker = StackwalkerRef::new(&self.unwinder, unwinder_cache);
             thread.check_thread_name(profile, self.thread_recycler.as_mut());
+            // Threads with a lower rate than the sampler's tick rate are only
+            // sampled on some ticks, and their samples are weighted accordingly.
+            let ticks_per_sample = thread_rates.ticks_per_sample(thread.name());
+            if tick % u64::from(ticks_per_sample) != 0 {
+                now_live_threads.insert(thread_act);
+                continue;
+            }
+            let state_label_frame = if self.profile_creation_props.thread_state_labels {
+                thread.run_state().map(|state| {
+                    let label = profile.handle_for_string(&label_frame_name(&[("state", state)]));
+                    profile.handle_for_frame_with_label(
+                        thread.profile_thread,
+                        label,
+                        CategoryHandle::OTHER,
+                        FrameFlags::empty(),
+                    )
+                })
+            } else {
+                None
+            };
             let still_alive = thread.sample(
                 stackwalker,
                 now,
@@ -420,6 +485,9 @@ impl TaskProfiler {
                 unresolved_stacks,
                 &mut self.unresolved_samples,
                 self.profile_creation_props.fold_recursive_prefix,
+                state_label_frame,
+                kernel_sampler,
+                ticks_per_sample as i32,
             )?;
             if still_alive {
                 now_live_threads.insert(thread_act);
@@ -601,8 +669,11 @@ impl TaskProfiler {
         }
     }
 
-    pub fn check_jitdump(
+    /// Reads the new records of the jitdump files and the new entries of the
+    /// perf map file.
+    pub fn check_jit_files(
         &mut self,
+        now_mono: u64,
         profile: &mut Profile,
         jit_category_manager: &mut JitCategoryManager,
     ) {
@@ -612,6 +683,80 @@ impl TaskProfiler {
             self.jit_function_recycler.as_mut(),
             &self.timestamp_converter,
         );
+        self.perf_map_processor.check_for_updates(
+            now_mono,
+            profile,
+            jit_category_manager,
+            self.jit_function_recycler.as_mut(),
+        );
+    }
+
+    /// Marks the times in which the task was suspended, e.g. with SIGSTOP or
+    /// by a debugger, with a span on its main thread.
+    fn check_suspension(
+        &mut self,
+        now: Timestamp,
+        basic_info: Option<&mach_task_basic_info>,
+        profile: &mut Profile,
+    ) {
+        let is_suspended = basic_info.is_some_and(|info| info.suspend_count > 0);
+        match (is_suspended, self.suspended_since) {
+            (true, None) => self.suspended_since = Some(now),
+            (false, Some(start)) => {
+                self.suspended_since = None;
+                add_suspend_span(
+                    profile,
+                    self.main_thread_handle,
+                    start,
+                    now,
+                    SuspendKind::ProcessSuspended,
+                    "Task suspend count",
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Tracks the task's virtual size in an "Address space" counter. Unlike on
+    /// Windows and Linux, there are no events for the individual mappings, so
+    /// this only sees the size at each sample.
+    fn check_address_space(
+        &mut self,
+        now: Timestamp,
+        basic_info: Option<&mach_task_basic_info>,
+        profile: &mut Profile,
+    ) {
+        let Some(virtual_size) = basic_info.map(|info| info.virtual_size) else {
+            return;
+        };
+        let (counter, prev_size) = self.address_space.get_or_insert_with(|| {
+            let counter = profile.add_counter(
+                self.profile_process,
+                "vm",
+                "Memory",
+                "Virtual size of the task",
+            );
+            (counter, 0)
+        });
+        if virtual_size != *prev_size {
+            let delta = virtual_size as f64 - *prev_size as f64;
+            profile.add_counter_sample(*counter, now, delta, 1);
+            *prev_size = virtual_size;
+        }
+    }
+
+    fn check_open_fds(&mut self, now: Timestamp, profile: &mut Profile) {
+        if Instant::now() < self.next_open_fds_poll {
+            return;
+        }
+        self.next_open_fds_poll = Instant::now() + OPEN_HANDLES_POLL_INTERVAL;
+        let Some(count) = get_open_fd_count(self.pid) else {
+            return;
+        };
+        let profile_process = self.profile_process;
+        self.open_fds
+            .get_or_insert_with(|| OpenHandlesCounter::for_fds(profile, profile_process))
+            .add_sample(profile, now, count);
     }
 
     /// Called when a process has exited, before finish(). Not called if the process
@@ -637,17 +782,21 @@ impl TaskProfiler {
         jit_category_manager: &mut JitCategoryManager,
         profile: &mut Profile,
     ) -> (ProcessSampleData, Option<(String, ProcessRecyclingData)>) {
-        let perf_map_mappings = if !self.unresolved_samples.is_empty() {
-            try_load_perf_map(self.pid, profile, jit_category_manager, None)
-        } else {
-            None
-        };
-        let jitdump_lib_ops = self.jitdump_manager.finish(
+        let mut jitdump_lib_ops = self.jitdump_manager.finish(
             jit_category_manager,
             profile,
             self.jit_function_recycler.as_mut(),
             &self.timestamp_converter,
         );
+        if !self.unresolved_samples.is_empty() {
+            if let Some(perf_map_ops) = self.perf_map_processor.finish(
+                profile,
+                jit_category_manager,
+                self.jit_function_recycler.as_mut(),
+            ) {
+                jitdump_lib_ops.push(perf_map_ops);
+            }
+        }
         let mut marker_spans = Vec::new();
         for (thread_handle, marker_file_path) in self.marker_file_paths {
             if let Ok(marker_spans_from_this_file) =
@@ -667,10 +816,10 @@ impl TaskProfiler {
             }
         }
         let process_sample_data = ProcessSampleData::new(
+            self.profile_process,
             self.unresolved_samples,
             self.lib_mapping_ops,
             jitdump_lib_ops,
-            perf_map_mappings,
             marker_spans,
         );
 
@@ -765,6 +914,75 @@ fn get_thread_list(
     Ok(thread_acts)
 }
 
+// From /usr/include/mach/task_info.h, which packs its structs to 4 bytes.
+#[allow(non_camel_case_types, dead_code)]
+#[repr(C, packed(4))]
+#[derive(Default)]
+struct mach_task_basic_info {
+    virtual_size: u64,
+    resident_size: u64,
+    resident_size_max: u64,
+    user_time: [i32; 2],
+    system_time: [i32; 2],
+    policy: i32,
+    suspend_count: i32,
+}
+
+const MACH_TASK_BASIC_INFO: u32 = 20;
+const MACH_TASK_BASIC_INFO_COUNT: mach_msg_type_number_t =
+    (mem::size_of::<mach_task_basic_info>() / mem::size_of::<i32>()) as mach_msg_type_number_t;
+
+/// Returns the task's virtual size and how many times it has been suspended
+/// without being resumed, among other things.
+fn get_task_basic_info(task: mach_port_t) -> Option<mach_task_basic_info> {
+    let mut info = mach_task_basic_info::default();
+    let mut count = MACH_TASK_BASIC_INFO_COUNT;
+    unsafe {
+        task_info(
+            task,
+            MACH_TASK_BASIC_INFO,
+            &mut info as *mut mach_task_basic_info as task_info_t,
+            &mut count,
+        )
+    }
+    .into_result()
+    .ok()?;
+    Some(info)
+}
+
+/// Returns the number of open file descriptors of the process.
+fn get_open_fd_count(pid: u32) -> Option<u64> {
+    let fdinfo_size = mem::size_of::<libc::proc_fdinfo>() as i32;
+    // Without a buffer, proc_pidinfo returns an upper bound of the size which
+    // the list of fds needs.
+    let buffer_size = unsafe {
+        libc::proc_pidinfo(
+            pid as i32,
+            libc::PROC_PIDLISTFDS,
+            0,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if buffer_size <= 0 {
+        return None;
+    }
+    let mut fds: Vec<libc::proc_fdinfo> = Vec::with_capacity((buffer_size / fdinfo_size) as usize);
+    let filled_size = unsafe {
+        libc::proc_pidinfo(
+            pid as i32,
+            libc::PROC_PIDLISTFDS,
+            0,
+            fds.as_mut_ptr() as *mut libc::c_void,
+            (fds.capacity() as i32) * fdinfo_size,
+        )
+    };
+    if filled_size <= 0 {
+        return None;
+    }
+    Some((filled_size / fdinfo_size) as u64)
+}
+
 fn compute_debug_id_from_text_section(
     text_segment: &VmSubData,
     base_svma: u64,
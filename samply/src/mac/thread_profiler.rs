@@ -1,13 +1,16 @@
 use std::mem;
 
 use framehop::FrameAddress;
-use fxprof_processed_profile::{CpuDelta, Profile, StringHandle, ThreadHandle, Timestamp};
+use fxprof_processed_profile::{
+    CpuDelta, FrameHandle, Profile, StringHandle, ThreadHandle, Timestamp,
+};
 use mach2::mach_types::thread_act_t;
 use mach2::port::mach_port_t;
 use time::get_monotonic_timestamp;
 
 use super::error::SamplingError;
 use super::kernel_error::{self, IntoResult, KernelError};
+use super::kernel_sampler::KernelSampler;
 use super::proc_maps::{get_backtrace, ForeignMemory, StackwalkerRef};
 use super::thread_act::thread_info; // https://github.com/JohnTitor/mach2/issues/31
 use super::thread_info::{
@@ -29,6 +32,7 @@ pub struct ThreadProfiler {
     tick_count: usize,
     stack_memory: ForeignMemory,
     previous_sample_cpu_time_us: u64,
+    previous_sample_mono: u64,
     ignored_errors: Vec<SamplingError>,
 }
 
@@ -50,10 +54,15 @@ impl ThreadProfiler {
             tick_count: 0,
             stack_memory: ForeignMemory::new(task),
             previous_sample_cpu_time_us: 0,
+            previous_sample_mono: 0,
             ignored_errors: Vec::new(),
         }
     }
 
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     /// Called before every call to `sample`.
     pub fn check_thread_name(
         &mut self,
@@ -85,6 +94,9 @@ impl ThreadProfiler {
         unresolved_stacks: &mut UnresolvedStacks,
         unresolved_samples: &mut UnresolvedSamples,
         fold_recursive_prefix: bool,
+        extra_label_frame: Option<FrameHandle>,
+        kernel_sampler: Option<&KernelSampler>,
+        weight: i32,
     ) -> Result<bool, SamplingError> {
         let result = self.sample_impl(
             stackwalker,
@@ -94,6 +106,9 @@ impl ThreadProfiler {
             unresolved_stacks,
             unresolved_samples,
             fold_recursive_prefix,
+            extra_label_frame,
+            kernel_sampler,
+            weight,
         );
         match result {
             Ok(()) => Ok(true),
@@ -127,6 +142,9 @@ impl ThreadProfiler {
         unresolved_stacks: &mut UnresolvedStacks,
         unresolved_samples: &mut UnresolvedSamples,
         fold_recursive_prefix: bool,
+        extra_label_frame: Option<FrameHandle>,
+        kernel_sampler: Option<&KernelSampler>,
+        weight: i32,
     ) -> Result<(), SamplingError> {
         self.tick_count += 1;
 
@@ -157,15 +175,31 @@ impl ThreadProfiler {
                     StackFrame::ReturnAddress((*address).into(), StackMode::User)
                 }
             });
-            let stack = unresolved_stacks.convert(frames);
+            let mut stack = unresolved_stacks.convert(frames);
+            // If the thread was in the kernel when kperf last sampled it, put
+            // the kernel stack on top of the user stack.
+            let kernel_frames = kernel_sampler
+                .and_then(|k| k.kernel_stack_since(self.tid, self.previous_sample_mono));
+            if let Some(kernel_frames) = kernel_frames {
+                let kernel_frames =
+                    kernel_frames
+                        .iter()
+                        .enumerate()
+                        .rev()
+                        .map(|(i, address)| match i {
+                            0 => StackFrame::InstructionPointer(*address, StackMode::Kernel),
+                            _ => StackFrame::ReturnAddress(*address, StackMode::Kernel),
+                        });
+                stack = unresolved_stacks.convert_with_prefix(stack, kernel_frames);
+            }
             unresolved_samples.add_sample(
                 self.profile_thread,
                 now,
                 sample_time_mono,
                 stack,
                 cpu_delta,
-                1,
-                None,
+                weight,
+                extra_label_frame,
             );
         } else {
             // No CPU time elapsed since just before the last time we grabbed a stack.
@@ -187,16 +221,42 @@ impl ThreadProfiler {
                 self.profile_thread,
                 now,
                 now_mono,
-                1,
-                None,
+                weight,
+                extra_label_frame,
             );
         }
 
         self.previous_sample_cpu_time_us = cpu_time_us;
+        self.previous_sample_mono = now_mono;
 
         Ok(())
     }
 
+    /// Returns the thread's run state, e.g. "Running" or "Waiting".
+    pub fn run_state(&self) -> Option<&'static str> {
+        let mut basic_info_data: thread_basic_info_data_t = unsafe { mem::zeroed() };
+        let mut count = THREAD_BASIC_INFO_COUNT;
+        unsafe {
+            thread_info(
+                self.thread_act,
+                THREAD_BASIC_INFO,
+                &mut basic_info_data as *mut _ as thread_info_t,
+                &mut count,
+            )
+        }
+        .into_result()
+        .ok()?;
+        // TH_STATE_* from mach/thread_info.h
+        match basic_info_data.run_state {
+            1 => Some("Running"),
+            2 => Some("Stopped"),
+            3 => Some("Waiting"),
+            4 => Some("Uninterruptible"),
+            5 => Some("Halted"),
+            _ => None,
+        }
+    }
+
     pub fn notify_dead(&mut self, end_time: Timestamp, profile: &mut Profile) {
         profile.set_thread_end_time(self.profile_thread, end_time);
     }
@@ -4,8 +4,12 @@ use mach2::mach_time;
 
 static NANOS_PER_TICK: OnceLock<mach_time::mach_timebase_info> = OnceLock::new();
 
-pub fn get_monotonic_timestamp() -> u64 {
-    let nanos_per_tick = NANOS_PER_TICK.get_or_init(|| unsafe {
+extern "C" {
+    fn mach_continuous_time() -> u64;
+}
+
+fn nanos_per_tick() -> &'static mach_time::mach_timebase_info {
+    NANOS_PER_TICK.get_or_init(|| unsafe {
         let mut info = mach_time::mach_timebase_info::default();
         let errno = mach_time::mach_timebase_info(&mut info as *mut _);
         if errno != 0 || info.denom == 0 {
@@ -13,9 +17,24 @@ pub fn get_monotonic_timestamp() -> u64 {
             info.denom = 1;
         };
         info
-    });
+    })
+}
 
-    let time = unsafe { mach_time::mach_absolute_time() };
+/// Converts a `mach_absolute_time` value, such as a kdebug event timestamp, to
+/// nanoseconds.
+pub fn mach_ticks_to_nanos(ticks: u64) -> u64 {
+    let nanos_per_tick = nanos_per_tick();
+    ticks * nanos_per_tick.numer as u64 / nanos_per_tick.denom as u64
+}
+
+/// Returns the time in nanoseconds, without the time in which the machine slept.
+pub fn get_monotonic_timestamp() -> u64 {
+    mach_ticks_to_nanos(unsafe { mach_time::mach_absolute_time() })
+}
 
+/// Returns the time in nanoseconds, including the time in which the machine slept.
+pub fn get_continuous_timestamp() -> u64 {
+    let nanos_per_tick = nanos_per_tick();
+    let time = unsafe { mach_continuous_time() };
     time * nanos_per_tick.numer as u64 / nanos_per_tick.denom as u64
 }
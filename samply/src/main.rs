@@ -7,25 +7,44 @@ mod linux;
 #[cfg(target_os = "windows")]
 mod windows;
 
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+mod bsd;
+
 mod cli;
 mod cli_utils;
+#[cfg(any(
+    target_os = "android",
+    target_os = "macos",
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
+mod daemon;
+mod export;
 mod import;
 mod linux_shared;
 mod name;
 mod profile_json_preparse;
+mod report;
 mod server;
 mod shared;
 mod symbols;
+mod symtool;
 
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read, Write};
 use std::path::Path;
 
 use fxprof_processed_profile::Profile;
+use linux_shared::{parse_guest_kernel_symbols, GuestKernelLib};
 use shared::ctrl_c::CtrlC;
+use shared::encryption;
 use wholesym::LibraryInfo;
 
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+use bsd::profiler;
 #[cfg(any(target_os = "android", target_os = "linux"))]
 use linux::profiler;
 #[cfg(target_os = "macos")]
@@ -33,49 +52,377 @@ use mac::profiler;
 #[cfg(target_os = "windows")]
 use windows::profiler;
 
-use profile_json_preparse::parse_libinfo_map_from_profile_file;
-use server::{start_server, RunningServerInfo, ServerProps};
-use shared::prop_types::{ImportProps, SymbolProps};
-use shared::save_profile::save_profile_to_file;
+use profile_json_preparse::{
+    parse_libinfo_map_from_profile_data, parse_libinfo_map_from_profile_file,
+};
+use server::{start_server, RunningServerInfo, ServedProfile, ServerProps};
+use shared::metrics_scraper::{warn_about_missing_metrics, MetricsScraper};
+use shared::prop_types::{ImportProps, ProfileCreationProps, SymbolProps};
+use shared::save_profile::{
+    load_profile_json, read_profile_json, save_profile_to_file, save_profile_with_post_processing,
+    PostProcessing,
+};
+use shared::self_profile;
 use symbols::create_symbol_manager_and_quota_manager;
 
 fn main() {
     env_logger::init();
 
+    // Answer dynamic completion requests from the shell, if this is one.
+    clap_complete::CompleteEnv::with_factory(<cli::Opt as clap::CommandFactory>::command)
+        .complete();
+
     use clap::Parser;
     let opt = cli::Opt::parse();
     match opt.action {
         cli::Action::Load(load_args) => do_load_action(load_args),
         cli::Action::Import(import_args) => do_import_action(import_args),
+        cli::Action::Report(report_args) => do_report_action(report_args),
+        cli::Action::Push(push_args) => do_push_action(push_args),
+        cli::Action::Assert(assert_args) => do_assert_action(assert_args),
+        cli::Action::Summarize(summarize_args) => do_summarize_action(summarize_args),
+        cli::Action::Grep(grep_args) => do_grep_action(grep_args),
+        cli::Action::Export(export_args) => do_export_action(export_args),
+        cli::Action::Diff(diff_args) => do_diff_action(diff_args),
+        cli::Action::Validate(validate_args) => do_validate_action(validate_args),
+        cli::Action::AddLogs(add_logs_args) => do_add_logs_action(add_logs_args),
 
         #[cfg(any(
             target_os = "android",
             target_os = "macos",
             target_os = "linux",
-            target_os = "windows"
+            target_os = "windows",
+            target_os = "freebsd",
+            target_os = "openbsd"
         ))]
         cli::Action::Record(record_args) => do_record_action(record_args),
 
+        #[cfg(any(
+            target_os = "android",
+            target_os = "macos",
+            target_os = "linux",
+            target_os = "windows",
+            target_os = "freebsd",
+            target_os = "openbsd"
+        ))]
+        cli::Action::Attach(attach_args) => do_attach_action(attach_args),
+
+        #[cfg(any(
+            target_os = "android",
+            target_os = "macos",
+            target_os = "linux",
+            target_os = "windows",
+            target_os = "freebsd",
+            target_os = "openbsd"
+        ))]
+        cli::Action::Daemon(daemon_args) => do_daemon_action(daemon_args),
+
         #[cfg(target_os = "windows")]
         cli::Action::RunElevatedHelper(args) => {
             windows::run_elevated_helper(&args.ipc_directory, args.output_path)
         }
 
         #[cfg(target_os = "macos")]
-        cli::Action::Setup(cli::SetupArgs { yes }) => mac::codesign_setup::codesign_setup(yes),
+        cli::Action::Snapshot(snapshot_args) => do_snapshot_action(snapshot_args),
+
+        #[cfg(target_os = "macos")]
+        cli::Action::Setup(cli::SetupArgs { yes, allow_attach }) => match allow_attach {
+            Some(target) => mac::codesign_setup::allow_attach_setup(&target, yes),
+            None => mac::codesign_setup::codesign_setup(yes),
+        },
+
+        cli::Action::Symtool(symtool_args) => do_symtool_action(symtool_args),
+
+        cli::Action::Completions(completions_args) => do_completions_action(completions_args),
+    }
+}
+
+fn do_symtool_action(symtool_args: cli::SymtoolArgs) {
+    match symtool_args.action {
+        cli::SymtoolAction::ExportBreakpad(args) => {
+            match symtool::export_breakpad(
+                &args.binary,
+                &args.output_dir,
+                args.symbol_args.symbol_props(),
+            ) {
+                Ok(path) => eprintln!("Wrote {path:?}."),
+                Err(err) => {
+                    eprintln!("Could not export symbols for {:?}: {}", args.binary, err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+fn do_completions_action(completions_args: cli::CompletionsArgs) {
+    use clap::CommandFactory;
+
+    let shell = completions_args.shell;
+    if completions_args.dynamic {
+        // The registration script calls back into samply with COMPLETE=<shell> set,
+        // which is handled by the CompleteEnv at the start of main().
+        let shell_name = shell.to_string();
+        let shells = clap_complete::env::Shells::builtins();
+        let Some(completer) = shells.completer(&shell_name) else {
+            eprintln!("Error: Dynamic completions are not supported for {shell_name}.");
+            std::process::exit(1);
+        };
+        let samply_path = std::env::current_exe()
+            .ok()
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "samply".to_string());
+        if let Err(err) = completer.write_registration(
+            "COMPLETE",
+            "samply",
+            "samply",
+            &samply_path,
+            &mut std::io::stdout(),
+        ) {
+            eprintln!("Error writing completion script: {err}");
+            std::process::exit(1);
+        }
+        return;
     }
+
+    let mut command = cli::Opt::command();
+    clap_complete::generate(shell, &mut command, "samply", &mut std::io::stdout());
 }
 
 fn do_load_action(load_args: cli::LoadArgs) {
-    run_server_serving_profile(
-        &load_args.file,
-        load_args.server_props(),
-        load_args.symbol_props(),
+    let profile = match profile_to_load(&load_args.file, |data| {
+        encryption::decrypt(data, &load_args.identity)
+    }) {
+        Ok(profile) => profile,
+        Err(err) => {
+            eprintln!(
+                "Could not read the profile from {:?}: {err}",
+                load_args.file
+            );
+            std::process::exit(1);
+        }
+    };
+    run_server_serving(profile, load_args.server_props(), load_args.symbol_props());
+}
+
+/// The profile to serve for `samply load`. A profile from stdin and a
+/// decrypted profile are kept in memory, so that the decrypted profile is
+/// never written to disk.
+fn profile_to_load(
+    path: &Path,
+    decrypt: impl FnOnce(&[u8]) -> std::io::Result<Vec<u8>>,
+) -> std::io::Result<ServedProfile> {
+    let is_stdin = path.as_os_str() == "-";
+    if !is_stdin && !is_encrypted_file(path) {
+        return Ok(ServedProfile::File(path.to_owned()));
+    }
+    let data = if is_stdin {
+        read_stdin()?
+    } else {
+        std::fs::read(path)?
+    };
+    let data = match encryption::is_encrypted(&data) {
+        true => decrypt(&data)?,
+        false => data,
+    };
+    Ok(ServedProfile::InMemory(data.into()))
+}
+
+fn is_encrypted_file(path: &Path) -> bool {
+    let mut header = [0; 64];
+    let len = File::open(path)
+        .and_then(|mut file| file.read(&mut header))
+        .unwrap_or(0);
+    encryption::is_encrypted(&header[..len])
+}
+
+fn read_stdin() -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn save_stdin_to_temp_file() -> std::io::Result<tempfile::NamedTempFile> {
+    let data = read_stdin()?;
+    let is_gz = data.starts_with(&[0x1f, 0x8b]);
+    let mut temp_file = tempfile::Builder::new()
+        .prefix("samply-stdin-")
+        .suffix(if is_gz { ".json.gz" } else { ".json" })
+        .tempfile()?;
+    temp_file.write_all(&data)?;
+    temp_file.flush()?;
+    Ok(temp_file)
+}
+
+/// Tells the user where the encrypted profile went, and how to open it.
+fn report_encrypted_output(output_path: &Path, post_processing: &PostProcessing) {
+    if post_processing.encryption_recipients.is_empty() || output_path.as_os_str() == "-" {
+        return;
+    }
+    let path = encryption::encrypted_output_path(output_path);
+    eprintln!(
+        "Saved the encrypted profile to {path:?}. Open it with: samply load {path:?} --identity <FILE>"
+    );
+}
+
+fn do_report_action(report_args: cli::ReportArgs) {
+    if let Err(err) = report::run_report(
+        &report_args.file,
+        report_args.report_props(),
+        report_args.symbol_props(),
+    ) {
+        eprintln!(
+            "Could not create report for {:?}: {}",
+            report_args.file, err
+        );
+        std::process::exit(1);
+    }
+}
+
+fn do_push_action(push_args: cli::PushArgs) {
+    let Some(push_props) = push_args.push_props() else {
+        eprintln!("Error: Specify where to upload the profile, with --pyroscope or --otlp.");
+        std::process::exit(1);
+    };
+    if let Err(err) = export::push_profile(&push_args.file, &push_props, push_args.symbol_props()) {
+        eprintln!("Could not upload {:?}: {}", push_args.file, err);
+        std::process::exit(1);
+    }
+}
+
+fn do_assert_action(assert_args: cli::AssertArgs) {
+    match report::run_assert(
+        &assert_args.file,
+        assert_args.assert_props(),
+        assert_args.symbol_props(),
+    ) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("Could not check {:?}: {}", assert_args.file, err);
+            // Use a different exit code than for failed thresholds.
+            std::process::exit(2);
+        }
+    }
+}
+
+fn do_summarize_action(summarize_args: cli::SummarizeArgs) {
+    if let Err(err) = report::run_summarize(
+        &summarize_args.file,
+        summarize_args.summarize_props(),
+        summarize_args.symbol_props(),
+    ) {
+        eprintln!("Could not summarize {:?}: {}", summarize_args.file, err);
+        std::process::exit(1);
+    }
+}
+
+fn do_grep_action(grep_args: cli::GrepArgs) {
+    match report::run_grep(
+        &grep_args.file,
+        grep_args.grep_props(),
+        grep_args.symbol_props(),
+    ) {
+        Ok(true) => {}
+        // Like grep, exit with 1 if nothing matched.
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("Could not search {:?}: {}", grep_args.file, err);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn do_export_action(export_args: cli::ExportArgs) {
+    if let Err(err) = report::run_export(&export_args.file, export_args.export_props()) {
+        eprintln!("Could not export {:?}: {}", export_args.file, err);
+        std::process::exit(1);
+    }
+}
+
+fn do_diff_action(diff_args: cli::DiffArgs) {
+    if let Err(err) = report::run_diff(
+        &diff_args.before,
+        &diff_args.after,
+        diff_args.diff_props(),
+        diff_args.symbol_props(),
+    ) {
+        eprintln!(
+            "Could not compare {:?} and {:?}: {}",
+            diff_args.before, diff_args.after, err
+        );
+        std::process::exit(1);
+    }
+}
+
+fn do_validate_action(validate_args: cli::ValidateArgs) {
+    let profile = match read_profile_json(&validate_args.file) {
+        Ok(profile) => profile,
+        Err(err) => {
+            eprintln!("Could not read {:?}: {}", validate_args.file, err);
+            std::process::exit(2);
+        }
+    };
+    let errors = shared::profile_validation::validate_profile(&profile);
+    if errors.is_empty() {
+        eprintln!("{:?} is valid.", validate_args.file);
+        return;
+    }
+    for error in &errors {
+        println!("{error}");
+    }
+    eprintln!(
+        "Found {} problems in {:?}.",
+        errors.len(),
+        validate_args.file
     );
+    std::process::exit(1);
+}
+
+fn do_add_logs_action(add_logs_args: cli::AddLogsArgs) {
+    let profile_path = &add_logs_args.file;
+    let mut profile = match load_profile_json(profile_path) {
+        Ok(profile) => profile,
+        Err(err) => {
+            eprintln!("Could not read profile {:?}: {}", profile_path, err);
+            std::process::exit(1);
+        }
+    };
+    let (mut pids, start, end) = import::os_log::profile_pids_and_time_range(&profile);
+    if !add_logs_args.pid.is_empty() {
+        pids = add_logs_args.pid.clone();
+    }
+    let entries = match import::os_log::read_os_log(&pids, start, end) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Could not read the system log: {err}");
+            std::process::exit(1);
+        }
+    };
+    let added = import::os_log::merge_into_profile(&mut profile, &entries);
+    let output_path = add_logs_args.output.as_deref().unwrap_or(profile_path);
+    eprintln!("Added {added} log entries as markers to {:?}.", output_path);
+    if let Err(err) = save_profile_to_file(&profile, output_path) {
+        eprintln!("Could not write {:?}: {}", output_path, err);
+        std::process::exit(1);
+    }
 }
 
 fn do_import_action(import_args: cli::ImportArgs) {
-    let input_path = &import_args.file;
+    // Keep the temporary file for stdin input alive until the import is done.
+    let stdin_file;
+    let input_path = if import_args.file.as_os_str() == "-" {
+        stdin_file = match save_stdin_to_temp_file() {
+            Ok(temp_file) => temp_file,
+            Err(err) => {
+                eprintln!("Could not read the input from stdin: {err}");
+                std::process::exit(1);
+            }
+        };
+        stdin_file.path()
+    } else {
+        &import_args.file
+    };
     let input_file = match File::open(input_path) {
         Ok(file) => file,
         Err(err) => {
@@ -84,84 +431,364 @@ fn do_import_action(import_args: cli::ImportArgs) {
         }
     };
 
+    if let Some(profile_path) = &import_args.merge_into {
+        merge_log_into_profile(&input_file, profile_path, &import_args.output);
+        if let Some(server_props) = import_args.server_props() {
+            run_server_serving_profile(
+                &import_args.output,
+                server_props,
+                import_args.symbol_props(),
+            );
+        }
+        return;
+    }
+
     let import_props = import_args.import_props();
     let unstable_presymbolicate = import_props.profile_creation_props.unstable_presymbolicate;
-    let profile = convert_file_to_profile(&input_file, input_path, import_props);
-
-    save_profile_to_file(&profile, &import_args.output).expect("Couldn't write JSON");
+    if import_props.profile_creation_props.self_profile {
+        self_profile::enable();
+    }
+    let post_processing = PostProcessing::from_props(&import_props.profile_creation_props);
+    let profile = {
+        let _phase = self_profile::phase("Import", Some(&input_path.to_string_lossy()));
+        convert_file_to_profile(&input_file, input_path, import_props)
+    };
 
-    if unstable_presymbolicate {
-        crate::shared::symbol_precog::presymbolicate(
-            &profile,
-            &import_args.output.with_extension("syms.json"),
-            import_args.symbol_props(),
-        );
+    let saved = {
+        let _phase = self_profile::phase("Save", None);
+        save_profile_with_post_processing(&profile, &import_args.output, &post_processing)
+    };
+    if let Err(err) = saved {
+        eprintln!("Could not save the profile: {err}");
+        std::process::exit(1);
     }
+    report_encrypted_output(&import_args.output, &post_processing);
 
-    // Drop the profile so that it doesn't take up memory while the server is running.
-    drop(profile);
+    let server_props = import_args.server_props();
+    let presymbolication = if unstable_presymbolicate
+        && import_args.output.as_os_str() != "-"
+        && post_processing.encryption_recipients.is_empty()
+    {
+        start_presymbolication(
+            profile,
+            &import_args.output,
+            import_args.symbol_props(),
+            server_props.is_some(),
+        )
+    } else {
+        // Drop the profile so that it doesn't take up memory while the server is running.
+        drop(profile);
+        self_profile::save(&import_args.output);
+        None
+    };
 
-    if let Some(server_props) = import_args.server_props() {
+    if let Some(server_props) = server_props {
         run_server_serving_profile(
             &import_args.output,
             server_props,
             import_args.symbol_props(),
         );
     }
+    finish_presymbolication(presymbolication);
+}
+
+/// Writes the .syms.json sidecar for --unstable-presymbolicate. If the profile
+/// is about to be served, this happens on a background thread, so that the
+/// browser can show the profile right away instead of waiting for every
+/// library to be symbolicated; until then, the server looks up symbols when
+/// the profiler asks for them. The self-profile, if any, is saved afterwards,
+/// so that it includes the symbolication.
+fn start_presymbolication(
+    profile: Profile,
+    output: &Path,
+    symbol_props: SymbolProps,
+    in_background: bool,
+) -> Option<std::thread::JoinHandle<()>> {
+    let output = output.to_owned();
+    let precog_output = output.with_extension("syms.json");
+    if !in_background {
+        crate::shared::symbol_precog::presymbolicate(&profile, &precog_output, symbol_props);
+        self_profile::save(&output);
+        return None;
+    }
+    Some(std::thread::spawn(move || {
+        crate::shared::symbol_precog::presymbolicate(&profile, &precog_output, symbol_props);
+        self_profile::save(&output);
+    }))
+}
+
+fn finish_presymbolication(presymbolication: Option<std::thread::JoinHandle<()>>) {
+    let Some(presymbolication) = presymbolication else {
+        return;
+    };
+    if !presymbolication.is_finished() {
+        eprintln!("Waiting for the presymbolicated symbols to be written...");
+    }
+    presymbolication
+        .join()
+        .expect("couldn't join presymbolication thread");
 }
 
 #[cfg(any(
     target_os = "android",
     target_os = "macos",
     target_os = "linux",
-    target_os = "windows"
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "openbsd"
 ))]
 fn do_record_action(record_args: cli::RecordArgs) {
-    let recording_props = record_args.recording_props();
-    let recording_mode = record_args.recording_mode();
-    let profile_creation_props = record_args.profile_creation_props();
-    let unstable_presymbolicate = profile_creation_props.unstable_presymbolicate;
+    #[cfg(target_os = "windows")]
+    if record_args.list_presets {
+        windows::print_kernel_presets();
+        return;
+    }
 
-    let (profile, exit_status) =
-        match profiler::run(recording_mode, recording_props, profile_creation_props) {
+    #[cfg(target_os = "macos")]
+    if record_args.use_sudo_helper && !mac::sudo_helper::is_root() {
+        // Record in a samply which runs as root, and serve its profile from here.
+        let exit_status = match mac::sudo_helper::rerun_as_root() {
             Ok(exit_status) => exit_status,
             Err(err) => {
-                eprintln!("Encountered an error during profiling: {err:?}");
+                eprintln!("Could not run samply via sudo: {err}");
                 std::process::exit(1);
             }
         };
+        if !exit_status.success() {
+            std::process::exit(exit_status.code().unwrap_or(1));
+        }
+        if let Some(server_props) = record_args.server_props() {
+            run_server_serving_profile(
+                &record_args.output,
+                server_props,
+                record_args.symbol_props(),
+            );
+        }
+        std::process::exit(0);
+    }
 
-    save_profile_to_file(&profile, &record_args.output).expect("Couldn't write JSON");
+    record_save_and_serve(
+        record_args.recording_mode(),
+        record_args.recording_props(),
+        record_args.profile_creation_props(),
+        &record_args.output,
+        record_args.server_props(),
+        record_args.symbol_props(),
+        || {
+            // The sudo helper child doesn't serve the profile, so its sidecar
+            // file has been written when the files are handed over.
+            #[cfg(target_os = "macos")]
+            if record_args.is_sudo_helper_child() {
+                mac::sudo_helper::chown_to_sudo_user(&[
+                    &record_args.output,
+                    &record_args.output.with_extension("syms.json"),
+                    &self_profile::self_profile_path(&record_args.output),
+                ]);
+            }
+        },
+    );
+}
 
-    if unstable_presymbolicate {
-        crate::shared::symbol_precog::presymbolicate(
-            &profile,
-            &record_args.output.with_extension("syms.json"),
-            record_args.symbol_props(),
-        );
+#[cfg(any(
+    target_os = "android",
+    target_os = "macos",
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
+fn do_attach_action(attach_args: cli::AttachArgs) {
+    record_save_and_serve(
+        attach_args.recording_mode(),
+        attach_args.recording_props(),
+        attach_args.profile_creation_props(),
+        &attach_args.output,
+        attach_args.server_props(),
+        attach_args.symbol_props(),
+        || {},
+    );
+}
+
+/// Records, saves the profile to `output`, and serves it if `server_props` is
+/// given. Exits with the exit code of the recorded command. `before_serving`
+/// is called once the profile and its symbols have been written.
+#[cfg(any(
+    target_os = "android",
+    target_os = "macos",
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
+fn record_save_and_serve(
+    recording_mode: shared::prop_types::RecordingMode,
+    recording_props: shared::prop_types::RecordingProps,
+    profile_creation_props: ProfileCreationProps,
+    output: &Path,
+    server_props: Option<ServerProps>,
+    symbol_props: SymbolProps,
+    before_serving: impl FnOnce(),
+) -> ! {
+    let unstable_presymbolicate = profile_creation_props.unstable_presymbolicate;
+    if profile_creation_props.self_profile {
+        self_profile::enable();
     }
+    let mut post_processing = PostProcessing::from_props(&profile_creation_props);
+    let metrics_sources = recording_props.metrics_sources.clone();
+    let metrics_scraper = MetricsScraper::start(&metrics_sources, recording_props.metrics_interval);
 
-    // Drop the profile so that it doesn't take up memory while the server is running.
-    drop(profile);
+    let recorded = {
+        let _phase = self_profile::phase("Record", None);
+        profiler::run(recording_mode, recording_props, profile_creation_props)
+    };
+    let (profile, exit_status) = match recorded {
+        Ok(exit_status) => exit_status,
+        Err(err) => {
+            eprintln!("Encountered an error during profiling: {err:?}");
+            std::process::exit(1);
+        }
+    };
+
+    post_processing.metrics = metrics_scraper.finish();
+    warn_about_missing_metrics(&metrics_sources, &post_processing.metrics);
+    let saved = {
+        let _phase = self_profile::phase("Save", None);
+        save_profile_with_post_processing(&profile, output, &post_processing)
+    };
+    if let Err(err) = saved {
+        eprintln!("Could not save the profile: {err}");
+        std::process::exit(1);
+    }
+    report_encrypted_output(output, &post_processing);
+
+    let presymbolication =
+        if unstable_presymbolicate && post_processing.encryption_recipients.is_empty() {
+            start_presymbolication(
+                profile,
+                output,
+                symbol_props.clone(),
+                server_props.is_some(),
+            )
+        } else {
+            // Drop the profile so that it doesn't take up memory while the server is running.
+            drop(profile);
+            self_profile::save(output);
+            None
+        };
+
+    before_serving();
 
     // then fire up the server for the profiler front end, if not save-only
-    if let Some(server_props) = record_args.server_props() {
-        run_server_serving_profile(
-            &record_args.output,
-            server_props,
-            record_args.symbol_props(),
-        );
+    if let Some(server_props) = server_props {
+        run_server_serving_profile(output, server_props, symbol_props);
     }
+    finish_presymbolication(presymbolication);
 
     std::process::exit(exit_status.code().unwrap_or(0));
 }
 
+#[cfg(any(
+    target_os = "android",
+    target_os = "macos",
+    target_os = "linux",
+    target_os = "windows",
+    target_os = "freebsd",
+    target_os = "openbsd"
+))]
+fn do_daemon_action(daemon_args: cli::DaemonArgs) {
+    if daemon_args.profile_creation_args.encrypt_output {
+        eprintln!("--encrypt-output is not supported by samply daemon.");
+        std::process::exit(1);
+    }
+    if daemon_args.profile_creation_args.self_profile {
+        eprintln!("--self-profile is not supported by samply daemon.");
+        std::process::exit(1);
+    }
+    let daemon_props = daemon_args.daemon_props();
+    let output_dir = daemon_props.output_dir.clone();
+    if let Err(err) = daemon::run_daemon(
+        daemon_props,
+        daemon_args.recording_mode(),
+        daemon_args.recording_props(),
+        daemon_args.profile_creation_props(),
+        daemon_args.symbol_props(),
+    ) {
+        eprintln!("Error while recording into {output_dir:?}: {err}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn do_snapshot_action(snapshot_args: cli::SnapshotArgs) {
+    let recording_mode = shared::prop_types::RecordingMode::Pid(snapshot_args.pid);
+    let profile_creation_props = snapshot_args.profile_creation_props();
+    let (profile, _exit_status) = match profiler::run(
+        recording_mode,
+        snapshot_args.recording_props(),
+        profile_creation_props,
+    ) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("Could not capture a snapshot: {err:?}");
+            std::process::exit(1);
+        }
+    };
+    save_profile_to_file(&profile, &snapshot_args.output).expect("Couldn't write JSON");
+    drop(profile);
+
+    if let Err(err) = report::run_snapshot_report(
+        &snapshot_args.output,
+        snapshot_args.addresses,
+        snapshot_args.symbol_props(),
+    ) {
+        eprintln!("Could not print the snapshot: {err}");
+        std::process::exit(1);
+    }
+    eprintln!("Saved the snapshot to {:?}.", snapshot_args.output);
+}
+
+/// Adds the calls from an strace or ltrace log to an existing profile, and saves
+/// the result to `output_path`.
+fn merge_log_into_profile(log_file: &File, profile_path: &Path, output_path: &Path) {
+    let calls = match import::strace::parse_log(BufReader::new(log_file)) {
+        Ok(calls) => calls,
+        Err(err) => {
+            eprintln!("Could not read the strace log: {err}");
+            std::process::exit(1);
+        }
+    };
+    let mut profile = match load_profile_json(profile_path) {
+        Ok(profile) => profile,
+        Err(err) => {
+            eprintln!("Could not read profile {:?}: {}", profile_path, err);
+            std::process::exit(1);
+        }
+    };
+    let merged = import::strace::merge_into_profile(&mut profile, &calls);
+    eprintln!(
+        "Added {merged} of {} calls as markers to {:?}.",
+        calls.len(),
+        profile_path
+    );
+    if let Err(err) = save_profile_to_file(&profile, output_path) {
+        eprintln!("Could not write {:?}: {}", output_path, err);
+        std::process::exit(1);
+    }
+}
+
+fn warn_if_utc_timestamps_unsupported(profile_creation_props: &ProfileCreationProps) {
+    if profile_creation_props.utc_timestamps {
+        eprintln!("--timestamps utc is only supported for perf.data files, ignoring it.");
+    }
+}
+
 fn convert_file_to_profile(
     input_file: &File,
     input_path: &Path,
     import_props: ImportProps,
 ) -> Profile {
     if input_path.extension() == Some(OsStr::new("etl")) {
+        warn_if_utc_timestamps_unsupported(&import_props.profile_creation_props);
         #[cfg(target_os = "windows")]
         {
             return windows::import::convert_etl_file_to_profile(input_path, import_props);
@@ -169,13 +796,94 @@ fn convert_file_to_profile(
 
         #[cfg(not(target_os = "windows"))]
         {
+            let result = std::fs::read(input_path)
+                .and_then(|data| import::etl::convert(&data, import_props.profile_creation_props));
+            return match result {
+                Ok(profile) => profile,
+                Err(error) => {
+                    eprintln!(
+                        "Error importing ETW trace {}: {}",
+                        input_path.to_string_lossy(),
+                        error
+                    );
+                    std::process::exit(1);
+                }
+            };
+        }
+    }
+
+    let file_meta = input_file.metadata().ok();
+    let file_mod_time = file_meta.and_then(|metadata| metadata.modified().ok());
+
+    let pid = input_path
+        .file_name()
+        .and_then(|name| import::pid_from_file_name(&name.to_string_lossy()))
+        .unwrap_or(0);
+    let profile_creation_props = import_props.profile_creation_props;
+    let detected_format = import::ImportFormat::detect(input_file);
+    let format = match import_props.format {
+        // Gzip-compressed heaptrack files are still detected as such.
+        Some(import::ImportFormat::Heaptrack)
+            if detected_format == import::ImportFormat::HeaptrackGz =>
+        {
+            detected_format
+        }
+        Some(format) => format,
+        None => import::ImportFormat::from_extension(input_path).unwrap_or(detected_format),
+    };
+    if format != import::ImportFormat::PerfData {
+        warn_if_utc_timestamps_unsupported(&profile_creation_props);
+    }
+    let result = match format {
+        import::ImportFormat::PerfData => None,
+        import::ImportFormat::PerfScript => Some(import::perf_script::convert(
+            BufReader::new(input_file),
+            file_mod_time,
+            profile_creation_props.clone(),
+        )),
+        import::ImportFormat::Massif => Some(import::massif::convert(
+            BufReader::new(input_file),
+            pid,
+            file_mod_time,
+            profile_creation_props.clone(),
+        )),
+        import::ImportFormat::Heaptrack => Some(import::heaptrack::convert(
+            BufReader::new(input_file),
+            pid,
+            file_mod_time,
+            profile_creation_props.clone(),
+        )),
+        import::ImportFormat::HeaptrackGz => Some(import::heaptrack::convert(
+            BufReader::new(flate2::read::GzDecoder::new(input_file)),
+            pid,
+            file_mod_time,
+            profile_creation_props.clone(),
+        )),
+        import::ImportFormat::Folded => Some(import::folded::convert(
+            BufReader::new(input_file),
+            pid,
+            file_mod_time,
+            profile_creation_props.clone(),
+        )),
+        import::ImportFormat::Zstd => {
             eprintln!(
-                "Error: Could not import ETW trace from file {}",
+                "Error: {} is compressed with zstd. Please decompress it with `zstd -d` first.",
                 input_path.to_string_lossy()
             );
-            eprintln!("Importing ETW traces is only supported on Windows.");
             std::process::exit(1);
         }
+    };
+    match result {
+        Some(Ok(profile)) => return profile,
+        Some(Err(error)) => {
+            eprintln!(
+                "Error importing {}: {:?}",
+                input_path.to_string_lossy(),
+                error
+            );
+            std::process::exit(1);
+        }
+        None => {}
     }
 
     // Treat all other files as perf.data files from Linux perf / Android simpleperf.
@@ -183,21 +891,26 @@ fn convert_file_to_profile(
     let path = input_path
         .canonicalize()
         .expect("Couldn't form absolute path");
-    let file_meta = input_file.metadata().ok();
-    let file_mod_time = file_meta.and_then(|metadata| metadata.modified().ok());
     let mut binary_lookup_dirs = import_props.symbol_props.symbol_dir;
     let mut aux_file_lookup_dirs = import_props.aux_file_dir;
     if let Some(parent_dir) = path.parent() {
         binary_lookup_dirs.push(parent_dir.into());
         aux_file_lookup_dirs.push(parent_dir.into());
     }
+    let guest_kernel_libs = match &import_props.guest_kallsyms {
+        Some(guest_kallsyms) => {
+            read_guest_kernel_libs(guest_kallsyms, import_props.guest_modules.as_deref())
+        }
+        None => Vec::new(),
+    };
     let reader = BufReader::new(input_file);
     match import::perf::convert(
         reader,
         file_mod_time,
         binary_lookup_dirs,
         aux_file_lookup_dirs,
-        import_props.profile_creation_props,
+        guest_kernel_libs,
+        profile_creation_props,
     ) {
         Ok(profile) => profile,
         Err(error) => {
@@ -207,23 +920,62 @@ fn convert_file_to_profile(
     }
 }
 
+/// Reads the guest's /proc/kallsyms and /proc/modules, as copied from the VM
+/// for `perf kvm`.
+fn read_guest_kernel_libs(
+    kallsyms_path: &Path,
+    modules_path: Option<&Path>,
+) -> Vec<GuestKernelLib> {
+    let read = |path: &Path| match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("Could not read {:?}: {}", path, err);
+            std::process::exit(1)
+        }
+    };
+    let kallsyms = read(kallsyms_path);
+    let modules = modules_path.map(read);
+    match parse_guest_kernel_symbols(&kallsyms, modules.as_deref()) {
+        Ok(libs) => libs,
+        Err(err) => {
+            eprintln!("Could not parse {:?}: {}", kallsyms_path, err);
+            std::process::exit(1)
+        }
+    }
+}
+
 fn run_server_serving_profile(
     profile_path: &Path,
     server_props: ServerProps,
     symbol_props: SymbolProps,
 ) {
-    let libinfo_map = {
-        let profile_file = match File::open(profile_path) {
-            Ok(file) => file,
-            Err(err) => {
-                eprintln!("Could not open file {:?}: {}", profile_path, err);
-                std::process::exit(1)
-            }
-        };
+    run_server_serving(
+        ServedProfile::File(profile_path.to_owned()),
+        server_props,
+        symbol_props,
+    );
+}
 
-        parse_libinfo_map_from_profile_file(profile_file, profile_path)
-            .expect("Couldn't parse libinfo map from profile file")
+fn run_server_serving(
+    profile: ServedProfile,
+    server_props: ServerProps,
+    symbol_props: SymbolProps,
+) {
+    let (libinfo_map, precog_path) = match &profile {
+        ServedProfile::File(profile_path) => {
+            let profile_file = match File::open(profile_path) {
+                Ok(file) => file,
+                Err(err) => {
+                    eprintln!("Could not open file {:?}: {}", profile_path, err);
+                    std::process::exit(1)
+                }
+            };
+            let libinfo_map = parse_libinfo_map_from_profile_file(profile_file, profile_path);
+            (libinfo_map, Some(profile_path.with_extension("syms.json")))
+        }
+        ServedProfile::InMemory(data) => (parse_libinfo_map_from_profile_data(data), None),
     };
+    let libinfo_map = libinfo_map.expect("Couldn't parse libinfo map from profile file");
 
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
@@ -237,8 +989,10 @@ fn run_server_serving_profile(
             symbol_manager.add_known_library(lib_info);
         }
 
-        let precog_path = profile_path.with_extension("syms.json");
-        if let Some(precog_info) = shared::symbol_precog::PrecogSymbolInfo::try_load(&precog_path) {
+        let precog_info = precog_path
+            .as_deref()
+            .and_then(shared::symbol_precog::PrecogSymbolInfo::try_load);
+        if let Some(precog_info) = precog_info {
             for (debug_id, syms) in precog_info.into_hash_map().into_iter() {
                 let lib_info = LibraryInfo {
                     debug_id: Some(debug_id),
@@ -250,14 +1004,22 @@ fn run_server_serving_profile(
 
         let ctrl_c_receiver = CtrlC::observe_oneshot();
 
-        let open_in_browser = server_props.open_in_browser;
+        let is_headless = server_props.browser.is_none() && server::is_headless_session();
+        let open_in_browser = server_props.open_in_browser && !is_headless;
+        let browser = server_props.browser.clone();
+        let print_qr_code = server_props.print_qr_code;
+        if server_props.open_in_browser && is_headless {
+            eprintln!(
+                "Not opening a browser because there doesn't seem to be a display. Open the URL below in a browser instead, and forward the port if you're connected via SSH (ssh -L)."
+            );
+        }
 
         let RunningServerInfo {
             server_join_handle,
             server_origin,
             profiler_url,
         } = start_server(
-            Some(profile_path),
+            Some(profile),
             server_props,
             symbol_manager,
             ctrl_c_receiver,
@@ -270,11 +1032,19 @@ fn run_server_serving_profile(
                 println!("{profiler_url}");
             }
         }
+        if print_qr_code {
+            if let Some(qr_code) = profiler_url.as_deref().and_then(server::qr_code_for_url) {
+                eprintln!("{qr_code}");
+            }
+        }
         eprintln!("Press Ctrl+C to stop.");
 
         if open_in_browser {
             if let Some(profiler_url) = &profiler_url {
-                let _ = opener::open_browser(profiler_url);
+                if let Err(e) = server::open_in_browser(profiler_url, browser.as_deref()) {
+                    eprintln!("Could not open the browser: {e}");
+                    println!("{profiler_url}");
+                }
             }
         }
 
@@ -288,3 +1058,42 @@ fn run_server_serving_profile(
         }
     });
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The files in the system temp dir which samply could have created.
+    fn samply_temp_files() -> Vec<std::path::PathBuf> {
+        let mut entries: Vec<_> = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().contains("samply-"))
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn decrypted_profile_stays_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let encrypted_path = dir.path().join("profile.json.age");
+        std::fs::write(&encrypted_path, b"age-encryption.org/v1\n-> X25519 abc\n").unwrap();
+        let plaintext = br#"{"meta":{"product":"secret"},"libs":[],"threads":[]}"#;
+
+        let samply_temp_files_before = samply_temp_files();
+        let profile = profile_to_load(&encrypted_path, |_| Ok(plaintext.to_vec())).unwrap();
+        let samply_temp_files_after = samply_temp_files();
+
+        match profile {
+            ServedProfile::InMemory(data) => assert_eq!(&data[..], plaintext),
+            ServedProfile::File(path) => panic!("The decrypted profile was saved to {path:?}"),
+        }
+        assert_eq!(samply_temp_files_after, samply_temp_files_before);
+        let files_next_to_profile: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files_next_to_profile, vec![encrypted_path]);
+    }
+}
@@ -59,6 +59,18 @@ pub fn parse_libinfo_map_from_profile_file(
     }
 }
 
+/// Like [`parse_libinfo_map_from_profile_file`], for a profile in memory,
+/// which may be gzipped.
+pub fn parse_libinfo_map_from_profile_data(
+    data: &[u8],
+) -> Result<HashMap<(String, DebugId), LibraryInfo>, std::io::Error> {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        parse_libinfo_map_from_profile(BufReader::new(GzDecoder::new(data)))
+    } else {
+        parse_libinfo_map_from_profile(data)
+    }
+}
+
 fn parse_libinfo_map_from_profile(
     reader: impl std::io::Read,
 ) -> Result<HashMap<(String, DebugId), LibraryInfo>, std::io::Error> {
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+/// A call tree of function names, for `samply report --by call-tree`.
+#[derive(Debug, Default)]
+pub struct CallTree {
+    /// All nodes except the implicit root. Children refer to nodes by index.
+    nodes: Vec<CallNode>,
+    roots: HashMap<String, usize>,
+}
+
+#[derive(Debug)]
+struct CallNode {
+    function: String,
+    total_weight: f64,
+    self_weight: f64,
+    children: HashMap<String, usize>,
+}
+
+impl CallTree {
+    /// Adds a stack, ordered from the root of the tree to the leaf.
+    pub fn add_stack<'a>(&mut self, functions: impl Iterator<Item = &'a str>, weight: f64) {
+        let mut parent: Option<usize> = None;
+        for function in functions {
+            let existing = match parent {
+                Some(parent) => self.nodes[parent].children.get(function).copied(),
+                None => self.roots.get(function).copied(),
+            };
+            let node_index = match existing {
+                Some(node_index) => node_index,
+                None => {
+                    let node_index = self.nodes.len();
+                    self.nodes.push(CallNode {
+                        function: function.to_owned(),
+                        total_weight: 0.0,
+                        self_weight: 0.0,
+                        children: HashMap::new(),
+                    });
+                    let siblings = match parent {
+                        Some(parent) => &mut self.nodes[parent].children,
+                        None => &mut self.roots,
+                    };
+                    siblings.insert(function.to_owned(), node_index);
+                    node_index
+                }
+            };
+            self.nodes[node_index].total_weight += weight;
+            parent = Some(node_index);
+        }
+        if let Some(leaf) = parent {
+            self.nodes[leaf].self_weight += weight;
+        }
+    }
+
+    /// Prints the tree depth-first, with the heaviest children first, and stops
+    /// after `max_lines` nodes.
+    pub fn print(
+        &self,
+        out: &mut impl Write,
+        total_weight: f64,
+        max_lines: usize,
+    ) -> Result<(), std::io::Error> {
+        writeln!(out, "{:>10} {:>7} {:>10}  Function", "Total", "%", "Self")?;
+        let mut lines = 0;
+        let mut stack: Vec<(usize, usize)> = self
+            .sorted_children(&self.roots)
+            .rev()
+            .map(|node| (node, 0))
+            .collect();
+        while let Some((node_index, depth)) = stack.pop() {
+            if lines >= max_lines {
+                break;
+            }
+            lines += 1;
+            let node = &self.nodes[node_index];
+            let percentage = if total_weight == 0.0 {
+                0.0
+            } else {
+                node.total_weight / total_weight * 100.0
+            };
+            writeln!(
+                out,
+                "{:>10} {:>6.2}% {:>10}  {:indent$}{}",
+                node.total_weight,
+                percentage,
+                node.self_weight,
+                "",
+                node.function,
+                indent = depth * 2
+            )?;
+            stack.extend(
+                self.sorted_children(&node.children)
+                    .rev()
+                    .map(|child| (child, depth + 1)),
+            );
+        }
+        Ok(())
+    }
+
+    fn sorted_children(
+        &self,
+        children: &HashMap<String, usize>,
+    ) -> impl DoubleEndedIterator<Item = usize> {
+        let mut children: Vec<usize> = children.values().copied().collect();
+        children.sort_by(|&a, &b| {
+            let (a, b) = (&self.nodes[a], &self.nodes[b]);
+            b.total_weight
+                .total_cmp(&a.total_weight)
+                .then_with(|| a.function.cmp(&b.function))
+        });
+        children.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn print_call_tree() {
+        let mut tree = CallTree::default();
+        tree.add_stack(["main", "a", "b"].into_iter(), 2.0);
+        tree.add_stack(["main", "c"].into_iter(), 3.0);
+        tree.add_stack(["main"].into_iter(), 1.0);
+
+        let mut out = Vec::new();
+        tree.print(&mut out, 6.0, 10).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().map(str::trim_end).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "     Total       %       Self  Function",
+                "         6 100.00%          1  main",
+                "         3  50.00%          3    c",
+                "         2  33.33%          0    a",
+                "         2  33.33%          2      b",
+            ]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! The `samply diff` command, which compares the stacks of two saved profiles
+//! in the output format of `difffolded.pl`: one line per stack, with the sample
+//! counts from both profiles. `flamegraph.pl` and other differential flame
+//! graph tools turn this into a flame graph which is colored by the change.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use super::{symbolicated_stacks, SymbolicatedStacks};
+use crate::shared::prop_types::{DiffFormat, DiffProps, SymbolProps};
+
+pub fn run_diff(
+    before_path: &Path,
+    after_path: &Path,
+    diff_props: DiffProps,
+    symbol_props: SymbolProps,
+) -> Result<(), std::io::Error> {
+    let before = symbolicated_stacks(before_path, symbol_props.clone())?;
+    let after = symbolicated_stacks(after_path, symbol_props)?;
+    let mut out: Box<dyn Write> = match &diff_props.output {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match diff_props.format {
+        DiffFormat::CollapsedDiff => {
+            for (stack, before_count, after_count) in
+                collapsed_diff(&before, &after, diff_props.normalize)
+            {
+                writeln!(out, "{stack} {before_count} {after_count}")?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Returns the folded stacks of both profiles, with the sample count in each
+/// profile, sorted by stack. Labels are ignored, so stacks which only differ
+/// in their labels are merged.
+///
+/// With `normalize`, the counts of the first profile are scaled so that both
+/// profiles have the same total, like with `difffolded.pl -n`.
+fn collapsed_diff(
+    before: &SymbolicatedStacks,
+    after: &SymbolicatedStacks,
+    normalize: bool,
+) -> Vec<(String, u64, u64)> {
+    let mut weights: BTreeMap<String, (f64, f64)> = BTreeMap::new();
+    for (stacks, is_after) in [(before, false), (after, true)] {
+        for stack in &stacks.stacks {
+            if stack.functions.is_empty() {
+                continue;
+            }
+            let names: Vec<String> = stack
+                .functions
+                .iter()
+                .map(|function| function.replace(';', ":"))
+                .collect();
+            let entry = weights.entry(names.join(";")).or_default();
+            if is_after {
+                entry.1 += stack.weight;
+            } else {
+                entry.0 += stack.weight;
+            }
+        }
+    }
+
+    let before_total = weights
+        .values()
+        .fold(0.0, |total, (before, _)| total + before);
+    let after_total = weights
+        .values()
+        .fold(0.0, |total, (_, after)| total + after);
+    let scale = if normalize && before_total > 0.0 {
+        after_total / before_total
+    } else {
+        1.0
+    };
+    weights
+        .into_iter()
+        .map(|(stack, (before, after))| {
+            (stack, (before * scale).round() as u64, after.round() as u64)
+        })
+        .filter(|(_, before, after)| *before > 0 || *after > 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::SymbolicatedStack;
+    use super::*;
+
+    fn stacks(stacks: &[(&[&str], f64)]) -> SymbolicatedStacks {
+        SymbolicatedStacks {
+            start_time: 0.0,
+            duration: 0.0,
+            interval: 1.0,
+            stacks: stacks
+                .iter()
+                .map(|(functions, weight)| SymbolicatedStack {
+                    labels: Vec::new(),
+                    functions: functions.iter().map(|f| f.to_string()).collect(),
+                    weight: *weight,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn counts_from_both_profiles() {
+        let before = stacks(&[(&["main", "parse"], 4.0), (&["main", "a;b"], 2.0)]);
+        let after = stacks(&[
+            (&["main", "parse"], 6.0),
+            (&["main", "layout"], 6.0),
+            (&["main", "parse"], 2.0),
+        ]);
+        assert_eq!(
+            collapsed_diff(&before, &after, false),
+            vec![
+                ("main;a:b".to_string(), 2, 0),
+                ("main;layout".to_string(), 0, 6),
+                ("main;parse".to_string(), 4, 8),
+            ]
+        );
+        assert_eq!(
+            collapsed_diff(&before, &after, true),
+            vec![
+                ("main;a:b".to_string(), 5, 0),
+                ("main;layout".to_string(), 0, 6),
+                ("main;parse".to_string(), 9, 8),
+            ]
+        );
+    }
+}
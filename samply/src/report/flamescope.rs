@@ -0,0 +1,109 @@
+//! The FlameScope export of `samply export`: a heat map of the samples, with
+//! one column per second and one row per slice of a second, in the JSON format
+//! of FlameScope's heat map. Work which happens at the same offset in every
+//! second, such as a periodic timer, shows up as a horizontal line.
+
+use std::io::Write;
+
+use serde_json::json;
+
+use super::profile::ReportProfile;
+
+/// The sample counts of a profile, by second and by offset within the second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubsecondHeatmap {
+    /// The start of each column, in seconds from the start of the profile.
+    pub columns: Vec<u64>,
+    /// The start of each row, in milliseconds from the start of its second.
+    pub rows: Vec<f64>,
+    /// The summed sample weights, indexed by column and then by row.
+    pub values: Vec<Vec<f64>>,
+}
+
+impl SubsecondHeatmap {
+    /// Buckets the samples of all threads by the second in which they were
+    /// taken, and by their offset within that second, with `rows_per_second`
+    /// rows for each second.
+    pub fn from_profile(profile: &ReportProfile, rows_per_second: usize) -> Self {
+        let rows_per_second = rows_per_second.max(1);
+        let row_ms = 1000.0 / rows_per_second as f64;
+        let mut values: Vec<Vec<f64>> = Vec::new();
+        for thread in &profile.threads {
+            for (time, (_stack, weight)) in thread.sample_times.iter().zip(&thread.samples) {
+                if *time < 0.0 || !time.is_finite() {
+                    continue;
+                }
+                let column = (*time / 1000.0) as usize;
+                let row = (((*time % 1000.0) / row_ms) as usize).min(rows_per_second - 1);
+                if values.len() <= column {
+                    values.resize_with(column + 1, || vec![0.0; rows_per_second]);
+                }
+                values[column][row] += weight;
+            }
+        }
+        SubsecondHeatmap {
+            columns: (0..values.len() as u64).collect(),
+            rows: (0..rows_per_second)
+                .map(|row| row as f64 * row_ms)
+                .collect(),
+            values,
+        }
+    }
+
+    pub fn max_value(&self) -> f64 {
+        self.values.iter().flatten().copied().fold(0.0, f64::max)
+    }
+
+    /// Writes the heat map as FlameScope's heat map JSON.
+    pub fn write_json(&self, out: &mut impl Write) -> Result<(), std::io::Error> {
+        let json = json!({
+            "columns": self.columns,
+            "rows": self.rows,
+            "values": self.values,
+            "maxvalue": self.max_value(),
+        });
+        serde_json::to_writer(&mut *out, &json)?;
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::profile::ReportThread;
+    use super::*;
+
+    #[test]
+    fn samples_by_second_and_offset() {
+        let thread = ReportThread {
+            name: None,
+            tid: None,
+            cpu: None,
+            frames: Vec::new(),
+            stacks: Vec::new(),
+            samples: vec![(None, 1.0), (None, 2.0), (None, 1.0), (None, 1.0)],
+            off_cpu: Vec::new(),
+            markers: Vec::new(),
+            sample_times: vec![10.0, 1010.0, 1999.0, 2500.0],
+        };
+        let profile = ReportProfile {
+            start_time: 0.0,
+            interval: 1.0,
+            libs: Vec::new(),
+            threads: vec![thread],
+            size_reductions: Vec::new(),
+            core_types: Vec::new(),
+        };
+        let heatmap = SubsecondHeatmap::from_profile(&profile, 4);
+        assert_eq!(heatmap.columns, vec![0, 1, 2]);
+        assert_eq!(heatmap.rows, vec![0.0, 250.0, 500.0, 750.0]);
+        assert_eq!(
+            heatmap.values,
+            vec![
+                vec![1.0, 0.0, 0.0, 0.0],
+                vec![2.0, 0.0, 0.0, 1.0],
+                vec![0.0, 0.0, 1.0, 0.0],
+            ]
+        );
+        assert_eq!(heatmap.max_value(), 2.0);
+    }
+}
@@ -0,0 +1,802 @@
+//! The `samply report` command, which prints aggregated sample counts from a
+//! saved profile to the terminal.
+
+mod call_tree;
+mod diff;
+mod flamescope;
+mod profile;
+mod stack_pattern;
+mod summary;
+mod symbolicator;
+mod transforms;
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+use call_tree::CallTree;
+pub use diff::run_diff;
+use flamescope::SubsecondHeatmap;
+use profile::{ReportFrame, ReportProfile};
+use regex::Regex;
+pub use stack_pattern::StackPattern;
+pub use summary::run_summarize;
+use symbolicator::{ReportSymbolicator, ResolvedAddress};
+use transforms::StackTransforms;
+
+use crate::shared::prop_types::{
+    AssertProps, CpuFilter, ExportFormat, ExportProps, GrepProps, ReportAggregation, ReportProps,
+    SymbolProps, TimeThresholdKind,
+};
+use crate::shared::sample_labels::parse_label_frame_name;
+
+/// How many instructions to show before and after a hot address with `--disassemble`.
+const DISASSEMBLY_CONTEXT_INSTRUCTIONS: usize = 4;
+
+/// The largest function we're willing to disassemble for `--disassemble`.
+const MAX_DISASSEMBLY_SIZE: u32 = 64 * 1024;
+
+/// Identifies a frame from the profile, across threads.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum FrameKey {
+    /// A native frame, identified by lib index and lib-relative address.
+    Address(usize, u32),
+    /// Any other frame, identified by the function name from the profile.
+    Label(String),
+}
+
+impl FrameKey {
+    fn for_frame(frame: &ReportFrame) -> Self {
+        match (frame.lib_index, frame.address) {
+            (Some(lib_index), Some(address)) => FrameKey::Address(lib_index, address),
+            _ => FrameKey::Label(frame.func_name.clone()),
+        }
+    }
+}
+
+/// A function in a symbolicated stack. A single frame from the profile turns
+/// into multiple entries if it contains inlined calls.
+#[derive(Debug, Clone)]
+struct StackEntry {
+    function: String,
+    frame: FrameKey,
+}
+
+/// One row of the report.
+struct ReportRow {
+    weight: f64,
+    location: String,
+    function: String,
+}
+
+/// A profile whose stacks have been symbolicated and transformed, ready for
+/// aggregation.
+struct SymbolicatedProfile {
+    profile: ReportProfile,
+    runtime: tokio::runtime::Runtime,
+    symbolicator: ReportSymbolicator,
+    resolved: HashMap<(usize, u32), ResolvedAddress>,
+    label_locations: HashMap<String, (Option<String>, Option<u32>)>,
+    /// Each distinct stack, ordered from root to leaf, with its summed sample weight.
+    stacks: Vec<(Vec<StackEntry>, f64)>,
+    /// The index of the thread in `profile.threads` for each entry in `stacks`.
+    stack_threads: Vec<usize>,
+    /// The part of the weight of each entry in `stacks` which comes from samples
+    /// taken while the thread wasn't running.
+    stack_off_cpu_weights: Vec<f64>,
+    total_weight: f64,
+}
+
+impl SymbolicatedProfile {
+    fn load(
+        profile_path: &Path,
+        symbol_props: SymbolProps,
+        transforms: &StackTransforms,
+        cpu_filter: Option<&CpuFilter>,
+    ) -> Result<Self, std::io::Error> {
+        let mut profile = ReportProfile::from_file(profile_path)?;
+        if let Some(cpu_filter) = cpu_filter {
+            profile.retain_cpu_tracks(cpu_filter).map_err(|message| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+            })?;
+        }
+
+        // Sum up the sample weights per stack, per thread, and find all native
+        // frames that need to be symbolicated.
+        let mut stack_weights: Vec<HashMap<usize, (f64, f64)>> = Vec::new();
+        let mut addresses: BTreeSet<(usize, u32)> = BTreeSet::new();
+        let mut label_locations: HashMap<String, (Option<String>, Option<u32>)> = HashMap::new();
+        for thread in &profile.threads {
+            let mut weights: HashMap<usize, (f64, f64)> = HashMap::new();
+            for (i, &(stack, weight)) in thread.samples.iter().enumerate() {
+                if let Some(stack) = stack {
+                    let (total, off_cpu) = weights.entry(stack).or_default();
+                    *total += weight;
+                    if thread.off_cpu.get(i) == Some(&true) {
+                        *off_cpu += weight;
+                    }
+                }
+            }
+            for &stack in weights.keys() {
+                for frame in thread.stack_frames(stack) {
+                    match FrameKey::for_frame(frame) {
+                        FrameKey::Address(lib_index, address) => {
+                            addresses.insert((lib_index, address));
+                        }
+                        FrameKey::Label(name) => {
+                            label_locations
+                                .entry(name)
+                                .or_insert_with(|| (frame.file.clone(), frame.line));
+                        }
+                    }
+                }
+            }
+            stack_weights.push(weights);
+        }
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let symbolicator = {
+            let _guard = runtime.enter();
+            ReportSymbolicator::new(profile_path, symbol_props)
+        };
+        let resolved = runtime.block_on(symbolicator.resolve_addresses(&profile.libs, &addresses));
+
+        // Symbolicate each stack, apply the transforms, and collect the resulting
+        // stacks with their weights.
+        let mut stacks: Vec<(Vec<StackEntry>, f64)> = Vec::new();
+        let mut stack_threads = Vec::new();
+        let mut stack_off_cpu_weights = Vec::new();
+        for (thread_index, (thread, weights)) in
+            profile.threads.iter().zip(stack_weights).enumerate()
+        {
+            for (stack, (weight, off_cpu_weight)) in weights {
+                let mut entries = Vec::new();
+                for frame in thread.stack_frames(stack).into_iter().rev() {
+                    let key = FrameKey::for_frame(frame);
+                    let inline_functions = match &key {
+                        FrameKey::Address(lib_index, address) => resolved
+                            .get(&(*lib_index, *address))
+                            .map(|info| info.inline_functions.as_slice()),
+                        FrameKey::Label(_) => None,
+                    };
+                    match inline_functions {
+                        Some(functions) if !functions.is_empty() => {
+                            entries.extend(functions.iter().rev().map(|function| StackEntry {
+                                function: function.clone(),
+                                frame: key.clone(),
+                            }))
+                        }
+                        _ => entries.push(StackEntry {
+                            function: frame.func_name.clone(),
+                            frame: key,
+                        }),
+                    }
+                }
+                if let Some(entries) = transforms.apply(entries, |entry| &entry.function) {
+                    stacks.push((entries, weight));
+                    stack_threads.push(thread_index);
+                    stack_off_cpu_weights.push(off_cpu_weight);
+                }
+            }
+        }
+        let total_weight: f64 = stacks.iter().map(|(_, weight)| weight).sum();
+
+        Ok(SymbolicatedProfile {
+            profile,
+            runtime,
+            symbolicator,
+            resolved,
+            label_locations,
+            stacks,
+            stack_threads,
+            stack_off_cpu_weights,
+            total_weight,
+        })
+    }
+
+    /// Returns the sample weight of the stacks whose leaf function matches `regex`.
+    fn self_weight_matching(&self, regex: &Regex) -> f64 {
+        self.stacks
+            .iter()
+            .filter(|(entries, _)| regex.is_match(&entries.last().unwrap().function))
+            .map(|(_, weight)| weight)
+            .sum()
+    }
+
+    /// Returns the sample weight of the stacks which contain a function matching `regex`.
+    fn total_weight_matching(&self, regex: &Regex) -> f64 {
+        self.stacks
+            .iter()
+            .filter(|(entries, _)| entries.iter().any(|entry| regex.is_match(&entry.function)))
+            .map(|(_, weight)| weight)
+            .sum()
+    }
+}
+
+pub fn run_report(
+    profile_path: &Path,
+    report_props: ReportProps,
+    symbol_props: SymbolProps,
+) -> Result<(), std::io::Error> {
+    let transforms = StackTransforms {
+        hide: report_props.hide.clone(),
+        focus: report_props.focus.clone(),
+    };
+    let SymbolicatedProfile {
+        profile,
+        runtime,
+        symbolicator,
+        resolved,
+        label_locations,
+        stacks,
+        total_weight,
+        ..
+    } = SymbolicatedProfile::load(
+        profile_path,
+        symbol_props,
+        &transforms,
+        report_props.cpu_filter.as_ref(),
+    )?;
+
+    let mut out = std::io::stdout().lock();
+    match report_props.aggregation {
+        ReportAggregation::Address | ReportAggregation::Line => {
+            let mut leaf_weights: HashMap<FrameKey, f64> = HashMap::new();
+            for (entries, weight) in &stacks {
+                let leaf = &entries.last().unwrap().frame;
+                *leaf_weights.entry(leaf.clone()).or_default() += weight;
+            }
+            let rows = leaf_rows(
+                &profile,
+                &report_props,
+                &leaf_weights,
+                &resolved,
+                &label_locations,
+            );
+            print_rows(&mut out, &report_props, &rows, total_weight)?;
+
+            if report_props.disassemble && report_props.aggregation == ReportAggregation::Address {
+                print_disassembly(
+                    &mut out,
+                    &runtime,
+                    &symbolicator,
+                    &profile,
+                    &report_props,
+                    &leaf_weights,
+                    &resolved,
+                )?;
+            }
+        }
+        ReportAggregation::Function => {
+            let mut self_weights: HashMap<Cow<str>, f64> = HashMap::new();
+            let mut total_weights: HashMap<Cow<str>, f64> = HashMap::new();
+            for (entries, weight) in &stacks {
+                let leaf = entries.last().unwrap();
+                *self_weights
+                    .entry(entry_name(leaf, &profile, report_props.addresses))
+                    .or_default() += weight;
+                // Count each function only once per stack, even if it's recursive.
+                let functions: HashSet<Cow<str>> = entries
+                    .iter()
+                    .map(|entry| entry_name(entry, &profile, report_props.addresses))
+                    .collect();
+                for function in functions {
+                    *total_weights.entry(function).or_default() += weight;
+                }
+            }
+            let mut rows: Vec<(Cow<str>, f64)> = total_weights.into_iter().collect();
+            rows.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            rows.truncate(report_props.limit);
+            writeln!(
+                out,
+                "{:>10} {:>7} {:>10} {:>7}  Function",
+                "Total", "%", "Self", "%"
+            )?;
+            for (function, weight) in rows {
+                let self_weight = self_weights.get(&function).copied().unwrap_or(0.0);
+                writeln!(
+                    out,
+                    "{:>10} {:>6.2}% {:>10} {:>6.2}%  {function}",
+                    weight,
+                    percentage(weight, total_weight),
+                    self_weight,
+                    percentage(self_weight, total_weight),
+                )?;
+            }
+        }
+        ReportAggregation::CallTree => {
+            let mut tree = CallTree::default();
+            for (entries, weight) in &stacks {
+                let names: Vec<Cow<str>> = entries
+                    .iter()
+                    .map(|entry| entry_name(entry, &profile, report_props.addresses))
+                    .collect();
+                let functions = names.iter().map(|name| name.as_ref());
+                if report_props.inverted {
+                    tree.add_stack(functions.rev(), *weight);
+                } else {
+                    tree.add_stack(functions, *weight);
+                }
+            }
+            tree.print(&mut out, total_weight, report_props.limit)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the stack of each thread in a profile from `samply snapshot`, which has
+/// a single sample per thread, starting with the leaf function.
+#[allow(dead_code)] // Only used on macOS
+pub fn run_snapshot_report(
+    profile_path: &Path,
+    addresses: bool,
+    symbol_props: SymbolProps,
+) -> Result<(), std::io::Error> {
+    let profile = SymbolicatedProfile::load(
+        profile_path,
+        symbol_props,
+        &StackTransforms::default(),
+        None,
+    )?;
+
+    let mut out = std::io::stdout().lock();
+    for (thread_index, thread) in profile.profile.threads.iter().enumerate() {
+        let Some(stack_index) = profile
+            .stack_threads
+            .iter()
+            .position(|&index| index == thread_index)
+        else {
+            continue;
+        };
+        let mut entries = profile.stacks[stack_index].0.as_slice();
+        // The root frame is a label frame with the thread's run state.
+        let state = match entries
+            .first()
+            .and_then(|entry| parse_label_frame_name(&entry.function))
+        {
+            Some(labels) => {
+                entries = &entries[1..];
+                labels
+                    .into_iter()
+                    .find(|(key, _)| key == "state")
+                    .map(|(_, value)| value)
+            }
+            None => None,
+        };
+
+        write!(
+            out,
+            "Thread \"{}\"",
+            thread.name.as_deref().unwrap_or("<unnamed>")
+        )?;
+        if let Some(tid) = &thread.tid {
+            write!(out, " (tid {tid})")?;
+        }
+        match state {
+            Some(state) => writeln!(out, ", {state}:")?,
+            None => writeln!(out, ":")?,
+        }
+        for entry in entries.iter().rev() {
+            writeln!(
+                out,
+                "    {}",
+                entry_name(entry, &profile.profile, addresses)
+            )?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Returns the function name of a stack entry. With `with_address`, native
+/// frames also get their library and library-relative address, so that the
+/// exact code location stays visible after symbolication.
+fn entry_name<'a>(
+    entry: &'a StackEntry,
+    profile: &ReportProfile,
+    with_address: bool,
+) -> Cow<'a, str> {
+    match &entry.frame {
+        FrameKey::Address(lib_index, address) if with_address => {
+            let lib_name = profile
+                .libs
+                .get(*lib_index)
+                .map_or("<unknown lib>", |lib| lib.name.as_str());
+            Cow::Owned(format!("{} [{lib_name}!{address:#x}]", entry.function))
+        }
+        _ => Cow::Borrowed(&entry.function),
+    }
+}
+
+/// The symbolicated stacks of a profile, for exporting them in other formats.
+pub struct SymbolicatedStacks {
+    /// The start time of the profile, in milliseconds since the Unix epoch.
+    pub start_time: f64,
+    /// The time from the start of the profile to its last sample, in milliseconds.
+    pub duration: f64,
+    /// The sampling interval, in milliseconds.
+    pub interval: f64,
+    pub stacks: Vec<SymbolicatedStack>,
+}
+
+/// A distinct stack, with the summed weight of its samples.
+pub struct SymbolicatedStack {
+    /// The labels which were active while the samples were taken, from the
+    /// stack's label frame.
+    pub labels: Vec<(String, String)>,
+    /// The function names, ordered from root to leaf. Inlined functions are included.
+    pub functions: Vec<String>,
+    pub weight: f64,
+}
+
+/// Loads and symbolicates the stacks of a saved profile.
+pub fn symbolicated_stacks(
+    profile_path: &Path,
+    symbol_props: SymbolProps,
+) -> Result<SymbolicatedStacks, std::io::Error> {
+    let profile = SymbolicatedProfile::load(
+        profile_path,
+        symbol_props,
+        &StackTransforms::default(),
+        None,
+    )?;
+    let stacks = profile
+        .stacks
+        .into_iter()
+        .map(|(entries, weight)| {
+            let mut functions: Vec<String> =
+                entries.into_iter().map(|entry| entry.function).collect();
+            let labels = match functions.first().and_then(|f| parse_label_frame_name(f)) {
+                Some(labels) => {
+                    functions.remove(0);
+                    labels
+                }
+                None => Vec::new(),
+            };
+            SymbolicatedStack {
+                labels,
+                functions,
+                weight,
+            }
+        })
+        .collect();
+    Ok(SymbolicatedStacks {
+        start_time: profile.profile.start_time,
+        duration: profile.profile.duration(),
+        interval: profile.profile.interval,
+        stacks,
+    })
+}
+
+/// Checks the thresholds in `assert_props` against the profile, and prints a
+/// line for each of them. Returns whether all thresholds were met.
+pub fn run_assert(
+    profile_path: &Path,
+    assert_props: AssertProps,
+    symbol_props: SymbolProps,
+) -> Result<bool, std::io::Error> {
+    let profile = SymbolicatedProfile::load(
+        profile_path,
+        symbol_props,
+        &StackTransforms::default(),
+        None,
+    )?;
+
+    let mut all_passed = true;
+    for threshold in &assert_props.thresholds {
+        let weight = match threshold.kind {
+            TimeThresholdKind::SelfTime => profile.self_weight_matching(&threshold.function),
+            TimeThresholdKind::TotalTime => profile.total_weight_matching(&threshold.function),
+        };
+        let actual_percentage = percentage(weight, profile.total_weight);
+        let passed = actual_percentage <= threshold.max_percentage;
+        all_passed &= passed;
+        eprintln!(
+            "{}: {} time of {} is {actual_percentage:.2}% ({weight} of {} samples), limit is {}%",
+            if passed { "ok" } else { "FAILED" },
+            match threshold.kind {
+                TimeThresholdKind::SelfTime => "self",
+                TimeThresholdKind::TotalTime => "total",
+            },
+            threshold.function,
+            profile.total_weight,
+            threshold.max_percentage,
+        );
+    }
+    Ok(all_passed)
+}
+
+/// Counts the samples whose stacks match the pattern in `grep_props`, and prints
+/// the heaviest distinct matching stacks. Returns whether any sample matched.
+pub fn run_grep(
+    profile_path: &Path,
+    grep_props: GrepProps,
+    symbol_props: SymbolProps,
+) -> Result<bool, std::io::Error> {
+    let profile = SymbolicatedProfile::load(
+        profile_path,
+        symbol_props,
+        &StackTransforms::default(),
+        None,
+    )?;
+
+    // The same stack can appear in multiple threads; merge them.
+    let mut matching_stacks: HashMap<Vec<Cow<str>>, f64> = HashMap::new();
+    for (entries, weight) in &profile.stacks {
+        let functions = entries.iter().map(|entry| entry.function.as_str());
+        if grep_props.pattern.is_match(functions) {
+            let names = entries
+                .iter()
+                .map(|entry| entry_name(entry, &profile.profile, grep_props.addresses))
+                .collect();
+            *matching_stacks.entry(names).or_default() += weight;
+        }
+    }
+    // Summing an empty iterator gives -0.0, which would be printed as such.
+    let matching_weight = matching_stacks
+        .values()
+        .fold(0.0, |sum, weight| sum + weight);
+
+    let mut out = std::io::stdout().lock();
+    writeln!(
+        out,
+        "{matching_weight} of {} samples ({:.2}%) match",
+        profile.total_weight,
+        percentage(matching_weight, profile.total_weight)
+    )?;
+    if grep_props.count_only {
+        return Ok(matching_weight > 0.0);
+    }
+
+    let mut stacks: Vec<(Vec<Cow<str>>, f64)> = matching_stacks.into_iter().collect();
+    stacks.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    for (functions, weight) in stacks.iter().take(grep_props.limit) {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{weight} samples ({:.2}%):",
+            percentage(*weight, profile.total_weight)
+        )?;
+        let functions = functions.iter().map(|function| function.as_ref());
+        let functions: Vec<&str> = if grep_props.pattern.root_first {
+            functions.collect()
+        } else {
+            functions.rev().collect()
+        };
+        for function in functions {
+            writeln!(out, "    {function}")?;
+        }
+    }
+    if stacks.len() > grep_props.limit {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "... and {} more matching stacks",
+            stacks.len() - grep_props.limit
+        )?;
+    }
+    Ok(matching_weight > 0.0)
+}
+
+/// Converts the profile into the format in `export_props`, and writes it to the
+/// output file or to stdout.
+pub fn run_export(profile_path: &Path, export_props: ExportProps) -> Result<(), std::io::Error> {
+    let profile = ReportProfile::from_file(profile_path)?;
+    let mut out: Box<dyn Write> = match &export_props.output {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match export_props.format {
+        ExportFormat::FlameScope => {
+            SubsecondHeatmap::from_profile(&profile, export_props.rows_per_second)
+                .write_json(&mut out)?;
+        }
+    }
+    out.flush()
+}
+
+/// Creates the rows for `--by address` and `--by line`, sorted by weight.
+fn leaf_rows(
+    profile: &ReportProfile,
+    report_props: &ReportProps,
+    leaf_weights: &HashMap<FrameKey, f64>,
+    resolved: &HashMap<(usize, u32), ResolvedAddress>,
+    label_locations: &HashMap<String, (Option<String>, Option<u32>)>,
+) -> Vec<ReportRow> {
+    let lib_name = |lib_index: usize| {
+        profile
+            .libs
+            .get(lib_index)
+            .map_or("<unknown lib>", |lib| lib.name.as_str())
+    };
+
+    let mut rows: Vec<ReportRow> = match report_props.aggregation {
+        ReportAggregation::Line => {
+            // Merge all addresses which map to the same source line. Addresses
+            // without line information are grouped by function instead.
+            let mut line_weights: HashMap<(String, String), f64> = HashMap::new();
+            for (key, &weight) in leaf_weights {
+                let (location, function) = match key {
+                    FrameKey::Address(lib_index, address) => {
+                        match resolved.get(&(*lib_index, *address)) {
+                            Some(info) => (
+                                file_line(&info.file, info.line).unwrap_or_else(|| {
+                                    format!("{} (no line info)", lib_name(*lib_index))
+                                }),
+                                info.function
+                                    .clone()
+                                    .unwrap_or_else(|| format!("{address:#x}")),
+                            ),
+                            None => (
+                                format!("{} (no symbols)", lib_name(*lib_index)),
+                                format!("{address:#x}"),
+                            ),
+                        }
+                    }
+                    FrameKey::Label(name) => {
+                        let (file, line) = &label_locations[name];
+                        (file_line(file, *line).unwrap_or_default(), name.clone())
+                    }
+                };
+                *line_weights.entry((location, function)).or_default() += weight;
+            }
+            line_weights
+                .into_iter()
+                .map(|((location, function), weight)| ReportRow {
+                    weight,
+                    location,
+                    function,
+                })
+                .collect()
+        }
+        _ => leaf_weights
+            .iter()
+            .map(|(key, &weight)| match key {
+                FrameKey::Address(lib_index, address) => {
+                    let info = resolved.get(&(*lib_index, *address));
+                    let function = info
+                        .and_then(|info| info.function.clone())
+                        .unwrap_or_else(|| format!("{address:#x}"));
+                    let source = info.and_then(|info| file_line(&info.file, info.line));
+                    let location = format!("{}!{address:#x}", lib_name(*lib_index));
+                    ReportRow {
+                        weight,
+                        location,
+                        function: match source {
+                            Some(source) => format!("{function} ({source})"),
+                            None => function,
+                        },
+                    }
+                }
+                FrameKey::Label(name) => ReportRow {
+                    weight,
+                    location: String::new(),
+                    function: name.clone(),
+                },
+            })
+            .collect(),
+    };
+    rows.sort_by(|a, b| {
+        b.weight
+            .total_cmp(&a.weight)
+            .then_with(|| a.location.cmp(&b.location))
+    });
+    rows.truncate(report_props.limit);
+    rows
+}
+
+fn print_rows(
+    out: &mut impl Write,
+    report_props: &ReportProps,
+    rows: &[ReportRow],
+    total_weight: f64,
+) -> Result<(), std::io::Error> {
+    let location_header = match report_props.aggregation {
+        ReportAggregation::Line => "Source line",
+        _ => "Address",
+    };
+    writeln!(
+        out,
+        "{:>10} {:>7}  {location_header:<40} Function",
+        "Samples", "%"
+    )?;
+    for row in rows {
+        writeln!(
+            out,
+            "{:>10} {:>6.2}%  {:<40} {}",
+            row.weight,
+            percentage(row.weight, total_weight),
+            row.location,
+            row.function
+        )?;
+    }
+    Ok(())
+}
+
+fn print_disassembly(
+    out: &mut impl Write,
+    runtime: &tokio::runtime::Runtime,
+    symbolicator: &ReportSymbolicator,
+    profile: &ReportProfile,
+    report_props: &ReportProps,
+    leaf_weights: &HashMap<FrameKey, f64>,
+    resolved: &HashMap<(usize, u32), ResolvedAddress>,
+) -> Result<(), std::io::Error> {
+    let mut hot_addresses: Vec<((usize, u32), f64)> = leaf_weights
+        .iter()
+        .filter_map(|(key, &weight)| match key {
+            FrameKey::Address(lib_index, address) => Some(((*lib_index, *address), weight)),
+            FrameKey::Label(_) => None,
+        })
+        .collect();
+    hot_addresses.sort_by(|a, b| b.1.total_cmp(&a.1));
+    hot_addresses.truncate(report_props.limit);
+
+    for ((lib_index, address), _weight) in hot_addresses {
+        let Some(info) = resolved.get(&(lib_index, address)) else {
+            continue;
+        };
+        let Some(lib) = profile.libs.get(lib_index) else {
+            continue;
+        };
+        let (Some(start_address), Some(size)) = (info.symbol_address, info.symbol_size) else {
+            continue;
+        };
+        let size = size.min(MAX_DISASSEMBLY_SIZE);
+        let Some(instructions) =
+            runtime.block_on(symbolicator.disassemble(lib, start_address, size))
+        else {
+            continue;
+        };
+        let Some(hot_index) = instructions.iter().position(|i| i.address == address) else {
+            continue;
+        };
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{}!{address:#x} in {}:",
+            lib.name,
+            info.function.as_deref().unwrap_or("<unknown>")
+        )?;
+        let start = hot_index.saturating_sub(DISASSEMBLY_CONTEXT_INSTRUCTIONS);
+        let end = (hot_index + DISASSEMBLY_CONTEXT_INSTRUCTIONS + 1).min(instructions.len());
+        for instruction in &instructions[start..end] {
+            let weight = leaf_weights
+                .get(&FrameKey::Address(lib_index, instruction.address))
+                .map(|weight| weight.to_string())
+                .unwrap_or_default();
+            let marker = if instruction.address == address {
+                "->"
+            } else {
+                "  "
+            };
+            writeln!(
+                out,
+                "{weight:>10} {marker} {:#10x}  {}",
+                instruction.address, instruction.text
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn file_line(file: &Option<String>, line: Option<u32>) -> Option<String> {
+    match (file, line) {
+        (Some(file), Some(line)) => Some(format!("{file}:{line}")),
+        (Some(file), None) => Some(file.clone()),
+        _ => None,
+    }
+}
+
+fn percentage(weight: f64, total_weight: f64) -> f64 {
+    if total_weight == 0.0 {
+        0.0
+    } else {
+        weight / total_weight * 100.0
+    }
+}
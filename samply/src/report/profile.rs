@@ -0,0 +1,563 @@
+//! A minimal reader for processed profile JSON files, as written by samply and
+//! the Firefox profiler, for the `samply report` command.
+//!
+//! Only the parts that are needed for aggregating samples are read: the libs,
+//! each thread's samples, stack table, frame table, func table and resource
+//! table, the per-CPU tracks and core types for `--cpu` and `--core-type`, and
+//! the interval markers and size reductions for `samply summarize`.
+
+use std::path::Path;
+
+use serde_derive::Deserialize;
+
+use crate::shared::cpu_topology::{format_cpu_list, parse_cpu_list, CORE_TYPES_SECTION_LABEL};
+use crate::shared::prop_types::CpuFilter;
+use crate::shared::save_profile::load_profile_json;
+use crate::shared::size_budget::REDUCTIONS_SECTION_LABEL;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ProfileJson {
+    #[serde(default)]
+    meta: MetaJson,
+    #[serde(default)]
+    libs: Vec<LibJson>,
+    #[serde(default)]
+    threads: Vec<ThreadJson>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct MetaJson {
+    #[serde(default)]
+    start_time: f64,
+    #[serde(default)]
+    interval: f64,
+    #[serde(default)]
+    extra: Vec<MetaExtraSectionJson>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MetaExtraSectionJson {
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    entries: Vec<MetaExtraEntryJson>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MetaExtraEntryJson {
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LibJson {
+    name: Option<String>,
+    debug_name: Option<String>,
+    breakpad_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ThreadJson {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    tid: Option<serde_json::Value>,
+    #[serde(default)]
+    process_name: Option<String>,
+    samples: SamplesJson,
+    stack_table: StackTableJson,
+    frame_table: FrameTableJson,
+    func_table: FuncTableJson,
+    resource_table: ResourceTableJson,
+    string_array: Vec<String>,
+    #[serde(default)]
+    markers: Option<MarkersJson>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SamplesJson {
+    stack: Vec<Option<usize>>,
+    #[serde(default)]
+    weight: Option<Vec<f64>>,
+    #[serde(default)]
+    time: Option<Vec<f64>>,
+    #[serde(default)]
+    time_deltas: Option<Vec<f64>>,
+    #[serde(default, rename = "threadCPUDelta")]
+    thread_cpu_delta: Option<Vec<Option<f64>>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct MarkersJson {
+    name: Vec<usize>,
+    start_time: Vec<Option<f64>>,
+    end_time: Vec<Option<f64>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct StackTableJson {
+    prefix: Vec<Option<usize>>,
+    frame: Vec<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FrameTableJson {
+    address: Vec<i64>,
+    func: Vec<usize>,
+    #[serde(default)]
+    line: Vec<Option<u32>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FuncTableJson {
+    name: Vec<usize>,
+    resource: Vec<i64>,
+    #[serde(default)]
+    file_name: Vec<Option<usize>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ResourceTableJson {
+    lib: Vec<Option<usize>>,
+}
+
+/// A library referenced by the profile.
+#[derive(Debug, Clone)]
+pub struct ReportLib {
+    pub name: String,
+    pub debug_name: Option<String>,
+    pub breakpad_id: Option<String>,
+}
+
+/// A frame from a thread's frame table, with its strings resolved.
+#[derive(Debug, Clone)]
+pub struct ReportFrame {
+    /// The index of the library in [`ReportProfile::libs`], for native frames.
+    pub lib_index: Option<usize>,
+    /// The library-relative address, for native frames.
+    pub address: Option<u32>,
+    /// The function name. For unsymbolicated profiles, this is usually a hex address.
+    pub func_name: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+/// A marker with a duration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportMarker {
+    pub name: String,
+    /// The start time, in milliseconds relative to the profile's start time.
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportThread {
+    pub name: Option<String>,
+    /// The thread ID, as it appears in the profile.
+    pub tid: Option<String>,
+    /// For the per-CPU tracks of profiles which were recorded with
+    /// `--per-cpu-threads`, the CPU of the track.
+    pub cpu: Option<u32>,
+    pub frames: Vec<ReportFrame>,
+    /// (prefix, frame index) for each stack.
+    pub stacks: Vec<(Option<usize>, usize)>,
+    /// (stack index, weight) for each sample.
+    pub samples: Vec<(Option<usize>, f64)>,
+    /// Whether each sample was taken while the thread wasn't running, i.e.
+    /// had no CPU time since the previous sample. Empty if the profile has no
+    /// CPU usage information.
+    pub off_cpu: Vec<bool>,
+    /// The interval markers, i.e. those with a start and an end time.
+    pub markers: Vec<ReportMarker>,
+    /// The time of each sample, in milliseconds relative to the profile's start time.
+    pub sample_times: Vec<f64>,
+}
+
+impl ReportThread {
+    /// Returns the frames of the given stack, starting with the leaf frame.
+    pub fn stack_frames(&self, stack_index: usize) -> Vec<&ReportFrame> {
+        let mut frames = Vec::new();
+        let mut stack = Some(stack_index);
+        while let Some(stack_index) = stack {
+            let Some(&(prefix, frame_index)) = self.stacks.get(stack_index) else {
+                break;
+            };
+            if let Some(frame) = self.frames.get(frame_index) {
+                frames.push(frame);
+            }
+            stack = prefix;
+        }
+        frames
+    }
+
+    /// Returns the leaf frame of the given stack, i.e. the frame whose code was running.
+    #[cfg(test)]
+    pub fn leaf_frame(&self, stack_index: usize) -> Option<&ReportFrame> {
+        let &(_prefix, frame_index) = self.stacks.get(stack_index)?;
+        self.frames.get(frame_index)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportProfile {
+    /// The start time of the profile, in milliseconds since the Unix epoch.
+    pub start_time: f64,
+    /// The sampling interval, in milliseconds.
+    pub interval: f64,
+    pub libs: Vec<ReportLib>,
+    pub threads: Vec<ReportThread>,
+    /// The reductions which were applied to fit the profile into a maximum size.
+    pub size_reductions: Vec<String>,
+    /// The CPUs of each core type, e.g. ("efficiency", [8, 9, 10, 11]), if the
+    /// profile was recorded on a CPU with different core types.
+    pub core_types: Vec<(String, Vec<u32>)>,
+}
+
+impl ReportProfile {
+    /// Reads a processed profile from a .json or .json.gz file, in any format
+    /// version which [`upgrade_profile`](crate::shared::profile_versioning::upgrade_profile)
+    /// understands.
+    pub fn from_file(path: &Path) -> Result<Self, std::io::Error> {
+        let profile: ProfileJson = serde_json::from_value(load_profile_json(path)?)?;
+        Ok(Self::from_json(profile))
+    }
+
+    fn from_json(profile: ProfileJson) -> Self {
+        let libs = profile
+            .libs
+            .into_iter()
+            .map(|lib| ReportLib {
+                name: lib
+                    .name
+                    .or_else(|| lib.debug_name.clone())
+                    .unwrap_or_default(),
+                debug_name: lib.debug_name,
+                breakpad_id: lib.breakpad_id,
+            })
+            .collect();
+        let threads = profile.threads.into_iter().map(convert_thread).collect();
+        let size_reductions = profile
+            .meta
+            .extra
+            .iter()
+            .filter(|section| section.label == REDUCTIONS_SECTION_LABEL)
+            .flat_map(|section| &section.entries)
+            .filter_map(|entry| Some(entry.value.as_str()?.to_string()))
+            .collect();
+        let core_types = profile
+            .meta
+            .extra
+            .iter()
+            .filter(|section| section.label == CORE_TYPES_SECTION_LABEL)
+            .flat_map(|section| &section.entries)
+            .filter_map(|entry| {
+                let cpus = parse_cpu_list(entry.value.as_str()?).ok()?;
+                Some((entry.label.clone(), cpus))
+            })
+            .collect();
+        ReportProfile {
+            start_time: profile.meta.start_time,
+            interval: profile.meta.interval,
+            libs,
+            threads,
+            size_reductions,
+            core_types,
+        }
+    }
+
+    /// Only keeps the per-CPU tracks of the CPUs which `filter` selects.
+    pub fn retain_cpu_tracks(&mut self, filter: &CpuFilter) -> Result<(), String> {
+        let available_cpus: Vec<u32> = self
+            .threads
+            .iter()
+            .filter_map(|thread| thread.cpu)
+            .collect();
+        if available_cpus.is_empty() {
+            return Err(
+                "The profile has no per-CPU tracks. Record it with --per-cpu-threads to filter by CPU."
+                    .to_string(),
+            );
+        }
+        let mut cpus = filter.cpus.clone();
+        if let Some(core_type) = filter.core_type {
+            let Some((_, core_type_cpus)) = self
+                .core_types
+                .iter()
+                .find(|(name, _)| name == core_type.name())
+            else {
+                return Err(format!(
+                    "The profile doesn't say which CPUs are {} cores. Core types are only recorded on Linux, on CPUs with more than one core type.",
+                    core_type.name()
+                ));
+            };
+            cpus = Some(match cpus {
+                Some(cpus) => cpus
+                    .into_iter()
+                    .filter(|cpu| core_type_cpus.contains(cpu))
+                    .collect(),
+                None => core_type_cpus.clone(),
+            });
+        }
+        if let Some(cpus) = cpus {
+            if !cpus.iter().any(|cpu| available_cpus.contains(cpu)) {
+                let mut available_cpus = available_cpus;
+                available_cpus.sort_unstable();
+                return Err(format!(
+                    "None of the selected CPUs have a track in the profile, which has tracks for CPUs {}.",
+                    format_cpu_list(&available_cpus)
+                ));
+            }
+            self.threads
+                .retain(|thread| thread.cpu.is_some_and(|cpu| cpus.contains(&cpu)));
+        }
+        Ok(())
+    }
+
+    /// Returns the time from the start of the profile to its last sample, in milliseconds.
+    pub fn duration(&self) -> f64 {
+        self.threads
+            .iter()
+            .filter_map(|thread| thread.sample_times.last().copied())
+            .fold(0.0, f64::max)
+    }
+}
+
+fn convert_thread(thread: ThreadJson) -> ReportThread {
+    let ThreadJson {
+        name,
+        tid,
+        process_name,
+        samples,
+        stack_table,
+        frame_table,
+        func_table,
+        resource_table,
+        string_array,
+        markers,
+    } = thread;
+    let string = |index: usize| string_array.get(index).cloned().unwrap_or_default();
+
+    let frames = frame_table
+        .func
+        .iter()
+        .enumerate()
+        .map(|(frame_index, &func)| {
+            let lib_index = usize::try_from(*func_table.resource.get(func).unwrap_or(&-1))
+                .ok()
+                .and_then(|resource| *resource_table.lib.get(resource)?);
+            let address = frame_table
+                .address
+                .get(frame_index)
+                .and_then(|address| u32::try_from(*address).ok());
+            ReportFrame {
+                lib_index,
+                address,
+                func_name: func_table
+                    .name
+                    .get(func)
+                    .map_or_else(String::new, |s| string(*s)),
+                file: func_table
+                    .file_name
+                    .get(func)
+                    .copied()
+                    .flatten()
+                    .map(string),
+                line: frame_table.line.get(frame_index).copied().flatten(),
+            }
+        })
+        .collect();
+
+    let stacks = stack_table
+        .prefix
+        .into_iter()
+        .zip(stack_table.frame)
+        .collect();
+
+    let sample_times = match (samples.time, samples.time_deltas) {
+        (Some(times), _) => times,
+        (None, Some(time_deltas)) => time_deltas
+            .iter()
+            .scan(0.0, |time, delta| {
+                *time += delta;
+                Some(*time)
+            })
+            .collect(),
+        (None, None) => Vec::new(),
+    };
+    let off_cpu = samples
+        .thread_cpu_delta
+        .map(|deltas| deltas.iter().map(|delta| *delta == Some(0.0)).collect())
+        .unwrap_or_default();
+    let weights = samples.weight.unwrap_or_default();
+    let samples = samples
+        .stack
+        .into_iter()
+        .enumerate()
+        .map(|(i, stack)| (stack, weights.get(i).copied().unwrap_or(1.0)))
+        .collect();
+
+    let tid = tid.and_then(|tid| match tid {
+        serde_json::Value::String(tid) => Some(tid),
+        serde_json::Value::Number(tid) => Some(tid.to_string()),
+        _ => None,
+    });
+
+    let markers = match markers {
+        Some(markers) => markers
+            .name
+            .iter()
+            .zip(markers.start_time.iter().zip(&markers.end_time))
+            .filter_map(|(&name, (start, end))| {
+                Some(ReportMarker {
+                    name: string(name),
+                    start: (*start)?,
+                    end: (*end)?,
+                })
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    // The per-CPU tracks are the threads "CPU 0", "CPU 1" and so on of the
+    // "CPU" process.
+    let cpu = match (process_name.as_deref(), name.as_deref()) {
+        (Some("CPU"), Some(name)) => name.strip_prefix("CPU ").and_then(|cpu| cpu.parse().ok()),
+        _ => None,
+    };
+
+    ReportThread {
+        name,
+        tid,
+        cpu,
+        frames,
+        stacks,
+        samples,
+        off_cpu,
+        markers,
+        sample_times,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shared::cpu_topology::CoreType;
+
+    #[test]
+    fn parse_minimal_profile() {
+        let json = r#"{
+            "libs": [{ "name": "libfoo.so", "debugName": "libfoo.so", "breakpadId": "0" }],
+            "threads": [{
+                "name": "main",
+                "tid": 42,
+                "samples": {
+                    "stack": [1, 0, null], "weight": null, "timeDeltas": [1.0, 1.5, 2.0],
+                    "threadCPUDelta": [null, 0, 3]
+                },
+                "stackTable": { "prefix": [null, 0], "frame": [0, 1] },
+                "frameTable": { "address": [16, -1], "func": [0, 1], "line": [null, 12] },
+                "funcTable": { "name": [0, 1], "resource": [0, -1], "fileName": [null, 2] },
+                "resourceTable": { "lib": [0] },
+                "stringArray": ["0x10", "label", "file.rs"],
+                "markers": {
+                    "name": [1, 1], "startTime": [0.5, 2.0], "endTime": [1.5, null],
+                    "phase": [1, 0], "category": [0, 0], "data": [null, null]
+                }
+            }]
+        }"#;
+        let profile = ReportProfile::from_json(serde_json::from_str(json).unwrap());
+        assert_eq!(profile.libs[0].name, "libfoo.so");
+        assert_eq!(profile.duration(), 4.5);
+        let thread = &profile.threads[0];
+        assert_eq!(thread.name.as_deref(), Some("main"));
+        assert_eq!(thread.tid.as_deref(), Some("42"));
+        assert_eq!(thread.samples.len(), 3);
+        assert_eq!(thread.samples[0], (Some(1), 1.0));
+        assert_eq!(thread.sample_times, vec![1.0, 2.5, 4.5]);
+        assert_eq!(thread.off_cpu, vec![false, true, false]);
+        assert_eq!(
+            thread.markers,
+            vec![ReportMarker {
+                name: "label".to_string(),
+                start: 0.5,
+                end: 1.5
+            }]
+        );
+        assert_eq!(thread.leaf_frame(1).unwrap().func_name, "label");
+        let stack: Vec<&str> = thread
+            .stack_frames(1)
+            .iter()
+            .map(|frame| frame.func_name.as_str())
+            .collect();
+        assert_eq!(stack, vec!["label", "0x10"]);
+        assert_eq!(thread.frames[0].lib_index, Some(0));
+        assert_eq!(thread.frames[0].address, Some(16));
+        assert_eq!(thread.frames[1].lib_index, None);
+        assert_eq!(thread.frames[1].address, None);
+        assert_eq!(thread.frames[1].file.as_deref(), Some("file.rs"));
+        assert_eq!(thread.frames[1].line, Some(12));
+    }
+
+    #[test]
+    fn cpu_tracks_and_core_types() {
+        let thread = |process_name: &str, name: &str| {
+            serde_json::json!({
+                "name": name, "processName": process_name,
+                "samples": { "stack": [] },
+                "stackTable": { "prefix": [], "frame": [] },
+                "frameTable": { "address": [], "func": [], "line": [] },
+                "funcTable": { "name": [], "resource": [], "fileName": [] },
+                "resourceTable": { "lib": [] },
+                "stringArray": []
+            })
+        };
+        let json = serde_json::json!({
+            "meta": { "extra": [{ "label": "CPU core types", "entries": [
+                { "label": "performance", "format": "string", "value": "0-1" },
+                { "label": "efficiency", "format": "string", "value": "2-3" }
+            ]}]},
+            "threads": [
+                thread("ls", "ls"), thread("CPU", "CPU 0"), thread("CPU", "CPU 1"),
+                thread("CPU", "CPU 2"), thread("CPU", "CPU 3")
+            ]
+        });
+        let profile = ReportProfile::from_json(serde_json::from_value(json).unwrap());
+        let cpus: Vec<Option<u32>> = profile.threads.iter().map(|thread| thread.cpu).collect();
+        assert_eq!(cpus, vec![None, Some(0), Some(1), Some(2), Some(3)]);
+
+        let retained_cpus = |cpus: Option<Vec<u32>>, core_type: Option<CoreType>| {
+            let mut profile = profile.clone();
+            profile.retain_cpu_tracks(&CpuFilter { cpus, core_type })?;
+            Ok::<_, String>(
+                profile
+                    .threads
+                    .iter()
+                    .filter_map(|thread| thread.cpu)
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(retained_cpus(Some(vec![1, 3]), None), Ok(vec![1, 3]));
+        assert_eq!(
+            retained_cpus(None, Some(CoreType::Efficiency)),
+            Ok(vec![2, 3])
+        );
+        assert_eq!(
+            retained_cpus(Some(vec![1, 2]), Some(CoreType::Performance)),
+            Ok(vec![1])
+        );
+        assert!(retained_cpus(Some(vec![7]), None).is_err());
+    }
+}
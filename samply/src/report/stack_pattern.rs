@@ -0,0 +1,65 @@
+use regex::Regex;
+
+/// Separates the function names in the text which patterns are matched against.
+const FRAME_SEPARATOR: &str = ";";
+
+/// A pattern for `samply grep`, which is matched against the function names of a
+/// stack, joined by `;`. By default, the stack is listed from the innermost
+/// function to the root, like in a backtrace, so that `mutex_lock.*my_crate::`
+/// matches stacks where a `mutex_lock` function was called, directly or
+/// indirectly, from a function in `my_crate`.
+#[derive(Debug, Clone)]
+pub struct StackPattern {
+    pub regex: Regex,
+    /// List the stack from the root to the innermost function instead.
+    pub root_first: bool,
+}
+
+impl StackPattern {
+    /// Returns the text which the pattern is matched against, for a stack which
+    /// is ordered from the root to the leaf.
+    pub fn stack_text<'a>(&self, functions: impl DoubleEndedIterator<Item = &'a str>) -> String {
+        let functions: Vec<&str> = if self.root_first {
+            functions.collect()
+        } else {
+            functions.rev().collect()
+        };
+        functions.join(FRAME_SEPARATOR)
+    }
+
+    pub fn is_match<'a>(&self, functions: impl DoubleEndedIterator<Item = &'a str>) -> bool {
+        self.regex.is_match(&self.stack_text(functions))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frame_sequences() {
+        let stack = [
+            "main",
+            "my_crate::worker",
+            "std::sync::Mutex::lock",
+            "mutex_lock",
+        ];
+        let pattern = StackPattern {
+            regex: Regex::new("mutex_lock.*my_crate::").unwrap(),
+            root_first: false,
+        };
+        assert_eq!(
+            pattern.stack_text(stack.into_iter()),
+            "mutex_lock;std::sync::Mutex::lock;my_crate::worker;main"
+        );
+        assert!(pattern.is_match(stack.into_iter()));
+        assert!(!pattern.is_match(["my_crate::worker", "mutex_lock"].into_iter().rev()));
+
+        let pattern = StackPattern {
+            regex: Regex::new("^main;my_crate::[^;]*;std::").unwrap(),
+            root_first: true,
+        };
+        assert!(pattern.is_match(stack.into_iter()));
+        assert!(!pattern.is_match(["main", "std::thread::spawn"].into_iter()));
+    }
+}
@@ -0,0 +1,317 @@
+//! The `samply summarize` command, which prints a short overview of a saved
+//! profile as Markdown, for pasting into bug reports.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+use super::profile::{ReportProfile, ReportThread};
+use super::transforms::StackTransforms;
+use super::{entry_name, percentage, StackEntry, SymbolicatedProfile};
+use crate::shared::prop_types::{SummarizeProps, SymbolProps};
+
+pub fn run_summarize(
+    profile_path: &Path,
+    summarize_props: SummarizeProps,
+    symbol_props: SymbolProps,
+) -> Result<(), std::io::Error> {
+    let profile = SymbolicatedProfile::load(
+        profile_path,
+        symbol_props,
+        &StackTransforms::default(),
+        None,
+    )?;
+    let mut out = std::io::stdout().lock();
+    write_summary(&mut out, &profile, &summarize_props)
+}
+
+fn write_summary(
+    out: &mut impl Write,
+    profile: &SymbolicatedProfile,
+    props: &SummarizeProps,
+) -> Result<(), std::io::Error> {
+    let report_profile = &profile.profile;
+    let limit = props.limit;
+
+    writeln!(out, "## Profile summary")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "- Duration: {:.2} s, sampling interval: {} ms",
+        report_profile.duration() / 1000.0,
+        report_profile.interval
+    )?;
+    writeln!(
+        out,
+        "- {} samples in {} threads",
+        profile.total_weight,
+        report_profile.threads.len()
+    )?;
+
+    writeln!(out)?;
+    writeln!(out, "### Top functions by self time")?;
+    let thread_weights = weight_per_thread(profile);
+    let mut threads: Vec<(usize, f64)> = thread_weights
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, weight)| *weight > 0.0)
+        .collect();
+    threads.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    for &(thread_index, thread_weight) in threads.iter().take(limit) {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{}, {thread_weight} samples ({:.2}%):",
+            thread_description(&report_profile.threads[thread_index]),
+            percentage(thread_weight, profile.total_weight)
+        )?;
+        let stacks = profile
+            .stacks
+            .iter()
+            .zip(&profile.stack_threads)
+            .filter(|(_, &index)| index == thread_index)
+            .map(|(stack, _)| stack);
+        for (function, weight) in top_self_functions(stacks, report_profile, limit) {
+            writeln!(
+                out,
+                "- {:.2}% ({weight}) {function}",
+                percentage(weight, thread_weight)
+            )?;
+        }
+    }
+    if threads.len() > limit {
+        writeln!(out)?;
+        writeln!(out, "({} more threads with samples)", threads.len() - limit)?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "### Biggest off-CPU waits")?;
+    writeln!(out)?;
+    if report_profile
+        .threads
+        .iter()
+        .all(|thread| thread.off_cpu.is_empty())
+    {
+        writeln!(out, "The profile has no CPU usage information.")?;
+    } else {
+        let waits = biggest_off_cpu_waits(profile, limit);
+        if waits.is_empty() {
+            writeln!(out, "No samples were taken while threads were waiting.")?;
+        }
+        for ((thread_index, function), weight) in waits {
+            writeln!(
+                out,
+                "- {weight} samples ({:.2}%) in {function}, {}",
+                percentage(weight, profile.total_weight),
+                thread_description(&report_profile.threads[thread_index])
+            )?;
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(out, "### Longest markers")?;
+    writeln!(out)?;
+    let markers = longest_markers(&report_profile.threads, limit);
+    if markers.is_empty() {
+        writeln!(out, "The profile has no markers with a duration.")?;
+    }
+    for (thread, marker) in markers {
+        writeln!(
+            out,
+            "- {:.2} ms: {}, {}, at {:.2} ms",
+            marker.end - marker.start,
+            marker.name,
+            thread_description(thread),
+            marker.start
+        )?;
+    }
+
+    let warnings = warnings(report_profile);
+    if !warnings.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "### Warnings")?;
+        writeln!(out)?;
+        for warning in warnings {
+            writeln!(out, "- {warning}")?;
+        }
+    }
+    Ok(())
+}
+
+fn thread_description(thread: &ReportThread) -> String {
+    let name = thread.name.as_deref().unwrap_or("<unnamed>");
+    match &thread.tid {
+        Some(tid) => format!("thread \"{name}\" (tid {tid})"),
+        None => format!("thread \"{name}\""),
+    }
+}
+
+fn weight_per_thread(profile: &SymbolicatedProfile) -> Vec<f64> {
+    let mut weights = vec![0.0; profile.profile.threads.len()];
+    for ((_, weight), &thread_index) in profile.stacks.iter().zip(&profile.stack_threads) {
+        weights[thread_index] += weight;
+    }
+    weights
+}
+
+/// Returns the functions with the highest self time in the given stacks.
+fn top_self_functions<'a>(
+    stacks: impl Iterator<Item = &'a (Vec<StackEntry>, f64)>,
+    profile: &ReportProfile,
+    limit: usize,
+) -> Vec<(Cow<'a, str>, f64)> {
+    let mut self_weights: HashMap<Cow<str>, f64> = HashMap::new();
+    for (entries, weight) in stacks {
+        if let Some(leaf) = entries.last() {
+            *self_weights
+                .entry(entry_name(leaf, profile, false))
+                .or_default() += weight;
+        }
+    }
+    sorted_and_truncated(self_weights, limit)
+}
+
+/// Returns the leaf functions with the most samples while their thread wasn't
+/// running, with the index of the thread.
+fn biggest_off_cpu_waits(
+    profile: &SymbolicatedProfile,
+    limit: usize,
+) -> Vec<((usize, Cow<'_, str>), f64)> {
+    let mut waits: HashMap<(usize, Cow<str>), f64> = HashMap::new();
+    for (((entries, _), &thread_index), &off_cpu_weight) in profile
+        .stacks
+        .iter()
+        .zip(&profile.stack_threads)
+        .zip(&profile.stack_off_cpu_weights)
+    {
+        let Some(leaf) = entries.last() else {
+            continue;
+        };
+        if off_cpu_weight > 0.0 {
+            let function = entry_name(leaf, &profile.profile, false);
+            *waits.entry((thread_index, function)).or_default() += off_cpu_weight;
+        }
+    }
+    sorted_and_truncated(waits, limit)
+}
+
+fn sorted_and_truncated<K: Ord>(weights: HashMap<K, f64>, limit: usize) -> Vec<(K, f64)> {
+    let mut rows: Vec<(K, f64)> = weights.into_iter().collect();
+    rows.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    rows.truncate(limit);
+    rows
+}
+
+/// Returns the markers with the longest durations, across all threads.
+fn longest_markers(
+    threads: &[ReportThread],
+    limit: usize,
+) -> Vec<(&ReportThread, &super::profile::ReportMarker)> {
+    let mut markers: Vec<_> = threads
+        .iter()
+        .flat_map(|thread| thread.markers.iter().map(move |marker| (thread, marker)))
+        .collect();
+    markers.sort_by(|(_, a), (_, b)| {
+        (b.end - b.start)
+            .total_cmp(&(a.end - a.start))
+            .then_with(|| a.start.total_cmp(&b.start))
+    });
+    markers.truncate(limit);
+    markers
+}
+
+/// Returns descriptions of anything which makes the profile less accurate.
+fn warnings(profile: &ReportProfile) -> Vec<String> {
+    let mut warnings: Vec<String> = profile
+        .size_reductions
+        .iter()
+        .map(|reduction| format!("The profile was reduced in size: {reduction}"))
+        .collect();
+    let multi_interval_samples = profile
+        .threads
+        .iter()
+        .flat_map(|thread| &thread.samples)
+        .filter(|(_, weight)| *weight > 1.0)
+        .count();
+    if multi_interval_samples > 0 {
+        warnings.push(format!(
+            "{multi_interval_samples} samples stand for more than one sampling interval, e.g. because samples were throttled or lost"
+        ));
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::profile::ReportMarker;
+    use super::super::FrameKey;
+    use super::*;
+
+    fn thread(name: &str, markers: &[(&str, f64, f64)], weights: &[f64]) -> ReportThread {
+        ReportThread {
+            name: Some(name.to_string()),
+            tid: None,
+            cpu: None,
+            frames: Vec::new(),
+            stacks: Vec::new(),
+            samples: weights.iter().map(|weight| (Some(0), *weight)).collect(),
+            off_cpu: Vec::new(),
+            markers: markers
+                .iter()
+                .map(|(name, start, end)| ReportMarker {
+                    name: name.to_string(),
+                    start: *start,
+                    end: *end,
+                })
+                .collect(),
+            sample_times: Vec::new(),
+        }
+    }
+
+    fn stack(functions: &[&str], weight: f64) -> (Vec<StackEntry>, f64) {
+        let entries = functions
+            .iter()
+            .map(|function| StackEntry {
+                function: function.to_string(),
+                frame: FrameKey::Label(function.to_string()),
+            })
+            .collect();
+        (entries, weight)
+    }
+
+    #[test]
+    fn self_functions_and_markers() {
+        let profile = ReportProfile {
+            start_time: 0.0,
+            interval: 1.0,
+            libs: Vec::new(),
+            threads: vec![
+                thread("main", &[("Load", 10.0, 30.0), ("Paint", 5.0, 6.0)], &[1.0]),
+                thread("worker", &[("GC", 0.0, 50.0)], &[1.0, 3.0]),
+            ],
+            size_reductions: vec!["Kept only one in 2 samples".to_string()],
+            core_types: Vec::new(),
+        };
+
+        let stacks = [
+            stack(&["main", "parse"], 3.0),
+            stack(&["main", "layout"], 5.0),
+            stack(&["main", "run", "parse"], 2.0),
+        ];
+        let functions = top_self_functions(stacks.iter(), &profile, 2);
+        assert_eq!(
+            functions,
+            vec![(Cow::from("layout"), 5.0), (Cow::from("parse"), 5.0)]
+        );
+
+        let markers: Vec<&str> = longest_markers(&profile.threads, 2)
+            .into_iter()
+            .map(|(_, marker)| marker.name.as_str())
+            .collect();
+        assert_eq!(markers, vec!["GC", "Load"]);
+
+        assert_eq!(warnings(&profile).len(), 2);
+    }
+}
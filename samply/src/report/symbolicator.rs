@@ -0,0 +1,176 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::path::Path;
+
+use debugid::DebugId;
+use serde_derive::Deserialize;
+use wholesym::{LookupAddress, SymbolManager};
+
+use super::profile::ReportLib;
+use crate::profile_json_preparse::parse_libinfo_map_from_profile_file;
+use crate::shared::prop_types::SymbolProps;
+use crate::symbols::create_symbol_manager_and_quota_manager;
+
+/// The symbol information for a single library-relative address.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedAddress {
+    /// The name of the innermost function at this address, taking inlining into account.
+    pub function: Option<String>,
+    /// The names of all functions at this address, starting with the innermost
+    /// inlined function and ending with the outer function.
+    pub inline_functions: Vec<String>,
+    /// The start address and size of the outer function's symbol.
+    pub symbol_address: Option<u32>,
+    pub symbol_size: Option<u32>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+/// A disassembled instruction, at a library-relative address.
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub address: u32,
+    pub text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AsmResponse {
+    #[serde(deserialize_with = "deserialize_prefixed_hex")]
+    start_address: u32,
+    instructions: Vec<(u32, String)>,
+}
+
+fn deserialize_prefixed_hex<'de, D: serde::Deserializer<'de>>(d: D) -> Result<u32, D::Error> {
+    let s: String = serde::Deserialize::deserialize(d)?;
+    u32::from_str_radix(s.trim_start_matches("0x"), 16).map_err(serde::de::Error::custom)
+}
+
+/// Looks up symbols for the addresses in a profile, using the same symbol
+/// sources as the profiler UI would when loading this profile with samply.
+pub struct ReportSymbolicator {
+    symbol_manager: SymbolManager,
+    /// For the libraries which shouldn't be symbolicated.
+    symbol_props: SymbolProps,
+}
+
+impl ReportSymbolicator {
+    pub fn new(profile_path: &Path, symbol_props: SymbolProps) -> Self {
+        let (mut symbol_manager, _quota_manager) =
+            create_symbol_manager_and_quota_manager(symbol_props.clone(), false);
+        if let Ok(profile_file) = File::open(profile_path) {
+            if let Ok(libinfo_map) = parse_libinfo_map_from_profile_file(profile_file, profile_path)
+            {
+                for lib_info in libinfo_map.into_values() {
+                    symbol_manager.add_known_library(lib_info);
+                }
+            }
+        }
+        Self {
+            symbol_manager,
+            symbol_props,
+        }
+    }
+
+    /// Resolves each (lib index, address) pair. Addresses in libraries without
+    /// symbols are missing from the returned map.
+    pub async fn resolve_addresses(
+        &self,
+        libs: &[ReportLib],
+        addresses: &BTreeSet<(usize, u32)>,
+    ) -> HashMap<(usize, u32), ResolvedAddress> {
+        let mut result = HashMap::new();
+        let mut current_lib = None;
+        let mut symbol_map = None;
+        for &(lib_index, address) in addresses {
+            if current_lib != Some(lib_index) {
+                current_lib = Some(lib_index);
+                let lib = libs.get(lib_index).filter(|lib| {
+                    self.symbol_props
+                        .should_symbolicate(Some(&lib.name), lib.debug_name.as_deref())
+                });
+                symbol_map = match lib.and_then(lib_identifier) {
+                    Some((debug_name, debug_id)) => self
+                        .symbol_manager
+                        .load_symbol_map(debug_name, debug_id)
+                        .await
+                        .ok(),
+                    None => None,
+                };
+            }
+            let Some(symbol_map) = &symbol_map else {
+                continue;
+            };
+            let Some(info) = symbol_map.lookup(LookupAddress::Relative(address)).await else {
+                continue;
+            };
+            let innermost_frame = info.frames.as_ref().and_then(|frames| frames.first());
+            let mut inline_functions: Vec<String> = info
+                .frames
+                .iter()
+                .flatten()
+                .filter_map(|frame| frame.function.clone())
+                .collect();
+            if inline_functions.is_empty() {
+                inline_functions.push(info.symbol.name.clone());
+            }
+            let resolved = ResolvedAddress {
+                function: innermost_frame
+                    .and_then(|frame| frame.function.clone())
+                    .or(Some(info.symbol.name)),
+                symbol_address: Some(info.symbol.address),
+                symbol_size: info.symbol.size,
+                file: innermost_frame
+                    .and_then(|frame| frame.file_path.as_ref())
+                    .map(
+                        |path| match self.symbol_manager.remapped_path(path.raw_path()) {
+                            Some(local_path) => local_path.display().to_string(),
+                            None => path.display_path(),
+                        },
+                    ),
+                line: innermost_frame.and_then(|frame| frame.line_number),
+                inline_functions,
+            };
+            result.insert((lib_index, address), resolved);
+        }
+        result
+    }
+
+    /// Disassembles the `size` bytes of machine code starting at `start_address`.
+    pub async fn disassemble(
+        &self,
+        lib: &ReportLib,
+        start_address: u32,
+        size: u32,
+    ) -> Option<Vec<Instruction>> {
+        let (debug_name, debug_id) = lib_identifier(lib)?;
+        let request = serde_json::json!({
+            "name": lib.name,
+            "debugName": debug_name,
+            "debugId": debug_id.breakpad().to_string(),
+            "startAddress": format!("{start_address:#x}"),
+            "size": format!("{size:#x}"),
+        });
+        let response = self
+            .symbol_manager
+            .query_json_api("/asm/v1", &request.to_string())
+            .await;
+        let response: AsmResponse = serde_json::from_str(&response).ok()?;
+        Some(
+            response
+                .instructions
+                .into_iter()
+                .map(|(offset, text)| Instruction {
+                    address: response.start_address + offset,
+                    text,
+                })
+                .collect(),
+        )
+    }
+}
+
+fn lib_identifier(lib: &ReportLib) -> Option<(&str, DebugId)> {
+    let debug_name = lib.debug_name.as_deref()?;
+    let debug_id = DebugId::from_breakpad(lib.breakpad_id.as_deref()?).ok()?;
+    Some((debug_name, debug_id))
+}
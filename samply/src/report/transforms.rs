@@ -0,0 +1,80 @@
+use regex::Regex;
+
+/// Call tree transforms for `samply report`, modeled after the transforms in the
+/// Firefox Profiler's call tree.
+#[derive(Debug, Clone, Default)]
+pub struct StackTransforms {
+    /// "Merge function": Remove all frames whose function matches one of these
+    /// regular expressions. Their time is attributed to their caller.
+    pub hide: Vec<Regex>,
+    /// "Focus on function": Drop samples whose stack doesn't contain a function
+    /// matching this regular expression, and remove all frames above the outermost
+    /// matching function from the remaining samples.
+    pub focus: Option<Regex>,
+}
+
+impl StackTransforms {
+    /// Applies the transforms to a stack, which is ordered from the root to the leaf.
+    /// Returns `None` if the sample should be dropped.
+    pub fn apply<T>(
+        &self,
+        mut stack: Vec<T>,
+        function_name: impl Fn(&T) -> &str,
+    ) -> Option<Vec<T>> {
+        if !self.hide.is_empty() {
+            stack.retain(|entry| {
+                let name = function_name(entry);
+                !self.hide.iter().any(|regex| regex.is_match(name))
+            });
+        }
+        if let Some(focus) = &self.focus {
+            let focus_index = stack
+                .iter()
+                .position(|entry| focus.is_match(function_name(entry)))?;
+            stack.drain(..focus_index);
+        }
+        if stack.is_empty() {
+            return None;
+        }
+        Some(stack)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn apply<'a>(transforms: &StackTransforms, stack: &[&'a str]) -> Option<Vec<&'a str>> {
+        transforms.apply(stack.to_vec(), |name| name)
+    }
+
+    #[test]
+    fn hide_and_focus() {
+        let stack = ["main", "run", "alloc::vec::Vec::push", "malloc"];
+
+        let transforms = StackTransforms {
+            hide: vec![Regex::new("^alloc::").unwrap()],
+            focus: None,
+        };
+        assert_eq!(
+            apply(&transforms, &stack),
+            Some(vec!["main", "run", "malloc"])
+        );
+
+        let transforms = StackTransforms {
+            hide: vec![],
+            focus: Some(Regex::new("^run$").unwrap()),
+        };
+        assert_eq!(
+            apply(&transforms, &stack),
+            Some(vec!["run", "alloc::vec::Vec::push", "malloc"])
+        );
+        assert_eq!(apply(&transforms, &["main", "idle"]), None);
+
+        let transforms = StackTransforms {
+            hide: vec![Regex::new(".*").unwrap()],
+            focus: None,
+        };
+        assert_eq!(apply(&transforms, &stack), None);
+    }
+}
@@ -1,14 +1,17 @@
 use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::io::{Read, SeekFrom, Write};
 use std::net::{IpAddr, SocketAddr};
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use futures_util::TryStreamExt;
+use futures_util::future::{BoxFuture, Shared};
+use futures_util::{FutureExt, TryStreamExt};
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, Either, StreamBody};
+use http_body_util::{BodyExt, Either, Full, StreamBody};
 use hyper::body::{Bytes, Frame};
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
@@ -16,6 +19,7 @@ use hyper::{header, Method, Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use rand::RngCore;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::net::TcpListener;
 use tokio_util::io::ReaderStream;
 use wholesym::SymbolManager;
@@ -28,6 +32,15 @@ pub struct ServerProps {
     pub port_selection: PortSelection,
     pub verbose: bool,
     pub open_in_browser: bool,
+    /// The command line to open the profiler URL with, without the URL.
+    pub browser: Option<Vec<String>>,
+    pub print_qr_code: bool,
+    /// Overrides the profiler front-end URL, see `PROFILER_URL`.
+    pub profiler_url: Option<String>,
+    /// A static build of the profiler front-end to serve from the server's root.
+    pub profiler_dir: Option<PathBuf>,
+    /// If non-empty, only these origins get CORS headers.
+    pub allowed_origins: Vec<String>,
 }
 
 const BAD_CHARS: &AsciiSet = &CONTROLS.add(b':').add(b'/');
@@ -50,6 +63,25 @@ impl PortSelection {
     }
 }
 
+/// The profile which the server serves at `/profile.json`.
+#[derive(Clone, Debug)]
+pub enum ServedProfile {
+    /// A profile file, which is read from disk for each request.
+    File(PathBuf),
+    /// A profile which is only kept in memory, e.g. because it was decrypted
+    /// and must not be written to disk unencrypted.
+    InMemory(Bytes),
+}
+
+impl ServedProfile {
+    fn is_gzipped(&self) -> bool {
+        match self {
+            ServedProfile::File(path) => path.extension() == Some(OsStr::new("gz")),
+            ServedProfile::InMemory(data) => data.starts_with(&[0x1f, 0x8b]),
+        }
+    }
+}
+
 pub struct RunningServerInfo {
     pub server_join_handle:
         tokio::task::JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
@@ -58,7 +90,7 @@ pub struct RunningServerInfo {
 }
 
 pub async fn start_server(
-    profile_filename: Option<&Path>,
+    profile: Option<ServedProfile>,
     server_props: ServerProps,
     symbol_manager: SymbolManager,
     stop_signal: ctrl_c::Receiver,
@@ -73,14 +105,25 @@ pub async fn start_server(
     template_values.insert("SERVER_URL", server_origin.clone());
     template_values.insert("PATH_PREFIX", path_prefix.clone());
 
-    let profiler_url = if profile_filename.is_some() {
-        let profile_url = format!("{symbol_server_url}/profile.json");
+    let profiler_override = match &server_props.profiler_dir {
+        Some(_) => Some(server_origin.clone()),
+        None => server_props
+            .profiler_url
+            .clone()
+            .or_else(|| std::env::var("PROFILER_URL").ok()),
+    };
+    let profiler_origin = match &profiler_override {
+        Some(s) => s.trim_end_matches('/'),
+        None => "https://profiler.firefox.com",
+    };
 
-        let env_profiler_override = std::env::var("PROFILER_URL").ok();
-        let profiler_origin = match &env_profiler_override {
-            Some(s) => s.trim_end_matches('/'),
-            None => "https://profiler.firefox.com",
-        };
+    let mut allowed_origins = server_props.allowed_origins;
+    if !allowed_origins.is_empty() {
+        allowed_origins.push(url_origin(profiler_origin).to_string());
+    }
+
+    let profiler_url = if profile.is_some() {
+        let profile_url = format!("{symbol_server_url}/profile.json");
 
         let encoded_profile_url = utf8_percent_encode(&profile_url, BAD_CHARS).to_string();
         let encoded_symbol_server_url =
@@ -100,9 +143,11 @@ pub async fn start_server(
     let server_join_handle = tokio::task::spawn(run_server(
         listener,
         symbol_manager,
-        profile_filename.map(PathBuf::from),
+        profile,
         template_values,
         path_prefix,
+        Arc::new(allowed_origins),
+        server_props.profiler_dir,
         stop_signal,
     ));
 
@@ -113,6 +158,48 @@ pub async fn start_server(
     }
 }
 
+/// Opens the URL with the given browser command, or with the default browser.
+pub fn open_in_browser(url: &str, browser: Option<&[String]>) -> std::io::Result<()> {
+    match browser {
+        Some([program, args @ ..]) => {
+            std::process::Command::new(program)
+                .args(args)
+                .arg(url)
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .spawn()?;
+            Ok(())
+        }
+        _ => opener::open_browser(url).map_err(std::io::Error::other),
+    }
+}
+
+/// Returns true if we're most likely unable to show a browser window to the
+/// user: when connected via SSH, or when there's no display server on Linux.
+pub fn is_headless_session() -> bool {
+    let has_env = |name: &str| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    if has_env("SSH_CONNECTION") || has_env("SSH_TTY") {
+        return true;
+    }
+    cfg!(any(target_os = "linux", target_os = "android"))
+        && !has_env("DISPLAY")
+        && !has_env("WAYLAND_DISPLAY")
+}
+
+/// Renders the URL as a QR code made of Unicode block characters.
+pub fn qr_code_for_url(url: &str) -> Option<String> {
+    use qrcode::render::unicode::Dense1x2;
+
+    let code = qrcode::QrCode::new(url.as_bytes()).ok()?;
+    let rendered = code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build();
+    Some(rendered)
+}
+
 // Returns a base32 string for 24 random bytes.
 fn generate_token() -> String {
     let mut bytes = [0u8; 24];
@@ -186,15 +273,18 @@ const TEMPLATE_WITHOUT_PROFILE: &str = r#"
 </ul>
 "#;
 
+#[allow(clippy::too_many_arguments)]
 async fn run_server(
     listener: TcpListener,
     symbol_manager: SymbolManager,
-    profile_filename: Option<PathBuf>,
+    profile: Option<ServedProfile>,
     template_values: Arc<HashMap<&'static str, String>>,
     path_prefix: String,
+    allowed_origins: Arc<Vec<String>>,
+    profiler_dir: Option<PathBuf>,
     mut stop_signal: ctrl_c::Receiver,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let symbol_manager = Arc::new(symbol_manager);
+    let query_cache = Arc::new(QueryCache::new(symbol_manager));
 
     // We start a loop to continuously accept incoming connections
     loop {
@@ -209,10 +299,12 @@ async fn run_server(
         // `hyper::rt` IO traits.
         let io = TokioIo::new(stream);
 
-        let symbol_manager = symbol_manager.clone();
-        let profile_filename = profile_filename.clone();
+        let query_cache = query_cache.clone();
+        let profile = profile.clone();
         let template_values = template_values.clone();
         let path_prefix = path_prefix.clone();
+        let allowed_origins = allowed_origins.clone();
+        let profiler_dir = profiler_dir.clone();
 
         // Spawn a tokio task to serve multiple connections concurrently
         tokio::task::spawn(async move {
@@ -225,9 +317,11 @@ async fn run_server(
                         symbolication_service(
                             req,
                             template_values.clone(),
-                            symbol_manager.clone(),
-                            profile_filename.clone(),
+                            query_cache.clone(),
+                            profile.clone(),
                             path_prefix.clone(),
+                            allowed_origins.clone(),
+                            profiler_dir.clone(),
                         )
                     }),
                 )
@@ -242,19 +336,24 @@ async fn run_server(
 async fn symbolication_service(
     req: Request<hyper::body::Incoming>,
     template_values: Arc<HashMap<&'static str, String>>,
-    symbol_manager: Arc<SymbolManager>,
-    profile_filename: Option<PathBuf>,
+    query_cache: Arc<QueryCache>,
+    profile: Option<ServedProfile>,
     path_prefix: String,
+    allowed_origins: Arc<Vec<String>>,
+    profiler_dir: Option<PathBuf>,
 ) -> Result<Response<Either<String, BoxBody<Bytes, std::io::Error>>>, hyper::Error> {
-    let has_profile = profile_filename.is_some();
+    let has_profile = profile.is_some();
     let method = req.method();
     let path = req.uri().path();
     let mut response = Response::new(Either::Left(String::new()));
 
     let Some(path_without_prefix) = path.strip_prefix(&path_prefix) else {
         // The secret prefix was not part of the URL. Do not send CORS headers.
-        match (method, path) {
-            (&Method::GET, "/") => {
+        match (method, path, profiler_dir) {
+            (&Method::GET, path, Some(profiler_dir)) => {
+                serve_profiler_file(&profiler_dir, path, &mut response).await;
+            }
+            (&Method::GET, "/", None) => {
                 response.headers_mut().insert(
                     header::CONTENT_TYPE,
                     header::HeaderValue::from_static("text/html"),
@@ -274,15 +373,32 @@ async fn symbolication_service(
     };
 
     // If we get here, then the secret prefix was part of the URL.
-    // This part is open to the public: we allow requests across origins.
+    // This part is open to the public: we allow requests across origins,
+    // unless the user restricted the allowed origins.
     // For background on CORS, see this document:
     // https://w3c.github.io/webappsec-cors-for-developers/#cors
-    response.headers_mut().insert(
-        header::ACCESS_CONTROL_ALLOW_ORIGIN,
-        header::HeaderValue::from_static("*"),
-    );
+    if allowed_origins.is_empty() {
+        response.headers_mut().insert(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            header::HeaderValue::from_static("*"),
+        );
+    } else {
+        response
+            .headers_mut()
+            .insert(header::VARY, header::HeaderValue::from_static("Origin"));
+        if let Some(origin) = req.headers().get(header::ORIGIN) {
+            let is_allowed = origin
+                .to_str()
+                .is_ok_and(|origin| allowed_origins.iter().any(|allowed| allowed == origin));
+            if is_allowed {
+                response
+                    .headers_mut()
+                    .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+            }
+        }
+    }
 
-    match (method, path_without_prefix, profile_filename) {
+    match (method, path_without_prefix, profile) {
         (&Method::OPTIONS, _, _) => {
             // https://developer.mozilla.org/en-US/docs/Web/HTTP/Methods/OPTIONS
             *response.status_mut() = StatusCode::NO_CONTENT;
@@ -315,29 +431,37 @@ async fn symbolication_service(
                 );
             }
         }
-        (&Method::GET, "/profile.json", Some(profile_filename)) => {
-            if profile_filename.extension() == Some(OsStr::new("gz")) {
+        (&Method::GET, "/profile.json", Some(profile)) => {
+            let is_gzipped = profile.is_gzipped();
+            if is_gzipped {
                 response.headers_mut().insert(
                     header::CONTENT_ENCODING,
                     header::HeaderValue::from_static("gzip"),
                 );
+            } else {
+                response.headers_mut().append(
+                    header::VARY,
+                    header::HeaderValue::from_static("Accept-Encoding"),
+                );
             }
             response.headers_mut().insert(
                 header::CONTENT_TYPE,
                 header::HeaderValue::from_static("application/json; charset=UTF-8"),
             );
+            response.headers_mut().insert(
+                header::ACCEPT_RANGES,
+                header::HeaderValue::from_static("bytes"),
+            );
 
-            // Stream the file. This follows the send_file example from the hyper repo.
-            // https://github.com/hyperium/hyper/blob/7206fe30302937075c51c16a69d1eb3bbce6a671/examples/send_file.rs
-            let file = tokio::fs::File::open(&profile_filename)
-                .await
-                .expect("couldn't open profile file");
-
-            // Wrap in a tokio_util::io::ReaderStream
-            let reader_stream = ReaderStream::new(file);
-
-            let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
-            *response.body_mut() = Either::Right(stream_body.boxed());
+            let body = match profile {
+                ServedProfile::File(path) => {
+                    file_profile_body(&path, &req, &mut response, is_gzipped).await
+                }
+                ServedProfile::InMemory(data) => {
+                    in_memory_profile_body(data, &req, &mut response, is_gzipped)
+                }
+            };
+            *response.body_mut() = body;
         }
         (&Method::POST, path, _) => {
             response.headers_mut().insert(
@@ -345,14 +469,43 @@ async fn symbolication_service(
                 header::HeaderValue::from_static("application/json"),
             );
             let path = path.to_string();
+            let encoding = req
+                .headers()
+                .get(header::ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .and_then(preferred_content_encoding);
             // Await the full body to be concatenated into a `Collected<Bytes>`.
             let full_body = req.into_body().collect().await?;
             // Convert the `Collected<Bytes>` into a `String`.
             let full_body =
                 String::from_utf8(full_body.to_bytes().to_vec()).expect("invalid utf-8");
-            let response_json = symbol_manager.query_json_api(&path, &full_body).await;
+            let response_json = query_cache.query_json_api(&path, &full_body).await;
 
-            *response.body_mut() = Either::Left(response_json);
+            response.headers_mut().append(
+                header::VARY,
+                header::HeaderValue::from_static("Accept-Encoding"),
+            );
+            match encoding {
+                Some(encoding) if response_json.len() >= MIN_COMPRESSED_RESPONSE_LEN => {
+                    let compressed = tokio::task::spawn_blocking(move || {
+                        let mut compressed = Vec::new();
+                        encoding
+                            .compress(&mut response_json.as_bytes(), &mut compressed)
+                            .map(|()| compressed)
+                    })
+                    .await
+                    .expect("compression task panicked")
+                    .expect("compressing into a Vec shouldn't fail");
+                    response
+                        .headers_mut()
+                        .insert(header::CONTENT_ENCODING, encoding.header_value());
+                    let body = Full::new(Bytes::from(compressed)).map_err(|never| match never {});
+                    *response.body_mut() = Either::Right(body.boxed());
+                }
+                _ => {
+                    *response.body_mut() = Either::Left(response_json);
+                }
+            }
         }
         _ => {
             *response.status_mut() = StatusCode::NOT_FOUND;
@@ -362,6 +515,435 @@ async fn symbolication_service(
     Ok(response)
 }
 
+/// Symbolication responses smaller than this are sent uncompressed.
+const MIN_COMPRESSED_RESPONSE_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ContentEncoding {
+    Brotli,
+    Gzip,
+}
+
+impl ContentEncoding {
+    fn header_value(self) -> header::HeaderValue {
+        match self {
+            ContentEncoding::Brotli => header::HeaderValue::from_static("br"),
+            ContentEncoding::Gzip => header::HeaderValue::from_static("gzip"),
+        }
+    }
+
+    fn compress(self, reader: &mut impl Read, writer: impl Write) -> std::io::Result<()> {
+        match self {
+            ContentEncoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(writer, 64 * 1024, 5, 22);
+                std::io::copy(reader, &mut encoder)?;
+                encoder.into_inner().flush()
+            }
+            ContentEncoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(writer, flate2::Compression::fast());
+                std::io::copy(reader, &mut encoder)?;
+                encoder.finish()?.flush()
+            }
+        }
+    }
+}
+
+/// Picks the encoding to use for a response, based on the request's
+/// `Accept-Encoding` header. Brotli is preferred over gzip.
+fn preferred_content_encoding(accept_encoding: &str) -> Option<ContentEncoding> {
+    let accepted = accept_encoding.split(',').filter_map(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        let name = parts.next()?;
+        let rejected = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+        (!rejected).then_some(name)
+    });
+    let mut best = None;
+    for name in accepted {
+        if name.eq_ignore_ascii_case("br") {
+            return Some(ContentEncoding::Brotli);
+        }
+        if name.eq_ignore_ascii_case("gzip") {
+            best = Some(ContentEncoding::Gzip);
+        }
+    }
+    best
+}
+
+/// Which part of the profile to send in response to `GET /profile.json`.
+enum ProfileBody {
+    /// The requested range, as it is, without additional compression.
+    Range(Range<u64>),
+    /// The whole profile, compressed with the encoding, if any.
+    Whole(Option<ContentEncoding>),
+}
+
+/// Sets the status and the headers of the response to `GET /profile.json`,
+/// for a profile of `len` bytes, and returns which part of the profile to
+/// send. Returns `None` if the requested range can't be satisfied, in which
+/// case the response has no body.
+fn prepare_profile_response(
+    req: &Request<hyper::body::Incoming>,
+    response: &mut Response<Either<String, BoxBody<Bytes, std::io::Error>>>,
+    len: u64,
+    is_gzipped: bool,
+) -> Option<ProfileBody> {
+    let range = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_byte_range(value, len));
+    match range {
+        Some(ByteRange::NotSatisfiable) => {
+            *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            response.headers_mut().insert(
+                header::CONTENT_RANGE,
+                header::HeaderValue::try_from(format!("bytes */{len}")).unwrap(),
+            );
+            None
+        }
+        Some(ByteRange::Satisfiable(range)) => {
+            // Ranges are always served from the profile as it is,
+            // without applying any additional compression.
+            *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+            let content_range = format!("bytes {}-{}/{len}", range.start, range.end - 1);
+            response.headers_mut().insert(
+                header::CONTENT_RANGE,
+                header::HeaderValue::try_from(content_range).unwrap(),
+            );
+            response.headers_mut().insert(
+                header::CONTENT_LENGTH,
+                header::HeaderValue::from(range.end - range.start),
+            );
+            Some(ProfileBody::Range(range))
+        }
+        None => {
+            let encoding = match is_gzipped {
+                true => None,
+                false => req
+                    .headers()
+                    .get(header::ACCEPT_ENCODING)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(preferred_content_encoding),
+            };
+            match encoding {
+                Some(encoding) => response
+                    .headers_mut()
+                    .insert(header::CONTENT_ENCODING, encoding.header_value()),
+                None => response
+                    .headers_mut()
+                    .insert(header::CONTENT_LENGTH, header::HeaderValue::from(len)),
+            };
+            Some(ProfileBody::Whole(encoding))
+        }
+    }
+}
+
+/// Streams the profile file. This follows the send_file example from the hyper repo.
+/// https://github.com/hyperium/hyper/blob/7206fe30302937075c51c16a69d1eb3bbce6a671/examples/send_file.rs
+async fn file_profile_body(
+    path: &Path,
+    req: &Request<hyper::body::Incoming>,
+    response: &mut Response<Either<String, BoxBody<Bytes, std::io::Error>>>,
+    is_gzipped: bool,
+) -> Either<String, BoxBody<Bytes, std::io::Error>> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .expect("couldn't open profile file");
+    let file_len = file
+        .metadata()
+        .await
+        .expect("couldn't get profile file metadata")
+        .len();
+
+    match prepare_profile_response(req, response, file_len, is_gzipped) {
+        None => Either::Left(String::new()),
+        Some(ProfileBody::Range(range)) => {
+            file.seek(SeekFrom::Start(range.start))
+                .await
+                .expect("couldn't seek in profile file");
+            let reader_stream = ReaderStream::new(file.take(range.end - range.start));
+            let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
+            Either::Right(stream_body.boxed())
+        }
+        Some(ProfileBody::Whole(Some(encoding))) => {
+            Either::Right(compressed_body(file.into_std().await, encoding))
+        }
+        Some(ProfileBody::Whole(None)) => {
+            // Wrap in a tokio_util::io::ReaderStream
+            let reader_stream = ReaderStream::new(file);
+            let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
+            Either::Right(stream_body.boxed())
+        }
+    }
+}
+
+/// Sends the profile from memory.
+fn in_memory_profile_body(
+    data: Bytes,
+    req: &Request<hyper::body::Incoming>,
+    response: &mut Response<Either<String, BoxBody<Bytes, std::io::Error>>>,
+    is_gzipped: bool,
+) -> Either<String, BoxBody<Bytes, std::io::Error>> {
+    let data = match prepare_profile_response(req, response, data.len() as u64, is_gzipped) {
+        None => return Either::Left(String::new()),
+        Some(ProfileBody::Range(range)) => data.slice(range.start as usize..range.end as usize),
+        Some(ProfileBody::Whole(Some(encoding))) => {
+            return Either::Right(compressed_body(std::io::Cursor::new(data), encoding));
+        }
+        Some(ProfileBody::Whole(None)) => data,
+    };
+    Either::Right(Full::new(data).map_err(|never| match never {}).boxed())
+}
+
+/// Compresses the data on a blocking thread and streams the compressed bytes
+/// into the response body as they are produced.
+fn compressed_body(
+    data: impl Read + Send + 'static,
+    encoding: ContentEncoding,
+) -> BoxBody<Bytes, std::io::Error> {
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter(sender.clone());
+        let writer = std::io::BufWriter::with_capacity(64 * 1024, writer);
+        let mut reader = std::io::BufReader::new(data);
+        if let Err(err) = encoding.compress(&mut reader, writer) {
+            // If the receiver is gone, the client has disconnected and there's
+            // nobody left to tell about the error.
+            let _ = sender.blocking_send(Err(err));
+        }
+    });
+    let stream = futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx));
+    StreamBody::new(stream.map_ok(Frame::data)).boxed()
+}
+
+/// Sends everything written to it as chunks of a response body.
+struct ChannelWriter(tokio::sync::mpsc::Sender<std::io::Result<Bytes>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Satisfiable(Range<u64>),
+    NotSatisfiable,
+}
+
+/// Parses the value of a `Range` header for a resource of `len` bytes.
+///
+/// Returns `None` for headers which should be ignored, i.e. for malformed
+/// ranges and for requests for multiple ranges, which we don't support.
+fn parse_byte_range(value: &str, len: u64) -> Option<ByteRange> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix_len) => {
+            let suffix_len: u64 = suffix_len.parse().ok()?;
+            len.saturating_sub(suffix_len)..len
+        }
+        (start, "") => start.parse().ok()?..len,
+        (start, end) => {
+            let start: u64 = start.parse().ok()?;
+            let end: u64 = end.parse().ok()?;
+            if end < start {
+                return None;
+            }
+            start..end.saturating_add(1).min(len)
+        }
+    };
+    if range.start >= range.end {
+        return Some(ByteRange::NotSatisfiable);
+    }
+    Some(ByteRange::Satisfiable(range))
+}
+
+/// How long a response which found no symbols is reused for identical requests.
+const NOT_FOUND_RESPONSE_TTL: Duration = Duration::from_secs(60);
+
+type QueryKey = (String, String);
+
+/// Sits in front of the symbol manager's JSON API.
+///
+/// The profiler front-end sends bursts of requests, often several identical
+/// ones at once, and loading a large PDB for each of them is expensive.
+/// Concurrent identical requests share a single query, and responses which
+/// found no symbols at all are remembered for [`NOT_FOUND_RESPONSE_TTL`], so
+/// that retries for libraries without symbols don't search for them again.
+struct QueryCache {
+    symbol_manager: Arc<SymbolManager>,
+    in_flight: Mutex<HashMap<QueryKey, Shared<BoxFuture<'static, String>>>>,
+    not_found: Mutex<HashMap<QueryKey, (Instant, String)>>,
+}
+
+impl QueryCache {
+    fn new(symbol_manager: SymbolManager) -> Self {
+        Self {
+            symbol_manager: Arc::new(symbol_manager),
+            in_flight: Mutex::new(HashMap::new()),
+            not_found: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn query_json_api(&self, path: &str, request_json: &str) -> String {
+        let key = (path.to_string(), request_json.to_string());
+        if let Some((inserted, response)) = self.not_found.lock().unwrap().get(&key) {
+            if inserted.elapsed() < NOT_FOUND_RESPONSE_TTL {
+                return response.clone();
+            }
+        }
+
+        let (query, guard) = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(query) => (query.clone(), None),
+                None => {
+                    let symbol_manager = self.symbol_manager.clone();
+                    let (path, request_json) = key.clone();
+                    let query =
+                        async move { symbol_manager.query_json_api(&path, &request_json).await }
+                            .boxed()
+                            .shared();
+                    in_flight.insert(key.clone(), query.clone());
+                    let guard = InFlightGuard {
+                        in_flight: &self.in_flight,
+                        key: &key,
+                    };
+                    (query, Some(guard))
+                }
+            }
+        };
+
+        let response = query.await;
+        if guard.is_some() && response_found_nothing(&response) {
+            let mut not_found = self.not_found.lock().unwrap();
+            not_found.retain(|_, (inserted, _)| inserted.elapsed() < NOT_FOUND_RESPONSE_TTL);
+            not_found.insert(key.clone(), (Instant::now(), response.clone()));
+        }
+        drop(guard);
+        response
+    }
+}
+
+/// Removes a query from the in-flight map once the request which started it
+/// is done with it, even if that request's connection goes away early. Other
+/// requests waiting on the same query keep their own handle to it.
+struct InFlightGuard<'a> {
+    in_flight: &'a Mutex<HashMap<QueryKey, Shared<BoxFuture<'static, String>>>>,
+    key: &'a QueryKey,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(self.key);
+    }
+}
+
+/// Returns true for error responses and for symbolication responses in which
+/// none of the requested libraries had symbols.
+fn response_found_nothing(response_json: &str) -> bool {
+    let Ok(response) = serde_json::from_str::<serde_json::Value>(response_json) else {
+        return false;
+    };
+    if response.get("error").is_some() {
+        return true;
+    }
+    let Some(results) = response.get("results").and_then(|r| r.as_array()) else {
+        return false;
+    };
+    let mut found_modules = results
+        .iter()
+        .filter_map(|result| result.get("found_modules")?.as_object())
+        .flat_map(|found_modules| found_modules.values())
+        .peekable();
+    found_modules.peek().is_some() && found_modules.all(|found| found.as_bool() == Some(false))
+}
+
+/// Serves a file from a static build of the profiler front-end. The front-end
+/// is a single-page app, so paths which don't match a file, such as
+/// `/from-url/...`, get its `index.html`.
+async fn serve_profiler_file(
+    profiler_dir: &Path,
+    path: &str,
+    response: &mut Response<Either<String, BoxBody<Bytes, std::io::Error>>>,
+) {
+    let relative_path = path.trim_start_matches('/');
+    let is_safe = relative_path
+        .split('/')
+        .all(|component| component != ".." && !component.contains(['\\', ':']));
+    let mut file_path = profiler_dir.join("index.html");
+    if is_safe && !relative_path.is_empty() {
+        let candidate = profiler_dir.join(relative_path);
+        if tokio::fs::metadata(&candidate)
+            .await
+            .is_ok_and(|metadata| metadata.is_file())
+        {
+            file_path = candidate;
+        }
+    }
+
+    let file = match tokio::fs::File::open(&file_path).await {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!(
+                "Could not open {:?} from the profiler directory: {err}",
+                file_path
+            );
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return;
+        }
+    };
+    let extension = file_path.extension().and_then(OsStr::to_str);
+    let content_type = match extension.unwrap_or("") {
+        "html" => "text/html; charset=UTF-8",
+        "js" | "mjs" => "text/javascript; charset=UTF-8",
+        "css" => "text/css; charset=UTF-8",
+        "json" | "map" => "application/json; charset=UTF-8",
+        "webmanifest" => "application/manifest+json",
+        "wasm" => "application/wasm",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "ico" => "image/x-icon",
+        "woff2" => "font/woff2",
+        "txt" => "text/plain; charset=UTF-8",
+        _ => "application/octet-stream",
+    };
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(content_type),
+    );
+    let reader_stream = ReaderStream::new(file);
+    let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
+    *response.body_mut() = Either::Right(stream_body.boxed());
+}
+
+/// Returns the scheme, host and port part of a URL, e.g.
+/// `https://example.com:8080` for `https://example.com:8080/profiler/`.
+fn url_origin(url: &str) -> &str {
+    let after_scheme = url.find("://").map_or(0, |pos| pos + 3);
+    match url[after_scheme..].find('/') {
+        Some(pos) => &url[..after_scheme + pos],
+        None => url,
+    }
+}
+
 fn substitute_template(template: &str, template_values: &HashMap<&'static str, String>) -> String {
     let mut s = template.to_string();
     for (key, value) in template_values {
@@ -369,3 +951,81 @@ fn substitute_template(template: &str, template_values: &HashMap<&'static str, S
     }
     s
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        parse_byte_range, preferred_content_encoding, response_found_nothing, url_origin,
+        ByteRange, ContentEncoding,
+    };
+
+    #[test]
+    fn detect_not_found_responses() {
+        assert!(response_found_nothing(
+            r#"{"error":"Unrecognized URL /foo"}"#
+        ));
+        assert!(response_found_nothing(
+            r#"{"results":[{"stacks":[[]],"found_modules":{"a.pdb/ABC1":false}},{"stacks":[[]],"found_modules":{"b.pdb/DEF2":false}}]}"#
+        ));
+        assert!(!response_found_nothing(
+            r#"{"results":[{"stacks":[[]],"found_modules":{"a.pdb/ABC1":false,"b.pdb/DEF2":true}}]}"#
+        ));
+        assert!(!response_found_nothing(r#"{"results":[]}"#));
+        assert!(!response_found_nothing(
+            r#"{"startAddress":"0x10","instructions":[]}"#
+        ));
+    }
+
+    #[test]
+    fn origins() {
+        assert_eq!(
+            url_origin("https://deploy-preview-1234--perf-html.netlify.app"),
+            "https://deploy-preview-1234--perf-html.netlify.app"
+        );
+        assert_eq!(
+            url_origin("http://localhost:4242/profiler/"),
+            "http://localhost:4242"
+        );
+    }
+
+    #[test]
+    fn content_encoding_negotiation() {
+        assert_eq!(
+            preferred_content_encoding("gzip, deflate, br, zstd"),
+            Some(ContentEncoding::Brotli)
+        );
+        assert_eq!(
+            preferred_content_encoding("gzip;q=1.0, br;q=0"),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(preferred_content_encoding("identity"), None);
+        assert_eq!(preferred_content_encoding("gzip;q=0"), None);
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(
+            parse_byte_range("bytes=0-99", 1000),
+            Some(ByteRange::Satisfiable(0..100))
+        );
+        assert_eq!(
+            parse_byte_range("bytes=900-", 1000),
+            Some(ByteRange::Satisfiable(900..1000))
+        );
+        assert_eq!(
+            parse_byte_range("bytes=-100", 1000),
+            Some(ByteRange::Satisfiable(900..1000))
+        );
+        assert_eq!(
+            parse_byte_range("bytes=500-2000", 1000),
+            Some(ByteRange::Satisfiable(500..1000))
+        );
+        assert_eq!(
+            parse_byte_range("bytes=1000-", 1000),
+            Some(ByteRange::NotSatisfiable)
+        );
+        assert_eq!(parse_byte_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_byte_range("bytes=5-1", 1000), None);
+        assert_eq!(parse_byte_range("items=0-1", 1000), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! The core types of hybrid CPUs, such as the performance and efficiency cores
+//! of recent Intel and ARM CPUs. They're recorded in the profile's meta
+//! information so that `samply report --core-type` can pick the per-CPU tracks
+//! of one core type later, on any machine.
+
+/// The label of the section in the profile's meta information which lists the
+/// CPUs of each core type.
+pub const CORE_TYPES_SECTION_LABEL: &str = "CPU core types";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreType {
+    Performance,
+    Efficiency,
+}
+
+impl CoreType {
+    pub fn name(self) -> &'static str {
+        match self {
+            CoreType::Performance => "performance",
+            CoreType::Efficiency => "efficiency",
+        }
+    }
+}
+
+/// Parses a CPU list like "0-3,8,10-11", in the format of Linux's sysfs and
+/// of `taskset -c`.
+pub fn parse_cpu_list(s: &str) -> Result<Vec<u32>, String> {
+    let mut cpus = Vec::new();
+    for part in s.trim().split(',').filter(|part| !part.is_empty()) {
+        let parse = |s: &str| {
+            s.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid CPU number {s:?} in {part:?}"))
+        };
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(format!("Invalid CPU range {part:?}"));
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(parse(part)?),
+        }
+    }
+    if cpus.is_empty() {
+        return Err("The CPU list is empty".to_string());
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+/// Formats a sorted list of CPUs like "0-3,8", the inverse of [`parse_cpu_list`].
+pub fn format_cpu_list(cpus: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == cpu => *last = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    let ranges: Vec<String> = ranges
+        .into_iter()
+        .map(|(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{first}-{last}")
+            }
+        })
+        .collect();
+    ranges.join(",")
+}
+
+/// Returns the CPUs of each core type of this machine, or an empty list if
+/// the CPU doesn't have different core types.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn core_types() -> Vec<(CoreType, Vec<u32>)> {
+    let read_cpu_list = |path: &str| {
+        let list = std::fs::read_to_string(path).ok()?;
+        parse_cpu_list(&list).ok()
+    };
+
+    // Intel hybrid CPUs have a separate PMU for each core type.
+    let mut core_types: Vec<(CoreType, Vec<u32>)> = [
+        (CoreType::Performance, "/sys/devices/cpu_core/cpus"),
+        (CoreType::Efficiency, "/sys/devices/cpu_atom/cpus"),
+    ]
+    .into_iter()
+    .filter_map(|(core_type, path)| Some((core_type, read_cpu_list(path)?)))
+    .collect();
+    if !core_types.is_empty() {
+        return core_types;
+    }
+
+    // On ARM, big and LITTLE cores have different capacities. Count the cores
+    // with the highest capacity as performance cores.
+    let Some(possible_cpus) = read_cpu_list("/sys/devices/system/cpu/possible") else {
+        return core_types;
+    };
+    let capacities: Vec<(u32, u32)> = possible_cpus
+        .into_iter()
+        .filter_map(|cpu| {
+            let path = format!("/sys/devices/system/cpu/cpu{cpu}/cpu_capacity");
+            let capacity = std::fs::read_to_string(path).ok()?.trim().parse().ok()?;
+            Some((cpu, capacity))
+        })
+        .collect();
+    let max_capacity = capacities.iter().map(|(_, capacity)| *capacity).max();
+    let min_capacity = capacities.iter().map(|(_, capacity)| *capacity).min();
+    if max_capacity == min_capacity {
+        return core_types;
+    }
+    for core_type in [CoreType::Performance, CoreType::Efficiency] {
+        let cpus: Vec<u32> = capacities
+            .iter()
+            .filter(|(_, capacity)| {
+                (Some(*capacity) == max_capacity) == (core_type == CoreType::Performance)
+            })
+            .map(|(cpu, _)| *cpu)
+            .collect();
+        core_types.push((core_type, cpus));
+    }
+    core_types
+}
+
+/// Lists the CPUs of each core type of this machine in the profile's meta
+/// information, if the CPU has different core types.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub fn add_core_types_to_profile_meta(profile: &mut fxprof_processed_profile::Profile) {
+    for (core_type, cpus) in core_types() {
+        profile.add_extra_info(
+            CORE_TYPES_SECTION_LABEL,
+            core_type.name(),
+            &format_cpu_list(&cpus),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cpu_lists() {
+        assert_eq!(parse_cpu_list("0-3,8\n"), Ok(vec![0, 1, 2, 3, 8]));
+        assert_eq!(parse_cpu_list("5,1-2,2"), Ok(vec![1, 2, 5]));
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+        assert!(parse_cpu_list("").is_err());
+        assert_eq!(format_cpu_list(&[0, 1, 2, 3, 8, 10, 11]), "0-3,8,10-11");
+        assert_eq!(format_cpu_list(&[4]), "4");
+    }
+}
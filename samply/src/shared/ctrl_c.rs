@@ -1,8 +1,10 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, OnceLock};
 
 use tokio::sync::oneshot;
 
 static INSTANCE: OnceLock<Arc<Mutex<CtrlCState>>> = OnceLock::new();
+static WAS_PRESSED: AtomicBool = AtomicBool::new(false);
 
 pub type Receiver = oneshot::Receiver<()>;
 
@@ -129,6 +131,16 @@ impl CtrlC {
         CtrlCState::get().lock().unwrap().current_sender = Some(tx);
         rx
     }
+
+    /// Returns whether Ctrl+C has been pressed at any point so far, and was
+    /// handled by a receiver rather than terminating the process.
+    ///
+    /// This is useful for code which runs several operations in sequence that
+    /// each observe Ctrl+C themselves, and which needs to stop after the
+    /// current operation.
+    pub fn was_pressed() -> bool {
+        WAS_PRESSED.load(Ordering::SeqCst)
+    }
 }
 
 struct CtrlCState {
@@ -144,6 +156,7 @@ impl CtrlCState {
                     if let Ok(()) = sender.send(()) {
                         // The receiver still existed. Trust that it will handle this Ctrl+C.
                         // Do not terminate this process.
+                        WAS_PRESSED.store(true, Ordering::SeqCst);
                         return;
                     }
                 }
@@ -0,0 +1,484 @@
+//! Counters which are computed from other counters when the profile is saved,
+//! for `--derived-counter`, e.g. `io = "Disk read" + "Disk write"` or
+//! `rss_growth = rate(RSS)`.
+//!
+//! An expression is made of counter names, numbers, `+`, `-`, `*`, `/`,
+//! parentheses, and `rate(...)`, which is the change of its argument per
+//! second. Counter names which aren't identifiers are written in double quotes.
+//!
+//! The expression is evaluated on the values of the counters, i.e. on the sum
+//! of their samples so far, at each time at which one of them has a sample.
+//! A derived counter is added to each process which has all of the counters
+//! that the expression refers to.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone)]
+pub struct DerivedCounter {
+    pub name: String,
+    /// The expression as it was written, used as the counter's description.
+    pub source: String,
+    expr: Expr,
+}
+
+impl DerivedCounter {
+    /// Parses a definition of the form `name = expression`.
+    pub fn parse(definition: &str) -> Result<Self, String> {
+        let (name, source) = definition
+            .split_once('=')
+            .filter(|(name, _)| !name.trim().is_empty())
+            .ok_or_else(|| "expected a definition of the form 'name = expression'".to_string())?;
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_sum()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected {token} in {:?}", source.trim()));
+        }
+        Ok(Self {
+            name: name.trim().to_string(),
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(f64),
+    Counter(String),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    Rate(Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Expr {
+    fn counter_names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Counter(name) => {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+            Expr::Neg(expr) | Expr::Rate(expr) => expr.counter_names(names),
+            Expr::Binary(lhs, _, rhs) => {
+                lhs.counter_names(names);
+                rhs.counter_names(names);
+            }
+        }
+    }
+
+    /// Evaluates the expression at each of the `times` (in milliseconds), with
+    /// the counter values at those times.
+    fn evaluate(&self, times: &[f64], values: &BTreeMap<&str, Vec<f64>>) -> Vec<f64> {
+        match self {
+            Expr::Number(n) => vec![*n; times.len()],
+            Expr::Counter(name) => values[name.as_str()].clone(),
+            Expr::Neg(expr) => expr.evaluate(times, values).iter().map(|v| -v).collect(),
+            Expr::Binary(lhs, op, rhs) => {
+                let lhs = lhs.evaluate(times, values);
+                let rhs = rhs.evaluate(times, values);
+                lhs.iter()
+                    .zip(&rhs)
+                    .map(|(l, r)| match op {
+                        BinaryOp::Add => l + r,
+                        BinaryOp::Sub => l - r,
+                        BinaryOp::Mul => l * r,
+                        BinaryOp::Div => l / r,
+                    })
+                    .collect()
+            }
+            Expr::Rate(expr) => {
+                let inner = expr.evaluate(times, values);
+                (0..times.len())
+                    .map(|i| {
+                        if i == 0 || times[i] <= times[i - 1] {
+                            return 0.0;
+                        }
+                        (inner[i] - inner[i - 1]) / ((times[i] - times[i - 1]) / 1000.0)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Op(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Name(name) => write!(f, "{name:?}"),
+            Token::Op(op) => write!(f, "'{op}'"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if "+-*/()".contains(c) {
+            tokens.push(Token::Op(c));
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let name: String = chars.by_ref().take_while(|&c| c != '"').collect();
+            tokens.push(Token::Name(name));
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                number.push(c);
+                chars.next();
+            }
+            let number = number
+                .parse()
+                .map_err(|_| format!("invalid number {number:?}"))?;
+            tokens.push(Token::Number(number));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_alphanumeric() || **c == '_' || **c == '.')
+            {
+                name.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Name(name));
+        } else {
+            return Err(format!("unexpected character '{c}'"));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next_op_if(&mut self, ops: &str) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(*op) => {
+                self.pos += 1;
+                Some(*op)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect_op(&mut self, op: char) -> Result<(), String> {
+        match self.next_op_if(&op.to_string()) {
+            Some(_) => Ok(()),
+            None => Err(format!("expected '{op}'")),
+        }
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_product()?;
+        while let Some(op) = self.next_op_if("+-") {
+            let op = if op == '+' {
+                BinaryOp::Add
+            } else {
+                BinaryOp::Sub
+            };
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.parse_product()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_unary()?;
+        while let Some(op) = self.next_op_if("*/") {
+            let op = if op == '*' {
+                BinaryOp::Mul
+            } else {
+                BinaryOp::Div
+            };
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.next_op_if("-").is_some() {
+            return Ok(Expr::Neg(Box::new(self.parse_unary()?)));
+        }
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| "unexpected end of the expression".to_string())?;
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Op('(') => {
+                let expr = self.parse_sum()?;
+                self.expect_op(')')?;
+                Ok(expr)
+            }
+            Token::Name(name) if name == "rate" && self.next_op_if("(").is_some() => {
+                let expr = self.parse_sum()?;
+                self.expect_op(')')?;
+                Ok(Expr::Rate(Box::new(expr)))
+            }
+            Token::Name(name) => Ok(Expr::Counter(name)),
+            Token::Op(op) => Err(format!("unexpected '{op}'")),
+        }
+    }
+}
+
+/// A counter of the profile, with absolute times and values.
+struct CounterValues<'a> {
+    name: &'a str,
+    category: &'a Value,
+    main_thread_index: &'a Value,
+    times: Vec<f64>,
+    values: Vec<f64>,
+}
+
+impl<'a> CounterValues<'a> {
+    fn read(counter: &'a Value) -> Option<Self> {
+        let samples = &counter["samples"];
+        let times: Vec<f64> = match (samples["time"].as_array(), samples["timeDeltas"].as_array()) {
+            (Some(time), _) => time.iter().map(|t| t.as_f64().unwrap_or(0.0)).collect(),
+            (None, Some(deltas)) => deltas
+                .iter()
+                .scan(0.0, |time, delta| {
+                    *time += delta.as_f64().unwrap_or(0.0);
+                    Some(*time)
+                })
+                .collect(),
+            (None, None) => return None,
+        };
+        // Samples are deltas, and the counter's value is their sum so far.
+        let values = samples["count"]
+            .as_array()?
+            .iter()
+            .scan(0.0, |value, count| {
+                *value += count.as_f64().unwrap_or(0.0);
+                Some(*value)
+            })
+            .collect();
+        Some(Self {
+            name: counter["name"].as_str()?,
+            category: &counter["category"],
+            main_thread_index: &counter["mainThreadIndex"],
+            times,
+            values,
+        })
+    }
+
+    /// The value at each of the sorted `times`, or 0 before the first sample.
+    fn values_at(&self, times: &[f64]) -> Vec<f64> {
+        let mut index = 0;
+        let mut value = 0.0;
+        times
+            .iter()
+            .map(|&time| {
+                while index < self.times.len() && self.times[index] <= time {
+                    value = self.values[index];
+                    index += 1;
+                }
+                value
+            })
+            .collect()
+    }
+}
+
+/// Adds the derived counters to the profile JSON. Returns the names of the
+/// derived counters which couldn't be added to any process, because no process
+/// has all the counters which their expressions refer to.
+pub fn add_derived_counters(
+    profile: &mut Value,
+    derived_counters: &[DerivedCounter],
+) -> Vec<String> {
+    let mut new_counters = Vec::new();
+    let mut missing = Vec::new();
+    {
+        let counters: Vec<(String, CounterValues)> = profile["counters"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|counter| {
+                let pid = match &counter["pid"] {
+                    Value::String(pid) => pid.clone(),
+                    pid => pid.to_string(),
+                };
+                Some((pid, CounterValues::read(counter)?))
+            })
+            .collect();
+        let mut pids: Vec<&str> = Vec::new();
+        for (pid, _) in &counters {
+            if !pids.contains(&pid.as_str()) {
+                pids.push(pid);
+            }
+        }
+
+        for derived_counter in derived_counters {
+            let mut names = Vec::new();
+            derived_counter.expr.counter_names(&mut names);
+            let mut added = false;
+            for &pid in &pids {
+                let inputs: Option<Vec<&CounterValues>> = names
+                    .iter()
+                    .map(|name| {
+                        counters
+                            .iter()
+                            .find(|(p, counter)| p == pid && counter.name == *name)
+                            .map(|(_, counter)| counter)
+                    })
+                    .collect();
+                let Some(inputs) = inputs else {
+                    continue;
+                };
+                // A process needs at least one counter to be found; expressions
+                // without counters are skipped.
+                let Some(first_input) = inputs.first() else {
+                    continue;
+                };
+                let mut times: Vec<f64> = inputs
+                    .iter()
+                    .flat_map(|input| input.times.iter().copied())
+                    .collect();
+                times.sort_by(f64::total_cmp);
+                times.dedup();
+                let values: BTreeMap<&str, Vec<f64>> = inputs
+                    .iter()
+                    .map(|input| (input.name, input.values_at(&times)))
+                    .collect();
+                let results = derived_counter.expr.evaluate(&times, &values);
+                new_counters.push(json!({
+                    "category": first_input.category,
+                    "name": derived_counter.name,
+                    "description": derived_counter.source,
+                    "mainThreadIndex": first_input.main_thread_index,
+                    "pid": pid,
+                    "samples": counter_samples_json(&times, &results),
+                }));
+                added = true;
+            }
+            if !added {
+                missing.push(derived_counter.name.clone());
+            }
+        }
+    }
+
+    if !new_counters.is_empty() {
+        match profile["counters"].as_array_mut() {
+            Some(counters) => counters.extend(new_counters),
+            None => profile["counters"] = Value::Array(new_counters),
+        }
+    }
+    missing
+}
+
+/// The samples table of a counter with the given values at the given times (in
+/// milliseconds), where each sample's count is the change from the previous value.
+pub fn counter_samples_json(times: &[f64], values: &[f64]) -> Value {
+    // Division by zero gives infinite values, which JSON can't represent.
+    let values = values.iter().map(|v| if v.is_finite() { *v } else { 0.0 });
+    let counts: Vec<f64> = values
+        .scan(0.0, |previous, value| {
+            let count = value - *previous;
+            *previous = value;
+            Some(count)
+        })
+        .collect();
+    let time_deltas: Vec<f64> = times
+        .iter()
+        .scan(0.0, |previous, time| {
+            let delta = time - *previous;
+            *previous = *time;
+            Some(delta)
+        })
+        .collect();
+    json!({
+        "length": times.len(),
+        "count": counts,
+        "number": vec![1; times.len()],
+        "timeDeltas": time_deltas,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn counter(name: &str, pid: &str, times: &[f64], counts: &[f64]) -> Value {
+        json!({
+            "category": "Memory",
+            "name": name,
+            "description": "",
+            "mainThreadIndex": 0,
+            "pid": pid,
+            "samples": { "length": times.len(), "time": times, "count": counts },
+        })
+    }
+
+    fn values(counter: &Value) -> Vec<(f64, f64)> {
+        let counter = CounterValues::read(counter).unwrap();
+        counter.times.into_iter().zip(counter.values).collect()
+    }
+
+    #[test]
+    fn sum_and_rate() {
+        let mut profile = json!({
+            "counters": [
+                counter("read", "1", &[0.0, 1000.0], &[10.0, 20.0]),
+                counter("write", "1", &[500.0], &[5.0]),
+                counter("read", "2", &[0.0], &[1.0]),
+            ]
+        });
+        let derived = [
+            DerivedCounter::parse("io = read + \"write\"").unwrap(),
+            DerivedCounter::parse("growth = rate(read * 2)").unwrap(),
+            DerivedCounter::parse("other = missing - 1").unwrap(),
+        ];
+        assert_eq!(add_derived_counters(&mut profile, &derived), vec!["other"]);
+
+        let counters = profile["counters"].as_array().unwrap();
+        assert_eq!(counters.len(), 6);
+        assert_eq!(counters[3]["name"], "io");
+        assert_eq!(counters[3]["pid"], "1");
+        assert_eq!(counters[3]["description"], "read + \"write\"");
+        assert_eq!(
+            values(&counters[3]),
+            vec![(0.0, 10.0), (500.0, 15.0), (1000.0, 35.0)]
+        );
+        // The second process has no "write" counter, but "growth" only needs "read".
+        assert_eq!(counters[4]["pid"], "1");
+        assert_eq!(values(&counters[4]), vec![(0.0, 0.0), (1000.0, 40.0)]);
+        assert_eq!(counters[5]["pid"], "2");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(DerivedCounter::parse("= a").is_err());
+        assert!(DerivedCounter::parse("x = a +").is_err());
+        assert!(DerivedCounter::parse("x = (a").is_err());
+        assert!(DerivedCounter::parse("x = a b").is_err());
+        assert!(DerivedCounter::parse("x = a % b").is_err());
+        assert!(DerivedCounter::parse("x = -a * (b - 2.5) / rate(c)").is_ok());
+    }
+}
@@ -0,0 +1,129 @@
+//! Encryption of saved profiles with age (https://age-encryption.org), for
+//! profiling software which processes sensitive data. The profile is piped
+//! through the `age` command (or the compatible `rage`), so that it's only
+//! ever written to disk encrypted.
+
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The commands which are tried, in this order.
+const AGE_COMMANDS: [&str; 2] = ["age", "rage"];
+
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1";
+const ARMORED_AGE_HEADER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Whether the data is an age-encrypted file, binary or ASCII-armored.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(AGE_HEADER) || data.starts_with(ARMORED_AGE_HEADER)
+}
+
+/// The path of the encrypted profile: the output path with `.age` appended.
+pub fn encrypted_output_path(output_path: &Path) -> PathBuf {
+    let mut path = output_path.as_os_str().to_owned();
+    path.push(".age");
+    PathBuf::from(path)
+}
+
+/// The age arguments for the recipients. A recipient is either a public key,
+/// like "age1..." or "ssh-ed25519 ...", or the path of a recipients file.
+fn recipient_args(recipients: &[String]) -> Vec<OsString> {
+    let mut args = Vec::new();
+    for recipient in recipients {
+        let is_key = recipient.starts_with("age1") || recipient.starts_with("ssh-");
+        args.push(OsString::from(if is_key { "-r" } else { "-R" }));
+        args.push(OsString::from(recipient));
+    }
+    args
+}
+
+/// Encrypts the data to all recipients.
+pub fn encrypt(data: &[u8], recipients: &[String]) -> std::io::Result<Vec<u8>> {
+    let mut args = vec![OsString::from("--encrypt")];
+    args.extend(recipient_args(recipients));
+    run_age(&args, data)
+}
+
+/// Decrypts the data with the first of the identity files which matches.
+pub fn decrypt(data: &[u8], identities: &[PathBuf]) -> std::io::Result<Vec<u8>> {
+    if identities.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "The profile is encrypted. Pass the file with the matching age identity with --identity.",
+        ));
+    }
+    let mut args = vec![OsString::from("--decrypt")];
+    for identity in identities {
+        args.push(OsString::from("-i"));
+        args.push(identity.clone().into_os_string());
+    }
+    run_age(&args, data)
+}
+
+/// Runs age with the input on stdin, and returns its stdout. Its error
+/// messages go to our stderr.
+fn run_age(args: &[OsString], input: &[u8]) -> std::io::Result<Vec<u8>> {
+    for command in AGE_COMMANDS {
+        let mut child = match Command::new(command)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        let mut stdin = child.stdin.take().unwrap();
+        let output = std::thread::scope(|scope| {
+            // Write on another thread, so that age doesn't block on a full
+            // stdout pipe while we're still writing its input. Dropping
+            // stdin closes it.
+            scope.spawn(move || {
+                let _ = stdin.write_all(input);
+            });
+            child.wait_with_output()
+        })?;
+        if !output.status.success() {
+            return Err(Error::other(format!(
+                "{command} failed with {}",
+                output.status
+            )));
+        }
+        return Ok(output.stdout);
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        "Couldn't find the age command. Install age (https://age-encryption.org) or rage.",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn age_files_and_recipients() {
+        assert!(is_encrypted(b"age-encryption.org/v1\n-> X25519 abc\n"));
+        assert!(is_encrypted(b"-----BEGIN AGE ENCRYPTED FILE-----\n"));
+        assert!(!is_encrypted(b"{\"meta\":{}}"));
+        assert!(!is_encrypted(&[0x1f, 0x8b, 0x08]));
+
+        assert_eq!(
+            encrypted_output_path(Path::new("out/profile.json.gz")),
+            Path::new("out/profile.json.gz.age")
+        );
+
+        let recipients = [
+            "age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p".to_string(),
+            "ssh-ed25519 AAAAC3Nza".to_string(),
+            "team-keys.txt".to_string(),
+        ];
+        let args = recipient_args(&recipients);
+        let flags: Vec<_> = args.iter().step_by(2).collect();
+        assert_eq!(flags, ["-r", "-r", "-R"]);
+        assert_eq!(args[5], "team-keys.txt");
+    }
+}
@@ -0,0 +1,220 @@
+//! Frame timing for game and render loops.
+//!
+//! Frames are marker spans named `frame` from a marker file, or the time between
+//! two consecutive events which were chosen with `--frame-marker` (Windows only).
+//! For each thread with frames, we add a "Frame rate" counter, mark slow frames,
+//! and print frame duration statistics.
+
+use fxprof_processed_profile::{
+    Category, CategoryColor, MarkerFieldFlags, MarkerFieldFormat, MarkerTiming, ProcessHandle,
+    Profile, StaticSchemaMarker, StaticSchemaMarkerField, StringHandle, ThreadHandle, Timestamp,
+};
+
+use super::process_sample_data::MarkerSpanOnThread;
+use super::types::FastHashMap;
+
+/// The name of marker spans which delimit a frame.
+pub const FRAME_MARKER_NAME: &str = "frame";
+
+/// Frames which take longer than this multiple of the median frame duration
+/// are marked as slow.
+const SLOW_FRAME_FACTOR: f64 = 2.0;
+
+/// Statistics about the frame durations on a thread, in milliseconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameStats {
+    pub count: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl FrameStats {
+    /// Returns `None` if there are no frames.
+    pub fn from_durations(durations: &[f64]) -> Option<Self> {
+        if durations.is_empty() {
+            return None;
+        }
+        let mut sorted = durations.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let percentile = |p: f64| {
+            let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+            sorted[index]
+        };
+        Some(FrameStats {
+            count: sorted.len(),
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            median: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// Turns the frame boundary times of a thread into frame spans, one from each
+/// boundary to the next.
+#[cfg(any(target_os = "windows", test))]
+pub fn frames_between_boundaries(mut boundaries: Vec<Timestamp>) -> Vec<(Timestamp, Timestamp)> {
+    boundaries.sort();
+    boundaries
+        .windows(2)
+        .map(|pair| (pair[0], pair[1]))
+        .collect()
+}
+
+/// Adds the frame rate counters and slow frame markers for the `frame` marker
+/// spans in `marker_spans`, and prints frame duration statistics.
+pub fn add_frame_tracks(
+    profile: &mut Profile,
+    process: ProcessHandle,
+    marker_spans: &[MarkerSpanOnThread],
+) {
+    let mut frames_per_thread: FastHashMap<ThreadHandle, Vec<(Timestamp, Timestamp)>> =
+        FastHashMap::default();
+    for span in marker_spans {
+        if span.name == FRAME_MARKER_NAME {
+            frames_per_thread
+                .entry(span.thread_handle)
+                .or_default()
+                .push((span.start_time, span.end_time));
+        }
+    }
+
+    for (thread_handle, mut frames) in frames_per_thread {
+        frames.sort();
+        let durations: Vec<f64> = frames
+            .iter()
+            .map(|(start, end)| duration_ms(*start, *end))
+            .collect();
+        let Some(stats) = FrameStats::from_durations(&durations) else {
+            continue;
+        };
+
+        let counter = profile.add_counter(
+            process,
+            "Frame rate",
+            "Frames",
+            "Frames per second, based on the duration of each frame",
+        );
+        let mut previous_fps = 0.0;
+        for (&(_start, end), &duration) in frames.iter().zip(&durations) {
+            let fps = if duration > 0.0 {
+                1000.0 / duration
+            } else {
+                0.0
+            };
+            profile.add_counter_sample(counter, end, fps - previous_fps, 1);
+            previous_fps = fps;
+        }
+
+        let slow_threshold = stats.median * SLOW_FRAME_FACTOR;
+        let mut slow_frame_count = 0;
+        for (&(start, end), &duration) in frames.iter().zip(&durations) {
+            if duration > slow_threshold {
+                slow_frame_count += 1;
+                profile.add_marker(
+                    thread_handle,
+                    MarkerTiming::Interval(start, end),
+                    SlowFrameMarker {
+                        duration,
+                        median_duration: stats.median,
+                    },
+                );
+            }
+        }
+
+        eprintln!(
+            "{} frames, {:.1} fps on average: mean {:.2}ms, median {:.2}ms, p95 {:.2}ms, p99 {:.2}ms, max {:.2}ms, {} slow frames (> {:.2}ms)",
+            stats.count,
+            1000.0 / stats.mean,
+            stats.mean,
+            stats.median,
+            stats.p95,
+            stats.p99,
+            stats.max,
+            slow_frame_count,
+            slow_threshold,
+        );
+    }
+}
+
+fn duration_ms(start: Timestamp, end: Timestamp) -> f64 {
+    end.nanos_since_reference()
+        .saturating_sub(start.nanos_since_reference()) as f64
+        / 1_000_000.0
+}
+
+#[derive(Debug, Clone)]
+pub struct SlowFrameMarker {
+    pub duration: f64,
+    pub median_duration: f64,
+}
+
+impl StaticSchemaMarker for SlowFrameMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "SlowFrame";
+
+    const CATEGORY: Category<'static> = Category("Graphics", CategoryColor::Red);
+    const DESCRIPTION: Option<&'static str> =
+        Some("A frame which took more than twice as long as the median frame on the same thread.");
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.duration}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("Slow frame: {marker.data.duration}");
+    const TABLE_LABEL: Option<&'static str> =
+        Some("{marker.data.duration} (median {marker.data.medianDuration})");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "duration",
+            label: "Duration",
+            format: MarkerFieldFormat::Milliseconds,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "medianDuration",
+            label: "Median frame duration",
+            format: MarkerFieldFormat::Milliseconds,
+            flags: MarkerFieldFlags::empty(),
+        },
+    ];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.handle_for_string("Slow frame")
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        unreachable!()
+    }
+
+    fn number_field_value(&self, field_index: u32) -> f64 {
+        match field_index {
+            0 => self.duration,
+            1 => self.median_duration,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stats_and_boundaries() {
+        let stats = FrameStats::from_durations(&[16.0, 17.0, 16.0, 50.0]).unwrap();
+        assert_eq!(stats.count, 4);
+        assert_eq!(stats.mean, 24.75);
+        assert_eq!(stats.median, 17.0);
+        assert_eq!(stats.max, 50.0);
+        assert_eq!(FrameStats::from_durations(&[]), None);
+
+        let t = Timestamp::from_millis_since_reference;
+        assert_eq!(
+            frames_between_boundaries(vec![t(32.0), t(0.0), t(16.0)]),
+            vec![(t(0.0), t(16.0)), (t(16.0), t(32.0))]
+        );
+        assert_eq!(duration_ms(t(16.0), t(32.5)), 16.5);
+    }
+}
@@ -0,0 +1,81 @@
+//! Randomizes the time between samples, so that sampling doesn't stay in
+//! lock-step with periodic work in the profiled program. For example, a 60 Hz
+//! render loop sampled at 1 kHz sees the same few phases of each frame over and
+//! over, which can make short functions look much hotter or colder than they
+//! are.
+
+use std::time::Duration;
+
+use fxprof_processed_profile::Profile;
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy)]
+pub struct IntervalJitter {
+    interval: Duration,
+    /// The maximum deviation from `interval`, as a fraction of it.
+    fraction: f64,
+}
+
+impl IntervalJitter {
+    /// `percent` is the maximum deviation from `interval` in either direction.
+    pub fn new(interval: Duration, percent: f64) -> Self {
+        Self {
+            interval,
+            fraction: (percent / 100.0).clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.fraction > 0.0
+    }
+
+    /// Returns a random interval, uniformly distributed within the jitter
+    /// range around the configured interval.
+    pub fn next_interval(&self) -> Duration {
+        if !self.is_enabled() {
+            return self.interval;
+        }
+        let factor = rand::rng().random_range(1.0 - self.fraction..=1.0 + self.fraction);
+        self.interval.mul_f64(factor)
+    }
+
+    /// Records the jitter settings in the profile's metadata.
+    pub fn add_to_profile_meta(&self, profile: &mut Profile) {
+        if !self.is_enabled() {
+            return;
+        }
+        let nominal_ms = self.interval.as_secs_f64() * 1000.0;
+        profile.add_extra_info(
+            "Sampling",
+            "Interval jitter",
+            &format!(
+                "±{}% ({:.3}ms to {:.3}ms)",
+                self.fraction * 100.0,
+                nominal_ms * (1.0 - self.fraction),
+                nominal_ms * (1.0 + self.fraction)
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intervals_stay_in_range() {
+        let jitter = IntervalJitter::new(Duration::from_millis(1), 20.0);
+        for _ in 0..1000 {
+            let interval = jitter.next_interval();
+            assert!(interval >= Duration::from_micros(800), "{interval:?}");
+            assert!(interval <= Duration::from_micros(1200), "{interval:?}");
+        }
+    }
+
+    #[test]
+    fn zero_percent_is_disabled() {
+        let jitter = IntervalJitter::new(Duration::from_millis(1), 0.0);
+        assert!(!jitter.is_enabled());
+        assert_eq!(jitter.next_interval(), Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,176 @@
+//! Writes the code of JIT-compiled functions into a jitdump file, for
+//! `samply record --save-jit-code`, so that the assembly view also works for
+//! JIT code which is only described by a perf map file.
+//!
+//! The functions are written in the order of the perf map entries. A jitdump
+//! file's relative address space lays out the code of its functions in
+//! sequence, just like the fake perf map library does, so the relative
+//! addresses in the profile can be looked up in the jitdump file.
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use linux_perf_data::jitdump::JitDumpHeader;
+
+/// The ELF machine architecture of this machine, for the jitdump header.
+const ELF_MACHINE_ARCH: u32 = if cfg!(target_arch = "x86_64") {
+    62
+} else if cfg!(target_arch = "aarch64") {
+    183
+} else if cfg!(target_arch = "x86") {
+    3
+} else if cfg!(target_arch = "arm") {
+    40
+} else if cfg!(target_arch = "riscv64") {
+    243
+} else {
+    0
+};
+
+const JIT_CODE_LOAD: u32 = 0;
+
+pub struct JitCodeDump {
+    path: PathBuf,
+    header: JitDumpHeader,
+    writer: BufWriter<File>,
+    /// The memory of the JIT process, or `None` if we can't read it.
+    memory: Option<File>,
+    code_index: u64,
+}
+
+impl JitCodeDump {
+    /// Creates `jit-<pid>.dump` in `dir`.
+    pub fn create(dir: &Path, pid: u32) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("jit-{pid}.dump"));
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let header = JitDumpHeader {
+            magic: *b"DTiJ",
+            version: 1,
+            total_size: JitDumpHeader::SIZE as u32,
+            elf_machine_arch: ELF_MACHINE_ARCH,
+            pid,
+            timestamp,
+            flags: 0,
+        };
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writer.write_all(&header.magic)?;
+        writer.write_all(&header.version.to_le_bytes())?;
+        writer.write_all(&header.total_size.to_le_bytes())?;
+        writer.write_all(&header.elf_machine_arch.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?; // padding
+        writer.write_all(&header.pid.to_le_bytes())?;
+        writer.write_all(&header.timestamp.to_le_bytes())?;
+        writer.write_all(&header.flags.to_le_bytes())?;
+        let memory = File::open(format!("/proc/{pid}/mem")).ok();
+        Ok(Self {
+            path,
+            header,
+            writer,
+            memory,
+            code_index: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn header(&self) -> &JitDumpHeader {
+        &self.header
+    }
+
+    /// Copies the code of a function from the JIT process's memory. If the
+    /// memory can't be read, e.g. because the function has been freed, the
+    /// code is written as zeros, so that the following functions still end
+    /// up at the right relative addresses.
+    pub fn add_function(
+        &mut self,
+        name: &str,
+        start_avma: u64,
+        len: u64,
+        timestamp: u64,
+    ) -> std::io::Result<()> {
+        let mut code = vec![0; len as usize];
+        if let Some(memory) = self.memory.as_mut() {
+            let read_result = memory
+                .seek(SeekFrom::Start(start_avma))
+                .and_then(|_| memory.read_exact(&mut code));
+            if read_result.is_err() {
+                code.fill(0);
+            }
+        }
+
+        // The record header, the fixed-size fields, the name with its nul
+        // terminator, and the code.
+        let total_size = 16 + 40 + name.len() + 1 + code.len();
+        let w = &mut self.writer;
+        w.write_all(&JIT_CODE_LOAD.to_le_bytes())?;
+        w.write_all(&(total_size as u32).to_le_bytes())?;
+        w.write_all(&timestamp.to_le_bytes())?;
+        w.write_all(&self.header.pid.to_le_bytes())?;
+        w.write_all(&self.header.pid.to_le_bytes())?; // tid
+        w.write_all(&start_avma.to_le_bytes())?; // vma
+        w.write_all(&start_avma.to_le_bytes())?; // code_addr
+        w.write_all(&len.to_le_bytes())?;
+        w.write_all(&self.code_index.to_le_bytes())?;
+        w.write_all(name.as_bytes())?;
+        w.write_all(&[0])?;
+        w.write_all(&code)?;
+        self.code_index += 1;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for JitCodeDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JitCodeDump")
+            .field("path", &self.path)
+            .field("code_index", &self.code_index)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use linux_perf_data::jitdump::{JitDumpReader, JitDumpRecord};
+
+    use super::*;
+
+    #[test]
+    fn readable_as_jitdump() {
+        let dir = std::env::temp_dir().join(format!("samply-jit-code-dump-{}", std::process::id()));
+        let mut dump = JitCodeDump::create(&dir, 0).unwrap();
+        // pid 0 has no memory that we can read, so the code is zeros.
+        dump.add_function("first", 0x1000, 16, 5).unwrap();
+        dump.add_function("second", 0x2000, 8, 6).unwrap();
+        let path = dump.path().to_owned();
+        drop(dump);
+
+        let mut reader = JitDumpReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.header().elf_machine_arch, ELF_MACHINE_ARCH);
+        let mut functions = Vec::new();
+        while let Some(record) = reader.next_record().unwrap() {
+            let JitDumpRecord::CodeLoad(record) = record.parse().unwrap() else {
+                panic!("expected only code load records");
+            };
+            functions.push((
+                String::from_utf8(record.function_name.as_slice().to_vec()).unwrap(),
+                record.code_addr,
+                record.code_bytes.len(),
+            ));
+        }
+        assert_eq!(
+            functions,
+            vec![
+                ("first".to_string(), 0x1000, 16),
+                ("second".to_string(), 0x2000, 8)
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,11 +2,13 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use fxprof_processed_profile::{
-    LibraryHandle, MarkerTiming, Profile, Symbol, SymbolTable, ThreadHandle,
+    LibraryHandle, MarkerTiming, Profile, SourceLocation, Symbol, SymbolTable, ThreadHandle,
+};
+use linux_perf_data::jitdump::{
+    JitCodeDebugInfoRecord, JitDumpReader, JitDumpRecord, JitDumpRecordType,
 };
-use linux_perf_data::jitdump::{JitDumpReader, JitDumpRecord, JitDumpRecordType};
 
-use super::jit_category_manager::JitCategoryManager;
+use super::jit_category_manager::{JitCategoryManager, JsFrame, JsName};
 use super::jit_function_add_marker::JitFunctionAddMarker;
 use super::jit_function_recycler::JitFunctionRecycler;
 use super::lib_mappings::{
@@ -103,6 +105,16 @@ impl JitDumpManager {
             .map(|processor| processor.finish(profile))
             .collect()
     }
+
+    /// Like [`finish`](Self::finish), but keeps the jitdump files open: commits
+    /// copies of their symbol tables so far to `profile`, and returns copies of
+    /// their lib mapping ops.
+    pub fn snapshot(&self, profile: &mut Profile) -> Vec<LibMappingOpQueue> {
+        self.processors
+            .iter()
+            .map(|processor| processor.snapshot(profile))
+            .collect()
+    }
 }
 
 #[derive(Debug)]
@@ -122,6 +134,11 @@ struct SingleJitDumpProcessor {
     /// relative address is the sum of the `code_size`s of all the `JIT_CODE_LOAD`
     /// entries that came before it in the file.
     cumulative_address: u32,
+
+    /// The code address and source location from the most recent JIT_CODE_DEBUG_INFO
+    /// record. The debug info record for a function comes right before its
+    /// JIT_CODE_LOAD record.
+    pending_source_location: Option<(u64, SourceLocation)>,
 }
 
 impl SingleJitDumpProcessor {
@@ -137,6 +154,7 @@ impl SingleJitDumpProcessor {
             symbols: Default::default(),
             thread_handle,
             cumulative_address: 0,
+            pending_source_location: None,
         }
     }
 
@@ -156,13 +174,13 @@ impl SingleJitDumpProcessor {
                 JitDumpRecordType::JIT_CODE_LOAD
                 | JitDumpRecordType::JIT_CODE_MOVE
                 | JitDumpRecordType::JIT_CODE_UNWINDING_INFO
+                | JitDumpRecordType::JIT_CODE_DEBUG_INFO
                 | JitDumpRecordType::JIT_CODE_CLOSE => {
-                    // These are interesting.
+                    // These are interesting. JIT_CODE_DEBUG_INFO records can be big,
+                    // but we need them for the source location of each function.
                 }
                 _ => {
-                    // We skip other records. We especially want to skip JIT_CODE_DEBUG_INFO
-                    // records because they can be big and we don't need to read them from
-                    // the file.
+                    // We skip other records.
                     if let Ok(true) = reader.skip_next_record() {
                         continue;
                     } else {
@@ -213,15 +231,31 @@ impl SingleJitDumpProcessor {
                             (self.lib_handle, relative_address_at_start)
                         };
 
-                    let (category, js_frame) =
+                    let source_location = match self.pending_source_location.take() {
+                        Some((code_addr, source_location)) if code_addr == start_avma => {
+                            Some(source_location)
+                        }
+                        _ => None,
+                    };
+                    let (category, mut js_frame) =
                         jit_category_manager.classify_jit_symbol(symbol_name, profile);
+                    if js_frame.is_none() && source_location.is_some() {
+                        // We don't know this JIT, but we know which source function
+                        // this code was compiled from. Put a frame for the source
+                        // function above the JIT frame, so that different
+                        // compilations of the same function can be merged.
+                        js_frame = Some(JsFrame::RegularInAdditionToNativeFrame(
+                            JsName::NonSelfHosted(profile.handle_for_string(symbol_name)),
+                        ));
+                    }
                     self.lib_mapping_ops.push(
                         raw_jitdump_record.timestamp,
                         LibMappingOp::Add(LibMappingAdd {
                             start_avma,
                             end_avma,
                             relative_address_at_start,
-                            info: LibMappingInfo::new_jit_function(lib_handle, category, js_frame),
+                            info: LibMappingInfo::new_jit_function(lib_handle, category, js_frame)
+                                .with_source_location(source_location),
                         }),
                     );
                     // TODO: Add to unwinder so that it can use the code bytes for prologue / epilogue detection
@@ -237,6 +271,10 @@ impl SingleJitDumpProcessor {
                     );
                     // TODO: Remove from + add to unwinder
                 }
+                Ok(JitDumpRecord::CodeDebugInfo(record)) => {
+                    self.pending_source_location = function_source_location(&record, profile)
+                        .map(|source_location| (record.code_addr, source_location));
+                }
                 Ok(JitDumpRecord::CodeUnwindingInfo(_unwinding_info)) => {
                     // TODO: Queue up, and add to unwinder on next CodeLoad
                 }
@@ -266,4 +304,30 @@ impl SingleJitDumpProcessor {
         self.close_and_commit_symbol_table(profile);
         self.lib_mapping_ops
     }
+
+    fn snapshot(&self, profile: &mut Profile) -> LibMappingOpQueue {
+        if self.reader.is_some() {
+            // Once closed, the symbol table has already been committed.
+            let symbol_table = SymbolTable::new(self.symbols.clone());
+            profile.set_lib_symbol_table(self.lib_handle, Arc::new(symbol_table));
+        }
+        self.lib_mapping_ops.clone()
+    }
+}
+
+/// Returns the source location of the start of the function, from the first
+/// line entry of its debug info.
+fn function_source_location(
+    record: &JitCodeDebugInfoRecord,
+    profile: &mut Profile,
+) -> Option<SourceLocation> {
+    let entry = record.entries.first()?;
+    let file_path = entry.file_path.as_slice();
+    let file_path = std::str::from_utf8(&file_path).ok()?;
+    Some(SourceLocation {
+        file_path: Some(profile.handle_for_string(file_path)),
+        line: Some(entry.line),
+        // Jitdump columns are 1-based, with 0 meaning "unknown".
+        col: entry.column.checked_sub(1),
+    })
 }
@@ -1,6 +1,7 @@
 use std::iter::Peekable;
+use std::sync::Arc;
 
-use fxprof_processed_profile::{LibMappings, LibraryHandle, SubcategoryHandle};
+use fxprof_processed_profile::{LibMappings, LibraryHandle, SourceLocation, SubcategoryHandle};
 
 use super::jit_category_manager::JsFrame;
 
@@ -10,6 +11,9 @@ pub struct LibMappingInfo {
     pub category: Option<SubcategoryHandle>,
     pub js_frame: Option<JsFrame>,
     pub art_info: Option<AndroidArtInfo>,
+    /// The source location of the JIT function, from the debug info in a
+    /// jitdump file. Used for the JS frame which is prepended to the JIT frame.
+    pub source_location: Option<SourceLocation>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -29,16 +33,17 @@ impl LibMappingInfo {
             category: None,
             js_frame: None,
             art_info: None,
+            source_location: None,
         }
     }
 
-    #[allow(unused)]
     pub fn new_lib_with_category(lib_handle: LibraryHandle, category: SubcategoryHandle) -> Self {
         Self {
             lib_handle,
             category: Some(category),
             js_frame: None,
             art_info: None,
+            source_location: None,
         }
     }
 
@@ -52,15 +57,23 @@ impl LibMappingInfo {
             category: Some(category),
             js_frame,
             art_info: None,
+            source_location: None,
         }
     }
 
+    /// Sets the source location of the JIT function.
+    pub fn with_source_location(mut self, source_location: Option<SourceLocation>) -> Self {
+        self.source_location = source_location;
+        self
+    }
+
     pub fn new_libart_mapping(lib_handle: LibraryHandle) -> Self {
         Self {
             lib_handle,
             category: None,
             js_frame: None,
             art_info: Some(AndroidArtInfo::LibArt),
+            source_location: None,
         }
     }
 
@@ -73,6 +86,7 @@ impl LibMappingInfo {
             category,
             js_frame: None,
             art_info: Some(AndroidArtInfo::JavaFrame),
+            source_location: None,
         }
     }
 }
@@ -81,7 +95,6 @@ impl LibMappingInfo {
 pub struct LibMappingsHierarchy {
     regular_libs: (LibMappings<LibMappingInfo>, LibMappingOpQueueIter),
     jitdumps: Vec<(LibMappings<LibMappingInfo>, LibMappingOpQueueIter)>,
-    perf_map: Option<LibMappings<LibMappingInfo>>,
 }
 
 impl LibMappingsHierarchy {
@@ -89,7 +102,6 @@ impl LibMappingsHierarchy {
         Self {
             regular_libs: (LibMappings::default(), regular_lib_mappings_ops.into_iter()),
             jitdumps: Vec::new(),
-            perf_map: None,
         }
     }
 
@@ -98,10 +110,6 @@ impl LibMappingsHierarchy {
             .push((LibMappings::default(), lib_mappings_ops.into_iter()));
     }
 
-    pub fn add_perf_map_mappings(&mut self, mappings: LibMappings<LibMappingInfo>) {
-        self.perf_map = Some(mappings);
-    }
-
     pub fn process_ops(&mut self, timestamp: u64) {
         while let Some(op) = self.regular_libs.1.next_op_if_at_or_before(timestamp) {
             op.apply_to(&mut self.regular_libs.0);
@@ -122,29 +130,59 @@ impl LibMappingsHierarchy {
                 return Some(x);
             }
         }
-        if let Some(perf_map) = &self.perf_map {
-            if let Some(x) = perf_map.convert_address(address) {
-                return Some(x);
-            }
-        }
         None
     }
 }
 
+/// The lib mapping ops of a process, in the order in which they were pushed.
+///
+/// A forked child process starts out with the ops of its parent. Fork-heavy
+/// workloads like shells and test harnesses create many processes with the
+/// same long list of ops, so the ops are shared between the parent and its
+/// children, see [`LibMappingOpQueue::share`]. Only the ops which are pushed
+/// after the fork are owned by each process.
 #[derive(Debug, Clone, Default)]
-pub struct LibMappingOpQueue(Vec<(u64, LibMappingOp)>);
+pub struct LibMappingOpQueue {
+    shared: Arc<Vec<(u64, LibMappingOp)>>,
+    own: Vec<(u64, LibMappingOp)>,
+}
 
 impl LibMappingOpQueue {
     pub fn push(&mut self, timestamp: u64, op: LibMappingOp) {
-        self.0.push((timestamp, op));
+        self.own.push((timestamp, op));
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.shared.is_empty() && self.own.is_empty()
+    }
+
+    /// Returns a queue with the same ops, for a forked child process. Both
+    /// queues share the ops pushed so far, without copying them. If this queue
+    /// has new ops since it was last shared, they're combined with the shared ops
+    /// first, which is the only time the shared ops are copied.
+    pub fn share(&mut self) -> Self {
+        if !self.own.is_empty() {
+            let mut shared = std::mem::take(&mut self.shared);
+            Arc::make_mut(&mut shared).append(&mut self.own);
+            self.shared = shared;
+        }
+        Self {
+            shared: self.shared.clone(),
+            own: Vec::new(),
+        }
     }
 
     pub fn into_iter(self) -> LibMappingOpQueueIter {
-        LibMappingOpQueueIter(self.0.into_iter().peekable())
+        let mut ops = match Arc::try_unwrap(self.shared) {
+            Ok(shared) => shared,
+            Err(shared) => shared.as_ref().clone(),
+        };
+        ops.extend(self.own);
+        // Ops are usually pushed in timestamp order, but not when they come from
+        // several trace files which are processed one after the other, e.g. rundowns.
+        // The sort is stable, so ops with the same timestamp keep their order.
+        ops.sort_by_key(|(timestamp, _op)| *timestamp);
+        LibMappingOpQueueIter(ops.into_iter().peekable())
     }
 }
 
@@ -165,7 +203,6 @@ impl LibMappingOpQueueIter {
 pub enum LibMappingOp {
     Add(LibMappingAdd),
     Move(LibMappingMove),
-    #[allow(unused)]
     Remove(LibMappingRemove),
     Clear,
 }
@@ -218,8 +255,50 @@ pub struct LibMappingMove {
     pub new_end_avma: u64,
 }
 
-#[allow(unused)]
 #[derive(Debug, Clone)]
 pub struct LibMappingRemove {
     pub start_avma: u64,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn add(start_avma: u64) -> LibMappingOp {
+        LibMappingOp::Move(LibMappingMove {
+            old_start_avma: 0,
+            new_start_avma: start_avma,
+            new_end_avma: start_avma + 0x1000,
+        })
+    }
+
+    /// The new start addresses of the ops in the queue, with 0 for a clear.
+    fn start_avmas(queue: LibMappingOpQueue) -> Vec<u64> {
+        let mut iter = queue.into_iter();
+        std::iter::from_fn(|| iter.next_op_if_at_or_before(u64::MAX))
+            .map(|op| match op {
+                LibMappingOp::Move(op) => op.new_start_avma,
+                _ => 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shared_ops_after_fork() {
+        let mut parent = LibMappingOpQueue::default();
+        parent.push(1, add(0x1000));
+        let mut child = parent.share();
+        let second_child = parent.share();
+        assert!(Arc::ptr_eq(&child.shared, &second_child.shared));
+
+        parent.push(2, add(0x2000));
+        child.push(3, LibMappingOp::Clear);
+        child.push(4, add(0x3000));
+        let grandchild = child.share();
+
+        assert_eq!(start_avmas(parent), vec![0x1000, 0x2000]);
+        assert_eq!(start_avmas(child), vec![0x1000, 0, 0x3000]);
+        assert_eq!(start_avmas(second_child), vec![0x1000]);
+        assert_eq!(start_avmas(grandchild), vec![0x1000, 0, 0x3000]);
+    }
+}
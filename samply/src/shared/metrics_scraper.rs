@@ -0,0 +1,507 @@
+//! Records application metrics as counter tracks, for
+//! `samply record --scrape-metrics`. Metrics are either scraped from a
+//! Prometheus endpoint in regular intervals, or received as statsd packets.
+//!
+//! The metrics are collected with wall-clock timestamps on background threads
+//! while the profiler runs, and are added to the profile JSON when it's saved,
+//! aligned with the samples via the profile's start time.
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+use indexmap::IndexMap;
+use serde_json::{json, Value};
+
+use super::derived_counters::counter_samples_json;
+
+/// The category of the added counters.
+const METRICS_CATEGORY: &str = "Metrics";
+
+/// How often Prometheus endpoints are scraped by default.
+pub const DEFAULT_METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub enum MetricsSource {
+    /// Scrape the Prometheus text format from this URL.
+    Prometheus { url: String, names: Vec<String> },
+    /// Listen for statsd packets on this UDP address.
+    Statsd {
+        addr: SocketAddr,
+        names: Vec<String>,
+    },
+}
+
+impl MetricsSource {
+    /// Parses `http(s)://...` or `statsd://HOST:PORT`, with an optional
+    /// `names=foo,bar` query parameter which selects the metrics to record.
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        let (base, names) = split_names_param(arg);
+        if let Some(addr) = base.strip_prefix("statsd://") {
+            let addr = addr
+                .trim_end_matches('/')
+                .parse()
+                .map_err(|e| format!("invalid statsd address {addr:?}: {e}"))?;
+            Ok(MetricsSource::Statsd { addr, names })
+        } else if base.starts_with("http://") || base.starts_with("https://") {
+            Ok(MetricsSource::Prometheus { url: base, names })
+        } else {
+            Err("expected an http:// or https:// URL, or statsd://HOST:PORT".to_string())
+        }
+    }
+
+    fn names(&self) -> &[String] {
+        match self {
+            MetricsSource::Prometheus { names, .. } | MetricsSource::Statsd { names, .. } => names,
+        }
+    }
+}
+
+/// Removes the `names` parameter from the URL's query, and returns the URL
+/// without it and the names in it.
+fn split_names_param(url: &str) -> (String, Vec<String>) {
+    let Some((base, query)) = url.split_once('?') else {
+        return (url.to_string(), Vec::new());
+    };
+    let mut names = Vec::new();
+    let mut other_params = Vec::new();
+    for param in query.split('&') {
+        match param.strip_prefix("names=") {
+            Some(list) => names.extend(
+                list.split(',')
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string),
+            ),
+            None => other_params.push(param),
+        }
+    }
+    if other_params.is_empty() {
+        (base.to_string(), names)
+    } else {
+        (format!("{base}?{}", other_params.join("&")), names)
+    }
+}
+
+/// The values of one metric, with the times at which they were recorded, in
+/// milliseconds since the Unix epoch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricSeries {
+    pub name: String,
+    pub samples: Vec<(f64, f64)>,
+}
+
+/// The values recorded from one source, by series name.
+type MetricValues = IndexMap<String, Vec<(f64, f64)>>;
+
+/// Collects metrics on a thread per source until [`MetricsScraper::finish`]
+/// is called.
+pub struct MetricsScraper {
+    /// Dropping these tells the threads to stop.
+    stop_senders: Vec<Sender<()>>,
+    threads: Vec<JoinHandle<MetricValues>>,
+}
+
+impl MetricsScraper {
+    pub fn start(sources: &[MetricsSource], interval: Duration) -> Self {
+        let mut stop_senders = Vec::new();
+        let threads = sources
+            .iter()
+            .cloned()
+            .map(|source| {
+                let (stop_sender, stop_receiver) = mpsc::channel();
+                stop_senders.push(stop_sender);
+                std::thread::spawn(move || {
+                    let stop = StopSignal(stop_receiver);
+                    match source {
+                        MetricsSource::Prometheus { url, names } => {
+                            scrape_prometheus(&url, &names, interval, &stop)
+                        }
+                        MetricsSource::Statsd { addr, names } => listen_statsd(addr, &names, &stop),
+                    }
+                })
+            })
+            .collect();
+        Self {
+            stop_senders,
+            threads,
+        }
+    }
+
+    /// Stops collecting, and returns the recorded series of all sources.
+    pub fn finish(self) -> Vec<MetricSeries> {
+        drop(self.stop_senders);
+        let mut series = Vec::new();
+        for thread in self.threads {
+            let Ok(values) = thread.join() else {
+                continue;
+            };
+            series.extend(
+                values
+                    .into_iter()
+                    .map(|(name, samples)| MetricSeries { name, samples }),
+            );
+        }
+        series
+    }
+}
+
+struct StopSignal(Receiver<()>);
+
+impl StopSignal {
+    /// Waits for up to `timeout`, and returns whether collecting should stop.
+    fn wait(&self, timeout: Duration) -> bool {
+        !matches!(self.0.recv_timeout(timeout), Err(RecvTimeoutError::Timeout))
+    }
+}
+
+fn unix_time_ms(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+}
+
+fn scrape_prometheus(
+    url: &str,
+    names: &[String],
+    interval: Duration,
+    stop: &StopSignal,
+) -> MetricValues {
+    let mut values = MetricValues::new();
+    let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    else {
+        return values;
+    };
+    let client = reqwest::Client::new();
+    let mut reported_error = false;
+    loop {
+        let start = Instant::now();
+        // The server reads the metrics at some point while handling the request,
+        // so the request's start is as good a time as any.
+        let time = unix_time_ms(SystemTime::now());
+        let response = runtime.block_on(async {
+            client
+                .get(url)
+                .timeout(interval.max(Duration::from_secs(1)))
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        });
+        match response {
+            Ok(text) => {
+                for (series, value) in parse_prometheus_text(&text, names) {
+                    values.entry(series).or_default().push((time, value));
+                }
+            }
+            Err(err) if !reported_error => {
+                eprintln!("Warning: Could not scrape metrics from {url}: {err}");
+                reported_error = true;
+            }
+            Err(_) => {}
+        }
+        if stop.wait(interval.saturating_sub(start.elapsed())) {
+            return values;
+        }
+    }
+}
+
+fn listen_statsd(addr: SocketAddr, names: &[String], stop: &StopSignal) -> MetricValues {
+    let mut values = MetricValues::new();
+    let socket = match UdpSocket::bind(addr) {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!("Warning: Could not listen for statsd packets on {addr}: {err}");
+            return values;
+        }
+    };
+    // Wake up regularly to check whether we should stop.
+    let _ = socket.set_read_timeout(Some(Duration::from_millis(100)));
+    let mut buf = vec![0; 65536];
+    loop {
+        if let Ok(len) = socket.recv(&mut buf) {
+            let time = unix_time_ms(SystemTime::now());
+            let packet = String::from_utf8_lossy(&buf[..len]);
+            for update in parse_statsd_packet(&packet, names) {
+                let samples = values.entry(update.name.to_string()).or_default();
+                let previous = samples.last().map_or(0.0, |(_, value)| *value);
+                let value = match update.kind {
+                    StatsdUpdate::Set(value) => value,
+                    StatsdUpdate::Add(delta) => previous + delta,
+                };
+                samples.push((time, value));
+            }
+        }
+        if stop.wait(Duration::ZERO) {
+            return values;
+        }
+    }
+}
+
+/// Parses the Prometheus text exposition format, and returns the value of each
+/// series whose metric name is in `names`, or of all series if `names` is empty.
+/// Series are named like the lines they come from, e.g.
+/// `http_requests_total{method="GET"}`.
+fn parse_prometheus_text(text: &str, names: &[String]) -> Vec<(String, f64)> {
+    let mut result = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let name_end = line
+            .find(|c: char| c == '{' || c.is_whitespace())
+            .unwrap_or(line.len());
+        let name = &line[..name_end];
+        if !names.is_empty() && !names.iter().any(|n| n == name) {
+            continue;
+        }
+        let series_end = if line[name_end..].starts_with('{') {
+            match labels_end(&line[name_end..]) {
+                Some(len) => name_end + len,
+                None => continue,
+            }
+        } else {
+            name_end
+        };
+        let Some(value) = line[series_end..].split_whitespace().next() else {
+            continue;
+        };
+        let Ok(value) = value.parse::<f64>() else {
+            continue;
+        };
+        if value.is_finite() {
+            result.push((line[..series_end].to_string(), value));
+        }
+    }
+    result
+}
+
+/// The length of the `{...}` label set at the start of `s`, which may contain
+/// braces inside quoted label values.
+fn labels_end(s: &str) -> Option<usize> {
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '}' if !in_quotes => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[derive(Debug, PartialEq)]
+struct StatsdMetricUpdate<'a> {
+    name: &'a str,
+    kind: StatsdUpdate,
+}
+
+#[derive(Debug, PartialEq)]
+enum StatsdUpdate {
+    /// Gauges and timers set the value.
+    Set(f64),
+    /// Counters and relative gauges change it.
+    Add(f64),
+}
+
+/// Parses the lines of a statsd packet, e.g. `requests:1|c|@0.5` or
+/// `queue_length:12|g`. Sets and unknown types are ignored.
+fn parse_statsd_packet<'a>(packet: &'a str, names: &[String]) -> Vec<StatsdMetricUpdate<'a>> {
+    let mut result = Vec::new();
+    for line in packet.lines() {
+        let Some((name, rest)) = line.trim().split_once(':') else {
+            continue;
+        };
+        if !names.is_empty() && !names.iter().any(|n| n == name) {
+            continue;
+        }
+        let mut fields = rest.split('|');
+        let (Some(value_str), Some(kind)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Ok(value) = value_str.parse::<f64>() else {
+            continue;
+        };
+        let sample_rate = fields
+            .find_map(|field| field.strip_prefix('@'))
+            .and_then(|rate| rate.parse::<f64>().ok())
+            .filter(|rate| *rate > 0.0)
+            .unwrap_or(1.0);
+        let kind = match kind {
+            "c" => StatsdUpdate::Add(value / sample_rate),
+            "g" if value_str.starts_with(['+', '-']) => StatsdUpdate::Add(value),
+            "g" | "ms" | "h" | "d" => StatsdUpdate::Set(value),
+            _ => continue,
+        };
+        result.push(StatsdMetricUpdate { name, kind });
+    }
+    result
+}
+
+/// Adds a counter for each metric series to the profile JSON, on the main
+/// thread of the first process. Samples from before the start of the profile
+/// are left out.
+pub fn add_metric_counters(profile: &mut Value, series: &[MetricSeries]) {
+    if series.is_empty() {
+        return;
+    }
+    let start_time = profile["meta"]["startTime"].as_f64().unwrap_or(0.0);
+    let threads = profile["threads"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let Some(main_thread_index) = threads
+        .iter()
+        .position(|thread| thread["isMainThread"] == true)
+        .or((!threads.is_empty()).then_some(0))
+    else {
+        eprintln!("Warning: The profile has no threads, so the scraped metrics weren't added.");
+        return;
+    };
+    let pid = threads[main_thread_index]["pid"].clone();
+
+    let mut new_counters = Vec::new();
+    for series in series {
+        let (times, values): (Vec<f64>, Vec<f64>) = series
+            .samples
+            .iter()
+            .map(|(time, value)| (time - start_time, *value))
+            .filter(|(time, _)| *time >= 0.0)
+            .unzip();
+        if times.is_empty() {
+            continue;
+        }
+        new_counters.push(json!({
+            "category": METRICS_CATEGORY,
+            "name": series.name,
+            "description": series.name,
+            "mainThreadIndex": main_thread_index,
+            "pid": pid,
+            "samples": counter_samples_json(&times, &values),
+        }));
+    }
+
+    match profile["counters"].as_array_mut() {
+        Some(counters) => counters.extend(new_counters),
+        None => profile["counters"] = Value::Array(new_counters),
+    }
+}
+
+/// Warns about selected metric names which never had a value.
+pub fn warn_about_missing_metrics(sources: &[MetricsSource], series: &[MetricSeries]) {
+    for source in sources {
+        for name in source.names() {
+            let found = series.iter().any(|s| {
+                s.name == *name
+                    || s.name
+                        .strip_prefix(name.as_str())
+                        .is_some_and(|rest| rest.starts_with('{'))
+            });
+            if !found {
+                eprintln!("Warning: No values were recorded for the metric {name:?}.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_sources() {
+        match MetricsSource::parse("http://localhost:9090/metrics?names=foo,bar&x=1").unwrap() {
+            MetricsSource::Prometheus { url, names } => {
+                assert_eq!(url, "http://localhost:9090/metrics?x=1");
+                assert_eq!(names, vec!["foo", "bar"]);
+            }
+            source => panic!("unexpected {source:?}"),
+        }
+        match MetricsSource::parse("statsd://127.0.0.1:8125").unwrap() {
+            MetricsSource::Statsd { addr, names } => {
+                assert_eq!(addr.port(), 8125);
+                assert!(names.is_empty());
+            }
+            source => panic!("unexpected {source:?}"),
+        }
+        assert!(MetricsSource::parse("localhost:9090").is_err());
+        assert!(MetricsSource::parse("statsd://localhost").is_err());
+    }
+
+    #[test]
+    fn prometheus_text() {
+        let text = r#"
+# HELP http_requests_total The total number of HTTP requests.
+# TYPE http_requests_total counter
+http_requests_total{method="post",path="/{id}"} 1027 1395066363000
+http_requests_total{method="get"} 3
+queue_length 12.5
+temperature NaN
+"#;
+        assert_eq!(
+            parse_prometheus_text(text, &[]),
+            vec![
+                (
+                    r#"http_requests_total{method="post",path="/{id}"}"#.to_string(),
+                    1027.0
+                ),
+                (r#"http_requests_total{method="get"}"#.to_string(), 3.0),
+                ("queue_length".to_string(), 12.5),
+            ]
+        );
+        assert_eq!(
+            parse_prometheus_text(text, &["queue_length".to_string()]),
+            vec![("queue_length".to_string(), 12.5)]
+        );
+    }
+
+    #[test]
+    fn statsd_packet() {
+        let packet = "requests:2|c|@0.5\nqueue:-3|g|#env:prod\nlatency:12|ms\nusers:joe|s";
+        let updates = parse_statsd_packet(packet, &[]);
+        assert_eq!(
+            updates,
+            vec![
+                StatsdMetricUpdate {
+                    name: "requests",
+                    kind: StatsdUpdate::Add(4.0)
+                },
+                StatsdMetricUpdate {
+                    name: "queue",
+                    kind: StatsdUpdate::Add(-3.0)
+                },
+                StatsdMetricUpdate {
+                    name: "latency",
+                    kind: StatsdUpdate::Set(12.0)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn counters_in_profile() {
+        let mut profile = json!({
+            "meta": { "startTime": 1000.0 },
+            "threads": [
+                { "pid": "7", "isMainThread": false },
+                { "pid": "7", "isMainThread": true },
+            ],
+        });
+        let series = [MetricSeries {
+            name: "queue".to_string(),
+            samples: vec![(900.0, 1.0), (1100.0, 5.0), (1300.0, 2.0)],
+        }];
+        add_metric_counters(&mut profile, &series);
+        let counter = &profile["counters"][0];
+        assert_eq!(counter["pid"], "7");
+        assert_eq!(counter["mainThreadIndex"], 1);
+        assert_eq!(counter["samples"]["timeDeltas"], json!([100.0, 200.0]));
+        assert_eq!(counter["samples"]["count"], json!([5.0, -3.0]));
+    }
+}
@@ -1,24 +1,54 @@
 pub mod context_switch;
+pub mod cpu_topology;
 pub mod ctrl_c;
+pub mod derived_counters;
+pub mod encryption;
+pub mod frame_stats;
 pub mod included_processes;
+pub mod interval_jitter;
 pub mod jit_category_manager;
+pub mod jit_code_dump;
 pub mod jit_function_add_marker;
 pub mod jit_function_recycler;
 pub mod jitdump_manager;
 pub mod lib_mappings;
 pub mod marker_file;
+pub mod metrics_scraper;
+pub mod name_pattern;
+pub mod open_handles;
+pub mod output_capture;
+pub mod panic_message;
 pub mod per_cpu;
 pub mod perf_map;
+// Probes are only attached by the perf_event profiler.
+#[cfg_attr(not(any(target_os = "android", target_os = "linux")), allow(dead_code))]
+pub mod probes;
 pub mod process_name;
 pub mod process_sample_data;
+pub mod profile_validation;
+pub mod profile_versioning;
 pub mod prop_types;
 pub mod recycling;
+pub mod redaction;
+// Only the ETW profiler finds restarts in the recorded process events.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub mod restarts;
+pub mod sample_labels;
+pub mod sample_weight;
 pub mod save_profile;
+pub mod sched_latency;
+pub mod self_profile;
+pub mod size_budget;
 pub mod stack_converter;
 pub mod stack_depth_limiting_frame_iter;
+pub mod suspend;
 pub mod symbol_manager_observer;
 pub mod symbol_precog;
 pub mod synthetic_jit_library;
+pub mod test_output;
+// The Linux profiler uses perf_event sampling and doesn't support rate overrides.
+#[cfg_attr(any(target_os = "android", target_os = "linux"), allow(dead_code))]
+pub mod thread_rates;
 pub mod timestamp_converter;
 pub mod types;
 pub mod unresolved_samples;
@@ -0,0 +1,38 @@
+/// Matches a name against a pattern in which `*` stands for any number of
+/// characters, e.g. a thread name for `--rate-override` or a library name for
+/// `--no-symbolicate`.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` in the pattern.
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        assert!(matches_pattern("RenderThread", "RenderThread"));
+        assert!(!matches_pattern("RenderThread", "RenderThread2"));
+        assert!(matches_pattern("Audio*", "AudioIPC Callback"));
+        assert!(matches_pattern("*Worker*", "DOM Worker #3"));
+        assert!(matches_pattern("a*b*c", "abc"));
+        assert!(!matches_pattern("a*b*c", "acb"));
+        assert!(!matches_pattern("Audio*", "MediaAudio"));
+    }
+}
@@ -0,0 +1,45 @@
+//! Counter tracks for the number of open handles (Windows) or file descriptors
+//! (Linux, macOS) of a profiled process. A count which keeps growing while the
+//! CPU graph stays flat usually means that something leaks descriptors.
+
+use std::time::Duration;
+
+use fxprof_processed_profile::{CounterHandle, ProcessHandle, Profile, Timestamp};
+
+/// How often the open handles of each profiled process are counted.
+pub const OPEN_HANDLES_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub struct OpenHandlesCounter {
+    counter: CounterHandle,
+    /// The last count which was added to the counter.
+    count: u64,
+}
+
+impl OpenHandlesCounter {
+    /// A counter named "handles", for Windows processes.
+    #[cfg(target_os = "windows")]
+    pub fn for_handles(profile: &mut Profile, process: ProcessHandle) -> Self {
+        Self::new(profile, process, "handles", "Number of open handles")
+    }
+
+    /// A counter named "fds", for Linux and macOS processes.
+    pub fn for_fds(profile: &mut Profile, process: ProcessHandle) -> Self {
+        Self::new(profile, process, "fds", "Number of open file descriptors")
+    }
+
+    fn new(profile: &mut Profile, process: ProcessHandle, name: &str, description: &str) -> Self {
+        let counter = profile.add_counter(process, name, "Handles", description);
+        Self { counter, count: 0 }
+    }
+
+    /// Adds a sample if the count has changed since the previous one.
+    pub fn add_sample(&mut self, profile: &mut Profile, timestamp: Timestamp, count: u64) {
+        if count == self.count {
+            return;
+        }
+        let delta = count as f64 - self.count as f64;
+        profile.add_counter_sample(self.counter, timestamp, delta, 1);
+        self.count = count;
+    }
+}
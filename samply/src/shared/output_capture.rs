@@ -0,0 +1,94 @@
+//! Limits for turning the stdout and stderr lines of a launched command into
+//! markers, so that a chatty command can't make the profile arbitrarily big.
+
+use std::borrow::Cow;
+
+/// Longer lines are cut off after this many bytes.
+pub const MAX_LINE_BYTES: usize = 1000;
+
+/// Once the captured lines add up to this many bytes, further lines are
+/// dropped.
+pub const MAX_TOTAL_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl OutputStream {
+    /// The marker name for lines from this stream.
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputStream::Stdout => "stdout",
+            OutputStream::Stderr => "stderr",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OutputCaptureBudget {
+    remaining_bytes: usize,
+    dropped_line_count: u64,
+}
+
+impl Default for OutputCaptureBudget {
+    fn default() -> Self {
+        Self {
+            remaining_bytes: MAX_TOTAL_BYTES,
+            dropped_line_count: 0,
+        }
+    }
+}
+
+impl OutputCaptureBudget {
+    /// Returns the line, truncated to [`MAX_LINE_BYTES`], or `None` if the
+    /// budget is used up.
+    pub fn take_line<'a>(&mut self, line: &'a str) -> Option<Cow<'a, str>> {
+        let line = truncate_line(line);
+        if line.len() > self.remaining_bytes {
+            self.remaining_bytes = 0;
+            self.dropped_line_count += 1;
+            return None;
+        }
+        self.remaining_bytes -= line.len();
+        Some(line)
+    }
+
+    /// The number of lines which didn't fit into the budget.
+    pub fn dropped_line_count(&self) -> u64 {
+        self.dropped_line_count
+    }
+}
+
+fn truncate_line(line: &str) -> Cow<'_, str> {
+    if line.len() <= MAX_LINE_BYTES {
+        return Cow::Borrowed(line);
+    }
+    let mut end = MAX_LINE_BYTES;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!("{}…", &line[..end]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn line_limits() {
+        let mut budget = OutputCaptureBudget::default();
+        assert_eq!(budget.take_line("hello").as_deref(), Some("hello"));
+
+        let long_line = "é".repeat(MAX_LINE_BYTES);
+        let truncated = budget.take_line(&long_line).unwrap();
+        assert!(truncated.ends_with('…'));
+        assert!(truncated.len() <= MAX_LINE_BYTES + '…'.len_utf8());
+
+        budget.remaining_bytes = 3;
+        assert_eq!(budget.take_line("four"), None);
+        assert_eq!(budget.take_line("a"), None);
+        assert_eq!(budget.dropped_line_count(), 2);
+    }
+}
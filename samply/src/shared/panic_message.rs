@@ -0,0 +1,120 @@
+//! Recognizes the messages which Rust's default panic hook prints to stderr,
+//! so that panics in a launched command can be shown as markers.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicMessage {
+    /// `None` for threads without a name, which are printed as `<unnamed>`.
+    pub thread_name: Option<String>,
+    /// The thread's tid, which Rust prints since version 1.89.
+    pub tid: Option<u32>,
+    pub location: String,
+    pub message: String,
+}
+
+/// Parses stderr output line by line. Understands both the current format,
+///
+/// ```text
+/// thread 'main' (12345) panicked at src/main.rs:2:5:
+/// explicit panic
+/// ```
+///
+/// in which the tid is optional, and the single-line format from before
+/// Rust 1.73: `thread 'main' panicked at 'explicit panic', src/main.rs:2:5`.
+#[derive(Debug, Default)]
+pub struct PanicMessageParser {
+    /// A panic whose message is on the next line.
+    pending: Option<PanicMessage>,
+}
+
+impl PanicMessageParser {
+    /// Returns the panic once its message has been seen.
+    pub fn push_line(&mut self, line: &str) -> Option<PanicMessage> {
+        if let Some(mut panic) = self.pending.take() {
+            panic.message = line.to_string();
+            return Some(panic);
+        }
+
+        let rest = line.strip_prefix("thread '")?;
+        let (thread_name, rest) = rest.split_once('\'')?;
+        let (tid, rest) = match rest
+            .strip_prefix(" (")
+            .and_then(|rest| rest.split_once(')'))
+        {
+            Some((tid, rest)) => (Some(tid.parse().ok()?), rest),
+            None => (None, rest),
+        };
+        let rest = rest.strip_prefix(" panicked at ")?;
+        let thread_name = (thread_name != "<unnamed>").then(|| thread_name.to_string());
+
+        if let Some(location) = rest.strip_suffix(':') {
+            self.pending = Some(PanicMessage {
+                thread_name,
+                tid,
+                location: location.to_string(),
+                message: String::new(),
+            });
+            return None;
+        }
+
+        let (message, location) = rest.strip_prefix('\'')?.rsplit_once("', ")?;
+        Some(PanicMessage {
+            thread_name,
+            tid,
+            location: location.to_string(),
+            message: message.to_string(),
+        })
+    }
+
+    /// Returns a panic whose message line never arrived, at the end of the
+    /// output.
+    pub fn finish(&mut self) -> Option<PanicMessage> {
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn panic_formats() {
+        let mut parser = PanicMessageParser::default();
+        assert_eq!(parser.push_line("Starting up"), None);
+        assert_eq!(
+            parser.push_line("thread 'main' (4242) panicked at src/main.rs:2:5:"),
+            None
+        );
+        assert_eq!(
+            parser.push_line("index out of bounds: the len is 3 but the index is 7"),
+            Some(PanicMessage {
+                thread_name: Some("main".to_string()),
+                tid: Some(4242),
+                location: "src/main.rs:2:5".to_string(),
+                message: "index out of bounds: the len is 3 but the index is 7".to_string(),
+            })
+        );
+        assert_eq!(
+            parser.push_line("note: run with `RUST_BACKTRACE=1` environment variable"),
+            None
+        );
+
+        assert_eq!(
+            parser.push_line("thread '<unnamed>' panicked at 'oh no', src/worker.rs:10:9"),
+            Some(PanicMessage {
+                thread_name: None,
+                tid: None,
+                location: "src/worker.rs:10:9".to_string(),
+                message: "oh no".to_string(),
+            })
+        );
+
+        assert_eq!(
+            parser.push_line("thread 'tokio-runtime-worker' panicked at src/lib.rs:1:1:"),
+            None
+        );
+        assert_eq!(
+            parser.finish().map(|panic| panic.thread_name),
+            Some(Some("tokio-runtime-worker".to_string()))
+        );
+    }
+}
@@ -1,60 +1,52 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use debugid::DebugId;
-use fxprof_processed_profile::{LibMappings, LibraryInfo, Profile, Symbol, SymbolTable};
+use fxprof_processed_profile::{LibraryHandle, LibraryInfo, Profile, Symbol, SymbolTable};
 
 use super::jit_category_manager::JitCategoryManager;
+use super::jit_code_dump::JitCodeDump;
 use super::jit_function_recycler::JitFunctionRecycler;
-use super::lib_mappings::LibMappingInfo;
+use super::lib_mappings::{
+    LibMappingAdd, LibMappingInfo, LibMappingOp, LibMappingOpQueue, LibMappingRemove,
+};
+use super::utils::lib_handle_for_jitdump;
 
-fn process_perf_map_line(line: &str) -> Option<(u64, u64, &str)> {
-    let mut split = line.splitn(3, ' ');
+/// How often, in nanoseconds of raw timestamp, we check the perf map file for changes.
+const PERF_MAP_CHECK_INTERVAL_NS: u64 = 100_000_000;
+
+/// A line of a perf map file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PerfMapLine<'a> {
+    /// `START SIZE name`: a JIT function.
+    Entry { start: u64, len: u64, name: &'a str },
+    /// `START SIZE`, without a name: a clear marker. The JIT has freed the
+    /// code in this range, and the entries which overlap it are gone.
+    Clear { start: u64, len: u64 },
+}
+
+fn parse_perf_map_line(line: &str) -> Option<PerfMapLine<'_>> {
+    let mut split = line.trim_end_matches('\r').splitn(3, ' ');
     let addr = split.next()?;
     let len = split.next()?;
-    let symbol_name = split.next()?;
-    if symbol_name.is_empty() {
-        return None;
-    }
-    let addr = u64::from_str_radix(addr.trim_start_matches("0x"), 16).ok()?;
+    let start = u64::from_str_radix(addr.trim_start_matches("0x"), 16).ok()?;
     let len = u64::from_str_radix(len.trim_start_matches("0x"), 16).ok()?;
-    Some((addr, len, symbol_name))
+    match split.next() {
+        Some(name) if !name.is_empty() => Some(PerfMapLine::Entry { start, len, name }),
+        _ => Some(PerfMapLine::Clear { start, len }),
+    }
 }
 
-/// Tries to load a perf mapping file that could have been generated by the process during
-/// execution.
-pub fn try_load_perf_map(
-    pid: u32,
-    profile: &mut Profile,
-    jit_category_manager: &mut JitCategoryManager,
-    mut recycler: Option<&mut JitFunctionRecycler>,
-) -> Option<LibMappings<LibMappingInfo>> {
-    let name = format!("perf-{}.map", pid);
-    let path = format!("/tmp/{name}");
-    let Ok(content) = std::fs::read_to_string(&path) else {
-        return None;
-    };
-
-    // Read the map file and set everything up so that absolute addresses
-    // in JIT code get symbolicated to the right function name.
-
-    // There are three ways to put function names into the profile:
-    //
-    //  1. Function name without address ("label frame"),
-    //  2. Address with after-the-fact symbolicated function name, and
-    //  3. Address with up-front symbolicated function name.
-    //
-    // Having the address on the frame allows the assembly view in the
-    // Firefox profiler to compute the right hitcount per instruction.
-    // However, with a perf.map file, we don't have the code bytes of the jitted
-    // code, so we have no way of displaying the instructions. So the code
-    // address is not overly useful information, and we could just discard
-    // it and use label frames for perf.map JIT frames (approach 1).
-    //
-    // We'll be using approach 3 here anyway, so our JIT frames will have
-    // both a function name and a code address.
-
-    // Create a fake "library" for the JIT code.
-    let lib_handle = profile.add_lib(LibraryInfo {
+fn perf_map_path(pid: u32) -> String {
+    format!("/tmp/perf-{pid}.map")
+}
+
+fn add_perf_map_lib(pid: u32, profile: &mut Profile) -> LibraryHandle {
+    let name = format!("perf-{pid}.map");
+    let path = perf_map_path(pid);
+    profile.add_lib(LibraryInfo {
         debug_name: name.clone(),
         name,
         debug_path: path.clone(),
@@ -62,52 +54,467 @@ pub fn try_load_perf_map(
         debug_id: DebugId::nil(),
         code_id: None,
         arch: None,
-    });
-
-    let mut symbols = Vec::new();
-    let mut mappings = LibMappings::new();
-    let mut cumulative_address = 0;
-
-    for (addr, len, symbol_name) in content.lines().filter_map(process_perf_map_line) {
-        let start_address = addr;
-        let end_address = addr + len;
-        let code_size = len as u32;
-
-        // Pretend that all JIT code is laid out consecutively in our fake library.
-        // This relative address is used for symbolication whenever we add a frame
-        // to the profile.
-        let relative_address = cumulative_address;
-        cumulative_address += code_size;
-
-        // Add a symbol for this function to the fake library's symbol table.
-        // This symbol will be looked up when the address is added to the profile,
-        // based on the relative address.
-        symbols.push(Symbol {
-            address: relative_address,
-            size: Some(code_size),
-            name: symbol_name.to_owned(),
-        });
+    })
+}
+
+/// Reads a process's perf map file incrementally while the profile is being
+/// converted, and records when each entry appeared.
+///
+/// Long-running JITs append to their perf map file throughout their lifetime, and
+/// they can reuse code addresses for different functions. If we only read the file
+/// once at the end, samples in reused code would be attributed to whichever
+/// function was written last. Instead, each entry is turned into a lib mapping op
+/// with the timestamp at which we first saw it, so that later entries only
+/// replace earlier ones for samples taken after that point.
+///
+/// The entries which are present when the file is first read are treated as
+/// having existed from the start. If the file shrinks, we assume it was rewritten
+/// from scratch, and clear all mappings from the previous contents. A line with
+/// an address and a size but no name is a clear marker, which removes the
+/// entries that overlap its range, e.g. because the JIT freed their code.
+///
+/// While the profile is recorded, only complete lines are read, because the
+/// JIT may be in the middle of writing the last one. When the profile is
+/// finished, the last line is read even if it doesn't end with a newline.
+///
+/// If a directory for JIT code is given, the code of each function is copied
+/// out of the process's memory when its entry is read, into a jitdump file
+/// which then serves as the fake library, so that the assembly view works.
+#[derive(Debug)]
+pub struct PerfMapProcessor {
+    pid: u32,
+    path: PathBuf,
+    jit_code_dir: Option<PathBuf>,
+    state: Option<PerfMapState>,
+    lib_mapping_ops: LibMappingOpQueue,
+    last_timestamp: u64,
+    next_check_timestamp: u64,
+}
+
+#[derive(Debug)]
+struct PerfMapState {
+    lib_handle: LibraryHandle,
+    symbols: Vec<Symbol>,
+    jit_code_dump: Option<JitCodeDump>,
+    /// The number of bytes of the file that we've consumed, always at a line boundary.
+    consumed_len: u64,
+    /// The relative address of the next function in our fake library.
+    cumulative_address: u32,
+    /// The address ranges of the entries which haven't been replaced or
+    /// cleared yet, as a map from the start address to the end address.
+    live_entries: BTreeMap<u64, u64>,
+}
+
+impl PerfMapProcessor {
+    pub fn new(pid: u32, jit_code_dir: Option<PathBuf>) -> Self {
+        Self {
+            pid,
+            path: PathBuf::from(perf_map_path(pid)),
+            jit_code_dir,
+            state: None,
+            lib_mapping_ops: Default::default(),
+            last_timestamp: 0,
+            next_check_timestamp: 0,
+        }
+    }
 
-        let (lib_handle, relative_address) = if let Some(recycler) = recycler.as_deref_mut() {
-            recycler.recycle(symbol_name, code_size, lib_handle, relative_address)
-        } else {
-            (lib_handle, relative_address)
+    /// Checks the perf map file for new entries, at most every 100ms of raw timestamp.
+    pub fn check_for_updates(
+        &mut self,
+        timestamp: u64,
+        profile: &mut Profile,
+        jit_category_manager: &mut JitCategoryManager,
+        recycler: Option<&mut JitFunctionRecycler>,
+    ) {
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+        if timestamp < self.next_check_timestamp {
+            return;
+        }
+        self.next_check_timestamp = timestamp.saturating_add(PERF_MAP_CHECK_INTERVAL_NS);
+        self.read_new_entries(timestamp, false, profile, jit_category_manager, recycler);
+    }
+
+    /// Reads the lines which were added since the last read. A last line
+    /// without a trailing newline is only read if `is_final_read` is true.
+    fn read_new_entries(
+        &mut self,
+        timestamp: u64,
+        is_final_read: bool,
+        profile: &mut Profile,
+        jit_category_manager: &mut JitCategoryManager,
+        mut recycler: Option<&mut JitFunctionRecycler>,
+    ) {
+        let Ok(mut file) = std::fs::File::open(&self.path) else {
+            return;
+        };
+        let Ok(file_len) = file.metadata().map(|m| m.len()) else {
+            return;
         };
 
-        let (category, js_frame) = jit_category_manager.classify_jit_symbol(symbol_name, profile);
+        // There are three ways to put function names into the profile:
+        //
+        //  1. Function name without address ("label frame"),
+        //  2. Address with after-the-fact symbolicated function name, and
+        //  3. Address with up-front symbolicated function name.
+        //
+        // Having the address on the frame allows the assembly view in the
+        // Firefox profiler to compute the right hitcount per instruction.
+        // However, with a perf.map file, we don't have the code bytes of the jitted
+        // code, unless we copy them out of the process's memory into a jitdump
+        // file. So we use approach 3, and our JIT frames have both a function
+        // name and a code address.
+        let is_first_read = self.state.is_none();
+        let pid = self.pid;
+        let jit_code_dir = self.jit_code_dir.as_deref();
+        let state = self.state.get_or_insert_with(|| {
+            let jit_code_dump = jit_code_dir.and_then(|dir| match JitCodeDump::create(dir, pid) {
+                Ok(dump) => Some(dump),
+                Err(e) => {
+                    eprintln!("Could not create the JIT code file for process {pid}: {e}");
+                    None
+                }
+            });
+            let lib_handle = match &jit_code_dump {
+                Some(dump) => lib_handle_for_jitdump(dump.path(), dump.header(), profile),
+                None => add_perf_map_lib(pid, profile),
+            };
+            PerfMapState {
+                lib_handle,
+                symbols: Vec::new(),
+                jit_code_dump,
+                consumed_len: 0,
+                cumulative_address: 0,
+                live_entries: BTreeMap::new(),
+            }
+        });
+
+        if file_len < state.consumed_len {
+            // The file was truncated and rewritten. Forget the old entries.
+            self.lib_mapping_ops.push(timestamp, LibMappingOp::Clear);
+            state.live_entries.clear();
+            state.consumed_len = 0;
+        }
+        if file_len == state.consumed_len {
+            return;
+        }
+
+        let mut new_bytes = Vec::new();
+        if file.seek(SeekFrom::Start(state.consumed_len)).is_err()
+            || file.read_to_end(&mut new_bytes).is_err()
+        {
+            return;
+        }
+
+        // Only consume complete lines while recording; the JIT may be in the
+        // middle of writing one.
+        if !is_final_read {
+            let Some(last_newline_pos) = new_bytes.iter().rposition(|b| *b == b'\n') else {
+                return;
+            };
+            new_bytes.truncate(last_newline_pos + 1);
+        }
+        state.consumed_len += new_bytes.len() as u64;
+
+        // Entries which were already present the first time we read the file may
+        // have been written at any point before that, so apply them from the start.
+        let op_timestamp = if is_first_read { 0 } else { timestamp };
+
+        let content = String::from_utf8_lossy(&new_bytes);
+        for line in content.lines().filter_map(parse_perf_map_line) {
+            let (start_avma, len, symbol_name) = match line {
+                PerfMapLine::Entry { start, len, name } => (start, len, name),
+                PerfMapLine::Clear { start, len } => {
+                    let end = start.saturating_add(len);
+                    for start_avma in remove_overlapping(&mut state.live_entries, start, end) {
+                        self.lib_mapping_ops.push(
+                            op_timestamp,
+                            LibMappingOp::Remove(LibMappingRemove { start_avma }),
+                        );
+                    }
+                    continue;
+                }
+            };
+            let end_avma = start_avma.saturating_add(len);
+            // Adding the mapping replaces the overlapping entries.
+            remove_overlapping(&mut state.live_entries, start_avma, end_avma);
+            state.live_entries.insert(start_avma, end_avma);
+
+            let code_size = len as u32;
+            let relative_address = state.cumulative_address;
+            state.cumulative_address += code_size;
+
+            state.symbols.push(Symbol {
+                address: relative_address,
+                size: Some(code_size),
+                name: symbol_name.to_owned(),
+            });
+
+            if let Some(dump) = state.jit_code_dump.as_mut() {
+                if let Err(e) = dump.add_function(symbol_name, start_avma, len, timestamp) {
+                    eprintln!(
+                        "Could not write JIT code to {}: {e}",
+                        dump.path().to_string_lossy()
+                    );
+                    state.jit_code_dump = None;
+                }
+            }
+
+            let (lib_handle, relative_address_at_start) =
+                if let Some(recycler) = recycler.as_deref_mut() {
+                    recycler.recycle(symbol_name, code_size, state.lib_handle, relative_address)
+                } else {
+                    (state.lib_handle, relative_address)
+                };
+
+            let (category, js_frame) =
+                jit_category_manager.classify_jit_symbol(symbol_name, profile);
+            self.lib_mapping_ops.push(
+                op_timestamp,
+                LibMappingOp::Add(LibMappingAdd {
+                    start_avma,
+                    end_avma,
+                    relative_address_at_start,
+                    info: LibMappingInfo::new_jit_function(lib_handle, category, js_frame),
+                }),
+            );
+        }
+    }
+
+    /// Reads any remaining entries, commits the symbol table of the fake perf map
+    /// library, and returns the lib mapping ops. Returns `None` if there was no
+    /// perf map file for this process.
+    pub fn finish(
+        mut self,
+        profile: &mut Profile,
+        jit_category_manager: &mut JitCategoryManager,
+        recycler: Option<&mut JitFunctionRecycler>,
+    ) -> Option<LibMappingOpQueue> {
+        self.read_new_entries(
+            self.last_timestamp,
+            true,
+            profile,
+            jit_category_manager,
+            recycler,
+        );
+        let state = self.state?;
+        profile.set_lib_symbol_table(state.lib_handle, Arc::new(SymbolTable::new(state.symbols)));
+        Some(self.lib_mapping_ops)
+    }
+
+    /// Like [`finish`](Self::finish), but only with the entries which have
+    /// been read so far, and without consuming the processor.
+    pub fn snapshot(&self, profile: &mut Profile) -> Option<LibMappingOpQueue> {
+        let state = self.state.as_ref()?;
+        let symbol_table = SymbolTable::new(state.symbols.clone());
+        profile.set_lib_symbol_table(state.lib_handle, Arc::new(symbol_table));
+        Some(self.lib_mapping_ops.clone())
+    }
+}
+
+/// Removes the entries which overlap `start..end` from `live_entries`, and
+/// returns their start addresses.
+fn remove_overlapping(live_entries: &mut BTreeMap<u64, u64>, start: u64, end: u64) -> Vec<u64> {
+    // Entries don't overlap each other, so at most one entry which starts
+    // before `start` can reach into the range.
+    let entry_before = live_entries
+        .range(..start)
+        .next_back()
+        .filter(|(_, entry_end)| **entry_end > start)
+        .map(|(entry_start, _)| *entry_start);
+    let overlapping: Vec<u64> = entry_before
+        .into_iter()
+        .chain(
+            live_entries
+                .range(start..end)
+                .map(|(entry_start, _)| *entry_start),
+        )
+        .collect();
+    for entry_start in &overlapping {
+        live_entries.remove(entry_start);
+    }
+    overlapping
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use fxprof_processed_profile::{LibMappings, ReferenceTimestamp, SamplingInterval};
+
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    struct TestPerfMap {
+        _dir: tempfile::TempDir,
+        path: PathBuf,
+        processor: PerfMapProcessor,
+        profile: Profile,
+        jit_category_manager: JitCategoryManager,
+    }
+
+    impl TestPerfMap {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("perf-1234.map");
+            Self {
+                _dir: dir,
+                processor: PerfMapProcessor {
+                    path: path.clone(),
+                    ..PerfMapProcessor::new(1234, None)
+                },
+                path,
+                profile: Profile::new(
+                    "test",
+                    ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+                    SamplingInterval::from_millis(1),
+                ),
+                jit_category_manager: JitCategoryManager::new(),
+            }
+        }
+
+        fn append(&self, content: &str) {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .unwrap();
+            file.write_all(content.as_bytes()).unwrap();
+        }
+
+        fn rewrite(&self, content: &str) {
+            std::fs::write(&self.path, content).unwrap();
+        }
+
+        fn read(&mut self, timestamp: u64, is_final_read: bool) {
+            self.processor.read_new_entries(
+                timestamp,
+                is_final_read,
+                &mut self.profile,
+                &mut self.jit_category_manager,
+                None,
+            );
+        }
 
-        // Add this function to the JIT lib mappings so that it can be consulted for
-        // category information, JS function prepending, and to translate the absolute
-        // address into a relative address.
-        mappings.add_mapping(
-            start_address,
-            end_address,
-            relative_address,
-            LibMappingInfo::new_jit_function(lib_handle, category, js_frame),
+        /// The name of the function at `address` at `timestamp`, according
+        /// to the entries read so far.
+        fn name_at(&self, timestamp: u64, address: u64) -> Option<String> {
+            let mut mappings = LibMappings::new();
+            let mut ops = self.processor.lib_mapping_ops.clone().into_iter();
+            while let Some(op) = ops.next_op_if_at_or_before(timestamp) {
+                op.apply_to(&mut mappings);
+            }
+            let (relative_address, _) = mappings.convert_address(address)?;
+            let symbols = &self.processor.state.as_ref()?.symbols;
+            let symbol = symbols
+                .iter()
+                .rfind(|symbol| symbol.address <= relative_address)?;
+            Some(symbol.name.clone())
+        }
+    }
+
+    #[test]
+    fn parse_lines() {
+        assert_eq!(
+            parse_perf_map_line("7f3a1000 40 LazyCompile:*foo bar.js:1"),
+            Some(PerfMapLine::Entry {
+                start: 0x7f3a1000,
+                len: 0x40,
+                name: "LazyCompile:*foo bar.js:1"
+            })
+        );
+        assert_eq!(
+            parse_perf_map_line("0x1000 0x10 foo\r"),
+            Some(PerfMapLine::Entry {
+                start: 0x1000,
+                len: 0x10,
+                name: "foo"
+            })
+        );
+        assert_eq!(
+            parse_perf_map_line("1000 10"),
+            Some(PerfMapLine::Clear {
+                start: 0x1000,
+                len: 0x10
+            })
+        );
+        assert_eq!(
+            parse_perf_map_line("1000 10 "),
+            Some(PerfMapLine::Clear {
+                start: 0x1000,
+                len: 0x10
+            })
         );
+        assert_eq!(parse_perf_map_line("1000"), None);
+        assert_eq!(parse_perf_map_line("xyz 10 foo"), None);
+        assert_eq!(parse_perf_map_line(""), None);
+    }
+
+    #[test]
+    fn incremental_appends_and_last_line_without_newline() {
+        let mut perf_map = TestPerfMap::new();
+        perf_map.append("1000 10 a\n2000 1");
+        perf_map.read(SECOND, false);
+        perf_map.append("0 b\n3000 10 c");
+        perf_map.read(2 * SECOND, false);
+
+        // Entries from the first read apply from the start.
+        assert_eq!(perf_map.name_at(0, 0x1005).as_deref(), Some("a"));
+        assert_eq!(perf_map.name_at(SECOND, 0x2005), None);
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x2005).as_deref(), Some("b"));
+        // The JIT may still be writing the last line.
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x3005), None);
+
+        perf_map.read(3 * SECOND, true);
+        assert_eq!(perf_map.name_at(3 * SECOND, 0x3005).as_deref(), Some("c"));
     }
 
-    profile.set_lib_symbol_table(lib_handle, Arc::new(SymbolTable::new(symbols)));
+    #[test]
+    fn address_reuse() {
+        let mut perf_map = TestPerfMap::new();
+        perf_map.append("1000 20 a\n");
+        perf_map.read(SECOND, false);
+        perf_map.append("1010 10 b\n");
+        perf_map.read(2 * SECOND, false);
 
-    Some(mappings)
+        assert_eq!(perf_map.name_at(SECOND, 0x1015).as_deref(), Some("a"));
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x1015).as_deref(), Some("b"));
+        // The new entry replaces the whole overlapping entry.
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x1005), None);
+    }
+
+    #[test]
+    fn truncation_and_rewrite() {
+        let mut perf_map = TestPerfMap::new();
+        perf_map.append("1000 10 a\n2000 10 b\n");
+        perf_map.read(SECOND, false);
+        perf_map.rewrite("3000 10 c\n");
+        perf_map.read(2 * SECOND, false);
+
+        assert_eq!(perf_map.name_at(SECOND, 0x1005).as_deref(), Some("a"));
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x1005), None);
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x2005), None);
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x3005).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn clear_markers() {
+        let mut perf_map = TestPerfMap::new();
+        perf_map.append("1000 10 a\n1010 10 b\n1020 10 c\n1030 10 d\n");
+        perf_map.read(SECOND, false);
+        // Clears b and c, which overlap the range, but not a and d.
+        perf_map.append("1018 10\n");
+        perf_map.read(2 * SECOND, false);
+
+        assert_eq!(perf_map.name_at(SECOND, 0x1015).as_deref(), Some("b"));
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x1005).as_deref(), Some("a"));
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x1015), None);
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x1025), None);
+        assert_eq!(perf_map.name_at(2 * SECOND, 0x1035).as_deref(), Some("d"));
+
+        // A cleared range can be reused by a new function.
+        perf_map.append("1010 20 e\n");
+        perf_map.read(3 * SECOND, true);
+        assert_eq!(perf_map.name_at(3 * SECOND, 0x1025).as_deref(), Some("e"));
+    }
 }
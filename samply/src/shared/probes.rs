@@ -0,0 +1,355 @@
+//! Resolving uprobes and USDT probes to the file offsets which the kernel's
+//! uprobe PMU attaches to, for `samply record --uprobe` and `--usdt`.
+//!
+//! A uprobe is placed at the start of a function from the binary's symbol
+//! table. A USDT probe, the statically defined tracepoints used by dtrace and
+//! SystemTap, is a nop instruction which the compiler emits at the probe site,
+//! with a note in the `.note.stapsdt` section which describes where it is.
+//! Neither needs the binary to be patched on disk; the kernel places the
+//! breakpoint in the process's memory while the probe is attached.
+
+use std::path::{Path, PathBuf};
+
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol};
+
+/// The type of the stapsdt notes.
+const NT_STAPSDT: u32 = 3;
+
+/// The directories in which a library given by name is looked for, after the
+/// multiarch directory of the current architecture.
+const LIBRARY_DIRS: [&str; 6] = [
+    "/lib",
+    "/lib64",
+    "/usr/lib",
+    "/usr/lib64",
+    "/usr/local/lib",
+    "/usr/local/lib64",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeKind {
+    /// A uprobe at the start of the function with this symbol name.
+    Uprobe { symbol: String },
+    /// A USDT probe.
+    Usdt { provider: String, name: String },
+}
+
+/// A probe given on the command line, as `BINARY:SYMBOL` for a uprobe or as
+/// `BINARY:PROVIDER:PROBE` for a USDT probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeSpec {
+    /// A path, or the name of an executable on the PATH or of a library in
+    /// the standard library directories, e.g. "libc" or "libc.so.6".
+    pub binary: String,
+    pub kind: ProbeKind,
+}
+
+impl ProbeSpec {
+    /// Parses `BINARY:SYMBOL`, for `--uprobe`.
+    pub fn parse_uprobe(arg: &str) -> Result<Self, String> {
+        match arg.rsplit_once(':') {
+            Some((binary, symbol)) if !binary.is_empty() && !symbol.is_empty() => Ok(Self {
+                binary: binary.to_string(),
+                kind: ProbeKind::Uprobe {
+                    symbol: symbol.to_string(),
+                },
+            }),
+            _ => Err(format!("Expected BINARY:SYMBOL, got {arg:?}")),
+        }
+    }
+
+    /// Parses `BINARY:PROVIDER:PROBE`, for `--usdt`.
+    pub fn parse_usdt(arg: &str) -> Result<Self, String> {
+        let mut parts = arg.rsplitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(provider), Some(binary))
+                if !binary.is_empty() && !provider.is_empty() && !name.is_empty() =>
+            {
+                Ok(Self {
+                    binary: binary.to_string(),
+                    kind: ProbeKind::Usdt {
+                        provider: provider.to_string(),
+                        name: name.to_string(),
+                    },
+                })
+            }
+            _ => Err(format!("Expected BINARY:PROVIDER:PROBE, got {arg:?}")),
+        }
+    }
+
+    /// The name of the markers for the hits of this probe.
+    pub fn marker_name(&self) -> String {
+        match &self.kind {
+            ProbeKind::Uprobe { symbol } => symbol.clone(),
+            ProbeKind::Usdt { provider, name } => format!("{provider}:{name}"),
+        }
+    }
+}
+
+/// Where the kernel places a probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeLocation {
+    pub path: PathBuf,
+    /// The file offset of the probed instruction.
+    pub offset: u64,
+    /// The file offset of the USDT probe's semaphore, or 0. The kernel
+    /// increments the semaphore while the probe is attached, so that the
+    /// program can skip preparing the probe's arguments when it isn't.
+    pub semaphore_offset: u64,
+}
+
+/// Finds the binary and the file offset of a probe.
+pub fn resolve_probe(spec: &ProbeSpec) -> Result<ProbeLocation, String> {
+    let path = find_binary(&spec.binary)
+        .ok_or_else(|| format!("Couldn't find the binary {:?}", spec.binary))?;
+    let data = std::fs::read(&path).map_err(|e| format!("Couldn't read {path:?}: {e}"))?;
+    let file = object::File::parse(&data[..])
+        .map_err(|e| format!("Couldn't parse {path:?} as an ELF file: {e}"))?;
+    let (address, semaphore_address) = match &spec.kind {
+        ProbeKind::Uprobe { symbol } => {
+            let address = file
+                .symbols()
+                .chain(file.dynamic_symbols())
+                .find(|s| s.is_definition() && s.address() != 0 && s.name() == Ok(symbol))
+                .map(|s| s.address())
+                .ok_or_else(|| format!("Couldn't find the function {symbol} in {path:?}"))?;
+            (address, 0)
+        }
+        ProbeKind::Usdt { provider, name } => {
+            let notes = file
+                .section_by_name(".note.stapsdt")
+                .and_then(|section| section.data().ok())
+                .map(|data| parse_stapsdt_notes(data, file.is_64(), file.is_little_endian()))
+                .unwrap_or_default();
+            let note = notes
+                .into_iter()
+                .find(|note| &note.provider == provider && &note.name == name)
+                .ok_or_else(|| {
+                    format!("Couldn't find the USDT probe {provider}:{name} in {path:?}")
+                })?;
+            // The note has the address which the .stapsdt.base section had at
+            // link time; the section moves if the binary is prelinked.
+            let address = match file.section_by_name(".stapsdt.base") {
+                Some(base) => note.pc.wrapping_add(base.address()).wrapping_sub(note.base),
+                None => note.pc,
+            };
+            (address, note.semaphore)
+        }
+    };
+    let offset = file_offset(&file, address)
+        .ok_or_else(|| format!("The probe address {address:#x} isn't mapped in {path:?}"))?;
+    let semaphore_offset = match semaphore_address {
+        0 => 0,
+        address => file_offset(&file, address).ok_or_else(|| {
+            format!("The semaphore address {address:#x} isn't mapped in {path:?}")
+        })?,
+    };
+    Ok(ProbeLocation {
+        path,
+        offset,
+        semaphore_offset,
+    })
+}
+
+/// The file offset at which the data for a virtual address is, from the
+/// binary's loadable segments.
+fn file_offset(file: &object::File, address: u64) -> Option<u64> {
+    file.segments().find_map(|segment| {
+        let (file_start, file_size) = segment.file_range();
+        let relative = address.checked_sub(segment.address())?;
+        (relative < file_size).then_some(file_start + relative)
+    })
+}
+
+/// Resolves a binary given by path or by name to an absolute path.
+fn find_binary(binary: &str) -> Option<PathBuf> {
+    if binary.contains('/') {
+        return Path::new(binary).canonicalize().ok();
+    }
+    let path_dirs: Vec<PathBuf> = std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    if let Some(path) = path_dirs
+        .iter()
+        .map(|dir| dir.join(binary))
+        .find(|path| path.is_file())
+    {
+        return path.canonicalize().ok();
+    }
+    let multiarch_dirs = [
+        format!("/lib/{}-linux-gnu", std::env::consts::ARCH),
+        format!("/usr/lib/{}-linux-gnu", std::env::consts::ARCH),
+    ];
+    let library_dirs = multiarch_dirs
+        .iter()
+        .map(String::as_str)
+        .chain(LIBRARY_DIRS)
+        .map(Path::new);
+    for dir in library_dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut file_names: Vec<String> = entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|file_name| is_library_file_name(file_name, binary))
+            .collect();
+        // Prefer "libfoo.so.1" over "libfoo.so.1.2.3", which it usually links
+        // to. "libfoo.so" is sometimes a linker script rather than a library.
+        file_names.sort_by_key(|file_name| file_name.len());
+        if let Some(path) = file_names
+            .iter()
+            .map(|file_name| dir.join(file_name))
+            .find(|path| is_elf_file(path))
+        {
+            return path.canonicalize().ok();
+        }
+    }
+    None
+}
+
+fn is_elf_file(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    std::fs::File::open(path)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut magic))
+        .is_ok_and(|()| &magic == b"\x7fELF")
+}
+
+/// Whether the file is the library `name`, e.g. "libfoo.so.1" for "libfoo",
+/// "libfoo.so" or "libfoo.so.1".
+fn is_library_file_name(file_name: &str, name: &str) -> bool {
+    let name = name.strip_suffix(".so").unwrap_or(name);
+    match file_name.strip_prefix(name) {
+        Some(rest) => rest.is_empty() || rest.starts_with(".so"),
+        None => false,
+    }
+}
+
+/// A USDT probe, from a stapsdt note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UsdtNote {
+    provider: String,
+    name: String,
+    /// The address of the probe's nop instruction.
+    pc: u64,
+    /// The address of the .stapsdt.base section at link time.
+    base: u64,
+    /// The address of the semaphore, or 0.
+    semaphore: u64,
+}
+
+/// Parses the notes in a `.note.stapsdt` section. Each note's descriptor has
+/// the three addresses, followed by the provider, the probe name and the
+/// argument description as nul-terminated strings.
+fn parse_stapsdt_notes(data: &[u8], is_64: bool, is_little_endian: bool) -> Vec<UsdtNote> {
+    let read_u32 = |bytes: &[u8]| {
+        let bytes: [u8; 4] = bytes.try_into().unwrap();
+        match is_little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        }
+    };
+    let read_address = |bytes: &[u8]| match (is_64, is_little_endian) {
+        (true, true) => u64::from_le_bytes(bytes.try_into().unwrap()),
+        (true, false) => u64::from_be_bytes(bytes.try_into().unwrap()),
+        (false, _) => u64::from(read_u32(bytes)),
+    };
+    let address_size = if is_64 { 8 } else { 4 };
+    let align = |size: usize| (size + 3) & !3;
+
+    let mut notes = Vec::new();
+    let mut rest = data;
+    while rest.len() >= 12 {
+        let name_size = read_u32(&rest[0..4]) as usize;
+        let desc_size = read_u32(&rest[4..8]) as usize;
+        let note_type = read_u32(&rest[8..12]);
+        let desc_start = 12 + align(name_size);
+        let Some(desc) = rest.get(desc_start..desc_start + desc_size) else {
+            break;
+        };
+        let name = &rest[12..12 + name_size];
+        rest = rest.get(desc_start + align(desc_size)..).unwrap_or(&[]);
+        if note_type != NT_STAPSDT || name != b"stapsdt\0" || desc.len() < 3 * address_size {
+            continue;
+        }
+        let (addresses, strings) = desc.split_at(3 * address_size);
+        let mut strings = strings
+            .split(|&b| b == 0)
+            .map(|s| String::from_utf8_lossy(s).into_owned());
+        let (Some(provider), Some(name)) = (strings.next(), strings.next()) else {
+            continue;
+        };
+        notes.push(UsdtNote {
+            provider,
+            name,
+            pc: read_address(&addresses[..address_size]),
+            base: read_address(&addresses[address_size..2 * address_size]),
+            semaphore: read_address(&addresses[2 * address_size..]),
+        });
+    }
+    notes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn probe_specs() {
+        assert_eq!(
+            ProbeSpec::parse_uprobe("./app:my_function"),
+            Ok(ProbeSpec {
+                binary: "./app".to_string(),
+                kind: ProbeKind::Uprobe {
+                    symbol: "my_function".to_string()
+                },
+            })
+        );
+        let usdt = ProbeSpec::parse_usdt("libfoo:provider:probe").unwrap();
+        assert_eq!(usdt.binary, "libfoo");
+        assert_eq!(usdt.marker_name(), "provider:probe");
+        assert!(ProbeSpec::parse_uprobe("my_function").is_err());
+        assert!(ProbeSpec::parse_usdt("app:probe").is_err());
+
+        assert!(is_library_file_name("libfoo.so.1", "libfoo"));
+        assert!(is_library_file_name("libfoo.so", "libfoo.so"));
+        assert!(!is_library_file_name("libfoobar.so", "libfoo"));
+    }
+
+    #[test]
+    fn stapsdt_notes() {
+        fn note(name: &[u8], note_type: u32, desc: &[u8]) -> Vec<u8> {
+            let mut bytes = Vec::new();
+            bytes.extend((name.len() as u32).to_le_bytes());
+            bytes.extend((desc.len() as u32).to_le_bytes());
+            bytes.extend(note_type.to_le_bytes());
+            bytes.extend(name);
+            bytes.resize((bytes.len() + 3) & !3, 0);
+            bytes.extend(desc);
+            bytes.resize((bytes.len() + 3) & !3, 0);
+            bytes
+        }
+        let mut desc = Vec::new();
+        for address in [0x1234u64, 0x2000, 0x4010] {
+            desc.extend(address.to_le_bytes());
+        }
+        desc.extend(b"myapp\0request__start\0-4@%edi 8@%rsi\0");
+
+        let mut data = note(b"GNU\0", NT_STAPSDT, &[0; 24]);
+        data.extend(note(b"stapsdt\0", NT_STAPSDT, &desc));
+        assert_eq!(
+            parse_stapsdt_notes(&data, true, true),
+            vec![UsdtNote {
+                provider: "myapp".to_string(),
+                name: "request__start".to_string(),
+                pc: 0x1234,
+                base: 0x2000,
+                semaphore: 0x4010,
+            }]
+        );
+        // A truncated note is ignored.
+        assert_eq!(
+            parse_stapsdt_notes(&data[..data.len() - 8], true, true),
+            vec![]
+        );
+    }
+}
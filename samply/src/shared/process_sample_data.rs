@@ -1,12 +1,15 @@
 use fxprof_processed_profile::{
-    LibMappings, MarkerFieldFlags, MarkerFieldFormat, MarkerTiming, Profile, StaticSchemaMarker,
-    StaticSchemaMarkerField, StringHandle, SubcategoryHandle, ThreadHandle, Timestamp,
+    Category, CategoryColor, MarkerFieldFlags, MarkerFieldFormat, MarkerTiming, ProcessHandle,
+    Profile, StackHandle, StaticSchemaMarker, StaticSchemaMarkerField, StringHandle,
+    SubcategoryHandle, ThreadHandle, Timestamp,
 };
 
-use super::lib_mappings::{LibMappingInfo, LibMappingOpQueue, LibMappingsHierarchy};
+use super::frame_stats::add_frame_tracks;
+use super::lib_mappings::{LibMappingOpQueue, LibMappingsHierarchy};
+use super::sample_labels::SampleLabels;
 use super::stack_converter::StackConverter;
 use super::stack_depth_limiting_frame_iter::StackDepthLimitingFrameIter;
-use super::types::StackFrame;
+use super::types::{FastHashMap, StackFrame};
 use super::unresolved_samples::{
     SampleData, SampleOrMarker, UnresolvedSampleOrMarker, UnresolvedSamples, UnresolvedStacks,
 };
@@ -29,26 +32,26 @@ pub enum RssStatMember {
 
 #[derive(Debug, Clone)]
 pub struct ProcessSampleData {
+    process: ProcessHandle,
     unresolved_samples: UnresolvedSamples,
     regular_lib_mapping_op_queue: LibMappingOpQueue,
     jitdump_lib_mapping_op_queues: Vec<LibMappingOpQueue>,
-    perf_map_mappings: Option<LibMappings<LibMappingInfo>>,
     marker_spans: Vec<MarkerSpanOnThread>,
 }
 
 impl ProcessSampleData {
     pub fn new(
+        process: ProcessHandle,
         unresolved_samples: UnresolvedSamples,
         regular_lib_mapping_op_queue: LibMappingOpQueue,
         jitdump_lib_mapping_op_queues: Vec<LibMappingOpQueue>,
-        perf_map_mappings: Option<LibMappings<LibMappingInfo>>,
         marker_spans: Vec<MarkerSpanOnThread>,
     ) -> Self {
         Self {
+            process,
             unresolved_samples,
             regular_lib_mapping_op_queue,
             jitdump_lib_mapping_op_queues,
-            perf_map_mappings,
             marker_spans,
         }
     }
@@ -57,6 +60,23 @@ impl ProcessSampleData {
         self.unresolved_samples.is_empty()
     }
 
+    pub fn discard_samples_before(&mut self, timestamp_mono: u64) {
+        self.unresolved_samples
+            .discard_samples_before(timestamp_mono);
+    }
+
+    /// Pushes the samples and markers of this process into the profile.
+    ///
+    /// If `marker_stacks_from_samples` is true, each marker from a marker file
+    /// gets the stack of a nearby sample on the same thread: the first sample
+    /// taken during the marker, or, if there is none, the last sample taken at
+    /// most one sampling interval before the marker started. This is an
+    /// approximation: marker files don't record where the marker was emitted
+    /// from, and the thread may have moved on between the sample and the marker.
+    ///
+    /// Samples taken during `label:` marker spans get a label frame at the root
+    /// of their stack, see [`SampleLabels`]. `frame` marker spans get a frame
+    /// rate track, see [`add_frame_tracks`].
     #[allow(clippy::too_many_arguments)]
     pub fn flush_samples_to_profile(
         self,
@@ -65,22 +85,25 @@ impl ProcessSampleData {
         kernel_category: SubcategoryHandle,
         stack_frame_scratch_buf: &mut Vec<StackFrame>,
         stacks: &UnresolvedStacks,
+        marker_stacks_from_samples: bool,
     ) {
         let ProcessSampleData {
+            process,
             unresolved_samples,
             regular_lib_mapping_op_queue,
             jitdump_lib_mapping_op_queues,
-            perf_map_mappings,
             marker_spans,
         } = self;
         let mut lib_mappings_hierarchy = LibMappingsHierarchy::new(regular_lib_mapping_op_queue);
         for jitdump_lib_mapping_ops in jitdump_lib_mapping_op_queues {
             lib_mappings_hierarchy.add_jitdump_lib_mappings_ops(jitdump_lib_mapping_ops);
         }
-        if let Some(perf_map_mappings) = perf_map_mappings {
-            lib_mappings_hierarchy.add_perf_map_mappings(perf_map_mappings);
-        }
+        let sample_labels = SampleLabels::new(profile, &marker_spans, user_category);
         let mut stack_converter = StackConverter::new(user_category, kernel_category);
+        let mut sample_stacks_per_thread: FastHashMap<
+            ThreadHandle,
+            Vec<(Timestamp, Option<StackHandle>)>,
+        > = FastHashMap::default();
         let samples = unresolved_samples.into_inner();
         for sample in samples {
             lib_mappings_hierarchy.process_ops(sample.timestamp_mono);
@@ -93,6 +116,12 @@ impl ProcessSampleData {
                 ..
             } = sample;
 
+            let extra_label_frame = match sample_or_marker {
+                SampleOrMarker::Sample(_) => extra_label_frame
+                    .or_else(|| sample_labels.label_frame_at(thread_handle, timestamp)),
+                SampleOrMarker::MarkerHandle(_) => extra_label_frame,
+            };
+
             stack_frame_scratch_buf.clear();
             stacks.convert_back(stack, stack_frame_scratch_buf);
             let frames = stack_converter.convert_stack(
@@ -108,6 +137,12 @@ impl ProcessSampleData {
             match sample_or_marker {
                 SampleOrMarker::Sample(SampleData { cpu_delta, weight }) => {
                     profile.add_sample(thread_handle, timestamp, stack_handle, cpu_delta, weight);
+                    if marker_stacks_from_samples {
+                        sample_stacks_per_thread
+                            .entry(thread_handle)
+                            .or_default()
+                            .push((timestamp, stack_handle));
+                    }
                 }
                 SampleOrMarker::MarkerHandle(mh) => {
                     profile.set_marker_stack(thread_handle, mh, stack_handle);
@@ -115,13 +150,52 @@ impl ProcessSampleData {
             }
         }
 
+        for sample_stacks in sample_stacks_per_thread.values_mut() {
+            sample_stacks.sort_by_key(|(timestamp, _)| *timestamp);
+        }
+
+        add_frame_tracks(profile, process, &marker_spans);
+
+        let max_stack_distance = profile.interval().nanos();
         for marker in marker_spans {
             let marker_name_string_index = profile.handle_for_string(&marker.name);
-            profile.add_marker(
+            let marker_handle = profile.add_marker(
                 marker.thread_handle,
                 MarkerTiming::Interval(marker.start_time, marker.end_time),
                 SimpleMarker(marker_name_string_index),
             );
+            if let Some(sample_stacks) = sample_stacks_per_thread.get(&marker.thread_handle) {
+                if let Some(stack_handle) = stack_for_marker(
+                    sample_stacks,
+                    marker.start_time,
+                    marker.end_time,
+                    max_stack_distance,
+                ) {
+                    profile.set_marker_stack(marker.thread_handle, marker_handle, stack_handle);
+                }
+            }
+        }
+    }
+}
+
+/// Picks the stack of the first sample in `start..=end`, or of the last sample
+/// before `start` if no sample was taken during the marker and that sample is
+/// at most `max_distance_ns` before `start`. `sample_stacks` must be sorted by
+/// timestamp.
+fn stack_for_marker<S: Copy>(
+    sample_stacks: &[(Timestamp, S)],
+    start: Timestamp,
+    end: Timestamp,
+    max_distance_ns: u64,
+) -> Option<S> {
+    let first_at_or_after_start =
+        sample_stacks.partition_point(|(timestamp, _)| *timestamp < start);
+    match sample_stacks.get(first_at_or_after_start) {
+        Some((timestamp, stack)) if *timestamp <= end => Some(*stack),
+        _ => {
+            let (timestamp, stack) = sample_stacks.get(first_at_or_after_start.checked_sub(1)?)?;
+            let distance_ns = start.nanos_since_reference() - timestamp.nanos_since_reference();
+            (distance_ns <= max_distance_ns).then_some(*stack)
         }
     }
 }
@@ -186,6 +260,501 @@ impl StaticSchemaMarker for RssStatMarker {
     }
 }
 
+/// A mapping or unmapping of virtual memory, from VirtualAlloc / VirtualFree
+/// on Windows or mmap / munmap on Linux.
+#[derive(Debug, Clone)]
+pub struct VirtualMemoryMarker {
+    pub name: StringHandle,
+    /// The start address of the region, formatted as hex.
+    pub address: StringHandle,
+    pub size_bytes: u64,
+    /// The process's mapped address space after this operation, as far as the
+    /// recorded events tell.
+    pub total_bytes: i64,
+}
+
+impl StaticSchemaMarker for VirtualMemoryMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "VirtualMemory";
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.size}");
+    const TOOLTIP_LABEL: Option<&'static str> =
+        Some("{marker.name} {marker.data.size} at {marker.data.address}");
+    const TABLE_LABEL: Option<&'static str> =
+        Some("{marker.data.size} at {marker.data.address}, total: {marker.data.total}");
+
+    const DESCRIPTION: Option<&'static str> =
+        Some("Emitted when a region of virtual memory is mapped or unmapped.");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "address",
+            label: "Address",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "size",
+            label: "Size",
+            format: MarkerFieldFormat::Bytes,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "total",
+            label: "Address space",
+            format: MarkerFieldFormat::Bytes,
+            flags: MarkerFieldFlags::empty(),
+        },
+    ];
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.address
+    }
+
+    fn number_field_value(&self, field_index: u32) -> f64 {
+        match field_index {
+            1 => self.size_bytes as f64,
+            2 => self.total_bytes as f64,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// The time a CPU spent in a hardware interrupt handler or in a softirq, on
+/// the synthetic IRQ track of that CPU.
+#[derive(Debug, Clone)]
+pub struct IrqMarker {
+    /// "IRQ" or "Softirq".
+    pub name: StringHandle,
+    /// The name of the interrupt handler, or of the softirq vector.
+    pub handler: StringHandle,
+    /// The IRQ number, or the softirq vector number.
+    pub number: i64,
+}
+
+impl StaticSchemaMarker for IrqMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Irq";
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.handler}");
+    const TOOLTIP_LABEL: Option<&'static str> =
+        Some("{marker.name} {marker.data.number}: {marker.data.handler}");
+    const TABLE_LABEL: Option<&'static str> =
+        Some("{marker.name} {marker.data.number}: {marker.data.handler}");
+
+    const DESCRIPTION: Option<&'static str> =
+        Some("Emitted between the irq:irq_handler_entry and exit, or the irq:softirq_entry and exit tracepoints.");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "handler",
+            label: "Handler",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "number",
+            label: "Number",
+            format: MarkerFieldFormat::Integer,
+            flags: MarkerFieldFlags::empty(),
+        },
+    ];
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.handler
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.number as f64
+    }
+}
+
+/// The creation or exit of a thread. Creation markers are placed on the
+/// creating thread, with its stack if one was recorded, and exit markers on
+/// the exiting thread.
+#[derive(Debug, Clone)]
+pub struct ThreadLifecycleMarker {
+    pub name: StringHandle,
+    /// The tid of the created or exiting thread.
+    pub tid: u32,
+}
+
+impl StaticSchemaMarker for ThreadLifecycleMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "ThreadLifecycle";
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.tid}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("{marker.name} (tid {marker.data.tid})");
+    const TABLE_LABEL: Option<&'static str> = Some("tid {marker.data.tid}");
+
+    const DESCRIPTION: Option<&'static str> = Some("Emitted when a thread is created or exits.");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[StaticSchemaMarkerField {
+        key: "tid",
+        label: "Thread ID",
+        format: MarkerFieldFormat::Integer,
+        flags: MarkerFieldFlags::SEARCHABLE,
+    }];
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        unreachable!()
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.tid.into()
+    }
+}
+
+/// A process replacing its image with exec. The samples before and after the
+/// exec stay on the same track; this marker shows where the image changed.
+#[derive(Debug, Clone)]
+pub struct ExecMarker {
+    /// The name of the process before the exec.
+    pub old_name: StringHandle,
+    /// The name of the process after the exec.
+    pub new_name: StringHandle,
+}
+
+impl StaticSchemaMarker for ExecMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Exec";
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.newName}");
+    const TOOLTIP_LABEL: Option<&'static str> =
+        Some("exec {marker.data.oldName} → {marker.data.newName}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.data.oldName} → {marker.data.newName}");
+
+    const DESCRIPTION: Option<&'static str> =
+        Some("Emitted when a process replaces its image with exec.");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "oldName",
+            label: "Old image",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "newName",
+            label: "New image",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+    ];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.handle_for_string("Exec")
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.old_name,
+            1 => self.new_name,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+/// A new instance of the recorded process, after the previous one quit, with
+/// `--follow-restarts`. It's put on the main thread of the new process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartMarker {
+    /// How many restarts there have been so far, including this one.
+    pub number: u32,
+    /// The pid of the process which was recorded before the restart.
+    pub previous_pid: u32,
+}
+
+impl StaticSchemaMarker for RestartMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Restart";
+
+    const CHART_LABEL: Option<&'static str> = Some("Restart {marker.data.number}");
+    const TOOLTIP_LABEL: Option<&'static str> =
+        Some("Restart {marker.data.number} (previous pid {marker.data.previousPid})");
+    const TABLE_LABEL: Option<&'static str> =
+        Some("Restart {marker.data.number} (previous pid {marker.data.previousPid})");
+
+    const DESCRIPTION: Option<&'static str> =
+        Some("Emitted when the recorded process was restarted and the new instance is recorded.");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "number",
+            label: "Restart",
+            format: MarkerFieldFormat::Integer,
+            flags: MarkerFieldFlags::empty(),
+        },
+        StaticSchemaMarkerField {
+            key: "previousPid",
+            label: "Previous PID",
+            format: MarkerFieldFormat::Integer,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+    ];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.handle_for_string("Restart")
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        unreachable!()
+    }
+
+    fn number_field_value(&self, field_index: u32) -> f64 {
+        match field_index {
+            0 => self.number.into(),
+            1 => self.previous_pid.into(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A hit of a uprobe or of a USDT probe, for `--uprobe` and `--usdt`.
+#[derive(Debug, Clone)]
+pub struct ProbeMarker {
+    /// The probed function, or "provider:probe" for a USDT probe.
+    pub name: StringHandle,
+    /// The path of the binary which has the probe.
+    pub binary: StringHandle,
+}
+
+impl StaticSchemaMarker for ProbeMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Probe";
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.name}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("{marker.name}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.name} in {marker.data.binary}");
+
+    const DESCRIPTION: Option<&'static str> =
+        Some("Emitted each time a recorded uprobe or USDT probe is hit.");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[StaticSchemaMarkerField {
+        key: "binary",
+        label: "Binary",
+        format: MarkerFieldFormat::FilePath,
+        flags: MarkerFieldFlags::SEARCHABLE,
+    }];
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.binary,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+/// A hit of a kprobe or of a tracepoint, for `--kprobe` and `--tracepoint`, or
+/// a tracepoint record in an imported perf.data file.
+#[derive(Debug, Clone)]
+pub struct KernelEventMarker {
+    /// The kernel function, or "category:name" for a tracepoint.
+    pub name: StringHandle,
+    /// The tracepoint's fields as `name=value` pairs, or the empty string.
+    pub fields: StringHandle,
+}
+
+impl StaticSchemaMarker for KernelEventMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "KernelEvent";
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.name}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("{marker.name} {marker.data.fields}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.name} {marker.data.fields}");
+
+    const DESCRIPTION: Option<&'static str> = Some(
+        "Emitted for the hits of kernel probes and tracepoints, with the tracepoint's fields.",
+    );
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[StaticSchemaMarkerField {
+        key: "fields",
+        label: "Fields",
+        format: MarkerFieldFormat::String,
+        flags: MarkerFieldFlags::SEARCHABLE,
+    }];
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.fields,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+/// A thrown exception or a panic: a C++ throw, a .NET exception, or a Rust
+/// panic.
+#[derive(Debug, Clone)]
+pub struct ExceptionMarker {
+    /// "Exception" or "Panic".
+    pub name: StringHandle,
+    /// The exception type and message, or the panic message, if known.
+    pub message: StringHandle,
+    /// The source location of a panic, or the empty string.
+    pub location: StringHandle,
+}
+
+impl StaticSchemaMarker for ExceptionMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Exception";
+
+    const CATEGORY: Category<'static> = Category("Error", CategoryColor::Red);
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.message}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("{marker.name}: {marker.data.message}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.data.message} {marker.data.location}");
+
+    const DESCRIPTION: Option<&'static str> =
+        Some("Emitted when an exception is thrown or a thread panics.");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "message",
+            label: "Message",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "location",
+            label: "Location",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+    ];
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.message,
+            1 => self.location,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+/// A line which a launched command printed to its stdout or stderr.
+#[derive(Debug, Clone)]
+pub struct OutputLineMarker {
+    /// "stdout" or "stderr".
+    pub name: StringHandle,
+    pub text: StringHandle,
+}
+
+impl StaticSchemaMarker for OutputLineMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "OutputLine";
+
+    const CATEGORY: Category<'static> = Category("Output", CategoryColor::Gray);
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.text}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("{marker.name}: {marker.data.text}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.data.text}");
+
+    const DESCRIPTION: Option<&'static str> =
+        Some("Emitted for each line which the launched command printed.");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[StaticSchemaMarkerField {
+        key: "text",
+        label: "Text",
+        format: MarkerFieldFormat::String,
+        flags: MarkerFieldFlags::SEARCHABLE,
+    }];
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.text
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+/// A test from a test run whose output samply read.
+#[derive(Debug, Clone)]
+pub struct TestMarker {
+    pub test: StringHandle,
+    /// E.g. "ok" or "failed".
+    pub outcome: StringHandle,
+}
+
+impl StaticSchemaMarker for TestMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Test";
+
+    const CATEGORY: Category<'static> = Category("Test", CategoryColor::Green);
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.test}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("{marker.data.test}: {marker.data.outcome}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.data.test}: {marker.data.outcome}");
+
+    const DESCRIPTION: Option<&'static str> =
+        Some("The time from the start of a test until its result was printed.");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "test",
+            label: "Test",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "outcome",
+            label: "Outcome",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+    ];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.handle_for_string("Test")
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.test,
+            1 => self.outcome,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct OtherEventMarker(pub StringHandle);
 
@@ -329,3 +898,38 @@ impl StaticSchemaMarker for SimpleMarker {
         unreachable!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn marker_stack_selection() {
+        let ts = Timestamp::from_millis_since_reference;
+        let samples = [(ts(1.0), 'a'), (ts(2.0), 'b'), (ts(5.0), 'c')];
+        let interval = 1_000_000;
+        // A sample during the marker wins.
+        assert_eq!(
+            stack_for_marker(&samples, ts(1.5), ts(3.0), interval),
+            Some('b')
+        );
+        assert_eq!(
+            stack_for_marker(&samples, ts(2.0), ts(2.0), interval),
+            Some('b')
+        );
+        // Otherwise, take the most recent sample before the marker, if it's
+        // at most one interval before the marker.
+        assert_eq!(
+            stack_for_marker(&samples, ts(3.0), ts(4.0), interval),
+            Some('b')
+        );
+        assert_eq!(
+            stack_for_marker(&samples, ts(5.5), ts(7.0), interval),
+            Some('c')
+        );
+        assert_eq!(stack_for_marker(&samples, ts(3.5), ts(4.0), interval), None);
+        assert_eq!(stack_for_marker(&samples, ts(6.5), ts(7.0), interval), None);
+        // No sample at all before the end of the marker.
+        assert_eq!(stack_for_marker(&samples, ts(0.0), ts(0.5), interval), None);
+    }
+}
@@ -0,0 +1,276 @@
+//! Structural checks for processed profiles, for `samply validate`.
+//!
+//! The Firefox Profiler often fails in unhelpful ways when a profile refers to
+//! rows or strings which don't exist, so these checks point at the exact
+//! value which is wrong. They're mostly useful for profiles which were made
+//! by hand or by other tools.
+
+use serde_json::Value;
+
+use super::profile_versioning::table_length_errors;
+
+/// Returns a message for each problem in the profile. An empty list means
+/// that the profile is valid.
+pub fn validate_profile(profile: &Value) -> Vec<String> {
+    if profile["meta"]["preprocessedProfileVersion"]
+        .as_u64()
+        .is_none()
+    {
+        return vec!["meta.preprocessedProfileVersion is missing".to_string()];
+    }
+    let Some(threads) = profile["threads"].as_array() else {
+        return vec!["threads is missing".to_string()];
+    };
+
+    let mut errors = table_length_errors(profile);
+    let lib_count = profile["libs"].as_array().map_or(0, Vec::len);
+    let category_count = profile["meta"]["categories"].as_array().map_or(0, Vec::len);
+    let marker_types: Vec<&str> = profile["meta"]["markerSchema"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice)
+        .iter()
+        .filter_map(|schema| schema["name"].as_str())
+        .collect();
+
+    for (thread_index, thread) in threads.iter().enumerate() {
+        let mut checker = ThreadChecker {
+            thread,
+            prefix: format!("threads[{thread_index}]"),
+            errors: &mut errors,
+        };
+        let strings = Bound::Strings;
+        let libs = Bound::List(lib_count, "libs");
+
+        checker.check_indexes(
+            "samples",
+            "stack",
+            Bound::Table("stackTable"),
+            Nulls::Allowed,
+        );
+        checker.check_sample_times();
+
+        checker.check_indexes(
+            "stackTable",
+            "frame",
+            Bound::Table("frameTable"),
+            Nulls::Denied,
+        );
+        checker.check_stack_prefixes();
+
+        checker.check_indexes(
+            "frameTable",
+            "func",
+            Bound::Table("funcTable"),
+            Nulls::Denied,
+        );
+        checker.check_indexes(
+            "frameTable",
+            "nativeSymbol",
+            Bound::Table("nativeSymbols"),
+            Nulls::Allowed,
+        );
+
+        checker.check_indexes("funcTable", "name", strings, Nulls::Denied);
+        checker.check_indexes("funcTable", "fileName", strings, Nulls::Allowed);
+        checker.check_indexes(
+            "funcTable",
+            "resource",
+            Bound::Table("resourceTable"),
+            Nulls::MinusOne,
+        );
+
+        checker.check_indexes("resourceTable", "name", strings, Nulls::Denied);
+        checker.check_indexes("resourceTable", "lib", libs, Nulls::Allowed);
+
+        checker.check_indexes("nativeSymbols", "name", strings, Nulls::Denied);
+        checker.check_indexes("nativeSymbols", "libIndex", libs, Nulls::Denied);
+
+        checker.check_indexes("markers", "name", strings, Nulls::Denied);
+        checker.check_indexes(
+            "markers",
+            "category",
+            Bound::List(category_count, "meta.categories"),
+            Nulls::Denied,
+        );
+        checker.check_marker_types(&marker_types);
+    }
+    errors
+}
+
+/// What an index column refers to.
+#[derive(Clone, Copy)]
+enum Bound {
+    /// Another table of the same thread.
+    Table(&'static str),
+    /// The string array of the same thread.
+    Strings,
+    /// A list with this many entries, with a name for the error messages.
+    List(usize, &'static str),
+}
+
+/// How a column says that there is no row.
+#[derive(Clone, Copy, PartialEq)]
+enum Nulls {
+    Denied,
+    Allowed,
+    MinusOne,
+}
+
+struct ThreadChecker<'a> {
+    thread: &'a Value,
+    prefix: String,
+    errors: &'a mut Vec<String>,
+}
+
+impl ThreadChecker<'_> {
+    fn column(&self, table: &str, column: &str) -> &[Value] {
+        self.thread[table][column]
+            .as_array()
+            .map_or(&[][..], Vec::as_slice)
+    }
+
+    fn check_indexes(&mut self, table: &str, column: &str, bound: Bound, nulls: Nulls) {
+        let (count, bound_name) = match bound {
+            Bound::Table(bound_table) => {
+                let count = self.thread[bound_table]["length"].as_u64().unwrap_or(0) as usize;
+                (count, format!("{}.{bound_table}", self.prefix))
+            }
+            Bound::Strings => {
+                let count = self.thread["stringArray"].as_array().map_or(0, Vec::len);
+                (count, format!("{}.stringArray", self.prefix))
+            }
+            Bound::List(count, name) => (count, name.to_string()),
+        };
+        let mut errors = Vec::new();
+        for (row, value) in self.column(table, column).iter().enumerate() {
+            let path = format!("{}.{table}.{column}[{row}]", self.prefix);
+            match value {
+                Value::Null if nulls == Nulls::Allowed => {}
+                Value::Number(n) if nulls == Nulls::MinusOne && n.as_i64() == Some(-1) => {}
+                Value::Number(n) => match n.as_u64() {
+                    Some(index) if (index as usize) < count => {}
+                    _ => errors.push(format!(
+                        "{path} is {n}, but {bound_name} only has {count} entries"
+                    )),
+                },
+                _ => errors.push(format!("{path} is {value}, but should be an index")),
+            }
+        }
+        self.errors.extend(errors);
+    }
+
+    fn check_sample_times(&mut self) {
+        if let Some(times) = self.thread["samples"]["time"].as_array() {
+            let mut previous = f64::NEG_INFINITY;
+            for (row, time) in times.iter().enumerate() {
+                let time = time.as_f64().unwrap_or(f64::NAN);
+                if time.is_nan() || time < previous {
+                    self.errors.push(format!(
+                        "{}.samples.time[{row}] is {time}, which is before the previous sample at {previous}",
+                        self.prefix
+                    ));
+                }
+                previous = time;
+            }
+        } else if let Some(deltas) = self.thread["samples"]["timeDeltas"].as_array() {
+            for (row, delta) in deltas.iter().enumerate() {
+                let value = delta.as_f64().unwrap_or(f64::NAN);
+                if value.is_nan() || value < 0.0 {
+                    self.errors.push(format!(
+                        "{}.samples.timeDeltas[{row}] is {delta}, but times need to increase",
+                        self.prefix
+                    ));
+                }
+            }
+        } else if self.thread["samples"].is_object() {
+            self.errors.push(format!(
+                "{}.samples has neither a time nor a timeDeltas column",
+                self.prefix
+            ));
+        }
+    }
+
+    /// Each stack's prefix needs to come before the stack itself.
+    fn check_stack_prefixes(&mut self) {
+        let mut errors = Vec::new();
+        for (row, prefix) in self.column("stackTable", "prefix").iter().enumerate() {
+            match prefix {
+                Value::Null => {}
+                Value::Number(n) if n.as_u64().is_some_and(|p| (p as usize) < row) => {}
+                _ => errors.push(format!(
+                    "{}.stackTable.prefix[{row}] is {prefix}, but needs to be null or the index of an earlier stack",
+                    self.prefix
+                )),
+            }
+        }
+        self.errors.extend(errors);
+    }
+
+    fn check_marker_types(&mut self, marker_types: &[&str]) {
+        let mut errors = Vec::new();
+        for (row, data) in self.column("markers", "data").iter().enumerate() {
+            let Some(marker_type) = data["type"].as_str() else {
+                continue;
+            };
+            if !marker_types.contains(&marker_type) {
+                errors.push(format!(
+                    "{}.markers.data[{row}] has the type {marker_type:?}, which has no entry in meta.markerSchema",
+                    self.prefix
+                ));
+            }
+        }
+        self.errors.extend(errors);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn valid_profile() {
+        let profile = json!({
+            "meta": { "preprocessedProfileVersion": 55, "categories": [{}], "markerSchema": [] },
+            "libs": [{}],
+            "threads": [{
+                "samples": { "length": 2, "stack": [1, null], "time": [0.0, 1.0] },
+                "stackTable": { "length": 2, "prefix": [null, 0], "frame": [0, 0] },
+                "frameTable": { "length": 1, "func": [0], "nativeSymbol": [null] },
+                "funcTable": { "length": 1, "name": [0], "fileName": [null], "resource": [0] },
+                "resourceTable": { "length": 1, "name": [1], "lib": [0] },
+                "markers": { "length": 1, "name": [0], "category": [0], "data": [null] },
+                "stringArray": ["main", "libfoo.so"],
+            }],
+        });
+        assert_eq!(validate_profile(&profile), Vec::<String>::new());
+    }
+
+    #[test]
+    fn invalid_profile() {
+        let profile = json!({
+            "meta": { "preprocessedProfileVersion": 55, "categories": [], "markerSchema": [] },
+            "libs": [],
+            "threads": [{
+                "samples": { "length": 2, "stack": [3, 0], "time": [1.0, 0.5] },
+                "stackTable": { "length": 1, "prefix": [0], "frame": [0] },
+                "frameTable": { "length": 1, "func": [0] },
+                "funcTable": { "length": 1, "name": [5], "resource": [-1] },
+                "markers": { "length": 1, "name": [0], "category": [0], "data": [{ "type": "Foo" }] },
+                "stringArray": ["main"],
+            }],
+        });
+        assert_eq!(
+            validate_profile(&profile),
+            vec![
+                "threads[0].samples.stack[0] is 3, but threads[0].stackTable only has 1 entries",
+                "threads[0].samples.time[1] is 0.5, which is before the previous sample at 1",
+                "threads[0].stackTable.prefix[0] is 0, but needs to be null or the index of an earlier stack",
+                "threads[0].funcTable.name[0] is 5, but threads[0].stringArray only has 1 entries",
+                "threads[0].markers.category[0] is 0, but meta.categories only has 0 entries",
+                "threads[0].markers.data[0] has the type \"Foo\", which has no entry in meta.markerSchema",
+            ]
+        );
+    }
+}
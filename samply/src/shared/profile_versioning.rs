@@ -0,0 +1,218 @@
+//! Up-conversion of processed profiles from other format versions.
+//!
+//! Saved profiles are read as JSON and brought into the shape of the version
+//! which fxprof-processed-profile writes, before they're used by `samply report`,
+//! `samply push` or `samply import --merge-into`. The Firefox Profiler does its
+//! own up-conversion, so `samply load` serves files unchanged.
+//!
+//! Older versions lack some table columns, and the `length` of some tables.
+//! Newer versions share one string array between all threads. Both are
+//! converted here. Gecko profiles (the format which Firefox writes before the
+//! profile is processed) aren't supported.
+
+use serde_json::{json, Value};
+
+/// The `preprocessedProfileVersion` which fxprof-processed-profile writes.
+pub const CURRENT_VERSION: u64 = 55;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProfileFormatError {
+    #[error("The file is not a profile: {0}")]
+    NotAProfile(&'static str),
+
+    #[error("This is a Gecko profile, which needs to be processed by the Firefox Profiler first")]
+    GeckoProfile,
+
+    #[error("The profile is invalid: {0}")]
+    Invalid(String),
+}
+
+impl From<ProfileFormatError> for std::io::Error {
+    fn from(err: ProfileFormatError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// A table name, a column name, and the value of the column for each row.
+type ColumnDefault = (&'static str, &'static str, fn() -> Value);
+
+/// Columns which older versions don't have, with the value for each row.
+const MISSING_COLUMN_DEFAULTS: &[ColumnDefault] = &[
+    ("frameTable", "address", || json!(-1)),
+    ("frameTable", "inlineDepth", || json!(0)),
+    ("frameTable", "nativeSymbol", || Value::Null),
+    ("frameTable", "line", || Value::Null),
+    ("frameTable", "column", || Value::Null),
+    ("frameTable", "innerWindowID", || json!(0)),
+    ("funcTable", "fileName", || Value::Null),
+    ("funcTable", "lineNumber", || Value::Null),
+    ("funcTable", "columnNumber", || Value::Null),
+    ("funcTable", "isJS", || json!(false)),
+    ("funcTable", "relevantForJS", || json!(false)),
+];
+
+/// The thread tables, with a column which every version has, for finding
+/// out the number of rows of tables without a `length`.
+const THREAD_TABLES: &[(&str, &str)] = &[
+    ("samples", "stack"),
+    ("stackTable", "frame"),
+    ("frameTable", "func"),
+    ("funcTable", "name"),
+    ("resourceTable", "lib"),
+    ("nativeSymbols", "address"),
+    ("markers", "name"),
+];
+
+/// Converts the profile to the current version, in place. Returns the version
+/// which the profile had before.
+pub fn upgrade_profile(profile: &mut Value) -> Result<u64, ProfileFormatError> {
+    if !profile.is_object() {
+        return Err(ProfileFormatError::NotAProfile("expected a JSON object"));
+    }
+    let Some(version) = profile["meta"]["preprocessedProfileVersion"].as_u64() else {
+        if profile["meta"]["version"].is_u64() {
+            return Err(ProfileFormatError::GeckoProfile);
+        }
+        return Err(ProfileFormatError::NotAProfile(
+            "meta.preprocessedProfileVersion is missing",
+        ));
+    };
+    if version == CURRENT_VERSION {
+        return Ok(version);
+    }
+
+    let shared_strings = profile["shared"]["stringArray"].as_array().cloned();
+    let Some(threads) = profile["threads"].as_array_mut() else {
+        return Err(ProfileFormatError::NotAProfile("threads is missing"));
+    };
+    for thread in threads {
+        if thread["stringArray"].is_null() {
+            if let Some(strings) = &shared_strings {
+                thread["stringArray"] = Value::Array(strings.clone());
+            }
+        }
+        add_table_lengths(thread);
+        add_missing_columns(thread);
+    }
+    if let Some(shared) = profile["shared"].as_object_mut() {
+        shared.remove("stringArray");
+    }
+    profile["meta"]["preprocessedProfileVersion"] = json!(CURRENT_VERSION);
+
+    check_table_lengths(profile)?;
+    Ok(version)
+}
+
+fn add_table_lengths(thread: &mut Value) {
+    for (table_name, column) in THREAD_TABLES {
+        let Some(table) = thread.get_mut(*table_name).filter(|t| t.is_object()) else {
+            continue;
+        };
+        if table["length"].is_u64() {
+            continue;
+        }
+        if let Some(len) = table[*column].as_array().map(Vec::len) {
+            table["length"] = json!(len);
+        }
+    }
+}
+
+fn add_missing_columns(thread: &mut Value) {
+    for (table_name, column, default) in MISSING_COLUMN_DEFAULTS {
+        let Some(table) = thread.get_mut(*table_name).filter(|t| t.is_object()) else {
+            continue;
+        };
+        if !table[*column].is_null() {
+            continue;
+        }
+        let Some(len) = table["length"].as_u64() else {
+            continue;
+        };
+        table[*column] = Value::Array((0..len).map(|_| default()).collect());
+    }
+}
+
+fn check_table_lengths(profile: &Value) -> Result<(), ProfileFormatError> {
+    match table_length_errors(profile).into_iter().next() {
+        Some(error) => Err(ProfileFormatError::Invalid(error)),
+        None => Ok(()),
+    }
+}
+
+/// Returns an error message for each column of the thread tables which
+/// doesn't have one value per row.
+pub fn table_length_errors(profile: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    let threads = profile["threads"].as_array().map_or(&[][..], Vec::as_slice);
+    for (thread_index, thread) in threads.iter().enumerate() {
+        for (table_name, _) in THREAD_TABLES {
+            let Some(table) = thread[*table_name].as_object() else {
+                continue;
+            };
+            let Some(len) = table.get("length").and_then(Value::as_u64) else {
+                continue;
+            };
+            for (column, values) in table {
+                if let Some(values) = values.as_array() {
+                    if values.len() as u64 != len {
+                        errors.push(format!(
+                            "threads[{thread_index}].{table_name}.{column} has {} values, but the table has {len} rows",
+                            values.len()
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn upgrade_old_profile() {
+        let mut profile = json!({
+            "meta": { "preprocessedProfileVersion": 40 },
+            "shared": { "stringArray": ["main", "0x10"] },
+            "threads": [{
+                "samples": { "stack": [0, 0], "time": [0.0, 1.0] },
+                "stackTable": { "prefix": [null], "frame": [0] },
+                "frameTable": { "func": [0] },
+                "funcTable": { "name": [1], "resource": [-1] },
+                "resourceTable": { "lib": [] },
+            }],
+        });
+        assert_eq!(upgrade_profile(&mut profile).unwrap(), 40);
+        let thread = &profile["threads"][0];
+        assert_eq!(thread["stringArray"], json!(["main", "0x10"]));
+        assert_eq!(thread["samples"]["length"], 2);
+        assert_eq!(thread["frameTable"]["address"], json!([-1]));
+        assert_eq!(thread["funcTable"]["fileName"], json!([null]));
+        assert!(profile["shared"].get("stringArray").is_none());
+        assert!(thread.get("markers").is_none());
+        assert_eq!(
+            profile["meta"]["preprocessedProfileVersion"],
+            CURRENT_VERSION
+        );
+    }
+
+    #[test]
+    fn reject_invalid_profiles() {
+        let mut gecko = json!({ "meta": { "version": 27 }, "threads": [] });
+        assert!(matches!(
+            upgrade_profile(&mut gecko),
+            Err(ProfileFormatError::GeckoProfile)
+        ));
+
+        let mut mismatched = json!({
+            "meta": { "preprocessedProfileVersion": 50 },
+            "threads": [{ "stackTable": { "length": 2, "prefix": [null], "frame": [0, 1] } }],
+        });
+        assert!(matches!(
+            upgrade_profile(&mut mismatched),
+            Err(ProfileFormatError::Invalid(_))
+        ));
+    }
+}
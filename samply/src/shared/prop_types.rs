@@ -4,7 +4,15 @@ use std::time::Duration;
 
 use serde_derive::{Deserialize, Serialize};
 
+use super::cpu_topology::CoreType;
+use super::derived_counters::DerivedCounter;
 use super::included_processes::IncludedProcesses;
+use super::metrics_scraper::MetricsSource;
+use super::name_pattern::matches_pattern;
+use super::probes::ProbeSpec;
+use super::redaction::RedactionRules;
+use crate::import::ImportFormat;
+use crate::report::StackPattern;
 
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct CoreClrProfileProps {
@@ -33,14 +41,104 @@ pub struct RecordingProps {
     pub output_file: PathBuf,
     pub time_limit: Option<Duration>,
     pub interval: Duration,
+    /// Randomize the time between samples by up to this percentage of `interval`.
+    pub interval_jitter: f64,
+    /// Thread name patterns (with `*` wildcards) which should be sampled at a
+    /// different rate, in Hz.
+    pub thread_rate_overrides: Vec<(String, f64)>,
     #[allow(dead_code)]
     pub vm_hack: bool,
     #[allow(dead_code)]
     pub gfx: bool,
     #[allow(dead_code)]
+    pub audio: bool,
+    #[allow(dead_code)]
+    pub network: bool,
+    #[allow(dead_code)]
+    pub registry: bool,
+    #[allow(dead_code)]
+    pub rpc: bool,
+    #[allow(dead_code)]
     pub browsers: bool,
     #[allow(dead_code)]
     pub keep_etl: bool,
+    /// ETW providers (names or GUIDs) whose events should be recorded with stacks.
+    #[allow(dead_code)]
+    pub providers_with_stacks: Vec<String>,
+    /// How often to request a rundown of processes, threads and images while recording.
+    #[allow(dead_code)]
+    pub rundown_interval: Option<Duration>,
+    /// Stop after sampling every thread this many times. Used by `samply snapshot`.
+    #[allow(dead_code)]
+    pub sample_limit: Option<u64>,
+    /// Sample with SuspendThread and stack walking instead of ETW, which works
+    /// without administrator rights.
+    #[allow(dead_code)]
+    pub user_mode_sampler: bool,
+    /// Also sample kernel stacks with kperf (macOS only, needs root).
+    #[allow(dead_code)]
+    pub kernel_stacks: bool,
+    /// Which kernel events the ETW kernel logger records (Windows only).
+    #[allow(dead_code)]
+    pub etw_preset: EtwPreset,
+    /// Pipe the launched command's stderr through samply to find Rust panic
+    /// messages (Linux only).
+    #[allow(dead_code)]
+    pub panic_markers: bool,
+    /// Add a marker on the main thread for every line which the launched
+    /// command prints to stdout or stderr (Linux only).
+    #[allow(dead_code)]
+    pub capture_output: bool,
+    /// Add a marker for each test of a test run from the launched command's
+    /// libtest JSON or pytest report log output on stdout (Linux only).
+    #[allow(dead_code)]
+    pub test_markers: bool,
+    /// Copy the code of perf map JIT functions into jitdump files next to the
+    /// profile (Linux only).
+    #[allow(dead_code)]
+    pub save_jit_code: bool,
+    /// Add markers for the hits of these uprobes and USDT probes (Linux only).
+    #[allow(dead_code)]
+    pub probes: Vec<ProbeSpec>,
+    /// Add markers for the hits of kprobes on these kernel functions (Linux
+    /// only).
+    #[allow(dead_code)]
+    pub kprobes: Vec<String>,
+    /// Add markers for the hits of the tracepoints which match these
+    /// `CATEGORY:NAME` patterns (Linux only).
+    #[allow(dead_code)]
+    pub tracepoints: Vec<String>,
+    /// Sample the stacks of the probe hits (Linux only).
+    #[allow(dead_code)]
+    pub probe_stacks: bool,
+    /// Sample the stacks at which threads block, from the sched_switch
+    /// tracepoint, for off-CPU samples (Linux only; macOS and Windows always
+    /// sample blocked threads).
+    #[allow(dead_code)]
+    pub off_cpu: bool,
+    /// Keep recording when the recorded process quits, and record the next
+    /// process with the same name, with a restart marker.
+    pub follow_restarts: bool,
+    /// Only keep the samples of this last stretch of time in memory, and save
+    /// them on SIGUSR2 (Linux only).
+    #[allow(dead_code)]
+    pub ring_buffer: Option<Duration>,
+    /// Record these application metrics as counters.
+    pub metrics_sources: Vec<MetricsSource>,
+    /// How often to scrape the Prometheus endpoints in `metrics_sources`.
+    pub metrics_interval: Duration,
+}
+
+/// A named set of ETW kernel flags, chosen with `samply record --preset`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EtwPreset {
+    /// CPU samples and context switches.
+    #[default]
+    Cpu,
+    /// Also disk and file I/O.
+    Io,
+    /// Also virtual memory allocations and page faults.
+    Full,
 }
 
 /// Which process(es) to record.
@@ -50,8 +148,38 @@ pub enum RecordingMode {
     All,
     /// Record just a single process (and its children).
     Pid(u32),
+    /// Wait for a process whose name contains this string to start, and record
+    /// just that process (and its children).
+    WaitForProcess(String),
     /// Launch a process, and record just that process (and its children).
     Launch(ProcessLaunchProps),
+    /// Record several existing processes (and their children), and the
+    /// launched process if there is one, into the same profile.
+    Multiple {
+        pids: Vec<u32>,
+        launch: Option<ProcessLaunchProps>,
+    },
+    /// Record the process of a Windows service (and its children).
+    #[cfg(target_os = "windows")]
+    Service(ServiceProps),
+    /// Record the processes of a systemd unit, by following its cgroup, also
+    /// across restarts of the unit.
+    #[cfg(target_os = "linux")]
+    SystemdUnit(String),
+    /// Record the processes of a launchd job, by its label, also across
+    /// restarts of the job.
+    #[cfg(target_os = "macos")]
+    LaunchdJob(String),
+}
+
+/// The Windows service to record with `--service`.
+#[cfg(target_os = "windows")]
+#[derive(Debug, Clone)]
+pub struct ServiceProps {
+    /// The service name, e.g. "Dnscache", as opposed to its display name.
+    pub name: String,
+    /// Start the service if it isn't running.
+    pub start: bool,
 }
 
 impl RecordingMode {
@@ -60,7 +188,15 @@ impl RecordingMode {
         match self {
             RecordingMode::All => true,
             RecordingMode::Pid(_) => true,
+            RecordingMode::WaitForProcess(_) => false,
             RecordingMode::Launch(_) => false,
+            RecordingMode::Multiple { launch, .. } => launch.is_none(),
+            #[cfg(target_os = "windows")]
+            RecordingMode::Service(_) => true,
+            #[cfg(target_os = "linux")]
+            RecordingMode::SystemdUnit(_) => true,
+            #[cfg(target_os = "macos")]
+            RecordingMode::LaunchdJob(_) => true,
         }
     }
 }
@@ -101,6 +237,32 @@ pub struct ProfileCreationProps {
     /// Whether to emit context switch markers.
     #[allow(dead_code)]
     pub should_emit_cswitch_markers: bool,
+    /// Attach the stack of a nearby sample to markers from marker files.
+    pub marker_stacks_from_samples: bool,
+    /// Reduce the profile until its JSON is at most this many bytes.
+    pub max_output_size: Option<u64>,
+    /// Treat events whose name starts with this `<provider>/<event>` prefix as
+    /// frame boundaries.
+    #[allow(dead_code)]
+    pub frame_marker: Option<String>,
+    /// Put a label with the thread's run state, e.g. "state=Waiting", at the root of
+    /// each sample's stack. Used by `samply snapshot`.
+    #[allow(dead_code)]
+    pub thread_state_labels: bool,
+    /// Make the profile's timestamps UTC wall-clock times, relative to the Unix
+    /// epoch, instead of relative to the start of the profile.
+    pub utc_timestamps: bool,
+    /// Counters which are computed from other counters when the profile is saved.
+    pub derived_counters: Vec<DerivedCounter>,
+    /// Add a scheduling latency track to each thread, and mark wakeups after
+    /// which the thread waited longer than this many milliseconds for a CPU.
+    pub sched_latency_threshold_ms: Option<f64>,
+    /// Encrypt the saved profile to these age recipients, if any.
+    pub encryption_recipients: Vec<String>,
+    /// Rules for redacting marker strings before the profile is saved.
+    pub redaction: RedactionRules,
+    /// Save the timings of samply's own work as an auxiliary profile.
+    pub self_profile: bool,
 }
 
 impl ProfileCreationProps {
@@ -130,8 +292,20 @@ pub struct ImportProps {
     pub included_processes: Option<IncludedProcesses>,
     #[allow(unused)] // Windows-only
     pub user_etl: Vec<PathBuf>,
+    /// Files with ETW schemas for decoding the events of the imported file
+    #[allow(unused)] // Windows-only
+    pub etw_schemas: Vec<PathBuf>,
+    /// Where to save the schemas of the imported file's ETW events
+    #[allow(unused)] // Windows-only
+    pub export_etw_schemas: Option<PathBuf>,
     #[allow(unused)] // todo: respect when converting perf.data
     pub time_range: Option<(std::time::Duration, std::time::Duration)>,
+    /// The format of the input file, if it shouldn't be detected.
+    pub format: Option<ImportFormat>,
+    /// The guest's /proc/kallsyms, for perf.data files from `perf kvm`
+    pub guest_kallsyms: Option<PathBuf>,
+    /// The guest's /proc/modules
+    pub guest_modules: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -150,4 +324,185 @@ pub struct SymbolProps {
     pub breakpad_symbol_cache: Option<PathBuf>,
     /// Extra directory containing symbol files, with the directory structure used by simpleperf's scripts
     pub simpleperf_binary_cache: Option<PathBuf>,
+    /// Archives which may contain binaries or debug files
+    pub symbol_archives: Vec<PathBuf>,
+    /// Path prefixes which are replaced with local directories, for source files
+    pub path_remappings: Vec<(String, PathBuf)>,
+    /// Repositories from which missing source files are fetched, by path prefix
+    pub source_repositories: Vec<(String, wholesym::SourceRepository)>,
+    /// The proxy for symbol downloads, instead of the proxy environment variables
+    pub download_proxy: Option<String>,
+    /// The connect and read timeout for symbol downloads
+    pub download_timeout: Option<std::time::Duration>,
+    /// How often to retry failed symbol downloads
+    pub download_retries: Option<u32>,
+    /// The maximum number of concurrent symbol downloads
+    pub max_concurrent_downloads: Option<usize>,
+    /// Patterns for the names of libraries which aren't symbolicated up front,
+    /// only on demand when the profiler asks for them
+    pub no_symbolicate: Vec<String>,
+}
+
+impl SymbolProps {
+    /// Returns false if the library's name or debug name matches one of the
+    /// `--no-symbolicate` patterns.
+    pub fn should_symbolicate(&self, name: Option<&str>, debug_name: Option<&str>) -> bool {
+        !self.no_symbolicate.iter().any(|pattern| {
+            [name, debug_name]
+                .into_iter()
+                .flatten()
+                .any(|name| matches_pattern(pattern, name))
+        })
+    }
+}
+
+/// Properties for the `samply report` command.
+#[derive(Debug, Clone)]
+pub struct ReportProps {
+    /// How to group samples.
+    pub aggregation: ReportAggregation,
+    /// The maximum number of rows to print.
+    pub limit: usize,
+    /// Print the disassembly around each hot address. Only used with `ReportAggregation::Address`.
+    pub disassemble: bool,
+    /// Invert the call tree. Only used with `ReportAggregation::CallTree`.
+    pub inverted: bool,
+    /// Show the library and library-relative address next to each native
+    /// function. Only used with `ReportAggregation::Function` and `ReportAggregation::CallTree`.
+    pub addresses: bool,
+    /// Only keep samples with a function matching this regex, and drop the frames above it.
+    pub focus: Option<regex::Regex>,
+    /// Remove frames with functions matching any of these regexes from all stacks.
+    pub hide: Vec<regex::Regex>,
+    /// Only include the samples from the per-CPU tracks of some CPUs.
+    pub cpu_filter: Option<CpuFilter>,
+}
+
+/// Selects per-CPU tracks, by CPU number and by core type. If both are given,
+/// only the CPUs which match both are selected.
+#[derive(Debug, Clone)]
+pub struct CpuFilter {
+    pub cpus: Option<Vec<u32>>,
+    pub core_type: Option<CoreType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportAggregation {
+    /// Group samples by the source line of the leaf frame.
+    Line,
+    /// Group samples by the instruction address of the leaf frame.
+    Address,
+    /// Group samples by function, with both self and total (running) time.
+    Function,
+    /// Print the call tree.
+    CallTree,
+}
+
+/// Properties for the `samply assert` command.
+#[derive(Debug, Clone)]
+pub struct AssertProps {
+    pub thresholds: Vec<TimeThreshold>,
+}
+
+/// A limit on the percentage of samples spent in functions matching a regex.
+#[derive(Debug, Clone)]
+pub struct TimeThreshold {
+    pub kind: TimeThresholdKind,
+    pub function: regex::Regex,
+    pub max_percentage: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeThresholdKind {
+    /// Samples whose leaf function matches.
+    SelfTime,
+    /// Samples with a matching function anywhere on the stack.
+    TotalTime,
+}
+
+/// Properties for the `samply grep` command.
+#[derive(Debug, Clone)]
+pub struct GrepProps {
+    pub pattern: StackPattern,
+    /// Only print the number of matching samples.
+    pub count_only: bool,
+    /// The maximum number of stacks to print.
+    pub limit: usize,
+    /// Show the library and library-relative address next to each native function.
+    pub addresses: bool,
+}
+
+/// Properties for the `samply summarize` command.
+#[derive(Debug, Clone)]
+pub struct SummarizeProps {
+    /// The maximum number of entries in each section.
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A heat map of the sample counts by second and by offset within the
+    /// second, in the JSON format of FlameScope's heat map.
+    FlameScope,
+}
+
+/// Properties for the `samply export` command.
+#[derive(Debug, Clone)]
+pub struct ExportProps {
+    pub format: ExportFormat,
+    /// For the FlameScope format, how many slices each second is divided into.
+    pub rows_per_second: usize,
+    /// The file to write to. If `None`, the output goes to stdout.
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    /// One line per folded stack with the sample counts from both profiles,
+    /// like the output of `difffolded.pl`.
+    CollapsedDiff,
+}
+
+/// Properties for the `samply diff` command.
+#[derive(Debug, Clone)]
+pub struct DiffProps {
+    pub format: DiffFormat,
+    /// Scale the counts of the first profile so that both have the same total.
+    pub normalize: bool,
+    /// The file to write to. If `None`, the output goes to stdout.
+    pub output: Option<PathBuf>,
+}
+
+/// Properties for the `samply daemon` command.
+#[derive(Debug, Clone)]
+pub struct DaemonProps {
+    /// The directory in which the profile chunks are saved.
+    pub output_dir: PathBuf,
+    /// How long each chunk is recorded for.
+    pub chunk_duration: Duration,
+    /// Chunks older than this are deleted. `None` keeps all chunks.
+    pub keep: Option<Duration>,
+    /// Where to upload each chunk after it has been saved, if anywhere.
+    pub push: Option<PushProps>,
+}
+
+/// Properties for pushing profiles to an observability backend.
+#[derive(Debug, Clone)]
+pub struct PushProps {
+    pub target: PushTarget,
+    /// The name of the profiled service. This is the application name in
+    /// Pyroscope, and the `service.name` resource attribute in OTLP.
+    pub service_name: String,
+    /// Extra labels, which are attached to all samples of the pushed profile.
+    pub labels: Vec<(String, String)>,
+    /// Extra HTTP headers, e.g. for authentication.
+    pub headers: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushTarget {
+    /// The base URL of a Pyroscope server, e.g. `http://localhost:4040`.
+    Pyroscope(String),
+    /// The base URL of an OTLP/HTTP endpoint which accepts profiles, e.g. `http://localhost:4318`.
+    Otlp(String),
 }
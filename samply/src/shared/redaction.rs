@@ -0,0 +1,334 @@
+//! Redaction of marker strings before the profile is saved, for `--redact`,
+//! `--redact-url-queries` and `--hash-path-prefix`, so that sensitive data in
+//! marker payloads, such as tokens in URLs or user names in file paths, never
+//! ends up in the saved profile.
+//!
+//! The rules apply to marker names and to the string fields of marker data.
+//! Function names, library paths and thread names are left alone.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+use serde_json::Value;
+
+/// What the matches of `--redact` patterns are replaced with.
+const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Default)]
+pub struct RedactionRules {
+    /// Matches of these patterns are replaced with "[redacted]".
+    patterns: Vec<Regex>,
+    /// Remove the query string and the fragment of URLs.
+    strip_url_queries: bool,
+    /// For each `--hash-path-prefix`, a regex which matches the prefix, the
+    /// separator and the path component after it.
+    hashed_path_prefixes: Vec<Regex>,
+}
+
+impl RedactionRules {
+    pub fn new(
+        patterns: Vec<Regex>,
+        strip_url_queries: bool,
+        hashed_path_prefixes: &[String],
+    ) -> Self {
+        let hashed_path_prefixes = hashed_path_prefixes
+            .iter()
+            .map(|prefix| {
+                let prefix = prefix.trim_end_matches(['/', '\\']);
+                // The prefix has to be at the start of a path, i.e. not in the
+                // middle of a word or of another path.
+                Regex::new(&format!(
+                    r#"(^|[^\w./\\-])({})([/\\])([^/\\\s"'<>]+)"#,
+                    regex::escape(prefix)
+                ))
+                .unwrap()
+            })
+            .collect();
+        Self {
+            patterns,
+            strip_url_queries,
+            hashed_path_prefixes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && !self.strip_url_queries && self.hashed_path_prefixes.is_empty()
+    }
+
+    /// Returns the redacted string, or `None` if no rule changed it.
+    pub fn redact(&self, s: &str) -> Option<String> {
+        let mut redacted = s.to_string();
+        if self.strip_url_queries {
+            redacted = url_with_query_regex()
+                .replace_all(&redacted, "$url")
+                .into_owned();
+        }
+        for prefix in &self.hashed_path_prefixes {
+            redacted = prefix
+                .replace_all(&redacted, |caps: &Captures| {
+                    format!(
+                        "{}{}{}#{:08x}",
+                        &caps[1],
+                        &caps[2],
+                        &caps[3],
+                        fnv1a_hash(&caps[4])
+                    )
+                })
+                .into_owned();
+        }
+        for pattern in &self.patterns {
+            redacted = pattern.replace_all(&redacted, REDACTED).into_owned();
+        }
+        (redacted != s).then_some(redacted)
+    }
+}
+
+/// Matches a URL with a query string or a fragment. The part before the `?`
+/// or `#` is captured as `url`.
+fn url_with_query_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r#"(?P<url>[a-zA-Z][a-zA-Z0-9+.-]*://[^\s?#"'<>]*)[?#][^\s"'<>]*"#).unwrap()
+    })
+}
+
+/// The 32-bit FNV-1a hash, which is stable across samply versions and
+/// platforms, so that hashed path components can be compared across profiles.
+fn fnv1a_hash(s: &str) -> u32 {
+    s.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+    })
+}
+
+/// Applies the rules to the marker names and the marker data strings of all
+/// threads. Returns the number of redacted strings.
+///
+/// Marker names and "unique-string" fields are indexes into the thread's
+/// string table, which is shared with function, file, library and symbol
+/// names. Strings which only markers use are redacted in place, so that the
+/// original doesn't stay in the saved profile. For strings which are also used
+/// outside the markers, the redacted string is added to the string table, and
+/// the markers are pointed at it.
+pub fn redact_marker_strings(profile: &mut Value, rules: &RedactionRules) -> usize {
+    let unique_string_fields = unique_string_fields(&profile["meta"]["markerSchema"]);
+    let Some(threads) = profile["threads"].as_array_mut() else {
+        return 0;
+    };
+    let mut redacted = 0;
+    for thread in threads {
+        // Redact the inline strings, and collect the string table indexes.
+        let mut string_indexes = BTreeSet::new();
+        if let Some(names) = thread["markers"]["name"].as_array() {
+            string_indexes.extend(names.iter().filter_map(Value::as_u64));
+        }
+        if let Some(data) = thread["markers"]["data"].as_array_mut() {
+            for fields in data.iter_mut().filter_map(Value::as_object_mut) {
+                let marker_type = match fields.get("type").and_then(Value::as_str) {
+                    Some(marker_type) => marker_type.to_string(),
+                    None => String::new(),
+                };
+                let unique_keys = unique_string_fields.get(&marker_type);
+                for (key, value) in fields.iter_mut().filter(|(key, _)| *key != "type") {
+                    match value {
+                        Value::String(s) => {
+                            if let Some(r) = rules.redact(s) {
+                                *s = r;
+                                redacted += 1;
+                            }
+                        }
+                        Value::Number(index)
+                            if unique_keys.is_some_and(|keys| keys.contains(key)) =>
+                        {
+                            string_indexes.extend(index.as_u64());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        // Redact the strings in the string table, or add the redacted strings
+        // to it if the originals are also used outside the markers.
+        let non_marker_string_indexes = non_marker_string_indexes(thread);
+        let Some(string_array) = thread["stringArray"].as_array_mut() else {
+            continue;
+        };
+        let mut remapped_indexes: HashMap<u64, u64> = HashMap::new();
+        for index in string_indexes {
+            let Some(r) = string_array
+                .get(index as usize)
+                .and_then(Value::as_str)
+                .and_then(|s| rules.redact(s))
+            else {
+                continue;
+            };
+            if non_marker_string_indexes.contains(&index) {
+                remapped_indexes.insert(index, string_array.len() as u64);
+                string_array.push(Value::String(r));
+            } else {
+                string_array[index as usize] = Value::String(r);
+            }
+            redacted += 1;
+        }
+        if remapped_indexes.is_empty() {
+            continue;
+        }
+
+        // Point the markers at the redacted strings.
+        let mut remap = |value: &mut Value| {
+            if let Some(new_index) = value.as_u64().and_then(|i| remapped_indexes.get(&i)) {
+                *value = Value::from(*new_index);
+            }
+        };
+        if let Some(names) = thread["markers"]["name"].as_array_mut() {
+            names.iter_mut().for_each(&mut remap);
+        }
+        if let Some(data) = thread["markers"]["data"].as_array_mut() {
+            for fields in data.iter_mut().filter_map(Value::as_object_mut) {
+                let marker_type = match fields.get("type").and_then(Value::as_str) {
+                    Some(marker_type) => marker_type.to_string(),
+                    None => String::new(),
+                };
+                let Some(unique_keys) = unique_string_fields.get(&marker_type) else {
+                    continue;
+                };
+                for key in unique_keys {
+                    if let Some(value) = fields.get_mut(key) {
+                        remap(value);
+                    }
+                }
+            }
+        }
+    }
+    redacted
+}
+
+/// The string table indexes which are used outside the markers of the thread,
+/// i.e. by functions, resources and native symbols.
+fn non_marker_string_indexes(thread: &Value) -> BTreeSet<u64> {
+    let columns = [
+        &thread["funcTable"]["name"],
+        &thread["funcTable"]["fileName"],
+        &thread["resourceTable"]["name"],
+        &thread["resourceTable"]["host"],
+        &thread["nativeSymbols"]["name"],
+    ];
+    columns
+        .into_iter()
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(Value::as_u64)
+        .collect()
+}
+
+/// The keys of the "unique-string" fields of each marker type, whose values
+/// are string table indexes.
+fn unique_string_fields(marker_schema: &Value) -> HashMap<String, Vec<String>> {
+    let mut fields_by_type = HashMap::new();
+    for schema in marker_schema.as_array().into_iter().flatten() {
+        let (Some(name), Some(fields)) = (schema["name"].as_str(), schema["fields"].as_array())
+        else {
+            continue;
+        };
+        let keys: Vec<String> = fields
+            .iter()
+            .filter(|field| field["format"] == "unique-string")
+            .filter_map(|field| Some(field["key"].as_str()?.to_string()))
+            .collect();
+        if !keys.is_empty() {
+            fields_by_type.insert(name.to_string(), keys);
+        }
+    }
+    fields_by_type
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn redaction_rules() {
+        let rules = RedactionRules::new(
+            vec![Regex::new(r"Bearer \S+").unwrap()],
+            true,
+            &["/home/".to_string()],
+        );
+        assert_eq!(
+            rules
+                .redact("GET https://example.com/api?token=abc#top done")
+                .as_deref(),
+            Some("GET https://example.com/api done")
+        );
+        assert_eq!(
+            rules.redact("open /home/alice/notes.txt").as_deref(),
+            Some(&*format!(
+                "open /home/#{:08x}/notes.txt",
+                fnv1a_hash("alice")
+            ))
+        );
+        assert_eq!(rules.redact("/data/home/alice"), None);
+        assert_eq!(
+            rules.redact("Authorization: Bearer s3cr3t").as_deref(),
+            Some("Authorization: [redacted]")
+        );
+        assert_eq!(rules.redact("nothing to see"), None);
+    }
+
+    #[test]
+    fn redacts_marker_names_and_fields() {
+        let mut profile = json!({
+            "meta": { "markerSchema": [{
+                "name": "Request",
+                "fields": [
+                    { "key": "url", "format": "url" },
+                    { "key": "user", "format": "unique-string" },
+                ],
+            }] },
+            "threads": [{
+                "stringArray": ["main", "Bearer x", "alice", "secret_fn"],
+                "funcTable": { "name": [3], "fileName": [null] },
+                "markers": {
+                    "name": [1, 0, 3],
+                    "data": [
+                        { "type": "Request", "url": "https://a.example/?q=1", "user": 2 },
+                        null,
+                        null,
+                    ],
+                },
+            }],
+        });
+        let rules = RedactionRules::new(
+            vec![Regex::new("Bearer .*|alice|secret").unwrap()],
+            true,
+            &[],
+        );
+        assert_eq!(redact_marker_strings(&mut profile, &rules), 4);
+
+        // No string which only markers used is left in the profile.
+        let serialized = serde_json::to_string(&profile).unwrap();
+        for original in ["Bearer x", "alice", "?q=1"] {
+            assert!(!serialized.contains(original), "{original} wasn't redacted");
+        }
+
+        // The function name stays, and the marker gets a redacted copy.
+        let thread = &profile["threads"][0];
+        assert_eq!(
+            thread["stringArray"],
+            json!([
+                "main",
+                "[redacted]",
+                "[redacted]",
+                "secret_fn",
+                "[redacted]_fn"
+            ])
+        );
+        assert_eq!(thread["markers"]["name"], json!([1, 0, 4]));
+        assert_eq!(
+            thread["markers"]["data"][0],
+            json!({ "type": "Request", "url": "https://a.example/", "user": 2 })
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Finding the restarts of the recorded process in a trace's process start and
+//! end events, for `--follow-restarts` with ETW, which records all processes
+//! and picks the recorded ones afterwards.
+
+use std::collections::HashSet;
+
+use super::process_sample_data::RestartMarker;
+
+/// What a process start means for the recorded process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstanceStart {
+    /// The process isn't an instance of the recorded process, or it's a child
+    /// of a recorded process, which is recorded anyway.
+    NotAnInstance,
+    /// The process is the first instance.
+    First,
+    /// The process is a new instance after all earlier ones have ended.
+    Restart(RestartMarker),
+}
+
+pub struct RestartTracker {
+    /// The pid of the first instance, with `--pid`.
+    first_pid: Option<u32>,
+    /// The executable name of the recorded process, or a substring of it. With
+    /// `--pid`, it's only known once the process has been seen.
+    name: Option<String>,
+    /// The instances which haven't ended yet.
+    running_pids: HashSet<u32>,
+    previous_pid: Option<u32>,
+    restart_count: u32,
+}
+
+impl RestartTracker {
+    /// Follows the restarts of the process `pid`, by its executable name.
+    pub fn for_pid(pid: u32) -> Self {
+        Self {
+            first_pid: Some(pid),
+            name: None,
+            running_pids: HashSet::new(),
+            previous_pid: None,
+            restart_count: 0,
+        }
+    }
+
+    /// Follows the restarts of the process whose executable name contains
+    /// `name`, as for `--wait-for-process`.
+    pub fn for_name(name: &str) -> Self {
+        Self {
+            first_pid: None,
+            name: Some(name.to_string()),
+            running_pids: HashSet::new(),
+            previous_pid: None,
+            restart_count: 0,
+        }
+    }
+
+    /// Called for each process which exists at the start of the trace or which
+    /// is started during it. A process with the recorded name is only a new
+    /// instance once all earlier instances have ended.
+    pub fn handle_process_start(
+        &mut self,
+        pid: u32,
+        exe_name: &str,
+        parent_is_recorded: bool,
+    ) -> InstanceStart {
+        if self.running_pids.contains(&pid) || parent_is_recorded {
+            return InstanceStart::NotAnInstance;
+        }
+        if self.first_pid == Some(pid) && self.previous_pid.is_none() {
+            self.name = Some(exe_name.to_string());
+        } else if !self
+            .name
+            .as_deref()
+            .is_some_and(|name| exe_name.contains(name))
+            || !self.running_pids.is_empty()
+        {
+            return InstanceStart::NotAnInstance;
+        }
+        self.running_pids.insert(pid);
+        let previous_pid = self.previous_pid.replace(pid);
+        match previous_pid {
+            Some(previous_pid) => {
+                self.restart_count += 1;
+                InstanceStart::Restart(RestartMarker {
+                    number: self.restart_count,
+                    previous_pid,
+                })
+            }
+            None => InstanceStart::First,
+        }
+    }
+
+    pub fn handle_process_end(&mut self, pid: u32) {
+        self.running_pids.remove(&pid);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn restart_number(start: InstanceStart) -> Option<(u32, u32)> {
+        match start {
+            InstanceStart::Restart(marker) => Some((marker.number, marker.previous_pid)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn restarts_of_pid() {
+        let mut tracker = RestartTracker::for_pid(10);
+        // Another instance which runs at the same time isn't recorded.
+        assert_eq!(
+            tracker.handle_process_start(5, "server.exe", false),
+            InstanceStart::NotAnInstance
+        );
+        assert_eq!(
+            tracker.handle_process_start(10, "server.exe", false),
+            InstanceStart::First
+        );
+        assert_eq!(
+            tracker.handle_process_start(11, "server.exe", true),
+            InstanceStart::NotAnInstance
+        );
+        assert_eq!(
+            tracker.handle_process_start(12, "server.exe", false),
+            InstanceStart::NotAnInstance
+        );
+        tracker.handle_process_end(10);
+        assert_eq!(
+            tracker.handle_process_start(13, "other.exe", false),
+            InstanceStart::NotAnInstance
+        );
+        assert_eq!(
+            restart_number(tracker.handle_process_start(14, "server.exe", false)),
+            Some((1, 10))
+        );
+        tracker.handle_process_end(14);
+        assert_eq!(
+            restart_number(tracker.handle_process_start(10, "server.exe", false)),
+            Some((2, 14))
+        );
+    }
+
+    #[test]
+    fn restarts_by_name() {
+        let mut tracker = RestartTracker::for_name("server");
+        assert_eq!(
+            tracker.handle_process_start(20, "my-server.exe", false),
+            InstanceStart::First
+        );
+        tracker.handle_process_end(20);
+        assert_eq!(
+            restart_number(tracker.handle_process_start(21, "my-server.exe", false)),
+            Some((1, 20))
+        );
+    }
+}
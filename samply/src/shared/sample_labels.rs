@@ -0,0 +1,194 @@
+//! Per-sample labels, such as the service, endpoint or request ID that a thread
+//! is currently working on.
+//!
+//! The profiled process sets a label by writing a marker span named
+//! `label:<key>=<value>` to its marker file, e.g. `label:endpoint=/api/foo`. Each
+//! sample taken on the same thread during the span gets a label frame at the
+//! root of its stack, named after all labels which are active at the time, e.g.
+//! `label:service=checkout, endpoint=/api/foo`. This keeps the labels visible in
+//! the profiler's call tree, and lets exporters turn them back into labels.
+
+use fxprof_processed_profile::{
+    FrameFlags, FrameHandle, Profile, SubcategoryHandle, ThreadHandle, Timestamp,
+};
+
+use super::process_sample_data::MarkerSpanOnThread;
+use super::types::FastHashMap;
+
+/// The prefix of marker names which set a label, and of the label frame names.
+pub const LABEL_PREFIX: &str = "label:";
+
+const LABEL_SEPARATOR: &str = ", ";
+
+/// Parses a label marker name like `label:endpoint=/api/foo` into its key and value.
+pub fn parse_label_marker_name(name: &str) -> Option<(&str, &str)> {
+    let (key, value) = name.strip_prefix(LABEL_PREFIX)?.split_once('=')?;
+    let key = key.trim();
+    if key.is_empty() {
+        return None;
+    }
+    Some((key, value.trim()))
+}
+
+/// Returns the name of a label frame for the given labels, e.g. `label:state=Waiting`.
+#[allow(dead_code)] // Only used on macOS
+pub fn label_frame_name(labels: &[(&str, &str)]) -> String {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+    format!("{LABEL_PREFIX}{}", labels.join(LABEL_SEPARATOR))
+}
+
+/// Parses the name of a label frame back into its labels. Returns `None` if this
+/// isn't a label frame.
+pub fn parse_label_frame_name(name: &str) -> Option<Vec<(String, String)>> {
+    let labels = name.strip_prefix(LABEL_PREFIX)?;
+    labels
+        .split(LABEL_SEPARATOR)
+        .map(|label| {
+            let (key, value) = label.split_once('=')?;
+            Some((key.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// The label frame to use for each thread's samples, over time.
+#[derive(Debug, Default)]
+pub struct SampleLabels {
+    /// Per thread, the times at which the set of active labels changes, sorted
+    /// by time, with the label frame for the samples from then on.
+    per_thread: FastHashMap<ThreadHandle, Vec<(Timestamp, Option<FrameHandle>)>>,
+}
+
+impl SampleLabels {
+    pub fn new(
+        profile: &mut Profile,
+        marker_spans: &[MarkerSpanOnThread],
+        category: SubcategoryHandle,
+    ) -> Self {
+        let mut spans_per_thread: FastHashMap<ThreadHandle, Vec<(Timestamp, Timestamp, String)>> =
+            FastHashMap::default();
+        for span in marker_spans {
+            if let Some((key, value)) = parse_label_marker_name(&span.name) {
+                spans_per_thread
+                    .entry(span.thread_handle)
+                    .or_default()
+                    .push((span.start_time, span.end_time, format!("{key}={value}")));
+            }
+        }
+
+        let mut per_thread = FastHashMap::default();
+        for (thread_handle, spans) in spans_per_thread {
+            let segments = label_segments(spans)
+                .into_iter()
+                .map(|(time, frame_name)| {
+                    let frame = frame_name.map(|frame_name| {
+                        let string = profile.handle_for_string(&frame_name);
+                        profile.handle_for_frame_with_label(
+                            thread_handle,
+                            string,
+                            category,
+                            FrameFlags::empty(),
+                        )
+                    });
+                    (time, frame)
+                })
+                .collect();
+            per_thread.insert(thread_handle, segments);
+        }
+        Self { per_thread }
+    }
+
+    /// Returns the label frame for a sample on `thread_handle` at `timestamp`, if
+    /// any labels are active at that time.
+    pub fn label_frame_at(
+        &self,
+        thread_handle: ThreadHandle,
+        timestamp: Timestamp,
+    ) -> Option<FrameHandle> {
+        let segments = self.per_thread.get(&thread_handle)?;
+        let index = segments.partition_point(|(time, _)| *time <= timestamp);
+        segments[index.checked_sub(1)?].1
+    }
+}
+
+/// Splits the time covered by the label spans into segments with a constant set
+/// of active labels. Returns the start time of each segment and its label frame
+/// name, or `None` for segments without labels. Labels are listed in the order
+/// in which their spans started. The span end is exclusive.
+fn label_segments(
+    mut spans: Vec<(Timestamp, Timestamp, String)>,
+) -> Vec<(Timestamp, Option<String>)> {
+    spans.sort_by_key(|(start, _, _)| *start);
+    let mut boundaries: Vec<Timestamp> = spans
+        .iter()
+        .flat_map(|(start, end, _)| [*start, *end])
+        .collect();
+    boundaries.sort();
+    boundaries.dedup();
+
+    let mut segments: Vec<(Timestamp, Option<String>)> = Vec::new();
+    for time in boundaries {
+        let active: Vec<&str> = spans
+            .iter()
+            .take_while(|(start, _, _)| *start <= time)
+            .filter(|(_, end, _)| time < *end)
+            .map(|(_, _, label)| label.as_str())
+            .collect();
+        let frame_name =
+            (!active.is_empty()).then(|| format!("{LABEL_PREFIX}{}", active.join(LABEL_SEPARATOR)));
+        if segments.last().map(|(_, name)| name) != Some(&frame_name) {
+            segments.push((time, frame_name));
+        }
+    }
+    segments
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn label_names() {
+        assert_eq!(
+            parse_label_marker_name("label:endpoint=/api/foo"),
+            Some(("endpoint", "/api/foo"))
+        );
+        assert_eq!(parse_label_marker_name("endpoint=/api/foo"), None);
+        assert_eq!(parse_label_marker_name("label:endpoint"), None);
+        assert_eq!(
+            parse_label_frame_name("label:service=checkout, endpoint=/api/foo"),
+            Some(vec![
+                ("service".to_string(), "checkout".to_string()),
+                ("endpoint".to_string(), "/api/foo".to_string()),
+            ])
+        );
+        assert_eq!(parse_label_frame_name("main"), None);
+        assert_eq!(
+            label_frame_name(&[("state", "Waiting")]),
+            "label:state=Waiting"
+        );
+    }
+
+    #[test]
+    fn overlapping_label_spans() {
+        let t = Timestamp::from_nanos_since_reference;
+        let segments = label_segments(vec![
+            (t(20), t(30), "endpoint=/api/foo".to_string()),
+            (t(10), t(40), "service=checkout".to_string()),
+        ]);
+        assert_eq!(
+            segments,
+            vec![
+                (t(10), Some("label:service=checkout".to_string())),
+                (
+                    t(20),
+                    Some("label:service=checkout, endpoint=/api/foo".to_string())
+                ),
+                (t(30), Some("label:service=checkout".to_string())),
+                (t(40), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! Sample weights for samples which don't cover exactly one sampling interval.
+//!
+//! Each sample in a profile stands for the time since the previous sample on
+//! its thread. Usually that's one sampling interval, and the sample gets a
+//! weight of 1. But some samples stand for more: perf reports the time since
+//! the previous sample as the sample's period, which can be several intervals
+//! if samples were throttled or lost, and pre-aggregated inputs such as folded
+//! stacks have a count for each stack. Giving these samples a matching weight
+//! keeps the call tree totals correct.
+
+/// Returns the weight of a sample with a period of `period_ns`, in multiples
+/// of the sampling interval. The weight is at least 1, so that no sample gets
+/// lost from the call tree.
+pub fn weight_for_period(period_ns: u64, interval_ns: u64) -> i32 {
+    if interval_ns == 0 {
+        return 1;
+    }
+    let weight = (period_ns as f64 / interval_ns as f64).round();
+    weight.clamp(1.0, i32::MAX as f64) as i32
+}
+
+/// Splits a sample count into weights which fit into the `i32` weights of the
+/// profile, so that huge counts from pre-aggregated inputs aren't truncated.
+pub fn weights_for_count(count: u64) -> impl Iterator<Item = i32> {
+    let max = i32::MAX as u64;
+    (0..count.div_ceil(max)).map(move |i| (count - i * max).min(max) as i32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn period_weights() {
+        assert_eq!(weight_for_period(1_000_000, 1_000_000), 1);
+        assert_eq!(weight_for_period(3_100_000, 1_000_000), 3);
+        assert_eq!(weight_for_period(200_000, 1_000_000), 1);
+        assert_eq!(weight_for_period(5, 0), 1);
+    }
+
+    #[test]
+    fn count_weights() {
+        assert_eq!(weights_for_count(42).collect::<Vec<_>>(), vec![42]);
+        assert_eq!(weights_for_count(0).count(), 0);
+        let max = i32::MAX as u64;
+        assert_eq!(
+            weights_for_count(2 * max + 5).collect::<Vec<_>>(),
+            vec![i32::MAX, i32::MAX, 5]
+        );
+    }
+}
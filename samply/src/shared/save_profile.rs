@@ -1,16 +1,35 @@
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufReader, BufWriter, Write};
 use std::path::Path;
 
+use flate2::bufread::GzDecoder;
 use flate2::{Compression, GzBuilder};
 use fxprof_processed_profile::Profile;
+use serde::Serialize;
+
+use super::derived_counters::{add_derived_counters, DerivedCounter};
+use super::encryption::{encrypt, encrypted_output_path};
+use super::metrics_scraper::{add_metric_counters, MetricSeries};
+use super::profile_versioning::upgrade_profile;
+use super::prop_types::ProfileCreationProps;
+use super::redaction::{redact_marker_strings, RedactionRules};
+use super::size_budget::{json_size, reduce_to_size};
 
 // Level two has an acceptable trade-off between how long compression
 // takes and how much data it saves on the profile JSONs I tested with.
 const GZIP_COMPRESSION_LEVEL: u32 = 2;
 
-pub fn save_profile_to_file(profile: &Profile, output_path: &Path) -> std::io::Result<()> {
+/// Saves the profile as JSON, gzip-compressed if the file name ends in `.gz`.
+/// An output path of `-` writes uncompressed JSON to stdout.
+pub fn save_profile_to_file(profile: &impl Serialize, output_path: &Path) -> std::io::Result<()> {
+    if output_path.as_os_str() == "-" {
+        let mut writer = BufWriter::new(std::io::stdout().lock());
+        serde_json::to_writer(&mut writer, &profile)?;
+        writer.flush()?;
+        return Ok(());
+    }
+
     let output_file = match File::create(output_path) {
         Ok(output_file) => output_file,
         Err(err) => {
@@ -19,7 +38,16 @@ pub fn save_profile_to_file(profile: &Profile, output_path: &Path) -> std::io::R
         }
     };
 
-    let writer = BufWriter::new(output_file);
+    write_profile(profile, BufWriter::new(output_file), output_path)
+}
+
+/// Writes the profile JSON, gzip-compressed if the file name of `output_path`
+/// ends in `.gz`.
+fn write_profile(
+    profile: &impl Serialize,
+    writer: impl Write,
+    output_path: &Path,
+) -> std::io::Result<()> {
     let is_gz = output_path.extension() == Some(OsStr::new("gz"));
     if is_gz {
         let name_without_gz = output_path.file_stem().unwrap().to_string_lossy();
@@ -32,3 +60,121 @@ pub fn save_profile_to_file(profile: &Profile, output_path: &Path) -> std::io::R
     }
     Ok(())
 }
+
+/// Saves the profile encrypted to the age recipients, at the output path with
+/// `.age` appended. The unencrypted profile is never written to disk. An output
+/// path of `-` writes the encrypted profile to stdout.
+pub fn save_encrypted_profile_to_file(
+    profile: &impl Serialize,
+    output_path: &Path,
+    recipients: &[String],
+) -> std::io::Result<()> {
+    let mut data = Vec::new();
+    write_profile(profile, &mut data, output_path)?;
+    let data = encrypt(&data, recipients)?;
+    if output_path.as_os_str() == "-" {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&data)?;
+        return stdout.flush();
+    }
+    std::fs::write(encrypted_output_path(output_path), data)
+}
+
+/// Changes which are made to the profile JSON when it's saved.
+#[derive(Debug, Default)]
+pub struct PostProcessing {
+    /// Reduce the profile until its JSON is at most this many bytes.
+    pub max_output_size: Option<u64>,
+    pub derived_counters: Vec<DerivedCounter>,
+    /// Application metrics which were recorded alongside the profile.
+    pub metrics: Vec<MetricSeries>,
+    /// Encrypt the saved profile to these age recipients, if any.
+    pub encryption_recipients: Vec<String>,
+    pub redaction: RedactionRules,
+}
+
+impl PostProcessing {
+    pub fn from_props(props: &ProfileCreationProps) -> Self {
+        Self {
+            max_output_size: props.max_output_size,
+            derived_counters: props.derived_counters.clone(),
+            metrics: Vec::new(),
+            encryption_recipients: props.encryption_recipients.clone(),
+            redaction: props.redaction.clone(),
+        }
+    }
+
+    fn save(&self, profile: &impl Serialize, output_path: &Path) -> std::io::Result<()> {
+        if self.encryption_recipients.is_empty() {
+            save_profile_to_file(profile, output_path)
+        } else {
+            save_encrypted_profile_to_file(profile, output_path, &self.encryption_recipients)
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.max_output_size.is_none()
+            && self.derived_counters.is_empty()
+            && self.metrics.is_empty()
+            && self.encryption_recipients.is_empty()
+            && self.redaction.is_empty()
+    }
+}
+
+/// Saves the profile like [`save_profile_to_file`], but first redacts the
+/// marker strings, adds the metric counters and the derived counters, and reduces it until its JSON is at most
+/// `max_output_size` bytes, if a size is given. With encryption recipients, the
+/// profile is saved like [`save_encrypted_profile_to_file`].
+pub fn save_profile_with_post_processing(
+    profile: &Profile,
+    output_path: &Path,
+    post_processing: &PostProcessing,
+) -> std::io::Result<()> {
+    if post_processing.is_empty() {
+        return save_profile_to_file(profile, output_path);
+    }
+    let mut profile = serde_json::to_value(profile)?;
+    let redacted = redact_marker_strings(&mut profile, &post_processing.redaction);
+    if redacted != 0 {
+        eprintln!("Redacted {redacted} marker strings.");
+    }
+    // Metrics are added first, so that derived counters can refer to them.
+    add_metric_counters(&mut profile, &post_processing.metrics);
+    for name in add_derived_counters(&mut profile, &post_processing.derived_counters) {
+        eprintln!(
+            "Warning: The derived counter {name:?} wasn't added, because no process has all the counters it refers to."
+        );
+    }
+    let Some(max_output_size) = post_processing.max_output_size else {
+        return post_processing.save(&profile, output_path);
+    };
+    for reduction in reduce_to_size(&mut profile, max_output_size) {
+        eprintln!("Reduced the profile size: {reduction}");
+    }
+    let size = json_size(&profile);
+    if size > max_output_size {
+        eprintln!(
+            "Warning: The profile is still {size} bytes large, which is more than the maximum of {max_output_size} bytes."
+        );
+    }
+    post_processing.save(&profile, output_path)
+}
+
+/// Reads a saved .json or .json.gz profile as JSON, and converts it to the
+/// current format version.
+pub fn load_profile_json(path: &Path) -> std::io::Result<serde_json::Value> {
+    let mut profile = read_profile_json(path)?;
+    upgrade_profile(&mut profile)?;
+    Ok(profile)
+}
+
+/// Reads a saved .json or .json.gz profile as JSON, as it is.
+pub fn read_profile_json(path: &Path) -> std::io::Result<serde_json::Value> {
+    let reader = BufReader::new(File::open(path)?);
+    let profile = if path.extension() == Some(OsStr::new("gz")) {
+        serde_json::from_reader(BufReader::new(GzDecoder::new(reader)))?
+    } else {
+        serde_json::from_reader(reader)?
+    };
+    Ok(profile)
+}
@@ -0,0 +1,158 @@
+//! Scheduling latency: the time a thread spends in the run queue, between being
+//! woken up and running on a CPU.
+//!
+//! Each thread which waited gets a "Sched latency" counter whose value is the
+//! latency of its most recent wakeup, and wakeups which waited longer than the
+//! threshold from `--sched-latency` are marked on the thread.
+
+use fxprof_processed_profile::{
+    Category, CategoryColor, CounterHandle, MarkerFieldFlags, MarkerFieldFormat, MarkerTiming,
+    ProcessHandle, Profile, StaticSchemaMarker, StaticSchemaMarkerField, StringHandle,
+    ThreadHandle, Timestamp,
+};
+
+use super::types::FastHashMap;
+
+struct ThreadLatency {
+    counter: CounterHandle,
+    last_latency_ms: f64,
+}
+
+pub struct SchedLatencyTracker {
+    outlier_threshold_ms: f64,
+    /// The time of the first wakeup of each thread which hasn't run since.
+    pending_wakeups: FastHashMap<u32, Timestamp>,
+    threads: FastHashMap<u32, ThreadLatency>,
+}
+
+impl SchedLatencyTracker {
+    pub fn new(outlier_threshold_ms: f64) -> Self {
+        Self {
+            outlier_threshold_ms,
+            pending_wakeups: FastHashMap::default(),
+            threads: FastHashMap::default(),
+        }
+    }
+
+    /// Called when the thread `tid` becomes runnable. If it's woken up again
+    /// before it runs, the first wakeup counts.
+    pub fn handle_wakeup(&mut self, tid: u32, timestamp: Timestamp) {
+        self.pending_wakeups.entry(tid).or_insert(timestamp);
+    }
+
+    /// Called when the thread `tid` starts running on a CPU. Returns the latency
+    /// in milliseconds, if the thread was woken up before.
+    pub fn handle_switch_in(
+        &mut self,
+        tid: u32,
+        timestamp: Timestamp,
+        process: ProcessHandle,
+        thread: ThreadHandle,
+        profile: &mut Profile,
+    ) -> Option<f64> {
+        let wakeup_timestamp = self.pending_wakeups.remove(&tid)?;
+        let latency_ms = timestamp
+            .nanos_since_reference()
+            .saturating_sub(wakeup_timestamp.nanos_since_reference())
+            as f64
+            / 1_000_000.0;
+
+        let thread_latency = self.threads.entry(tid).or_insert_with(|| ThreadLatency {
+            counter: profile.add_counter(
+                process,
+                &format!("Sched latency ({tid})"),
+                "Scheduling",
+                "Time between the wakeup of the thread and running on a CPU, in milliseconds",
+            ),
+            last_latency_ms: 0.0,
+        });
+        profile.add_counter_sample(
+            thread_latency.counter,
+            timestamp,
+            latency_ms - thread_latency.last_latency_ms,
+            1,
+        );
+        thread_latency.last_latency_ms = latency_ms;
+
+        if latency_ms > self.outlier_threshold_ms {
+            profile.add_marker(
+                thread,
+                MarkerTiming::Interval(wakeup_timestamp, timestamp),
+                SchedLatencyMarker { latency_ms },
+            );
+        }
+        Some(latency_ms)
+    }
+}
+
+/// A wakeup after which the thread waited longer than the threshold for a CPU.
+#[derive(Debug, Clone)]
+pub struct SchedLatencyMarker {
+    pub latency_ms: f64,
+}
+
+impl StaticSchemaMarker for SchedLatencyMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "SchedLatency";
+
+    const CATEGORY: Category<'static> = Category("Scheduling", CategoryColor::Orange);
+    const DESCRIPTION: Option<&'static str> =
+        Some("A wakeup after which the thread waited longer than the threshold for a CPU.");
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.latency}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("Sched latency: {marker.data.latency}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.data.latency}");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[StaticSchemaMarkerField {
+        key: "latency",
+        label: "Latency",
+        format: MarkerFieldFormat::Milliseconds,
+        flags: MarkerFieldFlags::SEARCHABLE,
+    }];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.handle_for_string("Sched latency")
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        unreachable!()
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.latency_ms
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{ReferenceTimestamp, SamplingInterval};
+
+    use super::*;
+
+    #[test]
+    fn latency_from_first_wakeup() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let t = Timestamp::from_millis_since_reference;
+        let process = profile.add_process("app", 1, t(0.0));
+        let thread = profile.add_thread(process, 1, t(0.0), true);
+
+        let mut tracker = SchedLatencyTracker::new(5.0);
+        assert_eq!(
+            tracker.handle_switch_in(1, t(1.0), process, thread, &mut profile),
+            None
+        );
+        tracker.handle_wakeup(1, t(2.0));
+        tracker.handle_wakeup(1, t(3.0));
+        assert_eq!(
+            tracker.handle_switch_in(1, t(4.5), process, thread, &mut profile),
+            Some(2.5)
+        );
+        assert_eq!(
+            tracker.handle_switch_in(1, t(5.0), process, thread, &mut profile),
+            None
+        );
+    }
+}
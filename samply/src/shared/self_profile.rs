@@ -0,0 +1,204 @@
+//! `--self-profile`: timings of samply's own work, such as reading the
+//! libraries of the profiled processes, saving the profile and symbolicating
+//! it, saved as an auxiliary profile next to the profile.
+//!
+//! Each timed phase becomes an interval marker on a thread for the samply
+//! thread which did the work, with the phase's stage and, for per-library
+//! work, the library. The marker table of the auxiliary profile then shows
+//! where samply spent its time, and can be attached to bug reports.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::thread::{Thread, ThreadId};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use fxprof_processed_profile::{
+    MarkerFieldFlags, MarkerFieldFormat, MarkerTiming, Profile, ReferenceTimestamp,
+    SamplingInterval, StaticSchemaMarker, StaticSchemaMarkerField, StringHandle, Timestamp,
+};
+
+use super::save_profile::save_profile_to_file;
+
+static RECORDER: OnceLock<Mutex<SelfProfileRecorder>> = OnceLock::new();
+
+struct SelfProfileRecorder {
+    start: Instant,
+    start_time: SystemTime,
+    /// The samply threads in the order in which they first recorded a phase,
+    /// with their names.
+    threads: Vec<(ThreadId, String)>,
+    phases: Vec<RecordedPhase>,
+}
+
+struct RecordedPhase {
+    thread: ThreadId,
+    stage: &'static str,
+    module: Option<String>,
+    start: Instant,
+    end: Instant,
+}
+
+/// Starts recording phases. Until this is called, [`phase`] does nothing.
+pub fn enable() {
+    RECORDER.get_or_init(|| {
+        Mutex::new(SelfProfileRecorder {
+            start: Instant::now(),
+            start_time: SystemTime::now(),
+            threads: Vec::new(),
+            phases: Vec::new(),
+        })
+    });
+}
+
+/// Times a phase of samply's work until the returned guard is dropped.
+/// `module` is the library or file which the phase works on, if any.
+pub fn phase(stage: &'static str, module: Option<&str>) -> PhaseGuard {
+    let active = RECORDER.get().map(|_| ActivePhase {
+        thread: std::thread::current(),
+        stage,
+        module: module.map(ToOwned::to_owned),
+        start: Instant::now(),
+    });
+    PhaseGuard(active)
+}
+
+/// Records its phase when dropped, if `--self-profile` is enabled.
+#[must_use]
+pub struct PhaseGuard(Option<ActivePhase>);
+
+struct ActivePhase {
+    /// The thread which started the phase. Async symbolication tasks can
+    /// finish on a different thread.
+    thread: Thread,
+    stage: &'static str,
+    module: Option<String>,
+    start: Instant,
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        let (Some(active), Some(recorder)) = (self.0.take(), RECORDER.get()) else {
+            return;
+        };
+        let end = Instant::now();
+        let thread = active.thread;
+        let mut recorder = recorder.lock().unwrap();
+        if !recorder.threads.iter().any(|(id, _)| *id == thread.id()) {
+            let name = match thread.name() {
+                Some(name) => name.to_owned(),
+                None => format!("Thread {}", recorder.threads.len()),
+            };
+            recorder.threads.push((thread.id(), name));
+        }
+        recorder.phases.push(RecordedPhase {
+            thread: thread.id(),
+            stage: active.stage,
+            module: active.module,
+            start: active.start,
+            end,
+        });
+    }
+}
+
+/// The path of the auxiliary profile for the profile at `output`, e.g.
+/// profile.json.self-profile.json for profile.json.gz.
+pub fn self_profile_path(output: &Path) -> PathBuf {
+    output.with_extension("self-profile.json")
+}
+
+/// Saves the phases recorded so far to the auxiliary profile for the profile
+/// at `output`. Does nothing if `--self-profile` isn't enabled.
+pub fn save(output: &Path) {
+    let Some(recorder) = RECORDER.get() else {
+        return;
+    };
+    if output.as_os_str() == "-" {
+        eprintln!("Not saving the self-profile, because the profile is written to stdout.");
+        return;
+    }
+    let profile = recorder.lock().unwrap().to_profile();
+    let path = self_profile_path(output);
+    match save_profile_to_file(&profile, &path) {
+        Ok(()) => eprintln!("Saved samply's self-profile to {path:?}."),
+        Err(err) => eprintln!("Could not save the self-profile to {path:?}: {err}"),
+    }
+}
+
+impl SelfProfileRecorder {
+    fn to_profile(&self) -> Profile {
+        let since_epoch = self
+            .start_time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut profile = Profile::new(
+            "samply self-profile",
+            ReferenceTimestamp::from_duration_since_unix_epoch(since_epoch),
+            SamplingInterval::from_millis(1),
+        );
+        let start = Timestamp::from_nanos_since_reference(0);
+        let process = profile.add_process("samply", std::process::id(), start);
+        let mut thread_handles = HashMap::new();
+        for (index, (thread_id, name)) in self.threads.iter().enumerate() {
+            let thread = profile.add_thread(process, index as u32, start, index == 0);
+            profile.set_thread_name(thread, name);
+            thread_handles.insert(*thread_id, thread);
+        }
+        for phase in &self.phases {
+            let timestamp = |instant: Instant| {
+                let nanos = instant.saturating_duration_since(self.start).as_nanos();
+                Timestamp::from_nanos_since_reference(nanos as u64)
+            };
+            let marker = PhaseMarker {
+                stage: profile.handle_for_string(phase.stage),
+                module: profile.handle_for_string(phase.module.as_deref().unwrap_or("")),
+            };
+            profile.add_marker(
+                thread_handles[&phase.thread],
+                MarkerTiming::Interval(timestamp(phase.start), timestamp(phase.end)),
+                marker,
+            );
+        }
+        profile
+    }
+}
+
+/// A phase of samply's own work, in the self-profile.
+#[derive(Debug, Clone)]
+struct PhaseMarker {
+    stage: StringHandle,
+    module: StringHandle,
+}
+
+impl StaticSchemaMarker for PhaseMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "SamplyPhase";
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.name} {marker.data.module}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("{marker.name} {marker.data.module}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.name} {marker.data.module}");
+
+    const DESCRIPTION: Option<&'static str> =
+        Some("A phase of samply's own work, recorded with --self-profile.");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[StaticSchemaMarkerField {
+        key: "module",
+        label: "Module",
+        format: MarkerFieldFormat::String,
+        flags: MarkerFieldFlags::SEARCHABLE,
+    }];
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.stage
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.module,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
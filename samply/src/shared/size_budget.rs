@@ -0,0 +1,317 @@
+//! Reducing a profile until its JSON fits into a size budget, for
+//! `--max-output-size`.
+//!
+//! The Firefox Profiler has to hold the whole profile in memory, and browsers
+//! struggle with profiles of several hundred megabytes. The reductions are
+//! applied in order, from the least to the most lossy, until the profile fits:
+//!
+//!  1. Adjacent samples with the same stack on the same thread are merged into
+//!     one sample, with the sum of their weights.
+//!  2. Long strings in marker data are truncated.
+//!  3. Only one in N samples of each thread is kept, with N = 2, 4, 8, ... The
+//!     kept sample takes on the weight and CPU time of the dropped samples after
+//!     it, so that the totals in the call tree stay correct.
+//!
+//! The applied reductions are listed in the profile's meta information.
+
+use serde_json::{json, Value};
+
+/// Marker strings are truncated to this many characters.
+const MAX_MARKER_STRING_CHARS: usize = 200;
+
+/// Give up on dropping samples beyond this factor.
+const MAX_SAMPLE_DROP_FACTOR: usize = 1024;
+
+/// Returns the size of the profile's JSON, in bytes.
+pub fn json_size(profile: &Value) -> u64 {
+    serde_json::to_vec(profile).map_or(0, |bytes| bytes.len() as u64)
+}
+
+/// Reduces the profile until its JSON is at most `max_size` bytes, or until
+/// nothing more can be done. Returns a description of each reduction.
+pub fn reduce_to_size(profile: &mut Value, max_size: u64) -> Vec<String> {
+    let mut reductions = Vec::new();
+    if json_size(profile) <= max_size {
+        return reductions;
+    }
+
+    let merged = for_each_samples_table(profile, merge_adjacent_identical_samples);
+    if merged > 0 {
+        reductions.push(format!(
+            "Merged {merged} samples into the previous sample with the same stack"
+        ));
+    }
+
+    if !fits(profile, &reductions, max_size) {
+        let truncated = truncate_marker_strings(profile);
+        if truncated > 0 {
+            reductions.push(format!(
+                "Truncated {truncated} marker strings to {MAX_MARKER_STRING_CHARS} characters"
+            ));
+        }
+    }
+
+    let mut factor = 1;
+    while factor < MAX_SAMPLE_DROP_FACTOR && !fits(profile, &reductions, max_size) {
+        // Each round halves the samples which are left from the previous round.
+        for_each_samples_table(profile, keep_every_other_sample);
+        if factor > 1 {
+            reductions.pop();
+        }
+        factor *= 2;
+        reductions.push(format!("Kept only one in {factor} samples"));
+    }
+
+    set_reductions_in_meta(profile, &reductions);
+    reductions
+}
+
+/// Checks whether the profile, with the reductions listed in its meta
+/// information, fits into `max_size` bytes.
+fn fits(profile: &mut Value, reductions: &[String], max_size: u64) -> bool {
+    set_reductions_in_meta(profile, reductions);
+    json_size(profile) <= max_size
+}
+
+/// Calls `f` for the samples table of each thread, and returns the sum of the
+/// results.
+fn for_each_samples_table(profile: &mut Value, mut f: impl FnMut(&mut Value) -> usize) -> usize {
+    let Some(threads) = profile["threads"].as_array_mut() else {
+        return 0;
+    };
+    threads
+        .iter_mut()
+        .filter_map(|thread| thread.get_mut("samples"))
+        .map(&mut f)
+        .sum()
+}
+
+/// The rows of a samples table, with absolute times.
+struct SampleRows {
+    stack: Vec<Value>,
+    time: Vec<f64>,
+    weight: Vec<f64>,
+    cpu_delta: Option<Vec<f64>>,
+}
+
+impl SampleRows {
+    fn read(samples: &Value) -> Option<Self> {
+        let stack = samples["stack"].as_array()?.clone();
+        let time: Vec<f64> = match (samples["time"].as_array(), samples["timeDeltas"].as_array()) {
+            (Some(time), _) => time.iter().map(|t| t.as_f64().unwrap_or(0.0)).collect(),
+            (None, Some(deltas)) => deltas
+                .iter()
+                .scan(0.0, |time, delta| {
+                    *time += delta.as_f64().unwrap_or(0.0);
+                    Some(*time)
+                })
+                .collect(),
+            (None, None) => return None,
+        };
+        let weight = match samples["weight"].as_array() {
+            Some(weight) => weight.iter().map(|w| w.as_f64().unwrap_or(1.0)).collect(),
+            None => vec![1.0; stack.len()],
+        };
+        let cpu_delta = samples["threadCPUDelta"]
+            .as_array()
+            .map(|deltas| deltas.iter().map(|d| d.as_f64().unwrap_or(0.0)).collect());
+        Some(SampleRows {
+            stack,
+            time,
+            weight,
+            cpu_delta,
+        })
+    }
+
+    /// Merges each row into the previous kept row if
+    /// `merge_into_previous(self, previous_kept_row, row)` returns true.
+    /// Returns the number of merged rows.
+    fn merge_rows(&mut self, merge_into_previous: impl Fn(&Self, usize, usize) -> bool) -> usize {
+        let mut kept = 0;
+        for row in 0..self.stack.len() {
+            if row > 0 && merge_into_previous(self, kept - 1, row) {
+                self.weight[kept - 1] += self.weight[row];
+                if let Some(cpu_delta) = &mut self.cpu_delta {
+                    cpu_delta[kept - 1] += cpu_delta[row];
+                }
+                continue;
+            }
+            self.stack.swap(kept, row);
+            self.time.swap(kept, row);
+            self.weight.swap(kept, row);
+            if let Some(cpu_delta) = &mut self.cpu_delta {
+                cpu_delta.swap(kept, row);
+            }
+            kept += 1;
+        }
+        let merged = self.stack.len() - kept;
+        self.stack.truncate(kept);
+        self.time.truncate(kept);
+        self.weight.truncate(kept);
+        if let Some(cpu_delta) = &mut self.cpu_delta {
+            cpu_delta.truncate(kept);
+        }
+        merged
+    }
+
+    fn write(self, samples: &mut Value) {
+        let mut previous_time = 0.0;
+        let time_deltas: Vec<f64> = self
+            .time
+            .iter()
+            .map(|time| {
+                let delta = time - previous_time;
+                previous_time = *time;
+                delta
+            })
+            .collect();
+        samples["length"] = json!(self.stack.len());
+        samples["stack"] = Value::Array(self.stack);
+        if let Some(object) = samples.as_object_mut() {
+            object.remove("time");
+        }
+        samples["timeDeltas"] = json!(time_deltas);
+        samples["weight"] = json!(self.weight);
+        if let Some(cpu_delta) = self.cpu_delta {
+            samples["threadCPUDelta"] = json!(cpu_delta);
+        }
+    }
+}
+
+fn merge_adjacent_identical_samples(samples: &mut Value) -> usize {
+    let Some(mut rows) = SampleRows::read(samples) else {
+        return 0;
+    };
+    let merged = rows.merge_rows(|rows, previous, row| rows.stack[previous] == rows.stack[row]);
+    if merged > 0 {
+        rows.write(samples);
+    }
+    merged
+}
+
+fn keep_every_other_sample(samples: &mut Value) -> usize {
+    let Some(mut rows) = SampleRows::read(samples) else {
+        return 0;
+    };
+    let merged = rows.merge_rows(|_, _, row| row % 2 != 0);
+    if merged > 0 {
+        rows.write(samples);
+    }
+    merged
+}
+
+/// Truncates long strings in the marker data of all threads. Returns the
+/// number of truncated strings.
+fn truncate_marker_strings(profile: &mut Value) -> usize {
+    let Some(threads) = profile["threads"].as_array_mut() else {
+        return 0;
+    };
+    let mut truncated = 0;
+    for thread in threads {
+        let Some(data) = thread["markers"]["data"].as_array_mut() else {
+            continue;
+        };
+        for fields in data.iter_mut().filter_map(Value::as_object_mut) {
+            for value in fields.values_mut() {
+                if let Value::String(s) = value {
+                    if let Some((index, _)) = s.char_indices().nth(MAX_MARKER_STRING_CHARS) {
+                        s.truncate(index);
+                        s.push('…');
+                        truncated += 1;
+                    }
+                }
+            }
+        }
+    }
+    truncated
+}
+
+pub const REDUCTIONS_SECTION_LABEL: &str = "Size reductions";
+
+/// Lists the reductions in the "extra" section of the profile's meta
+/// information, which the profiler shows in the profile info panel. Replaces
+/// the list from an earlier call.
+fn set_reductions_in_meta(profile: &mut Value, reductions: &[String]) {
+    if let Some(extra) = profile["meta"]["extra"].as_array_mut() {
+        extra.retain(|section| section["label"] != REDUCTIONS_SECTION_LABEL);
+    }
+    if reductions.is_empty() {
+        return;
+    }
+    let entries: Vec<Value> = reductions
+        .iter()
+        .enumerate()
+        .map(|(index, reduction)| {
+            json!({
+                "label": format!("Reduction {}", index + 1),
+                "format": "string",
+                "value": reduction,
+            })
+        })
+        .collect();
+    let section = json!({ "label": REDUCTIONS_SECTION_LABEL, "entries": entries });
+    match profile["meta"]["extra"].as_array_mut() {
+        Some(extra) => extra.push(section),
+        None => profile["meta"]["extra"] = json!([section]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn profile_with_samples(stack: Vec<Value>) -> Value {
+        let len = stack.len();
+        json!({
+            "meta": {},
+            "threads": [{
+                "samples": {
+                    "length": len,
+                    "stack": stack,
+                    "timeDeltas": vec![1.0; len],
+                    "weight": vec![1; len],
+                    "threadCPUDelta": vec![10; len],
+                },
+                "markers": { "data": [{ "type": "Text", "name": "x".repeat(1000) }] },
+            }],
+        })
+    }
+
+    #[test]
+    fn merge_identical_samples() {
+        let mut profile = profile_with_samples(vec![json!(0), json!(0), json!(1), json!(0)]);
+        let size = json_size(&profile);
+        let reductions = reduce_to_size(&mut profile, size - 1);
+        assert_eq!(
+            reductions[0],
+            "Merged 1 samples into the previous sample with the same stack"
+        );
+        let samples = &profile["threads"][0]["samples"];
+        assert_eq!(samples["stack"], json!([0, 1, 0]));
+        assert_eq!(samples["weight"], json!([2.0, 1.0, 1.0]));
+        assert_eq!(samples["threadCPUDelta"], json!([20.0, 10.0, 10.0]));
+        assert_eq!(samples["timeDeltas"], json!([1.0, 2.0, 1.0]));
+        assert_eq!(profile["meta"]["extra"][0]["label"], "Size reductions");
+    }
+
+    #[test]
+    fn drop_samples() {
+        let stacks: Vec<Value> = (0..64).map(|i| json!(i % 2)).collect();
+        let mut profile = profile_with_samples(stacks);
+        let reductions = reduce_to_size(&mut profile, 800);
+        assert!(json_size(&profile) <= 800);
+        assert!(reductions.last().unwrap().starts_with("Kept only one in"));
+        let samples = &profile["threads"][0]["samples"];
+        let total_weight: f64 = samples["weight"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|w| w.as_f64().unwrap())
+            .sum();
+        assert_eq!(total_weight, 64.0);
+        let marker_name = profile["threads"][0]["markers"]["data"][0]["name"]
+            .as_str()
+            .unwrap();
+        assert_eq!(marker_name.chars().count(), MAX_MARKER_STRING_CHARS + 1);
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::VecDeque;
 use std::iter::{Cloned, Rev};
 
 use fxprof_processed_profile::{
-    FrameAddress, FrameFlags, FrameHandle, Profile, SubcategoryHandle, ThreadHandle,
+    FrameAddress, FrameFlags, FrameHandle, Profile, SourceLocation, SubcategoryHandle, ThreadHandle,
 };
 
 use super::jit_category_manager::{JsFrame, JsName};
@@ -28,6 +28,7 @@ struct SecondPassFrameInfo {
     category: SubcategoryHandle,
     js_frame: Option<JsFrame>,
     art_info: Option<AndroidArtInfo>,
+    source_location: Option<SourceLocation>,
 }
 
 struct FirstPassIter<I: Iterator<Item = StackFrame>>(I);
@@ -100,7 +101,7 @@ impl<I: Iterator<Item = FirstPassFrameInfo>> Iterator for SecondPassIter<'_, I>
             lookup_address,
             from_ip,
         } = self.inner.next()?;
-        let (location, category, js_frame, art_info) = match mode {
+        let (location, category, js_frame, art_info, source_location) = match mode {
             StackMode::User => match self.lib_mappings.convert_address(lookup_address) {
                 Some((relative_lookup_address, info)) => {
                     let location = if from_ip {
@@ -120,6 +121,7 @@ impl<I: Iterator<Item = FirstPassFrameInfo>> Iterator for SecondPassIter<'_, I>
                         info.category.unwrap_or(self.user_category),
                         info.js_frame,
                         info.art_info,
+                        info.source_location,
                     )
                 }
                 None => {
@@ -127,7 +129,7 @@ impl<I: Iterator<Item = FirstPassFrameInfo>> Iterator for SecondPassIter<'_, I>
                         true => FrameAddress::InstructionPointer(lookup_address),
                         false => FrameAddress::AdjustedReturnAddress(lookup_address),
                     };
-                    (location, self.user_category, None, None)
+                    (location, self.user_category, None, None, None)
                 }
             },
             StackMode::Kernel => {
@@ -135,7 +137,7 @@ impl<I: Iterator<Item = FirstPassFrameInfo>> Iterator for SecondPassIter<'_, I>
                     true => FrameAddress::InstructionPointer(lookup_address),
                     false => FrameAddress::AdjustedReturnAddress(lookup_address),
                 };
-                (location, self.kernel_category, None, None)
+                (location, self.kernel_category, None, None, None)
             }
         };
         Some(SecondPassFrameInfo {
@@ -143,6 +145,7 @@ impl<I: Iterator<Item = FirstPassFrameInfo>> Iterator for SecondPassIter<'_, I>
             category,
             js_frame,
             art_info,
+            source_location,
         })
     }
 }
@@ -210,6 +213,7 @@ impl<I: Iterator<Item = SecondPassFrameInfo>> ConvertedStackIterD<I> {
             location,
             category,
             js_frame,
+            source_location,
             ..
         } = self.inner.next()?;
 
@@ -249,12 +253,24 @@ impl<I: Iterator<Item = SecondPassFrameInfo>> ConvertedStackIterD<I> {
         if let Some(JsName::NonSelfHosted(js_name)) = extra_js_name {
             // Prepend a JS frame.
             // We don't treat Spidermonkey "self-hosted" functions as JS (e.g. filter/map/push).
-            let prepended_js_frame = profile.handle_for_frame_with_label(
-                self.thread,
-                js_name,
-                category,
-                FrameFlags::IS_JS,
-            );
+            // If the JIT function has debug info, the JS frame gets its source location,
+            // so that all tiers of the same source function share one JS frame, with
+            // their own JIT frame below it.
+            let prepended_js_frame = match source_location {
+                Some(source_location) => profile.handle_for_frame_with_label_and_source_location(
+                    self.thread,
+                    js_name,
+                    source_location,
+                    category,
+                    FrameFlags::IS_JS,
+                ),
+                None => profile.handle_for_frame_with_label(
+                    self.thread,
+                    js_name,
+                    category,
+                    FrameFlags::IS_JS,
+                ),
+            };
             let buffered_frame = std::mem::replace(&mut frame_handle, prepended_js_frame);
             self.pending_frame_handle = Some(buffered_frame);
         };
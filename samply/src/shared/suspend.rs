@@ -0,0 +1,179 @@
+//! Markers for the times in which the profiled code couldn't run at all,
+//! because the machine was asleep or the process was suspended.
+//!
+//! Without them, these times show up as gaps in the samples, which look like
+//! the profiler missed something. With Windows' QPC timestamps, sleep is a
+//! span on the timeline. On macOS and Linux, the timestamps come from a clock
+//! which stops while the machine sleeps, so sleep only shows up as an instant
+//! marker with the time that was slept.
+
+use std::time::Duration;
+
+use fxprof_processed_profile::{
+    Category, CategoryColor, MarkerFieldFlags, MarkerFieldFormat, MarkerLocations, MarkerTiming,
+    Profile, StaticSchemaMarker, StaticSchemaMarkerField, StringHandle, ThreadHandle, Timestamp,
+};
+
+/// Sleeps shorter than this aren't marked; comparing the two clocks isn't
+/// precise enough to detect them reliably.
+const MIN_DETECTED_SLEEP: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspendKind {
+    /// The whole machine was asleep, in standby or hibernated.
+    SystemSleep,
+    /// The process was suspended, e.g. with SIGSTOP, by App Nap or by the
+    /// Windows process lifetime manager.
+    #[cfg_attr(not(any(target_os = "macos", target_os = "windows")), allow(dead_code))]
+    ProcessSuspended,
+}
+
+#[derive(Debug, Clone)]
+pub struct SuspendMarker {
+    pub kind: SuspendKind,
+    /// How the suspension was detected, e.g. the name of the ETW event.
+    pub reason: StringHandle,
+    /// The suspended time in milliseconds.
+    pub duration: f64,
+}
+
+impl StaticSchemaMarker for SuspendMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Suspended";
+
+    const CATEGORY: Category<'static> = Category("Idle", CategoryColor::Transparent);
+    const DESCRIPTION: Option<&'static str> =
+        Some("A time in which the machine was asleep or the process was suspended.");
+    const LOCATIONS: MarkerLocations = MarkerLocations::MARKER_CHART
+        .union(MarkerLocations::MARKER_TABLE)
+        .union(MarkerLocations::TIMELINE_OVERVIEW);
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.name} {marker.data.duration}");
+    const TOOLTIP_LABEL: Option<&'static str> =
+        Some("{marker.name} for {marker.data.duration} - {marker.data.reason}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.data.duration} - {marker.data.reason}");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "reason",
+            label: "Reason",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "duration",
+            label: "Duration",
+            format: MarkerFieldFormat::Milliseconds,
+            flags: MarkerFieldFlags::empty(),
+        },
+    ];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        match self.kind {
+            SuspendKind::SystemSleep => profile.handle_for_string("System sleep"),
+            SuspendKind::ProcessSuspended => profile.handle_for_string("Process suspended"),
+        }
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.reason
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.duration
+    }
+}
+
+/// Adds the "Power" track of a "System" pseudo-process, for sleep markers,
+/// which don't belong to any profiled process.
+pub fn add_power_track(profile: &mut Profile, start_time: Timestamp) -> ThreadHandle {
+    let process = profile.add_process("System", 0, start_time);
+    let thread = profile.add_thread(process, 0, start_time, true);
+    profile.set_thread_name(thread, "Power");
+    thread
+}
+
+/// Adds a marker for a suspension which the timestamps include, as a span
+/// from `start` to `end`.
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+pub fn add_suspend_span(
+    profile: &mut Profile,
+    thread: ThreadHandle,
+    start: Timestamp,
+    end: Timestamp,
+    kind: SuspendKind,
+    reason: &str,
+) {
+    let duration_nanos = end
+        .nanos_since_reference()
+        .saturating_sub(start.nanos_since_reference());
+    let marker = SuspendMarker {
+        kind,
+        reason: profile.handle_for_string(reason),
+        duration: duration_nanos as f64 / 1_000_000.0,
+    };
+    profile.add_marker(thread, MarkerTiming::Interval(start, end), marker);
+}
+
+/// Adds a marker for a sleep which the timestamps don't include, as an
+/// instant at the time at which the machine woke up.
+pub fn add_sleep_instant(
+    profile: &mut Profile,
+    thread: ThreadHandle,
+    wake_time: Timestamp,
+    slept: Duration,
+    reason: &str,
+) {
+    let marker = SuspendMarker {
+        kind: SuspendKind::SystemSleep,
+        reason: profile.handle_for_string(reason),
+        duration: slept.as_secs_f64() * 1000.0,
+    };
+    profile.add_marker(thread, MarkerTiming::Instant(wake_time), marker);
+}
+
+/// Detects that the machine slept by comparing a clock which stops during
+/// sleep (`mach_absolute_time`, `CLOCK_MONOTONIC`) with one which doesn't
+/// (`mach_continuous_time`, `CLOCK_BOOTTIME`). Both are in nanoseconds.
+#[derive(Debug, Clone)]
+pub struct SleepDetector {
+    last_awake_ns: u64,
+    last_total_ns: u64,
+}
+
+impl SleepDetector {
+    pub fn new(awake_ns: u64, total_ns: u64) -> Self {
+        Self {
+            last_awake_ns: awake_ns,
+            last_total_ns: total_ns,
+        }
+    }
+
+    /// Returns how long the machine slept since the previous call, if it
+    /// noticeably slept.
+    pub fn check(&mut self, awake_ns: u64, total_ns: u64) -> Option<Duration> {
+        let awake_delta = awake_ns.saturating_sub(self.last_awake_ns);
+        let total_delta = total_ns.saturating_sub(self.last_total_ns);
+        self.last_awake_ns = awake_ns;
+        self.last_total_ns = total_ns;
+        let slept = Duration::from_nanos(total_delta.saturating_sub(awake_delta));
+        (slept >= MIN_DETECTED_SLEEP).then_some(slept)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect_sleep() {
+        let ms = 1_000_000;
+        let mut detector = SleepDetector::new(1000 * ms, 5000 * ms);
+        assert_eq!(detector.check(1001 * ms, 5001 * ms), None);
+        assert_eq!(detector.check(1002 * ms, 5012 * ms), None);
+        assert_eq!(
+            detector.check(1003 * ms, 8013 * ms),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(detector.check(1004 * ms, 8014 * ms), None);
+    }
+}
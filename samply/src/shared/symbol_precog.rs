@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
@@ -17,6 +17,7 @@ use wholesym::{SourceFilePath, SymbolManager};
 use crate::symbols::create_symbol_manager_and_quota_manager;
 
 use super::prop_types::SymbolProps;
+use super::self_profile;
 
 #[derive(Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
 struct StringTableIndex(usize);
@@ -274,11 +275,19 @@ impl wholesym::samply_symbols::SymbolMapTrait for PrecogLibrarySymbols {
                 let sym_index = self.known_addresses[entry_index].1;
                 //eprintln!("lookup_sync: {:#x} -> {}", rva, sym_index);
                 let info = &self.symbol_table[sym_index];
+                let name = self.get_owned_string(info.symbol);
+                // The kind isn't stored in the sidecar file, so approximate it.
+                let fallback_kind = if info.frames.is_some() {
+                    wholesym::SymbolKind::Function
+                } else {
+                    wholesym::SymbolKind::Public
+                };
                 Some(wholesym::SyncAddressInfo {
                     symbol: wholesym::SymbolInfo {
                         address: info.rva,
                         size: info.size,
-                        name: self.get_owned_string(info.symbol),
+                        kind: wholesym::SymbolKind::from_name_or(&name, fallback_kind),
+                        name,
                     },
                     frames: info.frames.as_ref().map(|frames| {
                         wholesym::FramesLookupResult::Available(
@@ -336,14 +345,17 @@ pub fn presymbolicate(
     precog_output: &Path,
     symbol_props: SymbolProps,
 ) {
+    let _phase = self_profile::phase("Presymbolicate", None);
     let rt = tokio::runtime::Runtime::new().unwrap();
 
     let (mut results, string_table) = rt.block_on(async {
-        let (mut symbol_manager, quota_manager) =
-            create_symbol_manager_and_quota_manager(symbol_props, false);
-
+        // Libraries excluded with --no-symbolicate are left for the server, which
+        // looks up their symbols when the profiler asks for them.
         let lib_stuff: Vec<_> = profile
             .lib_used_rva_iter()
+            .filter(|(lib, _)| {
+                symbol_props.should_symbolicate(Some(&lib.name), Some(&lib.debug_name))
+            })
             .map(|(lib, rvas)| {
                 let lib_info = wholesym::LibraryInfo {
                     name: Some(lib.debug_name.clone()),
@@ -362,6 +374,8 @@ pub fn presymbolicate(
             })
             .collect();
 
+        let (mut symbol_manager, quota_manager) =
+            create_symbol_manager_and_quota_manager(symbol_props, false);
         for (lib_info, _) in &lib_stuff {
             // Add the library to the symbol manager with all the info, so that load_symbol_map can find it later
             symbol_manager.add_known_library(lib_info.clone());
@@ -405,9 +419,17 @@ pub fn presymbolicate(
         data: results,
     };
 
-    let file = File::create(precog_output).unwrap();
-    let writer = BufWriter::new(file);
-    to_writer(writer, &info).expect("Couldn't write JSON for presymbolication");
+    // Write to a temporary file first, because a server for the same profile
+    // may already be running and could read the sidecar file at any time.
+    let mut temp_output = precog_output.as_os_str().to_owned();
+    temp_output.push(".tmp");
+    let file = File::create(&temp_output).unwrap();
+    let mut writer = BufWriter::new(file);
+    to_writer(&mut writer, &info).expect("Couldn't write JSON for presymbolication");
+    writer
+        .flush()
+        .expect("Couldn't write JSON for presymbolication");
+    std::fs::rename(&temp_output, precog_output).expect("Couldn't rename presymbolication file");
 }
 
 async fn get_lib_symbols(
@@ -417,6 +439,7 @@ async fn get_lib_symbols(
     string_table: Arc<Mutex<StringTable>>,
 ) -> Option<PrecogLibrarySymbols> {
     //eprintln!("Library {} ({}) has {} rvas", lib.debug_name, lib.debug_id, rvas.len());
+    let _phase = self_profile::phase("Symbolicate library", lib.debug_name.as_deref());
     let Ok(symbol_map) = symbol_manager
         .load_symbol_map(lib.debug_name.as_deref().unwrap(), lib.debug_id.unwrap())
         .await
@@ -0,0 +1,152 @@
+//! Recognizes the machine-readable output of test runners, so that each test
+//! of a launched test run can be shown as an interval marker.
+//!
+//! Two formats are understood, both with one JSON object per line:
+//!
+//!  - libtest's JSON output, from `cargo test -- -Z unstable-options --format json`,
+//!    which has a "started" event and a result event for each test.
+//!  - pytest's report log, from `pytest --report-log=/dev/stdout` with the
+//!    pytest-reportlog plugin, which has a report with the duration of each
+//!    phase of each test.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+/// A finished test. The times are in the clock of the timestamps which were
+/// passed to [`TestOutputParser::push_line`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestRun {
+    pub name: String,
+    /// E.g. "ok", "failed" or "ignored" for libtest, and "passed", "failed"
+    /// or "skipped" for pytest.
+    pub outcome: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct TestOutputParser {
+    /// The start time of each libtest test which hasn't finished yet.
+    started: HashMap<String, u64>,
+}
+
+impl TestOutputParser {
+    /// Returns the test which finished with this line. `timestamp` is the time
+    /// at which the line was printed, in nanoseconds.
+    pub fn push_line(&mut self, line: &str, timestamp: u64) -> Option<TestRun> {
+        let line = line.trim();
+        if !line.starts_with('{') {
+            return None;
+        }
+        let event: Value = serde_json::from_str(line).ok()?;
+        if event["$report_type"] == "TestReport" {
+            return Self::pytest_report(&event, timestamp);
+        }
+        if event["type"] != "test" {
+            return None;
+        }
+        let name = event["name"].as_str()?;
+        match event["event"].as_str()? {
+            "started" => {
+                self.started.insert(name.to_string(), timestamp);
+                None
+            }
+            outcome => {
+                // exec_time is only there with --report-time.
+                let start = match self.started.remove(name) {
+                    Some(start) => start,
+                    None => {
+                        timestamp.saturating_sub(seconds_to_nanos(event["exec_time"].as_f64()?))
+                    }
+                };
+                Some(TestRun {
+                    name: name.to_string(),
+                    outcome: outcome.to_string(),
+                    start,
+                    end: timestamp,
+                })
+            }
+        }
+    }
+
+    /// Reports are written at the end of each phase of a test. Only the "call"
+    /// phase, which runs the test function, becomes a test run; its wall-clock
+    /// start and stop times are replaced by the time at which the report was
+    /// printed, minus the duration.
+    fn pytest_report(report: &Value, timestamp: u64) -> Option<TestRun> {
+        if report["when"] != "call" {
+            return None;
+        }
+        let duration = seconds_to_nanos(report["duration"].as_f64()?);
+        Some(TestRun {
+            name: report["nodeid"].as_str()?.to_string(),
+            outcome: report["outcome"].as_str().unwrap_or("unknown").to_string(),
+            start: timestamp.saturating_sub(duration),
+            end: timestamp,
+        })
+    }
+}
+
+fn seconds_to_nanos(seconds: f64) -> u64 {
+    (seconds * 1_000_000_000.0) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn libtest_and_pytest() {
+        let mut parser = TestOutputParser::default();
+        let lines = [
+            (
+                r#"{ "type": "suite", "event": "started", "test_count": 2 }"#,
+                10,
+            ),
+            (r#"{ "type": "test", "event": "started", "name": "a" }"#, 20),
+            (r#"{ "type": "test", "event": "started", "name": "b" }"#, 30),
+            ("some output from test b", 35),
+        ];
+        for (line, timestamp) in lines {
+            assert_eq!(parser.push_line(line, timestamp), None);
+        }
+        assert_eq!(
+            parser
+                .push_line(r#"{ "type": "test", "name": "b", "event": "ok" }"#, 40)
+                .map(|test| (test.start, test.end)),
+            Some((30, 40))
+        );
+        assert_eq!(
+            parser.push_line(r#"{ "type": "test", "name": "a", "event": "failed" }"#, 50),
+            Some(TestRun {
+                name: "a".to_string(),
+                outcome: "failed".to_string(),
+                start: 20,
+                end: 50,
+            })
+        );
+        assert_eq!(
+            parser
+                .push_line(
+                    r#"{ "type": "test", "name": "c", "event": "ok", "exec_time": 0.5 }"#,
+                    2_000_000_000
+                )
+                .map(|test| test.start),
+            Some(1_500_000_000)
+        );
+
+        let setup = r#"{"$report_type": "TestReport", "nodeid": "test_x.py::test_y", "when": "setup", "outcome": "passed", "duration": 0.1}"#;
+        assert_eq!(parser.push_line(setup, 1_000_000_000), None);
+        let call = r#"{"$report_type": "TestReport", "nodeid": "test_x.py::test_y", "when": "call", "outcome": "passed", "duration": 0.25}"#;
+        assert_eq!(
+            parser.push_line(call, 1_000_000_000),
+            Some(TestRun {
+                name: "test_x.py::test_y".to_string(),
+                outcome: "passed".to_string(),
+                start: 750_000_000,
+                end: 1_000_000_000,
+            })
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Per-thread sampling rates, for `--rate-override`.
+//!
+//! The sampler wakes up at the highest of the configured rates (a "tick") and
+//! samples each thread only on every n-th tick, where n depends on the thread's
+//! rate. Each sample gets a weight of n, so that the weights of all threads are
+//! in units of the tick interval and the call tree stays comparable across
+//! threads with different rates.
+
+use std::time::Duration;
+
+use fxprof_processed_profile::Profile;
+
+use super::name_pattern::matches_pattern;
+
+#[derive(Debug, Clone)]
+pub struct ThreadRates {
+    base_rate: f64,
+    /// Thread name patterns and their sampling rates in Hz. The first matching
+    /// pattern wins.
+    overrides: Vec<(String, f64)>,
+    tick_rate: f64,
+}
+
+impl ThreadRates {
+    pub fn new(base_interval: Duration, overrides: &[(String, f64)]) -> Self {
+        let base_rate = 1.0 / base_interval.as_secs_f64();
+        let tick_rate = overrides
+            .iter()
+            .map(|(_, rate)| *rate)
+            .fold(base_rate, f64::max);
+        Self {
+            base_rate,
+            overrides: overrides.to_vec(),
+            tick_rate,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.overrides.is_empty()
+    }
+
+    /// The time between two ticks of the sampler.
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.tick_rate)
+    }
+
+    /// How many ticks pass between two samples of the thread. This is also the
+    /// weight of each of the thread's samples.
+    pub fn ticks_per_sample(&self, thread_name: Option<&str>) -> u32 {
+        if !self.is_enabled() {
+            return 1;
+        }
+        let rate = thread_name
+            .and_then(|name| {
+                self.overrides
+                    .iter()
+                    .find(|(pattern, _)| matches_pattern(pattern, name))
+            })
+            .map_or(self.base_rate, |(_, rate)| *rate);
+        ((self.tick_rate / rate).round() as u32).max(1)
+    }
+
+    /// Whether the thread should be sampled on this tick.
+    pub fn should_sample(&self, thread_name: Option<&str>, tick: u64) -> bool {
+        tick % u64::from(self.ticks_per_sample(thread_name)) == 0
+    }
+
+    /// Records the overrides in the profile's metadata.
+    pub fn add_to_profile_meta(&self, profile: &mut Profile) {
+        for (pattern, rate) in &self.overrides {
+            profile.add_extra_info(
+                "Sampling",
+                &format!("Rate for {pattern}"),
+                &format!("{rate}Hz"),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn weights_are_in_tick_units() {
+        let overrides = vec![
+            ("RenderThread".to_string(), 4000.0),
+            ("Audio*".to_string(), 8000.0),
+        ];
+        let rates = ThreadRates::new(Duration::from_millis(1), &overrides);
+        assert!((rates.tick_interval().as_secs_f64() - 0.000125).abs() < 1e-9);
+        assert_eq!(rates.ticks_per_sample(Some("AudioIPC")), 1);
+        assert_eq!(rates.ticks_per_sample(Some("RenderThread")), 2);
+        assert_eq!(rates.ticks_per_sample(Some("main")), 8);
+        assert_eq!(rates.ticks_per_sample(None), 8);
+        assert!(rates.should_sample(Some("main"), 16));
+        assert!(!rates.should_sample(Some("main"), 17));
+    }
+}
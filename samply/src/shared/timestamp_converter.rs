@@ -1,4 +1,6 @@
-use fxprof_processed_profile::{CpuDelta, Timestamp};
+use std::time::Duration;
+
+use fxprof_processed_profile::{CpuDelta, ReferenceTimestamp, Timestamp};
 
 #[derive(Debug, Clone, Copy)]
 pub struct TimestampConverter {
@@ -6,12 +8,51 @@ pub struct TimestampConverter {
     pub reference_raw: u64,
     /// A "ticks per nanosecond" conversion factor. If raw values are in nanoseconds, this is 1.
     pub raw_to_ns_factor: u64,
+    /// Added to all converted timestamps. If the profile's timestamps are UTC
+    /// wall-clock times, this is the time of `reference_raw` in nanoseconds since
+    /// the Unix epoch, and otherwise it's zero.
+    pub offset_ns: u64,
 }
 
 impl TimestampConverter {
+    /// Creates a converter for raw nanosecond timestamps, and the profile's
+    /// reference timestamp. `reference_time` is the wall-clock time of
+    /// `reference_raw`, as a duration since the Unix epoch.
+    ///
+    /// By default, the profile's timestamps are relative to `reference_raw`, and
+    /// `reference_time` is the profile's reference timestamp. With
+    /// `utc_timestamps`, the reference timestamp is the Unix epoch, so that the
+    /// profile's timestamps are UTC times which can be compared with the
+    /// timestamps in logs and in profiles from other machines.
+    pub fn with_reference_time(
+        reference_raw: u64,
+        reference_time: Duration,
+        utc_timestamps: bool,
+    ) -> (Self, ReferenceTimestamp) {
+        if utc_timestamps {
+            let converter = Self {
+                reference_raw,
+                raw_to_ns_factor: 1,
+                offset_ns: reference_time.as_nanos() as u64,
+            };
+            let unix_epoch = ReferenceTimestamp::from_millis_since_unix_epoch(0.0);
+            (converter, unix_epoch)
+        } else {
+            let converter = Self {
+                reference_raw,
+                raw_to_ns_factor: 1,
+                offset_ns: 0,
+            };
+            let reference_timestamp =
+                ReferenceTimestamp::from_duration_since_unix_epoch(reference_time);
+            (converter, reference_timestamp)
+        }
+    }
+
     pub fn convert_time(&self, timestamp_raw: u64) -> Timestamp {
         Timestamp::from_nanos_since_reference(
-            timestamp_raw.saturating_sub(self.reference_raw) * self.raw_to_ns_factor,
+            timestamp_raw.saturating_sub(self.reference_raw) * self.raw_to_ns_factor
+                + self.offset_ns,
         )
     }
 
@@ -23,7 +64,37 @@ impl TimestampConverter {
     #[allow(unused)]
     pub fn convert_us(&self, time_us: u64) -> Timestamp {
         Timestamp::from_nanos_since_reference(
-            (time_us * 1000).saturating_sub(self.reference_raw * self.raw_to_ns_factor),
+            (time_us * 1000).saturating_sub(self.reference_raw * self.raw_to_ns_factor)
+                + self.offset_ns,
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn utc_timestamps() {
+        let reference_time = Duration::from_nanos(1_700_000_000_123_456_789);
+
+        let (converter, reference_timestamp) =
+            TimestampConverter::with_reference_time(5_000, reference_time, false);
+        assert_eq!(
+            reference_timestamp,
+            ReferenceTimestamp::from_duration_since_unix_epoch(reference_time)
+        );
+        assert_eq!(converter.convert_time(7_000).nanos_since_reference(), 2_000);
+
+        let (converter, reference_timestamp) =
+            TimestampConverter::with_reference_time(5_000, reference_time, true);
+        assert_eq!(
+            reference_timestamp,
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0)
+        );
+        assert_eq!(
+            converter.convert_time(7_000).nanos_since_reference(),
+            1_700_000_000_123_458_789
+        );
+    }
+}
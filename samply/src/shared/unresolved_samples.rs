@@ -25,6 +25,24 @@ impl UnresolvedSamples {
         self.samples_and_markers.is_empty()
     }
 
+    /// Drops the samples which were taken before `timestamp_mono`, for
+    /// `--ring-buffer-seconds`. The stacks of markers are kept, because the
+    /// markers themselves are already in the profile.
+    pub fn discard_samples_before(&mut self, timestamp_mono: u64) {
+        let len_before = self.samples_and_markers.len();
+        self.samples_and_markers.retain(|sample| {
+            sample.timestamp_mono >= timestamp_mono
+                || matches!(sample.sample_or_marker, SampleOrMarker::MarkerHandle(_))
+        });
+        if self.samples_and_markers.len() != len_before {
+            // The sample indexes have changed. Zero-CPU samples after this
+            // start a new sample instead of adding their weight to an old one.
+            for sample_info in self.prev_sample_info_per_thread.values_mut() {
+                sample_info.prev_sample_index_if_zero_cpu = None;
+            }
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn add_sample(
         &mut self,
@@ -220,3 +238,42 @@ impl UnresolvedStacks {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use fxprof_processed_profile::{Profile, ReferenceTimestamp, SamplingInterval};
+
+    use super::*;
+
+    #[test]
+    fn discard_old_samples() {
+        let mut profile = Profile::new(
+            "test",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.0),
+            SamplingInterval::from_millis(1),
+        );
+        let start = Timestamp::from_millis_since_reference(0.0);
+        let process = profile.add_process("app", 1, start);
+        let thread = profile.add_thread(process, 1, start, true);
+        let t = |ms: u64| Timestamp::from_millis_since_reference(ms as f64);
+
+        let mut samples = UnresolvedSamples::default();
+        let stack = UnresolvedStackHandle::EMPTY;
+        for ms in 1..=3 {
+            let cpu_delta = CpuDelta::from_nanos(1_000_000);
+            samples.add_sample(thread, t(ms), ms * 1_000_000, stack, cpu_delta, 1, None);
+        }
+        samples.add_sample_same_stack_zero_cpu(thread, t(4), 4_000_000, 1, None);
+
+        samples.discard_samples_before(3_000_000);
+        // Not merged into the zero-CPU sample at 4ms, whose index has changed.
+        samples.add_sample_same_stack_zero_cpu(thread, t(5), 5_000_000, 1, None);
+
+        let times: Vec<u64> = samples
+            .into_inner()
+            .iter()
+            .map(|sample| sample.timestamp_mono / 1_000_000)
+            .collect();
+        assert_eq!(times, vec![3, 4, 5]);
+    }
+}
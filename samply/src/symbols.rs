@@ -93,6 +93,38 @@ fn create_symbol_manager_config_and_quota_manager(
         config = config.extra_symbol_directory(dir);
     }
 
+    for archive in symbol_props.symbol_archives {
+        config = config.symbol_archive(archive);
+    }
+    if let Some(symbols_dir) = symbols_dir {
+        config = config.symbol_archive_extraction_dir(symbols_dir.join("archives"));
+    }
+
+    for (from_prefix, to_dir) in symbol_props.path_remappings {
+        config = config.path_remapping(from_prefix, to_dir);
+    }
+    for (path_prefix, repository) in symbol_props.source_repositories {
+        config = config.source_repository(path_prefix, repository);
+    }
+    if let Some(symbols_dir) = symbols_dir {
+        config = config.source_repository_cache_dir(symbols_dir.join("source-repos"));
+    }
+
+    if let Some(proxy) = symbol_props.download_proxy {
+        config = config.download_proxy(proxy);
+    }
+    if let Some(timeout) = symbol_props.download_timeout {
+        config = config
+            .download_connect_timeout(timeout)
+            .download_read_timeout(timeout);
+    }
+    if let Some(retries) = symbol_props.download_retries {
+        config = config.download_retries(retries);
+    }
+    if let Some(max) = symbol_props.max_concurrent_downloads {
+        config = config.max_concurrent_downloads(max);
+    }
+
     (config, quota_manager)
 }
 
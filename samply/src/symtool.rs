@@ -0,0 +1,192 @@
+//! `samply symtool`, for producing symbol files with samply's parsers.
+//!
+//! `export-breakpad` writes a Breakpad .sym file in the directory layout which
+//! Mozilla's symbol server (Tecken) expects in uploaded zip files:
+//! `<debug name>/<breakpad ID>/<debug name without .pdb>.sym`. The same layout
+//! works for `--breakpad-symbol-dir` and any server which serves Breakpad
+//! symbols.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use wholesym::{CodeId, LibraryInfo, LookupAddress, SymbolInfo, SymbolKind, SymbolManager};
+
+use crate::shared::prop_types::SymbolProps;
+use crate::symbols::create_symbol_manager_and_quota_manager;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SymtoolError {
+    #[error("Could not load symbols: {0}")]
+    Symbols(#[from] wholesym::Error),
+
+    #[error("The binary has no debug name")]
+    NoDebugName,
+
+    #[error("The binary has no debug ID")]
+    NoDebugId,
+
+    #[error("Could not write the symbol file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Writes a Breakpad symbol file for the binary at `binary_path` into
+/// `output_dir`, and returns the path of the written file.
+///
+/// The file contains a `FUNC` record for each function whose size is known,
+/// and a `PUBLIC` record for every other symbol. It has no line records.
+pub fn export_breakpad(
+    binary_path: &Path,
+    output_dir: &Path,
+    symbol_props: SymbolProps,
+) -> Result<PathBuf, SymtoolError> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let info = SymbolManager::library_info_for_binary_at_path(binary_path, None).await?;
+        let debug_name = info.debug_name.clone().ok_or(SymtoolError::NoDebugName)?;
+        let debug_id = info.debug_id.ok_or(SymtoolError::NoDebugId)?;
+
+        let (mut symbol_manager, _quota_manager) =
+            create_symbol_manager_and_quota_manager(symbol_props, false);
+        symbol_manager.add_known_library(info.clone());
+        let symbol_map = symbol_manager
+            .load_symbol_map(&debug_name, debug_id)
+            .await?;
+
+        let mut symbols: Vec<SymbolInfo> = symbol_map
+            .iter_symbols()
+            .filter_map(|(address, _name)| {
+                Some(
+                    symbol_map
+                        .lookup_sync(LookupAddress::Relative(address))?
+                        .symbol,
+                )
+            })
+            .collect();
+        symbols.sort_by_key(|symbol| symbol.address);
+        symbols.dedup_by_key(|symbol| symbol.address);
+
+        let path = output_dir.join(sym_file_path(&debug_name, &debug_id.breakpad().to_string()));
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, sym_file_contents(&info, &debug_name, &symbols))?;
+        Ok(path)
+    })
+}
+
+/// The path of the symbol file relative to the root of the upload, e.g.
+/// `xul.pdb/<ID>/xul.sym` or `libxul.so/<ID>/libxul.so.sym`.
+fn sym_file_path(debug_name: &str, breakpad_id: &str) -> PathBuf {
+    let sym_name = match debug_name.len().checked_sub(4) {
+        Some(stem_len) if debug_name[stem_len..].eq_ignore_ascii_case(".pdb") => {
+            format!("{}.sym", &debug_name[..stem_len])
+        }
+        _ => format!("{debug_name}.sym"),
+    };
+    [debug_name, breakpad_id, &sym_name].iter().collect()
+}
+
+/// The OS name in the `MODULE` record, as written by Breakpad's dump_syms.
+fn breakpad_os(info: &LibraryInfo, debug_name: &str) -> &'static str {
+    match &info.code_id {
+        Some(CodeId::PeCodeId(_)) => "windows",
+        Some(CodeId::MachoUuid(_)) => "mac",
+        Some(CodeId::ElfBuildId(_)) => "Linux",
+        None if debug_name.to_ascii_lowercase().ends_with(".pdb") => "windows",
+        None => "Linux",
+    }
+}
+
+fn sym_file_contents(info: &LibraryInfo, debug_name: &str, symbols: &[SymbolInfo]) -> String {
+    let mut s = String::new();
+    let debug_id = info.debug_id.unwrap_or_default();
+    let _ = writeln!(
+        s,
+        "MODULE {} {} {} {}",
+        breakpad_os(info, debug_name),
+        info.arch.as_deref().unwrap_or("unknown"),
+        debug_id.breakpad(),
+        debug_name
+    );
+    match (&info.code_id, &info.name) {
+        (Some(code_id @ CodeId::PeCodeId(_)), Some(name)) => {
+            let _ = writeln!(s, "INFO CODE_ID {code_id} {name}");
+        }
+        (Some(code_id), _) => {
+            let _ = writeln!(s, "INFO CODE_ID {}", code_id.to_string().to_uppercase());
+        }
+        (None, _) => {}
+    }
+    for symbol in symbols {
+        // Names can't span lines in the .sym format.
+        let name = symbol.name.replace(['\r', '\n'], " ");
+        match (symbol.kind, symbol.size) {
+            (SymbolKind::Function | SymbolKind::Thunk, Some(size)) if size > 0 => {
+                let _ = writeln!(s, "FUNC {:x} {size:x} 0 {name}", symbol.address);
+            }
+            _ => {
+                let _ = writeln!(s, "PUBLIC {:x} 0 {name}", symbol.address);
+            }
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use wholesym::debugid::DebugId;
+
+    use super::*;
+
+    #[test]
+    fn upload_layout() {
+        assert_eq!(
+            sym_file_path("xul.pdb", "ABC1"),
+            Path::new("xul.pdb").join("ABC1").join("xul.sym")
+        );
+        assert_eq!(
+            sym_file_path("Foo.PDB", "ABC1"),
+            Path::new("Foo.PDB").join("ABC1").join("Foo.sym")
+        );
+        assert_eq!(
+            sym_file_path("libxul.so", "ABC1"),
+            Path::new("libxul.so").join("ABC1").join("libxul.so.sym")
+        );
+    }
+
+    #[test]
+    fn sym_file_records() {
+        let info = LibraryInfo {
+            debug_name: Some("app.pdb".to_string()),
+            debug_id: Some(DebugId::from_breakpad("5C1C1F3A0D2B4D3CA2B7E4F5A6B7C8D91").unwrap()),
+            name: Some("app.exe".to_string()),
+            code_id: Some(CodeId::from_str("5F3E2A1B9000").unwrap()),
+            arch: Some("x86_64".to_string()),
+            ..Default::default()
+        };
+        let symbols = vec![
+            SymbolInfo {
+                address: 0x1000,
+                size: Some(0x20),
+                name: "main".to_string(),
+                kind: SymbolKind::Function,
+            },
+            SymbolInfo {
+                address: 0x1020,
+                size: None,
+                name: "exported_thing".to_string(),
+                kind: SymbolKind::Public,
+            },
+        ];
+        assert_eq!(
+            sym_file_contents(&info, "app.pdb", &symbols),
+            "MODULE windows x86_64 5C1C1F3A0D2B4D3CA2B7E4F5A6B7C8D91 app.pdb\n\
+             INFO CODE_ID 5F3E2A1B9000 app.exe\n\
+             FUNC 1000 20 0 main\n\
+             PUBLIC 1020 0 exported_thing\n"
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use super::elevated_helper::ElevatedRecordingProps;
+
+pub fn audio_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
+    if !props.audio {
+        return vec![];
+    }
+
+    // The audio engine (audiodg.exe) reports glitches and buffer underruns
+    // through this provider.
+    vec!["Microsoft-Windows-Audio:0xffffffffffffffff:4".to_string()]
+}
+
+/// Returns whether an event from the Microsoft-Windows-Audio provider reports
+/// a glitch, i.e. audio which didn't reach the device in time.
+pub fn is_audio_glitch_event(name: &str) -> bool {
+    let Some(task_and_op) = name.strip_prefix("Microsoft-Windows-Audio/") else {
+        return false;
+    };
+    let task_and_op = task_and_op.to_ascii_lowercase();
+    ["glitch", "underrun", "starv"]
+        .iter()
+        .any(|pattern| task_and_op.contains(pattern))
+}
@@ -11,6 +11,7 @@ use super::elevated_helper::ElevatedRecordingProps;
 use super::etw_reader::event_properties_to_string;
 use super::etw_reader::parser::{Parser, TryParse};
 use super::etw_reader::schema::TypedEvent;
+use crate::shared::process_sample_data::ExceptionMarker;
 use crate::shared::prop_types::{CoreClrProfileProps, ProfileCreationProps};
 use crate::windows::profile_context::ProfileContext;
 
@@ -319,7 +320,7 @@ pub fn coreclr_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
     // which is only useful if we're tracing an already running process.
     // if STACK is enabled, then every CoreCLR event will also generate a stack event right afterwards
     use constants::*;
-    let mut info_keywords = CORECLR_LOADER_KEYWORD;
+    let mut info_keywords = CORECLR_LOADER_KEYWORD | CORECLR_EXCEPTION_KEYWORD;
     if props.coreclr.event_stacks {
         info_keywords |= CORECLR_STACK_KEYWORD;
     }
@@ -532,7 +533,7 @@ pub fn handle_coreclr_event(
                 .chain(parser.buffer.chunks_exact(8))
                 .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()));
 
-            context.handle_coreclr_stack(timestamp_raw, tid, address_iter, marker);
+            context.handle_marker_stack(timestamp_raw, tid, address_iter, marker);
             handled = true;
         }
         ("GarbageCollection", gc_event) => {
@@ -704,6 +705,28 @@ pub fn handle_coreclr_event(
                 }
             }
         }
+        ("Exception", "win:Start") => {
+            if !is_in_time_range {
+                return;
+            }
+
+            // ExceptionThrown_V1; it gets a CLRStackWalk if event_stacks is set.
+            let exception_type: String = parser.parse("ExceptionType");
+            let exception_message: String = parser.parse("ExceptionMessage");
+            let message = if exception_message.is_empty() {
+                exception_type
+            } else {
+                format!("{exception_type}: {exception_message}")
+            };
+            let marker = ExceptionMarker {
+                name: context.handle_for_profile_string("Exception"),
+                message: context.handle_for_profile_string(&message),
+                location: context.handle_for_profile_string(""),
+            };
+            let mh = context.add_thread_instant_marker(timestamp_raw, tid, marker);
+            coreclr_context.set_last_event_for_thread(tid, mh);
+            handled = true;
+        }
         ("CLRRuntimeInformation", _) => {
             handled = true;
         }
@@ -0,0 +1,21 @@
+use super::elevated_helper::ElevatedRecordingProps;
+
+/// Returns the xperf provider strings for the user-specified providers whose
+/// events should come with stacks, and for the provider of the `--frame-marker` event.
+///
+/// The `'stack'` suffix makes ETW capture a stack walk for every event from the
+/// provider. The stack is delivered as an extended data item on the event itself,
+/// and we attach it to the marker we create for the event.
+pub fn custom_provider_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
+    props
+        .providers_with_stacks
+        .iter()
+        .map(|provider| format!("{provider}:0xffffffffffffffff:5:'stack'"))
+        .chain(
+            props
+                .frame_marker_provider
+                .iter()
+                .map(|provider| format!("{provider}:0xffffffffffffffff:5")),
+        )
+        .collect()
+}
@@ -8,7 +8,7 @@ use super::utility_process::{
 };
 use super::xperf::Xperf;
 use crate::shared::prop_types::{
-    CoreClrProfileProps, ProfileCreationProps, RecordingMode, RecordingProps,
+    CoreClrProfileProps, EtwPreset, ProfileCreationProps, RecordingMode, RecordingProps,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,7 +27,16 @@ pub struct ElevatedRecordingProps {
     pub vm_hack: bool,
     pub is_attach: bool,
     pub gfx: bool,
+    pub audio: bool,
+    pub network: bool,
+    pub registry: bool,
+    pub rpc: bool,
+    pub sched_latency: bool,
     pub browsers: bool,
+    pub providers_with_stacks: Vec<String>,
+    pub frame_marker_provider: Option<String>,
+    pub rundown_interval_seconds: Option<f64>,
+    pub etw_preset: EtwPreset,
 }
 
 impl ElevatedRecordingProps {
@@ -43,7 +52,22 @@ impl ElevatedRecordingProps {
             vm_hack: recording_props.vm_hack,
             is_attach: recording_mode.is_attach_mode(),
             gfx: recording_props.gfx,
+            audio: recording_props.audio,
+            network: recording_props.network,
+            registry: recording_props.registry,
+            rpc: recording_props.rpc,
+            sched_latency: profile_creation_props.sched_latency_threshold_ms.is_some(),
             browsers: recording_props.browsers,
+            providers_with_stacks: recording_props.providers_with_stacks.clone(),
+            frame_marker_provider: profile_creation_props
+                .frame_marker
+                .as_deref()
+                .and_then(|frame_marker| frame_marker.split('/').next())
+                .map(ToOwned::to_owned),
+            rundown_interval_seconds: recording_props
+                .rundown_interval
+                .map(|interval| interval.as_secs_f64()),
+            etw_preset: recording_props.etw_preset,
         }
     }
 }
@@ -56,6 +80,7 @@ enum ElevatedHelperReplyMsg {
     AckStopXperf {
         kernel_output_file: PathBuf,
         user_output_file: Option<PathBuf>,
+        rundown_output_files: Vec<PathBuf>,
     },
     AckGetKernelModules,
 }
@@ -107,7 +132,7 @@ impl ElevatedHelperSession {
 
     pub fn stop_xperf(
         &mut self,
-    ) -> Result<(PathBuf, Option<PathBuf>), Box<dyn Error + Send + Sync>> {
+    ) -> Result<(PathBuf, Option<PathBuf>, Vec<PathBuf>), Box<dyn Error + Send + Sync>> {
         let reply = self
             .elevated_session
             .send_msg_and_wait_for_response(ElevatedHelperRequestMsg::StopXperf)?;
@@ -115,7 +140,8 @@ impl ElevatedHelperSession {
             ElevatedHelperReplyMsg::AckStopXperf {
                 kernel_output_file,
                 user_output_file,
-            } => Ok((kernel_output_file, user_output_file)),
+                rundown_output_files,
+            } => Ok((kernel_output_file, user_output_file, rundown_output_files)),
             other_msg => Err(format!("Unexpected reply to StartXperf msg: {other_msg:?}").into()),
         }
     }
@@ -211,10 +237,12 @@ impl UtilityProcessChild<ElevatedHelperRequestMsg, ElevatedHelperReplyMsg> for E
                 Ok(ElevatedHelperReplyMsg::AckStartXperf)
             }
             ElevatedHelperRequestMsg::StopXperf => {
-                let (kernel_output_file, user_output_file) = self.xperf.stop_xperf()?;
+                let (kernel_output_file, user_output_file, rundown_output_files) =
+                    self.xperf.stop_xperf()?;
                 Ok(ElevatedHelperReplyMsg::AckStopXperf {
                     kernel_output_file,
                     user_output_file,
+                    rundown_output_files,
                 })
             }
             ElevatedHelperRequestMsg::GetKernelModules => Err("todo".into()),
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::convert::TryInto;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
@@ -6,13 +7,24 @@ use debugid::DebugId;
 use fxprof_processed_profile::debugid;
 use uuid::Uuid;
 
+use super::audio::is_audio_glitch_event;
 use super::coreclr::CoreClrContext;
 use super::etw_reader::parser::{Address, Parser, TryParse};
 use super::etw_reader::schema::SchemaLocator;
+use super::etw_reader::schema_bundle::SchemaBundle;
 use super::etw_reader::{
     add_custom_schemas, event_properties_to_string, open_trace, print_property, GUID,
 };
+use super::network::{
+    is_http_failure, is_http_request_end, network_provider, NetworkProvider,
+    HTTP_REQUEST_HANDLE_PROPERTIES, HTTP_URL_PROPERTIES,
+};
+use super::power::{process_freeze_edge, system_sleep_edge};
 use super::profile_context::ProfileContext;
+use super::registry::{registry_event, RegistryEvent};
+use super::rpc::{
+    rpc_call_event, CallDetails, CallEdge, CALL_INTERFACE_PROPERTIES, CALL_METHOD_PROPERTIES,
+};
 use crate::windows::coreclr;
 use crate::windows::profile_context::PeInfo;
 
@@ -20,8 +32,20 @@ pub fn process_etl_files(
     context: &mut ProfileContext,
     etl_file: &Path,
     extra_etl_filenames: &[PathBuf],
+    schema_bundles: &[PathBuf],
+    export_schemas_to: Option<&Path>,
 ) {
     let mut schema_locator = SchemaLocator::new();
+    for path in schema_bundles {
+        match SchemaBundle::load(path) {
+            Ok(bundle) => schema_locator.add_schema_bundle(bundle),
+            Err(e) => {
+                eprintln!("Could not load ETW schemas from {path:?}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    // The built-in schemas take precedence over the ones from the bundles.
     add_custom_schemas(&mut schema_locator);
 
     let processing_start_timestamp = Instant::now();
@@ -58,6 +82,14 @@ pub fn process_etl_files(
         "Took {} seconds",
         (Instant::now() - processing_start_timestamp).as_secs_f32()
     );
+
+    if let Some(path) = export_schemas_to {
+        let bundle = schema_locator.export_schemas();
+        match bundle.save(path) {
+            Ok(()) => eprintln!("Saved {} ETW schemas to {path:?}.", bundle.schemas.len()),
+            Err(e) => eprintln!("Could not save ETW schemas to {path:?}: {e}"),
+        }
+    }
 }
 
 struct PendingImageInfo {
@@ -83,6 +115,14 @@ fn process_trace(
         let mut parser = Parser::create(&s);
         let timestamp_raw = e.EventHeader.TimeStamp as u64;
 
+        if context.is_frame_marker_event(s.name()) {
+            context.handle_frame_marker_event(
+                timestamp_raw,
+                e.EventHeader.ProcessId,
+                e.EventHeader.ThreadId,
+            );
+        }
+
         //eprintln!("{}", s.name());
         match s.name() {
             "MSNT_SystemTrace/EventTrace/Header" => {
@@ -93,8 +133,9 @@ fn process_trace(
                 if events_lost != 0 {
                     log::warn!("{} events lost", events_lost);
                 }
+                let pointer_size: Option<u32> = parser.try_parse("PointerSize").ok();
 
-                context.handle_header(timestamp_raw, perf_freq, clock_type);
+                context.handle_header(timestamp_raw, perf_freq, clock_type, pointer_size);
 
                 if log::log_enabled!(log::Level::Info) {
                     for i in 0..s.property_count() {
@@ -117,13 +158,26 @@ fn process_trace(
                 let tid: u32 = parser.parse("TThreadId");
                 let pid: u32 = parser.parse("ProcessId");
                 let thread_name: Option<String> = parser.try_parse("ThreadName").ok();
-                context.handle_thread_dcstart(timestamp_raw, tid, pid, thread_name)
+                let sub_process_tag: u32 = parser.try_parse("SubProcessTag").unwrap_or(0);
+                context.handle_thread_dcstart(timestamp_raw, tid, pid, thread_name, sub_process_tag)
             }
             "MSNT_SystemTrace/Thread/Start" => {
                 let tid: u32 = parser.parse("TThreadId");
                 let pid: u32 = parser.parse("ProcessId");
                 let thread_name: Option<String> = parser.try_parse("ThreadName").ok();
-                context.handle_thread_start(timestamp_raw, tid, pid, thread_name);
+                // The event is logged by the creating thread.
+                let creator_tid = e.EventHeader.ThreadId;
+                // The service which the thread was started for, in a process
+                // which hosts services, or 0.
+                let sub_process_tag: u32 = parser.try_parse("SubProcessTag").unwrap_or(0);
+                context.handle_thread_start(
+                    timestamp_raw,
+                    tid,
+                    pid,
+                    creator_tid,
+                    thread_name,
+                    sub_process_tag,
+                );
             }
             "MSNT_SystemTrace/Thread/End" => {
                 let tid: u32 = parser.parse("TThreadId");
@@ -142,12 +196,14 @@ fn process_trace(
                 let parent_pid: u32 = parser.parse("ParentId");
                 let image_file_name: String = parser.parse("ImageFileName");
                 let cmdline: String = parser.parse("CommandLine");
+                let flags: u32 = parser.try_parse("Flags").unwrap_or(0);
                 context.handle_process_dcstart(
                     timestamp_raw,
                     pid,
                     parent_pid,
                     image_file_name,
                     cmdline,
+                    flags,
                 );
             }
             "MSNT_SystemTrace/Process/Start" => {
@@ -158,12 +214,14 @@ fn process_trace(
                 let parent_pid: u32 = parser.parse("ParentId");
                 let image_file_name: String = parser.parse("ImageFileName");
                 let cmdline: String = parser.parse("CommandLine");
+                let flags: u32 = parser.try_parse("Flags").unwrap_or(0);
                 context.handle_process_start(
                     timestamp_raw,
                     pid,
                     parent_pid,
                     image_file_name,
                     cmdline,
+                    flags,
                 );
             }
             "MSNT_SystemTrace/Process/End" => {
@@ -229,6 +287,53 @@ fn process_trace(
                 let cpu = u32::from(unsafe { e.BufferContext.Anonymous.ProcessorIndex });
                 context.handle_sample(timestamp_raw, tid, cpu);
             }
+            io_event
+                if io_event.starts_with("MSNT_SystemTrace/DiskIo/")
+                    || io_event.starts_with("MSNT_SystemTrace/FileIo/") =>
+            {
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                // I/O completions are logged on whichever thread happens to run,
+                // so prefer the thread which issued the request.
+                let tid: u32 = parser
+                    .try_parse("IssuingThreadId")
+                    .or_else(|_| parser.try_parse("TTID"))
+                    .unwrap_or(e.EventHeader.ThreadId);
+                let task_and_op = io_event.split_once('/').unwrap().1;
+                let text = event_properties_to_string(&s, &mut parser, None);
+                context.handle_io_event(timestamp_raw, tid, task_and_op, text);
+            }
+            reg_event if registry_event(reg_event).is_some() => {
+                let key_handle: u64 = parser.try_parse("KeyHandle").unwrap_or(0);
+                let key_name: String = parser.try_parse("KeyName").unwrap_or_default();
+                match registry_event(reg_event).unwrap() {
+                    RegistryEvent::KcbCreate => {
+                        context.handle_registry_kcb_create(key_handle, key_name);
+                    }
+                    RegistryEvent::KcbDelete => {
+                        context.handle_registry_kcb_delete(key_handle);
+                    }
+                    RegistryEvent::Operation(operation) => {
+                        if !context.is_in_time_range(timestamp_raw) {
+                            return;
+                        }
+                        // The event is logged when the operation completes, and
+                        // InitialTime is when it started.
+                        let initial_time: i64 = parser.try_parse("InitialTime").unwrap_or(0);
+                        let status: u32 = parser.try_parse("Status").unwrap_or(0);
+                        context.handle_registry_operation(
+                            timestamp_raw,
+                            u64::try_from(initial_time).ok(),
+                            e.EventHeader.ThreadId,
+                            operation,
+                            key_handle,
+                            &key_name,
+                            status,
+                        );
+                    }
+                }
+            }
             "MSNT_SystemTrace/PageFault/VirtualAlloc"
             | "MSNT_SystemTrace/PageFault/VirtualFree" => {
                 if !context.is_in_time_range(timestamp_raw) {
@@ -237,15 +342,15 @@ fn process_trace(
                 let is_free = s.name() == "MSNT_SystemTrace/PageFault/VirtualFree";
                 let pid = e.EventHeader.ProcessId;
                 let tid = e.EventHeader.ThreadId;
+                let base_address: u64 = parser.parse("BaseAddress");
                 let region_size: u64 = parser.parse("RegionSize");
-                let text = event_properties_to_string(&s, &mut parser, None);
                 context.handle_virtual_alloc_free(
                     timestamp_raw,
                     is_free,
                     pid,
                     tid,
+                    base_address,
                     region_size,
-                    text,
                 );
             }
             // KernelTraceControl/ImageID/ and KernelTraceControl/ImageID/DbgID_RSDS are synthesized by xperf during
@@ -319,7 +424,9 @@ fn process_trace(
                 context.handle_image_load(timestamp_raw, pid, image_base, path, info);
             }
             "MSNT_SystemTrace/Image/UnLoad" => {
-                // nothing, but we don't want a marker for it
+                let pid = parser.try_parse("ProcessId").unwrap();
+                let image_base: u64 = parser.try_parse("ImageBase").unwrap();
+                context.handle_image_unload(timestamp_raw, pid, image_base);
             }
             "Microsoft-Windows-DxgKrnl/VSyncDPC/Info " => {
                 if !context.is_in_time_range(timestamp_raw) {
@@ -327,6 +434,154 @@ fn process_trace(
                 }
                 context.handle_vsync(timestamp_raw);
             }
+            "Microsoft-Windows-DXGI/Present/Start" => {
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                context.handle_dxgi_present_start(timestamp_raw, e.EventHeader.ThreadId);
+            }
+            "Microsoft-Windows-DXGI/Present/Stop" => {
+                context.handle_dxgi_present_stop(
+                    timestamp_raw,
+                    e.EventHeader.ProcessId,
+                    e.EventHeader.ThreadId,
+                );
+            }
+            audio_event if is_audio_glitch_event(audio_event) => {
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                let task_and_op = s.name().split_once('/').unwrap().1;
+                let text = event_properties_to_string(&s, &mut parser, None);
+                context.handle_audio_glitch(
+                    timestamp_raw,
+                    e.EventHeader.ThreadId,
+                    task_and_op,
+                    text,
+                );
+            }
+            network_event if network_provider(network_event).is_some() => {
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                let pid = e.EventHeader.ProcessId;
+                let tid = e.EventHeader.ThreadId;
+                match network_provider(network_event).unwrap() {
+                    NetworkProvider::Dns => {
+                        let query_name: Option<String> = parser.try_parse("QueryName").ok();
+                        let Some(query_name) = query_name else {
+                            return;
+                        };
+                        let query_type: Option<u32> = parser.try_parse("QueryType").ok();
+                        let query_status: Option<u32> = parser.try_parse("QueryStatus").ok();
+                        match query_status {
+                            Some(status) => {
+                                let results: Option<String> = parser.try_parse("QueryResults").ok();
+                                context.handle_dns_query_end(
+                                    timestamp_raw,
+                                    pid,
+                                    &query_name,
+                                    status,
+                                    results,
+                                );
+                            }
+                            None => {
+                                context.handle_dns_query_start(
+                                    timestamp_raw,
+                                    pid,
+                                    tid,
+                                    query_name,
+                                    query_type,
+                                );
+                            }
+                        }
+                    }
+                    NetworkProvider::Http => {
+                        let request: Option<u64> = HTTP_REQUEST_HANDLE_PROPERTIES
+                            .iter()
+                            .find_map(|name| parser.try_parse(name).ok());
+                        let Some(request) = request else {
+                            return;
+                        };
+                        let url: Option<String> = HTTP_URL_PROPERTIES
+                            .iter()
+                            .find_map(|name| parser.try_parse(name).ok());
+                        let url = url.filter(|url| !url.is_empty());
+                        let task_and_op = s.name().split_once('/').unwrap().1;
+                        context.handle_http_event(
+                            timestamp_raw,
+                            pid,
+                            tid,
+                            request,
+                            url,
+                            task_and_op,
+                            is_http_request_end(network_event),
+                            is_http_failure(network_event),
+                        );
+                    }
+                }
+            }
+            rpc_event if rpc_call_event(rpc_event).is_some() => {
+                let call = rpc_call_event(rpc_event).unwrap();
+                let tid = e.EventHeader.ThreadId;
+                match call.edge {
+                    CallEdge::Start => {
+                        if !context.is_in_time_range(timestamp_raw) {
+                            return;
+                        }
+                        let interface: Option<GUID> = CALL_INTERFACE_PROPERTIES
+                            .iter()
+                            .find_map(|name| parser.try_parse(name).ok());
+                        let method: Option<u32> = CALL_METHOD_PROPERTIES
+                            .iter()
+                            .find_map(|name| parser.try_parse(name).ok());
+                        let endpoint: String = parser.try_parse("Endpoint").unwrap_or_default();
+                        let network_address: String =
+                            parser.try_parse("NetworkAddress").unwrap_or_default();
+                        let to_uuid = |guid: GUID| {
+                            Uuid::from_fields(guid.data1, guid.data2, guid.data3, &guid.data4)
+                        };
+                        let correlation_id = Some(to_uuid(e.EventHeader.ActivityId))
+                            .filter(|activity_id| !activity_id.is_nil());
+                        let endpoint = match (network_address.is_empty(), endpoint.is_empty()) {
+                            (true, _) => endpoint,
+                            (false, true) => network_address,
+                            (false, false) => format!("{network_address} {endpoint}"),
+                        };
+                        let details = CallDetails {
+                            interface: interface.map(to_uuid),
+                            method,
+                            endpoint,
+                            correlation_id,
+                        };
+                        context.handle_rpc_call_start(timestamp_raw, tid, call, details);
+                    }
+                    CallEdge::Stop => {
+                        let status: u32 = parser.try_parse("Status").unwrap_or(0);
+                        context.handle_rpc_call_stop(timestamp_raw, tid, call, status);
+                    }
+                }
+            }
+            power_event if system_sleep_edge(power_event).is_some() => {
+                let edge = system_sleep_edge(power_event).unwrap();
+                let task_and_op = s.name().split_once('/').unwrap().1;
+                context.handle_system_sleep_edge(timestamp_raw, edge, task_and_op);
+            }
+            freeze_event if process_freeze_edge(freeze_event).is_some() => {
+                let edge = process_freeze_edge(freeze_event).unwrap();
+                let pid: u32 = parser
+                    .try_parse("ProcessID")
+                    .unwrap_or(e.EventHeader.ProcessId);
+                context.handle_process_freeze_edge(timestamp_raw, pid, edge);
+            }
+            dwm_event if dwm_event.starts_with("Microsoft-Windows-Dwm-Core/") => {
+                if !context.is_in_time_range(timestamp_raw) {
+                    return;
+                }
+                let task_and_op = s.name().split_once('/').unwrap().1;
+                let text = event_properties_to_string(&s, &mut parser, None);
+                context.handle_dwm_event(timestamp_raw, task_and_op, text);
+            }
             "MSNT_SystemTrace/Thread/CSwitch" => {
                 if !context.is_in_time_range(timestamp_raw) {
                     return;
@@ -335,14 +590,23 @@ fn process_trace(
                 let new_tid: u32 = parser.parse("NewThreadId");
                 let cpu = u32::from(unsafe { e.BufferContext.Anonymous.ProcessorIndex });
                 let wait_reason: i8 = parser.parse("OldThreadWaitReason");
-                context.handle_cswitch(timestamp_raw, old_tid, new_tid, cpu, wait_reason);
+                let new_thread_priority: i8 = parser.parse("NewThreadPriority");
+                context.handle_cswitch(
+                    timestamp_raw,
+                    old_tid,
+                    new_tid,
+                    cpu,
+                    wait_reason,
+                    new_thread_priority,
+                );
             }
             "MSNT_SystemTrace/Thread/ReadyThread" => {
                 if !context.is_in_time_range(timestamp_raw) {
                     return;
                 }
                 // these events can give us the unblocking stack
-                let _thread_id: u32 = parser.parse("TThreadId");
+                let thread_id: u32 = parser.parse("TThreadId");
+                context.handle_ready_thread(timestamp_raw, thread_id);
             }
             "V8.js/SourceLoad/Start"
             | "Microsoft-JScript/ScriptContextRuntime/SourceLoad"
@@ -490,7 +754,7 @@ fn process_trace(
                     log::warn!("No Timestamp field on Chrome {marker_name} event");
                     return;
                 };
-                let phase: String = parser.try_parse("Phase").unwrap();
+                let phase: Cow<str> = parser.try_parse("Phase").unwrap();
                 let keyword_bitfield = e.EventHeader.EventDescriptor.Keyword; // a bitfield of keywords
                 let text = event_properties_to_string(
                     &s,
@@ -533,7 +797,20 @@ fn process_trace(
 
                 let task_and_op = s.name().split_once('/').unwrap().1;
                 let text = event_properties_to_string(&s, &mut parser, None);
-                context.handle_unknown_event(timestamp_raw, tid, task_and_op, text);
+                // Events from providers enabled with --provider-with-stacks carry their stack
+                // in the extended data.
+                let stack = e.stack_trace64();
+                let marker = context.handle_unknown_event(
+                    timestamp_raw,
+                    tid,
+                    task_and_op,
+                    text,
+                    stack.is_some(),
+                );
+                if let (Some(marker), Some(stack)) = (marker, stack) {
+                    let pid = e.EventHeader.ProcessId;
+                    context.handle_marker_stack(timestamp_raw, pid, stack.iter().copied(), marker);
+                }
             }
         }
     })
@@ -1,6 +1,8 @@
 use std::borrow::Cow;
 use std::mem;
+use std::net::IpAddr;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use memoffset::offset_of;
 pub use windows::core::GUID;
@@ -26,6 +28,7 @@ pub mod etw_types;
 pub mod parser;
 pub mod property;
 pub mod schema;
+pub mod schema_bundle;
 pub mod sddl;
 pub mod tdh;
 pub mod tdh_types;
@@ -319,15 +322,16 @@ pub fn event_properties_to_string(
     skip_properties: Option<&[&str]>,
 ) -> String {
     let mut text = String::new();
-    for i in 0..s.property_count() {
-        let property = s.property(i);
+    // The schema caches its properties, unlike s.property(i), which creates them on each call.
+    let properties = s.schema.properties();
+    for property in properties.properties(properties.top_level()) {
         if let Some(propfilter) = skip_properties {
             if propfilter.iter().any(|&s| s == property.name) {
                 continue;
             }
         }
 
-        write_property(&mut text, parser, &property, false);
+        write_property(&mut text, parser, property, false);
         text += ", "
     }
 
@@ -403,80 +407,138 @@ pub fn write_property(
             )
             .unwrap();
         }
+    } else if let PropertyDesc::Struct(_) = &property.desc {
+        match parser.try_parse_structs(&property.name) {
+            Ok(elements) => {
+                for (i, mut element) in elements.into_iter().enumerate() {
+                    write!(output, "{}{{", if i == 0 { "" } else { ", " }).unwrap();
+                    for member in element.properties() {
+                        write_property(output, &mut element, member, false);
+                    }
+                    write!(output, " }}").unwrap();
+                }
+            }
+            Err(e) => write!(output, "Err({:?}) type: {:?}", e, property.desc).unwrap(),
+        }
+    } else if property.is_array() {
+        let PropertyDesc::Primitive(desc) = &property.desc else {
+            unreachable!()
+        };
+        let value =
+            match desc.in_type {
+                TdhInType::InTypeUInt16 => TryParse::<Vec<u16>>::try_parse(parser, &property.name)
+                    .map(|x| format!("{:?}", x)),
+                TdhInType::InTypeUInt32 => TryParse::<Vec<u32>>::try_parse(parser, &property.name)
+                    .map(|x| format!("{:?}", x)),
+                TdhInType::InTypeInt32 => TryParse::<Vec<i32>>::try_parse(parser, &property.name)
+                    .map(|x| format!("{:?}", x)),
+                TdhInType::InTypeUInt64
+                | TdhInType::InTypeHexInt64
+                | TdhInType::InTypePointer
+                | TdhInType::InTypeSizeT => TryParse::<Vec<u64>>::try_parse(parser, &property.name)
+                    .map(|x| format!("{:x?}", x)),
+                TdhInType::InTypeUnicodeString
+                | TdhInType::InTypeAnsiString
+                | TdhInType::InTypeCountedString
+                | TdhInType::InTypeCountedAnsiString => {
+                    TryParse::<Vec<String>>::try_parse(parser, &property.name)
+                        .map(|x| format!("{:?}", x))
+                }
+                _ => TryParse::<Vec<u8>>::try_parse(parser, &property.name)
+                    .map(|x| format!("{:?} {:x?}", desc.in_type, x)),
+            };
+        match value {
+            Ok(value) => write!(output, "{}", value).unwrap(),
+            Err(e) => write!(output, "Err({:?}) type: {:?}", e, property.desc).unwrap(),
+        }
     } else {
-        let value = match &property.desc {
+        let value: Result<Cow<str>, ParserError> = match &property.desc {
+            PropertyDesc::Primitive(desc)
+                if matches!(
+                    desc.out_type,
+                    TdhOutType::OutTypeIpv4 | TdhOutType::OutTypeIpv6
+                ) =>
+            {
+                TryParse::<IpAddr>::try_parse(parser, &property.name).map(|x| x.to_string().into())
+            }
             PropertyDesc::Primitive(desc) => {
                 // XXX: we should be using the out_type here instead of in_type
                 match desc.in_type {
-                    TdhInType::InTypeUnicodeString => {
-                        TryParse::<String>::try_parse(parser, &property.name)
-                    }
-                    TdhInType::InTypeAnsiString => {
-                        TryParse::<String>::try_parse(parser, &property.name)
-                    }
-                    TdhInType::InTypeBoolean => {
-                        TryParse::<bool>::try_parse(parser, &property.name).map(|x| x.to_string())
-                    }
-                    TdhInType::InTypeHexInt32 => {
-                        TryParse::<i32>::try_parse(parser, &property.name).map(|x| x.to_string())
-                    }
-                    TdhInType::InTypeUInt32 => {
-                        TryParse::<u32>::try_parse(parser, &property.name).map(|x| x.to_string())
-                    }
-                    TdhInType::InTypeUInt16 => {
-                        TryParse::<u16>::try_parse(parser, &property.name).map(|x| x.to_string())
-                    }
-                    TdhInType::InTypeUInt8 => {
-                        TryParse::<u8>::try_parse(parser, &property.name).map(|x| x.to_string())
-                    }
-                    TdhInType::InTypeInt8 => {
-                        TryParse::<i8>::try_parse(parser, &property.name).map(|x| x.to_string())
-                    }
-                    TdhInType::InTypeInt64 => {
-                        TryParse::<i64>::try_parse(parser, &property.name).map(|x| x.to_string())
+                    TdhInType::InTypeUnicodeString | TdhInType::InTypeAnsiString => {
+                        TryParse::<Cow<str>>::try_parse(parser, &property.name)
                     }
+                    TdhInType::InTypeBoolean => TryParse::<bool>::try_parse(parser, &property.name)
+                        .map(|x| x.to_string().into()),
+                    TdhInType::InTypeHexInt32 => TryParse::<i32>::try_parse(parser, &property.name)
+                        .map(|x| x.to_string().into()),
+                    TdhInType::InTypeUInt32 => TryParse::<u32>::try_parse(parser, &property.name)
+                        .map(|x| x.to_string().into()),
+                    TdhInType::InTypeUInt16 => TryParse::<u16>::try_parse(parser, &property.name)
+                        .map(|x| x.to_string().into()),
+                    TdhInType::InTypeUInt8 => TryParse::<u8>::try_parse(parser, &property.name)
+                        .map(|x| x.to_string().into()),
+                    TdhInType::InTypeInt8 => TryParse::<i8>::try_parse(parser, &property.name)
+                        .map(|x| x.to_string().into()),
+                    TdhInType::InTypeInt64 => TryParse::<i64>::try_parse(parser, &property.name)
+                        .map(|x| x.to_string().into()),
                     TdhInType::InTypeUInt64 => {
                         let i = TryParse::<u64>::try_parse(parser, &property.name);
                         if desc.out_type == TdhOutType::OutTypeHexInt64 {
-                            i.map(|x| format!("0x{:x}", x))
+                            i.map(|x| format!("0x{:x}", x).into())
                         } else {
-                            i.map(|x| x.to_string())
+                            i.map(|x| x.to_string().into())
                         }
                     }
                     TdhInType::InTypeHexInt64 => {
                         let i = TryParse::<i64>::try_parse(parser, &property.name);
-                        i.map(|x| format!("0x{:x}", x))
+                        i.map(|x| format!("0x{:x}", x).into())
                     }
                     TdhInType::InTypePointer | TdhInType::InTypeSizeT => {
                         TryParse::<u64>::try_parse(parser, &property.name)
-                            .map(|x| format!("0x{:x}", x))
+                            .map(|x| format!("0x{:x}", x).into())
                     }
                     TdhInType::InTypeGuid => TryParse::<GUID>::try_parse(parser, &property.name)
-                        .map(|x| format!("{:?}", x)),
-                    TdhInType::InTypeInt32 => {
-                        TryParse::<i32>::try_parse(parser, &property.name).map(|x| x.to_string())
+                        .map(|x| format!("{:?}", x).into()),
+                    TdhInType::InTypeInt32 => TryParse::<i32>::try_parse(parser, &property.name)
+                        .map(|x| x.to_string().into()),
+                    TdhInType::InTypeFloat => TryParse::<f32>::try_parse(parser, &property.name)
+                        .map(|x| x.to_string().into()),
+                    TdhInType::InTypeDouble => TryParse::<f64>::try_parse(parser, &property.name)
+                        .map(|x| x.to_string().into()),
+                    TdhInType::InTypeCountedString
+                    | TdhInType::InTypeCountedAnsiString
+                    | TdhInType::InTypeReverseCountedString
+                    | TdhInType::InTypeReverseCountedAnsiString
+                    | TdhInType::InTypeNonNullTerminatedString
+                    | TdhInType::InTypeNonNullTerminatedAnsiString
+                    | TdhInType::InTypeUnicodeChar
+                    | TdhInType::InTypeAnsiChar
+                    | TdhInType::InTypeSid
+                    | TdhInType::InTypeWBEMSID => {
+                        TryParse::<Cow<str>>::try_parse(parser, &property.name)
                     }
-                    TdhInType::InTypeFloat => {
-                        TryParse::<f32>::try_parse(parser, &property.name).map(|x| x.to_string())
+                    TdhInType::InTypeFileTime | TdhInType::InTypeSystemTime => {
+                        TryParse::<SystemTime>::try_parse(parser, &property.name).map(|x| {
+                            let since_epoch = x.duration_since(UNIX_EPOCH).unwrap_or_default();
+                            format!("{:.3}s since 1970", since_epoch.as_secs_f64()).into()
+                        })
                     }
-                    _ => Ok(format!("Unknown {:?} -> {:?}", desc.in_type, desc.out_type)),
+                    _ => Ok(format!("Unknown {:?} -> {:?}", desc.in_type, desc.out_type).into()),
                 }
             }
-            PropertyDesc::Struct(desc) => Ok(format!(
-                "unhandled struct {} {}",
-                desc.start_index, desc.num_members
-            )),
+            PropertyDesc::Struct(_) => unreachable!(),
         };
         let value = match value {
             Ok(value) => value,
-            Err(ParserError::InvalidType) => format!("invalid type {:?}", property.desc),
+            Err(ParserError::InvalidType) => format!("invalid type {:?}", property.desc).into(),
             Err(ParserError::LengthMismatch) => format!(
                 "Err(LengthMismatch) type: {:?}, flags: {:?}, buf: {}",
                 property.desc,
                 property.flags,
                 parser.buffer.len()
-            ),
-            Err(e) => format!("Err({:?}) type: {:?}", e, property.desc),
+            )
+            .into(),
+            Err(e) => format!("Err({:?}) type: {:?}", e, property.desc).into(),
         };
         write!(output, "{}", value).unwrap();
     }
@@ -3,10 +3,12 @@
 //! The `property` module expose the basic structures that represent the Properties an Event contains
 //! based on it's Schema. This Properties can then be used to parse accordingly their values.
 
+use std::ops::Range;
+
 use rustc_hash::FxHashMap;
 
 use super::schema::Schema;
-use super::tdh_types::Property;
+use super::tdh_types::{Property, PropertyDesc, StructDesc};
 
 /// Event Property information
 #[derive(Clone, Debug)]
@@ -29,23 +31,39 @@ impl<'a> PropertyInfo<'a> {
 }
 
 pub(crate) struct PropertyIter {
+    /// The top-level properties, followed by the members of the struct properties
     properties: Vec<Property>,
+    top_level_count: usize,
     pub(crate) name_to_indx: FxHashMap<String, usize>,
 }
 
 impl PropertyIter {
     pub fn new(schema: &Schema) -> Self {
-        let prop_count = schema.event_schema.property_count();
+        let top_level_count = schema.event_schema.property_count() as usize;
         let mut properties = Vec::new();
         let mut name_to_indx = FxHashMap::default();
-        for i in 0..prop_count {
-            let prop = schema.event_schema.property(i);
-            name_to_indx.insert(prop.name.clone(), i as usize);
+        for i in 0..top_level_count {
+            let prop = schema.event_schema.property(i as u32);
+            name_to_indx.insert(prop.name.clone(), i);
             properties.push(prop);
         }
 
+        // The members of structs come after the top-level properties. Structs can be nested, so
+        // the loop also visits the members which were just added.
+        let mut i = 0;
+        while i < properties.len() {
+            if let PropertyDesc::Struct(desc) = &properties[i].desc {
+                let end = desc.start_index as usize + desc.num_members as usize;
+                for j in properties.len()..end {
+                    properties.push(schema.event_schema.property(j as u32));
+                }
+            }
+            i += 1;
+        }
+
         PropertyIter {
             properties,
+            top_level_count,
             name_to_indx,
         }
     }
@@ -53,4 +71,19 @@ impl PropertyIter {
     pub fn property(&self, index: usize) -> Option<&Property> {
         self.properties.get(index)
     }
+
+    /// The indexes of the top-level properties.
+    pub fn top_level(&self) -> Range<usize> {
+        0..self.top_level_count
+    }
+
+    /// The indexes of the members of a struct property.
+    pub fn struct_members(&self, desc: &StructDesc) -> Range<usize> {
+        let start = desc.start_index as usize;
+        start..start + desc.num_members as usize
+    }
+
+    pub fn properties(&self, range: Range<usize>) -> &[Property] {
+        self.properties.get(range).unwrap_or_default()
+    }
 }
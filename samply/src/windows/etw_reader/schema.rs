@@ -11,6 +11,7 @@ use windows::Win32::System::Diagnostics::Etw::{self, EVENT_HEADER_FLAG_64_BIT_HE
 
 use super::etw_types::{DecodingSource, EventRecord, TraceEventInfoRaw};
 use super::property::PropertyIter;
+use super::schema_bundle::{BundledSchema, SchemaBundle};
 use super::tdh;
 use super::tdh_types::Property;
 
@@ -167,6 +168,39 @@ impl SchemaLocator {
         self.schemas.insert(key, Rc::new(Schema::new(schema)));
     }
 
+    /// Adds the schemas of a bundle which was exported with [SchemaLocator::export_schemas],
+    /// e.g. on a different machine which has the providers' manifests.
+    pub fn add_schema_bundle(&mut self, bundle: SchemaBundle) {
+        for schema in bundle.schemas {
+            self.add_custom_schema(Box::new(schema));
+        }
+    }
+
+    /// Exports the schemas which were located so far, so that the events can be decoded
+    /// on machines which don't have the providers' manifests.
+    ///
+    /// TraceLogging schemas are not included: they are contained in the events themselves.
+    pub fn export_schemas(&self) -> SchemaBundle {
+        let schemas = self
+            .schemas
+            .iter()
+            .filter(|(_, schema)| {
+                !matches!(schema.event_schema.decoding_source(), DecodingSource::Tlg)
+            })
+            .map(|(key, schema)| {
+                BundledSchema::new(
+                    key.provider,
+                    key.id,
+                    key.opcode,
+                    key.version,
+                    key.level,
+                    schema.event_schema.as_ref(),
+                )
+            })
+            .collect();
+        SchemaBundle { schemas }
+    }
+
     /// Use the `event_schema` function to retrieve the Schema of an ETW Event
     ///
     /// # Arguments
@@ -115,6 +115,17 @@ impl Property {
     }
 }
 
+impl Property {
+    /// Whether the property is an array, with a fixed count or with the count in another
+    /// property.
+    pub fn is_array(&self) -> bool {
+        self.count > 1
+            || self.flags.intersects(
+                PropertyFlags::PROPERTY_PARAM_COUNT | PropertyFlags::PROPERTY_PARAM_FIXED_COUNT,
+            )
+    }
+}
+
 /// Represent a TDH_IN_TYPE
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, FromPrimitive, ToPrimitive, PartialEq)]
@@ -63,14 +63,15 @@ pub fn parse_unk_size_null_ansi_vec(v: &[u8]) -> Vec<u8> {
 }
 
 pub fn parse_null_utf16_string(v: &[u8]) -> String {
-    String::from_utf16_lossy(
-        v.chunks_exact(2)
-            .map(|a| u16::from_ne_bytes([a[0], a[1]]))
-            .collect::<Vec<u16>>()
-            .as_slice(),
-    )
-    .trim_matches(char::default())
-    .to_string()
+    // Decode straight into the String, without collecting the code units first.
+    let units = v.chunks_exact(2).map(|a| u16::from_ne_bytes([a[0], a[1]]));
+    let mut s = String::with_capacity(v.len() / 2);
+    s.extend(char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+    s.truncate(s.trim_end_matches(char::default()).len());
+    if s.starts_with(char::default()) {
+        return s.trim_start_matches(char::default()).to_string();
+    }
+    s
 }
 
 pub fn parse_utf16_guid(v: &[u8]) -> String {
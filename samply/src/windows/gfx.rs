@@ -19,5 +19,12 @@ pub fn gfx_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
         ));
     }
 
+    // Present/Start and Present/Stop around each IDXGISwapChain::Present call.
+    providers.push("Microsoft-Windows-DXGI:0xffffffffffffffff:4".to_string());
+
+    // The compositor's frames, so that presents can be matched up with the
+    // frame in which they reached the screen.
+    providers.push("Microsoft-Windows-Dwm-Core:0xffffffffffffffff:4".to_string());
+
     providers
 }
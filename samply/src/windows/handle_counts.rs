@@ -0,0 +1,88 @@
+//! Polls the open handle counts of the profiled processes while xperf is
+//! recording. The kernel session has no events for this, so the counts are
+//! sampled from a separate thread and timestamped with the same QPC clock as
+//! the ETW events, and added to the profile when the trace is converted.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use windows::Win32::Foundation::{CloseHandle, HANDLE};
+use windows::Win32::System::Performance::QueryPerformanceCounter;
+use windows::Win32::System::Threading::{
+    GetProcessHandleCount, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+};
+
+use crate::shared::open_handles::OPEN_HANDLES_POLL_INTERVAL;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HandleCountSample {
+    pub pid: u32,
+    pub timestamp_raw: u64,
+    pub count: u32,
+}
+
+pub struct HandleCountPoller {
+    pids: Arc<Mutex<Vec<u32>>>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Vec<HandleCountSample>>,
+}
+
+impl HandleCountPoller {
+    pub fn start() -> Self {
+        let pids: Arc<Mutex<Vec<u32>>> = Default::default();
+        let stop: Arc<AtomicBool> = Default::default();
+        let thread = std::thread::spawn({
+            let pids = pids.clone();
+            let stop = stop.clone();
+            move || {
+                let mut samples = Vec::new();
+                while !stop.load(Ordering::Relaxed) {
+                    let pids = pids.lock().unwrap().clone();
+                    for pid in pids {
+                        if let Some(count) = handle_count_of_pid(pid) {
+                            samples.push(HandleCountSample {
+                                pid,
+                                timestamp_raw: qpc_now(),
+                                count,
+                            });
+                        }
+                    }
+                    std::thread::sleep(OPEN_HANDLES_POLL_INTERVAL);
+                }
+                samples
+            }
+        });
+        Self { pids, stop, thread }
+    }
+
+    pub fn add_pid(&self, pid: u32) {
+        self.pids.lock().unwrap().push(pid);
+    }
+
+    /// Stops polling and returns the samples, ordered by time.
+    pub fn stop(self) -> Vec<HandleCountSample> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+/// Returns the number of handles which the process has open.
+pub fn handle_count(process: HANDLE) -> Option<u32> {
+    let mut count = 0;
+    unsafe { GetProcessHandleCount(process, &mut count) }.ok()?;
+    Some(count)
+}
+
+fn handle_count_of_pid(pid: u32) -> Option<u32> {
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }.ok()?;
+    let count = handle_count(process);
+    let _ = unsafe { CloseHandle(process) };
+    count
+}
+
+fn qpc_now() -> u64 {
+    let mut counter = 0;
+    let _ = unsafe { QueryPerformanceCounter(&mut counter) };
+    counter as u64
+}
@@ -29,7 +29,13 @@ pub fn convert_etl_file_to_profile(filename: &Path, import_props: ImportProps) -
         import_props.time_range,
     );
 
-    etw_gecko::process_etl_files(&mut context, filename, &import_props.user_etl);
+    etw_gecko::process_etl_files(
+        &mut context,
+        filename,
+        &import_props.user_etl,
+        &import_props.etw_schemas,
+        import_props.export_etw_schemas.as_deref(),
+    );
 
     context.finish()
 }
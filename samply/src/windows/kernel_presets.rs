@@ -0,0 +1,110 @@
+//! The sets of kernel flags which `samply record --preset` enables in the
+//! kernel logger session.
+
+use crate::shared::prop_types::EtwPreset;
+
+pub struct KernelProviderSet {
+    pub description: &'static str,
+    /// The kernel flags for `xperf -on`.
+    pub flags: &'static [&'static str],
+    /// The kernel events which are recorded with stacks, for `xperf -stackwalk`.
+    pub stackwalk: &'static [&'static str],
+    /// The kernel event classes which end up in the trace, as listed by
+    /// `--list-presets`.
+    pub events: &'static [&'static str],
+}
+
+const CPU: KernelProviderSet = KernelProviderSet {
+    description: "CPU samples and context switches, with stacks, and the stacks of thread \
+                  creations.",
+    flags: &["PROC_THREAD", "LOADER", "PROFILE", "CSWITCH"],
+    stackwalk: &["PROFILE", "CSWITCH", "ThreadCreate"],
+    events: &["SampleProf", "CSwitch", "Thread"],
+};
+
+const IO: KernelProviderSet = KernelProviderSet {
+    description: "Everything from cpu, plus disk and file I/O as markers, with stacks for \
+                  reads and writes.",
+    flags: &[
+        "PROC_THREAD",
+        "LOADER",
+        "PROFILE",
+        "CSWITCH",
+        "DISK_IO",
+        "DISK_IO_INIT",
+        "FILE_IO",
+        "FILE_IO_INIT",
+    ],
+    stackwalk: &[
+        "PROFILE",
+        "CSWITCH",
+        "ThreadCreate",
+        "DiskReadInit",
+        "DiskWriteInit",
+        "FileRead",
+        "FileWrite",
+    ],
+    events: &["SampleProf", "CSwitch", "Thread", "DiskIO", "FileIO"],
+};
+
+const FULL: KernelProviderSet = KernelProviderSet {
+    description: "Everything from io, plus VirtualAlloc / VirtualFree (shown in the memory \
+                  track) and hard page faults. This produces much larger traces.",
+    flags: &[
+        "PROC_THREAD",
+        "LOADER",
+        "PROFILE",
+        "CSWITCH",
+        "DISK_IO",
+        "DISK_IO_INIT",
+        "FILE_IO",
+        "FILE_IO_INIT",
+        "VIRT_ALLOC",
+        "HARD_FAULTS",
+    ],
+    stackwalk: &[
+        "PROFILE",
+        "CSWITCH",
+        "ThreadCreate",
+        "DiskReadInit",
+        "DiskWriteInit",
+        "FileRead",
+        "FileWrite",
+        "VirtualAlloc",
+        "VirtualFree",
+        "HardFault",
+    ],
+    events: &[
+        "SampleProf",
+        "CSwitch",
+        "Thread",
+        "DiskIO",
+        "FileIO",
+        "VirtualAlloc",
+        "PageFault",
+    ],
+};
+
+pub fn kernel_provider_set(preset: EtwPreset) -> &'static KernelProviderSet {
+    match preset {
+        EtwPreset::Cpu => &CPU,
+        EtwPreset::Io => &IO,
+        EtwPreset::Full => &FULL,
+    }
+}
+
+/// Prints what each preset captures, for `samply record --list-presets`.
+pub fn print_presets() {
+    for (name, preset) in [
+        ("cpu", EtwPreset::Cpu),
+        ("io", EtwPreset::Io),
+        ("full", EtwPreset::Full),
+    ] {
+        let set = kernel_provider_set(preset);
+        println!("{name}: {}", set.description);
+        println!("    Events:        {}", set.events.join(", "));
+        println!("    Kernel flags:  {}", set.flags.join("+"));
+        println!("    With stacks:   {}", set.stackwalk.join("+"));
+        println!();
+    }
+}
@@ -1,16 +1,27 @@
+mod audio;
 mod chrome;
 mod coreclr;
+mod custom_providers;
 mod elevated_helper;
 mod etw_gecko;
 #[allow(dead_code)]
 mod etw_reader;
 mod firefox;
 mod gfx;
+mod handle_counts;
 pub mod import;
+mod kernel_presets;
+mod network;
+mod power;
 mod profile_context;
 pub mod profiler;
+mod registry;
+mod rpc;
+mod service;
+mod user_mode_sampler;
 mod utility_process;
 mod winutils;
 mod xperf;
 
 pub use elevated_helper::run_elevated_helper;
+pub use kernel_presets::print_presets as print_kernel_presets;
@@ -0,0 +1,84 @@
+use std::borrow::Cow;
+
+use super::elevated_helper::ElevatedRecordingProps;
+
+pub fn network_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
+    if !props.network {
+        return vec![];
+    }
+
+    vec![
+        // Name resolution through the DNS client service.
+        "Microsoft-Windows-DNS-Client:0xffffffffffffffff:4".to_string(),
+        // HTTP requests of the two Windows HTTP stacks.
+        "Microsoft-Windows-WinHttp:0xffffffffffffffff:4".to_string(),
+        "Microsoft-Windows-WinINet:0xffffffffffffffff:4".to_string(),
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkProvider {
+    Dns,
+    Http,
+}
+
+/// Returns which kind of network provider an event comes from.
+pub fn network_provider(name: &str) -> Option<NetworkProvider> {
+    let (provider, _) = name.split_once('/')?;
+    match provider {
+        "Microsoft-Windows-DNS-Client" => Some(NetworkProvider::Dns),
+        "Microsoft-Windows-WinHttp" | "Microsoft-Windows-WinINet" => Some(NetworkProvider::Http),
+        _ => None,
+    }
+}
+
+/// The names of the properties which contain the request handle in WinHTTP
+/// and WinINet events. All events of a request carry it, so it's used to find
+/// the events which belong together.
+pub const HTTP_REQUEST_HANDLE_PROPERTIES: &[&str] = &["Request", "RequestHandle", "hRequest"];
+
+/// The names of the properties which contain the URL, or a part of it, in
+/// WinHTTP and WinINet events.
+pub const HTTP_URL_PROPERTIES: &[&str] = &["URL", "Url", "URI", "ObjectName", "ServerName"];
+
+/// Whether an HTTP event ends its request, i.e. closes the request handle.
+///
+/// The task and opcode names differ between the two providers and between
+/// Windows versions, so this goes by the words in them.
+pub fn is_http_request_end(name: &str) -> bool {
+    let task_and_op = name.split_once('/').map_or(name, |(_, rest)| rest);
+    let task_and_op = task_and_op.to_ascii_lowercase();
+    task_and_op.ends_with("/stop")
+        || task_and_op.ends_with("/win:stop")
+        || ["close", "complete"]
+            .iter()
+            .any(|pattern| task_and_op.contains(pattern))
+}
+
+/// Whether an HTTP event reports that its request failed.
+pub fn is_http_failure(name: &str) -> bool {
+    let task_and_op = name.split_once('/').map_or(name, |(_, rest)| rest);
+    let task_and_op = task_and_op.to_ascii_lowercase();
+    ["error", "fail", "timeout"]
+        .iter()
+        .any(|pattern| task_and_op.contains(pattern))
+}
+
+/// Returns the name of a DNS record type, e.g. "AAAA" for 28.
+pub fn dns_query_type_name(query_type: u32) -> Cow<'static, str> {
+    match query_type {
+        1 => "A".into(),
+        2 => "NS".into(),
+        5 => "CNAME".into(),
+        6 => "SOA".into(),
+        12 => "PTR".into(),
+        15 => "MX".into(),
+        16 => "TXT".into(),
+        28 => "AAAA".into(),
+        33 => "SRV".into(),
+        64 => "SVCB".into(),
+        65 => "HTTPS".into(),
+        255 => "ANY".into(),
+        _ => query_type.to_string().into(),
+    }
+}
@@ -0,0 +1,60 @@
+use super::elevated_helper::ElevatedRecordingProps;
+
+/// The keyword for the ProcessFreeze events of Microsoft-Windows-Kernel-Process.
+const KERNEL_PROCESS_FREEZE_KEYWORD: u64 = 0x200;
+
+pub fn power_xperf_args(_props: &ElevatedRecordingProps) -> Vec<String> {
+    // Both providers only log a handful of events, so they're always on.
+    vec![
+        // Sleep, hibernation and (Modern) standby transitions.
+        "Microsoft-Windows-Kernel-Power:0xffffffffffffffff:4".to_string(),
+        // Processes which are frozen and thawed, e.g. suspended UWP apps.
+        format!("Microsoft-Windows-Kernel-Process:0x{KERNEL_PROCESS_FREEZE_KEYWORD:x}:4"),
+    ]
+}
+
+/// Whether an event starts or ends a time in which code couldn't run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspendEdge {
+    Begin,
+    End,
+}
+
+/// Returns whether an event from the Microsoft-Windows-Kernel-Power provider
+/// marks the start or the end of system sleep, standby or hibernation.
+///
+/// The task names differ between Windows versions, so this goes by the words
+/// in the task and opcode names.
+pub fn system_sleep_edge(name: &str) -> Option<SuspendEdge> {
+    let task_and_op = name.strip_prefix("Microsoft-Windows-Kernel-Power/")?;
+    let task_and_op = task_and_op.to_ascii_lowercase();
+    let is_sleep_event = ["sleep", "standby", "hibernat", "suspend"]
+        .iter()
+        .any(|pattern| task_and_op.contains(pattern));
+    if !is_sleep_event {
+        return None;
+    }
+    if ["resume", "wake", "exit", "/stop"]
+        .iter()
+        .any(|pattern| task_and_op.contains(pattern))
+    {
+        Some(SuspendEdge::End)
+    } else if ["enter", "/start"]
+        .iter()
+        .any(|pattern| task_and_op.contains(pattern))
+    {
+        Some(SuspendEdge::Begin)
+    } else {
+        None
+    }
+}
+
+/// Returns whether an event from the Microsoft-Windows-Kernel-Process provider
+/// marks a process being frozen or thawed.
+pub fn process_freeze_edge(name: &str) -> Option<SuspendEdge> {
+    match name {
+        "Microsoft-Windows-Kernel-Process/ProcessFreeze/Start" => Some(SuspendEdge::Begin),
+        "Microsoft-Windows-Kernel-Process/ProcessFreeze/Stop" => Some(SuspendEdge::End),
+        _ => None,
+    }
+}
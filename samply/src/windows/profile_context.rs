@@ -13,20 +13,34 @@ use shlex::Shlex;
 use wholesym::PeCodeId;
 
 use super::chrome::KeywordNames;
+use super::network::dns_query_type_name;
+use super::power::SuspendEdge;
+use super::registry::full_key_path;
+use super::rpc::{CallDetails, CallEvent, CallRuntime, CallSide};
+use super::service::ServiceThreadFilter;
 use super::winutils;
 use crate::shared::context_switch::{
     ContextSwitchHandler, OffCpuSampleGroup, ThreadContextSwitchData,
 };
+use crate::shared::frame_stats::{frames_between_boundaries, FRAME_MARKER_NAME};
 use crate::shared::included_processes::IncludedProcesses;
 use crate::shared::jit_category_manager::{JitCategoryManager, JsFrame};
 use crate::shared::jit_function_add_marker::JitFunctionAddMarker;
 use crate::shared::jit_function_recycler::JitFunctionRecycler;
-use crate::shared::lib_mappings::{LibMappingAdd, LibMappingInfo, LibMappingOp, LibMappingOpQueue};
+use crate::shared::lib_mappings::{
+    LibMappingAdd, LibMappingInfo, LibMappingOp, LibMappingOpQueue, LibMappingRemove,
+};
+use crate::shared::open_handles::OpenHandlesCounter;
 use crate::shared::per_cpu::Cpus;
 use crate::shared::process_name::make_process_name;
-use crate::shared::process_sample_data::{ProcessSampleData, UserTimingMarker};
+use crate::shared::process_sample_data::{
+    MarkerSpanOnThread, ProcessSampleData, UserTimingMarker, VirtualMemoryMarker,
+};
 use crate::shared::prop_types::ProfileCreationProps;
 use crate::shared::recycling::{ProcessRecycler, ProcessRecyclingData, ThreadRecycler};
+use crate::shared::restarts::{InstanceStart, RestartTracker};
+use crate::shared::sched_latency::SchedLatencyTracker;
+use crate::shared::suspend::{add_suspend_span, SuspendKind};
 use crate::shared::synthetic_jit_library::SyntheticJitLibrary;
 use crate::shared::timestamp_converter::TimestampConverter;
 use crate::shared::types::{StackFrame, StackMode};
@@ -49,12 +63,13 @@ pub struct SampleWithPendingStack {
     pub cpu_delta: CpuDelta,
     pub has_on_cpu_sample: bool,
     pub per_cpu_stuff: Option<(ThreadHandle, CpuDelta)>,
+    /// Whether the thread was running at background priority when the sample was taken.
+    pub is_background: bool,
 }
 
 #[derive(Debug)]
 pub struct MemoryUsage {
     pub counter: CounterHandle,
-    #[allow(dead_code)]
     pub value: f64,
 }
 
@@ -144,15 +159,24 @@ pub struct Thread {
     pub handle: ThreadHandle,
     pub thread_label: StringHandle,
     pub samples_with_pending_stacks: VecDeque<SampleWithPendingStack>,
+    /// Markers for kernel events which were recorded with -stackwalk, and the
+    /// raw timestamps of their events. Their user stacks arrive later.
+    pub markers_with_pending_stacks: VecDeque<(u64, MarkerHandle)>,
     pub context_switch_data: ThreadContextSwitchData,
     #[allow(dead_code)]
     pub thread_id: u32,
     pub tid_reused_timestamp_raw: Option<u64>,
-    #[allow(dead_code)]
     pub process_id: u32,
     pub pending_markers: HashMap<String, PendingMarker>,
+    /// The priority the thread had when it was last switched in, from CSwitch events.
+    pub priority: Option<i8>,
 }
 
+/// Threads at or below this priority are doing background work. This covers threads
+/// in the idle priority class and threads in background processing mode
+/// (THREAD_MODE_BACKGROUND_BEGIN), which run at priority 4.
+const BACKGROUND_PRIORITY_MAX: i8 = 4;
+
 impl Thread {
     fn new(
         name: Option<String>,
@@ -168,11 +192,13 @@ impl Thread {
             handle,
             thread_label,
             samples_with_pending_stacks: VecDeque::new(),
+            markers_with_pending_stacks: VecDeque::new(),
             context_switch_data: Default::default(),
             pending_markers: HashMap::new(),
             thread_id: tid,
             tid_reused_timestamp_raw: None,
             process_id: pid,
+            priority: None,
         }
     }
 }
@@ -210,12 +236,27 @@ impl Processes {
                     Vec::new()
                 };
 
+                let frame_spans = process
+                    .frame_boundaries
+                    .into_iter()
+                    .flat_map(|(thread_handle, boundaries)| {
+                        frames_between_boundaries(boundaries).into_iter().map(
+                            move |(start_time, end_time)| MarkerSpanOnThread {
+                                thread_handle,
+                                start_time,
+                                end_time,
+                                name: FRAME_MARKER_NAME.to_string(),
+                            },
+                        )
+                    })
+                    .collect();
+
                 ProcessSampleData::new(
+                    process.handle,
                     process.unresolved_samples,
                     process.regular_lib_mapping_ops,
                     jitdump_lib_mapping_op_queues,
-                    None,
-                    Vec::new(),
+                    frame_spans,
                 )
             })
             .collect()
@@ -291,6 +332,12 @@ pub struct Process {
     pub thread_recycler: Option<ThreadRecycler>,
     pub jit_function_recycler: Option<JitFunctionRecycler>,
     pub js_sources: HashMap<u64, String>,
+    /// The times of the `--frame-marker` events, per thread.
+    pub frame_boundaries: HashMap<ThreadHandle, Vec<Timestamp>>,
+    /// Whether this is a protected process (PPL), whose stacks Windows doesn't
+    /// walk. Its samples only have CPU usage.
+    pub is_protected: bool,
+    pub open_handles: Option<OpenHandlesCounter>,
 }
 
 impl Process {
@@ -321,6 +368,9 @@ impl Process {
             thread_recycler,
             jit_function_recycler,
             js_sources: HashMap::new(),
+            frame_boundaries: HashMap::new(),
+            is_protected: false,
+            open_handles: None,
         }
     }
 
@@ -386,6 +436,7 @@ pub enum KnownCategory {
     System,
     CoreClrR2r,
     CoreClrJit,
+    Background,
     Unknown,
 }
 
@@ -403,6 +454,7 @@ impl KnownCategories {
         (KnownCategory::System, "System Libraries", CategoryColor::Orange),
         (KnownCategory::CoreClrR2r, "CoreCLR R2R", CategoryColor::Blue),
         (KnownCategory::CoreClrJit, "CoreCLR JIT", CategoryColor::Purple),
+        (KnownCategory::Background, "Background", CategoryColor::Gray),
         (KnownCategory::Unknown, "Other", CategoryColor::DarkGray),
     ];
 
@@ -456,8 +508,34 @@ pub struct ProfileContext {
     /// thread of the same name.
     process_recycler: Option<ProcessRecycler>,
 
-    // some special threads
-    gpu_thread_handle: Option<ThreadHandle>,
+    // Pseudo-processes which aren't real processes, such as "GPU" with the
+    // vsync, present and DWM tracks, and their tracks.
+    pseudo_processes: HashMap<&'static str, ProcessHandle>,
+    pseudo_tracks: HashMap<(&'static str, &'static str), ThreadHandle>,
+
+    /// The start timestamps of DXGI presents which haven't finished yet, by tid.
+    pending_presents: HashMap<u32, u64>,
+
+    /// The start timestamp and event of a system sleep which hasn't ended yet.
+    pending_system_sleep: Option<(u64, String)>,
+
+    /// The start timestamps of process freezes which haven't ended yet, by pid.
+    pending_process_freezes: HashMap<u32, u64>,
+
+    /// DNS queries which haven't completed yet, by pid and host name.
+    pending_dns_queries: HashMap<(u32, String), PendingDnsQuery>,
+
+    /// WinHTTP and WinINet requests whose handle hasn't been closed yet, by pid
+    /// and request handle.
+    pending_http_requests: HashMap<(u32, u64), PendingHttpRequest>,
+
+    /// The paths of the registry key control blocks, by handle.
+    registry_key_paths: HashMap<u64, String>,
+
+    /// RPC and COM calls which haven't finished yet, by tid, runtime and side.
+    /// Calls on one thread can be nested, e.g. when a server makes a call of
+    /// its own while handling one, so these are stacks.
+    pending_rpc_calls: HashMap<(u32, CallRuntime, CallSide), Vec<(u64, CallDetails)>>,
 
     // These are the processes + their descendants that we want to write into
     // the profile.json. If it's None, include everything.
@@ -501,6 +579,17 @@ pub struct ProfileContext {
     time_range: Option<(Timestamp, Timestamp)>,
 
     cpus: Option<Cpus>,
+
+    /// The run-queue latency of each thread, with `--sched-latency`.
+    sched_latency: Option<SchedLatencyTracker>,
+
+    /// Leaves out the threads of other services, with `--service` for a
+    /// service which shares its process.
+    service_thread_filter: Option<ServiceThreadFilter>,
+
+    /// Finds the new instances of the recorded process, with
+    /// `--follow-restarts`.
+    restart_tracker: Option<RestartTracker>,
 }
 
 impl ProfileContext {
@@ -559,6 +648,9 @@ impl ProfileContext {
         } else {
             None
         };
+        let sched_latency = profile_creation_props
+            .sched_latency_threshold_ms
+            .map(SchedLatencyTracker::new);
 
         Self {
             profile,
@@ -568,7 +660,15 @@ impl ProfileContext {
             thread_handles: BTreeMap::new(),
             unresolved_stacks: UnresolvedStacks::default(),
             process_recycler,
-            gpu_thread_handle: None,
+            pseudo_processes: HashMap::new(),
+            pseudo_tracks: HashMap::new(),
+            pending_presents: HashMap::new(),
+            pending_system_sleep: None,
+            pending_process_freezes: HashMap::new(),
+            pending_dns_queries: HashMap::new(),
+            pending_http_requests: HashMap::new(),
+            registry_key_paths: HashMap::new(),
+            pending_rpc_calls: HashMap::new(),
             included_processes,
             categories,
             known_images: HashMap::new(),
@@ -588,14 +688,53 @@ impl ProfileContext {
             timestamp_converter: TimestampConverter {
                 reference_raw: 0,
                 raw_to_ns_factor: 1,
+                offset_ns: 0,
             },
             event_timestamps_are_qpc: false,
             main_thread_only,
             time_range,
             cpus,
+            sched_latency,
+            service_thread_filter: None,
+            restart_tracker: None,
+        }
+    }
+
+    pub fn set_service_thread_filter(&mut self, service_thread_filter: ServiceThreadFilter) {
+        self.service_thread_filter = Some(service_thread_filter);
+    }
+
+    pub fn set_restart_tracker(&mut self, restart_tracker: RestartTracker) {
+        self.restart_tracker = Some(restart_tracker);
+    }
+
+    /// Tells the restart tracker about a process start. New instances of the
+    /// recorded process are recorded even though they aren't included
+    /// otherwise.
+    fn check_instance_start(
+        &mut self,
+        pid: u32,
+        parent_pid: u32,
+        image_file_name: &str,
+    ) -> InstanceStart {
+        let parent_is_recorded = self.processes.has(parent_pid);
+        let exe_name = extract_filename(&self.map_device_path(image_file_name)).to_string();
+        match &mut self.restart_tracker {
+            Some(restart_tracker) => {
+                restart_tracker.handle_process_start(pid, &exe_name, parent_is_recorded)
+            }
+            None => InstanceStart::NotAnInstance,
         }
     }
 
+    /// Whether the thread belongs to another service than the one which is
+    /// recorded with `--service`.
+    fn is_other_service_thread(&mut self, pid: u32, sub_process_tag: u32) -> bool {
+        self.service_thread_filter
+            .as_mut()
+            .is_some_and(|filter| !filter.includes_thread(pid, sub_process_tag))
+    }
+
     pub fn creation_props(&self) -> ProfileCreationProps {
         self.profile_creation_props.clone()
     }
@@ -683,7 +822,13 @@ impl ProfileContext {
         self.profile.add_marker(thread.handle, timing, marker)
     }
 
-    pub fn handle_header(&mut self, timestamp_raw: u64, perf_freq: u64, clock_type: u32) {
+    pub fn handle_header(
+        &mut self,
+        timestamp_raw: u64,
+        perf_freq: u64,
+        clock_type: u32,
+        pointer_size: Option<u32>,
+    ) {
         if clock_type != 1 {
             log::warn!("QPC not used as clock");
             self.event_timestamps_are_qpc = false;
@@ -697,8 +842,19 @@ impl ProfileContext {
             self.timestamp_converter = TimestampConverter {
                 reference_raw: timestamp_raw,
                 raw_to_ns_factor: 1000 * 1000 * 1000 / perf_freq,
+                offset_ns: 0,
             };
             self.seen_header = true;
+
+            if pointer_size == Some(4) && self.kernel_min != 0x8000_0000 {
+                // The trace was recorded on 32-bit Windows, e.g. an imported
+                // trace. Its kernel lives in the upper 2GB.
+                self.kernel_min = 0x8000_0000;
+                self.address_classifier = AddressClassifier {
+                    kernel_min: self.kernel_min,
+                };
+                self.arch = "x86".to_string();
+            }
         } else {
             // The header we're processing is the header of the user trace.
             // Make sure the timestamps in the two traces are comparable.
@@ -740,8 +896,17 @@ impl ProfileContext {
         parent_pid: u32,
         image_file_name: String,
         cmdline: String,
+        flags: u32,
     ) {
-        if !self.is_interesting_process(pid, Some(parent_pid), Some(&image_file_name)) {
+        let instance_start = self.check_instance_start(pid, parent_pid, &image_file_name);
+        if instance_start == InstanceStart::NotAnInstance
+            && !self.is_interesting_process(pid, Some(parent_pid), Some(&image_file_name))
+        {
+            return;
+        }
+
+        if self.processes.has(pid) {
+            // Already known, e.g. from an earlier rundown.
             return;
         }
 
@@ -770,7 +935,7 @@ impl ProfileContext {
             thread_recycler,
             jit_function_recycler,
         );
-        self.processes.add(pid, timestamp_raw, process);
+        self.add_process(pid, timestamp_raw, process, flags);
     }
 
     pub fn handle_process_start(
@@ -780,10 +945,14 @@ impl ProfileContext {
         parent_pid: u32,
         image_file_name: String,
         cmdline: String,
+        flags: u32,
     ) {
         self.processes.notify_process_created(pid, timestamp_raw);
 
-        if !self.is_interesting_process(pid, Some(parent_pid), Some(&image_file_name)) {
+        let instance_start = self.check_instance_start(pid, parent_pid, &image_file_name);
+        if instance_start == InstanceStart::NotAnInstance
+            && !self.is_interesting_process(pid, Some(parent_pid), Some(&image_file_name))
+        {
             return;
         }
 
@@ -829,6 +998,14 @@ impl ProfileContext {
             (None, None)
         };
 
+        if let InstanceStart::Restart(restart_marker) = instance_start {
+            self.profile.add_marker(
+                main_thread_handle,
+                MarkerTiming::Instant(timestamp),
+                restart_marker,
+            );
+        }
+
         let process = Process::new(
             name,
             pid,
@@ -839,10 +1016,28 @@ impl ProfileContext {
             thread_recycler,
             jit_function_recycler,
         );
+        self.add_process(pid, timestamp_raw, process, flags);
+    }
+
+    /// Adds a process from a Process/Start or DCStart event. Protected
+    /// processes get a marker which says why their samples have no stacks.
+    fn add_process(&mut self, pid: u32, timestamp_raw: u64, mut process: Process, flags: u32) {
+        if flags & PROCESS_FLAG_PROTECTED != 0 {
+            process.is_protected = true;
+            let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+            self.profile.add_marker(
+                process.main_thread_handle,
+                MarkerTiming::Instant(timestamp),
+                ProtectedProcessMarker,
+            );
+        }
         self.processes.add(pid, timestamp_raw, process);
     }
 
     pub fn handle_process_end(&mut self, timestamp_raw: u64, pid: u32) {
+        if let Some(restart_tracker) = &mut self.restart_tracker {
+            restart_tracker.handle_process_end(pid);
+        }
         let Some(process) = self.processes.get_by_pid(pid) else {
             return;
         };
@@ -874,8 +1069,11 @@ impl ProfileContext {
         tid: u32,
         pid: u32,
         mut name: Option<String>,
+        sub_process_tag: u32,
     ) {
-        if !self.is_interesting_process(pid, None, None) {
+        if !self.is_interesting_process(pid, None, None)
+            || self.is_other_service_thread(pid, sub_process_tag)
+        {
             return;
         }
 
@@ -883,6 +1081,11 @@ impl ProfileContext {
             name = None;
         }
 
+        if self.threads.get_by_tid(tid).is_some() {
+            // Already known, e.g. from an earlier rundown.
+            return;
+        }
+
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
 
         let Some(process) = self.processes.get_by_pid(pid) else {
@@ -929,11 +1132,15 @@ impl ProfileContext {
         timestamp_raw: u64,
         tid: u32,
         pid: u32,
+        creator_tid: u32,
         name: Option<String>,
+        sub_process_tag: u32,
     ) {
         self.threads.notify_thread_created(tid, timestamp_raw);
 
-        if !self.is_interesting_process(pid, None, None) {
+        if !self.is_interesting_process(pid, None, None)
+            || self.is_other_service_thread(pid, sub_process_tag)
+        {
             return;
         }
 
@@ -957,6 +1164,8 @@ impl ProfileContext {
             return;
         }
 
+        self.add_thread_create_marker(timestamp_raw, creator_tid, tid);
+
         if self.main_thread_only {
             // Ignore this thread.
             return;
@@ -1049,12 +1258,42 @@ impl ProfileContext {
         thread.name = Some(name);
     }
 
+    /// Adds a "ThreadCreate" marker to the creating thread. Its stack arrives in
+    /// a later StackWalk event, if ThreadCreate stacks are recorded.
+    fn add_thread_create_marker(&mut self, timestamp_raw: u64, creator_tid: u32, tid: u32) {
+        if creator_tid == tid {
+            return;
+        }
+        let Some(creator_handle) = self.thread_handle_at_time(creator_tid, timestamp_raw) else {
+            return;
+        };
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let marker = ThreadLifecycleMarker {
+            name: self.profile.handle_for_string("ThreadCreate"),
+            tid,
+        };
+        let marker_handle =
+            self.profile
+                .add_marker(creator_handle, MarkerTiming::Instant(timestamp), marker);
+        if let Some(creator) = self.threads.get_by_tid(creator_tid) {
+            creator
+                .markers_with_pending_stacks
+                .push_back((timestamp_raw, marker_handle));
+        }
+    }
+
     pub fn handle_thread_end(&mut self, timestamp_raw: u64, pid: u32, tid: u32) {
         let Some(thread) = self.threads.get_by_tid(tid) else {
             return;
         };
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
         self.profile.set_thread_end_time(thread.handle, timestamp);
+        let marker = ThreadLifecycleMarker {
+            name: self.profile.handle_for_string("ThreadExit"),
+            tid,
+        };
+        self.profile
+            .add_marker(thread.handle, MarkerTiming::Instant(timestamp), marker);
 
         let Some(process) = self.processes.get_by_pid(pid) else {
             return;
@@ -1073,8 +1312,9 @@ impl ProfileContext {
 
     /// Attach a stack to an existing marker.
     ///
-    /// CoreCLR emits these stacks after the corresponding marker.
-    pub fn handle_coreclr_stack(
+    /// CoreCLR emits these stacks after the corresponding marker. For events from
+    /// providers enabled with --provider-with-stacks, the stack is part of the event.
+    pub fn handle_marker_stack(
         &mut self,
         timestamp_raw: u64,
         pid: u32,
@@ -1114,6 +1354,10 @@ impl ProfileContext {
         let Some(process) = self.processes.get_by_pid(pid) else {
             return;
         };
+        if process.is_protected {
+            // The sample was already added by handle_sample.
+            return;
+        }
         let Some(thread) = self.threads.get_by_tid(tid) else {
             return;
         };
@@ -1237,9 +1481,35 @@ impl ProfileContext {
             .drain(..num_samples_with_pending_stacks)
             .collect();
 
+        let num_markers_with_pending_stacks = thread
+            .markers_with_pending_stacks
+            .iter()
+            .take_while(|(marker_timestamp_raw, _)| *marker_timestamp_raw <= timestamp_raw)
+            .count();
+        let markers_with_pending_stacks: Vec<_> = thread
+            .markers_with_pending_stacks
+            .drain(..num_markers_with_pending_stacks)
+            .filter(|(marker_timestamp_raw, _)| *marker_timestamp_raw == timestamp_raw)
+            .collect();
+
         let thread_handle = thread.handle;
         let thread_label = thread.thread_label;
 
+        if !markers_with_pending_stacks.is_empty() {
+            if let Some(process) = self.processes.get_by_pid(pid) {
+                let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+                for (_, marker_handle) in markers_with_pending_stacks {
+                    process.unresolved_samples.attach_stack_to_marker(
+                        thread_handle,
+                        timestamp,
+                        timestamp_raw,
+                        user_stack_index,
+                        marker_handle,
+                    );
+                }
+            }
+        }
+
         // Use this user stack for all pending stacks from this thread.
         for sample_info in samples_with_pending_stacks {
             self.consume_sample(
@@ -1270,6 +1540,7 @@ impl ProfileContext {
             mut cpu_delta,
             has_on_cpu_sample,
             per_cpu_stuff,
+            is_background,
         } = sample_info;
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
 
@@ -1320,6 +1591,22 @@ impl ProfileContext {
         } else {
             user_stack_index
         };
+        // Put samples of background work under a separate root node, so that they
+        // can be told apart from foreground work in the call tree.
+        let background_frame = if is_background {
+            let category = self
+                .categories
+                .get(KnownCategory::Background, &mut self.profile);
+            let label = self.profile.handle_for_string("Background priority");
+            Some(self.profile.handle_for_frame_with_label(
+                thread_handle,
+                label,
+                category,
+                FrameFlags::empty(),
+            ))
+        } else {
+            None
+        };
         process.unresolved_samples.add_sample(
             thread_handle,
             timestamp,
@@ -1327,7 +1614,7 @@ impl ProfileContext {
             stack_index,
             cpu_delta,
             1,
-            None,
+            background_frame,
         );
 
         if let Some((cpu_thread_handle, cpu_delta)) = per_cpu_stuff {
@@ -1396,18 +1683,38 @@ impl ProfileContext {
             None
         };
 
-        thread
-            .samples_with_pending_stacks
-            .push_back(SampleWithPendingStack {
-                timestamp: timestamp_raw,
-                kernel_stack: None,
-                off_cpu_sample_group,
-                cpu_delta,
-                has_on_cpu_sample: true,
-                per_cpu_stuff,
-            });
-
+        let sample_info = SampleWithPendingStack {
+            timestamp: timestamp_raw,
+            kernel_stack: None,
+            off_cpu_sample_group,
+            cpu_delta,
+            has_on_cpu_sample: true,
+            per_cpu_stuff,
+            is_background: thread
+                .priority
+                .is_some_and(|priority| priority <= BACKGROUND_PRIORITY_MAX),
+        };
         self.sample_count += 1;
+
+        let pid = thread.process_id;
+        if self
+            .processes
+            .get_by_pid(pid)
+            .is_some_and(|process| process.is_protected)
+        {
+            // No stack will arrive for this sample. Add it without a stack, so
+            // that the thread's CPU usage still shows up.
+            let (thread_handle, thread_label) = (thread.handle, thread.thread_label);
+            self.consume_sample(
+                pid,
+                sample_info,
+                UnresolvedStackHandle::EMPTY,
+                thread_handle,
+                thread_label,
+            );
+        } else {
+            thread.samples_with_pending_stacks.push_back(sample_info);
+        }
     }
 
     pub fn handle_virtual_alloc_free(
@@ -1415,10 +1722,11 @@ impl ProfileContext {
         timestamp_raw: u64,
         is_free: bool,
         pid: u32,
-        _tid: u32,
+        tid: u32,
+        base_address: u64,
         region_size: u64,
-        _stringified_properties: String,
     ) {
+        let thread_handle = self.thread_handle_at_time(tid, timestamp_raw);
         let Some(process) = self.processes.get_by_pid(pid) else {
             return;
         };
@@ -1429,18 +1737,60 @@ impl ProfileContext {
         } else {
             region_size as f64
         };
-        // let op_name = if is_free {
-        //     "VirtualFree"
-        // } else {
-        //     "VirtualAlloc"
-        // };
 
         let memory_usage_counter = process.get_memory_usage_counter(&mut self.profile);
         self.profile
             .add_counter_sample(memory_usage_counter, timestamp, 0.0, 0);
         self.profile
             .add_counter_sample(memory_usage_counter, timestamp, delta_size, 1);
-        // TODO: Consider adding a marker here
+        let memory_usage = process.memory_usage.as_mut().unwrap();
+        memory_usage.value += delta_size;
+        let total_bytes = memory_usage.value as i64;
+
+        let Some(thread_handle) = thread_handle else {
+            return;
+        };
+        let op_name = if is_free {
+            "VirtualFree"
+        } else {
+            "VirtualAlloc"
+        };
+        let marker = VirtualMemoryMarker {
+            name: self.profile.handle_for_string(op_name),
+            address: self
+                .profile
+                .handle_for_string(&format!("{base_address:#x}")),
+            size_bytes: region_size,
+            total_bytes,
+        };
+        let marker_handle =
+            self.profile
+                .add_marker(thread_handle, MarkerTiming::Instant(timestamp), marker);
+        // The stack of the call, if it was recorded, arrives in a later StackWalk event.
+        if let Some(thread) = self.threads.get_by_tid(tid) {
+            thread
+                .markers_with_pending_stacks
+                .push_back((timestamp_raw, marker_handle));
+        }
+    }
+
+    /// Adds a sample to the process's "handles" counter. The counts are
+    /// polled outside of ETW, with QPC timestamps.
+    pub fn handle_handle_count(&mut self, timestamp_raw: u64, pid: u32, count: u32) {
+        if !self.event_timestamps_are_qpc {
+            return;
+        }
+        let Some(process) = self.processes.get_by_pid_and_timestamp(pid, timestamp_raw) else {
+            return;
+        };
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let profile_process = process.handle;
+        process
+            .open_handles
+            .get_or_insert_with(|| {
+                OpenHandlesCounter::for_handles(&mut self.profile, profile_process)
+            })
+            .add_sample(&mut self.profile, timestamp, u64::from(count));
     }
 
     fn lib_handle_and_category_for_image(
@@ -1462,6 +1812,8 @@ impl ProfileContext {
 
         let code_id = image_info.code_id();
         let debug_id = image_info.debug_id.unwrap_or_default();
+        // WOW64 processes have both 32-bit and 64-bit images.
+        let arch = image_info.arch.unwrap_or(self.arch.as_str()).to_owned();
         let pdb_path = image_info.pdb_path.unwrap_or_else(|| path.clone());
         let path_lower = path.to_lowercase();
         let pdb_path_lower = pdb_path.to_lowercase();
@@ -1475,13 +1827,15 @@ impl ProfileContext {
             debug_path: pdb_path,
             debug_id,
             code_id: code_id.map(|ci| ci.to_string()),
-            arch: Some(self.arch.to_owned()),
+            arch: Some(arch),
         });
 
         // attempt to categorize the library based on the path
         let known_category = if pdb_path_lower.contains(".ni.pdb") {
             KnownCategory::CoreClrR2r
-        } else if path_lower.contains("windows\\system32") || path_lower.contains("windows\\winsxs")
+        } else if path_lower.contains("windows\\system32")
+            || path_lower.contains("windows\\syswow64")
+            || path_lower.contains("windows\\winsxs")
         {
             KnownCategory::System
         } else {
@@ -1537,6 +1891,27 @@ impl ProfileContext {
         );
     }
 
+    /// Remove the mapping of an unloaded image, so that samples taken after the unload
+    /// are not attributed to it. If a different image is later loaded at the same
+    /// address, its load event adds a fresh mapping.
+    pub fn handle_image_unload(&mut self, timestamp_raw: u64, pid: u32, image_base: u64) {
+        if pid == 0 || image_base >= self.kernel_min {
+            // Kernel modules are global and rarely unloaded. Keep them mapped.
+            return;
+        }
+
+        let Some(process) = self.processes.get_by_pid(pid) else {
+            return;
+        };
+
+        process.regular_lib_mapping_ops.push(
+            timestamp_raw,
+            LibMappingOp::Remove(LibMappingRemove {
+                start_avma: image_base,
+            }),
+        );
+    }
+
     pub fn handle_vsync(&mut self, timestamp_raw: u64) {
         #[derive(Debug, Clone)]
         pub struct VSyncMarker;
@@ -1563,14 +1938,434 @@ impl ProfileContext {
             }
         }
 
-        let gpu_thread = self.gpu_thread_handle.get_or_insert_with(|| {
-            let start_timestamp = Timestamp::from_nanos_since_reference(0);
-            let gpu = self.profile.add_process("GPU", 1, start_timestamp);
-            self.profile.add_thread(gpu, 1, start_timestamp, false)
-        });
+        let gpu_thread = self.gpu_track("Vsync");
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        self.profile
+            .add_marker(gpu_thread, MarkerTiming::Instant(timestamp), VSyncMarker);
+    }
+
+    fn gpu_track(&mut self, name: &'static str) -> ThreadHandle {
+        self.pseudo_track("GPU", name)
+    }
+
+    /// Returns the track with the given name in the pseudo-process with the
+    /// given name, creating the process and the track on first use.
+    fn pseudo_track(
+        &mut self,
+        process_name: &'static str,
+        track_name: &'static str,
+    ) -> ThreadHandle {
+        if let Some(thread_handle) = self.pseudo_tracks.get(&(process_name, track_name)) {
+            return *thread_handle;
+        }
+        let start_timestamp = Timestamp::from_nanos_since_reference(0);
+        let pid = self.pseudo_processes.len() as u32 + 1;
+        let process = *self
+            .pseudo_processes
+            .entry(process_name)
+            .or_insert_with(|| self.profile.add_process(process_name, pid, start_timestamp));
+        let tid = self
+            .pseudo_tracks
+            .keys()
+            .filter(|(name, _)| *name == process_name)
+            .count() as u32
+            + 1;
+        let thread_handle = self
+            .profile
+            .add_thread(process, tid, start_timestamp, tid == 1);
+        self.profile.set_thread_name(thread_handle, track_name);
+        self.pseudo_tracks
+            .insert((process_name, track_name), thread_handle);
+        thread_handle
+    }
+
+    pub fn handle_dxgi_present_start(&mut self, timestamp_raw: u64, tid: u32) {
+        self.pending_presents.insert(tid, timestamp_raw);
+    }
+
+    /// Adds a "Present" marker on the presenting thread and on the "Present"
+    /// track, from the matching Present/Start event up to now.
+    pub fn handle_dxgi_present_stop(&mut self, timestamp_raw: u64, pid: u32, tid: u32) {
+        let Some(start_raw) = self.pending_presents.remove(&tid) else {
+            return;
+        };
+        let timing = MarkerTiming::Interval(
+            self.timestamp_converter.convert_time(start_raw),
+            self.timestamp_converter.convert_time(timestamp_raw),
+        );
+        let process_name = self
+            .processes
+            .get_by_pid_and_timestamp(pid, start_raw)
+            .map(|process| process.name.clone())
+            .unwrap_or_default();
+        let marker = PresentMarker {
+            process_name: self.profile.handle_for_string(&process_name),
+            tid,
+        };
+        if let Some(thread_handle) = self.thread_handle_at_time(tid, start_raw) {
+            self.profile
+                .add_marker(thread_handle, timing.clone(), marker.clone());
+        }
+        let present_track = self.gpu_track("Present");
+        self.profile.add_marker(present_track, timing, marker);
+    }
+
+    /// Adds an audio glitch marker on the thread which reported the glitch, if
+    /// it's in the profile, and on the "Glitches" track of the "Audio"
+    /// pseudo-process.
+    pub fn handle_audio_glitch(
+        &mut self,
+        timestamp_raw: u64,
+        tid: u32,
+        task_and_op: &str,
+        text: String,
+    ) {
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let marker = AudioGlitchMarker {
+            event: self.profile.handle_for_string(task_and_op),
+            details: self.profile.handle_for_string(&text),
+        };
+        if let Some(thread_handle) = self.thread_handle_at_time(tid, timestamp_raw) {
+            self.profile.add_marker(
+                thread_handle,
+                MarkerTiming::Instant(timestamp),
+                marker.clone(),
+            );
+        }
+        let glitch_track = self.pseudo_track("Audio", "Glitches");
         self.profile
-            .add_marker(*gpu_thread, MarkerTiming::Instant(timestamp), VSyncMarker);
+            .add_marker(glitch_track, MarkerTiming::Instant(timestamp), marker);
+    }
+
+    pub fn handle_dns_query_start(
+        &mut self,
+        timestamp_raw: u64,
+        pid: u32,
+        tid: u32,
+        host: String,
+        query_type: Option<u32>,
+    ) {
+        self.pending_dns_queries
+            .entry((pid, host))
+            .or_insert(PendingDnsQuery {
+                start_raw: timestamp_raw,
+                tid,
+                query_type,
+            });
+    }
+
+    /// Adds a "DNS query" marker on the querying thread, if it's in the
+    /// profile, and on the "DNS" track of the "Network" pseudo-process, from
+    /// the start of the query up to now.
+    pub fn handle_dns_query_end(
+        &mut self,
+        timestamp_raw: u64,
+        pid: u32,
+        host: &str,
+        status: u32,
+        results: Option<String>,
+    ) {
+        let Some(query) = self.pending_dns_queries.remove(&(pid, host.to_string())) else {
+            return;
+        };
+        let timing = MarkerTiming::Interval(
+            self.timestamp_converter.convert_time(query.start_raw),
+            self.timestamp_converter.convert_time(timestamp_raw),
+        );
+        let query_type = match query.query_type {
+            Some(query_type) => dns_query_type_name(query_type),
+            None => "".into(),
+        };
+        let marker = DnsQueryMarker {
+            host: self.profile.handle_for_string(host),
+            query_type: self.profile.handle_for_string(&query_type),
+            status,
+            results: self
+                .profile
+                .handle_for_string(results.as_deref().unwrap_or_default()),
+        };
+        if let Some(thread_handle) = self.thread_handle_at_time(query.tid, query.start_raw) {
+            self.profile
+                .add_marker(thread_handle, timing.clone(), marker.clone());
+        }
+        let dns_track = self.pseudo_track("Network", "DNS");
+        self.profile.add_marker(dns_track, timing, marker);
+    }
+
+    /// Tracks a WinHTTP or WinINet request from the first event which mentions
+    /// its handle up to the event which closes the handle, and then adds an
+    /// "HTTP request" marker for it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn handle_http_event(
+        &mut self,
+        timestamp_raw: u64,
+        pid: u32,
+        tid: u32,
+        request: u64,
+        url: Option<String>,
+        task_and_op: &str,
+        is_end: bool,
+        is_failure: bool,
+    ) {
+        let pending = self
+            .pending_http_requests
+            .entry((pid, request))
+            .or_insert_with(|| PendingHttpRequest {
+                start_raw: timestamp_raw,
+                end_raw: timestamp_raw,
+                tid,
+                url: None,
+                failure: None,
+            });
+        pending.end_raw = timestamp_raw;
+        if pending.url.is_none() {
+            pending.url = url;
+        }
+        if is_failure && pending.failure.is_none() {
+            pending.failure = Some(task_and_op.to_string());
+        }
+        if is_end {
+            let request = self.pending_http_requests.remove(&(pid, request)).unwrap();
+            self.add_http_request_marker(request);
+        }
+    }
+
+    fn add_http_request_marker(&mut self, request: PendingHttpRequest) {
+        let timing = MarkerTiming::Interval(
+            self.timestamp_converter.convert_time(request.start_raw),
+            self.timestamp_converter.convert_time(request.end_raw),
+        );
+        let marker = HttpRequestMarker {
+            url: self
+                .profile
+                .handle_for_string(request.url.as_deref().unwrap_or_default()),
+            failed: request.failure.is_some(),
+            failure: self
+                .profile
+                .handle_for_string(request.failure.as_deref().unwrap_or_default()),
+        };
+        if let Some(thread_handle) = self.thread_handle_at_time(request.tid, request.start_raw) {
+            self.profile
+                .add_marker(thread_handle, timing.clone(), marker.clone());
+        }
+        let http_track = self.pseudo_track("Network", "HTTP");
+        self.profile.add_marker(http_track, timing, marker);
+    }
+
+    pub fn handle_registry_kcb_create(&mut self, key_handle: u64, key_name: String) {
+        if key_handle != 0 && !key_name.is_empty() {
+            self.registry_key_paths.insert(key_handle, key_name);
+        }
+    }
+
+    pub fn handle_registry_kcb_delete(&mut self, key_handle: u64) {
+        self.registry_key_paths.remove(&key_handle);
+    }
+
+    /// Adds a registry marker on the thread which did the operation, from the
+    /// start of the operation up to now.
+    #[allow(clippy::too_many_arguments)]
+    pub fn handle_registry_operation(
+        &mut self,
+        timestamp_raw: u64,
+        start_timestamp_raw: Option<u64>,
+        tid: u32,
+        operation: &str,
+        key_handle: u64,
+        key_name: &str,
+        status: u32,
+    ) {
+        let start_timestamp_raw = start_timestamp_raw
+            .filter(|start| *start != 0 && *start <= timestamp_raw)
+            .unwrap_or(timestamp_raw);
+        let Some(thread_handle) = self.thread_handle_at_time(tid, start_timestamp_raw) else {
+            return;
+        };
+        let base_path = self.registry_key_paths.get(&key_handle).map(String::as_str);
+        let key = full_key_path(base_path, key_name);
+        let timing = MarkerTiming::Interval(
+            self.timestamp_converter.convert_time(start_timestamp_raw),
+            self.timestamp_converter.convert_time(timestamp_raw),
+        );
+        let marker = RegistryMarker {
+            operation: self.profile.handle_for_string(operation),
+            key: self.profile.handle_for_string(&key),
+            status,
+        };
+        self.profile.add_marker(thread_handle, timing, marker);
+    }
+
+    pub fn handle_rpc_call_start(
+        &mut self,
+        timestamp_raw: u64,
+        tid: u32,
+        call: CallEvent,
+        details: CallDetails,
+    ) {
+        self.pending_rpc_calls
+            .entry((tid, call.runtime, call.side))
+            .or_default()
+            .push((timestamp_raw, details));
+    }
+
+    /// Adds a call marker on the thread which made or handled the call, if
+    /// it's in the profile, and on the "Client calls" or "Server calls" track
+    /// of the "RPC" pseudo-process, from the start of the call up to now.
+    pub fn handle_rpc_call_stop(
+        &mut self,
+        timestamp_raw: u64,
+        tid: u32,
+        call: CallEvent,
+        status: u32,
+    ) {
+        let Some(calls) = self
+            .pending_rpc_calls
+            .get_mut(&(tid, call.runtime, call.side))
+        else {
+            return;
+        };
+        let Some((start_raw, details)) = calls.pop() else {
+            return;
+        };
+        let timing = MarkerTiming::Interval(
+            self.timestamp_converter.convert_time(start_raw),
+            self.timestamp_converter.convert_time(timestamp_raw),
+        );
+        let name = format!("{} {} call", call.runtime.name(), call.side.name());
+        let marker = RpcCallMarker {
+            name: self.profile.handle_for_string(&name),
+            interface: self.profile.handle_for_string(
+                &details
+                    .interface
+                    .map(|interface| interface.to_string())
+                    .unwrap_or_default(),
+            ),
+            method: self.profile.handle_for_string(
+                &details
+                    .method
+                    .map(|method| method.to_string())
+                    .unwrap_or_default(),
+            ),
+            endpoint: self.profile.handle_for_string(&details.endpoint),
+            status,
+            correlation_id: self.profile.handle_for_string(
+                &details
+                    .correlation_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+            ),
+        };
+        if let Some(thread_handle) = self.thread_handle_at_time(tid, start_raw) {
+            self.profile
+                .add_marker(thread_handle, timing.clone(), marker.clone());
+        }
+        let track_name = match call.side {
+            CallSide::Client => "Client calls",
+            CallSide::Server => "Server calls",
+        };
+        let call_track = self.pseudo_track("RPC", track_name);
+        self.profile.add_marker(call_track, timing, marker);
+    }
+
+    /// Starts or ends a "System sleep" span on the "Power" track of the
+    /// "System" pseudo-process.
+    pub fn handle_system_sleep_edge(
+        &mut self,
+        timestamp_raw: u64,
+        edge: SuspendEdge,
+        task_and_op: &str,
+    ) {
+        match edge {
+            SuspendEdge::Begin => {
+                self.pending_system_sleep
+                    .get_or_insert_with(|| (timestamp_raw, task_and_op.to_string()));
+            }
+            SuspendEdge::End => {
+                let Some((start_timestamp_raw, reason)) = self.pending_system_sleep.take() else {
+                    return;
+                };
+                let power_track = self.pseudo_track("System", "Power");
+                self.add_suspend_marker(
+                    power_track,
+                    start_timestamp_raw,
+                    timestamp_raw,
+                    SuspendKind::SystemSleep,
+                    &reason,
+                );
+            }
+        }
+    }
+
+    /// Starts or ends a "Process suspended" span on the main thread of a
+    /// frozen process.
+    pub fn handle_process_freeze_edge(&mut self, timestamp_raw: u64, pid: u32, edge: SuspendEdge) {
+        match edge {
+            SuspendEdge::Begin => {
+                self.pending_process_freezes
+                    .entry(pid)
+                    .or_insert(timestamp_raw);
+            }
+            SuspendEdge::End => {
+                let Some(start_timestamp_raw) = self.pending_process_freezes.remove(&pid) else {
+                    return;
+                };
+                let Some(process) = self
+                    .processes
+                    .get_by_pid_and_timestamp(pid, start_timestamp_raw)
+                else {
+                    return;
+                };
+                let main_thread_handle = process.main_thread_handle;
+                self.add_suspend_marker(
+                    main_thread_handle,
+                    start_timestamp_raw,
+                    timestamp_raw,
+                    SuspendKind::ProcessSuspended,
+                    "ProcessFreeze",
+                );
+            }
+        }
+    }
+
+    fn add_suspend_marker(
+        &mut self,
+        thread_handle: ThreadHandle,
+        start_timestamp_raw: u64,
+        end_timestamp_raw: u64,
+        kind: SuspendKind,
+        reason: &str,
+    ) {
+        let start_timestamp = self.timestamp_converter.convert_time(start_timestamp_raw);
+        let end_timestamp = self.timestamp_converter.convert_time(end_timestamp_raw);
+        add_suspend_span(
+            &mut self.profile,
+            thread_handle,
+            start_timestamp,
+            end_timestamp,
+            kind,
+            reason,
+        );
+    }
+
+    /// Adds an instant marker for a Dwm-Core event to the "DWM" track.
+    pub fn handle_dwm_event(&mut self, timestamp_raw: u64, task_and_op: &str, text: String) {
+        let dwm_track = self.gpu_track("DWM");
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        let name = self.profile.handle_for_string(task_and_op);
+        let description = self.profile.handle_for_string(&text);
+        self.profile.add_marker(
+            dwm_track,
+            MarkerTiming::Instant(timestamp),
+            FreeformMarker(name, description),
+        );
+    }
+
+    /// Called for ReadyThread events, when a thread becomes runnable.
+    pub fn handle_ready_thread(&mut self, timestamp_raw: u64, tid: u32) {
+        if let Some(sched_latency) = &mut self.sched_latency {
+            let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+            sched_latency.handle_wakeup(tid, timestamp);
+        }
     }
 
     pub fn handle_cswitch(
@@ -1580,6 +2375,7 @@ impl ProfileContext {
         new_tid: u32,
         cpu_index: u32,
         wait_reason: i8,
+        new_thread_priority: i8,
     ) {
         // CSwitch events may or may not have stacks.
         // If they have stacks, the stack will be the stack of new_tid.
@@ -1615,6 +2411,7 @@ impl ProfileContext {
         }
 
         if let Some(new_thread) = self.threads.get_by_tid(new_tid) {
+            new_thread.priority = Some(new_thread_priority);
             let off_cpu_sample_group = self
                 .context_switch_handler
                 .handle_switch_in(timestamp_raw, &mut new_thread.context_switch_data);
@@ -1632,8 +2429,20 @@ impl ProfileContext {
                         cpu_delta,
                         has_on_cpu_sample: false,
                         per_cpu_stuff: None,
+                        is_background: false,
                     });
             }
+            if let Some(sched_latency) = &mut self.sched_latency {
+                if let Some(process) = self.processes.get_by_pid(new_thread.process_id) {
+                    sched_latency.handle_switch_in(
+                        new_tid,
+                        self.timestamp_converter.convert_time(timestamp_raw),
+                        process.handle,
+                        new_thread.handle,
+                        &mut self.profile,
+                    );
+                }
+            }
             if let Some(cpus) = &mut self.cpus {
                 let combined_thread = cpus.combined_thread_handle();
                 let idle_frame_label = cpus.idle_frame_label();
@@ -1971,31 +2780,78 @@ impl ProfileContext {
         }
     }
 
+    pub fn is_frame_marker_event(&self, event_name: &str) -> bool {
+        self.profile_creation_props
+            .frame_marker
+            .as_deref()
+            .is_some_and(|frame_marker| event_name.starts_with(frame_marker))
+    }
+
+    pub fn handle_frame_marker_event(&mut self, timestamp_raw: u64, pid: u32, tid: u32) {
+        if !self.is_in_time_range(timestamp_raw) {
+            return;
+        }
+        let Some(thread_handle) = self.thread_handle_at_time(tid, timestamp_raw) else {
+            return;
+        };
+        let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
+        if let Some(process) = self.processes.get_by_pid(pid) {
+            process
+                .frame_boundaries
+                .entry(thread_handle)
+                .or_default()
+                .push(timestamp);
+        }
+    }
+
     pub fn handle_unknown_event(
         &mut self,
         timestamp_raw: u64,
         tid: u32,
         task_and_op: &str,
         stringified_properties: String,
-    ) {
-        if !self.profile_creation_props.unknown_event_markers {
-            return;
+        has_stack: bool,
+    ) -> Option<(ThreadHandle, MarkerHandle)> {
+        // Events with stacks only show up if the user asked for them with
+        // --provider-with-stacks, so we always turn those into markers.
+        if !self.profile_creation_props.unknown_event_markers && !has_stack {
+            return None;
         }
 
-        let Some(thread_handle) = self.thread_handle_at_time(tid, timestamp_raw) else {
-            return;
-        };
+        self.add_event_marker(timestamp_raw, tid, task_and_op, &stringified_properties)
+    }
+
+    /// Adds a marker for a DiskIo or FileIo kernel event. These are only in the
+    /// trace if the io or full preset was used.
+    pub fn handle_io_event(
+        &mut self,
+        timestamp_raw: u64,
+        tid: u32,
+        task_and_op: &str,
+        stringified_properties: String,
+    ) {
+        self.add_event_marker(timestamp_raw, tid, task_and_op, &stringified_properties);
+    }
+
+    fn add_event_marker(
+        &mut self,
+        timestamp_raw: u64,
+        tid: u32,
+        task_and_op: &str,
+        stringified_properties: &str,
+    ) -> Option<(ThreadHandle, MarkerHandle)> {
+        let thread_handle = self.thread_handle_at_time(tid, timestamp_raw)?;
 
         let timestamp = self.timestamp_converter.convert_time(timestamp_raw);
         let timing = MarkerTiming::Instant(timestamp);
         let marker_name = self.profile.handle_for_string(task_and_op);
-        let description = self.profile.handle_for_string(&stringified_properties);
-        self.profile.add_marker(
+        let description = self.profile.handle_for_string(stringified_properties);
+        let marker_handle = self.profile.add_marker(
             thread_handle,
             timing,
             FreeformMarker(marker_name, description),
         );
-        //println!("unhandled {}", s.name())
+        Some((thread_handle, marker_handle))
     }
 
     pub fn is_in_time_range(&self, ts_raw: u64) -> bool {
@@ -2012,6 +2868,17 @@ impl ProfileContext {
     }
 
     pub fn finish(mut self) -> Profile {
+        // Requests whose handle was still open at the end of the recording
+        // end at their last event.
+        let mut open_http_requests: Vec<PendingHttpRequest> =
+            std::mem::take(&mut self.pending_http_requests)
+                .into_values()
+                .collect();
+        open_http_requests.sort_by_key(|request| request.start_raw);
+        for request in open_http_requests {
+            self.add_http_request_marker(request);
+        }
+
         // Push queued samples into the profile.
         // We queue them so that we can get symbolicated JIT function names. To get symbolicated JIT function names,
         // we have to call profile.add_sample after we call profile.set_lib_symbol_table, and we don't have the
@@ -2038,6 +2905,7 @@ impl ProfileContext {
                 kernel_category.into(),
                 &mut stack_frame_scratch_buf,
                 &self.unresolved_stacks,
+                self.profile_creation_props.marker_stacks_from_samples,
             )
         }
 
@@ -2059,6 +2927,9 @@ pub struct PeInfo {
     pub image_timestamp: Option<u32>,
     pub debug_id: Option<DebugId>,
     pub pdb_path: Option<String>,
+    /// The architecture from the image's machine type, in the names we use
+    /// for `ProfileContext::arch`.
+    pub arch: Option<&'static str>,
 }
 
 impl PeInfo {
@@ -2069,6 +2940,7 @@ impl PeInfo {
             image_timestamp: None,
             debug_id: None,
             pdb_path: None,
+            arch: None,
         }
     }
 
@@ -2098,6 +2970,12 @@ impl PeInfo {
             .file_header()
             .time_date_stamp
             .get(object::LittleEndian);
+        let arch = match header.file_header().machine.get(object::LittleEndian) {
+            object::pe::IMAGE_FILE_MACHINE_I386 => Some("x86"),
+            object::pe::IMAGE_FILE_MACHINE_AMD64 => Some("x86_64"),
+            object::pe::IMAGE_FILE_MACHINE_ARM64 => Some("arm64"),
+            _ => None,
+        };
         use object::read::pe::ImageOptionalHeader;
         let image_size = header.optional_header().size_of_image();
         let image_checksum = header.optional_header().check_sum();
@@ -2117,6 +2995,7 @@ impl PeInfo {
             image_timestamp: Some(image_timestamp),
             debug_id,
             pdb_path,
+            arch,
         }
     }
 
@@ -2150,6 +3029,9 @@ impl PeInfo {
         if self.pdb_path.is_none() {
             self.pdb_path = pe_info.pdb_path;
         }
+        if self.arch.is_none() {
+            self.arch = pe_info.arch;
+        }
     }
 
     pub fn code_id(&self) -> Option<wholesym::CodeId> {
@@ -2222,6 +3104,452 @@ impl StaticSchemaMarker for FreeformMarker {
     }
 }
 
+/// A DXGI present, from the start to the end of the Present call.
+#[derive(Debug, Clone)]
+pub struct PresentMarker {
+    process_name: StringHandle,
+    tid: u32,
+}
+
+impl StaticSchemaMarker for PresentMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Present";
+
+    const CATEGORY: Category<'static> = Category("Graphics", CategoryColor::Red);
+    const LOCATIONS: MarkerLocations = MarkerLocations::MARKER_CHART
+        .union(MarkerLocations::MARKER_TABLE)
+        .union(MarkerLocations::TIMELINE_OVERVIEW);
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.process}");
+    const TOOLTIP_LABEL: Option<&'static str> =
+        Some("Present - {marker.data.process} (tid {marker.data.tid})");
+    const TABLE_LABEL: Option<&'static str> =
+        Some("Present - {marker.data.process} (tid {marker.data.tid})");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "process",
+            label: "Process",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "tid",
+            label: "Thread ID",
+            format: MarkerFieldFormat::Integer,
+            flags: MarkerFieldFlags::empty(),
+        },
+    ];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.handle_for_string("Present")
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        self.process_name
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        self.tid.into()
+    }
+}
+
+/// The "Protected" bit in the Flags of the Process/Start and DCStart events.
+const PROCESS_FLAG_PROTECTED: u32 = 0x4;
+
+/// Says that a process is protected (PPL), so that its tracks only show CPU
+/// usage.
+#[derive(Debug, Clone)]
+pub struct ProtectedProcessMarker;
+
+impl StaticSchemaMarker for ProtectedProcessMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "ProtectedProcess";
+
+    const DESCRIPTION: Option<&'static str> = Some(
+        "Windows doesn't walk the stacks of protected processes, such as anti-malware services. Their samples only show CPU usage.",
+    );
+    const LOCATIONS: MarkerLocations = MarkerLocations::MARKER_CHART
+        .union(MarkerLocations::MARKER_TABLE)
+        .union(MarkerLocations::TIMELINE_OVERVIEW);
+
+    const CHART_LABEL: Option<&'static str> = Some("Protected process");
+    const TOOLTIP_LABEL: Option<&'static str> =
+        Some("Protected process - stacks are not available, only CPU usage");
+    const TABLE_LABEL: Option<&'static str> =
+        Some("Protected process - stacks are not available, only CPU usage");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.handle_for_string("Protected process")
+    }
+
+    fn string_field_value(&self, _field_index: u32) -> StringHandle {
+        unreachable!()
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+/// A glitch or underrun reported by the audio engine.
+#[derive(Debug, Clone)]
+pub struct AudioGlitchMarker {
+    event: StringHandle,
+    details: StringHandle,
+}
+
+impl StaticSchemaMarker for AudioGlitchMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "AudioGlitch";
+
+    const CATEGORY: Category<'static> = Category("Audio", CategoryColor::Orange);
+    const DESCRIPTION: Option<&'static str> = Some(
+        "Audio which didn't reach the audio device in time, e.g. because of a buffer underrun.",
+    );
+    const LOCATIONS: MarkerLocations = MarkerLocations::MARKER_CHART
+        .union(MarkerLocations::MARKER_TABLE)
+        .union(MarkerLocations::TIMELINE_OVERVIEW);
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.event}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("Audio glitch - {marker.data.event}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.data.event} - {marker.data.details}");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "event",
+            label: "Event",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "details",
+            label: "Details",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+    ];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.handle_for_string("Audio glitch")
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.event,
+            1 => self.details,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PendingDnsQuery {
+    start_raw: u64,
+    tid: u32,
+    query_type: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+struct PendingHttpRequest {
+    start_raw: u64,
+    /// The timestamp of the last event of the request.
+    end_raw: u64,
+    tid: u32,
+    url: Option<String>,
+    /// The event which reported a failure, if any.
+    failure: Option<String>,
+}
+
+/// A DNS query made through the DNS client service, from the query start to
+/// its completion.
+#[derive(Debug, Clone)]
+pub struct DnsQueryMarker {
+    host: StringHandle,
+    query_type: StringHandle,
+    status: u32,
+    results: StringHandle,
+}
+
+impl StaticSchemaMarker for DnsQueryMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "DnsQuery";
+
+    const CATEGORY: Category<'static> = Category("Network", CategoryColor::LightBlue);
+    const DESCRIPTION: Option<&'static str> =
+        Some("A host name lookup. A non-zero status means that the lookup failed.");
+    const LOCATIONS: MarkerLocations = MarkerLocations::MARKER_CHART
+        .union(MarkerLocations::MARKER_TABLE)
+        .union(MarkerLocations::TIMELINE_OVERVIEW);
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.host}");
+    const TOOLTIP_LABEL: Option<&'static str> =
+        Some("{marker.name} - {marker.data.host} {marker.data.queryType}");
+    const TABLE_LABEL: Option<&'static str> =
+        Some("{marker.data.host} {marker.data.queryType} - status {marker.data.status}");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "host",
+            label: "Host",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "queryType",
+            label: "Record type",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "status",
+            label: "Status",
+            format: MarkerFieldFormat::Integer,
+            flags: MarkerFieldFlags::empty(),
+        },
+        StaticSchemaMarkerField {
+            key: "results",
+            label: "Results",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+    ];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        if self.status == 0 {
+            profile.handle_for_string("DNS query")
+        } else {
+            profile.handle_for_string("DNS query failed")
+        }
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.host,
+            1 => self.query_type,
+            3 => self.results,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, field_index: u32) -> f64 {
+        match field_index {
+            2 => self.status.into(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A WinHTTP or WinINet request, from the first event for its handle until
+/// the handle was closed.
+#[derive(Debug, Clone)]
+pub struct HttpRequestMarker {
+    url: StringHandle,
+    failed: bool,
+    failure: StringHandle,
+}
+
+impl StaticSchemaMarker for HttpRequestMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "HttpRequest";
+
+    const CATEGORY: Category<'static> = Category("Network", CategoryColor::LightBlue);
+    const DESCRIPTION: Option<&'static str> =
+        Some("An HTTP request made through WinHTTP or WinINet.");
+    const LOCATIONS: MarkerLocations = MarkerLocations::MARKER_CHART
+        .union(MarkerLocations::MARKER_TABLE)
+        .union(MarkerLocations::TIMELINE_OVERVIEW);
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.url}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("{marker.name} - {marker.data.url}");
+    const TABLE_LABEL: Option<&'static str> = Some("{marker.data.url} {marker.data.failure}");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "url",
+            label: "URL",
+            format: MarkerFieldFormat::Url,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "failure",
+            label: "Failure",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+    ];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        if self.failed {
+            profile.handle_for_string("HTTP request failed")
+        } else {
+            profile.handle_for_string("HTTP request")
+        }
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.url,
+            1 => self.failure,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, _field_index: u32) -> f64 {
+        unreachable!()
+    }
+}
+
+/// An RPC or COM call, on the client or on the server side.
+#[derive(Debug, Clone)]
+pub struct RpcCallMarker {
+    name: StringHandle,
+    interface: StringHandle,
+    method: StringHandle,
+    endpoint: StringHandle,
+    status: u32,
+    correlation_id: StringHandle,
+}
+
+impl StaticSchemaMarker for RpcCallMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "RpcCall";
+
+    const CATEGORY: Category<'static> = Category("RPC", CategoryColor::Purple);
+    const DESCRIPTION: Option<&'static str> = Some(
+        "An RPC or COM call. The client and the server side of a call which crosses threads or processes have the same correlation ID.",
+    );
+    const LOCATIONS: MarkerLocations = MarkerLocations::MARKER_CHART
+        .union(MarkerLocations::MARKER_TABLE)
+        .union(MarkerLocations::TIMELINE_OVERVIEW);
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.interface}");
+    const TOOLTIP_LABEL: Option<&'static str> =
+        Some("{marker.name} - {marker.data.interface} method {marker.data.method}");
+    const TABLE_LABEL: Option<&'static str> = Some(
+        "{marker.data.interface} method {marker.data.method} {marker.data.endpoint} - status {marker.data.status}",
+    );
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "interface",
+            label: "Interface",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "method",
+            label: "Method",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "endpoint",
+            label: "Endpoint",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "status",
+            label: "Status",
+            format: MarkerFieldFormat::Integer,
+            flags: MarkerFieldFlags::empty(),
+        },
+        StaticSchemaMarkerField {
+            key: "correlationId",
+            label: "Correlation ID",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+    ];
+
+    fn name(&self, _profile: &mut Profile) -> StringHandle {
+        self.name
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.interface,
+            1 => self.method,
+            2 => self.endpoint,
+            4 => self.correlation_id,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, field_index: u32) -> f64 {
+        match field_index {
+            3 => self.status.into(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A registry operation, from the kernel's Registry events.
+#[derive(Debug, Clone)]
+pub struct RegistryMarker {
+    operation: StringHandle,
+    key: StringHandle,
+    status: u32,
+}
+
+impl StaticSchemaMarker for RegistryMarker {
+    const UNIQUE_MARKER_TYPE_NAME: &'static str = "Registry";
+
+    const CATEGORY: Category<'static> = Category("Registry", CategoryColor::Brown);
+    const DESCRIPTION: Option<&'static str> = Some(
+        "A registry operation. A non-zero status is the NTSTATUS of a failed operation, e.g. 0xC0000034 if the key or value doesn't exist.",
+    );
+    const LOCATIONS: MarkerLocations = MarkerLocations::MARKER_CHART
+        .union(MarkerLocations::MARKER_TABLE)
+        .union(MarkerLocations::TIMELINE_OVERVIEW);
+
+    const CHART_LABEL: Option<&'static str> = Some("{marker.data.operation}");
+    const TOOLTIP_LABEL: Option<&'static str> = Some("{marker.data.operation} - {marker.data.key}");
+    const TABLE_LABEL: Option<&'static str> =
+        Some("{marker.data.operation} {marker.data.key} - status {marker.data.status}");
+
+    const FIELDS: &'static [StaticSchemaMarkerField] = &[
+        StaticSchemaMarkerField {
+            key: "operation",
+            label: "Operation",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "key",
+            label: "Key",
+            format: MarkerFieldFormat::String,
+            flags: MarkerFieldFlags::SEARCHABLE,
+        },
+        StaticSchemaMarkerField {
+            key: "status",
+            label: "Status",
+            format: MarkerFieldFormat::Integer,
+            flags: MarkerFieldFlags::empty(),
+        },
+    ];
+
+    fn name(&self, profile: &mut Profile) -> StringHandle {
+        profile.handle_for_string("Registry")
+    }
+
+    fn string_field_value(&self, field_index: u32) -> StringHandle {
+        match field_index {
+            0 => self.operation,
+            1 => self.key,
+            _ => unreachable!(),
+        }
+    }
+
+    fn number_field_value(&self, field_index: u32) -> f64 {
+        match field_index {
+            2 => self.status.into(),
+            _ => unreachable!(),
+        }
+    }
+}
+
 fn extract_filename(path: &str) -> &str {
     match path.rsplit_once(['/', '\\']) {
         Some((_base, file_name)) => file_name,
@@ -1,13 +1,18 @@
 use std::os::windows::process::ExitStatusExt;
 use std::process::ExitStatus;
+use std::time::Duration;
 
 use fxprof_processed_profile::{Profile, ReferenceTimestamp, SamplingInterval};
 
 use super::etw_gecko;
+use super::handle_counts::HandleCountPoller;
 use super::profile_context::ProfileContext;
+use super::service::{resolve_service, ServiceThreadFilter};
+use super::user_mode_sampler;
 use crate::shared::ctrl_c::CtrlC;
 use crate::shared::included_processes::IncludedProcesses;
 use crate::shared::prop_types::{ProfileCreationProps, RecordingMode, RecordingProps};
+use crate::shared::restarts::RestartTracker;
 use crate::windows::elevated_helper::ElevatedHelperSession;
 
 // Hello intrepid explorer! You may be in this code because you'd like to extend something,
@@ -41,6 +46,55 @@ pub fn run(
     recording_props: RecordingProps,
     profile_creation_props: ProfileCreationProps,
 ) -> Result<(Profile, ExitStatus), i32> {
+    if let RecordingMode::Multiple { .. } = recording_mode {
+        eprintln!(
+            "Error: Recording several processes at once is currently only supported on Linux."
+        );
+        return Err(1);
+    }
+    if profile_creation_props.utc_timestamps {
+        eprintln!("--timestamps utc is currently not supported on Windows, ignoring it.");
+    }
+    // A service is recorded like an existing process. If it shares its
+    // process with other services, their threads are left out.
+    let mut service_thread_filter = None;
+    let recording_mode = match recording_mode {
+        RecordingMode::Service(service_props) => {
+            let service = match resolve_service(&service_props) {
+                Ok(service) => service,
+                Err(err) => {
+                    eprintln!("Error: {err}");
+                    return Err(1);
+                }
+            };
+            eprintln!(
+                "Service {} runs in the process with pid {}.",
+                service_props.name, service.pid
+            );
+            if service.shares_process {
+                eprintln!("The process is shared with other services, only the threads of {} and untagged threads are recorded.", service_props.name);
+                service_thread_filter =
+                    Some(ServiceThreadFilter::new(service.pid, service_props.name));
+            }
+            RecordingMode::Pid(service.pid)
+        }
+        recording_mode => recording_mode,
+    };
+    if recording_props.user_mode_sampler {
+        if !user_mode_sampler::supports_recording_mode(&recording_mode) {
+            eprintln!(
+                "--user-mode-sampler can only record a launched command or an existing process (--pid)."
+            );
+            return Err(1);
+        }
+        return user_mode_sampler::run(recording_mode, recording_props, profile_creation_props);
+    }
+    if !recording_props.thread_rate_overrides.is_empty() {
+        // ETW samples all threads with one system-wide timer.
+        eprintln!("Error: --rate-override needs --user-mode-sampler on Windows.");
+        return Err(1);
+    }
+
     let timebase = std::time::SystemTime::now();
     let timebase = ReferenceTimestamp::from_system_time(timebase);
 
@@ -51,35 +105,75 @@ pub fn run(
     );
 
     // Start xperf.
-    let mut elevated_helper = ElevatedHelperSession::new(recording_props.output_file.clone())
-        .unwrap_or_else(|e| panic!("Couldn't start elevated helper process: {e:?}"));
+    let mut elevated_helper = match ElevatedHelperSession::new(recording_props.output_file.clone())
+    {
+        Ok(elevated_helper) => elevated_helper,
+        Err(e) if user_mode_sampler::supports_recording_mode(&recording_mode) => {
+            // Without administrator rights we can't use ETW.
+            eprintln!("Couldn't start elevated helper process: {e}");
+            eprintln!("Falling back to the user-mode sampler, which doesn't need administrator rights but is less accurate.");
+            return user_mode_sampler::run(recording_mode, recording_props, profile_creation_props);
+        }
+        Err(e) => panic!("Couldn't start elevated helper process: {e:?}"),
+    };
+    if recording_props.interval_jitter > 0.0 {
+        // ETW samples all CPUs with one system-wide timer, which can't be
+        // changed while the trace is running.
+        eprintln!(
+            "--interval-jitter is only supported by the user-mode sampler on Windows, ignoring it."
+        );
+    }
     elevated_helper
         .start_xperf(&recording_props, &profile_creation_props, &recording_mode)
         .unwrap();
 
+    // Handle counts are only polled for the processes whose pid we know.
+    let handle_count_poller = HandleCountPoller::start();
+
+    // All processes are in the trace, so the restarts are found when it's
+    // converted.
+    let restart_tracker = match &recording_mode {
+        RecordingMode::Pid(pid) if recording_props.follow_restarts => {
+            Some(RestartTracker::for_pid(*pid))
+        }
+        RecordingMode::WaitForProcess(name) if recording_props.follow_restarts => {
+            Some(RestartTracker::for_name(name))
+        }
+        _ => None,
+    };
+
     let included_processes = match recording_mode {
         RecordingMode::All => {
             let ctrl_c_receiver = CtrlC::observe_oneshot();
             eprintln!("Profiling all processes...");
             eprintln!("Press Ctrl+C to stop.");
-            // TODO: Respect recording_props.time_limit, if specified
-            // Wait for Ctrl+C.
-            let _ = ctrl_c_receiver.blocking_recv();
+            wait_for_ctrl_c_or_time_limit(ctrl_c_receiver, recording_props.time_limit);
             None
         }
         RecordingMode::Pid(pid) => {
+            handle_count_poller.add_pid(pid);
             let ctrl_c_receiver = CtrlC::observe_oneshot();
             // TODO: check that process with this pid exists
             eprintln!("Profiling process with pid {pid}...");
             eprintln!("Press Ctrl+C to stop.");
-            // TODO: Respect recording_props.time_limit, if specified
-            // Wait for Ctrl+C.
-            let _ = ctrl_c_receiver.blocking_recv();
+            wait_for_ctrl_c_or_time_limit(ctrl_c_receiver, recording_props.time_limit);
             Some(IncludedProcesses {
                 name_substrings: Vec::new(),
                 pids: vec![pid],
             })
         }
+        RecordingMode::WaitForProcess(name) => {
+            // The kernel session sees every process start, so processes with a matching
+            // name are recorded from their first instructions.
+            let ctrl_c_receiver = CtrlC::observe_oneshot();
+            eprintln!("Recording processes named \"{name}\" once they start...");
+            eprintln!("Press Ctrl+C to stop.");
+            wait_for_ctrl_c_or_time_limit(ctrl_c_receiver, recording_props.time_limit);
+            Some(IncludedProcesses {
+                name_substrings: vec![name],
+                pids: Vec::new(),
+            })
+        }
         RecordingMode::Launch(process_launch_props) => {
             // Ignore Ctrl+C while the subcommand is running. The signal still reaches the process
             // under observation while we continue to record it.
@@ -93,6 +187,7 @@ pub fn run(
                 let mut child = child.spawn().unwrap();
 
                 pids.push(child.id());
+                handle_count_poller.add_pid(child.id());
 
                 // Wait for the child to exit.
                 //
@@ -120,11 +215,14 @@ pub fn run(
                 pids,
             })
         }
+        RecordingMode::Multiple { .. } => unreachable!("rejected at the start of run()"),
+        RecordingMode::Service(_) => unreachable!("resolved at the start of run()"),
     };
 
+    let handle_count_samples = handle_count_poller.stop();
     eprintln!("Stopping xperf...");
 
-    let (kernel_output_file, user_output_file) = elevated_helper
+    let (kernel_output_file, user_output_file, rundown_output_files) = elevated_helper
         .stop_xperf()
         .expect("Should have produced a merged ETL file");
 
@@ -144,11 +242,21 @@ pub fn run(
         profile_creation_props,
         None,
     );
-    let extra_etls = match &user_output_file {
+    if let Some(service_thread_filter) = service_thread_filter {
+        context.set_service_thread_filter(service_thread_filter);
+    }
+    if let Some(restart_tracker) = restart_tracker {
+        context.set_restart_tracker(restart_tracker);
+    }
+    let mut extra_etls = match &user_output_file {
         Some(user_etl) => vec![user_etl.clone()],
         None => Vec::new(),
     };
-    etw_gecko::process_etl_files(&mut context, &kernel_output_file, &extra_etls);
+    extra_etls.extend(rundown_output_files.iter().cloned());
+    etw_gecko::process_etl_files(&mut context, &kernel_output_file, &extra_etls, &[], None);
+    for sample in handle_count_samples {
+        context.handle_handle_count(sample.timestamp_raw, sample.pid, sample.count);
+    }
 
     if let Some(win_version) = winver::WindowsVersion::detect() {
         context.set_os_name(&format!("Windows {win_version}"))
@@ -171,27 +279,56 @@ pub fn run(
                 )
             });
         }
+        for rundown_output_file in &rundown_output_files {
+            let _ = std::fs::remove_file(rundown_output_file);
+        }
     } else {
         eprintln!("ETL path: {}", kernel_output_file.to_str().unwrap());
         if let Some(user_output_file) = &user_output_file {
             eprintln!("User ETL path: {}", user_output_file.to_str().unwrap());
         }
+        for rundown_output_file in &rundown_output_files {
+            eprintln!(
+                "Rundown ETL path: {}",
+                rundown_output_file.to_str().unwrap()
+            );
+        }
     }
 
     Ok((profile, ExitStatus::from_raw(0)))
 }
 
 #[cfg(target_arch = "x86")]
-fn get_native_arch() -> &'static str {
+pub(super) fn get_native_arch() -> &'static str {
     "x86"
 }
 
 #[cfg(target_arch = "x86_64")]
-fn get_native_arch() -> &'static str {
+pub(super) fn get_native_arch() -> &'static str {
     "x86_64"
 }
 
 #[cfg(target_arch = "aarch64")]
-fn get_native_arch() -> &'static str {
+pub(super) fn get_native_arch() -> &'static str {
     "arm64"
 }
+
+/// Blocks until Ctrl+C is pressed, or until the time limit has elapsed, if there is one.
+fn wait_for_ctrl_c_or_time_limit(
+    ctrl_c_receiver: crate::shared::ctrl_c::Receiver,
+    time_limit: Option<Duration>,
+) {
+    let Some(time_limit) = time_limit else {
+        let _ = ctrl_c_receiver.blocking_recv();
+        return;
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    // When the time is up, the receiver is dropped, which restores the default
+    // Ctrl+C handling.
+    runtime.block_on(async {
+        let _ = tokio::time::timeout(time_limit, ctrl_c_receiver).await;
+    });
+}
@@ -0,0 +1,42 @@
+/// The kernel flag which enables the Registry events of the kernel logger.
+pub const REGISTRY_KERNEL_FLAG: &str = "REGISTRY";
+
+/// What a Registry event from the kernel logger is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryEvent<'a> {
+    /// A registry operation, such as "QueryValue", which was logged when it
+    /// completed.
+    Operation(&'a str),
+    /// The kernel created a key control block (KCB), or listed an existing one
+    /// during rundown. Operations on keys below it name their key relative to
+    /// this one.
+    KcbCreate,
+    /// The kernel deleted a key control block.
+    KcbDelete,
+}
+
+/// Returns what an event from the kernel logger is, if it's a Registry event.
+pub fn registry_event(name: &str) -> Option<RegistryEvent> {
+    let op = name.strip_prefix("MSNT_SystemTrace/Registry/")?;
+    match op {
+        "KCBCreate" | "KCBRundownBegin" | "KCBRundownEnd" => Some(RegistryEvent::KcbCreate),
+        "KCBDelete" => Some(RegistryEvent::KcbDelete),
+        // Statistics and configuration events which aren't about a key.
+        "Counters" | "Config" => None,
+        op => Some(RegistryEvent::Operation(op)),
+    }
+}
+
+/// Returns the full path of a key, from the path of the key control block
+/// which `relative_name` is relative to, if it's known.
+pub fn full_key_path(base_path: Option<&str>, relative_name: &str) -> String {
+    match base_path {
+        Some(base_path) if relative_name.is_empty() => base_path.to_string(),
+        Some(base_path) => format!(
+            "{}\\{}",
+            base_path.trim_end_matches('\\'),
+            relative_name.trim_start_matches('\\')
+        ),
+        None => relative_name.to_string(),
+    }
+}
@@ -0,0 +1,118 @@
+use uuid::Uuid;
+
+use super::elevated_helper::ElevatedRecordingProps;
+
+pub fn rpc_xperf_args(props: &ElevatedRecordingProps) -> Vec<String> {
+    if !props.rpc {
+        return vec![];
+    }
+
+    vec![
+        // Calls through the RPC runtime, including LRPC / ALPC calls to
+        // other processes on the same machine.
+        "Microsoft-Windows-RPC:0xffffffffffffffff:4".to_string(),
+        // COM calls, which are made through RPC when they cross apartments or
+        // processes.
+        "Microsoft-Windows-COMRuntime:0xffffffffffffffff:4".to_string(),
+    ]
+}
+
+/// The names of the properties which contain the interface of a call, in RPC
+/// and COM events.
+pub const CALL_INTERFACE_PROPERTIES: &[&str] = &["InterfaceUuid", "InterfaceId", "Iid"];
+
+/// The names of the properties which contain the procedure or method number of
+/// a call.
+pub const CALL_METHOD_PROPERTIES: &[&str] = &["ProcNum", "MethodNum", "Method"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallRuntime {
+    Rpc,
+    Com,
+}
+
+/// Whether the calling or the called side logged a call event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallSide {
+    Client,
+    Server,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallEdge {
+    Start,
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallEvent {
+    pub runtime: CallRuntime,
+    pub side: CallSide,
+    pub edge: CallEdge,
+}
+
+impl CallRuntime {
+    pub fn name(self) -> &'static str {
+        match self {
+            CallRuntime::Rpc => "RPC",
+            CallRuntime::Com => "COM",
+        }
+    }
+}
+
+impl CallSide {
+    pub fn name(self) -> &'static str {
+        match self {
+            CallSide::Client => "client",
+            CallSide::Server => "server",
+        }
+    }
+}
+
+/// Returns which side of an RPC or COM call an event starts or stops, e.g.
+/// for Microsoft-Windows-RPC/RpcClientCall/Start.
+///
+/// The COM runtime's task names differ between Windows versions, so this goes
+/// by the words in the task and opcode names.
+pub fn rpc_call_event(name: &str) -> Option<CallEvent> {
+    let (provider, task_and_op) = name.split_once('/')?;
+    let runtime = match provider {
+        "Microsoft-Windows-RPC" => CallRuntime::Rpc,
+        "Microsoft-Windows-COMRuntime" => CallRuntime::Com,
+        _ => return None,
+    };
+    let task_and_op = task_and_op.to_ascii_lowercase();
+    let (task, op) = task_and_op.rsplit_once('/')?;
+    if !task.contains("call") {
+        return None;
+    }
+    let side = if task.contains("client") {
+        CallSide::Client
+    } else if task.contains("server") {
+        CallSide::Server
+    } else {
+        return None;
+    };
+    let edge = match op {
+        "start" | "win:start" => CallEdge::Start,
+        "stop" | "win:stop" => CallEdge::Stop,
+        _ => return None,
+    };
+    Some(CallEvent {
+        runtime,
+        side,
+        edge,
+    })
+}
+
+/// What the start event of a call says about the call.
+#[derive(Debug, Clone, Default)]
+pub struct CallDetails {
+    pub interface: Option<Uuid>,
+    pub method: Option<u32>,
+    /// The endpoint and the network address, if any, e.g. an ALPC port name.
+    pub endpoint: String,
+    /// The activity ID of the call, which is the same on the client and on the
+    /// server side of calls which cross processes.
+    pub correlation_id: Option<Uuid>,
+}
@@ -0,0 +1,181 @@
+//! Recording a Windows service, for `samply record --service`.
+//!
+//! The service is resolved to the process it runs in with the service control
+//! manager, and recorded like an existing process. Several services can share
+//! one svchost.exe process; in that case, the threads which were started for
+//! another service are left out. The kernel tags each thread with the service
+//! it was started for (the "SubProcessTag" of the thread events), and
+//! `I_QueryTagInformation` turns such a tag into a service name.
+
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::time::{Duration, Instant};
+
+use windows::core::{HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{LocalFree, HLOCAL};
+use windows::Win32::System::Services::{
+    CloseServiceHandle, OpenSCManagerW, OpenServiceW, QueryServiceStatusEx, StartServiceW,
+    SC_HANDLE, SC_MANAGER_CONNECT, SC_STATUS_PROCESS_INFO, SERVICE_QUERY_STATUS, SERVICE_START,
+    SERVICE_STATUS_PROCESS, SERVICE_STOPPED, SERVICE_WIN32_SHARE_PROCESS,
+};
+
+use crate::shared::prop_types::ServiceProps;
+
+/// How long to wait for a service to start with `--start-service`.
+const START_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The process which a service runs in.
+pub struct ServiceProcess {
+    pub pid: u32,
+    /// Whether the service shares its process with other services.
+    pub shares_process: bool,
+}
+
+/// Finds the process of a running service, starting the service first if
+/// `props.start` is set and it isn't running.
+pub fn resolve_service(props: &ServiceProps) -> Result<ServiceProcess, String> {
+    unsafe {
+        let scm = OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), SC_MANAGER_CONNECT)
+            .map_err(|e| format!("Couldn't connect to the service control manager: {e}"))?;
+        let access = if props.start {
+            SERVICE_QUERY_STATUS | SERVICE_START
+        } else {
+            SERVICE_QUERY_STATUS
+        };
+        let result = match OpenServiceW(scm, &HSTRING::from(props.name.as_str()), access) {
+            Ok(service) => {
+                let result = resolve_opened_service(service, props);
+                let _ = CloseServiceHandle(service);
+                result
+            }
+            Err(e) => Err(format!("Couldn't open service {:?}: {e}", props.name)),
+        };
+        let _ = CloseServiceHandle(scm);
+        result
+    }
+}
+
+unsafe fn resolve_opened_service(
+    service: SC_HANDLE,
+    props: &ServiceProps,
+) -> Result<ServiceProcess, String> {
+    let mut status = query_status(service)?;
+    if status.dwCurrentState == SERVICE_STOPPED && props.start {
+        eprintln!("Starting service {}...", props.name);
+        StartServiceW(service, None)
+            .map_err(|e| format!("Couldn't start service {:?}: {e}", props.name))?;
+        // Only wait until the service has a process, so that the recording
+        // can begin while the service is still starting up.
+        let deadline = Instant::now() + START_TIMEOUT;
+        while status.dwProcessId == 0
+            && status.dwCurrentState != SERVICE_STOPPED
+            && Instant::now() < deadline
+        {
+            std::thread::sleep(Duration::from_millis(10));
+            status = query_status(service)?;
+        }
+    }
+    if status.dwProcessId == 0 {
+        return Err(format!(
+            "Service {:?} isn't running. Use --start-service to start it.",
+            props.name
+        ));
+    }
+    Ok(ServiceProcess {
+        pid: status.dwProcessId,
+        shares_process: status.dwServiceType.0 & SERVICE_WIN32_SHARE_PROCESS.0 != 0,
+    })
+}
+
+unsafe fn query_status(service: SC_HANDLE) -> Result<SERVICE_STATUS_PROCESS, String> {
+    let mut status = SERVICE_STATUS_PROCESS::default();
+    let mut bytes_needed = 0;
+    QueryServiceStatusEx(
+        service,
+        SC_STATUS_PROCESS_INFO,
+        Some(std::slice::from_raw_parts_mut(
+            &mut status as *mut _ as *mut u8,
+            std::mem::size_of::<SERVICE_STATUS_PROCESS>(),
+        )),
+        &mut bytes_needed,
+    )
+    .map_err(|e| format!("Couldn't query the service status: {e}"))?;
+    Ok(status)
+}
+
+/// TAG_INFO_NAME_FROM_TAG from the Windows SDK's internal headers.
+#[repr(C)]
+struct TagInfoNameFromTag {
+    pid: u32,
+    tag: u32,
+    tag_type: u32,
+    name: PWSTR,
+}
+
+/// eTagInfoLevelNameFromTag
+const TAG_INFO_LEVEL_NAME_FROM_TAG: u32 = 1;
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn I_QueryTagInformation(machine_name: PCWSTR, info_level: u32, tag_info: *mut c_void) -> u32;
+}
+
+/// The name of the service which a thread's service tag stands for, in the
+/// process `pid`.
+fn service_name_from_tag(pid: u32, tag: u32) -> Option<String> {
+    let mut info = TagInfoNameFromTag {
+        pid,
+        tag,
+        tag_type: 0,
+        name: PWSTR::null(),
+    };
+    unsafe {
+        let err = I_QueryTagInformation(
+            PCWSTR::null(),
+            TAG_INFO_LEVEL_NAME_FROM_TAG,
+            &mut info as *mut _ as *mut c_void,
+        );
+        if err != 0 || info.name.is_null() {
+            return None;
+        }
+        let name = info.name.to_string().ok();
+        let _ = LocalFree(Some(HLOCAL(info.name.0 as *mut _)));
+        name
+    }
+}
+
+/// Leaves out the threads of the other services in a process which several
+/// services share.
+pub struct ServiceThreadFilter {
+    pid: u32,
+    service_name: String,
+    /// Whether each service tag belongs to the recorded service.
+    tags: HashMap<u32, bool>,
+}
+
+impl ServiceThreadFilter {
+    pub fn new(pid: u32, service_name: String) -> Self {
+        Self {
+            pid,
+            service_name,
+            tags: HashMap::new(),
+        }
+    }
+
+    /// Whether a thread of process `pid` with this service tag is recorded.
+    /// Threads without a tag, such as thread pool threads, can run work for
+    /// any service in the process, so they're kept, and so are threads whose
+    /// tag can't be looked up anymore.
+    pub fn includes_thread(&mut self, pid: u32, sub_process_tag: u32) -> bool {
+        if pid != self.pid || sub_process_tag == 0 {
+            return true;
+        }
+        let service_name = &self.service_name;
+        *self.tags.entry(sub_process_tag).or_insert_with(|| {
+            match service_name_from_tag(pid, sub_process_tag) {
+                Some(name) => name.eq_ignore_ascii_case(service_name),
+                None => true,
+            }
+        })
+    }
+}
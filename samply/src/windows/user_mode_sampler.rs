@@ -0,0 +1,760 @@
+//! A sampler for a single process which doesn't need ETW, and therefore doesn't
+//! need administrator rights.
+//!
+//! On every tick, each thread of the target process whose CPU time has changed
+//! is suspended, its register context is read, and its stack is walked with
+//! dbghelp's `StackWalk64`, which uses the unwind info of the loaded modules
+//! (the same information `RtlVirtualUnwind` uses). Threads which haven't run
+//! since the previous tick get a zero-CPU sample with their previous stack.
+//!
+//! This is less accurate than ETW sampling: the sampling timer is coarser,
+//! suspending a thread skews what it was doing, and kernel stacks aren't
+//! available. But it works for regular users.
+//!
+//! 32-bit processes on 64-bit x86 Windows (WOW64) are walked with their 32-bit
+//! context, so their stacks show the 32-bit code rather than the WOW64 layer.
+
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::os::windows::ffi::OsStringExt;
+use std::os::windows::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+use fxprof_processed_profile::{
+    CategoryHandle, CpuDelta, FrameAddress, FrameFlags, LibraryInfo, ProcessHandle, Profile,
+    ReferenceTimestamp, SamplingInterval, StackHandle, ThreadHandle, Timestamp,
+};
+use tokio::sync::oneshot::error::TryRecvError;
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{CloseHandle, FILETIME, HANDLE, HMODULE, WAIT_OBJECT_0};
+use windows::Win32::Media::{timeBeginPeriod, timeEndPeriod};
+use windows::Win32::System::Diagnostics::Debug::{
+    AddrModeFlat, GetThreadContext, StackWalk64, SymCleanup, SymFunctionTableAccess64,
+    SymGetModuleBase64, SymGetOptions, SymInitializeW, SymRefreshModuleList, SymSetOptions,
+    CONTEXT, STACKFRAME64, SYMOPT_INCLUDE_32BIT_MODULES,
+};
+use windows::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+};
+use windows::Win32::System::ProcessStatus::{
+    EnumProcessModulesEx, GetModuleFileNameExW, GetModuleInformation, LIST_MODULES_ALL, MODULEINFO,
+};
+use windows::Win32::System::Threading::{
+    GetThreadDescription, GetThreadTimes, OpenProcess, OpenThread, ResumeThread, SuspendThread,
+    WaitForSingleObject, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ, THREAD_GET_CONTEXT,
+    THREAD_QUERY_LIMITED_INFORMATION, THREAD_SUSPEND_RESUME,
+};
+
+use super::handle_counts::handle_count;
+use super::profile_context::PeInfo;
+use crate::shared::ctrl_c::CtrlC;
+use crate::shared::interval_jitter::IntervalJitter;
+use crate::shared::open_handles::OpenHandlesCounter;
+use crate::shared::prop_types::{EtwPreset, ProfileCreationProps, RecordingMode, RecordingProps};
+use crate::shared::thread_rates::ThreadRates;
+
+/// Threads and modules are re-enumerated this often.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Stacks deeper than this are truncated.
+const MAX_STACK_DEPTH: usize = 1024;
+
+/// Returns whether the user-mode sampler can record in this mode. It can only
+/// sample processes it knows the pid of.
+pub fn supports_recording_mode(recording_mode: &RecordingMode) -> bool {
+    matches!(
+        recording_mode,
+        RecordingMode::Pid(_) | RecordingMode::Launch(_)
+    )
+}
+
+pub fn run(
+    recording_mode: RecordingMode,
+    recording_props: RecordingProps,
+    profile_creation_props: ProfileCreationProps,
+) -> Result<(Profile, ExitStatus), i32> {
+    if recording_props.etw_preset != EtwPreset::Cpu {
+        eprintln!("--preset only applies to ETW recording, the user-mode sampler only records CPU samples.");
+    }
+    if recording_props.follow_restarts {
+        eprintln!("--follow-restarts isn't supported by the user-mode sampler, ignoring it.");
+    }
+    let reference_time = Instant::now();
+    let timebase = ReferenceTimestamp::from_system_time(std::time::SystemTime::now());
+    let thread_rates = ThreadRates::new(
+        recording_props.interval,
+        &recording_props.thread_rate_overrides,
+    );
+    let mut profile = Profile::new(
+        profile_creation_props.profile_name(),
+        timebase,
+        SamplingInterval::from_nanos(thread_rates.tick_interval().as_nanos() as u64),
+    );
+    if let Some(win_version) = winver::WindowsVersion::detect() {
+        profile.set_os_name(&format!("Windows {win_version}"));
+    }
+
+    // The default timer resolution of ~15ms is too coarse for sampling.
+    unsafe { timeBeginPeriod(1) };
+
+    let mut exit_status = ExitStatus::from_raw(0);
+    match recording_mode {
+        RecordingMode::Pid(pid) => {
+            let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+            eprintln!("Profiling process with pid {pid} with the user-mode sampler...");
+            eprintln!("Press Ctrl+C to stop.");
+            let Some(mut sampler) = ProcessSampler::new(pid, &mut profile, reference_time) else {
+                eprintln!("Could not open process with pid {pid}.");
+                unsafe { timeEndPeriod(1) };
+                return Err(1);
+            };
+            sampler.run(&mut profile, &recording_props, reference_time, || {
+                !matches!(ctrl_c_receiver.try_recv(), Err(TryRecvError::Empty))
+            });
+        }
+        RecordingMode::Launch(process_launch_props) => {
+            // Ignore Ctrl+C while the subcommand is running. The signal still reaches the process
+            // under observation while we continue to record it.
+            let mut ctrl_c_receiver = CtrlC::observe_oneshot();
+
+            for _ in 0..process_launch_props.iteration_count {
+                let mut child = std::process::Command::new(&process_launch_props.command_name);
+                child.args(&process_launch_props.args);
+                child.envs(process_launch_props.env_vars.iter().map(|(k, v)| (k, v)));
+                let mut child = child.spawn().unwrap();
+
+                if let Some(mut sampler) =
+                    ProcessSampler::new(child.id(), &mut profile, reference_time)
+                {
+                    sampler.run(&mut profile, &recording_props, reference_time, || false);
+                } else {
+                    eprintln!("Could not open the launched process for sampling.");
+                }
+
+                exit_status = child.wait().unwrap();
+                if !process_launch_props.ignore_exit_code && !exit_status.success() {
+                    eprintln!(
+                        "Skipping remaining iterations due to non-success exit status: \"{}\"",
+                        exit_status
+                    );
+                    break;
+                }
+            }
+
+            // The launched subprocess is done. From now on, we want to terminate if the user presses Ctrl+C.
+            ctrl_c_receiver.close();
+        }
+        RecordingMode::All
+        | RecordingMode::WaitForProcess(_)
+        | RecordingMode::Multiple { .. }
+        | RecordingMode::Service(_) => {
+            eprintln!(
+                "The user-mode sampler can only record a launched process or an existing process (--pid)."
+            );
+            unsafe { timeEndPeriod(1) };
+            return Err(1);
+        }
+    }
+
+    unsafe { timeEndPeriod(1) };
+    IntervalJitter::new(
+        thread_rates.tick_interval(),
+        recording_props.interval_jitter,
+    )
+    .add_to_profile_meta(&mut profile);
+    thread_rates.add_to_profile_meta(&mut profile);
+    Ok((profile, exit_status))
+}
+
+struct SampledThread {
+    handle: HANDLE,
+    profile_thread: ThreadHandle,
+    previous_cpu_time: u64,
+    previous_stack: Option<StackHandle>,
+    name: Option<String>,
+}
+
+impl Drop for SampledThread {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.handle) };
+    }
+}
+
+struct ProcessSampler {
+    pid: u32,
+    process: HANDLE,
+    profile_process: ProcessHandle,
+    machine: u32,
+    is_wow64: bool,
+    threads: HashMap<u32, SampledThread>,
+    known_modules: HashMap<u64, u64>,
+    frames: Vec<u64>,
+    open_handles: OpenHandlesCounter,
+}
+
+impl ProcessSampler {
+    fn new(pid: u32, profile: &mut Profile, reference_time: Instant) -> Option<Self> {
+        let process =
+            unsafe { OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid) }.ok()?;
+        let is_wow64 = is_wow64_process(process);
+        if is_wow64 {
+            // dbghelp only loads the 64-bit modules of WOW64 processes by default.
+            unsafe { SymSetOptions(SymGetOptions() | SYMOPT_INCLUDE_32BIT_MODULES) };
+        }
+        // Let dbghelp find the process's modules, so that StackWalk64 can use
+        // their unwind info.
+        if unsafe { SymInitializeW(process, PCWSTR::null(), true) }.is_err() {
+            let _ = unsafe { CloseHandle(process) };
+            return None;
+        }
+        let now = timestamp_since(reference_time);
+        let name = module_path(process, None)
+            .map(|path| file_name_of(&path).to_string())
+            .unwrap_or_else(|| format!("Process {pid}"));
+        let profile_process = profile.add_process(&name, pid, now);
+        let open_handles = OpenHandlesCounter::for_handles(profile, profile_process);
+        let mut sampler = Self {
+            pid,
+            process,
+            profile_process,
+            machine: native_machine(),
+            is_wow64,
+            threads: HashMap::new(),
+            known_modules: HashMap::new(),
+            frames: Vec::new(),
+            open_handles,
+        };
+        sampler.refresh_modules(profile);
+        sampler.refresh_threads(profile, now);
+        Some(sampler)
+    }
+
+    fn run(
+        &mut self,
+        profile: &mut Profile,
+        recording_props: &RecordingProps,
+        reference_time: Instant,
+        mut should_stop: impl FnMut() -> bool,
+    ) {
+        let start = Instant::now();
+        let mut last_refresh = start;
+        let mut sample_count = 0;
+        // With per-thread rates, we wake up at the highest rate and only
+        // sample the slower threads on some of the ticks.
+        let thread_rates = ThreadRates::new(
+            recording_props.interval,
+            &recording_props.thread_rate_overrides,
+        );
+        let interval_jitter = IntervalJitter::new(
+            thread_rates.tick_interval(),
+            recording_props.interval_jitter,
+        );
+        loop {
+            if self.has_exited() || should_stop() {
+                break;
+            }
+            if recording_props
+                .time_limit
+                .is_some_and(|time_limit| start.elapsed() >= time_limit)
+            {
+                break;
+            }
+            if recording_props
+                .sample_limit
+                .is_some_and(|limit| sample_count >= limit)
+            {
+                break;
+            }
+
+            let now = timestamp_since(reference_time);
+            if last_refresh.elapsed() >= REFRESH_INTERVAL {
+                self.refresh_modules(profile);
+                self.refresh_threads(profile, now);
+                if let Some(count) = handle_count(self.process) {
+                    self.open_handles.add_sample(profile, now, u64::from(count));
+                }
+                last_refresh = Instant::now();
+            }
+            self.sample_threads(profile, now, &thread_rates, sample_count);
+            sample_count += 1;
+
+            std::thread::sleep(interval_jitter.next_interval());
+        }
+
+        let end = timestamp_since(reference_time);
+        for thread in self.threads.values() {
+            profile.set_thread_end_time(thread.profile_thread, end);
+        }
+        profile.set_process_end_time(self.profile_process, end);
+    }
+
+    fn has_exited(&self) -> bool {
+        unsafe { WaitForSingleObject(self.process, 0) == WAIT_OBJECT_0 }
+    }
+
+    fn refresh_threads(&mut self, profile: &mut Profile, now: Timestamp) {
+        let tids = thread_ids_of_process(self.pid);
+        let is_first_refresh = self.threads.is_empty();
+        for (index, tid) in tids.iter().enumerate() {
+            let tid = *tid;
+            if self.threads.contains_key(&tid) {
+                continue;
+            }
+            let access =
+                THREAD_SUSPEND_RESUME | THREAD_GET_CONTEXT | THREAD_QUERY_LIMITED_INFORMATION;
+            let Ok(handle) = (unsafe { OpenThread(access, false, tid) }) else {
+                continue;
+            };
+            // Thread snapshots list the threads of a process in creation order.
+            let is_main = is_first_refresh && index == 0;
+            let profile_thread = profile.add_thread(self.profile_process, tid, now, is_main);
+            self.threads.insert(
+                tid,
+                SampledThread {
+                    handle,
+                    profile_thread,
+                    previous_cpu_time: thread_cpu_time(handle).unwrap_or(0),
+                    previous_stack: None,
+                    name: None,
+                },
+            );
+        }
+
+        self.threads.retain(|tid, thread| {
+            let is_alive = tids.contains(tid);
+            if !is_alive {
+                profile.set_thread_end_time(thread.profile_thread, now);
+            }
+            is_alive
+        });
+
+        for thread in self.threads.values_mut() {
+            if thread.name.is_some() {
+                continue;
+            }
+            if let Some(name) = thread_description(thread.handle) {
+                profile.set_thread_name(thread.profile_thread, &name);
+                thread.name = Some(name);
+            }
+        }
+    }
+
+    fn refresh_modules(&mut self, profile: &mut Profile) {
+        let modules = process_modules(self.process);
+        let mut added_any = false;
+        for module in modules {
+            let mut info = MODULEINFO::default();
+            let result = unsafe {
+                GetModuleInformation(
+                    self.process,
+                    module,
+                    &mut info,
+                    std::mem::size_of::<MODULEINFO>() as u32,
+                )
+            };
+            if result.is_err() {
+                continue;
+            }
+            let base = info.lpBaseOfDll as u64;
+            let size = u64::from(info.SizeOfImage);
+            if self.known_modules.get(&base) == Some(&size) {
+                continue;
+            }
+            let Some(path) = module_path(self.process, Some(module)) else {
+                continue;
+            };
+            self.known_modules.insert(base, size);
+            added_any = true;
+
+            let mut pe_info = PeInfo::new_with_size_and_checksum(info.SizeOfImage, 0);
+            if let Some(info_from_file) = PeInfo::try_from_image_at_path(Path::new(&path)) {
+                pe_info = info_from_file;
+            }
+            let pdb_path = pe_info.pdb_path.clone().unwrap_or_else(|| path.clone());
+            let lib = profile.add_lib(LibraryInfo {
+                name: file_name_of(&path).to_string(),
+                debug_name: file_name_of(&pdb_path).to_string(),
+                path,
+                debug_path: pdb_path,
+                debug_id: pe_info.debug_id.unwrap_or_default(),
+                code_id: pe_info.code_id().map(|code_id| code_id.to_string()),
+                // WOW64 processes have both 32-bit and 64-bit modules.
+                arch: Some(
+                    pe_info
+                        .arch
+                        .unwrap_or(super::profiler::get_native_arch())
+                        .to_string(),
+                ),
+            });
+            profile.add_lib_mapping(self.profile_process, lib, base, base + size, 0);
+        }
+        if added_any {
+            let _ = unsafe { SymRefreshModuleList(self.process) };
+        }
+    }
+
+    fn sample_threads(
+        &mut self,
+        profile: &mut Profile,
+        now: Timestamp,
+        thread_rates: &ThreadRates,
+        tick: u64,
+    ) {
+        for thread in self.threads.values_mut() {
+            let ticks_per_sample = thread_rates.ticks_per_sample(thread.name.as_deref());
+            if tick % u64::from(ticks_per_sample) != 0 {
+                continue;
+            }
+            let weight = ticks_per_sample as i32;
+            let Some(cpu_time) = thread_cpu_time(thread.handle) else {
+                continue;
+            };
+            let cpu_delta_100ns = cpu_time.saturating_sub(thread.previous_cpu_time);
+            thread.previous_cpu_time = cpu_time;
+
+            if cpu_delta_100ns == 0 && thread.previous_stack.is_some() {
+                profile.add_sample_same_stack_zero_cpu(thread.profile_thread, now, weight);
+                continue;
+            }
+
+            self.frames.clear();
+            if !walk_thread_stack(
+                self.process,
+                thread.handle,
+                self.machine,
+                self.is_wow64,
+                &mut self.frames,
+            ) {
+                continue;
+            }
+
+            let profile_thread = thread.profile_thread;
+            let mut frames = self
+                .frames
+                .iter()
+                .enumerate()
+                .map(|(i, address)| match i {
+                    0 => FrameAddress::InstructionPointer(*address),
+                    _ => FrameAddress::ReturnAddress(*address),
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev();
+            let stack = profile.handle_for_stack_frames(profile_thread, |profile| {
+                let frame_address = frames.next()?;
+                Some(profile.handle_for_frame_with_address(
+                    profile_thread,
+                    frame_address,
+                    CategoryHandle::OTHER,
+                    FrameFlags::empty(),
+                ))
+            });
+            let cpu_delta = CpuDelta::from_nanos(cpu_delta_100ns * 100);
+            profile.add_sample(profile_thread, now, stack, cpu_delta, weight);
+            thread.previous_stack = stack;
+        }
+    }
+}
+
+impl Drop for ProcessSampler {
+    fn drop(&mut self) {
+        self.threads.clear();
+        unsafe {
+            let _ = SymCleanup(self.process);
+            let _ = CloseHandle(self.process);
+        }
+    }
+}
+
+/// Suspends the thread, walks its stack, and resumes it. The instruction
+/// pointer comes first in `frames`, followed by the return addresses.
+fn walk_thread_stack(
+    process: HANDLE,
+    thread: HANDLE,
+    machine: u32,
+    is_wow64: bool,
+    frames: &mut Vec<u64>,
+) -> bool {
+    if unsafe { SuspendThread(thread) } == u32::MAX {
+        return false;
+    }
+    let walked = match is_wow64 {
+        true => unsafe { walk_suspended_wow64_thread_stack(process, thread, frames) },
+        false => unsafe { walk_suspended_thread_stack(process, thread, machine, frames) },
+    };
+    unsafe { ResumeThread(thread) };
+    walked
+}
+
+unsafe fn walk_suspended_thread_stack(
+    process: HANDLE,
+    thread: HANDLE,
+    machine: u32,
+    frames: &mut Vec<u64>,
+) -> bool {
+    let mut context: CONTEXT = std::mem::zeroed();
+    context.ContextFlags = context_flags_full();
+    if GetThreadContext(thread, &mut context).is_err() {
+        return false;
+    }
+
+    let (pc, fp, sp) = context_registers(&context);
+    stack_walk(
+        process,
+        thread,
+        machine,
+        (pc, fp, sp),
+        &mut context as *mut CONTEXT as *mut c_void,
+        frames,
+    )
+}
+
+/// Walks the 32-bit stack of a thread of a WOW64 process. Its 64-bit context
+/// would only show the WOW64 layer which runs the 32-bit code.
+#[cfg(target_arch = "x86_64")]
+unsafe fn walk_suspended_wow64_thread_stack(
+    process: HANDLE,
+    thread: HANDLE,
+    frames: &mut Vec<u64>,
+) -> bool {
+    use windows::Win32::System::Diagnostics::Debug::{
+        Wow64GetThreadContext, WOW64_CONTEXT, WOW64_CONTEXT_FULL,
+    };
+    use windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_I386;
+
+    let mut context: WOW64_CONTEXT = std::mem::zeroed();
+    context.ContextFlags = WOW64_CONTEXT_FULL;
+    if Wow64GetThreadContext(thread, &mut context).is_err() {
+        return false;
+    }
+    let registers = (
+        u64::from(context.Eip),
+        u64::from(context.Ebp),
+        u64::from(context.Esp),
+    );
+    stack_walk(
+        process,
+        thread,
+        IMAGE_FILE_MACHINE_I386.0 as u32,
+        registers,
+        &mut context as *mut WOW64_CONTEXT as *mut c_void,
+        frames,
+    )
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+unsafe fn walk_suspended_wow64_thread_stack(
+    _process: HANDLE,
+    _thread: HANDLE,
+    _frames: &mut Vec<u64>,
+) -> bool {
+    false
+}
+
+/// Walks the stack with StackWalk64, starting with the instruction pointer,
+/// frame pointer and stack pointer in `registers`. `context` is the thread
+/// context for `machine`, which StackWalk64 updates as it goes.
+unsafe fn stack_walk(
+    process: HANDLE,
+    thread: HANDLE,
+    machine: u32,
+    registers: (u64, u64, u64),
+    context: *mut c_void,
+    frames: &mut Vec<u64>,
+) -> bool {
+    let mut frame: STACKFRAME64 = std::mem::zeroed();
+    let (pc, fp, sp) = registers;
+    frame.AddrPC.Offset = pc;
+    frame.AddrPC.Mode = AddrModeFlat;
+    frame.AddrFrame.Offset = fp;
+    frame.AddrFrame.Mode = AddrModeFlat;
+    frame.AddrStack.Offset = sp;
+    frame.AddrStack.Mode = AddrModeFlat;
+
+    while frames.len() < MAX_STACK_DEPTH {
+        let ok = StackWalk64(
+            machine,
+            process,
+            thread,
+            &mut frame,
+            context,
+            None,
+            Some(SymFunctionTableAccess64),
+            Some(SymGetModuleBase64),
+            None,
+        );
+        if !ok.as_bool() || frame.AddrPC.Offset == 0 {
+            break;
+        }
+        frames.push(frame.AddrPC.Offset);
+    }
+    !frames.is_empty()
+}
+
+/// Whether this is a 32-bit process on 64-bit x86 Windows. x86 and x64
+/// processes on arm64 Windows are emulated, and are sampled like native ones.
+#[cfg(target_arch = "x86_64")]
+fn is_wow64_process(process: HANDLE) -> bool {
+    use windows::Win32::System::Threading::IsWow64Process;
+
+    let mut is_wow64 = Default::default();
+    let result = unsafe { IsWow64Process(process, &mut is_wow64) };
+    result.is_ok() && is_wow64.as_bool()
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn is_wow64_process(_process: HANDLE) -> bool {
+    false
+}
+
+#[cfg(target_arch = "x86_64")]
+fn native_machine() -> u32 {
+    windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_AMD64.0 as u32
+}
+
+#[cfg(target_arch = "aarch64")]
+fn native_machine() -> u32 {
+    windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_ARM64.0 as u32
+}
+
+#[cfg(target_arch = "x86")]
+fn native_machine() -> u32 {
+    windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_I386.0 as u32
+}
+
+#[cfg(target_arch = "x86_64")]
+fn context_flags_full() -> windows::Win32::System::Diagnostics::Debug::CONTEXT_FLAGS {
+    windows::Win32::System::Diagnostics::Debug::CONTEXT_FULL_AMD64
+}
+
+#[cfg(target_arch = "aarch64")]
+fn context_flags_full() -> windows::Win32::System::Diagnostics::Debug::CONTEXT_FLAGS {
+    windows::Win32::System::Diagnostics::Debug::CONTEXT_FULL_ARM64
+}
+
+#[cfg(target_arch = "x86")]
+fn context_flags_full() -> windows::Win32::System::Diagnostics::Debug::CONTEXT_FLAGS {
+    windows::Win32::System::Diagnostics::Debug::CONTEXT_FULL_X86
+}
+
+/// Returns the instruction pointer, frame pointer and stack pointer.
+#[cfg(target_arch = "x86_64")]
+fn context_registers(context: &CONTEXT) -> (u64, u64, u64) {
+    (context.Rip, context.Rbp, context.Rsp)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn context_registers(context: &CONTEXT) -> (u64, u64, u64) {
+    let fp = unsafe { context.Anonymous.Anonymous.Fp };
+    (context.Pc, fp, context.Sp)
+}
+
+#[cfg(target_arch = "x86")]
+fn context_registers(context: &CONTEXT) -> (u64, u64, u64) {
+    (
+        u64::from(context.Eip),
+        u64::from(context.Ebp),
+        u64::from(context.Esp),
+    )
+}
+
+fn timestamp_since(reference_time: Instant) -> Timestamp {
+    Timestamp::from_nanos_since_reference(reference_time.elapsed().as_nanos() as u64)
+}
+
+/// The sum of the thread's kernel and user time, in 100ns units.
+fn thread_cpu_time(thread: HANDLE) -> Option<u64> {
+    let mut creation_time = FILETIME::default();
+    let mut exit_time = FILETIME::default();
+    let mut kernel_time = FILETIME::default();
+    let mut user_time = FILETIME::default();
+    unsafe {
+        GetThreadTimes(
+            thread,
+            &mut creation_time,
+            &mut exit_time,
+            &mut kernel_time,
+            &mut user_time,
+        )
+    }
+    .ok()?;
+    let to_u64 = |t: FILETIME| (u64::from(t.dwHighDateTime) << 32) | u64::from(t.dwLowDateTime);
+    Some(to_u64(kernel_time) + to_u64(user_time))
+}
+
+fn thread_description(thread: HANDLE) -> Option<String> {
+    let description = unsafe { GetThreadDescription(thread) }.ok()?;
+    let name = unsafe { description.to_string() }.ok();
+    unsafe {
+        let _ = windows::Win32::Foundation::LocalFree(Some(windows::Win32::Foundation::HLOCAL(
+            description.0 as *mut c_void,
+        )));
+    }
+    name.filter(|name| !name.is_empty())
+}
+
+fn thread_ids_of_process(pid: u32) -> Vec<u32> {
+    let mut tids = Vec::new();
+    let Ok(snapshot) = (unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) }) else {
+        return tids;
+    };
+    let mut entry = THREADENTRY32 {
+        dwSize: std::mem::size_of::<THREADENTRY32>() as u32,
+        ..Default::default()
+    };
+    let mut has_entry = unsafe { Thread32First(snapshot, &mut entry) }.is_ok();
+    while has_entry {
+        if entry.th32OwnerProcessID == pid {
+            tids.push(entry.th32ThreadID);
+        }
+        has_entry = unsafe { Thread32Next(snapshot, &mut entry) }.is_ok();
+    }
+    let _ = unsafe { CloseHandle(snapshot) };
+    tids
+}
+
+fn process_modules(process: HANDLE) -> Vec<HMODULE> {
+    let mut modules = vec![HMODULE::default(); 256];
+    loop {
+        let mut needed = 0;
+        let result = unsafe {
+            EnumProcessModulesEx(
+                process,
+                modules.as_mut_ptr(),
+                (modules.len() * std::mem::size_of::<HMODULE>()) as u32,
+                &mut needed,
+                LIST_MODULES_ALL,
+            )
+        };
+        if result.is_err() {
+            return Vec::new();
+        }
+        let count = needed as usize / std::mem::size_of::<HMODULE>();
+        if count <= modules.len() {
+            modules.truncate(count);
+            return modules;
+        }
+        modules.resize(count, HMODULE::default());
+    }
+}
+
+/// Returns the path of the module, or of the process's executable if `module`
+/// is `None`.
+fn module_path(process: HANDLE, module: Option<HMODULE>) -> Option<String> {
+    let mut buffer = vec![0u16; 1024];
+    let len = unsafe { GetModuleFileNameExW(Some(process), module, &mut buffer) } as usize;
+    if len == 0 {
+        return None;
+    }
+    Some(
+        std::ffi::OsString::from_wide(&buffer[..len])
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+fn file_name_of(path: &str) -> &str {
+    path.rsplit(['\\', '/']).next().unwrap_or(path)
+}
@@ -1,8 +1,13 @@
 use std::error::Error;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::Duration;
 
 use super::elevated_helper::ElevatedRecordingProps;
+use super::kernel_presets::kernel_provider_set;
+use super::registry::REGISTRY_KERNEL_FLAG;
 
 const XPERF_NOT_FOUND_ERROR_MSG: &str = "\
 Could not find an xperf installation.\n\
@@ -13,6 +18,81 @@ except \"Windows Performance Toolkit\" during the installation.)";
 pub struct Xperf {
     state: XperfState,
     xperf_path: Option<PathBuf>,
+    rundown_thread: Option<RundownThread>,
+}
+
+/// Periodically requests a rundown while xperf is recording.
+///
+/// The kernel logger only emits the DCStart events, which describe the processes,
+/// threads and images that already exist, when the session starts. Every rundown
+/// starts and immediately stops a short-lived session with the same kernel flags,
+/// which emits these events again into its own ETL file, and asks the providers of
+/// the user session to log their current state.
+struct RundownThread {
+    stop_sender: mpsc::Sender<()>,
+    join_handle: JoinHandle<Vec<PathBuf>>,
+}
+
+impl RundownThread {
+    fn start(
+        xperf_path: PathBuf,
+        output_path: &Path,
+        user_providers: Option<String>,
+        interval: Duration,
+    ) -> Self {
+        let (stop_sender, stop_receiver) = mpsc::channel();
+        let output_path = output_path.to_owned();
+        let join_handle = std::thread::spawn(move || {
+            let mut rundown_etl_files = Vec::new();
+            while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                let mut rundown_etl_file = output_path.clone();
+                if rundown_etl_file.extension() == Some(OsStr::new("gz")) {
+                    rundown_etl_file.set_extension("");
+                }
+                rundown_etl_file
+                    .set_extension(format!("rundown-{}.etl", rundown_etl_files.len() + 1));
+
+                let mut xperf = std::process::Command::new(&xperf_path);
+                xperf.arg("-start");
+                xperf.arg("SamplyRundownSession");
+                xperf.arg("-on");
+                xperf.arg("PROC_THREAD+LOADER");
+                xperf.arg("-f");
+                xperf.arg(&rundown_etl_file);
+                match xperf.status() {
+                    Ok(status) if status.success() => {}
+                    _ => {
+                        eprintln!("Could not start an xperf rundown session.");
+                        continue;
+                    }
+                }
+                let _ = std::process::Command::new(&xperf_path)
+                    .arg("-stop")
+                    .arg("SamplyRundownSession")
+                    .status();
+                rundown_etl_files.push(rundown_etl_file);
+
+                if let Some(user_providers) = &user_providers {
+                    let _ = std::process::Command::new(&xperf_path)
+                        .arg("-capturestate")
+                        .arg("SamplySession")
+                        .arg(user_providers)
+                        .status();
+                }
+            }
+            rundown_etl_files
+        });
+        Self {
+            stop_sender,
+            join_handle,
+        }
+    }
+
+    /// Stops the thread and returns the ETL files of the rundowns it did.
+    fn stop(self) -> Vec<PathBuf> {
+        let _ = self.stop_sender.send(());
+        self.join_handle.join().unwrap_or_default()
+    }
 }
 
 enum XperfState {
@@ -26,6 +106,7 @@ impl Xperf {
         Self {
             state: XperfState::Stopped,
             xperf_path: None,
+            rundown_thread: None,
         }
     }
 
@@ -69,8 +150,15 @@ impl Xperf {
 
         user_providers.append(&mut super::coreclr::coreclr_xperf_args(props));
         user_providers.append(&mut super::gfx::gfx_xperf_args(props));
+        user_providers.append(&mut super::audio::audio_xperf_args(props));
+        user_providers.append(&mut super::network::network_xperf_args(props));
+        user_providers.append(&mut super::rpc::rpc_xperf_args(props));
+        user_providers.append(&mut super::power::power_xperf_args(props));
         user_providers.append(&mut super::firefox::firefox_xperf_args(props));
         user_providers.append(&mut super::chrome::chrome_xperf_args(props));
+        user_providers.append(&mut super::custom_providers::custom_provider_xperf_args(
+            props,
+        ));
         user_providers.sort_unstable();
         user_providers.dedup();
 
@@ -88,7 +176,7 @@ impl Xperf {
         const NANOS_PER_TICK: u64 = 100;
         let interval_ticks = interval_nanos / NANOS_PER_TICK;
 
-        let mut xperf = std::process::Command::new(xperf_path);
+        let mut xperf = std::process::Command::new(&xperf_path);
         xperf.arg("-SetProfInt");
         xperf.arg(interval_ticks.to_string());
 
@@ -96,9 +184,19 @@ impl Xperf {
         // hack argument lets things still continue to run for development of samply.
         xperf.arg("-on");
         if !props.vm_hack {
-            xperf.arg("PROC_THREAD+LOADER+PROFILE+CSWITCH");
+            let kernel_providers = kernel_provider_set(props.etw_preset);
+            let mut kernel_flags = kernel_providers.flags.to_vec();
+            if props.registry {
+                kernel_flags.push(REGISTRY_KERNEL_FLAG);
+            }
+            if props.sched_latency {
+                // ReadyThread events, for the time between a wakeup and the
+                // thread's next CSwitch.
+                kernel_flags.push("DISPATCHER");
+            }
+            xperf.arg(kernel_flags.join("+"));
             xperf.arg("-stackwalk");
-            xperf.arg("PROFILE+CSWITCH");
+            xperf.arg(kernel_providers.stackwalk.join("+"));
         } else {
             // virtualized arm64 hack, to give us enough interesting events
             xperf.arg("PROC_THREAD+LOADER+CSWITCH+SYSCALL+VIRT_ALLOC+OB_HANDLE");
@@ -143,6 +241,16 @@ impl Xperf {
 
         eprintln!("xperf session running...");
 
+        if let Some(interval_seconds) = props.rundown_interval_seconds {
+            let user_providers = user_etl_file.is_some().then(|| user_providers.join("+"));
+            self.rundown_thread = Some(RundownThread::start(
+                xperf_path,
+                output_path,
+                user_providers,
+                Duration::from_secs_f64(interval_seconds),
+            ));
+        }
+
         if user_etl_file.is_some() {
             self.state =
                 XperfState::RecordingKernelAndUserToFile(kernel_etl_file, user_etl_file.unwrap());
@@ -153,9 +261,15 @@ impl Xperf {
         Ok(())
     }
 
+    /// Returns the kernel ETL file, the user ETL file if there is one, and the ETL
+    /// files of the periodic rundowns.
     pub fn stop_xperf(
         &mut self,
-    ) -> Result<(PathBuf, Option<PathBuf>), Box<dyn Error + Send + Sync>> {
+    ) -> Result<(PathBuf, Option<PathBuf>, Vec<PathBuf>), Box<dyn Error + Send + Sync>> {
+        let rundown_etls = match self.rundown_thread.take() {
+            Some(rundown_thread) => rundown_thread.stop(),
+            None => Vec::new(),
+        };
         let prev_state = std::mem::replace(&mut self.state, XperfState::Stopped);
         let (kernel_etl, user_etl) = match prev_state {
             XperfState::Stopped => return Err("xperf wasn't running, can't stop it".into()),
@@ -178,7 +292,7 @@ impl Xperf {
 
         eprintln!("xperf session stopped.");
 
-        Ok((kernel_etl, user_etl))
+        Ok((kernel_etl, user_etl, rundown_etls))
     }
 }
 
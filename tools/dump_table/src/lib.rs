@@ -6,8 +6,8 @@ pub use samply_symbols::debugid;
 use samply_symbols::debugid::DebugId;
 use samply_symbols::{
     self, BinaryImage, CandidatePathInfo, CompactSymbolTable, Error, FileAndPathHelper,
-    FileAndPathHelperResult, FileLocation, LibraryInfo, MultiArchDisambiguator,
-    OptionallySendFuture, SymbolManager,
+    FileAndPathHelperResult, FileLocation, LibraryInfo, LookupAddress, MultiArchDisambiguator,
+    OptionallySendFuture, SymbolInfo, SymbolManager,
 };
 #[cfg(feature = "chunked_caching")]
 use samply_symbols::{FileByteSource, FileContents};
@@ -58,6 +58,34 @@ pub async fn get_table_for_binary(
     Ok(CompactSymbolTable::from_symbol_map(&symbol_map))
 }
 
+/// Like [`get_table_for_binary`], but returns the full [`SymbolInfo`] for each
+/// symbol, including its size and kind.
+pub async fn get_symbol_infos_for_binary(
+    binary_path: &Path,
+    debug_id: Option<DebugId>,
+) -> Result<Vec<SymbolInfo>, Error> {
+    let helper = Helper {
+        symbol_directory: binary_path.parent().unwrap().to_path_buf(),
+    };
+    let symbol_manager = SymbolManager::with_helper(helper);
+    let binary =
+        get_library_info_with_dyld_cache_fallback(&symbol_manager, binary_path, debug_id).await?;
+    let info = binary.library_info();
+    drop(binary);
+    let symbol_map = symbol_manager.load_symbol_map(&info).await?;
+    let mut addresses: Vec<u32> = symbol_map.iter_symbols().map(|(addr, _)| addr).collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+    let symbol_infos = addresses
+        .into_iter()
+        .filter_map(|address| {
+            let address_info = symbol_map.lookup_sync(LookupAddress::Relative(address))?;
+            Some(address_info.symbol)
+        })
+        .collect();
+    Ok(symbol_infos)
+}
+
 pub async fn get_table_for_debug_name_and_id(
     debug_name: &str,
     debug_id: Option<DebugId>,
@@ -96,6 +124,39 @@ pub fn dump_table(w: &mut impl Write, table: CompactSymbolTable, full: bool) ->
     Ok(())
 }
 
+/// Prints one line per symbol with its address, size, kind and name.
+pub fn dump_symbol_infos(
+    w: &mut impl Write,
+    symbol_infos: &[SymbolInfo],
+    full: bool,
+) -> anyhow::Result<()> {
+    let mut w = BufWriter::new(w);
+    writeln!(w, "Found {} symbols.", symbol_infos.len())?;
+    for (i, symbol) in symbol_infos.iter().enumerate() {
+        if i >= 15 && !full {
+            writeln!(
+                w,
+                "and {} more symbols. Pass --full to print the full list.",
+                symbol_infos.len() - i
+            )?;
+            break;
+        }
+
+        let size = match symbol.size {
+            Some(size) => format!("{size:x}"),
+            None => "?".to_string(),
+        };
+        writeln!(
+            w,
+            "{:x} {size} {} {}",
+            symbol.address,
+            symbol.kind.as_str(),
+            symbol.name
+        )?;
+    }
+    Ok(())
+}
+
 struct Helper {
     symbol_directory: PathBuf,
 }
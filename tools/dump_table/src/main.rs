@@ -1,7 +1,9 @@
 use std::path::{Path, PathBuf};
 
 use clap::Parser;
-use dump_table::{dump_table, get_table_for_binary};
+use dump_table::{
+    dump_symbol_infos, dump_table, get_symbol_infos_for_binary, get_table_for_binary,
+};
 use samply_symbols::debugid::DebugId;
 use samply_symbols::Error;
 
@@ -20,12 +22,20 @@ struct Opt {
     /// When specified, print the entire symbol table.
     #[arg(short, long)]
     full: bool,
+
+    /// Print the size and kind (function, thunk, public, synthesized) of each symbol.
+    #[arg(short, long)]
+    verbose: bool,
 }
 
 fn main() -> anyhow::Result<()> {
     let opt = Opt::parse();
-    let result =
-        futures::executor::block_on(main_impl(&opt.binary_path, opt.breakpad_id, opt.full));
+    let result = futures::executor::block_on(main_impl(
+        &opt.binary_path,
+        opt.breakpad_id,
+        opt.full,
+        opt.verbose,
+    ));
     match result {
         Ok(()) => Ok(()),
         Err(Error::NoDisambiguatorForFatArchive(members)) => {
@@ -47,10 +57,16 @@ async fn main_impl(
     binary_path: &Path,
     breakpad_id: Option<String>,
     full: bool,
+    verbose: bool,
 ) -> Result<(), Error> {
     let debug_id = breakpad_id
         .as_deref()
         .and_then(|debug_id| DebugId::from_breakpad(debug_id).ok());
+    if verbose {
+        let symbol_infos = get_symbol_infos_for_binary(binary_path, debug_id).await?;
+        dump_symbol_infos(&mut std::io::stdout(), &symbol_infos, full).unwrap();
+        return Ok(());
+    }
     let table = get_table_for_binary(binary_path, debug_id).await?;
     dump_table(&mut std::io::stdout(), table, full).unwrap();
     Ok(())
@@ -0,0 +1,536 @@
+//! Binaries and debug files inside archives: static libraries (`.a`), Debian (`.deb`) and
+//! RPM (`.rpm`) packages, zip files like Android `.apk` / `.aab` and iOS `.ipa` files, and
+//! symbol bundles in zip or tar files.
+//!
+//! The archives are indexed by ELF build ID and mach-O UUID, and by debug name and debug
+//! ID, the first time a file is looked up. Files are only extracted once they're needed.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use debugid::DebugId;
+use samply_symbols::object::read::archive::ArchiveFile;
+use samply_symbols::object::read::macho::{FatArch, MachOFatFile32, MachOFatFile64};
+use samply_symbols::object::{self, FileKind, Object};
+use samply_symbols::{code_id_for_object, debug_id_for_object, pdb, CodeId};
+use tokio::sync::OnceCell;
+
+pub struct SymbolArchives {
+    archive_paths: Vec<PathBuf>,
+    extraction_dir: PathBuf,
+    index: OnceCell<ArchiveIndex>,
+}
+
+#[derive(Debug, Default)]
+struct ArchiveIndex {
+    by_code_id: HashMap<CodeId, IndexedMember>,
+    by_debug_name_and_id: HashMap<(String, DebugId), IndexedMember>,
+}
+
+/// Where a file was found.
+#[derive(Debug, Clone)]
+struct IndexedMember {
+    archive_path: PathBuf,
+    member_path: String,
+    has_debug_info: bool,
+}
+
+impl SymbolArchives {
+    pub fn new(archive_paths: Vec<PathBuf>, extraction_dir: PathBuf) -> Self {
+        Self {
+            archive_paths,
+            extraction_dir,
+            index: OnceCell::new(),
+        }
+    }
+
+    /// Whether `path` is a file which can be used as a symbol archive.
+    pub fn is_archive(path: &Path) -> bool {
+        ArchiveKind::for_path(path).is_some() && path.is_file()
+    }
+
+    /// Returns the path of the file with this code ID, extracting it from its archive
+    /// if it hasn't been extracted before. If several files have the same code ID, e.g.
+    /// a stripped binary and its debug file, the one with debug info is used.
+    pub async fn get_file(&self, code_id: &CodeId) -> Option<PathBuf> {
+        let dir = self.extraction_dir.join(code_id.to_string());
+        if let Some(path) = extracted_file_in_dir(&dir) {
+            return Some(path);
+        }
+        let member = self.index().await.by_code_id.get(code_id)?.clone();
+        extract_member_into_dir(member, dir).await
+    }
+
+    /// Returns the path of the debug file with this debug name and debug ID, e.g. a PDB
+    /// file or a Breakpad .sym file, extracting it from its archive if needed.
+    pub async fn get_debug_file(&self, debug_name: &str, debug_id: DebugId) -> Option<PathBuf> {
+        let dir = self
+            .extraction_dir
+            .join(debug_name)
+            .join(debug_id.breakpad().to_string());
+        if let Some(path) = extracted_file_in_dir(&dir) {
+            return Some(path);
+        }
+        let key = (debug_name.to_string(), debug_id);
+        let member = self.index().await.by_debug_name_and_id.get(&key)?.clone();
+        extract_member_into_dir(member, dir).await
+    }
+
+    async fn index(&self) -> &ArchiveIndex {
+        self.index
+            .get_or_init(|| {
+                let archive_paths = self.archive_paths.clone();
+                run_on_thread(move || build_index(&archive_paths))
+            })
+            .await
+    }
+}
+
+async fn extract_member_into_dir(member: IndexedMember, dir: PathBuf) -> Option<PathBuf> {
+    let file_name = Path::new(&member.member_path).file_name()?.to_owned();
+    let dest_path = dir.join(file_name);
+    run_on_thread(move || extract_member(&member, &dest_path).map(|()| dest_path))
+        .await
+        .ok()
+}
+
+/// Runs `f` on a new thread, so that reading and decompressing large archives doesn't
+/// block the async runtime.
+async fn run_on_thread<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    std::thread::Builder::new()
+        .name("symbol-archives".to_string())
+        .spawn(move || {
+            let _ = tx.send(f());
+        })
+        .expect("couldn't create symbol archive thread");
+    rx.await.expect("symbol archive thread panicked")
+}
+
+fn extracted_file_in_dir(dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .find(|path| path.is_file() && path.extension() != Some(OsStr::new("part")))
+}
+
+fn build_index(archive_paths: &[PathBuf]) -> ArchiveIndex {
+    let mut index = ArchiveIndex::default();
+    for archive_path in archive_paths {
+        // Archives which can't be read are skipped; their files just won't be found.
+        let _ = index_archive(archive_path, &mut index);
+    }
+    index
+}
+
+fn index_archive(archive_path: &Path, index: &mut ArchiveIndex) -> io::Result<()> {
+    visit_members(archive_path, &mut |member_path, reader| {
+        let member = |has_debug_info| IndexedMember {
+            archive_path: archive_path.to_owned(),
+            member_path: member_path.to_owned(),
+            has_debug_info,
+        };
+
+        // Symbol bundles often use the directory layout of symbol servers,
+        // <debug name>/<debug ID>/<file name>, e.g. xul.pdb/<ID>/xul.sym.
+        let components: Vec<&str> = member_path.split('/').collect();
+        if let [.., debug_name, id, _file_name] = components[..] {
+            if let Ok(debug_id) = DebugId::from_breakpad(id) {
+                insert_member(
+                    &mut index.by_debug_name_and_id,
+                    (debug_name.to_string(), debug_id),
+                    member(true),
+                );
+            }
+        }
+
+        let mut data = Vec::new();
+        (&mut *reader).take(4).read_to_end(&mut data)?;
+        if !is_object_magic(&data) {
+            return Ok(false);
+        }
+        reader.read_to_end(&mut data)?;
+        let file_name = components.last().copied().unwrap_or_default();
+        for ids in ids_of_object(&data) {
+            if let Some(code_id) = ids.code_id {
+                insert_member(&mut index.by_code_id, code_id, member(ids.has_debug_info));
+            }
+            if let Some(debug_id) = ids.debug_id {
+                insert_member(
+                    &mut index.by_debug_name_and_id,
+                    (file_name.to_string(), debug_id),
+                    member(ids.has_debug_info),
+                );
+            }
+        }
+        Ok(false)
+    })
+}
+
+/// Inserts `member` unless there already is a member for `key` which is at least as
+/// useful, i.e. which has debug info or which is also missing debug info.
+fn insert_member<K: Hash + Eq>(map: &mut HashMap<K, IndexedMember>, key: K, member: IndexedMember) {
+    match map.get(&key) {
+        Some(existing) if existing.has_debug_info || !member.has_debug_info => {}
+        _ => {
+            map.insert(key, member);
+        }
+    }
+}
+
+fn is_object_magic(data: &[u8]) -> bool {
+    matches!(
+        data,
+        [0x7f, b'E', b'L', b'F']
+            | [0xfe, 0xed, 0xfa, 0xce | 0xcf]
+            | [0xce | 0xcf, 0xfa, 0xed, 0xfe]
+            | [0xca, 0xfe, 0xba, 0xbe | 0xbf]
+            | [b'M', b'i', b'c', b'r']
+    )
+}
+
+struct ObjectIds {
+    code_id: Option<CodeId>,
+    debug_id: Option<DebugId>,
+    has_debug_info: bool,
+}
+
+/// Returns the IDs of the object in `data`. Fat mach-O files have IDs for each
+/// architecture.
+fn ids_of_object(data: &[u8]) -> Vec<ObjectIds> {
+    match FileKind::parse(data) {
+        Ok(FileKind::MachOFat32) => match MachOFatFile32::parse(data) {
+            Ok(fat_file) => fat_members_ids(fat_file.arches(), data),
+            Err(_) => Vec::new(),
+        },
+        Ok(FileKind::MachOFat64) => match MachOFatFile64::parse(data) {
+            Ok(fat_file) => fat_members_ids(fat_file.arches(), data),
+            Err(_) => Vec::new(),
+        },
+        Ok(_) => single_object_ids(data).into_iter().collect(),
+        Err(_) => pdb_ids(data).into_iter().collect(),
+    }
+}
+
+fn fat_members_ids(arches: &[impl FatArch], data: &[u8]) -> Vec<ObjectIds> {
+    arches
+        .iter()
+        .filter_map(|arch| single_object_ids(arch.data(data).ok()?))
+        .collect()
+}
+
+fn single_object_ids(data: &[u8]) -> Option<ObjectIds> {
+    let obj = object::File::parse(data).ok()?;
+    Some(ObjectIds {
+        code_id: code_id_for_object(&obj),
+        debug_id: debug_id_for_object(&obj),
+        has_debug_info: obj.section_by_name(".debug_info").is_some(),
+    })
+}
+
+fn pdb_ids(data: &[u8]) -> Option<ObjectIds> {
+    let mut pdb = pdb::PDB::open(io::Cursor::new(data)).ok()?;
+    let info = pdb.pdb_information().ok()?;
+    let age = match pdb.debug_information() {
+        Ok(dbi) => dbi.age().unwrap_or(info.age),
+        Err(_) => info.age,
+    };
+    Some(ObjectIds {
+        code_id: None,
+        debug_id: Some(DebugId::from_parts(info.guid, age)),
+        has_debug_info: true,
+    })
+}
+
+fn extract_member(member: &IndexedMember, dest_path: &Path) -> io::Result<()> {
+    if let Some(dir) = dest_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Write to a temporary file first, so that a partially extracted file is never
+    // mistaken for a complete one.
+    let mut part_path = dest_path.as_os_str().to_owned();
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
+    let mut file = File::create(&part_path)?;
+    let mut found = false;
+    visit_members(&member.archive_path, &mut |member_path, reader| {
+        if member_path != member.member_path {
+            return Ok(false);
+        }
+        io::copy(reader, &mut file)?;
+        found = true;
+        Ok(true)
+    })?;
+    drop(file);
+    if !found {
+        let _ = std::fs::remove_file(&part_path);
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "{} not found in {:?}",
+                member.member_path, member.archive_path
+            ),
+        ));
+    }
+    std::fs::rename(&part_path, dest_path)
+}
+
+/// Called with the path and the contents of each file in an archive. Returns true to stop
+/// visiting the remaining files.
+type MemberVisitor<'a> = dyn FnMut(&str, &mut dyn Read) -> io::Result<bool> + 'a;
+
+enum ArchiveKind {
+    Ar,
+    Deb,
+    Rpm,
+    Zip,
+    Tar,
+}
+
+impl ArchiveKind {
+    fn for_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        let (_, extension) = file_name.rsplit_once('.')?;
+        match extension {
+            "a" => Some(Self::Ar),
+            "deb" | "ddeb" | "udeb" => Some(Self::Deb),
+            "rpm" => Some(Self::Rpm),
+            "zip" | "apk" | "aab" | "ipa" => Some(Self::Zip),
+            "tar" | "tgz" => Some(Self::Tar),
+            "gz" | "xz" | "zst" if file_name.contains(".tar.") => Some(Self::Tar),
+            _ => None,
+        }
+    }
+}
+
+fn visit_members(archive_path: &Path, visit: &mut MemberVisitor) -> io::Result<()> {
+    let kind = ArchiveKind::for_path(archive_path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown archive type: {archive_path:?}"),
+        )
+    })?;
+    let file = File::open(archive_path)?;
+    match kind {
+        ArchiveKind::Ar => {
+            let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
+            visit_ar_members(&mmap, visit)
+        }
+        ArchiveKind::Deb => {
+            // A .deb file is an ar archive whose data.tar.* member has the installed files.
+            let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
+            let mut result = Ok(());
+            visit_ar_members(&mmap, &mut |member_path, reader| {
+                if !member_path.starts_with("data.tar") {
+                    return Ok(false);
+                }
+                let reader = decompressed_reader(BufReader::new(reader))?;
+                result = visit_tar_members(reader, visit);
+                Ok(true)
+            })?;
+            result
+        }
+        ArchiveKind::Rpm => visit_rpm_members(BufReader::new(file), visit),
+        ArchiveKind::Zip => {
+            let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
+            visit_zip_members(&mmap, visit)
+        }
+        ArchiveKind::Tar => visit_tar_members(decompressed_reader(BufReader::new(file))?, visit),
+    }
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Detects gzip, xz and zstd compressed data by its magic bytes.
+fn decompressed_reader<'a>(mut reader: impl BufRead + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        // lzma-rs can't decompress incrementally.
+        let mut data = Vec::new();
+        lzma_rs::xz_decompress(&mut reader, &mut data).map_err(invalid_data)?;
+        Ok(Box::new(io::Cursor::new(data)))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Ok(Box::new(
+            ruzstd::StreamingDecoder::new(reader).map_err(invalid_data)?,
+        ))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+fn visit_ar_members(data: &[u8], visit: &mut MemberVisitor) -> io::Result<()> {
+    let archive = ArchiveFile::parse(data).map_err(invalid_data)?;
+    for member in archive.members() {
+        let member = member.map_err(invalid_data)?;
+        let name = String::from_utf8_lossy(member.name());
+        let mut member_data = member.data(data).map_err(invalid_data)?;
+        if visit(&name, &mut member_data)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn visit_tar_members(reader: impl Read, visit: &mut MemberVisitor) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let path = path.strip_prefix("./").unwrap_or(&path).to_owned();
+        if visit(&path, &mut entry)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// An RPM package has a 96 byte "lead", a signature header, the main header, and then
+/// a compressed cpio archive with the installed files.
+fn visit_rpm_members(mut reader: impl BufRead, visit: &mut MemberVisitor) -> io::Result<()> {
+    let mut lead = [0; 96];
+    reader.read_exact(&mut lead)?;
+    if lead[..4] != [0xed, 0xab, 0xee, 0xdb] {
+        return Err(invalid_data("Not an RPM package"));
+    }
+    // The signature header is padded to a multiple of 8 bytes, the main header isn't.
+    let signature_header_len = skip_rpm_header(&mut reader)?;
+    let padding_len = (8 - signature_header_len % 8) % 8;
+    io::copy(&mut (&mut reader).take(padding_len), &mut io::sink())?;
+    skip_rpm_header(&mut reader)?;
+    visit_cpio_members(decompressed_reader(reader)?, visit)
+}
+
+/// Skips an RPM header structure and returns its length.
+fn skip_rpm_header(reader: &mut impl Read) -> io::Result<u64> {
+    let mut intro = [0; 16];
+    reader.read_exact(&mut intro)?;
+    if intro[..3] != [0x8e, 0xad, 0xe8] {
+        return Err(invalid_data("Invalid RPM header"));
+    }
+    let index_entry_count = u32::from_be_bytes(intro[8..12].try_into().unwrap());
+    let data_len = u32::from_be_bytes(intro[12..16].try_into().unwrap());
+    let len = index_entry_count as u64 * 16 + data_len as u64;
+    io::copy(&mut reader.take(len), &mut io::sink())?;
+    Ok(16 + len)
+}
+
+/// Visits the files in a cpio archive in the "newc" format, which RPM packages use.
+fn visit_cpio_members(mut reader: impl Read, visit: &mut MemberVisitor) -> io::Result<()> {
+    loop {
+        let mut header = [0; 110];
+        reader.read_exact(&mut header)?;
+        if !header.starts_with(b"070701") && !header.starts_with(b"070702") {
+            return Err(invalid_data("Invalid cpio header"));
+        }
+        let mode = cpio_header_field(&header, 1)?;
+        let file_size = cpio_header_field(&header, 6)?;
+        let name_size = cpio_header_field(&header, 11)?;
+        let mut name = vec![0; name_size as usize];
+        reader.read_exact(&mut name)?;
+        skip_cpio_padding(&mut reader, 110 + name_size)?;
+        let name = String::from_utf8_lossy(name.strip_suffix(&[0]).unwrap_or(&name)).into_owned();
+        if name == "TRAILER!!!" {
+            return Ok(());
+        }
+
+        let mut data = (&mut reader).take(file_size);
+        let is_regular_file = mode & 0o170000 == 0o100000;
+        let path = name.strip_prefix("./").unwrap_or(&name);
+        if is_regular_file && visit(path, &mut data)? {
+            return Ok(());
+        }
+        io::copy(&mut data, &mut io::sink())?;
+        skip_cpio_padding(&mut reader, file_size)?;
+    }
+}
+
+fn cpio_header_field(header: &[u8; 110], index: usize) -> io::Result<u64> {
+    let start = 6 + 8 * index;
+    std::str::from_utf8(&header[start..start + 8])
+        .ok()
+        .and_then(|field| u64::from_str_radix(field, 16).ok())
+        .ok_or_else(|| invalid_data("Invalid cpio header field"))
+}
+
+/// Names and file contents in cpio archives are padded to a multiple of 4 bytes.
+fn skip_cpio_padding(reader: &mut impl Read, len: u64) -> io::Result<()> {
+    let padding_len = (4 - len % 4) % 4;
+    io::copy(&mut reader.take(padding_len), &mut io::sink())?;
+    Ok(())
+}
+
+/// Visits the files in a zip file. Only stored and deflated files are supported, and
+/// zip64 isn't supported, i.e. the zip file has to be smaller than 4GB.
+fn visit_zip_members(data: &[u8], visit: &mut MemberVisitor) -> io::Result<()> {
+    // The end of central directory record is 22 bytes long, followed by a comment of
+    // up to 64KB.
+    let search_start = data.len().saturating_sub(22 + 0xffff);
+    let eocd_pos = (search_start..data.len().saturating_sub(21))
+        .rev()
+        .find(|&pos| data[pos..].starts_with(b"PK\x05\x06"))
+        .ok_or_else(|| invalid_data("No end of central directory record"))?;
+    let eocd = &data[eocd_pos..];
+    let entry_count = le_u16(eocd, 10)?;
+    let mut pos = le_u32(eocd, 16)? as usize;
+    for _ in 0..entry_count {
+        let entry = data
+            .get(pos..)
+            .filter(|entry| entry.starts_with(b"PK\x01\x02"))
+            .ok_or_else(|| invalid_data("Invalid central directory entry"))?;
+        let method = le_u16(entry, 10)?;
+        let compressed_size = le_u32(entry, 20)? as usize;
+        let name_len = le_u16(entry, 28)? as usize;
+        let extra_len = le_u16(entry, 30)? as usize;
+        let comment_len = le_u16(entry, 32)? as usize;
+        let local_header_offset = le_u32(entry, 42)? as usize;
+        let name = entry
+            .get(46..46 + name_len)
+            .ok_or_else(|| invalid_data("Invalid central directory entry"))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        pos += 46 + name_len + extra_len + comment_len;
+
+        let local_header = data
+            .get(local_header_offset..)
+            .filter(|header| header.starts_with(b"PK\x03\x04"))
+            .ok_or_else(|| invalid_data("Invalid local file header"))?;
+        let data_start =
+            30 + le_u16(local_header, 26)? as usize + le_u16(local_header, 28)? as usize;
+        let mut compressed = local_header
+            .get(data_start..data_start + compressed_size)
+            .ok_or_else(|| invalid_data("Invalid local file header"))?;
+        let stop = match method {
+            0 => visit(&name, &mut compressed)?,
+            8 => visit(&name, &mut flate2::bufread::DeflateDecoder::new(compressed))?,
+            _ => false,
+        };
+        if stop {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn le_u16(data: &[u8], offset: usize) -> io::Result<u16> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .ok_or_else(|| invalid_data("Unexpected end of zip file"))?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn le_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .ok_or_else(|| invalid_data("Unexpected end of zip file"))?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
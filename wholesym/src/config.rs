@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use symsrv::{parse_nt_symbol_path, NtSymbolPathEntry};
 
+use crate::downloader::DownloadSettings;
+use crate::source_repository::SourceRepository;
+
 /// The configuration of a [`SymbolManager`](crate::SymbolManager).
 ///
 /// Allows specifying various sources of symbol files.
@@ -21,6 +25,12 @@ pub struct SymbolManagerConfig {
     pub(crate) debuginfod_servers: Vec<(String, PathBuf)>,
     pub(crate) extra_symbol_directories: Vec<PathBuf>,
     pub(crate) simpleperf_binary_cache_directories: Vec<PathBuf>,
+    pub(crate) symbol_archives: Vec<PathBuf>,
+    pub(crate) symbol_archive_extraction_dir: Option<PathBuf>,
+    pub(crate) download_settings: DownloadSettings,
+    pub(crate) path_remappings: Vec<(String, PathBuf)>,
+    pub(crate) source_repositories: Vec<(String, SourceRepository)>,
+    pub(crate) source_repository_cache_dir: Option<PathBuf>,
 }
 
 impl SymbolManagerConfig {
@@ -93,6 +103,10 @@ impl SymbolManagerConfig {
 
     /// Add a server to search for breakpad symbol files, along with a local cache directory.
     ///
+    /// The base URL can also be an `s3://bucket/prefix` or `gs://bucket/prefix`
+    /// URL, for symbol files in S3 or Google Cloud Storage. The credentials are
+    /// found in the same places as the AWS and gcloud command line tools look.
+    ///
     /// This method can be called multiple times; the servers and caches will be tried in the order of those calls.
     pub fn breakpad_symbol_server(
         mut self,
@@ -172,6 +186,17 @@ impl SymbolManagerConfig {
 
     /// Add an additional directory that may contain symbol files.
     /// We will check "\<dir\>/\<binaryname\>" and "\<dir\>/\<debug_name\>".
+    ///
+    /// Files with these names in subdirectories of the directory are found too, at
+    /// any depth, e.g. in a build output directory. The directory is walked once, the
+    /// first time a file is looked up, and the file paths are then looked up in the
+    /// resulting index.
+    ///
+    /// Instead of a directory, this can also be a zip or tar file (`.zip`, `.tar`,
+    /// `.tar.gz`, `.tar.xz` or `.tar.zst`), e.g. a symbol bundle from a CI build. Its
+    /// files are found by their debug name and debug ID, or by their build ID, like
+    /// the files in a [`symbol_archive`](Self::symbol_archive), without extracting
+    /// the whole file.
     pub fn extra_symbol_directory(mut self, dir: impl Into<PathBuf>) -> Self {
         self.extra_symbol_directories.push(dir.into());
         self
@@ -184,4 +209,145 @@ impl SymbolManagerConfig {
         self.simpleperf_binary_cache_directories.push(dir.into());
         self
     }
+
+    /// Add an archive which may contain binaries or debug files: a static library (`.a`),
+    /// a Debian (`.deb` / `.ddeb`) or RPM (`.rpm`) package, such as a `-dbgsym` or
+    /// `-debuginfo` package, or a zip file such as an Android `.apk` / `.aab` or an
+    /// iOS `.ipa`.
+    ///
+    /// The files in the archives are found by ELF build ID or mach-O UUID. The archives
+    /// are indexed the first time a file is looked up, and matching files are extracted
+    /// into the [`symbol_archive_extraction_dir`](Self::symbol_archive_extraction_dir)
+    /// when they're needed.
+    pub fn symbol_archive(mut self, path: impl Into<PathBuf>) -> Self {
+        self.symbol_archives.push(path.into());
+        self
+    }
+
+    /// Set the directory into which files from symbol archives are extracted. Defaults
+    /// to a directory in the system's temporary directory.
+    pub fn symbol_archive_extraction_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.symbol_archive_extraction_dir = Some(dir.into());
+        self
+    }
+
+    /// Add a rule which replaces the `from_prefix` of local file paths with
+    /// `to_dir`, e.g. from `/builds/worker/checkouts/gecko` to
+    /// `/home/me/mozilla-central`, so that the source files of binaries which
+    /// were built elsewhere are found in a local checkout.
+    ///
+    /// The prefix has to match whole path components, with either kind of
+    /// slash. If several rules match, the one with the longest prefix is used.
+    pub fn path_remapping(
+        mut self,
+        from_prefix: impl Into<String>,
+        to_dir: impl Into<PathBuf>,
+    ) -> Self {
+        self.path_remappings
+            .push((from_prefix.into(), to_dir.into()));
+        self
+    }
+
+    /// Applies the [`path_remapping`](Self::path_remapping) rules to `path`.
+    /// Returns `None` if no rule matches.
+    pub fn remapped_path(&self, path: &str) -> Option<PathBuf> {
+        let (to_dir, components) = longest_prefix_match(&self.path_remappings, path)?;
+        let mut remapped = to_dir.clone();
+        for component in components {
+            remapped.push(component);
+        }
+        Some(remapped)
+    }
+
+    /// Add a repository from which source files under `path_prefix` are
+    /// fetched if they aren't on disk, at the revision from which the binary
+    /// was built. Files which are found via a [`path_remapping`](Self::path_remapping)
+    /// are used first.
+    ///
+    /// For git remotes which aren't on GitHub or GitLab, this needs `git` and a
+    /// [`source_repository_cache_dir`](Self::source_repository_cache_dir).
+    pub fn source_repository(
+        mut self,
+        path_prefix: impl Into<String>,
+        repository: SourceRepository,
+    ) -> Self {
+        self.source_repositories
+            .push((path_prefix.into(), repository));
+        self
+    }
+
+    /// Set the directory in which partial clones of git repositories are kept,
+    /// for [`source_repository`](Self::source_repository).
+    pub fn source_repository_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.source_repository_cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Returns the repository and the path inside it of a local file path,
+    /// if a [`source_repository`](Self::source_repository) rule matches.
+    pub(crate) fn source_repository_path(&self, path: &str) -> Option<(&SourceRepository, String)> {
+        let (repository, components) = longest_prefix_match(&self.source_repositories, path)?;
+        Some((repository, components.join("/")))
+    }
+
+    /// Send all downloads through this proxy, e.g. `"http://proxy.example.com:8080"`.
+    ///
+    /// By default, the `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY`
+    /// environment variables are respected. Downloads from Windows symbol
+    /// servers only use these environment variables, and none of the other
+    /// download settings.
+    pub fn download_proxy(mut self, proxy_url: impl Into<String>) -> Self {
+        self.download_settings.proxy = Some(proxy_url.into());
+        self
+    }
+
+    /// How long to wait for a connection to a symbol server. Defaults to 30 seconds.
+    pub fn download_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.download_settings.connect_timeout = timeout;
+        self
+    }
+
+    /// How long to wait for more data from a symbol server during a download,
+    /// before the download fails. Defaults to 60 seconds.
+    pub fn download_read_timeout(mut self, timeout: Duration) -> Self {
+        self.download_settings.read_timeout = timeout;
+        self
+    }
+
+    /// How often to retry a request which failed with a connection error, a
+    /// timeout, or a status which indicates a temporary problem on the server.
+    /// The delay between retries doubles each time. Defaults to 3.
+    pub fn download_retries(mut self, retries: u32) -> Self {
+        self.download_settings.retries = retries;
+        self
+    }
+
+    /// Limit the number of downloads which are in progress at the same time.
+    /// Unlimited by default.
+    pub fn max_concurrent_downloads(mut self, max: usize) -> Self {
+        self.download_settings.max_concurrent_downloads = Some(max);
+        self
+    }
+}
+
+/// Finds the rule with the longest prefix of `path`, where the prefix has to
+/// match whole path components, with either kind of slash. Returns the rule's
+/// value and the remaining path components.
+fn longest_prefix_match<'r, 'p, T>(
+    rules: &'r [(String, T)],
+    path: &'p str,
+) -> Option<(&'r T, Vec<&'p str>)> {
+    let (_, value, rest) = rules
+        .iter()
+        .filter_map(|(prefix, value)| {
+            let prefix = prefix.trim_end_matches(['/', '\\']);
+            let rest = path.strip_prefix(prefix)?;
+            if !rest.is_empty() && !rest.starts_with(['/', '\\']) {
+                return None;
+            }
+            Some((prefix.len(), value, rest))
+        })
+        .max_by_key(|(prefix_len, _, _)| *prefix_len)?;
+    let components = rest.split(['/', '\\']).filter(|c| !c.is_empty()).collect();
+    Some((value, components))
 }
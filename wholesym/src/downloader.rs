@@ -5,10 +5,13 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use futures_util::{AsyncRead, AsyncReadExt as _};
+use http::StatusCode;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 use crate::download::response_to_uncompressed_stream_with_progress;
 use crate::file_creation::{create_file_cleanly, CleanFileCreationError};
+use crate::object_store::object_store_request;
 use crate::DownloadError;
 
 /// A trait for observing the behavior of a `BreakpadSymbolDownloader` or `DebuginfodDownloader`.
@@ -161,19 +164,71 @@ impl Drop for DownloadStatusReporter {
     }
 }
 
+/// The settings for HTTP downloads, from the [`SymbolManagerConfig`](crate::SymbolManagerConfig).
+#[derive(Debug, Clone)]
+pub struct DownloadSettings {
+    /// The proxy for all requests. If `None`, the `HTTP_PROXY`, `HTTPS_PROXY`,
+    /// `ALL_PROXY` and `NO_PROXY` environment variables are respected.
+    pub proxy: Option<String>,
+    /// How long to wait for a connection to the server.
+    pub connect_timeout: Duration,
+    /// How long to wait for the next chunk of data, while connected.
+    pub read_timeout: Duration,
+    /// How often to retry a request which failed with a connection error, a
+    /// timeout, a server error or a "Too Many Requests" status.
+    pub retries: u32,
+    /// The maximum number of downloads which are in progress at the same time.
+    pub max_concurrent_downloads: Option<usize>,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            connect_timeout: Duration::from_secs(30),
+            read_timeout: Duration::from_secs(60),
+            retries: 3,
+            max_concurrent_downloads: None,
+        }
+    }
+}
+
+/// The delay before the first retry. Each further retry waits twice as long,
+/// up to `MAX_RETRY_DELAY`.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(16);
+
+fn retry_delay(retry_index: u32) -> Duration {
+    INITIAL_RETRY_DELAY
+        .saturating_mul(1 << retry_index.min(16))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Whether a failed request might succeed if it's sent again.
+fn is_retryable(error: &reqwest::Error) -> bool {
+    match error.status() {
+        Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+        None => error.is_timeout() || error.is_connect(),
+    }
+}
+
 pub struct Downloader {
     reqwest_client: Result<reqwest::Client, reqwest::Error>,
+    retries: u32,
+    download_slots: Option<Arc<Semaphore>>,
 }
 
 impl Default for Downloader {
     fn default() -> Self {
-        Downloader::new()
+        Downloader::new(&DownloadSettings::default())
     }
 }
 
 impl Downloader {
-    pub fn new() -> Self {
-        let builder = reqwest::Client::builder();
+    pub fn new(settings: &DownloadSettings) -> Self {
+        let builder = reqwest::Client::builder()
+            .connect_timeout(settings.connect_timeout)
+            .read_timeout(settings.read_timeout);
 
         // Turn off HTTP 2, in order to work around https://github.com/seanmonstar/reqwest/issues/1761 .
         let builder = builder.http1_only();
@@ -185,10 +240,59 @@ impl Downloader {
         let builder = builder.no_gzip().no_brotli().no_deflate();
 
         // Create the client.
-        // TODO: Add timeouts, user agent, maybe other settings
-        let reqwest_client = builder.build();
+        // TODO: Add user agent, maybe other settings
+        let reqwest_client = match &settings.proxy {
+            Some(proxy) => {
+                reqwest::Proxy::all(proxy).and_then(|proxy| builder.proxy(proxy).build())
+            }
+            None => builder.build(),
+        };
+
+        let download_slots = settings
+            .max_concurrent_downloads
+            .map(|max| Arc::new(Semaphore::new(max.max(1))));
 
-        Self { reqwest_client }
+        Self {
+            reqwest_client,
+            retries: settings.retries,
+            download_slots,
+        }
+    }
+
+    /// Sends a GET request and waits for the headers of a successful response,
+    /// retrying with exponential backoff.
+    async fn send_with_retries(
+        &self,
+        reqwest_client: &reqwest::Client,
+        url: &str,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let mut retry_index = 0;
+        loop {
+            // s3:// and gs:// URLs are requested over HTTPS, with credentials.
+            // They're signed again for each attempt, because signatures expire.
+            let request_builder = match object_store_request(reqwest_client, url).await {
+                Some(request) => reqwest_client
+                    .get(request.https_url)
+                    .headers(request.headers),
+                None => reqwest_client.get(url),
+            };
+
+            // Manually specify the Accept-Encoding header.
+            // This would happen automatically if we hadn't turned off automatic
+            // decompression for this reqwest client.
+            let result = request_builder
+                .header("Accept-Encoding", "gzip")
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match result {
+                Err(e) if retry_index < self.retries && is_retryable(&e) => {
+                    tokio::time::sleep(retry_delay(retry_index)).await;
+                    retry_index += 1;
+                }
+                result => return result,
+            }
+        }
     }
 
     pub async fn initiate_download(
@@ -206,18 +310,14 @@ impl Downloader {
             }
         };
 
-        let request_builder = reqwest_client.get(url);
-
-        // Manually specify the Accept-Encoding header.
-        // This would happen automatically if we hadn't turned off automatic
-        // decompression for this reqwest client.
-        let request_builder = request_builder.header("Accept-Encoding", "gzip");
-
-        // Send the request and wait for the headers.
-        let response_result = request_builder.send().await;
+        // Wait for a download slot. It's held until the download is done.
+        let download_slot = match &self.download_slots {
+            Some(slots) => slots.clone().acquire_owned().await.ok(),
+            None => None,
+        };
 
-        // Check the HTTP status code.
-        let response_result = response_result.and_then(|response| response.error_for_status());
+        // Send the request and wait for the headers, and check the HTTP status code.
+        let response_result = self.send_with_retries(reqwest_client, url).await;
 
         let response = match response_result {
             Ok(response) => response,
@@ -272,6 +372,7 @@ impl Downloader {
             stream,
             observer,
             ts_after_status,
+            _download_slot: download_slot,
         })
     }
 }
@@ -281,6 +382,7 @@ pub struct PendingDownload {
     stream: Pin<Box<dyn AsyncRead + Send + Sync>>,
     observer: Option<Arc<dyn DownloaderObserver>>,
     ts_after_status: Instant,
+    _download_slot: Option<OwnedSemaphorePermit>,
 }
 
 pub enum FileDownloadOutcome {
@@ -300,6 +402,7 @@ impl PendingDownload {
             mut stream,
             observer,
             ts_after_status,
+            _download_slot,
         } = self;
         let download_id = reporter.download_id();
         if let Some(observer) = observer.as_deref() {
@@ -407,6 +510,7 @@ impl PendingDownload {
             mut stream,
             observer,
             ts_after_status,
+            _download_slot,
         } = self;
         let download_id = reporter.download_id();
         if let Some(observer) = observer.as_deref() {
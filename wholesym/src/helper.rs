@@ -13,10 +13,13 @@ use samply_symbols::{
 use symsrv::{SymsrvDownloader, SymsrvObserver};
 use uuid::Uuid;
 
+use crate::archive::SymbolArchives;
 use crate::breakpad::BreakpadSymbolDownloader;
 use crate::config::SymbolManagerConfig;
 use crate::debuginfod::DebuginfodDownloader;
 use crate::downloader::{Downloader, DownloaderObserver};
+use crate::source_repository::{git_show_via_partial_clone, SourceRepository};
+use crate::symbol_dir_index::SymbolDirIndex;
 use crate::vdso::get_vdso_data;
 use crate::{DownloadError, SymbolManagerObserver};
 
@@ -55,6 +58,8 @@ pub enum WholesymFileLocation {
     BreakpadSymindexFile(String),
     DebuginfodDebugFile(ElfBuildId),
     DebuginfodExecutable(ElfBuildId),
+    SymbolArchiveMember(CodeId),
+    SymbolArchiveDebugFile(String, DebugId),
     UrlForSourceFile(String),
     VdsoLoadedIntoThisProcess,
 }
@@ -115,6 +120,17 @@ impl FileLocation for WholesymFileLocation {
                         .map(|base_path| Self::LocalFile(base_path.join(source_file_path)))
                 }
             }
+            Self::LocalSymsrvFile(..) | Self::SymsrvFile(..)
+                if source_file_path.starts_with("https://") =>
+            {
+                // PDB files from Windows symbol servers, e.g. Microsoft's, have
+                // the download URLs of their source files in their srcsrv and
+                // sourcelink streams.
+                //
+                // SECURITY: We only allow https URLs here. The PDB comes from a
+                // symbol server which was configured by the user.
+                Some(Self::UrlForSourceFile(source_file_path.to_owned()))
+            }
             Self::DebuginfodDebugFile(_build_id) | Self::DebuginfodExecutable(_build_id) => {
                 // TODO: load source file via debuginfod
                 None
@@ -247,6 +263,8 @@ pub struct Helper {
     symsrv_downloader: Option<SymsrvDownloader>,
     breakpad_downloader: BreakpadSymbolDownloader,
     debuginfod_downloader: Option<DebuginfodDownloader>,
+    symbol_archives: Option<SymbolArchives>,
+    symbol_dir_index: SymbolDirIndex,
     known_libs: Mutex<KnownLibs>,
     config: SymbolManagerConfig,
     precog_symbol_data: Mutex<HashMap<DebugId, Arc<dyn SymbolMapTrait + Send + Sync>>>,
@@ -262,9 +280,17 @@ struct KnownLibs {
 }
 
 impl Helper {
-    pub fn with_config(config: SymbolManagerConfig) -> Self {
+    pub fn with_config(mut config: SymbolManagerConfig) -> Self {
+        // Symbol directories can also be zip or tar files. These are indexed like symbol
+        // archives instead of being extracted.
+        let (bundles, directories): (Vec<PathBuf>, Vec<PathBuf>) =
+            std::mem::take(&mut config.extra_symbol_directories)
+                .into_iter()
+                .partition(|dir| SymbolArchives::is_archive(dir));
+        config.extra_symbol_directories = directories;
+        config.symbol_archives.extend(bundles);
         let observer = Arc::new(HelperDownloaderObserver::new());
-        let downloader = Arc::new(Downloader::new());
+        let downloader = Arc::new(Downloader::new(&config.download_settings));
         let symsrv_downloader = match config.effective_nt_symbol_path() {
             Some(nt_symbol_path) => {
                 let mut downloader = SymsrvDownloader::new(nt_symbol_path);
@@ -292,11 +318,26 @@ impl Helper {
             Some(downloader.clone()),
         );
         breakpad_downloader.set_observer(Some(observer.clone()));
+        let symbol_archives = if config.symbol_archives.is_empty() {
+            None
+        } else {
+            let extraction_dir = config
+                .symbol_archive_extraction_dir
+                .clone()
+                .unwrap_or_else(|| std::env::temp_dir().join("wholesym-symbol-archives"));
+            Some(SymbolArchives::new(
+                config.symbol_archives.clone(),
+                extraction_dir,
+            ))
+        };
+        let symbol_dir_index = SymbolDirIndex::new(config.extra_symbol_directories.clone());
         Self {
             downloader,
             symsrv_downloader,
             breakpad_downloader,
             debuginfod_downloader,
+            symbol_archives,
+            symbol_dir_index,
             known_libs: Mutex::new(Default::default()),
             config,
             precog_symbol_data: Mutex::new(Default::default()),
@@ -334,6 +375,39 @@ impl Helper {
         }
     }
 
+    pub fn remapped_path(&self, path: &str) -> Option<PathBuf> {
+        self.config.remapped_path(path)
+    }
+
+    /// Fetches a file which isn't on disk from its source repository, if a
+    /// `source_repository` rule matches its path. Returns `None` if no rule
+    /// matches.
+    async fn load_from_source_repository(
+        &self,
+        path: &Path,
+    ) -> FileAndPathHelperResult<Option<Vec<u8>>> {
+        let Some((repository, repo_path)) = path
+            .to_str()
+            .and_then(|path| self.config.source_repository_path(path))
+        else {
+            return Ok(None);
+        };
+        if let Some(url) = repository.raw_file_url(&repo_path) {
+            let download = self
+                .downloader
+                .initiate_download(&url, Some(self.observer.clone()))
+                .await?;
+            return Ok(Some(download.download_to_memory(None).await?));
+        }
+        let (SourceRepository::Git { remote, rev }, Some(cache_dir)) =
+            (repository, &self.config.source_repository_cache_dir)
+        else {
+            return Err(format!("Can't fetch {repo_path} from {repository:?}").into());
+        };
+        let bytes = git_show_via_partial_clone(cache_dir, remote, rev, &repo_path).await?;
+        Ok(Some(bytes))
+    }
+
     pub fn add_precog_symbol_map(
         &self,
         lib_info: LibraryInfo,
@@ -360,9 +434,16 @@ impl Helper {
         location: WholesymFileLocation,
     ) -> FileAndPathHelperResult<WholesymFileContents> {
         let file_path = match location {
-            WholesymFileLocation::LocalFile(path) => {
-                let path = self.config.redirect_paths.get(&path).unwrap_or(&path);
+            WholesymFileLocation::LocalFile(original_path) => {
+                let remapped_path = original_path
+                    .to_str()
+                    .and_then(|path| self.config.remapped_path(path));
+                let path = remapped_path.as_ref().unwrap_or(&original_path);
+                let path = self.config.redirect_paths.get(path).unwrap_or(path);
                 if !self.check_file_exists(path).await {
+                    if let Some(bytes) = self.load_from_source_repository(&original_path).await? {
+                        return Ok(WholesymFileContents::Bytes(bytes.into()));
+                    }
                     return Err(format!("File not found: {path:?}").into());
                 }
                 path.to_owned()
@@ -423,6 +504,20 @@ impl Helper {
                 .get_file(&build_id.to_string(), "executable")
                 .await
                 .ok_or("Debuginfod could not find executable")?,
+            WholesymFileLocation::SymbolArchiveMember(code_id) => self
+                .symbol_archives
+                .as_ref()
+                .unwrap()
+                .get_file(&code_id)
+                .await
+                .ok_or("Not found in symbol archives")?,
+            WholesymFileLocation::SymbolArchiveDebugFile(debug_name, debug_id) => self
+                .symbol_archives
+                .as_ref()
+                .unwrap()
+                .get_debug_file(&debug_name, debug_id)
+                .await
+                .ok_or("Not found in symbol archives")?,
             WholesymFileLocation::VdsoLoadedIntoThisProcess => {
                 let vdso = get_vdso_data().ok_or("No vdso in this process")?;
                 // Pretend that the VDSO data came from a file.
@@ -573,6 +668,17 @@ impl FileAndPathHelper for Helper {
             }
         }
 
+        // Look for debug files in the symbol archives, e.g. in a -dbgsym package.
+        if let (
+            Some(_symbol_archives),
+            Some(code_id @ (CodeId::ElfBuildId(_) | CodeId::MachoUuid(_))),
+        ) = (&self.symbol_archives, &info.code_id)
+        {
+            paths.push(CandidatePathInfo::SingleFile(
+                WholesymFileLocation::SymbolArchiveMember(code_id.clone()),
+            ));
+        }
+
         if let (Some(debug_name), Some(debug_id)) = (&info.debug_name, info.debug_id) {
             let rel_path = format!(
                 "{}/{}/{}.sym",
@@ -623,6 +729,20 @@ impl FileAndPathHelper for Helper {
                     WholesymFileLocation::LocalFile(p),
                 ));
             }
+            for p in self.symbol_dir_index.nested_files(debug_name) {
+                paths.push(CandidatePathInfo::SingleFile(
+                    WholesymFileLocation::LocalFile(p.clone()),
+                ));
+            }
+        }
+
+        // Look for the debug file by name and ID in symbol archives and bundles.
+        if let (Some(_symbol_archives), Some(debug_name), Some(debug_id)) =
+            (&self.symbol_archives, &info.debug_name, info.debug_id)
+        {
+            paths.push(CandidatePathInfo::SingleFile(
+                WholesymFileLocation::SymbolArchiveDebugFile(debug_name.clone(), debug_id),
+            ));
         }
 
         if !might_be_fake_jit_file(&info) {
@@ -680,6 +800,11 @@ impl FileAndPathHelper for Helper {
                     WholesymFileLocation::LocalFile(p),
                 ));
             }
+            for p in self.symbol_dir_index.nested_files(name) {
+                paths.push(CandidatePathInfo::SingleFile(
+                    WholesymFileLocation::LocalFile(p.clone()),
+                ));
+            }
         }
 
         if info.name.as_deref() == Some("[vdso]") {
@@ -749,6 +874,11 @@ impl FileAndPathHelper for Helper {
                     WholesymFileLocation::LocalFile(p),
                 ));
             }
+            for p in self.symbol_dir_index.nested_files(name) {
+                paths.push(CandidatePathInfo::SingleFile(
+                    WholesymFileLocation::LocalFile(p.clone()),
+                ));
+            }
         }
 
         // Check any simpleperf binary_cache directories.
@@ -766,6 +896,17 @@ impl FileAndPathHelper for Helper {
             }
         }
 
+        // Check the symbol archives, e.g. an .apk or a .deb package.
+        if let (
+            Some(_symbol_archives),
+            Some(code_id @ (CodeId::ElfBuildId(_) | CodeId::MachoUuid(_))),
+        ) = (&self.symbol_archives, &info.code_id)
+        {
+            paths.push(CandidatePathInfo::SingleFile(
+                WholesymFileLocation::SymbolArchiveMember(code_id.clone()),
+            ));
+        }
+
         if let Some(path) = &info.path {
             // For macOS system libraries, also consult the dyld shared cache.
             if path.starts_with("/usr/") || path.starts_with("/System/") {
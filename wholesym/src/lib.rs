@@ -129,6 +129,7 @@
 
 pub use debugid;
 
+mod archive;
 mod breakpad;
 mod config;
 mod debuginfod;
@@ -140,6 +141,9 @@ mod helper;
 mod moria_mac;
 #[cfg(target_os = "macos")]
 mod moria_mac_spotlight;
+mod object_store;
+mod source_repository;
+mod symbol_dir_index;
 mod symbol_manager;
 mod symbol_manager_observer;
 mod vdso;
@@ -152,8 +156,9 @@ pub use samply_symbols::{
     AddressInfo, CodeId, ElfBuildId, Error, ExternalFileAddressInFileRef, ExternalFileAddressRef,
     ExternalFileRef, ExternalFileSymbolMap, FrameDebugInfo, FramesLookupResult, LibraryInfo,
     LookupAddress, MappedPath, MultiArchDisambiguator, PeCodeId, SourceFilePath, SymbolInfo,
-    SyncAddressInfo,
+    SymbolKind, SyncAddressInfo,
 };
+pub use source_repository::SourceRepository;
 pub use symbol_manager::{SymbolFileOrigin, SymbolManager, SymbolMap};
 pub use symbol_manager_observer::SymbolManagerObserver;
 pub use verbose_symbol_manager_observer::VerboseSymbolManagerObserver;
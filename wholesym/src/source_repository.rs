@@ -0,0 +1,105 @@
+//! Fetching source files from version control, for binaries which were built
+//! on a different machine and whose source files aren't on disk.
+//!
+//! Files are fetched as raw files over HTTP where the hosting service has a
+//! URL scheme for it (hgweb, GitHub, GitLab). For other git remotes, a bare
+//! partial clone is kept in the cache directory, which only fetches the
+//! commits and files that are requested.
+
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+
+/// A repository and the revision at which the binary was built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceRepository {
+    /// A git remote, e.g. `"https://github.com/mstange/samply"`, and a commit hash.
+    Git { remote: String, rev: String },
+    /// A mercurial remote, e.g. `"https://hg.mozilla.org/mozilla-central"`, and a changeset hash.
+    Hg { remote: String, rev: String },
+}
+
+impl SourceRepository {
+    /// Returns the URL of the raw file at `path` in the repository, if the
+    /// remote has one.
+    pub(crate) fn raw_file_url(&self, path: &str) -> Option<String> {
+        match self {
+            SourceRepository::Hg { remote, rev } => {
+                let remote = remote.trim_end_matches('/');
+                Some(format!("{remote}/raw-file/{rev}/{path}"))
+            }
+            SourceRepository::Git { remote, rev } => {
+                let remote = remote.trim_end_matches('/').trim_end_matches(".git");
+                if let Some(repo) = remote.strip_prefix("https://github.com/") {
+                    Some(format!(
+                        "https://raw.githubusercontent.com/{repo}/{rev}/{path}"
+                    ))
+                } else if remote.starts_with("https://gitlab.") {
+                    Some(format!("{remote}/-/raw/{rev}/{path}"))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Runs git with the arguments, and returns its stdout if it succeeded.
+async fn git(repo_dir: &Path, args: &[&str]) -> Result<Vec<u8>, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| format!("Could not run git: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// The directory of the partial clone of `remote` in `cache_dir`.
+fn clone_dir(cache_dir: &Path, remote: &str) -> PathBuf {
+    let name: String = remote
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    cache_dir.join(name)
+}
+
+/// Reads the file at `path` at revision `rev` of the git repository at
+/// `remote`, using a bare partial clone in `cache_dir`.
+pub(crate) async fn git_show_via_partial_clone(
+    cache_dir: &Path,
+    remote: &str,
+    rev: &str,
+    path: &str,
+) -> Result<Vec<u8>, String> {
+    let repo_dir = clone_dir(cache_dir, remote);
+    if !repo_dir.exists() {
+        std::fs::create_dir_all(&repo_dir).map_err(|e| e.to_string())?;
+        git(&repo_dir, &["init", "--bare", "--quiet"]).await?;
+        git(&repo_dir, &["remote", "add", "origin", remote]).await?;
+    }
+    let commit = format!("{rev}^{{commit}}");
+    if git(&repo_dir, &["cat-file", "-e", &commit]).await.is_err() {
+        git(
+            &repo_dir,
+            &[
+                "fetch",
+                "--quiet",
+                "--depth=1",
+                "--filter=blob:none",
+                "origin",
+                rev,
+            ],
+        )
+        .await?;
+    }
+    git(&repo_dir, &["show", &format!("{rev}:{path}")]).await
+}
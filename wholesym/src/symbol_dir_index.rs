@@ -0,0 +1,88 @@
+//! An index of the files in nested extra symbol directories.
+//!
+//! Build output directories, e.g. `out/` directories or Conan and ccache layouts, put
+//! binaries and debug files in subdirectories at varying depths. Instead of requiring
+//! the files to be directly inside a symbol directory, the directories are walked
+//! once, the first time a file is looked up, and the paths of all files are recorded
+//! by file name. Later lookups only consult this manifest.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub struct SymbolDirIndex {
+    dirs: Vec<PathBuf>,
+    manifest: OnceLock<HashMap<OsString, Vec<PathBuf>>>,
+}
+
+impl SymbolDirIndex {
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        Self {
+            dirs,
+            manifest: OnceLock::new(),
+        }
+    }
+
+    /// Returns the paths of the files named `name` in the subdirectories of the
+    /// symbol directories. Files directly inside a symbol directory aren't included,
+    /// because they are checked without the index.
+    pub fn nested_files(&self, name: &str) -> &[PathBuf] {
+        let Some(file_name) = Path::new(name).file_name() else {
+            return &[];
+        };
+        self.manifest()
+            .get(file_name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn manifest(&self) -> &HashMap<OsString, Vec<PathBuf>> {
+        self.manifest.get_or_init(|| {
+            let mut manifest = HashMap::new();
+            for dir in &self.dirs {
+                let Ok(entries) = std::fs::read_dir(dir) else {
+                    continue;
+                };
+                for entry in entries.flatten() {
+                    if is_indexed_dir(&entry) {
+                        index_dir(&entry.path(), &mut manifest);
+                    }
+                }
+            }
+            manifest
+        })
+    }
+}
+
+/// Adds all files in `dir` and its subdirectories to the manifest.
+fn index_dir(dir: &Path, manifest: &mut HashMap<OsString, Vec<PathBuf>>) {
+    let mut pending_dirs = vec![dir.to_owned()];
+    while let Some(dir) = pending_dirs.pop() {
+        // Directories which can't be read are skipped; their files just won't be found.
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if is_indexed_dir(&entry) {
+                pending_dirs.push(entry.path());
+            } else if !file_type.is_dir() {
+                manifest
+                    .entry(entry.file_name())
+                    .or_default()
+                    .push(entry.path());
+            }
+        }
+    }
+}
+
+/// Whether the entry is a directory which should be walked. Symlinked directories
+/// are not followed, so that cycles can't make us walk forever, and hidden
+/// directories like `.git` are skipped.
+fn is_indexed_dir(entry: &std::fs::DirEntry) -> bool {
+    entry.file_type().is_ok_and(|t| t.is_dir())
+        && !entry.file_name().as_encoded_bytes().starts_with(b".")
+}
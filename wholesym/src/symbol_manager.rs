@@ -1,5 +1,5 @@
 use std::borrow::Cow;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use debugid::DebugId;
@@ -241,6 +241,13 @@ impl SymbolManager {
         self.symbol_manager.helper().add_known_lib(lib_info);
     }
 
+    /// Applies the path remapping rules from the config to `path`, e.g. to show
+    /// where a source file from the debug info is found locally. Returns `None`
+    /// if no rule matches.
+    pub fn remapped_path(&self, path: &str) -> Option<PathBuf> {
+        self.symbol_manager.helper().remapped_path(path)
+    }
+
     /// Tell the `SymbolManager` about a library's symbol table. The library
     /// must contain a DebugId. This is useful when a library's symbols are
     /// available in some way other than normal symbol lookup, or if a custom
@@ -2,7 +2,7 @@ use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use debugid::DebugId;
-use wholesym::{CodeId, FramesLookupResult, LookupAddress};
+use wholesym::{CodeId, FramesLookupResult, LookupAddress, SymbolKind};
 
 fn fixtures_dir() -> PathBuf {
     let this_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -145,6 +145,7 @@ async fn dwz_symbolication() {
     assert_eq!(&sym.symbol.name, "gobble_file.constprop.0");
     assert_eq!(sym.symbol.address, 0xd5d4);
     assert_eq!(sym.symbol.size, Some(0xebc));
+    assert_eq!(sym.symbol.kind, SymbolKind::Function);
 
     // Check information coming from the debug info found via build ID:
     assert_eq!(
@@ -291,6 +292,106 @@ mod simple_example {
         .await;
     }
 
+    #[tokio::test]
+    async fn run_test_symbol_archive() {
+        // Put the debug file into a static library archive, and find it there by build ID.
+        let dbg_path = fixtures_dir().join("other/simple-example/out/regular-debuglink/main.dbg");
+        let dbg_data = std::fs::read(dbg_path).unwrap();
+        let mut archive = b"!<arch>\n".to_vec();
+        archive.extend_from_slice(
+            format!(
+                "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                "main.dbg/",
+                0,
+                0,
+                0,
+                644,
+                dbg_data.len()
+            )
+            .as_bytes(),
+        );
+        archive.extend_from_slice(&dbg_data);
+        if dbg_data.len() % 2 == 1 {
+            archive.push(b'\n');
+        }
+        let test_dir =
+            std::env::temp_dir().join(format!("wholesym-archive-test-{}", std::process::id()));
+        std::fs::create_dir_all(&test_dir).unwrap();
+        let archive_path = test_dir.join("debug-files.a");
+        std::fs::write(&archive_path, archive).unwrap();
+
+        let config = wholesym::SymbolManagerConfig::default()
+            .symbol_archive(&archive_path)
+            .symbol_archive_extraction_dir(test_dir.join("extracted"));
+        let mut symbol_manager = wholesym::SymbolManager::with_config(config);
+        let debug_id = DebugId::from_breakpad("0C3E1D589F360C231BC06257AD3D38270").unwrap();
+        symbol_manager.add_known_library(wholesym::LibraryInfo {
+            debug_name: Some("main".to_string()),
+            debug_id: Some(debug_id),
+            code_id: Some(CodeId::from_str("581d3e0c369f230c1bc06257ad3d38274392ec85").unwrap()),
+            ..Default::default()
+        });
+        let symbol_map = symbol_manager
+            .load_symbol_map("main", debug_id)
+            .await
+            .unwrap();
+        linux_simple_example_test_fn(&symbol_map).await;
+        std::fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn run_test_symbol_bundle() {
+        // Put the debug file into a zip file, in the layout of a symbol server, and pass
+        // the zip file as a symbol directory.
+        let dbg_path = fixtures_dir().join("other/simple-example/out/regular-debuglink/main.dbg");
+        let dbg_data = std::fs::read(dbg_path).unwrap();
+        let name = b"main/0C3E1D589F360C231BC06257AD3D38270/main.dbg";
+        let mut zip = Vec::new();
+        // Local file header, stored without compression. The CRC isn't checked.
+        zip.extend_from_slice(b"PK\x03\x04\x14\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        zip.extend_from_slice(&[0; 4]);
+        zip.extend_from_slice(&(dbg_data.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&(dbg_data.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&[0; 2]);
+        zip.extend_from_slice(name);
+        zip.extend_from_slice(&dbg_data);
+        // Central directory entry.
+        let central_directory_start = zip.len();
+        zip.extend_from_slice(b"PK\x01\x02\x14\x00\x14\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        zip.extend_from_slice(&[0; 4]);
+        zip.extend_from_slice(&(dbg_data.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&(dbg_data.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&[0; 12]);
+        zip.extend_from_slice(&0u32.to_le_bytes());
+        zip.extend_from_slice(name);
+        // End of central directory record.
+        let central_directory_len = zip.len() - central_directory_start;
+        zip.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00\x01\x00\x01\x00");
+        zip.extend_from_slice(&(central_directory_len as u32).to_le_bytes());
+        zip.extend_from_slice(&(central_directory_start as u32).to_le_bytes());
+        zip.extend_from_slice(&[0; 2]);
+
+        let test_dir =
+            std::env::temp_dir().join(format!("wholesym-bundle-test-{}", std::process::id()));
+        std::fs::create_dir_all(&test_dir).unwrap();
+        let bundle_path = test_dir.join("symbols.zip");
+        std::fs::write(&bundle_path, zip).unwrap();
+
+        let config = wholesym::SymbolManagerConfig::default()
+            .extra_symbol_directory(&bundle_path)
+            .symbol_archive_extraction_dir(test_dir.join("extracted"));
+        let symbol_manager = wholesym::SymbolManager::with_config(config);
+        let debug_id = DebugId::from_breakpad("0C3E1D589F360C231BC06257AD3D38270").unwrap();
+        let symbol_map = symbol_manager
+            .load_symbol_map("main", debug_id)
+            .await
+            .unwrap();
+        linux_simple_example_test_fn(&symbol_map).await;
+        std::fs::remove_dir_all(&test_dir).unwrap();
+    }
+
     #[tokio::test]
     async fn run_test_with_dwo() {
         let dwo_obj_dir = fixtures_dir().join("other/simple-example/out/with-dwo");
@@ -420,3 +521,35 @@ mod simple_example {
         .await;
     }
 }
+
+#[test]
+fn path_remapping() {
+    let config = wholesym::SymbolManagerConfig::new()
+        .path_remapping("/builds/worker/checkouts/gecko", "/home/me/mozilla-central")
+        .path_remapping(
+            "/builds/worker/checkouts/gecko/js/",
+            "/home/me/spidermonkey",
+        )
+        .path_remapping("C:\\b\\s\\w\\ir\\cache", "/home/me/chromium");
+    assert_eq!(
+        config.remapped_path("/builds/worker/checkouts/gecko/dom/base/nsINode.cpp"),
+        Some(PathBuf::from(
+            "/home/me/mozilla-central/dom/base/nsINode.cpp"
+        ))
+    );
+    assert_eq!(
+        config.remapped_path("/builds/worker/checkouts/gecko/js/src/vm/Interpreter.cpp"),
+        Some(PathBuf::from(
+            "/home/me/spidermonkey/src/vm/Interpreter.cpp"
+        ))
+    );
+    assert_eq!(
+        config.remapped_path("C:\\b\\s\\w\\ir\\cache\\base\\logging.cc"),
+        Some(PathBuf::from("/home/me/chromium/base/logging.cc"))
+    );
+    assert_eq!(
+        config.remapped_path("/builds/worker/checkouts/gecko-dev/README"),
+        None
+    );
+    assert_eq!(config.remapped_path("/usr/include/stdio.h"), None);
+}